@@ -67,6 +67,17 @@ pub trait AlephRpc {
         hash: BlockHash,
         key_pair: AlephKeyPair,
     ) -> anyhow::Result<()>;
+
+    /// Finalize the block with given hash and number using attached signature, attributing the
+    /// finalization to the operator for the given reason.
+    async fn emergency_finalize_with_custody(
+        &self,
+        number: BlockNumber,
+        hash: BlockHash,
+        key_pair: AlephKeyPair,
+        reason: u16,
+        operator_key_pair: AlephKeyPair,
+    ) -> anyhow::Result<()>;
 }
 
 #[async_trait::async_trait]
@@ -139,4 +150,29 @@ impl<C: ConnectionApi> AlephRpc for C {
 
         Ok(())
     }
+
+    async fn emergency_finalize_with_custody(
+        &self,
+        number: BlockNumber,
+        hash: BlockHash,
+        key_pair: AlephKeyPair,
+        reason: u16,
+        operator_key_pair: AlephKeyPair,
+    ) -> anyhow::Result<()> {
+        let method = "alephNode_emergencyFinalizeWithCustody";
+        let signature = key_pair.sign(&hash.encode());
+        let operator_signature = operator_key_pair.sign(&(hash, reason, signature.0).encode());
+        let params = rpc_params![
+            Bytes::from(signature.0.to_vec()),
+            reason,
+            Bytes::from(operator_key_pair.public().0.to_vec()),
+            Bytes::from(operator_signature.0.to_vec()),
+            hash,
+            number
+        ];
+
+        let _: () = self.rpc_call_no_return(method.to_string(), params).await?;
+
+        Ok(())
+    }
 }
@@ -1,10 +1,21 @@
-use std::path::PathBuf;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+};
 
-use finality_aleph::UnitCreationDelay;
+use finality_aleph::{
+    AlephConfigError, AlephNetworkConfig, AlephNodeConfig, AlephSyncConfig, JustificationRetention,
+    UnitCreationDelay, DEFAULT_PUBLIC_HANDSHAKE_TIMEOUT, DEFAULT_PUBLIC_MAX_MESSAGE_SIZE,
+    DEFAULT_SYNC_CAPTURE_MAX_FILES, DEFAULT_SYNC_CAPTURE_MAX_FILE_MB,
+    DEFAULT_SYNC_FINALIZATION_BATCH, DEFAULT_SYNC_PEER_FORGIVE_AFTER_SECS,
+    DEFAULT_SYNC_PEER_SCORE_DECAY, DEFAULT_SYNC_VERIFICATION_SAMPLE,
+    DEFAULT_VALIDATOR_BIT_RATE_PER_CONNECTION,
+};
 use log::warn;
 use sc_cli::clap::{self, ArgGroup, Parser};
+use sp_core::{crypto::Ss58Codec, ed25519};
 
-use crate::aleph_primitives::DEFAULT_UNIT_CREATION_DELAY;
+use crate::aleph_primitives::{AuthorityId as AlephId, DEFAULT_UNIT_CREATION_DELAY};
 
 #[derive(Debug, Parser, Clone)]
 #[clap(group(ArgGroup::new("backup")))]
@@ -21,6 +32,17 @@ pub struct AlephCli {
     #[clap(long, default_value_t = 30343)]
     validator_port: u16,
 
+    /// The local address on which to listen to validator network connections. Together with the
+    /// listening addresses of the public network, allows keeping the committee traffic on a
+    /// separate interface, e.g. a private network segment.
+    #[clap(long, default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    validator_listen_address: IpAddr,
+
+    /// The local address from which to make validator network connections, so that they leave
+    /// through the same interface as the one listened on. Chosen by the system if not provided.
+    #[clap(long)]
+    validator_outgoing_address: Option<IpAddr>,
+
     /// Turn off backups, at the cost of limiting crash recoverability.
     ///
     /// If backups are turned off and the node crashes, it most likely will not be able to continue
@@ -48,9 +70,278 @@ pub struct AlephCli {
     #[clap(long, default_value_t = false)]
     experimental_pruning: bool,
 
+    /// Read the block sync and network configuration from this JSON file, instead of the
+    /// individual flags. Missing values take the defaults.
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = [
+            "alephbft_bit_rate_per_connection",
+            "public_gossip_max_message_size",
+            "public_gossip_bit_rate_per_peer",
+            "public_gossip_handshake_timeout_ms",
+            "sync_serving_window",
+            "sync_max_batch_bytes",
+            "sync_min_broadcast_period_ms",
+            "sync_max_broadcast_period_ms",
+            "sync_capture_path",
+            "sync_capture_max_file_mb",
+            "sync_capture_max_files",
+            "sync_snapshot_every_sessions",
+            "sync_snapshot_export_path",
+            "sync_priority_key_path",
+            "sync_priority_follower",
+            "sync_headers_first",
+            "sync_warp",
+            "sync_parallel_download",
+            "sync_session_pipeline",
+            "sync_request_response",
+            "sync_finalization_batch",
+            "sync_dry_run",
+            "sync_light",
+            "sync_max_message_bytes",
+            "sync_max_response_blocks",
+            "sync_peer_score_decay",
+            "sync_peer_forgive_after_secs",
+            "sync_require_emergency_custody",
+            "sync_emergency_operator",
+            "sync_trusted_checkpoint",
+            "sync_verification_sample",
+            "sync_justification_retention",
+            "sync_compress_justifications",
+            "sync_block_source",
+            "sync_fork_id",
+            "head_push_endpoint",
+            "disk_quota_mb",
+            "finality_stall_timeout_secs",
+            "status_page_address",
+        ]
+    )]
+    aleph_config: Option<PathBuf>,
+
     /// Maximum bit-rate per node in bytes per second of the alephbft validator network.
-    #[clap(long, default_value_t = 64 * 1024)]
+    #[clap(long, default_value_t = DEFAULT_VALIDATOR_BIT_RATE_PER_CONNECTION as u64)]
     alephbft_bit_rate_per_connection: u64,
+
+    /// Maximum size in bytes of a single message of the public gossip protocols, used for
+    /// discovery and block sync.
+    #[clap(long, default_value_t = DEFAULT_PUBLIC_MAX_MESSAGE_SIZE)]
+    public_gossip_max_message_size: u64,
+
+    /// Maximum bit-rate in bytes per second of the public gossip messages received from a single
+    /// peer, the messages above it are dropped. Does not affect the validator network.
+    #[clap(long)]
+    public_gossip_bit_rate_per_peer: Option<u64>,
+
+    /// Time in milliseconds a peer opening the block sync protocol has to send a message we can
+    /// decode, before it gets disconnected, with the likely cause logged.
+    #[clap(long, default_value_t = DEFAULT_PUBLIC_HANDSHAKE_TIMEOUT.as_millis() as u64)]
+    public_gossip_handshake_timeout_ms: u64,
+
+    /// Only serve block sync requests from peers at most this many blocks behind. Can only be
+    /// stricter than the value set on chain.
+    #[clap(long)]
+    sync_serving_window: Option<u32>,
+
+    /// Maximum size in bytes of a single block sync response. Can only be stricter than the value
+    /// set on chain.
+    #[clap(long)]
+    sync_max_batch_bytes: Option<u32>,
+
+    /// Minimum time in milliseconds between broadcasts of the block sync state. Has to be within
+    /// the bounds set on chain.
+    #[clap(long)]
+    sync_min_broadcast_period_ms: Option<u64>,
+
+    /// Maximum time in milliseconds between broadcasts of the block sync state. Has to be within
+    /// the bounds set on chain.
+    #[clap(long)]
+    sync_max_broadcast_period_ms: Option<u64>,
+
+    /// Capture all the block sync traffic into files in this directory, for debugging. The files
+    /// can be read with the `decode-sync-capture` subcommand.
+    #[clap(long, value_name = "PATH")]
+    sync_capture_path: Option<PathBuf>,
+
+    /// The size in megabytes after which a new block sync capture file is started.
+    #[clap(long, default_value_t = DEFAULT_SYNC_CAPTURE_MAX_FILE_MB)]
+    sync_capture_max_file_mb: u64,
+
+    /// How many block sync capture files are kept, the oldest ones are removed.
+    #[clap(long, default_value_t = DEFAULT_SYNC_CAPTURE_MAX_FILES)]
+    sync_capture_max_files: usize,
+
+    /// Trigger a snapshot of the database every this many finalized sessions, pausing block sync
+    /// briefly so that the snapshot is taken at a finality consistent point.
+    #[clap(long)]
+    sync_snapshot_every_sessions: Option<u32>,
+
+    /// Write every snapshot point to a marker file in this directory, keeping block sync paused
+    /// until external tooling removes it or the maximum pause passes.
+    #[clap(long, value_name = "PATH", requires = "sync_snapshot_every_sessions")]
+    sync_snapshot_export_path: Option<PathBuf>,
+
+    /// A file with the secret phrase of the key proving this node belongs to the infrastructure
+    /// of a validator, e.g. its monitoring or backup node. The validator serves block sync
+    /// requests of this node with priority, if it knows the key.
+    #[clap(long, value_name = "PATH")]
+    sync_priority_key_path: Option<PathBuf>,
+
+    /// The public key of a follower node belonging to the infrastructure of this validator, whose
+    /// block sync requests are served with priority. Can be given multiple times.
+    #[clap(long, value_name = "PUBLIC_KEY", value_parser = parse_public_key)]
+    sync_priority_follower: Vec<AlephId>,
+
+    /// When far behind the network, fetch and verify the chains of headers and justifications
+    /// before downloading the block bodies.
+    #[clap(long, default_value_t = false)]
+    sync_headers_first: bool,
+
+    /// When sessions behind the network, first download and verify the chain of justifications
+    /// ending the sessions, learning the authorities of every session up to the head. The blocks
+    /// are still imported one by one.
+    #[clap(long, default_value_t = false)]
+    sync_warp: bool,
+
+    /// When far behind the network, split the missing blocks into ranges downloaded from several
+    /// peers at once, importing them in order.
+    #[clap(long, default_value_t = false)]
+    sync_parallel_download: bool,
+
+    /// When sessions behind the network, fetch and verify the justifications and headers of the
+    /// next session while the blocks of the current one are still imported.
+    #[clap(long, default_value_t = false)]
+    sync_session_pipeline: bool,
+
+    /// Exchange block sync requests and responses through a dedicated request-response protocol,
+    /// instead of notifications. Peers that do not support it still get notifications.
+    #[clap(long, default_value_t = false)]
+    sync_request_response: bool,
+
+    /// Finalize at most this many justified blocks in a single database transaction during block
+    /// sync. Only the justifications of the highest blocks of the batches and of the last blocks
+    /// of sessions are stored.
+    #[clap(long, default_value_t = DEFAULT_SYNC_FINALIZATION_BATCH)]
+    sync_finalization_batch: u32,
+
+    /// Run block sync dry, for validating a release against the traffic of a live network. The
+    /// blocks are imported and served to peers as usual, but justified blocks are only finalized
+    /// in memory, leaving the finality in the database as it was. Not meant for validators.
+    #[clap(long, default_value_t = false)]
+    sync_dry_run: bool,
+
+    /// Follow the chain through block sync tracking only the headers and justifications, never
+    /// downloading the block bodies, for RPC and observer nodes. The finalized headers are kept in
+    /// memory and passed on to the finalization hooks, the finality in the database is left as it
+    /// was. Not meant for validators.
+    #[clap(long, default_value_t = false)]
+    sync_light: bool,
+
+    /// Maximum size in bytes of a single block sync message, sent or received, for chains with
+    /// blocks larger than the default allows. Overrides the value from the chain spec, and is
+    /// clamped to sane bounds. Larger messages also need a large enough public gossip message size.
+    #[clap(long)]
+    sync_max_message_bytes: Option<u32>,
+
+    /// Serve at most this many blocks in response to a single block sync request.
+    #[clap(long)]
+    sync_max_response_blocks: Option<u32>,
+
+    /// How many points of the misbehavior score of a peer are forgiven every second.
+    #[clap(long, default_value_t = DEFAULT_SYNC_PEER_SCORE_DECAY)]
+    sync_peer_score_decay: u32,
+
+    /// Forgive a peer completely after this many seconds without misbehaving, so that
+    /// temporary trouble does not get it penalized for good.
+    #[clap(long, default_value_t = DEFAULT_SYNC_PEER_FORGIVE_AFTER_SECS)]
+    sync_peer_forgive_after_secs: u64,
+
+    /// Reject emergency justifications that do not carry the signature of an emergency operator
+    /// and the reason for the emergency finalization. Requires at least one emergency operator.
+    #[clap(long, default_value_t = false, requires = "sync_emergency_operator")]
+    sync_require_emergency_custody: bool,
+
+    /// The public key of an operator allowed to order emergency finalizations. Can be given
+    /// multiple times, any operator is accepted if none is given.
+    #[clap(long, value_name = "PUBLIC_KEY", value_parser = parse_public_key)]
+    sync_emergency_operator: Vec<AlephId>,
+
+    /// Only verify a random sample of the justifications of the blocks up to this one, to rebuild
+    /// archives of long chains faster. The blocks ending sessions and all the blocks above are
+    /// always verified. Only use a checkpoint you trust, e.g. a block you know to be finalized.
+    #[clap(long, value_name = "BLOCK_NUMBER")]
+    sync_trusted_checkpoint: Option<u32>,
+
+    /// Verify one in this many justifications below the trusted checkpoint. A chain with `k`
+    /// forged justifications there is accepted with probability `(1 - 1 / N)^k`.
+    #[clap(long, value_name = "N", default_value_t = DEFAULT_SYNC_VERIFICATION_SAMPLE, requires = "sync_trusted_checkpoint")]
+    sync_verification_sample: u32,
+
+    /// Which justifications of finalized blocks to keep: `keep-all`, `session-boundaries` or
+    /// `last-sessions:N`, counting the current session. The ones of the last blocks of sessions
+    /// are always kept, the others are pruned in the background once outside the retention.
+    #[clap(long, value_name = "RETENTION", default_value = "keep-all", value_parser = parse_justification_retention)]
+    sync_justification_retention: JustificationRetention,
+
+    /// Compress the justifications archived for pruning, which any retention other than
+    /// `keep-all` does. The ones archived earlier can be compressed with the
+    /// `compress-justification-archive` subcommand.
+    #[clap(long, default_value_t = false)]
+    sync_compress_justifications: bool,
+
+    /// Fetch the missing bodies of finalized blocks, e.g. during a body backfill, from this HTTPS
+    /// source rather than from peers. It has to serve the SCALE-encoded block with hash `0x...`
+    /// at `<URL>/0x...`. The bodies are checked against the headers, peers are asked whenever the
+    /// source fails.
+    #[clap(long, value_name = "URL")]
+    sync_block_source: Option<String>,
+
+    /// The fork of the chain to follow after a hard fork, overriding the fork id of the chain spec
+    /// for the Aleph protocols. Nodes with different fork ids do not connect over those
+    /// protocols, and ignore each other in sync if they get connected anyway.
+    #[clap(long, value_name = "FORK_ID")]
+    sync_fork_id: Option<String>,
+
+    /// Push a signed announcement of every newly finalized block to this HTTP endpoint, e.g. one
+    /// of a bridge relayer. Only plain http:// endpoints are supported.
+    #[clap(long, value_name = "URL")]
+    head_push_endpoint: Option<String>,
+
+    /// The most megabytes of disk the sync captures, AlephBFT backups and shutdown reports can
+    /// take together. The oldest ones are evicted first, except the ones still in use.
+    #[clap(long, value_name = "MB")]
+    disk_quota_mb: Option<u64>,
+
+    /// Log a diagnostic snapshot of sync, AlephBFT and the peers whenever no block is finalized
+    /// for this many seconds. The recent snapshots are also available through the
+    /// `alephNode_finalityStallDiagnostics` RPC.
+    #[clap(long, value_name = "SECONDS")]
+    finality_stall_timeout_secs: Option<u64>,
+
+    /// Serve a plain status page of sync, with the finalized head, the peers, the forest and the
+    /// recent errors, at this address. It is available as HTML at `/` and as JSON at
+    /// `/status.json`. Every request asks sync for its status, so keep it off public networks.
+    #[clap(long, value_name = "ADDRESS")]
+    status_page_address: Option<SocketAddr>,
+}
+
+fn parse_public_key(s: &str) -> Result<AlephId, String> {
+    ed25519::Public::from_string(s)
+        .map(AlephId::from)
+        .map_err(|e| format!("invalid public key {s}: {e:?}"))
+}
+
+fn parse_justification_retention(s: &str) -> Result<JustificationRetention, String> {
+    match s {
+        "keep-all" => Ok(JustificationRetention::KeepAll),
+        "session-boundaries" => Ok(JustificationRetention::SessionBoundaries),
+        _ => match s.strip_prefix("last-sessions:").map(str::parse) {
+            Some(Ok(sessions)) if sessions > 0 => Ok(JustificationRetention::LastSessions(sessions)),
+            _ => Err(format!(
+                "invalid justification retention {s}, expected keep-all, session-boundaries or last-sessions:N with a positive N"
+            )),
+        },
+    }
 }
 
 impl AlephCli {
@@ -70,6 +361,14 @@ impl AlephCli {
         self.validator_port
     }
 
+    pub fn validator_listen_address(&self) -> IpAddr {
+        self.validator_listen_address
+    }
+
+    pub fn validator_outgoing_address(&self) -> Option<IpAddr> {
+        self.validator_outgoing_address
+    }
+
     pub fn backup_path(&self) -> Option<PathBuf> {
         self.backup_path.clone()
     }
@@ -89,7 +388,65 @@ impl AlephCli {
         self.experimental_pruning
     }
 
-    pub fn alephbft_bit_rate_per_connection(&self) -> u64 {
-        self.alephbft_bit_rate_per_connection
+    /// The block sync and network configuration, either from the config file or from the
+    /// individual flags. Validated in both cases.
+    pub fn node_config(&self) -> Result<AlephNodeConfig, AlephConfigError> {
+        if let Some(path) = &self.aleph_config {
+            return AlephNodeConfig::load(path);
+        }
+        let config = AlephNodeConfig {
+            sync: AlephSyncConfig {
+                serving_window: self.sync_serving_window,
+                max_batch_bytes: self.sync_max_batch_bytes,
+                min_broadcast_period_ms: self.sync_min_broadcast_period_ms,
+                max_broadcast_period_ms: self.sync_max_broadcast_period_ms,
+                capture_path: self.sync_capture_path.clone(),
+                capture_max_file_mb: self.sync_capture_max_file_mb,
+                capture_max_files: self.sync_capture_max_files,
+                snapshot_every_sessions: self.sync_snapshot_every_sessions,
+                snapshot_export_path: self.sync_snapshot_export_path.clone(),
+                priority_key_path: self.sync_priority_key_path.clone(),
+                priority_followers: self.sync_priority_follower.clone(),
+                headers_first: self.sync_headers_first,
+                warp_sync: self.sync_warp,
+                parallel_download: self.sync_parallel_download,
+                session_pipeline: self.sync_session_pipeline,
+                request_response: self.sync_request_response,
+                finalization_batch: self.sync_finalization_batch,
+                dry_run: self.sync_dry_run,
+                light_sync: self.sync_light,
+                max_message_bytes: self.sync_max_message_bytes,
+                max_response_blocks: self.sync_max_response_blocks,
+                peer_score_decay: self.sync_peer_score_decay,
+                peer_forgive_after_secs: self.sync_peer_forgive_after_secs,
+                require_emergency_custody: self.sync_require_emergency_custody,
+                emergency_operators: self.sync_emergency_operator.clone(),
+                trusted_checkpoint: self.sync_trusted_checkpoint,
+                verification_sample: self.sync_verification_sample,
+                // The features of every peer are too much for flags, only the file sets them.
+                v1_shim_peers: Vec::new(),
+                justification_retention: self.sync_justification_retention,
+                compress_justifications: self.sync_compress_justifications,
+                block_source: self.sync_block_source.clone(),
+                fork_id: self.sync_fork_id.clone(),
+            },
+            network: AlephNetworkConfig {
+                validator_bit_rate_per_connection: self
+                    .alephbft_bit_rate_per_connection
+                    .try_into()
+                    .unwrap_or(usize::MAX),
+                public_max_message_size: self.public_gossip_max_message_size,
+                public_bit_rate_per_peer: self
+                    .public_gossip_bit_rate_per_peer
+                    .map(|rate| rate.try_into().unwrap_or(usize::MAX)),
+                public_handshake_timeout_ms: self.public_gossip_handshake_timeout_ms,
+            },
+            head_push_endpoint: self.head_push_endpoint.clone(),
+            disk_quota_mb: self.disk_quota_mb,
+            finality_stall_timeout_secs: self.finality_stall_timeout_secs,
+            status_page_address: self.status_page_address,
+        };
+        config.validate()?;
+        Ok(config)
     }
 }
@@ -1,20 +1,45 @@
-use std::sync::Arc;
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
 
-use finality_aleph::{AlephJustification, BlockId, Justification, JustificationTranslator};
-use futures::channel::mpsc;
+use finality_aleph::{
+    archived_justification, encode_aleph_justification, AlephJustification, BlockId,
+    BlockIdentifier, BodyBackfill, EmergencyAudit, EmergencyCustody, EmergencyFinalization,
+    EmergencyReason, FinalityStallDiagnostic, FinalityStallDiagnostics, ForestDump, ForestSummary,
+    InnerJustification, Justification, JustificationTranslator, Provenance, SyncBackfillProgress,
+    SyncCounterValues, SyncCounters, SyncFinalityStats, SyncFinalityStatus, SyncForestDumps,
+    SyncImportEvent, SyncImportNotifications, SyncImportedBlock, SyncJustificationFetches,
+    SyncNetworkView, SyncPeerFinality, SyncPeerScore, SyncPeerServing, SyncPeerTracing,
+    SyncProvenance, SyncScoreChange, SyncStatus, SyncStatusReports, ValidatorNetworkHealth,
+    ValidatorPeerHealth, VertexContents, VertexDump, VertexInterest, PEER_TRACE_LOG_TARGET,
+};
+use futures::{channel::mpsc, stream, FutureExt, StreamExt};
 use jsonrpsee::{
-    core::{error::Error as JsonRpseeError, RpcResult},
+    core::{async_trait, error::Error as JsonRpseeError, RpcResult},
     proc_macros::rpc,
-    types::error::{CallError, ErrorObject},
+    types::{
+        error::{CallError, ErrorObject},
+        SubscriptionResult,
+    },
+    SubscriptionSink,
 };
-use parity_scale_codec::Decode;
-use primitives::{AccountId, Block, BlockHash, BlockNumber, Signature};
-use sc_client_api::StorageProvider;
+use log::info;
+use parity_scale_codec::{Decode, Encode};
+use primitives::{
+    AccountId, AuthorityId, Block, BlockHash, BlockNumber, Signature, ALEPH_ENGINE_ID,
+};
+use sc_client_api::{AuxStore, BlockBackend, StorageProvider};
+use sc_network::PeerId;
+use sc_rpc::SubscriptionTaskExecutor;
+use sc_rpc_api::DenyUnsafe;
+use serde::{Deserialize, Serialize};
 use sp_arithmetic::traits::Zero;
 use sp_blockchain::HeaderBackend;
 use sp_consensus::SyncOracle;
 use sp_consensus_aura::digests::CompatibleDigestItem;
-use sp_core::{twox_128, Bytes};
+use sp_core::{ed25519, twox_128, Bytes};
 use sp_runtime::{
     traits::{Block as BlockT, Header as HeaderT},
     DigestItem,
@@ -55,6 +80,21 @@ pub enum Error {
     /// Failed to find a block with provided hash.
     #[error("Failed to find a block with hash {0}.")]
     UnknownHash(String),
+    /// Peer id argument is malformed.
+    #[error("Malformed peer id {0}.")]
+    MalformedPeerId(String),
+    /// Block sync did not respond.
+    #[error("Block sync did not respond.")]
+    SyncUnavailable,
+    /// The block range is empty.
+    #[error("Empty block range from {0} to {1}.")]
+    EmptyRange(BlockNumber, BlockNumber),
+    /// The block is not finalized.
+    #[error("The block with hash {0} is not finalized.")]
+    NotFinalized(String),
+    /// Failed to read the justifications of a block.
+    #[error("Failed to read the justifications of a block {0}: {1:?}.")]
+    FailedJustificationRead(String, sp_blockchain::Error),
 }
 
 // Base code for all system errors.
@@ -77,6 +117,16 @@ const FAILED_STORAGE_DECODING_ERROR: i32 = BASE_ERROR + 7;
 const FAILED_HEADER_DECODING_ERROR: i32 = BASE_ERROR + 8;
 /// Failed to find a block with provided hash.
 const UNKNOWN_HASH_ERROR: i32 = BASE_ERROR + 9;
+/// Peer id argument is malformed.
+const MALFORMED_PEER_ID_ERROR: i32 = BASE_ERROR + 10;
+/// Block sync did not respond.
+const SYNC_UNAVAILABLE_ERROR: i32 = BASE_ERROR + 11;
+/// The block range is empty.
+const EMPTY_RANGE_ERROR: i32 = BASE_ERROR + 12;
+/// The block is not finalized.
+const NOT_FINALIZED_ERROR: i32 = BASE_ERROR + 13;
+/// Failed to read the justifications of a block.
+const FAILED_JUSTIFICATION_READ_ERROR: i32 = BASE_ERROR + 14;
 
 impl From<Error> for JsonRpseeError {
     fn from(e: Error) -> Self {
@@ -132,11 +182,548 @@ impl From<Error> for JsonRpseeError {
                 format!("Failed to find a block with hash {hash}.",),
                 None::<()>,
             )),
+            Error::MalformedPeerId(peer) => CallError::Custom(ErrorObject::owned(
+                MALFORMED_PEER_ID_ERROR,
+                format!("Malformed peer id {peer}."),
+                None::<()>,
+            )),
+            Error::SyncUnavailable => CallError::Custom(ErrorObject::owned(
+                SYNC_UNAVAILABLE_ERROR,
+                "Block sync did not respond.",
+                None::<()>,
+            )),
+            Error::EmptyRange(from, to) => CallError::Custom(ErrorObject::owned(
+                EMPTY_RANGE_ERROR,
+                format!("Empty block range from {from} to {to}."),
+                None::<()>,
+            )),
+            Error::NotFinalized(hash) => CallError::Custom(ErrorObject::owned(
+                NOT_FINALIZED_ERROR,
+                format!("The block with hash {hash} is not finalized."),
+                None::<()>,
+            )),
+            Error::FailedJustificationRead(hash, err) => CallError::Custom(ErrorObject::owned(
+                FAILED_JUSTIFICATION_READ_ERROR,
+                format!("Failed to read the justifications of a block {hash}: {err:?}."),
+                None::<()>,
+            )),
         }
         .into()
     }
 }
 
+/// How many blocks the provenance is returned for, if the limit is not specified.
+const DEFAULT_PROVENANCE_LIMIT: u32 = 64;
+/// How many emergency finalizations are returned, if the limit is not specified.
+const DEFAULT_EMERGENCY_FINALIZATIONS_LIMIT: u32 = 64;
+
+/// The peers that supplied the justification and the body of a block finalized by sync.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockProvenance {
+    pub hash: BlockHash,
+    pub number: BlockNumber,
+    /// Missing if the justification did not come from a peer.
+    pub justification_from: Option<String>,
+    /// Empty if the body did not come from a peer.
+    pub bodies_from: Vec<String>,
+}
+
+impl From<Provenance<PeerId, BlockId>> for BlockProvenance {
+    fn from(provenance: Provenance<PeerId, BlockId>) -> Self {
+        BlockProvenance {
+            hash: provenance.block.hash(),
+            number: provenance.block.number(),
+            justification_from: provenance.justification_from.map(|peer| peer.to_string()),
+            bodies_from: provenance
+                .bodies_from
+                .iter()
+                .map(|peer| peer.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// An emergency justification accepted by this node, with whoever is accountable for it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmergencyFinalizationRecord {
+    pub hash: BlockHash,
+    pub number: BlockNumber,
+    /// Missing if the justification came without a chain of custody.
+    pub reason: Option<EmergencyReason>,
+    /// The public key of the operator, missing if the justification came without a chain of
+    /// custody.
+    pub operator: Option<Bytes>,
+    /// Milliseconds since the unix epoch.
+    pub accepted_at_ms: u64,
+}
+
+impl From<EmergencyFinalization> for EmergencyFinalizationRecord {
+    fn from(finalization: EmergencyFinalization) -> Self {
+        EmergencyFinalizationRecord {
+            hash: finalization.block.hash(),
+            number: finalization.block.number(),
+            reason: finalization.reason,
+            operator: finalization
+                .operator
+                .map(|operator| Bytes(operator.encode())),
+            accepted_at_ms: finalization
+                .accepted_at
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_millis() as u64)
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// A block in the block sync forest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForestBlock {
+    pub hash: BlockHash,
+    pub number: BlockNumber,
+    /// Missing if the header is not known yet.
+    pub parent: Option<BlockHash>,
+    /// One of `empty`, `header` or `justification`.
+    pub contents: String,
+    /// One of `auxiliary`, `required`, `explicitlyRequired` or `imported`.
+    pub interest: String,
+    pub know_most: Vec<String>,
+}
+
+impl From<VertexDump<PeerId, BlockId>> for ForestBlock {
+    fn from(vertex: VertexDump<PeerId, BlockId>) -> Self {
+        let contents = match vertex.contents {
+            VertexContents::Empty => "empty",
+            VertexContents::Header => "header",
+            VertexContents::Justification => "justification",
+        };
+        let interest = match vertex.interest {
+            VertexInterest::Auxiliary => "auxiliary",
+            VertexInterest::Required => "required",
+            VertexInterest::ExplicitlyRequired => "explicitlyRequired",
+            VertexInterest::Imported => "imported",
+        };
+        ForestBlock {
+            hash: vertex.id.hash(),
+            number: vertex.id.number(),
+            parent: vertex.parent.map(|parent| parent.hash()),
+            contents: contents.to_string(),
+            interest: interest.to_string(),
+            know_most: vertex
+                .know_most
+                .iter()
+                .map(|peer| peer.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// The whole block sync forest, growing from the highest finalized block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncForest {
+    pub root_hash: BlockHash,
+    pub root_number: BlockNumber,
+    pub highest_justified_hash: BlockHash,
+    pub highest_justified_number: BlockNumber,
+    /// Sorted by block number.
+    pub blocks: Vec<ForestBlock>,
+}
+
+impl From<ForestDump<PeerId, BlockId>> for SyncForest {
+    fn from(dump: ForestDump<PeerId, BlockId>) -> Self {
+        SyncForest {
+            root_hash: dump.root.hash(),
+            root_number: dump.root.number(),
+            highest_justified_hash: dump.highest_justified.hash(),
+            highest_justified_number: dump.highest_justified.number(),
+            blocks: dump.vertices.into_iter().map(ForestBlock::from).collect(),
+        }
+    }
+}
+
+/// How far the backfill of missing block bodies got.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BodyBackfillProgress {
+    pub running: bool,
+    pub from: BlockNumber,
+    /// Missing if the backfill goes up to the top finalized block.
+    pub to: Option<BlockNumber>,
+    /// The next block to be checked for a missing body.
+    pub next: BlockNumber,
+    pub requests: u64,
+    pub fetched: u64,
+}
+
+impl From<SyncBackfillProgress> for BodyBackfillProgress {
+    fn from(progress: SyncBackfillProgress) -> Self {
+        BodyBackfillProgress {
+            running: progress.running,
+            from: progress.from,
+            to: progress.to,
+            next: progress.next,
+            requests: progress.requests,
+            fetched: progress.fetched,
+        }
+    }
+}
+
+/// Cumulative statistics of block sync, over all the runs of the node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStats {
+    pub justifications_imported: u64,
+    pub bytes_served: u64,
+    pub bans: u64,
+}
+
+impl From<SyncCounterValues> for SyncStats {
+    fn from(values: SyncCounterValues) -> Self {
+        SyncStats {
+            justifications_imported: values.justifications_imported,
+            bytes_served: values.bytes_served,
+            bans: values.bans,
+        }
+    }
+}
+
+/// Statistics of the top finalized blocks advertised by the peers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkFinalityStats {
+    pub peers: usize,
+    pub median_finalized: BlockNumber,
+    pub highest_finalized: BlockNumber,
+    pub lag_median: BlockNumber,
+    pub lag_p90: BlockNumber,
+    pub lag_max: BlockNumber,
+    pub median_unchanged_secs: u64,
+}
+
+impl From<SyncFinalityStats> for NetworkFinalityStats {
+    fn from(stats: SyncFinalityStats) -> Self {
+        NetworkFinalityStats {
+            peers: stats.peers,
+            median_finalized: stats.median_finalized,
+            highest_finalized: stats.highest_finalized,
+            lag_median: stats.lag_median,
+            lag_p90: stats.lag_p90,
+            lag_max: stats.lag_max,
+            median_unchanged_secs: stats.median_unchanged_for.as_secs(),
+        }
+    }
+}
+
+/// Whether finality is progressing, as seen in the states advertised by the peers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncNetworkStatus {
+    /// A description of the status, distinguishing us being behind from the network stalling.
+    pub status: String,
+    pub behind: bool,
+    pub network_stalled: bool,
+    /// Missing if no peer advertised its state recently.
+    pub stats: Option<NetworkFinalityStats>,
+}
+
+impl SyncNetworkStatus {
+    fn new(status: SyncFinalityStatus, stats: Option<SyncFinalityStats>) -> Self {
+        SyncNetworkStatus {
+            status: status.to_string(),
+            behind: matches!(status, SyncFinalityStatus::Behind(_)),
+            network_stalled: matches!(status, SyncFinalityStatus::NetworkStalled(_)),
+            stats: stats.map(NetworkFinalityStats::from),
+        }
+    }
+}
+
+/// The last state advertised by a peer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerSyncState {
+    pub peer: String,
+    pub finalized: BlockNumber,
+    pub age_millis: u64,
+    /// Whether the state is recent enough to count in the view of the network.
+    pub fresh: bool,
+}
+
+impl From<SyncPeerFinality<PeerId>> for PeerSyncState {
+    fn from(state: SyncPeerFinality<PeerId>) -> Self {
+        PeerSyncState {
+            peer: state.peer.to_string(),
+            finalized: state.finalized,
+            age_millis: state.age.as_millis().try_into().unwrap_or(u64::MAX),
+            fresh: state.fresh,
+        }
+    }
+}
+
+/// A past misbehavior of a peer in block sync.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerScoreChange {
+    pub misbehavior: String,
+    /// The score of the peer right after the misbehavior.
+    pub score: u32,
+    /// How the peer got penalized for it, if at all.
+    pub penalty: Option<String>,
+    pub age_millis: u64,
+}
+
+impl From<SyncScoreChange> for PeerScoreChange {
+    fn from(change: SyncScoreChange) -> Self {
+        PeerScoreChange {
+            misbehavior: change.misbehavior.to_string(),
+            score: change.score,
+            penalty: change.penalty.map(|penalty| format!("{penalty:?}")),
+            age_millis: change.age.as_millis().try_into().unwrap_or(u64::MAX),
+        }
+    }
+}
+
+/// The misbehavior score of a peer, decaying over time, with its recent history.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerScoreState {
+    pub peer: String,
+    pub score: u32,
+    /// The most recent first.
+    pub history: Vec<PeerScoreChange>,
+}
+
+impl From<SyncPeerScore<PeerId>> for PeerScoreState {
+    fn from(score: SyncPeerScore<PeerId>) -> Self {
+        PeerScoreState {
+            peer: score.peer.to_string(),
+            score: score.score,
+            history: score
+                .history
+                .into_iter()
+                .map(PeerScoreChange::from)
+                .collect(),
+        }
+    }
+}
+
+/// How block sync served the requests of a peer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerServingState {
+    pub peer: String,
+    pub served: u64,
+    pub rejected: u64,
+    /// Dropped without handling, because of the rate limits or the queue being full.
+    pub limit_exceeded: u64,
+    /// Given up on, because the peer disconnected or superseded them with newer requests.
+    pub canceled: u64,
+    /// The average and longest time handling the requests that were not dropped took.
+    pub average_serving_micros: u64,
+    pub max_serving_micros: u64,
+}
+
+impl From<SyncPeerServing<PeerId>> for PeerServingState {
+    fn from(serving: SyncPeerServing<PeerId>) -> Self {
+        let micros = |duration: Duration| duration.as_micros().try_into().unwrap_or(u64::MAX);
+        let handled = (serving.served + serving.rejected + serving.canceled).max(1);
+        PeerServingState {
+            peer: serving.peer.to_string(),
+            served: serving.served,
+            rejected: serving.rejected,
+            limit_exceeded: serving.limit_exceeded,
+            canceled: serving.canceled,
+            average_serving_micros: micros(serving.total_serving_time) / handled,
+            max_serving_micros: micros(serving.max_serving_time),
+        }
+    }
+}
+
+/// What block sync is doing, for finding out why finalization is stuck.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncComponentStatus {
+    pub top_finalized_hash: BlockHash,
+    pub top_finalized_number: BlockNumber,
+    pub favourite_hash: BlockHash,
+    pub favourite_number: BlockNumber,
+    /// How many tips of the trees in the sync forest there are.
+    pub forks: usize,
+    pub pending_bodies: usize,
+    /// How many requests for blocks still wait for responses.
+    pub in_flight_requests: usize,
+    /// The most recently updated first.
+    pub peers: Vec<PeerSyncState>,
+    /// The scores of the peers that misbehaved, the most recently misbehaving first.
+    pub peer_scores: Vec<PeerScoreState>,
+    /// How the requests of the peers were served, the ones that sent the most first.
+    pub serving: Vec<PeerServingState>,
+    /// The events sync handled most recently, the latest first.
+    pub recent_events: Vec<String>,
+    /// The most recent errors of sync, the latest first.
+    pub recent_errors: Vec<String>,
+}
+
+impl From<SyncStatus<PeerId, BlockId>> for SyncComponentStatus {
+    fn from(status: SyncStatus<PeerId, BlockId>) -> Self {
+        SyncComponentStatus {
+            top_finalized_hash: status.top_finalized.hash(),
+            top_finalized_number: status.top_finalized.number(),
+            favourite_hash: status.favourite.hash(),
+            favourite_number: status.favourite.number(),
+            forks: status.forks,
+            pending_bodies: status.pending_bodies,
+            in_flight_requests: status.in_flight_requests,
+            peers: status.peers.into_iter().map(PeerSyncState::from).collect(),
+            peer_scores: status
+                .peer_scores
+                .into_iter()
+                .map(PeerScoreState::from)
+                .collect(),
+            serving: status
+                .serving
+                .into_iter()
+                .map(PeerServingState::from)
+                .collect(),
+            recent_events: status
+                .recent_events
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            recent_errors: status.recent_errors,
+        }
+    }
+}
+
+/// The shape of the sync forest, without the individual vertices.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncForestSummary {
+    pub root_hash: BlockHash,
+    pub root_number: BlockNumber,
+    pub highest_justified_hash: BlockHash,
+    pub highest_justified_number: BlockNumber,
+    pub vertices: usize,
+    /// Justified, but not imported yet.
+    pub justified: usize,
+    /// Wanted, but with the blocks still unknown.
+    pub required: usize,
+}
+
+impl From<ForestSummary<BlockId>> for SyncForestSummary {
+    fn from(summary: ForestSummary<BlockId>) -> Self {
+        SyncForestSummary {
+            root_hash: summary.root.hash(),
+            root_number: summary.root.number(),
+            highest_justified_hash: summary.highest_justified.hash(),
+            highest_justified_number: summary.highest_justified.number(),
+            vertices: summary.vertices,
+            justified: summary.justified,
+            required: summary.required,
+        }
+    }
+}
+
+/// A diagnostic snapshot taken when no block was finalized for too long.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinalityStallReport {
+    pub taken_at_unix_ms: u64,
+    pub stalled_secs: u64,
+    pub last_finalized: BlockNumber,
+    pub session: u32,
+    /// Missing if the AlephBFT metrics are not registered.
+    pub abft_round: Option<u64>,
+    pub network: SyncNetworkStatus,
+    /// Missing if sync did not respond, which usually means it is stuck itself.
+    pub sync: Option<SyncComponentStatus>,
+    pub forest: Option<SyncForestSummary>,
+}
+
+impl From<FinalityStallDiagnostic> for FinalityStallReport {
+    fn from(diagnostic: FinalityStallDiagnostic) -> Self {
+        FinalityStallReport {
+            taken_at_unix_ms: diagnostic.taken_at_unix_ms.try_into().unwrap_or(u64::MAX),
+            stalled_secs: diagnostic.stalled_for.as_secs(),
+            last_finalized: diagnostic.last_finalized,
+            session: diagnostic.session.0,
+            abft_round: diagnostic.abft_round,
+            network: SyncNetworkStatus::new(diagnostic.network_status, diagnostic.network_stats),
+            sync: diagnostic.sync.map(SyncComponentStatus::from),
+            forest: diagnostic.forest.map(SyncForestSummary::from),
+        }
+    }
+}
+
+/// A block imported with a body supplied by peers through block sync.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncImport {
+    pub hash: BlockHash,
+    pub number: BlockNumber,
+    pub bodies_from: Vec<String>,
+    /// How many imports were not announced right before this one, because the subscriber was
+    /// too slow.
+    pub missed_before: u64,
+}
+
+impl SyncImport {
+    fn new(imported: SyncImportedBlock<PeerId, BlockId>, missed_before: u64) -> Self {
+        SyncImport {
+            hash: imported.block.hash(),
+            number: imported.block.number(),
+            bodies_from: imported
+                .bodies_from
+                .iter()
+                .map(|peer| peer.to_string())
+                .collect(),
+            missed_before,
+        }
+    }
+}
+
+/// The connectivity of a validator we should be directly connected to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidatorConnectionHealth {
+    pub authority: String,
+    /// Whether we dial the validator, rather than wait for it to connect to us.
+    pub outgoing: bool,
+    pub connected: bool,
+    pub connected_for_millis: Option<u64>,
+    pub since_sent_millis: Option<u64>,
+    pub since_received_millis: Option<u64>,
+    /// Whether the validator is connected, but did not send us anything for a while.
+    pub silent: bool,
+    pub dial_failures: u32,
+    pub consecutive_dial_failures: u32,
+    /// How many times the connection got dropped and dialed again, because the validator went
+    /// silent.
+    pub silent_redials: u32,
+}
+
+impl From<ValidatorPeerHealth> for ValidatorConnectionHealth {
+    fn from(health: ValidatorPeerHealth) -> Self {
+        let millis = |duration: Option<Duration>| {
+            duration.map(|d| d.as_millis().try_into().unwrap_or(u64::MAX))
+        };
+        ValidatorConnectionHealth {
+            authority: health.peer.to_string(),
+            outgoing: health.outgoing,
+            connected: health.connected,
+            connected_for_millis: millis(health.connected_for),
+            since_sent_millis: millis(health.since_sent),
+            since_received_millis: millis(health.since_received),
+            silent: health.silent,
+            dial_failures: health.dial_failures,
+            consecutive_dial_failures: health.consecutive_dial_failures,
+            silent_redials: health.silent_redials,
+        }
+    }
+}
+
 /// Aleph Node RPC API
 #[rpc(client, server, namespace = "alephNode")]
 pub trait AlephNodeApi<BE> {
@@ -149,13 +736,121 @@ pub trait AlephNodeApi<BE> {
         number: BlockNumber,
     ) -> RpcResult<()>;
 
+    /// Finalize the block with given hash and number using the attached emergency signature,
+    /// together with the reason and the operator ordering the finalization, who signs the hash
+    /// of the block, the reason and the emergency signature, in this order.
+    #[method(name = "emergencyFinalizeWithCustody")]
+    fn emergency_finalize_with_custody(
+        &self,
+        emergency_signature: Bytes,
+        reason: EmergencyReason,
+        operator: Bytes,
+        operator_signature: Bytes,
+        hash: BlockHash,
+        number: BlockNumber,
+    ) -> RpcResult<()>;
+
+    /// Get the emergency justifications most recently accepted by this node, with the operators
+    /// that ordered them and their reasons, newest first. Returns at most `limit` of them, 64 by
+    /// default.
+    #[method(name = "emergencyFinalizations")]
+    fn emergency_finalizations(
+        &self,
+        limit: Option<u32>,
+    ) -> RpcResult<Vec<EmergencyFinalizationRecord>>;
+
     /// Get the author of the block with given hash.
     #[method(name = "getBlockAuthor")]
     fn block_author(&self, hash: BlockHash) -> RpcResult<Option<AccountId>>;
 
+    /// Get the encoded AlephBFT justification of the finalized block with given hash. If it is
+    /// not stored locally, e.g. because the block was finalized by a descendant, it is fetched
+    /// from the peers, which is only allowed through unsafe RPC. Returns `null` for the genesis
+    /// block, or if no peer sent the justification in time.
+    #[method(name = "getJustification", aliases = ["aleph_getJustification"])]
+    async fn get_justification(&self, hash: BlockHash) -> RpcResult<Option<Bytes>>;
+
     ///
     #[method(name = "ready")]
     fn ready(&self) -> RpcResult<bool>;
+
+    /// Get the peers that supplied the justifications and bodies of the blocks most recently
+    /// finalized by sync, newest first. Returns at most `limit` blocks, 64 by default.
+    #[method(name = "syncProvenance")]
+    fn sync_provenance(&self, limit: Option<u32>) -> RpcResult<Vec<BlockProvenance>>;
+
+    /// Log all the sync messages exchanged with the given peer, replacing any previously traced
+    /// one, for the given number of seconds, at most an hour. The messages are summarized under
+    /// the `aleph-sync-peer-trace` log target. Returns for how many seconds the peer is traced.
+    /// Unsafe.
+    #[method(name = "traceSyncPeer")]
+    fn trace_sync_peer(&self, peer_id: String, seconds: u64) -> RpcResult<u64>;
+
+    /// Stop tracing the sync messages of the peer chosen with `traceSyncPeer`. Unsafe.
+    #[method(name = "stopSyncPeerTrace")]
+    fn stop_sync_peer_trace(&self) -> RpcResult<()>;
+
+    /// Dump the block sync forest, with the import status and our interest in every block.
+    /// Unsafe, for debugging why a branch is not being finalized.
+    #[method(name = "syncForest")]
+    async fn sync_forest(&self) -> RpcResult<SyncForest>;
+
+    /// Dump the block sync forest in the graphviz DOT format. Unsafe.
+    #[method(name = "syncForestDot")]
+    async fn sync_forest_dot(&self) -> RpcResult<String>;
+
+    /// Fetch from peers the bodies of finalized blocks missing from the database, e.g. after
+    /// switching from pruning to archive, in the given range, from genesis and up to the top
+    /// finalized block by default. Replaces any backfill in progress. Unsafe.
+    #[method(name = "startBodyBackfill")]
+    fn start_body_backfill(
+        &self,
+        from: Option<BlockNumber>,
+        to: Option<BlockNumber>,
+    ) -> RpcResult<()>;
+
+    /// Stop the backfill started with `startBodyBackfill`. Unsafe.
+    #[method(name = "stopBodyBackfill")]
+    fn stop_body_backfill(&self) -> RpcResult<()>;
+
+    /// Get the progress of the backfill started with `startBodyBackfill`.
+    #[method(name = "bodyBackfillProgress")]
+    fn body_backfill_progress(&self) -> RpcResult<BodyBackfillProgress>;
+
+    /// Get the cumulative statistics of block sync, persisted across restarts of the node.
+    #[method(name = "syncStats")]
+    fn sync_stats(&self) -> RpcResult<SyncStats>;
+
+    /// Get the view of the finality of the network aggregated from the states advertised by
+    /// peers, telling apart this node being behind from the whole network being stalled.
+    #[method(name = "syncNetworkStatus")]
+    fn sync_network_status(&self) -> RpcResult<SyncNetworkStatus>;
+
+    /// Get the internal view of block sync: the top finalized and favourite blocks, the forks
+    /// in the sync forest, the requests waiting for responses, how fresh the states of the
+    /// peers are, the scores of the misbehaving peers with their recent misbehavior, how the
+    /// requests of the peers were served and the events and errors sync handled recently.
+    #[method(name = "syncStatus", aliases = ["aleph_syncStatus"])]
+    async fn sync_status(&self) -> RpcResult<SyncComponentStatus>;
+
+    /// Get the diagnostic snapshots taken when no block was finalized for longer than the
+    /// configured stall timeout, the latest first. Empty if the stall watchdog is disabled.
+    #[method(name = "finalityStallDiagnostics")]
+    fn finality_stall_diagnostics(&self) -> RpcResult<Vec<FinalityStallReport>>;
+
+    /// Get the connectivity of the validators this node should be directly connected to in the
+    /// current session, the ones we did not hear from for the longest first.
+    #[method(name = "validatorNetworkHealth")]
+    fn validator_network_health(&self) -> RpcResult<Vec<ValidatorConnectionHealth>>;
+
+    /// Subscribe to the blocks imported with bodies supplied by peers through block sync. Slow
+    /// subscribers miss some of the imports, which is reported in the following ones.
+    #[subscription(
+        name = "subscribeSyncImports" => "syncImport",
+        unsubscribe = "unsubscribeSyncImports",
+        item = SyncImport,
+    )]
+    fn subscribe_sync_imports(&self);
 }
 
 /// Aleph Node API implementation
@@ -164,6 +859,20 @@ pub struct AlephNode<Client, SO> {
     justification_translator: JustificationTranslator,
     client: Arc<Client>,
     sync_oracle: SO,
+    sync_provenance: SyncProvenance,
+    sync_peer_tracing: SyncPeerTracing,
+    sync_forest_dumps: SyncForestDumps,
+    sync_status_reports: SyncStatusReports,
+    justification_fetches: SyncJustificationFetches,
+    body_backfill: BodyBackfill,
+    import_notifications: SyncImportNotifications,
+    sync_counters: SyncCounters,
+    sync_network_view: SyncNetworkView,
+    emergency_audit: EmergencyAudit,
+    validator_network_health: ValidatorNetworkHealth,
+    stall_diagnostics: FinalityStallDiagnostics,
+    subscription_executor: SubscriptionTaskExecutor,
+    deny_unsafe: DenyUnsafe,
 }
 
 impl<Client, SO> AlephNode<Client, SO>
@@ -175,20 +884,83 @@ where
         justification_translator: JustificationTranslator,
         client: Arc<Client>,
         sync_oracle: SO,
+        sync_provenance: SyncProvenance,
+        sync_peer_tracing: SyncPeerTracing,
+        sync_forest_dumps: SyncForestDumps,
+        sync_status_reports: SyncStatusReports,
+        justification_fetches: SyncJustificationFetches,
+        body_backfill: BodyBackfill,
+        import_notifications: SyncImportNotifications,
+        sync_counters: SyncCounters,
+        sync_network_view: SyncNetworkView,
+        emergency_audit: EmergencyAudit,
+        validator_network_health: ValidatorNetworkHealth,
+        stall_diagnostics: FinalityStallDiagnostics,
+        subscription_executor: SubscriptionTaskExecutor,
+        deny_unsafe: DenyUnsafe,
     ) -> Self {
         AlephNode {
             import_justification_tx,
             justification_translator,
             client,
             sync_oracle,
+            sync_provenance,
+            sync_peer_tracing,
+            sync_forest_dumps,
+            sync_status_reports,
+            justification_fetches,
+            body_backfill,
+            import_notifications,
+            sync_counters,
+            sync_network_view,
+            emergency_audit,
+            validator_network_health,
+            stall_diagnostics,
+            subscription_executor,
+            deny_unsafe,
         }
     }
+
+    fn send_justification(
+        &self,
+        justification: AlephJustification,
+        hash: BlockHash,
+        number: BlockNumber,
+    ) -> RpcResult<()> {
+        let justification = self
+            .justification_translator
+            .translate(justification, BlockId::new(hash, number))
+            .map_err(|e| Error::FailedJustificationTranslation(format!("{e}")))?;
+        self.import_justification_tx
+            .unbounded_send(justification)
+            .map_err(|_| {
+                Error::FailedJustificationSend(
+                    "AlephNodeApiServer failed to send JustifictionNotification via its channel"
+                        .into(),
+                )
+            })?;
+        Ok(())
+    }
+
+    async fn forest_dump(&self) -> RpcResult<ForestDump<PeerId, BlockId>> {
+        self.deny_unsafe.check_if_safe()?;
+        Ok(self
+            .sync_forest_dumps
+            .dump()
+            .await
+            .ok_or(Error::SyncUnavailable)?)
+    }
 }
 
+#[async_trait]
 impl<Client, BE, SO> AlephNodeApiServer<BE> for AlephNode<Client, SO>
 where
     BE: sc_client_api::Backend<Block> + 'static,
-    Client: HeaderBackend<Block> + StorageProvider<Block, BE> + 'static,
+    Client: HeaderBackend<Block>
+        + BlockBackend<Block>
+        + StorageProvider<Block, BE>
+        + AuxStore
+        + 'static,
     SO: SyncOracle + Send + Sync + 'static,
 {
     fn emergency_finalize(
@@ -203,19 +975,51 @@ where
                     "Provided justification cannot be converted into correct type".into(),
                 )
             })?);
-        let justification = self
-            .justification_translator
-            .translate(justification, BlockId::new(hash, number))
-            .map_err(|e| Error::FailedJustificationTranslation(format!("{e}")))?;
-        self.import_justification_tx
-            .unbounded_send(justification)
-            .map_err(|_| {
-                Error::FailedJustificationSend(
-                    "AlephNodeApiServer failed to send JustifictionNotification via its channel"
-                        .into(),
-                )
-            })?;
-        Ok(())
+        self.send_justification(justification, hash, number)
+    }
+
+    fn emergency_finalize_with_custody(
+        &self,
+        emergency_signature: Bytes,
+        reason: EmergencyReason,
+        operator: Bytes,
+        operator_signature: Bytes,
+        hash: BlockHash,
+        number: BlockNumber,
+    ) -> RpcResult<()> {
+        let malformed =
+            |what: &str| Error::MalformedJustificationArg(format!("Provided {what} is malformed"));
+        let operator: [u8; 32] = operator
+            .0
+            .as_slice()
+            .try_into()
+            .map_err(|_| malformed("operator key"))?;
+        let justification = AlephJustification::EmergencyCustody(EmergencyCustody {
+            reason,
+            operator: AuthorityId::from(ed25519::Public::from_raw(operator)),
+            operator_signature: operator_signature
+                .0
+                .try_into()
+                .map_err(|_| malformed("operator signature"))?,
+            emergency_signature: emergency_signature
+                .0
+                .try_into()
+                .map_err(|_| malformed("emergency signature"))?,
+        });
+        self.send_justification(justification, hash, number)
+    }
+
+    fn emergency_finalizations(
+        &self,
+        limit: Option<u32>,
+    ) -> RpcResult<Vec<EmergencyFinalizationRecord>> {
+        let limit = limit.unwrap_or(DEFAULT_EMERGENCY_FINALIZATIONS_LIMIT) as usize;
+        Ok(self
+            .emergency_audit
+            .recent(limit)
+            .into_iter()
+            .map(EmergencyFinalizationRecord::from)
+            .collect())
     }
 
     fn block_author(&self, hash: BlockHash) -> RpcResult<Option<AccountId>> {
@@ -245,9 +1049,182 @@ where
         ))
     }
 
+    async fn get_justification(&self, hash: BlockHash) -> RpcResult<Option<Bytes>> {
+        let header = self
+            .client
+            .header(hash)
+            .map_err(|e| Error::FailedHeaderDecoding(hash.to_string(), e))?
+            .ok_or(Error::UnknownHash(hash.to_string()))?;
+        let number = *header.number();
+        if number.is_zero() {
+            return Ok(None);
+        }
+        let finalized = number <= self.client.info().finalized_number
+            && self.client.hash(number).ok().flatten() == Some(hash);
+        if !finalized {
+            return Err(Error::NotFinalized(hash.to_string()).into());
+        }
+        let stored = self
+            .client
+            .justifications(hash)
+            .map_err(|e| Error::FailedJustificationRead(hash.to_string(), e))?
+            .and_then(|justifications| justifications.into_justification(ALEPH_ENGINE_ID));
+        let stored = match stored {
+            Some(justification) => Some(justification),
+            None => archived_justification(&*self.client, &BlockId::new(hash, number))
+                .map_err(|e| Error::FailedJustificationRead(hash.to_string(), e))?,
+        };
+        if let Some(justification) = stored {
+            return Ok(Some(justification.into()));
+        }
+        self.deny_unsafe.check_if_safe()?;
+        let fetched = self
+            .justification_fetches
+            .fetch(BlockId::new(hash, number))
+            .await;
+        Ok(match fetched.map(Justification::into_inner) {
+            Some(InnerJustification::AlephJustification(justification)) => {
+                Some(encode_aleph_justification(justification).into())
+            }
+            _ => None,
+        })
+    }
+
     fn ready(&self) -> RpcResult<bool> {
         Ok(!self.sync_oracle.is_offline() && !self.sync_oracle.is_major_syncing())
     }
+
+    fn sync_provenance(&self, limit: Option<u32>) -> RpcResult<Vec<BlockProvenance>> {
+        let limit = limit.unwrap_or(DEFAULT_PROVENANCE_LIMIT) as usize;
+        Ok(self
+            .sync_provenance
+            .recent(limit)
+            .into_iter()
+            .map(BlockProvenance::from)
+            .collect())
+    }
+
+    fn trace_sync_peer(&self, peer_id: String, seconds: u64) -> RpcResult<u64> {
+        self.deny_unsafe.check_if_safe()?;
+        let peer = PeerId::from_str(&peer_id).map_err(|_| Error::MalformedPeerId(peer_id))?;
+        let duration = self
+            .sync_peer_tracing
+            .enable(peer, Duration::from_secs(seconds));
+        info!(
+            target: PEER_TRACE_LOG_TARGET,
+            "Tracing sync messages of {} for {:?}.",
+            peer,
+            duration
+        );
+        Ok(duration.as_secs())
+    }
+
+    fn stop_sync_peer_trace(&self) -> RpcResult<()> {
+        self.deny_unsafe.check_if_safe()?;
+        self.sync_peer_tracing.disable();
+        info!(target: PEER_TRACE_LOG_TARGET, "Stopped tracing sync messages.");
+        Ok(())
+    }
+
+    async fn sync_forest(&self) -> RpcResult<SyncForest> {
+        Ok(self.forest_dump().await?.into())
+    }
+
+    async fn sync_forest_dot(&self) -> RpcResult<String> {
+        Ok(self.forest_dump().await?.to_dot())
+    }
+
+    fn start_body_backfill(
+        &self,
+        from: Option<BlockNumber>,
+        to: Option<BlockNumber>,
+    ) -> RpcResult<()> {
+        self.deny_unsafe.check_if_safe()?;
+        let from = from.unwrap_or(0);
+        if let Some(to) = to {
+            if to < from {
+                return Err(Error::EmptyRange(from, to).into());
+            }
+        }
+        self.body_backfill.start(from, to);
+        Ok(())
+    }
+
+    fn stop_body_backfill(&self) -> RpcResult<()> {
+        self.deny_unsafe.check_if_safe()?;
+        self.body_backfill.stop();
+        Ok(())
+    }
+
+    fn body_backfill_progress(&self) -> RpcResult<BodyBackfillProgress> {
+        Ok(self.body_backfill.progress().into())
+    }
+
+    fn sync_stats(&self) -> RpcResult<SyncStats> {
+        Ok(self.sync_counters.values().into())
+    }
+
+    fn sync_network_status(&self) -> RpcResult<SyncNetworkStatus> {
+        let now = Instant::now();
+        Ok(SyncNetworkStatus::new(
+            self.sync_network_view.status(now),
+            self.sync_network_view.stats(now),
+        ))
+    }
+
+    async fn sync_status(&self) -> RpcResult<SyncComponentStatus> {
+        Ok(self
+            .sync_status_reports
+            .report()
+            .await
+            .ok_or(Error::SyncUnavailable)?
+            .into())
+    }
+
+    fn finality_stall_diagnostics(&self) -> RpcResult<Vec<FinalityStallReport>> {
+        Ok(self
+            .stall_diagnostics
+            .recent()
+            .into_iter()
+            .map(FinalityStallReport::from)
+            .collect())
+    }
+
+    fn validator_network_health(&self) -> RpcResult<Vec<ValidatorConnectionHealth>> {
+        Ok(self
+            .validator_network_health
+            .report()
+            .into_iter()
+            .map(ValidatorConnectionHealth::from)
+            .collect())
+    }
+
+    fn subscribe_sync_imports(&self, sink: SubscriptionSink) -> SubscriptionResult {
+        let subscription = self.import_notifications.subscribe();
+        let imports = stream::unfold(
+            (subscription, 0),
+            |(mut subscription, mut missed)| async move {
+                loop {
+                    match subscription.next().await? {
+                        SyncImportEvent::Missed(count) => missed += count,
+                        SyncImportEvent::Imported(imported) => {
+                            return Some((SyncImport::new(imported, missed), (subscription, 0)))
+                        }
+                    }
+                }
+            },
+        )
+        .boxed();
+        self.subscription_executor.spawn(
+            "aleph-sync-imports-subscription",
+            Some("rpc"),
+            async move {
+                sink.pipe_from_stream(imports).await;
+            }
+            .boxed(),
+        );
+        Ok(())
+    }
 }
 
 fn read_storage<
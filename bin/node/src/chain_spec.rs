@@ -7,6 +7,7 @@ use aleph_runtime::{
 };
 use libp2p::PeerId;
 use pallet_staking::{Forcing, StakerStatus};
+use sc_chain_spec::ChainSpecExtension;
 use sc_cli::{
     clap::{self, Args},
     Error as CliError,
@@ -20,8 +21,8 @@ use sp_core::{sr25519, Pair};
 
 use crate::aleph_primitives::{
     staking::{MIN_NOMINATOR_BOND, MIN_VALIDATOR_BOND},
-    AuthorityId as AlephId, SessionValidators, Version as FinalityVersion, ADDRESSES_ENCODING,
-    LEGACY_FINALITY_VERSION, TOKEN, TOKEN_DECIMALS,
+    AuthorityId as AlephId, BlockNumber, SessionValidators, Version as FinalityVersion,
+    ADDRESSES_ENCODING, LEGACY_FINALITY_VERSION, TOKEN, TOKEN_DECIMALS,
 };
 
 pub const CHAINTYPE_DEV: &str = "dev";
@@ -35,8 +36,28 @@ pub const DEFAULT_SUDO_ACCOUNT: &str = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcN
 
 pub const DEFAULT_BACKUP_FOLDER: &str = "backup-stash";
 
+/// Node settings that have to be the same for the whole network, so they come with the chain
+/// spec instead of the command line.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ChainSpecExtension)]
+#[serde(rename_all = "camelCase")]
+pub struct Extensions {
+    /// After this block is finalized the legacy version of the sync protocol is neither sent nor
+    /// accepted, so the whole network retires it at once.
+    pub legacy_sync_cutoff: Option<BlockNumber>,
+    /// The maximum size in bytes of a single sync message, for chains with blocks larger than the
+    /// default allows. All the nodes have to accept what the others send.
+    pub sync_max_message_bytes: Option<u32>,
+}
+
+impl Extensions {
+    /// The extensions of the chain spec, if it has them.
+    pub fn try_get(chain_spec: &dyn sc_service::ChainSpec) -> Option<&Self> {
+        sc_chain_spec::get_extension(chain_spec.extensions())
+    }
+}
+
 /// Specialized `ChainSpec`. This is a specialization of the general Substrate ChainSpec type.
-pub type ChainSpec = sc_service::GenericChainSpec<GenesisConfig>;
+pub type ChainSpec = sc_service::GenericChainSpec<GenesisConfig, Extensions>;
 
 #[derive(Clone)]
 pub struct SerializablePeerId {
@@ -266,7 +287,7 @@ fn generate_chain_spec_config(
         // Properties
         Some(system_properties(token_symbol)),
         // Extensions
-        None,
+        Extensions::default(),
     ))
 }
 
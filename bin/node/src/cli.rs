@@ -3,10 +3,16 @@ use sc_cli::{
     ChainSpec, PurgeChainCmd, RunCmd, RuntimeVersion, SubstrateCli,
 };
 
+#[cfg(feature = "simnet")]
+use crate::commands::SimnetCmd;
 use crate::{
     aleph_cli::AlephCli,
     chain_spec,
-    commands::{BootstrapChainCmd, BootstrapNodeCmd, ConvertChainspecToRawCmd},
+    commands::{
+        BootstrapChainCmd, BootstrapNodeCmd, CheckAbftBackupCmd, CompressJustificationArchiveCmd,
+        ConvertChainspecToRawCmd, DecodeSyncCaptureCmd, ReplaySyncCaptureCmd,
+        VerifyJustificationCmd,
+    },
 };
 
 #[derive(Debug, Parser)]
@@ -82,6 +88,34 @@ pub enum Subcommand {
     /// Takes a chainspec and generates a corresponfing raw chainspec
     ConvertChainspecToRaw(ConvertChainspecToRawCmd),
 
+    /// Print the block sync traffic captured with `--sync-capture-path`
+    DecodeSyncCapture(DecodeSyncCaptureCmd),
+
+    /// Check the AlephBFT backups saved with `--backup-path` for corrupted records, optionally
+    /// cutting them short like the node does on startup
+    CheckAbftBackup(CheckAbftBackupCmd),
+
+    /// Replay the block sync traffic captured with `--sync-capture-path` against a copy of the
+    /// local database, failing if the responses differ from the captured ones
+    #[command(hide = true)]
+    ReplaySyncCapture(ReplaySyncCaptureCmd),
+
+    /// Verify a justification of a block against the authorities in the local database
+    VerifyJustification(VerifyJustificationCmd),
+
+    /// Compress the justifications archived for pruning before `--sync-compress-justifications`
+    /// was enabled
+    CompressJustificationArchive(CompressJustificationArchiveCmd),
+
+    /// Simulate finality of a local network of in-process nodes, possibly with faults
+    #[cfg(feature = "simnet")]
+    Simnet(SimnetCmd),
+
+    /// Simulate finality of a local network of in-process nodes. Note: `simnet` feature must be
+    /// enabled.
+    #[cfg(not(feature = "simnet"))]
+    Simnet,
+
     /// Validate blocks.
     CheckBlock(sc_cli::CheckBlockCmd),
 
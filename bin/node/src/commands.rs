@@ -1,23 +1,46 @@
+#[cfg(feature = "simnet")]
+use std::str::FromStr;
 use std::{
     fs,
     io::Write,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use aleph_runtime::AccountId;
+use finality_aleph::{
+    check_abft_backup, compress_archived_justifications, on_chain_session_info,
+    replay_sync_capture, sync_capture_files, BlockImporter, ClientForAleph, SessionBoundaryInfo,
+    SessionId, SessionPeriod, SubstrateChainStatus, SyncCaptureError, SyncCaptureReader,
+    SyncCaptureRecord, SyncNetworkData,
+};
+#[cfg(feature = "simnet")]
+use finality_aleph::{
+    run_simnet, SimnetConfig, SimnetCrash, SimnetFaultParseError, SimnetSessionRange,
+};
 use libp2p::identity::{ed25519 as libp2p_ed25519, PublicKey};
+use light_verifier::{signers, LightVerifier, SessionPeriods, Signers};
 use sc_cli::{
     clap::{self, Args, Parser},
-    Error, KeystoreParams,
+    CliConfiguration, DatabaseParams, Error, KeystoreParams, SharedParams,
 };
+use sc_consensus::ImportQueue;
 use sc_keystore::LocalKeystore;
-use sc_service::config::{BasePath, KeystoreConfig};
+use sc_service::{
+    config::{BasePath, DatabaseSource, KeystoreConfig},
+    Configuration, TFullBackend,
+};
+use sp_api::{ApiError, ProvideRuntimeApi};
 use sp_application_crypto::{key_types, Ss58Codec};
+use sp_blockchain::HeaderBackend;
 use sp_consensus_aura::sr25519::AuthorityId as AuraId;
 use sp_keystore::Keystore;
 
 use crate::{
-    aleph_primitives::AuthorityId as AlephId,
+    aleph_primitives::{
+        AlephSessionApi, AuthorityId as AlephId, Block, BlockHash, BlockNumber,
+        SessionAuthorityData,
+    },
     chain_spec::{
         self, account_id_from_string, AuthorityKeys, ChainParams, ChainSpec, SerializablePeerId,
         DEFAULT_BACKUP_FOLDER,
@@ -284,3 +307,466 @@ impl ConvertChainspecToRawCmd {
         Ok(())
     }
 }
+
+/// Command used to print captured block sync traffic in a human readable form
+#[derive(Debug, Parser)]
+pub struct DecodeSyncCaptureCmd {
+    /// Specify path to a capture file, or a directory of them, which are then read oldest first
+    #[arg(long)]
+    pub path: PathBuf,
+}
+
+impl DecodeSyncCaptureCmd {
+    fn print(record: SyncCaptureRecord) {
+        let frame = match record.decode_frame::<SyncNetworkData>() {
+            Ok(data) => format!("{data:?}"),
+            Err(e) => format!("undecodable frame of {} bytes: {}", record.frame.len(), e),
+        };
+        println!(
+            "{} {:?} [{}] {}",
+            record.timestamp_millis,
+            record.direction,
+            record.peers.join(", "),
+            frame
+        );
+    }
+
+    fn print_file(path: &Path) -> Result<(), SyncCaptureError> {
+        for record in SyncCaptureReader::open(path)? {
+            Self::print(record?);
+        }
+        Ok(())
+    }
+
+    pub fn run(&self) -> Result<(), Error> {
+        let files = match self.path.is_dir() {
+            true => sync_capture_files(&self.path)?,
+            false => vec![self.path.clone()],
+        };
+        for file in files {
+            Self::print_file(&file)
+                .map_err(|e| Error::Input(format!("Failed to read capture {file:?}: {e}")))?;
+        }
+        Ok(())
+    }
+}
+
+/// Command used to check the AlephBFT backups for corrupted records
+#[derive(Debug, Parser)]
+pub struct CheckAbftBackupCmd {
+    /// Specify path to the backup directory, as given with `--backup-path`
+    #[arg(long)]
+    pub path: PathBuf,
+
+    /// Cut the backups short at the first corrupted record, as the node does on startup
+    #[arg(long, default_value_t = false)]
+    pub repair: bool,
+}
+
+impl CheckAbftBackupCmd {
+    pub fn run(&self) -> Result<(), Error> {
+        let reports = check_abft_backup(&self.path, self.repair)
+            .map_err(|e| Error::Input(format!("Failed to read backups at {:?}: {e}", self.path)))?;
+        let mut failed = 0;
+        for (session_id, report) in reports {
+            match report {
+                Ok(report) => println!("session {session_id}: {report}"),
+                Err(e) => {
+                    failed += 1;
+                    println!("session {session_id}: {e}");
+                }
+            }
+        }
+        match failed {
+            0 => Ok(()),
+            failed => Err(Error::Input(format!(
+                "Backups of {failed} sessions cannot be loaded"
+            ))),
+        }
+    }
+}
+
+/// Command used to replay captured block sync traffic against a copy of the local database
+#[derive(Debug, Parser)]
+pub struct ReplaySyncCaptureCmd {
+    /// Specify path to a capture file, or a directory of them, which are then replayed oldest first
+    #[arg(long)]
+    pub path: PathBuf,
+
+    /// Directory the database is copied to before replaying, a temporary one if not provided
+    #[arg(long)]
+    pub copy_path: Option<PathBuf>,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub shared_params: SharedParams,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub database_params: DatabaseParams,
+}
+
+impl CliConfiguration for ReplaySyncCaptureCmd {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+
+    fn database_params(&self) -> Option<&DatabaseParams> {
+        Some(&self.database_params)
+    }
+}
+
+fn copy_directory(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        match entry.file_type()?.is_dir() {
+            true => copy_directory(&entry.path(), &target)?,
+            false => {
+                fs::copy(entry.path(), target)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl ReplaySyncCaptureCmd {
+    /// Points the configuration at a fresh copy of the database, since replaying imports and
+    /// finalizes blocks.
+    pub fn use_database_copy(&self, config: &mut Configuration) -> Result<(), Error> {
+        let copy = match &self.copy_path {
+            Some(path) => path.clone(),
+            None => std::env::temp_dir().join(format!("aleph-sync-replay-{}", std::process::id())),
+        };
+        if copy.exists() {
+            return Err(Error::Input(format!(
+                "Database copy {copy:?} already exists"
+            )));
+        }
+        eprintln!("Replaying against a copy of the database in {copy:?}.");
+        config.database = match &config.database {
+            DatabaseSource::RocksDb { path, cache_size } => {
+                copy_directory(path, &copy)?;
+                DatabaseSource::RocksDb {
+                    path: copy,
+                    cache_size: *cache_size,
+                }
+            }
+            DatabaseSource::ParityDb { path } => {
+                copy_directory(path, &copy)?;
+                DatabaseSource::ParityDb { path: copy }
+            }
+            other => {
+                return Err(Error::Input(format!(
+                    "Cannot copy the database {other:?}, specify it with `--database`"
+                )))
+            }
+        };
+        Ok(())
+    }
+
+    pub async fn run<C, IQ>(
+        &self,
+        client: Arc<C>,
+        backend: Arc<TFullBackend<Block>>,
+        import_queue: IQ,
+    ) -> Result<(), Error>
+    where
+        C: ClientForAleph<Block, TFullBackend<Block>> + Send + Sync + 'static,
+        C::Api: AlephSessionApi<Block>,
+        IQ: ImportQueue<Block>,
+    {
+        let files = match self.path.is_dir() {
+            true => sync_capture_files(&self.path)?,
+            false => vec![self.path.clone()],
+        };
+        let finalized = client.info().finalized_hash;
+        let session_period = client
+            .runtime_api()
+            .session_period(finalized)
+            .map_err(|e| Error::Input(format!("Runtime API failure: {e}")))?;
+        let chain_status = SubstrateChainStatus::new(backend)
+            .map_err(|e| Error::Input(format!("Failed to set up chain status: {e}")))?;
+        // The queue only has to be kept alive, the imports reach the replay through the import
+        // notifications.
+        let import_queue_handle = BlockImporter(import_queue.service());
+        let session_info = on_chain_session_info(client.clone(), SessionPeriod(session_period));
+        let report = replay_sync_capture(
+            client,
+            chain_status,
+            import_queue_handle,
+            session_info,
+            &files,
+        )
+        .await
+        .map_err(|e| Error::Input(format!("Failed to replay the capture: {e}")))?;
+        drop(import_queue);
+        println!("{report}");
+        match report.identical() {
+            true => Ok(()),
+            false => Err(Error::Input(
+                "The replay decided differently than the capturing node".to_string(),
+            )),
+        }
+    }
+}
+
+/// Command used to simulate finality of a local network of nodes, possibly faulty
+#[cfg(feature = "simnet")]
+#[derive(Debug, Parser)]
+pub struct SimnetCmd {
+    /// The number of simulated nodes
+    #[arg(long, default_value_t = 4)]
+    pub nodes: usize,
+
+    /// The number of sessions to simulate
+    #[arg(long, default_value_t = 10)]
+    pub sessions: u32,
+
+    /// The number of blocks in a session
+    #[arg(long, default_value_t = 30)]
+    pub session_period: u32,
+
+    /// Split the nodes into two halves during the sessions, e.g. `3-5`. Can be repeated
+    #[arg(long, value_parser = parse_simnet_fault::<SimnetSessionRange>)]
+    pub partition: Vec<SimnetSessionRange>,
+
+    /// Take the node offline during the sessions, e.g. `2@3-5`. Can be repeated
+    #[arg(long, value_parser = parse_simnet_fault::<SimnetCrash>)]
+    pub crash: Vec<SimnetCrash>,
+
+    /// The number of nodes, counting from the last one, sending invalid justifications
+    #[arg(long, default_value_t = 0)]
+    pub byzantine: usize,
+}
+
+#[cfg(feature = "simnet")]
+fn parse_simnet_fault<T: FromStr<Err = SimnetFaultParseError>>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|e: SimnetFaultParseError| e.to_string())
+}
+
+#[cfg(feature = "simnet")]
+impl SimnetCmd {
+    pub fn run(&self) -> Result<(), Error> {
+        let report = run_simnet(SimnetConfig {
+            nodes: self.nodes,
+            sessions: self.sessions,
+            session_period: SessionPeriod(self.session_period),
+            partitions: self.partition.clone(),
+            crashes: self.crash.clone(),
+            byzantine: self.byzantine,
+        })
+        .map_err(|e| Error::Input(format!("Cannot run the simulation: {e}")))?;
+        print!("{report}");
+        Ok(())
+    }
+}
+
+/// Command used to verify a justification of a block against the authorities of its session, as
+/// recorded in the local database
+#[derive(Debug, Parser)]
+pub struct VerifyJustificationCmd {
+    /// Hash of the block the justification should finalize
+    #[arg(long)]
+    pub block: BlockHash,
+
+    /// Path to a file with the encoded justification, either raw or in hex, as returned by RPC
+    #[arg(long)]
+    pub file: PathBuf,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub shared_params: SharedParams,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub database_params: DatabaseParams,
+}
+
+impl CliConfiguration for VerifyJustificationCmd {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+
+    fn database_params(&self) -> Option<&DatabaseParams> {
+        Some(&self.database_params)
+    }
+}
+
+impl VerifyJustificationCmd {
+    fn encoded_justification(&self) -> Result<Vec<u8>, Error> {
+        let contents = fs::read(&self.file).map_err(|e| {
+            Error::Input(format!("Failed to read justification {:?}: {e}", self.file))
+        })?;
+        let hex_decoded = std::str::from_utf8(&contents).ok().and_then(|text| {
+            let text = text.trim();
+            hex::decode(text.strip_prefix("0x").unwrap_or(text)).ok()
+        });
+        Ok(hex_decoded.unwrap_or(contents))
+    }
+
+    fn block_hash<C: HeaderBackend<Block>>(
+        client: &C,
+        number: BlockNumber,
+    ) -> Result<BlockHash, Error> {
+        client
+            .hash(number)?
+            .ok_or_else(|| Error::Input(format!("Block #{number} is not in the database")))
+    }
+
+    /// The authorities of the session, the same way the node learns them: from the genesis for
+    /// the first session, and from the first block of the previous session for the others. Falls
+    /// back to the calls of older runtimes.
+    fn authority_data<C>(
+        client: &C,
+        session_info: &SessionBoundaryInfo,
+        session: SessionId,
+    ) -> Result<SessionAuthorityData, Error>
+    where
+        C: HeaderBackend<Block> + ProvideRuntimeApi<Block>,
+        C::Api: AlephSessionApi<Block>,
+    {
+        let api = client.runtime_api();
+        let runtime_error = |e: ApiError| Error::Input(format!("Runtime API failure: {e}"));
+        match session {
+            SessionId(0) => {
+                let genesis = Self::block_hash(client, 0)?;
+                match api.authority_data(genesis) {
+                    Ok(authority_data) => Ok(authority_data),
+                    Err(_) => api
+                        .authorities(genesis)
+                        .map(|authorities| SessionAuthorityData::new(authorities, None))
+                        .map_err(runtime_error),
+                }
+            }
+            SessionId(id) => {
+                let previous_first = Self::block_hash(
+                    client,
+                    session_info.first_block_of_session(SessionId(id - 1)),
+                )?;
+                let authority_data = match api.next_session_authority_data(previous_first) {
+                    Ok(authority_data) => authority_data,
+                    Err(_) => api
+                        .next_session_authorities(previous_first)
+                        .map_err(runtime_error)?
+                        .map(|authorities| SessionAuthorityData::new(authorities, None)),
+                };
+                authority_data
+                    .map_err(|e| Error::Input(format!("No authorities for session {id}: {e:?}")))
+            }
+        }
+    }
+
+    pub fn run<C>(&self, client: Arc<C>) -> Result<(), Error>
+    where
+        C: ClientForAleph<Block, TFullBackend<Block>> + Send + Sync + 'static,
+        C::Api: AlephSessionApi<Block>,
+    {
+        let number = client
+            .number(self.block)?
+            .ok_or_else(|| Error::Input(format!("Block {} is not in the database", self.block)))?;
+        let session_period = client
+            .runtime_api()
+            .session_period(self.block)
+            .map_err(|e| Error::Input(format!("Runtime API failure: {e}")))?;
+        let session_period = SessionPeriod(session_period);
+        let session_info = on_chain_session_info(client.clone(), session_period);
+        let session = session_info.session_id_from_block_num(number);
+        let authority_data = Self::authority_data(&*client, &session_info, session)?;
+        let periods = SessionPeriods::new(session_info.period_changes())
+            .map_err(|e| Error::Input(format!("Invalid session lengths: {e}")))?;
+        let verifier = LightVerifier::new(periods, session.0, authority_data.clone());
+        println!("Block #{} {} in session {}", number, self.block, session.0);
+        let justification = verifier
+            .verify_encoded(self.block, number, self.encoded_justification()?)
+            .map_err(|e| Error::Input(format!("The justification is incorrect: {e}")))?;
+        match signers(&authority_data, &justification) {
+            Signers::Committee { signed, members } => {
+                println!(
+                    "Signed by {} of {} committee members:",
+                    signed.len(),
+                    members
+                );
+                for (index, authority) in signed {
+                    match authority {
+                        Some(authority) => println!("  #{index} {}", authority.to_ss58check()),
+                        None => println!("  #{index} outside of the committee"),
+                    }
+                }
+            }
+            Signers::Emergency(finalizer) => {
+                println!(
+                    "Signed by the emergency finalizer {}",
+                    describe(finalizer.as_ref())
+                )
+            }
+            Signers::Custody {
+                finalizer,
+                operator,
+                reason,
+            } => println!(
+                "Signed by the emergency finalizer {}, ordered by operator {} for reason {}",
+                describe(finalizer.as_ref()),
+                operator.to_ss58check(),
+                reason
+            ),
+        }
+        println!("The justification is correct");
+        Ok(())
+    }
+}
+
+/// Command used to compress the justifications archived for pruning before compressing them was
+/// enabled with `--sync-compress-justifications`
+#[derive(Debug, Parser)]
+pub struct CompressJustificationArchiveCmd {
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub shared_params: SharedParams,
+
+    #[allow(missing_docs)]
+    #[clap(flatten)]
+    pub database_params: DatabaseParams,
+}
+
+impl CliConfiguration for CompressJustificationArchiveCmd {
+    fn shared_params(&self) -> &SharedParams {
+        &self.shared_params
+    }
+
+    fn database_params(&self) -> Option<&DatabaseParams> {
+        Some(&self.database_params)
+    }
+}
+
+impl CompressJustificationArchiveCmd {
+    pub fn run<C>(&self, client: Arc<C>) -> Result<(), Error>
+    where
+        C: ClientForAleph<Block, TFullBackend<Block>> + Send + Sync + 'static,
+        C::Api: AlephSessionApi<Block>,
+    {
+        let info = client.info();
+        let session_period = client
+            .runtime_api()
+            .session_period(info.finalized_hash)
+            .map_err(|e| Error::Input(format!("Runtime API failure: {e}")))?;
+        let session_info = on_chain_session_info(client.clone(), SessionPeriod(session_period));
+        let top_session = session_info.session_id_from_block_num(info.finalized_number);
+        let compressed = compress_archived_justifications(&*client, &session_info, top_session)
+            .map_err(|e| {
+                Error::Input(format!(
+                    "Failed to compress the archived justifications: {e}"
+                ))
+            })?;
+        println!("Compressed {compressed} archived justifications");
+        Ok(())
+    }
+}
+
+fn describe(authority: Option<&AlephId>) -> String {
+    authority.map_or_else(
+        || "(none)".to_string(),
+        |authority| authority.to_ss58check(),
+    )
+}
@@ -46,7 +46,43 @@ fn main() -> sc_cli::Result<()> {
         Some(Subcommand::BootstrapChain(cmd)) => cmd.run(),
         Some(Subcommand::BootstrapNode(cmd)) => cmd.run(),
         Some(Subcommand::ConvertChainspecToRaw(cmd)) => cmd.run(),
+        Some(Subcommand::DecodeSyncCapture(cmd)) => cmd.run(),
+        Some(Subcommand::CheckAbftBackup(cmd)) => cmd.run(),
+        #[cfg(feature = "simnet")]
+        Some(Subcommand::Simnet(cmd)) => cmd.run(),
+        #[cfg(not(feature = "simnet"))]
+        Some(Subcommand::Simnet) => Err("Simnet wasn't enabled when building the node. \
+        You can enable it with `--features simnet`."
+            .into()),
         Some(Subcommand::Key(cmd)) => cmd.run(&cli),
+        Some(Subcommand::VerifyJustification(cmd)) => {
+            let runner = cli.create_runner(cmd)?;
+            runner.sync_run(|config| {
+                let PartialComponents { client, .. } = new_partial(&config)?;
+                cmd.run(client)
+            })
+        }
+        Some(Subcommand::CompressJustificationArchive(cmd)) => {
+            let runner = cli.create_runner(cmd)?;
+            runner.sync_run(|config| {
+                let PartialComponents { client, .. } = new_partial(&config)?;
+                cmd.run(client)
+            })
+        }
+        Some(Subcommand::ReplaySyncCapture(cmd)) => {
+            let runner = cli.create_runner(cmd)?;
+            runner.async_run(|mut config| {
+                cmd.use_database_copy(&mut config)?;
+                let PartialComponents {
+                    client,
+                    backend,
+                    task_manager,
+                    import_queue,
+                    ..
+                } = new_partial(&config)?;
+                Ok((cmd.run(client, backend, import_queue), task_manager))
+            })
+        }
         Some(Subcommand::CheckBlock(cmd)) => {
             let runner = cli.create_runner(cmd)?;
             runner.async_run(|config| {
@@ -8,10 +8,15 @@
 use std::sync::Arc;
 
 use aleph_runtime::{opaque::Block, AccountId, Balance, Index};
-use finality_aleph::{Justification, JustificationTranslator};
+use finality_aleph::{
+    BodyBackfill, EmergencyAudit, FinalityStallDiagnostics, Justification, JustificationTranslator,
+    SyncCounters, SyncForestDumps, SyncImportNotifications, SyncJustificationFetches,
+    SyncNetworkView, SyncPeerTracing, SyncProvenance, SyncStatusReports, ValidatorNetworkHealth,
+};
 use futures::channel::mpsc;
 use jsonrpsee::RpcModule;
-use sc_client_api::StorageProvider;
+use sc_client_api::{AuxStore, BlockBackend, StorageProvider};
+use sc_rpc::SubscriptionTaskExecutor;
 pub use sc_rpc_api::DenyUnsafe;
 use sc_transaction_pool_api::TransactionPool;
 use sp_api::ProvideRuntimeApi;
@@ -30,6 +35,32 @@ pub struct FullDeps<C, P, SO> {
     pub import_justification_tx: mpsc::UnboundedSender<Justification>,
     pub justification_translator: JustificationTranslator,
     pub sync_oracle: SO,
+    /// The peers that supplied data about blocks recently finalized by sync.
+    pub sync_provenance: SyncProvenance,
+    /// The switch for detailed tracing of the sync messages of a single peer.
+    pub sync_peer_tracing: SyncPeerTracing,
+    /// The handle for requesting dumps of the sync forest.
+    pub sync_forest_dumps: SyncForestDumps,
+    /// The handle for requesting reports of the sync status.
+    pub sync_status_reports: SyncStatusReports,
+    /// The handle for fetching justifications of finalized blocks from the peers.
+    pub justification_fetches: SyncJustificationFetches,
+    /// The handle for backfilling missing bodies of finalized blocks.
+    pub body_backfill: BodyBackfill,
+    /// The announcements of blocks imported through sync.
+    pub import_notifications: SyncImportNotifications,
+    /// The long-term statistics of sync.
+    pub sync_counters: SyncCounters,
+    /// The view of the finality of the network.
+    pub sync_network_view: SyncNetworkView,
+    /// The emergency justifications accepted recently.
+    pub emergency_audit: EmergencyAudit,
+    /// The connectivity of the validator network.
+    pub validator_network_health: ValidatorNetworkHealth,
+    /// The diagnostic snapshots of finality stalls.
+    pub stall_diagnostics: FinalityStallDiagnostics,
+    /// Where the subscriptions are served.
+    pub subscription_executor: SubscriptionTaskExecutor,
 }
 
 /// Instantiate all full RPC extensions.
@@ -40,7 +71,9 @@ where
     C: ProvideRuntimeApi<Block>
         + HeaderBackend<Block>
         + HeaderMetadata<Block, Error = BlockChainError>
+        + BlockBackend<Block>
         + StorageProvider<Block, BE>
+        + AuxStore
         + Send
         + Sync
         + 'static,
@@ -62,6 +95,19 @@ where
         import_justification_tx,
         justification_translator,
         sync_oracle,
+        sync_provenance,
+        sync_peer_tracing,
+        sync_forest_dumps,
+        sync_status_reports,
+        justification_fetches,
+        body_backfill,
+        import_notifications,
+        sync_counters,
+        sync_network_view,
+        emergency_audit,
+        validator_network_health,
+        stall_diagnostics,
+        subscription_executor,
     } = deps;
 
     module.merge(System::new(client.clone(), pool, deny_unsafe).into_rpc())?;
@@ -75,6 +121,20 @@ where
             justification_translator,
             client,
             sync_oracle,
+            sync_provenance,
+            sync_peer_tracing,
+            sync_forest_dumps,
+            sync_status_reports,
+            justification_fetches,
+            body_backfill,
+            import_notifications,
+            sync_counters,
+            sync_network_view,
+            emergency_audit,
+            validator_network_health,
+            stall_diagnostics,
+            subscription_executor,
+            deny_unsafe,
         )
         .into_rpc(),
     )?;
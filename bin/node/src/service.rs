@@ -7,9 +7,13 @@ use std::{
 
 use aleph_runtime::{self, opaque::Block, RuntimeApi};
 use finality_aleph::{
-    run_validator_node, AlephBlockImport, AlephConfig, BlockImporter, BlockMetrics, Justification,
-    JustificationTranslator, MillisecsPerBlock, Protocol, ProtocolNaming, RateLimiterConfig,
-    SessionPeriod, SubstrateChainStatus, TracingBlockImport,
+    block_sync_requests_config, run_validator_node, AlephBlockImport, AlephConfig, BlockImporter,
+    BlockMetrics, BlockSyncRequests, BodyBackfill, EmergencyAudit, FinalityStallDiagnostics,
+    Justification, JustificationTranslator, MillisecsPerBlock, NetworkLimits, Protocol,
+    ProtocolNaming, SessionPeriod, SubstrateChainStatus, SyncConfig, SyncCounters,
+    SyncFinalizationHooks, SyncForestDumps, SyncForkId, SyncImportNotifications,
+    SyncJustificationFetches, SyncLongestChain, SyncNetworkView, SyncPeerTracing, SyncProvenance,
+    SyncSnapshotTriggers, SyncStatusReports, TracingBlockImport, ValidatorNetworkHealth,
 };
 use futures::channel::mpsc;
 use log::{info, warn};
@@ -31,7 +35,7 @@ use sp_consensus_aura::{sr25519::AuthorityPair as AuraPair, Slot};
 use crate::{
     aleph_cli::AlephCli,
     aleph_primitives::{AlephSessionApi, BlockHash, MAX_BLOCK_SIZE},
-    chain_spec::DEFAULT_BACKUP_FOLDER,
+    chain_spec::{Extensions, DEFAULT_BACKUP_FOLDER},
     executor::AlephExecutor,
     rpc::{create_full as create_full_rpc, FullDeps as RpcFullDeps},
 };
@@ -40,7 +44,26 @@ type FullClient = sc_service::TFullClient<Block, RuntimeApi, AlephExecutor>;
 type FullBackend = sc_service::TFullBackend<Block>;
 type FullSelectChain = sc_consensus::LongestChain<FullBackend, Block>;
 
-struct LimitNonfinalized(u32);
+struct LimitNonfinalized {
+    limit: u32,
+    client: Arc<FullClient>,
+}
+
+impl LimitNonfinalized {
+    // Blocks held back by the finalization depth offset are decided, they should not stop
+    // production. The offset can change with the session, so the larger of the two is used.
+    fn finalization_depth_offset(&self) -> u32 {
+        let finalized = self.client.info().finalized_hash;
+        let runtime_api = self.client.runtime_api();
+        let current = runtime_api
+            .finalization_depth_offset(finalized)
+            .unwrap_or(0);
+        let next = runtime_api
+            .next_session_finalization_depth_offset(finalized)
+            .unwrap_or(0);
+        current.max(next)
+    }
+}
 
 impl<N: BaseArithmetic> BackoffAuthoringBlocksStrategy<N> for LimitNonfinalized {
     fn should_backoff(
@@ -54,9 +77,10 @@ impl<N: BaseArithmetic> BackoffAuthoringBlocksStrategy<N> for LimitNonfinalized
         let nonfinalized_blocks: u32 = chain_head_number
             .saturating_sub(finalized_number)
             .unique_saturated_into();
-        match nonfinalized_blocks >= self.0 {
+        let limit = self.limit.saturating_add(self.finalization_depth_offset());
+        match nonfinalized_blocks >= limit {
             true => {
-                warn!("We have {} nonfinalized blocks, with the limit being {}, delaying block production.", nonfinalized_blocks, self.0);
+                warn!("We have {} nonfinalized blocks, with the limit being {}, delaying block production.", nonfinalized_blocks, limit);
                 true
             }
             false => false,
@@ -219,12 +243,28 @@ fn setup(
     client: Arc<FullClient>,
     telemetry: &mut Option<Telemetry>,
     import_justification_tx: mpsc::UnboundedSender<Justification>,
+    sync_provenance: SyncProvenance,
+    sync_peer_tracing: SyncPeerTracing,
+    sync_forest_dumps: SyncForestDumps,
+    sync_status_reports: SyncStatusReports,
+    justification_fetches: SyncJustificationFetches,
+    body_backfill: BodyBackfill,
+    import_notifications: SyncImportNotifications,
+    sync_counters: SyncCounters,
+    sync_network_view: SyncNetworkView,
+    emergency_audit: EmergencyAudit,
+    validator_network_health: ValidatorNetworkHealth,
+    stall_diagnostics: FinalityStallDiagnostics,
+    network_limits: &NetworkLimits,
+    sync_request_response: bool,
+    fork_id: Option<&str>,
 ) -> Result<
     (
         RpcHandlers,
         Arc<NetworkService<Block, BlockHash>>,
         Arc<SyncingService<Block>>,
         ProtocolNaming,
+        Option<BlockSyncRequests>,
         NetworkStarter,
     ),
     ServiceError,
@@ -234,20 +274,29 @@ fn setup(
         .ok()
         .flatten()
         .expect("we should have a hash");
-    let chain_prefix = match config.chain_spec.fork_id() {
-        Some(fork_id) => format!("/{genesis_hash}/{fork_id}"),
-        None => format!("/{genesis_hash}"),
-    };
-    let protocol_naming = ProtocolNaming::new(chain_prefix);
+    let protocol_naming = ProtocolNaming::new(genesis_hash.to_string(), fork_id);
     let mut net_config = sc_network::config::FullNetworkConfiguration::new(&config.network);
     net_config.add_notification_protocol(finality_aleph::peers_set_config(
         protocol_naming.clone(),
         Protocol::Authentication,
+        &network_limits.public,
     ));
     net_config.add_notification_protocol(finality_aleph::peers_set_config(
         protocol_naming.clone(),
         Protocol::BlockSync,
+        &network_limits.public,
     ));
+    let sync_requests = match sync_request_response {
+        true => {
+            let (config, requests) = block_sync_requests_config(
+                &protocol_naming,
+                network_limits.public.max_message_size,
+            );
+            net_config.add_request_response_protocol(config);
+            Some(requests)
+        }
+        false => None,
+    };
 
     let (network, system_rpc_tx, tx_handler_controller, network_starter, sync_network) =
         sc_service::build_network(sc_service::BuildNetworkParams {
@@ -265,7 +314,7 @@ fn setup(
     let rpc_builder = {
         let client = client.clone();
         let pool = transaction_pool.clone();
-        Box::new(move |deny_unsafe, _| {
+        Box::new(move |deny_unsafe, subscription_executor| {
             let deps = RpcFullDeps {
                 client: client.clone(),
                 pool: pool.clone(),
@@ -273,6 +322,19 @@ fn setup(
                 import_justification_tx: import_justification_tx.clone(),
                 justification_translator: JustificationTranslator::new(chain_status.clone()),
                 sync_oracle: sync_oracle.clone(),
+                sync_provenance: sync_provenance.clone(),
+                sync_peer_tracing: sync_peer_tracing.clone(),
+                sync_forest_dumps: sync_forest_dumps.clone(),
+                sync_status_reports: sync_status_reports.clone(),
+                justification_fetches: justification_fetches.clone(),
+                body_backfill: body_backfill.clone(),
+                import_notifications: import_notifications.clone(),
+                sync_counters: sync_counters.clone(),
+                sync_network_view: sync_network_view.clone(),
+                emergency_audit: emergency_audit.clone(),
+                validator_network_health: validator_network_health.clone(),
+                stall_diagnostics: stall_diagnostics.clone(),
+                subscription_executor,
             };
 
             Ok(create_full_rpc(deps)?)
@@ -299,6 +361,7 @@ fn setup(
         network,
         sync_network,
         protocol_naming,
+        sync_requests,
         network_starter,
     ))
 }
@@ -320,6 +383,8 @@ pub fn new_authority(
     } = new_partial(&config)?;
 
     let backup_path = backup_path(&aleph_config, config.base_path.path());
+    let legacy_sync_cutoff = Extensions::try_get(&*config.chain_spec)
+        .and_then(|extensions| extensions.legacy_sync_cutoff);
 
     let finalized = client.info().finalized_hash;
 
@@ -329,25 +394,71 @@ pub fn new_authority(
         MillisecsPerBlock(client.runtime_api().millisecs_per_block(finalized).unwrap());
 
     let force_authoring = config.force_authoring;
-    let backoff_authoring_blocks = Some(LimitNonfinalized(aleph_config.max_nonfinalized_blocks()));
+    let backoff_authoring_blocks = Some(LimitNonfinalized {
+        limit: aleph_config.max_nonfinalized_blocks(),
+        client: client.clone(),
+    });
     let prometheus_registry = config.prometheus_registry().cloned();
 
     let import_queue_handle = BlockImporter(import_queue.service());
 
     let chain_status = SubstrateChainStatus::new(backend.clone())
         .map_err(|e| ServiceError::Other(format!("failed to set up chain status: {e}")))?;
-    let (_rpc_handlers, network, sync_network, protocol_naming, network_starter) = setup(
-        config,
-        backend,
-        chain_status.clone(),
-        &keystore_container,
-        import_queue,
-        transaction_pool.clone(),
-        &mut task_manager,
-        client.clone(),
-        &mut telemetry,
-        justification_tx,
-    )?;
+    let sync_provenance = SyncProvenance::new();
+    let sync_peer_tracing = SyncPeerTracing::new();
+    let sync_forest_dumps = SyncForestDumps::new();
+    let sync_status_reports = SyncStatusReports::new();
+    let justification_fetches = SyncJustificationFetches::new();
+    let body_backfill = BodyBackfill::new();
+    let import_notifications = SyncImportNotifications::default();
+    let sync_counters = SyncCounters::new();
+    let sync_network_view = SyncNetworkView::new();
+    let emergency_audit = EmergencyAudit::new();
+    let validator_network_health = ValidatorNetworkHealth::new();
+    let stall_diagnostics = FinalityStallDiagnostics::new();
+    let node_config = aleph_config
+        .node_config()
+        .map_err(|e| ServiceError::Other(format!("invalid aleph configuration: {e}")))?;
+    let network_limits = node_config.network.limits();
+    let mut sync_limits = node_config.sync.limits();
+    // The local configuration takes precedence over the chain spec.
+    sync_limits.max_message_bytes = sync_limits.max_message_bytes.or_else(|| {
+        Extensions::try_get(&*config.chain_spec)
+            .and_then(|extensions| extensions.sync_max_message_bytes)
+    });
+    let fork_id = node_config
+        .sync
+        .fork_id
+        .clone()
+        .or_else(|| config.chain_spec.fork_id().map(String::from));
+    let (_rpc_handlers, network, sync_network, protocol_naming, sync_requests, network_starter) =
+        setup(
+            config,
+            backend,
+            chain_status.clone(),
+            &keystore_container,
+            import_queue,
+            transaction_pool.clone(),
+            &mut task_manager,
+            client.clone(),
+            &mut telemetry,
+            justification_tx,
+            sync_provenance.clone(),
+            sync_peer_tracing.clone(),
+            sync_forest_dumps.clone(),
+            sync_status_reports.clone(),
+            justification_fetches.clone(),
+            body_backfill.clone(),
+            import_notifications.clone(),
+            sync_counters.clone(),
+            sync_network_view.clone(),
+            emergency_audit.clone(),
+            validator_network_health.clone(),
+            stall_diagnostics.clone(),
+            &network_limits,
+            node_config.sync.request_response,
+            fork_id.as_deref(),
+        )?;
 
     let mut proposer_factory = sc_basic_authorship::ProposerFactory::new(
         task_manager.spawn_handle(),
@@ -398,11 +509,57 @@ pub fn new_authority(
         panic!("Cannot run a validator node without external addresses, stopping.");
     }
 
-    let rate_limiter_config = RateLimiterConfig {
-        alephbft_bit_rate_per_connection: aleph_config
-            .alephbft_bit_rate_per_connection()
-            .try_into()
-            .unwrap_or(usize::MAX),
+    let sync_config = SyncConfig {
+        // Light sync nodes do not have the bodies, not even the ones of the latest blocks.
+        archive_bodies: !aleph_config.experimental_pruning() && !node_config.sync.light_sync,
+        limits: sync_limits,
+        fork_id: SyncForkId::new(fork_id.as_deref()),
+        provenance: sync_provenance,
+        peer_tracing: sync_peer_tracing,
+        forest_dumps: sync_forest_dumps,
+        status_reports: sync_status_reports,
+        justification_fetches,
+        body_backfill,
+        import_notifications,
+        finalization_hooks: SyncFinalizationHooks::default(),
+        chain_selection: Arc::new(SyncLongestChain),
+        snapshot_triggers: SyncSnapshotTriggers::new(node_config.sync.snapshot_every_sessions()),
+        counters: sync_counters,
+        network_view: sync_network_view,
+        snapshot_export_path: node_config.sync.snapshot_export_path.clone(),
+        legacy_cutoff: legacy_sync_cutoff,
+        v1_shim: node_config
+            .sync
+            .v1_shim()
+            .map_err(|e| ServiceError::Other(format!("invalid aleph configuration: {e}")))?,
+        capture: node_config.sync.capture(),
+        priority_key_phrase: node_config
+            .sync
+            .priority_key_phrase()
+            .map_err(|e| ServiceError::Other(format!("invalid aleph configuration: {e}")))?,
+        priority_followers: node_config
+            .sync
+            .priority_followers
+            .iter()
+            .cloned()
+            .collect(),
+        headers_first: node_config.sync.headers_first,
+        warp_sync: node_config.sync.warp_sync,
+        parallel_download: node_config.sync.parallel_download,
+        session_pipeline: node_config.sync.session_pipeline,
+        requests: sync_requests,
+        finalization_batch: node_config.sync.finalization_batch,
+        dry_run: node_config.sync.dry_run,
+        light_sync: node_config.sync.light_sync,
+        emergency_custody: node_config.sync.emergency_custody(),
+        emergency_audit,
+        verification_sampling: node_config.sync.verification_sampling(),
+        justification_retention: node_config.sync.justification_retention,
+        compress_justifications: node_config.sync.compress_justifications,
+        block_source: node_config
+            .sync
+            .block_source()
+            .map_err(|e| ServiceError::Other(format!("invalid aleph configuration: {e}")))?,
     };
 
     let aleph_config = AlephConfig {
@@ -423,8 +580,19 @@ pub fn new_authority(
         backup_saving_path: backup_path,
         external_addresses: aleph_config.external_addresses(),
         validator_port: aleph_config.validator_port(),
+        validator_listen_address: aleph_config.validator_listen_address(),
+        validator_outgoing_address: aleph_config.validator_outgoing_address(),
         protocol_naming,
-        rate_limiter_config,
+        network_limits,
+        sync_config,
+        head_push: node_config
+            .head_push_endpoint()
+            .map_err(|e| ServiceError::Other(format!("invalid aleph configuration: {e}")))?,
+        disk_quota: node_config.disk_quota(),
+        validator_network_health,
+        finality_stall_timeout: node_config.finality_stall_timeout(),
+        stall_diagnostics,
+        status_page: node_config.status_page_address,
     };
 
     task_manager.spawn_essential_handle().spawn_blocking(
@@ -23,7 +23,7 @@ pub use frame_support::{
 use frame_support::{
     sp_runtime::Perquintill,
     traits::{
-        ConstBool, ConstU32, EqualPrivilegeOnly, EstimateNextSessionRotation, SortedMembers,
+        ConstBool, ConstU32, EqualPrivilegeOnly, EstimateNextSessionRotation, Get, SortedMembers,
         U128CurrencyToVote, WithdrawReasons,
     },
     weights::constants::WEIGHT_REF_TIME_PER_MILLIS,
@@ -40,9 +40,9 @@ use primitives::{
     staking::MAX_NOMINATORS_REWARDED_PER_VALIDATOR, wrap_methods, ApiError as AlephApiError,
     AuthorityId as AlephId, Block as AlephBlock, BlockId as AlephBlockId,
     BlockNumber as AlephBlockNumber, Header as AlephHeader, SessionAuthorityData, SessionCommittee,
-    SessionIndex, SessionInfoProvider, SessionValidatorError, Version as FinalityVersion,
-    ADDRESSES_ENCODING, DEFAULT_BAN_REASON_LENGTH, DEFAULT_MAX_WINNERS, DEFAULT_SESSIONS_PER_ERA,
-    DEFAULT_SESSION_PERIOD, MAX_BLOCK_SIZE, MILLISECS_PER_BLOCK, TOKEN,
+    SessionIndex, SessionInfoProvider, SessionPeriodChange, SessionValidatorError, SyncParams,
+    Version as FinalityVersion, ADDRESSES_ENCODING, DEFAULT_BAN_REASON_LENGTH, DEFAULT_MAX_WINNERS,
+    DEFAULT_SESSIONS_PER_ERA, DEFAULT_SESSION_PERIOD, MAX_BLOCK_SIZE, MILLISECS_PER_BLOCK, TOKEN,
 };
 pub use primitives::{AccountId, AccountIndex, Balance, Hash, Index, Signature};
 use sp_api::impl_runtime_apis;
@@ -93,7 +93,7 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
     spec_name: create_runtime_str!("aleph-node"),
     impl_name: create_runtime_str!("aleph-node"),
     authoring_version: 1,
-    spec_version: 56,
+    spec_version: 57,
     impl_version: 1,
     apis: RUNTIME_API_VERSIONS,
     transaction_version: 16,
@@ -329,6 +329,7 @@ impl pallet_aleph::Config for Runtime {
         Runtime,
     >;
     type NextSessionAuthorityProvider = Session;
+    type SessionPeriod = SessionPeriod;
 }
 
 #[cfg(feature = "liminal")]
@@ -362,6 +363,14 @@ parameter_types! {
     pub const MaxWinners: u32 = DEFAULT_MAX_WINNERS;
 }
 
+/// The length of the current session, which root can change.
+pub struct CurrentSessionPeriod;
+impl Get<u32> for CurrentSessionPeriod {
+    fn get() -> u32 {
+        Aleph::current_session_period()
+    }
+}
+
 impl pallet_elections::Config for Runtime {
     type RuntimeEvent = RuntimeEvent;
     type DataProvider = Staking;
@@ -378,21 +387,17 @@ impl pallet_committee_management::Config for Runtime {
     type ValidatorRewardsHandler = Staking;
     type ValidatorExtractor = Staking;
     type FinalityCommitteeManager = Aleph;
-    type SessionPeriod = SessionPeriod;
+    type SessionPeriod = CurrentSessionPeriod;
 }
 
 impl pallet_insecure_randomness_collective_flip::Config for Runtime {}
 
-parameter_types! {
-    pub const Offset: u32 = 0;
-}
-
 impl pallet_session::Config for Runtime {
     type RuntimeEvent = RuntimeEvent;
     type ValidatorId = <Self as frame_system::Config>::AccountId;
     type ValidatorIdOf = pallet_staking::StashOf<Self>;
-    type ShouldEndSession = pallet_session::PeriodicSessions<SessionPeriod, Offset>;
-    type NextSessionRotation = pallet_session::PeriodicSessions<SessionPeriod, Offset>;
+    type ShouldEndSession = Aleph;
+    type NextSessionRotation = Aleph;
     type SessionManager = Aleph;
     type SessionHandler = <SessionKeys as OpaqueKeys>::KeyTypeIdProviders;
     type Keys = SessionKeys;
@@ -974,6 +979,7 @@ impl_runtime_apis! {
         }
     }
 
+    #[api_version(3)]
     impl primitives::AlephSessionApi<Block> for Runtime {
         fn millisecs_per_block() -> u64 {
             MILLISECS_PER_BLOCK
@@ -1020,6 +1026,22 @@ impl_runtime_apis! {
         ) -> Result<SessionCommittee<AccountId>, SessionValidatorError> {
             CommitteeManagement::predict_session_committee_for_session(session)
         }
+
+        fn sync_params() -> SyncParams {
+            Aleph::sync_params()
+        }
+
+        fn finalization_depth_offset() -> AlephBlockNumber {
+            Aleph::finalization_depth_offset()
+        }
+
+        fn next_session_finalization_depth_offset() -> AlephBlockNumber {
+            Aleph::next_session_finalization_depth_offset()
+        }
+
+        fn session_period_changes() -> Vec<SessionPeriodChange> {
+            Aleph::session_period_changes()
+        }
     }
 
     impl pallet_nomination_pools_runtime_api::NominationPoolsApi<Block, AccountId, Balance> for Runtime {
@@ -1156,7 +1178,6 @@ impl_runtime_apis! {
 
 #[cfg(test)]
 mod tests {
-    use frame_support::traits::Get;
     use primitives::HEAP_PAGES;
     use smallvec::Array;
 
@@ -0,0 +1,248 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::PublicKey;
+
+/// How long a connected peer can send us nothing before we consider it silent. Peers send
+/// heartbeats every five seconds when they have no data, so this is two missed heartbeats, half
+/// of what it takes for the connection to be dropped.
+pub const SILENCE_THRESHOLD: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+struct Connectivity {
+    outgoing: bool,
+    connections: u32,
+    connected_since: Option<Instant>,
+    last_sent: Option<Instant>,
+    last_received: Option<Instant>,
+    dial_failures: u32,
+    consecutive_dial_failures: u32,
+    silent_redials: u32,
+}
+
+impl Connectivity {
+    fn new(outgoing: bool) -> Self {
+        Connectivity {
+            outgoing,
+            connections: 0,
+            connected_since: None,
+            last_sent: None,
+            last_received: None,
+            dial_failures: 0,
+            consecutive_dial_failures: 0,
+            silent_redials: 0,
+        }
+    }
+
+    fn silent(&self, now: Instant) -> bool {
+        self.connections > 0
+            && self
+                .last_received
+                .map(|received| now.saturating_duration_since(received) >= SILENCE_THRESHOLD)
+                .unwrap_or(true)
+    }
+}
+
+/// The connectivity of a single peer, at the time of the report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerHealth<PK: PublicKey> {
+    pub peer: PK,
+    /// Whether we dial the peer, rather than wait for it to connect to us.
+    pub outgoing: bool,
+    pub connected: bool,
+    pub connected_for: Option<Duration>,
+    pub since_sent: Option<Duration>,
+    pub since_received: Option<Duration>,
+    /// Whether the peer is connected, but did not send us anything for a while.
+    pub silent: bool,
+    pub dial_failures: u32,
+    /// The failures since we were last connected, they make us back off when dialing.
+    pub consecutive_dial_failures: u32,
+    /// How many times we dropped the connection and dialed again, because the peer went silent.
+    pub silent_redials: u32,
+}
+
+/// The connectivity of all the peers the network should be connected to: when anything was last
+/// successfully sent to and received from them, and how dialing them goes. Can be cloned and
+/// queried while the network is running.
+#[derive(Clone)]
+pub struct ConnectionHealth<PK: PublicKey> {
+    peers: Arc<Mutex<HashMap<PK, Connectivity>>>,
+}
+
+impl<PK: PublicKey> ConnectionHealth<PK> {
+    pub fn new() -> Self {
+        ConnectionHealth {
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Starts tracking the peer, or updates the direction of the connection with it.
+    pub(crate) fn track(&self, peer: PK, outgoing: bool) {
+        self.peers
+            .lock()
+            .entry(peer)
+            .or_insert_with(|| Connectivity::new(outgoing))
+            .outgoing = outgoing;
+    }
+
+    pub(crate) fn forget(&self, peer: &PK) {
+        self.peers.lock().remove(peer);
+    }
+
+    fn update(&self, peer: &PK, update: impl FnOnce(&mut Connectivity)) {
+        if let Some(connectivity) = self.peers.lock().get_mut(peer) {
+            update(connectivity);
+        }
+    }
+
+    pub(crate) fn connected(&self, peer: &PK) {
+        let now = Instant::now();
+        self.update(peer, |connectivity| {
+            connectivity.connections += 1;
+            connectivity.connected_since = Some(now);
+            // A fresh connection is not silent, even if the peer has nothing to say.
+            connectivity.last_received = Some(now);
+            connectivity.consecutive_dial_failures = 0;
+        });
+    }
+
+    pub(crate) fn disconnected(&self, peer: &PK) {
+        self.update(peer, |connectivity| {
+            connectivity.connections = connectivity.connections.saturating_sub(1);
+            if connectivity.connections == 0 {
+                connectivity.connected_since = None;
+            }
+        });
+    }
+
+    pub(crate) fn sent(&self, peer: &PK) {
+        let now = Instant::now();
+        self.update(peer, |connectivity| connectivity.last_sent = Some(now));
+    }
+
+    pub(crate) fn received(&self, peer: &PK) {
+        let now = Instant::now();
+        self.update(peer, |connectivity| connectivity.last_received = Some(now));
+    }
+
+    /// Records a failed attempt at connecting to the peer, returns how many attempts failed in a
+    /// row.
+    pub(crate) fn dial_failed(&self, peer: &PK) -> u32 {
+        let mut peers = self.peers.lock();
+        match peers.get_mut(peer) {
+            Some(connectivity) => {
+                connectivity.dial_failures += 1;
+                connectivity.consecutive_dial_failures += 1;
+                connectivity.consecutive_dial_failures
+            }
+            None => 1,
+        }
+    }
+
+    pub(crate) fn redialed(&self, peer: &PK) {
+        self.update(peer, |connectivity| connectivity.silent_redials += 1);
+    }
+
+    /// The connected peers that did not send us anything for at least the silence threshold.
+    pub(crate) fn silent(&self, now: Instant) -> Vec<PK> {
+        self.peers
+            .lock()
+            .iter()
+            .filter(|(_, connectivity)| connectivity.silent(now))
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+
+    /// The connectivity of all the tracked peers, the ones we did not hear from for the longest
+    /// first.
+    pub fn report(&self) -> Vec<PeerHealth<PK>> {
+        let now = Instant::now();
+        let since = |at: Option<Instant>| at.map(|at| now.saturating_duration_since(at));
+        let mut report: Vec<_> = self
+            .peers
+            .lock()
+            .iter()
+            .map(|(peer, connectivity)| PeerHealth {
+                peer: peer.clone(),
+                outgoing: connectivity.outgoing,
+                connected: connectivity.connections > 0,
+                connected_for: since(connectivity.connected_since),
+                since_sent: since(connectivity.last_sent),
+                since_received: since(connectivity.last_received),
+                silent: connectivity.silent(now),
+                dial_failures: connectivity.dial_failures,
+                consecutive_dial_failures: connectivity.consecutive_dial_failures,
+                silent_redials: connectivity.silent_redials,
+            })
+            .collect();
+        report.sort_by_key(|health| Reverse(health.since_received.unwrap_or(Duration::MAX)));
+        report
+    }
+}
+
+impl<PK: PublicKey> Default for ConnectionHealth<PK> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{ConnectionHealth, SILENCE_THRESHOLD};
+    use crate::mock::key;
+
+    #[test]
+    fn tracks_connections_and_dial_failures() {
+        let health = ConnectionHealth::new();
+        let (peer, _) = key();
+        let (untracked, _) = key();
+        health.track(peer.clone(), true);
+        assert_eq!(health.dial_failed(&peer), 1);
+        assert_eq!(health.dial_failed(&peer), 2);
+        health.connected(&peer);
+        health.connected(&untracked);
+        health.sent(&peer);
+        let report = health.report();
+        assert_eq!(report.len(), 1);
+        let peer_health = &report[0];
+        assert_eq!(peer_health.peer, peer);
+        assert!(peer_health.outgoing);
+        assert!(peer_health.connected);
+        assert!(peer_health.since_sent.is_some());
+        assert_eq!(peer_health.dial_failures, 2);
+        assert_eq!(peer_health.consecutive_dial_failures, 0);
+        // A replaced connection ends after the new one starts.
+        health.connected(&peer);
+        health.disconnected(&peer);
+        assert!(health.report()[0].connected);
+        health.disconnected(&peer);
+        assert!(!health.report()[0].connected);
+        health.forget(&peer);
+        assert!(health.report().is_empty());
+    }
+
+    #[test]
+    fn recognizes_silent_peers() {
+        let health = ConnectionHealth::new();
+        let (connected, _) = key();
+        let (disconnected, _) = key();
+        health.track(connected.clone(), true);
+        health.track(disconnected.clone(), false);
+        health.connected(&connected);
+        let now = Instant::now();
+        assert!(health.silent(now).is_empty());
+        let later = now + SILENCE_THRESHOLD + Duration::from_secs(1);
+        assert_eq!(health.silent(later), vec![connected.clone()]);
+        health.received(&connected);
+        assert!(health.silent(Instant::now()).is_empty());
+    }
+}
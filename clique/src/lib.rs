@@ -10,6 +10,7 @@ use parity_scale_codec::Codec;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 mod crypto;
+mod health;
 mod incoming;
 mod io;
 mod manager;
@@ -23,6 +24,7 @@ mod service;
 mod testing;
 
 pub use crypto::{PublicKey, SecretKey};
+pub use health::{ConnectionHealth, PeerHealth};
 pub use rate_limiting::{RateLimitingDialer, RateLimitingListener};
 pub use service::{Service, SpawnHandleT};
 
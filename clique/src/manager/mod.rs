@@ -214,6 +214,14 @@ impl<PK: PublicKey + PeerId, A: Data, D: Data> Manager<PK, A, D> {
         self.have.remove(peer_id);
     }
 
+    /// Close the connection with a peer, while still wanting to be connected with it. Returns
+    /// whether there was a working connection to close.
+    pub fn drop_connection(&mut self, peer_id: &PK) -> bool {
+        let active = self.active_connection(peer_id);
+        self.have.remove(peer_id);
+        active
+    }
+
     /// Send data to a peer.
     /// Returns error if there is no outgoing connection to the peer,
     /// or if the connection is dead.
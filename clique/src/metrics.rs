@@ -1,4 +1,4 @@
-use substrate_prometheus_endpoint::{register, Gauge, PrometheusError, Registry, U64};
+use substrate_prometheus_endpoint::{register, Counter, Gauge, PrometheusError, Registry, U64};
 
 #[derive(Clone)]
 pub enum Metrics {
@@ -7,6 +7,9 @@ pub enum Metrics {
         missing_incoming_connections: Gauge<U64>,
         outgoing_connections: Gauge<U64>,
         missing_outgoing_connections: Gauge<U64>,
+        silent_connections: Gauge<U64>,
+        dial_failures: Counter<U64>,
+        silent_redials: Counter<U64>,
     },
     Noop,
 }
@@ -20,6 +23,8 @@ pub enum Event {
     ConnectedIncoming,
     DisconnectedOutgoing,
     DisconnectedIncoming,
+    DialFailed,
+    SilentRedial,
 }
 
 impl Metrics {
@@ -54,6 +59,27 @@ impl Metrics {
                     )?,
                     &registry,
                 )?,
+                silent_connections: register(
+                    Gauge::new(
+                        "clique_network_silent_connections",
+                        "present connections with peers that did not send anything recently",
+                    )?,
+                    &registry,
+                )?,
+                dial_failures: register(
+                    Counter::new(
+                        "clique_network_dial_failures",
+                        "failed attempts at connecting to peers",
+                    )?,
+                    &registry,
+                )?,
+                silent_redials: register(
+                    Counter::new(
+                        "clique_network_silent_redials",
+                        "connections dropped and dialed again, because the peer went silent",
+                    )?,
+                    &registry,
+                )?,
             }),
             None => Ok(Metrics::Noop),
         }
@@ -70,6 +96,9 @@ impl Metrics {
             outgoing_connections,
             missing_incoming_connections,
             missing_outgoing_connections,
+            dial_failures,
+            silent_redials,
+            ..
         } = self
         {
             match event {
@@ -93,7 +122,18 @@ impl Metrics {
                     outgoing_connections.dec();
                     missing_outgoing_connections.inc();
                 }
+                DialFailed => dial_failures.inc(),
+                SilentRedial => silent_redials.inc(),
             }
         }
     }
+
+    pub fn report_silent_connections(&self, count: usize) {
+        if let Metrics::Prometheus {
+            silent_connections, ..
+        } = self
+        {
+            silent_connections.set(count as u64);
+        }
+    }
 }
@@ -5,7 +5,8 @@ use log::{debug, info};
 use tokio::time::{sleep, timeout, Duration};
 
 use crate::{
-    metrics::Metrics,
+    health::ConnectionHealth,
+    metrics::{Event, Metrics},
     protocols::{protocol, ProtocolError, ProtocolNegotiationError, ResultForService},
     ConnectionInfo, Data, Dialer, PeerAddressInfo, PublicKey, SecretKey, LOG_TARGET,
 };
@@ -32,6 +33,18 @@ impl<PK: PublicKey, A: Data, ND: Dialer<A>> Display for OutgoingError<PK, A, ND>
     }
 }
 
+impl<PK: PublicKey, A: Data, ND: Dialer<A>> OutgoingError<PK, A, ND> {
+    /// Whether we failed to connect at all, rather than the connection breaking afterwards.
+    fn is_dial_failure(&self) -> bool {
+        use OutgoingError::*;
+        match self {
+            Dial(_) | TimedOut | ProtocolNegotiation(_, _) => true,
+            Protocol(_, ProtocolError::HandshakeError(_)) => true,
+            Protocol(_, _) => false,
+        }
+    }
+}
+
 /// Arbitrarily chosen timeout, should be more than enough.
 const DIAL_TIMEOUT: Duration = Duration::from_secs(60);
 
@@ -43,6 +56,7 @@ async fn manage_outgoing<SK: SecretKey, D: Data, A: Data, ND: Dialer<A>>(
     result_for_parent: mpsc::UnboundedSender<ResultForService<SK::PublicKey, D>>,
     data_for_user: mpsc::UnboundedSender<D>,
     metrics: Metrics,
+    health: ConnectionHealth<SK::PublicKey>,
 ) -> Result<(), OutgoingError<SK::PublicKey, A, ND>> {
     debug!(target: LOG_TARGET, "Trying to connect to {}.", public_key);
     let stream = timeout(DIAL_TIMEOUT, dialer.connect(address))
@@ -66,16 +80,27 @@ async fn manage_outgoing<SK: SecretKey, D: Data, A: Data, ND: Dialer<A>>(
             result_for_parent,
             data_for_user,
             metrics,
+            health,
         )
         .await
         .map_err(|e| OutgoingError::Protocol(peer_address_info.clone(), e))
 }
 
+/// How long we wait before dialing again after a connection broke, quickly, since the peer was
+/// reachable moments ago.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// The delay doubles with every failed dial up to this.
 const RETRY_DELAY: Duration = Duration::from_secs(10);
 
+fn retry_delay(consecutive_dial_failures: u32) -> Duration {
+    let doublings = consecutive_dial_failures.saturating_sub(1).min(16);
+    (MIN_RETRY_DELAY * 2u32.pow(doublings)).min(RETRY_DELAY)
+}
+
 /// Establish an outgoing connection to the provided peer using the dialer and then manage it.
 /// While this works it will send any data from the user to the peer. Any failures will be reported
-/// to the parent, so that connections can be reestablished if necessary.
+/// to the parent, so that connections can be reestablished if necessary, after a delay growing with
+/// the failed dials in a row.
 pub async fn outgoing<SK: SecretKey, D: Data, A: Data + Debug, ND: Dialer<A>>(
     secret_key: SK,
     public_key: SK::PublicKey,
@@ -84,6 +109,7 @@ pub async fn outgoing<SK: SecretKey, D: Data, A: Data + Debug, ND: Dialer<A>>(
     result_for_parent: mpsc::UnboundedSender<ResultForService<SK::PublicKey, D>>,
     data_for_user: mpsc::UnboundedSender<D>,
     metrics: Metrics,
+    health: ConnectionHealth<SK::PublicKey>,
 ) {
     if let Err(e) = manage_outgoing(
         secret_key,
@@ -92,19 +118,27 @@ pub async fn outgoing<SK: SecretKey, D: Data, A: Data + Debug, ND: Dialer<A>>(
         address.clone(),
         result_for_parent.clone(),
         data_for_user,
-        metrics,
+        metrics.clone(),
+        health.clone(),
     )
     .await
     {
+        let delay = match e.is_dial_failure() {
+            true => {
+                metrics.report_event(Event::DialFailed);
+                retry_delay(health.dial_failed(&public_key))
+            }
+            false => MIN_RETRY_DELAY,
+        };
         info!(
             target: LOG_TARGET,
             "Outgoing connection to {} {:?} failed: {}, will retry after {}s.",
             public_key,
             address,
             e,
-            RETRY_DELAY.as_secs()
+            delay.as_secs()
         );
-        sleep(RETRY_DELAY).await;
+        sleep(delay).await;
         if result_for_parent
             .unbounded_send((public_key, None))
             .is_err()
@@ -113,3 +147,18 @@ pub async fn outgoing<SK: SecretKey, D: Data, A: Data + Debug, ND: Dialer<A>>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_delay, MIN_RETRY_DELAY, RETRY_DELAY};
+
+    #[test]
+    fn backs_off_with_failed_dials() {
+        assert_eq!(retry_delay(0), MIN_RETRY_DELAY);
+        assert_eq!(retry_delay(1), MIN_RETRY_DELAY);
+        assert_eq!(retry_delay(2), 2 * MIN_RETRY_DELAY);
+        assert_eq!(retry_delay(3), 4 * MIN_RETRY_DELAY);
+        assert_eq!(retry_delay(5), RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), RETRY_DELAY);
+    }
+}
@@ -3,6 +3,7 @@ use std::fmt::{Display, Error as FmtError, Formatter};
 use futures::channel::{mpsc, oneshot};
 
 use crate::{
+    health::ConnectionHealth,
     io::{ReceiveError, SendError},
     metrics::Metrics,
     Data, PublicKey, SecretKey, Splittable,
@@ -12,7 +13,7 @@ mod handshake;
 mod negotiation;
 mod v1;
 
-use handshake::HandshakeError;
+pub use handshake::HandshakeError;
 pub use negotiation::{protocol, ProtocolNegotiationError};
 
 pub type Version = u32;
@@ -87,10 +88,10 @@ impl<PK: PublicKey> From<ReceiveError> for ProtocolError<PK> {
 
 impl Protocol {
     /// Minimal supported protocol version.
-    const MIN_VERSION: Version = 1;
+    pub const MIN_VERSION: Version = 1;
 
     /// Maximal supported protocol version.
-    const MAX_VERSION: Version = 1;
+    pub const MAX_VERSION: Version = 1;
 
     /// Launches the proper variant of the protocol (receiver half).
     pub async fn manage_incoming<SK: SecretKey, D: Data, S: Splittable>(
@@ -104,6 +105,7 @@ impl Protocol {
             oneshot::Sender<bool>,
         )>,
         metrics: Metrics,
+        health: ConnectionHealth<SK::PublicKey>,
     ) -> Result<(), ProtocolError<SK::PublicKey>> {
         use Protocol::*;
         match self {
@@ -115,6 +117,7 @@ impl Protocol {
                     result_for_parent,
                     data_for_user,
                     metrics,
+                    health,
                 )
                 .await
             }
@@ -130,6 +133,7 @@ impl Protocol {
         result_for_service: mpsc::UnboundedSender<ResultForService<SK::PublicKey, D>>,
         data_for_user: mpsc::UnboundedSender<D>,
         metrics: Metrics,
+        health: ConnectionHealth<SK::PublicKey>,
     ) -> Result<(), ProtocolError<SK::PublicKey>> {
         use Protocol::*;
         match self {
@@ -141,6 +145,7 @@ impl Protocol {
                     result_for_service,
                     data_for_user,
                     metrics,
+                    health,
                 )
                 .await
             }
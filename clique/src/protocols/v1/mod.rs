@@ -10,6 +10,7 @@ use tokio::{
 };
 
 use crate::{
+    health::ConnectionHealth,
     io::{receive_data, send_data},
     metrics::{Event, Metrics},
     protocols::{
@@ -44,6 +45,8 @@ async fn check_authorization<SK: SecretKey>(
 async fn sending<PK: PublicKey, D: Data, S: AsyncWrite + Unpin + Send>(
     mut sender: S,
     mut data_from_user: mpsc::UnboundedReceiver<D>,
+    public_key: &PK,
+    health: &ConnectionHealth<PK>,
 ) -> Result<(), ProtocolError<PK>> {
     use Message::*;
     loop {
@@ -61,12 +64,15 @@ async fn sending<PK: PublicKey, D: Data, S: AsyncWrite + Unpin + Send>(
         )
         .await
         .map_err(|_| ProtocolError::SendTimeout)??;
+        health.sent(public_key);
     }
 }
 
 async fn receiving<PK: PublicKey, D: Data, S: AsyncRead + Unpin + Send>(
     mut stream: S,
     data_for_user: mpsc::UnboundedSender<D>,
+    public_key: &PK,
+    health: &ConnectionHealth<PK>,
 ) -> Result<(), ProtocolError<PK>> {
     use Message::*;
     loop {
@@ -77,6 +83,7 @@ async fn receiving<PK: PublicKey, D: Data, S: AsyncRead + Unpin + Send>(
         .await
         .map_err(|_| ProtocolError::CardiacArrest)??;
         stream = old_stream;
+        health.received(public_key);
         match message {
             Data(data) => data_for_user
                 .unbounded_send(data)
@@ -96,9 +103,11 @@ async fn manage_connection<
     receiver: R,
     data_from_user: mpsc::UnboundedReceiver<D>,
     data_for_user: mpsc::UnboundedSender<D>,
+    public_key: &PK,
+    health: &ConnectionHealth<PK>,
 ) -> Result<(), ProtocolError<PK>> {
-    let sending = sending(sender, data_from_user);
-    let receiving = receiving(receiver, data_for_user);
+    let sending = sending(sender, data_from_user, public_key, health);
+    let receiving = receiving(receiver, data_for_user, public_key, health);
     tokio::select! {
         result = receiving => result,
         result = sending => result,
@@ -114,6 +123,7 @@ pub async fn outgoing<SK: SecretKey, D: Data, S: Splittable>(
     result_for_parent: mpsc::UnboundedSender<ResultForService<SK::PublicKey, D>>,
     data_for_user: mpsc::UnboundedSender<D>,
     metrics: Metrics,
+    health: ConnectionHealth<SK::PublicKey>,
 ) -> Result<(), ProtocolError<SK::PublicKey>> {
     use Event::*;
     trace!(target: LOG_TARGET, "Extending hand to {}.", public_key);
@@ -127,13 +137,23 @@ pub async fn outgoing<SK: SecretKey, D: Data, S: Splittable>(
         .unbounded_send((public_key.clone(), Some(data_for_network)))
         .map_err(|_| ProtocolError::NoParentConnection)?;
     metrics.report_event(ConnectedOutgoing);
+    health.connected(&public_key);
 
     debug!(
         target: LOG_TARGET,
         "Starting worker for communicating with {}.", public_key
     );
-    let result = manage_connection(sender, receiver, data_from_user, data_for_user).await;
+    let result = manage_connection(
+        sender,
+        receiver,
+        data_from_user,
+        data_for_user,
+        &public_key,
+        &health,
+    )
+    .await;
     metrics.report_event(DisconnectedOutgoing);
+    health.disconnected(&public_key);
     result
 }
 
@@ -147,6 +167,7 @@ pub async fn incoming<SK: SecretKey, D: Data, S: Splittable>(
     result_for_parent: mpsc::UnboundedSender<ResultForService<SK::PublicKey, D>>,
     data_for_user: mpsc::UnboundedSender<D>,
     metrics: Metrics,
+    health: ConnectionHealth<SK::PublicKey>,
 ) -> Result<(), ProtocolError<SK::PublicKey>> {
     use Event::*;
     trace!(target: LOG_TARGET, "Waiting for extended hand...");
@@ -165,12 +186,22 @@ pub async fn incoming<SK: SecretKey, D: Data, S: Splittable>(
         .unbounded_send((public_key.clone(), Some(data_for_network)))
         .map_err(|_| ProtocolError::NoParentConnection)?;
     metrics.report_event(ConnectedIncoming);
+    health.connected(&public_key);
     debug!(
         target: LOG_TARGET,
         "Starting worker for communicating with {}.", public_key
     );
-    let result = manage_connection(sender, receiver, data_from_user, data_for_user).await;
+    let result = manage_connection(
+        sender,
+        receiver,
+        data_from_user,
+        data_for_user,
+        &public_key,
+        &health,
+    )
+    .await;
     metrics.report_event(DisconnectedIncoming);
+    health.disconnected(&public_key);
     result
 }
 
@@ -182,6 +213,7 @@ mod tests {
     };
 
     use crate::{
+        health::ConnectionHealth,
         metrics::Metrics,
         mock::{key, MockPrelims, MockSplittable},
         protocols::{
@@ -208,6 +240,7 @@ mod tests {
             incoming_result_for_service,
             incoming_data_for_user,
             Metrics::noop(),
+            ConnectionHealth::new(),
         ));
         let outgoing_handle = Box::pin(outgoing(
             stream_outgoing,
@@ -216,6 +249,7 @@ mod tests {
             outgoing_result_for_service,
             outgoing_data_for_user,
             Metrics::noop(),
+            ConnectionHealth::new(),
         ));
         MockPrelims {
             id_incoming,
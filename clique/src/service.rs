@@ -1,4 +1,8 @@
-use std::{fmt::Debug, pin::Pin, time::Duration};
+use std::{
+    fmt::Debug,
+    pin::Pin,
+    time::{Duration, Instant},
+};
 
 use futures::{
     channel::{mpsc, oneshot},
@@ -9,15 +13,17 @@ use substrate_prometheus_endpoint::Registry;
 use tokio::time;
 
 use crate::{
+    health::ConnectionHealth,
     incoming::incoming,
     manager::{AddResult, Manager},
-    metrics::Metrics,
+    metrics::{Event, Metrics},
     outgoing::outgoing,
     protocols::ResultForService,
     Data, Dialer, Listener, Network, PeerId, PublicKey, SecretKey, LOG_TARGET,
 };
 
 const STATUS_REPORT_INTERVAL: Duration = Duration::from_secs(20);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(2);
 
 enum ServiceCommand<PK: PublicKey, D: Data, A: Data> {
     AddConnection(PK, A),
@@ -98,6 +104,7 @@ where
     spawn_handle: SH,
     secret_key: SK,
     metrics: Metrics,
+    health: ConnectionHealth<SK::PublicKey>,
 }
 
 impl<SK: SecretKey, D: Data, A: Data + Debug, ND: Dialer<A>, NL: Listener, SH: SpawnHandleT>
@@ -105,13 +112,15 @@ impl<SK: SecretKey, D: Data, A: Data + Debug, ND: Dialer<A>, NL: Listener, SH: S
 where
     SK::PublicKey: PeerId,
 {
-    /// Create a new clique network service plus an interface for interacting with it.
+    /// Create a new clique network service plus an interface for interacting with it. The
+    /// connectivity of the peers gets recorded in the provided health.
     pub fn new(
         dialer: ND,
         listener: NL,
         secret_key: SK,
         spawn_handle: SH,
         metrics_registry: Option<Registry>,
+        health: ConnectionHealth<SK::PublicKey>,
     ) -> (Self, impl Network<SK::PublicKey, A, D>) {
         // Channel for sending commands between the service and interface
         let (commands_for_service, commands_from_interface) = mpsc::unbounded();
@@ -134,6 +143,7 @@ where
                 spawn_handle,
                 secret_key,
                 metrics,
+                health,
             },
             ServiceInterface {
                 commands_for_service,
@@ -152,6 +162,7 @@ where
         let dialer = self.dialer.clone();
         let next_to_interface = self.next_to_interface.clone();
         let metrics = self.metrics.clone();
+        let health = self.health.clone();
         self.spawn_handle
             .spawn("aleph/clique_network_outgoing", async move {
                 outgoing(
@@ -162,6 +173,7 @@ where
                     result_for_parent,
                     next_to_interface,
                     metrics,
+                    health,
                 )
                 .await;
             });
@@ -179,6 +191,7 @@ where
         let secret_key = self.secret_key.clone();
         let next_to_interface = self.next_to_interface.clone();
         let metrics = self.metrics.clone();
+        let health = self.health.clone();
         self.spawn_handle
             .spawn("aleph/clique_network_incoming", async move {
                 incoming(
@@ -188,6 +201,7 @@ where
                     next_to_interface,
                     authorization_requests_sender,
                     metrics,
+                    health,
                 )
                 .await;
             });
@@ -205,9 +219,32 @@ where
         self.manager.add_connection(public_key, data_for_network)
     }
 
+    /// Drops the connections with the peers that went silent and dials them again, if the
+    /// connections are ours to make, the other peers will do the same on their side.
+    fn redial_silent(
+        &mut self,
+        result_for_parent: &mpsc::UnboundedSender<ResultForService<SK::PublicKey, D>>,
+    ) {
+        let silent = self.health.silent(Instant::now());
+        self.metrics.report_silent_connections(silent.len());
+        for public_key in silent {
+            let address = match self.peer_address(&public_key) {
+                Some(address) => address,
+                None => continue,
+            };
+            if self.manager.drop_connection(&public_key) {
+                warn!(target: LOG_TARGET, "Peer {} went silent, dialing it again.", public_key);
+                self.health.redialed(&public_key);
+                self.metrics.report_event(Event::SilentRedial);
+                self.spawn_new_outgoing(public_key, address, result_for_parent.clone());
+            }
+        }
+    }
+
     /// Run the service until a signal from exit.
     pub async fn run(mut self, mut exit: oneshot::Receiver<()>) {
         let mut status_ticker = time::interval(STATUS_REPORT_INTERVAL);
+        let mut health_ticker = time::interval(HEALTH_CHECK_INTERVAL);
         let (result_for_parent, mut worker_results) = mpsc::unbounded();
         let (authorization_requests_sender, mut authorization_requests) = mpsc::unbounded();
         use ServiceCommand::*;
@@ -223,13 +260,16 @@ where
                     // register new peer in manager or update its address if already there
                     // spawn a worker managing outgoing connection if the peer was not known
                     AddConnection(public_key, address) => {
-                        if self.manager.add_peer(public_key.clone(), address.clone()) {
+                        let dialing = self.manager.add_peer(public_key.clone(), address.clone());
+                        self.health.track(public_key.clone(), self.peer_address(&public_key).is_some());
+                        if dialing {
                             self.spawn_new_outgoing(public_key, address, result_for_parent.clone());
                         };
                     },
                     // remove the peer from the manager all workers will be killed automatically, due to closed channels
                     DelConnection(public_key) => {
                         self.manager.remove_peer(&public_key);
+                        self.health.forget(&public_key);
                     },
                     // pass the data to the manager
                     SendData(data, public_key) => {
@@ -264,6 +304,8 @@ where
                 _ = status_ticker.tick() => {
                     info!(target: LOG_TARGET, "Clique Network status: {}", self.manager.status_report());
                 }
+                // periodically looking for peers that went silent
+                _ = health_ticker.tick() => self.redial_silent(&result_for_parent),
                 // received exit signal, stop the network
                 // all workers will be killed automatically after the manager gets dropped
                 _ = &mut exit => break,
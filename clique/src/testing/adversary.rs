@@ -0,0 +1,249 @@
+use std::io::Result as IoResult;
+
+use futures::{channel::mpsc, StreamExt};
+use parity_scale_codec::Encode;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::{sleep, Duration},
+};
+
+use crate::{
+    health::ConnectionHealth,
+    io::{Error as DataError, ReceiveError, MAX_DATA_SIZE},
+    metrics::Metrics,
+    mock::{key, MockData, MockPublicKey, MockSecretKey, MockSplittable},
+    protocols::{
+        protocol, HandshakeError, Protocol, ProtocolError, ProtocolNegotiationError, Version,
+    },
+    SecretKey,
+};
+
+/// The ways in which the adversarial peer breaks the protocol.
+#[derive(Clone, Debug)]
+pub enum Misbehavior {
+    /// Follows the protocol, for checking that the simulation itself is correct.
+    Compliant,
+    /// Offers only protocol versions newer than the ones we support.
+    WrongVersion,
+    /// Finishes the handshake and then announces a message above the size limit.
+    OversizedPayload,
+    /// Sends a handshake response without waiting for the challenge.
+    UnsolicitedResponse,
+    /// Sends the handshake response one byte at a time, waiting the given delay between bytes.
+    SlowLoris(Duration),
+}
+
+/// A peer that dials an honest node and then misbehaves in the specified way. It speaks the wire
+/// format directly, so that it is not constrained by the checks of our own implementation.
+pub struct AdversarialPeer {
+    secret_key: MockSecretKey,
+    misbehavior: Misbehavior,
+}
+
+fn encoded_range(min: Version, max: Version) -> Vec<u8> {
+    [min.to_le_bytes(), max.to_le_bytes()].concat()
+}
+
+fn framed(payload: Vec<u8>) -> Vec<u8> {
+    let mut result = (payload.len() as u32).to_le_bytes().to_vec();
+    result.extend(payload);
+    result
+}
+
+async fn receive_frame<S: AsyncRead + Unpin>(stream: &mut S) -> IoResult<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len).await?;
+    let mut payload = vec![0; u32::from_le_bytes(len) as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+impl AdversarialPeer {
+    pub fn new(secret_key: MockSecretKey, misbehavior: Misbehavior) -> Self {
+        AdversarialPeer {
+            secret_key,
+            misbehavior,
+        }
+    }
+
+    /// The encoded handshake response signing the given encoded challenge.
+    fn response(&self, challenge: &[u8]) -> Vec<u8> {
+        (
+            self.secret_key.public_key(),
+            self.secret_key.sign(challenge),
+        )
+            .encode()
+    }
+
+    async fn negotiate<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        min: Version,
+        max: Version,
+    ) -> IoResult<()> {
+        let mut their_range = [0; 8];
+        stream.write_all(&encoded_range(min, max)).await?;
+        stream.read_exact(&mut their_range).await?;
+        Ok(())
+    }
+
+    /// Runs the peer on a fresh connection. Returns the connection once the peer is done, which
+    /// is usually when the honest node gave up on it, in which case this returns an error.
+    pub async fn run<S: AsyncRead + AsyncWrite + Unpin>(self, mut stream: S) -> IoResult<S> {
+        use Misbehavior::*;
+        let (min, max) = match self.misbehavior {
+            WrongVersion => (Protocol::MAX_VERSION + 1, Protocol::MAX_VERSION + 2),
+            _ => (Protocol::MIN_VERSION, Protocol::MAX_VERSION),
+        };
+        self.negotiate(&mut stream, min, max).await?;
+        match self.misbehavior {
+            Compliant => {
+                let challenge = receive_frame(&mut stream).await?;
+                stream.write_all(&framed(self.response(&challenge))).await?;
+                return Ok(stream);
+            }
+            WrongVersion => (),
+            OversizedPayload => {
+                let challenge = receive_frame(&mut stream).await?;
+                stream.write_all(&framed(self.response(&challenge))).await?;
+                stream.write_all(&(MAX_DATA_SIZE + 1).to_le_bytes()).await?;
+                stream.write_all(&[0; 1024]).await?;
+            }
+            UnsolicitedResponse => {
+                let made_up_challenge = (self.secret_key.public_key(), [0u8; 32]).encode();
+                stream
+                    .write_all(&framed(self.response(&made_up_challenge)))
+                    .await?;
+            }
+            SlowLoris(delay) => {
+                let challenge = receive_frame(&mut stream).await?;
+                for byte in framed(self.response(&challenge)) {
+                    stream.write_all(&[byte]).await?;
+                    sleep(delay).await;
+                }
+            }
+        }
+        // Keep the connection open until the honest node gives up on it.
+        stream.read_to_end(&mut Vec::new()).await?;
+        Ok(stream)
+    }
+}
+
+#[derive(Debug)]
+enum Failure {
+    Negotiation(ProtocolNegotiationError),
+    Protocol(ProtocolError<MockPublicKey>),
+}
+
+/// Runs an honest node accepting the connection until it ends. Returns why it ended and whether
+/// the peer was ever reported to the service.
+async fn accept(stream: MockSplittable, authorize: bool) -> (Failure, bool) {
+    let (_, secret_key) = key();
+    let (result_for_parent, mut results) = mpsc::unbounded();
+    let (data_for_user, _data_from_network) = mpsc::unbounded::<MockData>();
+    let (authorization_requests_sender, mut authorization_requests) = mpsc::unbounded();
+    tokio::spawn(async move {
+        while let Some((_, response)) = authorization_requests.next().await {
+            let _ = response.send(authorize);
+        }
+    });
+    let failure = match protocol(stream).await {
+        Ok((stream, protocol)) => match protocol
+            .manage_incoming(
+                stream,
+                secret_key,
+                result_for_parent,
+                data_for_user,
+                authorization_requests_sender,
+                Metrics::noop(),
+                ConnectionHealth::new(),
+            )
+            .await
+        {
+            Ok(()) => panic!("the connection should not end cleanly"),
+            Err(e) => Failure::Protocol(e),
+        },
+        Err(e) => Failure::Negotiation(e),
+    };
+    let reported = matches!(results.try_next(), Ok(Some(_)));
+    (failure, reported)
+}
+
+async fn run_against_honest(misbehavior: Misbehavior, authorize: bool) -> (Failure, bool) {
+    let (honest_stream, adversary_stream) = MockSplittable::new(4096);
+    let (_, secret_key) = key();
+    let adversary = async move {
+        // Whatever the adversary gets back is irrelevant, and we drop the connection when done.
+        let _ = AdversarialPeer::new(secret_key, misbehavior)
+            .run(adversary_stream)
+            .await;
+    };
+    let (result, ()) = tokio::join!(accept(honest_stream, authorize), adversary);
+    result
+}
+
+#[tokio::test]
+async fn compliant_peer_is_accepted() {
+    let (failure, reported) = run_against_honest(Misbehavior::Compliant, true).await;
+    assert!(reported);
+    assert!(matches!(
+        failure,
+        Failure::Protocol(ProtocolError::ReceiveError(ReceiveError::Error(
+            DataError::ConnectionClosed(_)
+        )))
+    ));
+}
+
+#[tokio::test]
+async fn unauthorized_peer_is_rejected() {
+    let (failure, reported) = run_against_honest(Misbehavior::Compliant, false).await;
+    assert!(!reported);
+    assert!(matches!(
+        failure,
+        Failure::Protocol(ProtocolError::NotAuthorized)
+    ));
+}
+
+#[tokio::test]
+async fn wrong_version_is_rejected() {
+    let (failure, reported) = run_against_honest(Misbehavior::WrongVersion, true).await;
+    assert!(!reported);
+    assert!(matches!(
+        failure,
+        Failure::Negotiation(ProtocolNegotiationError::ProtocolMismatch(_, _))
+    ));
+}
+
+#[tokio::test]
+async fn oversized_payload_is_rejected() {
+    let (failure, _) = run_against_honest(Misbehavior::OversizedPayload, true).await;
+    assert!(matches!(
+        failure,
+        Failure::Protocol(ProtocolError::ReceiveError(ReceiveError::Error(
+            DataError::DataTooLong(length)
+        ))) if length == MAX_DATA_SIZE + 1
+    ));
+}
+
+#[tokio::test]
+async fn unsolicited_response_is_rejected() {
+    let (failure, reported) = run_against_honest(Misbehavior::UnsolicitedResponse, true).await;
+    assert!(!reported);
+    assert!(matches!(
+        failure,
+        Failure::Protocol(ProtocolError::HandshakeError(
+            HandshakeError::SignatureError
+        ))
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn slow_loris_times_out() {
+    let (failure, reported) =
+        run_against_honest(Misbehavior::SlowLoris(Duration::from_secs(1)), true).await;
+    assert!(!reported);
+    assert!(matches!(
+        failure,
+        Failure::Protocol(ProtocolError::HandshakeError(HandshakeError::TimedOut))
+    ));
+}
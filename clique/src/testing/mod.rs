@@ -1 +1,2 @@
+mod adversary;
 mod clique_network;
@@ -82,6 +82,14 @@ if [[ -n "${FLAG_L_ALEPH_BFT:-}" ]]; then
   ARGS+=(-lAlephBFT=debug)
 fi
 
+if [[ -n "${VALIDATOR_LISTEN_ADDRESS:-}" ]]; then
+  ARGS+=(--validator-listen-address "${VALIDATOR_LISTEN_ADDRESS}")
+fi
+
+if [[ -n "${VALIDATOR_OUTGOING_ADDRESS:-}" ]]; then
+  ARGS+=(--validator-outgoing-address "${VALIDATOR_OUTGOING_ADDRESS}")
+fi
+
 if [[ -n "${PUBLIC_ADDR:-}" ]]; then
   ARGS+=(--public-addr "${PUBLIC_ADDR}")
 fi
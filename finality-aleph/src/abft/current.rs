@@ -9,7 +9,7 @@ pub use crate::aleph_primitives::{BlockHash, BlockNumber, CURRENT_FINALITY_VERSI
 use crate::{
     abft::{
         common::{unit_creation_delay_fn, MAX_ROUNDS},
-        NetworkWrapper,
+        AbftMetrics, NetworkWrapper,
     },
     crypto::Signature,
     data_io::{AlephData, OrderedDataInterpreter, SubstrateChainInfoProvider},
@@ -22,10 +22,11 @@ use crate::{
     CurrentNetworkData, Hasher, Keychain, NodeIndex, SessionId, SignatureSet, UnitCreationDelay,
 };
 
+#[allow(clippy::too_many_arguments)]
 pub fn run_member<B, C, ADN>(
     subtask_common: SubtaskCommon,
     multikeychain: Keychain,
-    config: Config,
+    mut config: Config,
     network: NetworkWrapper<
         current_aleph_bft::NetworkData<Hasher, AlephData, Signature, SignatureSet<Signature>>,
         ADN,
@@ -33,6 +34,7 @@ pub fn run_member<B, C, ADN>(
     data_provider: impl current_aleph_bft::DataProvider<AlephData> + Send + 'static,
     ordered_data_interpreter: OrderedDataInterpreter<SubstrateChainInfoProvider<B, C>>,
     backup: ABFTBackup,
+    metrics: AbftMetrics,
 ) -> Task
 where
     B: Block<Hash = BlockHash>,
@@ -52,7 +54,16 @@ where
     } = subtask_common;
     let (stop, exit) = oneshot::channel();
     let member_terminator = Terminator::create_root(exit, "member");
-    let local_io = LocalIO::new(data_provider, ordered_data_interpreter, backup.0, backup.1);
+    // Instrumented only after the check, so that it does not count as rounds.
+    let metrics = metrics.member();
+    config.delay_config.unit_creation_delay =
+        metrics.unit_creation_delay(config.delay_config.unit_creation_delay.clone());
+    let local_io = LocalIO::new(
+        data_provider,
+        ordered_data_interpreter,
+        metrics.backup_saver(backup.0),
+        backup.1,
+    );
 
     let task = {
         let spawn_handle = spawn_handle.clone();
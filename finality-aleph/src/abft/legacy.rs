@@ -7,7 +7,7 @@ use sp_runtime::traits::{Block, Header};
 use super::common::{sanity_check_round_delays, unit_creation_delay_fn, MAX_ROUNDS};
 pub use crate::aleph_primitives::{BlockHash, BlockNumber, LEGACY_FINALITY_VERSION as VERSION};
 use crate::{
-    abft::NetworkWrapper,
+    abft::{AbftMetrics, NetworkWrapper},
     data_io::{AlephData, OrderedDataInterpreter, SubstrateChainInfoProvider},
     network::data::Network,
     oneshot,
@@ -18,14 +18,16 @@ use crate::{
     Keychain, LegacyNetworkData, NodeIndex, SessionId, UnitCreationDelay,
 };
 
+#[allow(clippy::too_many_arguments)]
 pub fn run_member<B, C, ADN>(
     subtask_common: SubtaskCommon,
     multikeychain: Keychain,
-    config: Config,
+    mut config: Config,
     network: NetworkWrapper<LegacyNetworkData, ADN>,
     data_provider: impl legacy_aleph_bft::DataProvider<AlephData> + Send + 'static,
     ordered_data_interpreter: OrderedDataInterpreter<SubstrateChainInfoProvider<B, C>>,
     backup: ABFTBackup,
+    metrics: AbftMetrics,
 ) -> Task
 where
     B: Block<Hash = BlockHash>,
@@ -45,7 +47,16 @@ where
     } = subtask_common;
     let (stop, exit) = oneshot::channel();
     let member_terminator = Terminator::create_root(exit, "member");
-    let local_io = LocalIO::new(data_provider, ordered_data_interpreter, backup.0, backup.1);
+    // Instrumented only after the check, so that it does not count as rounds.
+    let metrics = metrics.member();
+    config.delay_config.unit_creation_delay =
+        metrics.unit_creation_delay(config.delay_config.unit_creation_delay.clone());
+    let local_io = LocalIO::new(
+        data_provider,
+        ordered_data_interpreter,
+        metrics.backup_saver(backup.0),
+        backup.1,
+    );
 
     let task = {
         let spawn_handle = spawn_handle.clone();
@@ -0,0 +1,308 @@
+use std::{
+    io::{Result as IoResult, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use substrate_prometheus_endpoint::{
+    exponential_buckets, register, Counter, Gauge, Histogram, HistogramOpts, PrometheusError,
+    Registry, U64,
+};
+
+use crate::{abft::common::DelaySchedule, party::backup::Saver};
+
+fn histogram(
+    name: &str,
+    help: &str,
+    buckets: Vec<f64>,
+    registry: &Registry,
+) -> Result<Histogram, PrometheusError> {
+    register(
+        Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets))?,
+        registry,
+    )
+}
+
+/// Metrics of the AlephBFT sessions: how fast the rounds go, how the DAG grows, and how long the
+/// units wait for the blocks they propose to become available.
+#[derive(Clone)]
+pub enum AbftMetrics {
+    Prometheus {
+        round: Gauge<U64>,
+        round_durations: Histogram,
+        units_per_round: Histogram,
+        dag_units: Counter<U64>,
+        dag_bytes: Counter<U64>,
+        held_messages: Counter<U64>,
+        availability_waits: Histogram,
+    },
+    Noop,
+}
+
+impl AbftMetrics {
+    pub fn new(registry: Option<Registry>) -> Result<Self, PrometheusError> {
+        let registry = match registry {
+            Some(registry) => registry,
+            None => return Ok(AbftMetrics::Noop),
+        };
+        Ok(AbftMetrics::Prometheus {
+            round: register(
+                Gauge::new(
+                    "aleph_abft_round",
+                    "round the unit creation of the current session is at",
+                )?,
+                &registry,
+            )?,
+            round_durations: histogram(
+                "aleph_abft_round_duration",
+                "seconds between starting to create the units of consecutive rounds",
+                // From 50ms to almost two minutes.
+                exponential_buckets(0.05, 1.5, 20)?,
+                &registry,
+            )?,
+            units_per_round: histogram(
+                "aleph_abft_units_per_round",
+                "number of units added to the DAG while creating the unit of a round",
+                exponential_buckets(1.0, 2.0, 10)?,
+                &registry,
+            )?,
+            dag_units: register(
+                Counter::new("aleph_abft_dag_units", "number of units added to the DAG")?,
+                &registry,
+            )?,
+            dag_bytes: register(
+                Counter::new(
+                    "aleph_abft_dag_bytes",
+                    "encoded size of the units added to the DAG",
+                )?,
+                &registry,
+            )?,
+            held_messages: register(
+                Counter::new(
+                    "aleph_abft_held_messages",
+                    "number of messages held back until the blocks they propose were available",
+                )?,
+                &registry,
+            )?,
+            availability_waits: histogram(
+                "aleph_abft_data_availability_wait",
+                "seconds messages were held back until the blocks they propose were available",
+                // From 1ms to over eight minutes.
+                exponential_buckets(0.001, 2.0, 20)?,
+                &registry,
+            )?,
+        })
+    }
+
+    pub fn noop() -> Self {
+        AbftMetrics::Noop
+    }
+
+    /// Metrics for a single run of the member, the rounds of every run start from the beginning.
+    pub fn member(&self) -> MemberMetrics {
+        MemberMetrics {
+            metrics: self.clone(),
+            round: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The round the unit creation of the current session is at, unknown without the metrics.
+    pub fn round(&self) -> Option<u64> {
+        match self {
+            AbftMetrics::Prometheus { round, .. } => Some(round.get()),
+            AbftMetrics::Noop => None,
+        }
+    }
+
+    pub fn report_data_availability_wait(&self, wait: Duration) {
+        if let AbftMetrics::Prometheus {
+            held_messages,
+            availability_waits,
+            ..
+        } = self
+        {
+            held_messages.inc();
+            availability_waits.observe(wait.as_secs_f64());
+        }
+    }
+}
+
+struct Round {
+    number: usize,
+    started: Instant,
+    units: u64,
+}
+
+/// Tracks the rounds of a single run of the member.
+#[derive(Clone)]
+pub struct MemberMetrics {
+    metrics: AbftMetrics,
+    round: Arc<Mutex<Option<Round>>>,
+}
+
+impl MemberMetrics {
+    /// Wraps the schedule of unit creation delays. AlephBFT asks for the delay of a round when it
+    /// starts creating the unit of the round, which is when we consider the round started.
+    pub fn unit_creation_delay(&self, delays: DelaySchedule) -> DelaySchedule {
+        if let AbftMetrics::Noop = self.metrics {
+            return delays;
+        }
+        let member = self.clone();
+        Arc::new(move |round| {
+            member.round_started(round);
+            delays(round)
+        })
+    }
+
+    /// Wraps the saver of the backup, which AlephBFT writes and flushes every unit to before
+    /// adding it to the DAG.
+    pub fn backup_saver(&self, saver: Saver) -> Saver {
+        if let AbftMetrics::Noop = self.metrics {
+            return saver;
+        }
+        Box::new(CountingSaver {
+            inner: saver,
+            member: self.clone(),
+            bytes: 0,
+        })
+    }
+
+    fn round_started(&self, number: usize) {
+        if let AbftMetrics::Prometheus {
+            round: round_gauge,
+            round_durations,
+            units_per_round,
+            ..
+        } = &self.metrics
+        {
+            let now = Instant::now();
+            let mut round = self.round.lock();
+            if let Some(previous) = round.as_ref() {
+                if number <= previous.number {
+                    return;
+                }
+                // After recovering from a backup rounds can be skipped, we only time whole ones.
+                if number == previous.number + 1 {
+                    round_durations.observe(
+                        now.saturating_duration_since(previous.started)
+                            .as_secs_f64(),
+                    );
+                    units_per_round.observe(previous.units as f64);
+                }
+            }
+            *round = Some(Round {
+                number,
+                started: now,
+                units: 0,
+            });
+            round_gauge.set(number as u64);
+        }
+    }
+
+    fn unit_saved(&self, bytes: u64) {
+        if let AbftMetrics::Prometheus {
+            dag_units,
+            dag_bytes,
+            ..
+        } = &self.metrics
+        {
+            dag_units.inc();
+            dag_bytes.inc_by(bytes);
+            if let Some(round) = self.round.lock().as_mut() {
+                round.units += 1;
+            }
+        }
+    }
+}
+
+struct CountingSaver {
+    inner: Saver,
+    member: MemberMetrics,
+    bytes: u64,
+}
+
+impl Write for CountingSaver {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()?;
+        if self.bytes > 0 {
+            self.member.unit_saved(self.bytes);
+            self.bytes = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, sync::Arc, time::Duration};
+
+    use substrate_prometheus_endpoint::Registry;
+
+    use super::AbftMetrics;
+
+    fn metrics() -> AbftMetrics {
+        AbftMetrics::new(Some(Registry::new())).expect("metrics register")
+    }
+
+    #[test]
+    fn counts_units_saved_in_rounds() {
+        let metrics = metrics();
+        let member = metrics.member();
+        let delays = member.unit_creation_delay(Arc::new(|_| Duration::from_millis(1)));
+        let mut saver = member.backup_saver(Box::new(Vec::new()));
+        assert_eq!(delays(0), Duration::from_millis(1));
+        for _ in 0..3 {
+            saver.write_all(&[0; 10]).expect("writing works");
+            saver.flush().expect("flushing works");
+        }
+        // Delays of earlier rounds do not restart them.
+        delays(0);
+        delays(1);
+        match metrics {
+            AbftMetrics::Prometheus {
+                round,
+                round_durations,
+                units_per_round,
+                dag_units,
+                dag_bytes,
+                ..
+            } => {
+                assert_eq!(round.get(), 1);
+                assert_eq!(round_durations.get_sample_count(), 1);
+                assert_eq!(units_per_round.get_sample_sum(), 3.0);
+                assert_eq!(dag_units.get(), 3);
+                assert_eq!(dag_bytes.get(), 30);
+            }
+            AbftMetrics::Noop => panic!("metrics should be registered"),
+        }
+    }
+
+    #[test]
+    fn skipped_rounds_are_not_timed() {
+        let metrics = metrics();
+        let delays = metrics
+            .member()
+            .unit_creation_delay(Arc::new(|_| Duration::from_millis(1)));
+        delays(3);
+        delays(7);
+        assert_eq!(metrics.round(), Some(7));
+        match metrics {
+            AbftMetrics::Prometheus {
+                round,
+                round_durations,
+                ..
+            } => {
+                assert_eq!(round.get(), 7);
+                assert_eq!(round_durations.get_sample_count(), 0);
+            }
+            AbftMetrics::Noop => panic!("metrics should be registered"),
+        }
+    }
+}
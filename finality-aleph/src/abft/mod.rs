@@ -10,6 +10,7 @@ mod common;
 mod crypto;
 mod current;
 mod legacy;
+mod metrics;
 mod network;
 mod traits;
 mod types;
@@ -26,6 +27,7 @@ pub use legacy::{
     create_aleph_config as legacy_create_aleph_config, run_member as run_legacy_member,
     VERSION as LEGACY_VERSION,
 };
+pub use metrics::AbftMetrics;
 pub use network::{CurrentNetworkData, LegacyNetworkData, NetworkWrapper};
 use parity_scale_codec::{Decode, Encode};
 pub use traits::{Hash, SpawnHandle, Wrapper as HashWrapper};
@@ -0,0 +1,723 @@
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    fs,
+    io::Error as IoError,
+    net::SocketAddr,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use sc_network::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aleph_primitives::{AuthorityId, MIN_SYNC_MAX_BATCH_BYTES},
+    head_push::{EndpointError, HeadPushEndpoint},
+    network::{
+        LimitsError, NetworkLimits, PublicNetworkLimits, ValidatorNetworkLimits,
+        DEFAULT_PUBLIC_HANDSHAKE_TIMEOUT, DEFAULT_PUBLIC_MAX_MESSAGE_SIZE,
+        DEFAULT_VALIDATOR_BIT_RATE_PER_CONNECTION,
+    },
+    sync::{
+        substrate::{
+            BlockSource, BlockSourceError, CustodyPolicy, JustificationRetention,
+            VerificationSampling,
+        },
+        CaptureConfig, LocalLimits, RatingParams, ShimFeature, V1Shim, DEFAULT_FORGIVE_AFTER,
+        DEFAULT_SCORE_DECAY_PER_SECOND,
+    },
+    BlockNumber,
+};
+
+/// The default size in megabytes after which a new sync capture file is started.
+pub const DEFAULT_SYNC_CAPTURE_MAX_FILE_MB: u64 = 64;
+/// The default number of sync capture files that are kept.
+pub const DEFAULT_SYNC_CAPTURE_MAX_FILES: usize = 16;
+/// The default number of blocks finalized by sync in a single database transaction.
+pub const DEFAULT_SYNC_FINALIZATION_BATCH: u32 = 1;
+/// The default number of points of the misbehavior score of a peer forgiven every second.
+pub const DEFAULT_SYNC_PEER_SCORE_DECAY: u32 = DEFAULT_SCORE_DECAY_PER_SECOND;
+/// The default number of seconds without misbehavior after which a peer is forgiven completely.
+pub const DEFAULT_SYNC_PEER_FORGIVE_AFTER_SECS: u64 = DEFAULT_FORGIVE_AFTER.as_secs();
+/// The default number of justifications below the trusted checkpoint out of which one is verified.
+pub const DEFAULT_SYNC_VERIFICATION_SAMPLE: u32 = 16;
+
+/// A peer on a fork that only ever understands the first version of the sync protocol.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct V1ShimPeerConfig {
+    /// The network identity of the peer.
+    pub peer: String,
+    /// The newer features translated into messages of the first version for the peer, the rest
+    /// are only sent if they have an equivalent there.
+    #[serde(default)]
+    pub features: Vec<ShimFeature>,
+}
+
+/// The locally configurable parts of block sync.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlephSyncConfig {
+    /// Only serve requests from peers at most this many blocks behind. Can only be stricter than
+    /// the value set on chain.
+    pub serving_window: Option<BlockNumber>,
+    /// Maximum size in bytes of a single response. Can only be stricter than the value set on
+    /// chain.
+    pub max_batch_bytes: Option<u32>,
+    /// Minimum time in milliseconds between state broadcasts, within the on-chain bounds.
+    pub min_broadcast_period_ms: Option<u64>,
+    /// Maximum time in milliseconds between state broadcasts, within the on-chain bounds.
+    pub max_broadcast_period_ms: Option<u64>,
+    /// Capture all the sync traffic into files in this directory, if provided.
+    pub capture_path: Option<PathBuf>,
+    /// The size in megabytes after which a new capture file is started.
+    pub capture_max_file_mb: u64,
+    /// How many capture files are kept, the oldest ones are removed.
+    pub capture_max_files: usize,
+    /// Trigger a snapshot of the database every this many finalized sessions, if provided.
+    pub snapshot_every_sessions: Option<u32>,
+    /// Export the snapshot points to this directory for external tooling, if provided.
+    pub snapshot_export_path: Option<PathBuf>,
+    /// A file with the secret phrase of the key proving this node belongs to the infrastructure
+    /// of a validator, if it does.
+    pub priority_key_path: Option<PathBuf>,
+    /// The keys of the followers belonging to the infrastructure of this validator, served with
+    /// priority once they prove it.
+    pub priority_followers: Vec<AuthorityId>,
+    /// When far behind, fetch the chains of headers and justifications before the bodies.
+    pub headers_first: bool,
+    /// When sessions behind, verify the chain of justifications ending the sessions first, to
+    /// learn the authorities up to the head of the network.
+    pub warp_sync: bool,
+    /// When far behind, download the gap below the network in ranges from several peers at once.
+    pub parallel_download: bool,
+    /// When sessions behind, fetch the justifications and headers of the next session while the
+    /// blocks of the current one are still imported.
+    pub session_pipeline: bool,
+    /// Exchange sync requests and responses through a dedicated request-response protocol,
+    /// falling back to notifications for peers that do not support it.
+    pub request_response: bool,
+    /// Finalize at most this many justified blocks in a single database transaction, only the
+    /// justifications of the highest ones and of the last blocks of sessions are stored.
+    pub finalization_batch: u32,
+    /// Run sync dry, serving peers as usual but only finalizing the justified blocks virtually,
+    /// leaving the finality in the database as it was.
+    pub dry_run: bool,
+    /// Follow only the headers and justifications of the chain, never downloading the bodies, for
+    /// RPC and observer nodes that do not validate. The finalized headers are kept in memory and
+    /// passed to the finalization hooks, the database stays as it was.
+    pub light_sync: bool,
+    /// Maximum size in bytes of a single sync message, sent or received. Replaces the default,
+    /// for chains with larger blocks.
+    pub max_message_bytes: Option<u32>,
+    /// Serve at most this many blocks in response to a single request.
+    pub max_response_blocks: Option<u32>,
+    /// How many points of the misbehavior score of a peer are forgiven every second.
+    pub peer_score_decay: u32,
+    /// After this many seconds without misbehaving a peer is forgiven completely.
+    pub peer_forgive_after_secs: u64,
+    /// Reject emergency justifications that do not say which operator ordered them and why.
+    pub require_emergency_custody: bool,
+    /// The keys of the operators allowed to order emergency finalizations, any if empty.
+    pub emergency_operators: Vec<AuthorityId>,
+    /// Trust the justifications of the blocks up to this one enough to only verify a sample of
+    /// them, if provided. The blocks ending sessions are always verified.
+    pub trusted_checkpoint: Option<BlockNumber>,
+    /// Below the trusted checkpoint verify one in this many justifications. A chain with `k`
+    /// forged justifications there goes unnoticed with probability `(1 - 1 / sample)^k`.
+    pub verification_sample: u32,
+    /// The peers that only ever get the first version of the protocol, also after the legacy
+    /// cutoff, for bridging deployments with forks that can never upgrade.
+    pub v1_shim_peers: Vec<V1ShimPeerConfig>,
+    /// Which justifications of finalized blocks are kept, the ones of the last blocks of sessions
+    /// always are. The others are archived and pruned in the background once outside the
+    /// retention.
+    pub justification_retention: JustificationRetention,
+    /// Compress the justifications archived for pruning, which only the retention other than
+    /// keeping all of them does.
+    pub compress_justifications: bool,
+    /// Fetch the missing bodies of finalized blocks from this HTTPS source, e.g. an exchange or a
+    /// CDN, rather than from peers, if provided. The bodies are checked against the headers.
+    pub block_source: Option<String>,
+    /// The fork of the chain to follow after a hard fork, taking precedence over the one in the
+    /// chain spec, if provided. It goes into the names of the Aleph protocols and is announced
+    /// to the peers, so that the networks of the two forks get separated.
+    pub fork_id: Option<String>,
+}
+
+impl Default for AlephSyncConfig {
+    fn default() -> Self {
+        AlephSyncConfig {
+            serving_window: None,
+            max_batch_bytes: None,
+            min_broadcast_period_ms: None,
+            max_broadcast_period_ms: None,
+            capture_path: None,
+            capture_max_file_mb: DEFAULT_SYNC_CAPTURE_MAX_FILE_MB,
+            capture_max_files: DEFAULT_SYNC_CAPTURE_MAX_FILES,
+            snapshot_every_sessions: None,
+            snapshot_export_path: None,
+            priority_key_path: None,
+            priority_followers: Vec::new(),
+            headers_first: false,
+            warp_sync: false,
+            parallel_download: false,
+            session_pipeline: false,
+            request_response: false,
+            finalization_batch: DEFAULT_SYNC_FINALIZATION_BATCH,
+            dry_run: false,
+            light_sync: false,
+            max_message_bytes: None,
+            max_response_blocks: None,
+            peer_score_decay: DEFAULT_SYNC_PEER_SCORE_DECAY,
+            peer_forgive_after_secs: DEFAULT_SYNC_PEER_FORGIVE_AFTER_SECS,
+            require_emergency_custody: false,
+            emergency_operators: Vec::new(),
+            trusted_checkpoint: None,
+            verification_sample: DEFAULT_SYNC_VERIFICATION_SAMPLE,
+            v1_shim_peers: Vec::new(),
+            justification_retention: JustificationRetention::KeepAll,
+            compress_justifications: false,
+            block_source: None,
+            fork_id: None,
+        }
+    }
+}
+
+impl AlephSyncConfig {
+    /// The local limits on the on-chain sync parameters.
+    pub fn limits(&self) -> LocalLimits {
+        LocalLimits {
+            serving_window: self.serving_window,
+            max_batch_bytes: self.max_batch_bytes,
+            min_broadcast_period: self.min_broadcast_period_ms.map(Duration::from_millis),
+            max_broadcast_period: self.max_broadcast_period_ms.map(Duration::from_millis),
+            max_message_bytes: self.max_message_bytes,
+            max_response_blocks: self.max_response_blocks,
+            peer_rating: RatingParams {
+                decay_per_second: self.peer_score_decay,
+                forgive_after: Duration::from_secs(self.peer_forgive_after_secs),
+            },
+        }
+    }
+
+    /// Where and how to capture the sync traffic, if at all.
+    pub fn capture(&self) -> Option<CaptureConfig> {
+        self.capture_path.clone().map(|directory| CaptureConfig {
+            directory,
+            max_file_bytes: self.capture_max_file_mb.saturating_mul(1024 * 1024),
+            max_files: self.capture_max_files,
+        })
+    }
+
+    /// How many finalized sessions apart the snapshots are triggered, if at all.
+    pub fn snapshot_every_sessions(&self) -> Option<NonZeroU32> {
+        self.snapshot_every_sessions.and_then(NonZeroU32::new)
+    }
+
+    /// Which chain of custody emergency justifications have to have.
+    pub fn emergency_custody(&self) -> CustodyPolicy {
+        CustodyPolicy {
+            required: self.require_emergency_custody,
+            operators: self.emergency_operators.iter().cloned().collect(),
+        }
+    }
+
+    /// Which justifications below the trusted checkpoint are verified, if there is one.
+    pub fn verification_sampling(&self) -> Option<VerificationSampling> {
+        let one_in = NonZeroU32::new(self.verification_sample)?;
+        self.trusted_checkpoint
+            .map(|checkpoint| VerificationSampling::new(checkpoint, one_in))
+    }
+
+    /// The peers only ever getting the first version of the protocol.
+    pub fn v1_shim(&self) -> Result<V1Shim<PeerId>, ConfigError> {
+        let mut shim = V1Shim::new();
+        for peer_config in &self.v1_shim_peers {
+            let peer = PeerId::from_str(&peer_config.peer)
+                .map_err(|_| ConfigError::MalformedShimPeer(peer_config.peer.clone()))?;
+            shim.add_peer(peer, peer_config.features.iter().cloned());
+        }
+        Ok(shim)
+    }
+
+    /// Where the missing bodies of finalized blocks are fetched from instead of peers, if anywhere.
+    pub fn block_source(&self) -> Result<Option<BlockSource>, ConfigError> {
+        self.block_source
+            .as_deref()
+            .map(|source| source.parse().map_err(ConfigError::BlockSource))
+            .transpose()
+    }
+
+    /// The secret phrase of the priority key, read from the file, if one is configured.
+    pub fn priority_key_phrase(&self) -> Result<Option<String>, ConfigError> {
+        let path = match &self.priority_key_path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let contents = fs::read_to_string(path).map_err(ConfigError::PriorityKey)?;
+        match contents.trim() {
+            "" => Err(ConfigError::EmptyPriorityKey),
+            phrase => Ok(Some(phrase.to_string())),
+        }
+    }
+
+    /// Checks that the values make sense together.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        use ConfigError::*;
+        if self.serving_window == Some(0) {
+            return Err(ZeroServingWindow);
+        }
+        if let Some(bytes) = self.max_batch_bytes {
+            if bytes < MIN_SYNC_MAX_BATCH_BYTES {
+                return Err(BatchTooSmall(bytes));
+            }
+        }
+        if let Some(bytes) = self.max_message_bytes {
+            if bytes < MIN_SYNC_MAX_BATCH_BYTES {
+                return Err(MessageTooSmall(bytes));
+            }
+        }
+        if self.max_response_blocks == Some(0) {
+            return Err(ZeroResponseBlocks);
+        }
+        if let (Some(min), Some(max)) = (self.min_broadcast_period_ms, self.max_broadcast_period_ms)
+        {
+            if min > max {
+                return Err(BroadcastPeriods(min, max));
+            }
+        }
+        if self.capture_max_file_mb == 0 || self.capture_max_files == 0 {
+            return Err(EmptyCapture);
+        }
+        if self.snapshot_every_sessions == Some(0) {
+            return Err(ZeroSnapshotInterval);
+        }
+        if self.snapshot_export_path.is_some() && self.snapshot_every_sessions.is_none() {
+            return Err(SnapshotExportWithoutInterval);
+        }
+        if self.require_emergency_custody && self.emergency_operators.is_empty() {
+            return Err(CustodyWithoutOperators);
+        }
+        if self.peer_forgive_after_secs == 0 {
+            return Err(ZeroForgiveness);
+        }
+        if self.verification_sample == 0 {
+            return Err(ZeroVerificationSample);
+        }
+        if self.justification_retention == JustificationRetention::LastSessions(0) {
+            return Err(ZeroRetainedSessions);
+        }
+        if self.compress_justifications
+            && self.justification_retention == JustificationRetention::KeepAll
+        {
+            return Err(CompressionWithoutArchiving);
+        }
+        if self.light_sync {
+            let conflict = [
+                (self.dry_run, "a dry run"),
+                (self.parallel_download, "parallel download"),
+                (self.session_pipeline, "the session pipeline"),
+                (self.block_source.is_some(), "a block source"),
+            ]
+            .into_iter()
+            .find_map(|(enabled, feature)| enabled.then_some(feature));
+            if let Some(conflict) = conflict {
+                return Err(LightSyncConflict(conflict));
+            }
+        }
+        if let Some(fork_id) = &self.fork_id {
+            if fork_id.is_empty()
+                || !fork_id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(MalformedForkId(fork_id.clone()));
+            }
+        }
+        self.block_source()?;
+        self.v1_shim().map(|_| ())
+    }
+}
+
+/// The locally configurable limits of the networking.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlephNetworkConfig {
+    /// Maximum bit-rate per connection in bytes per second of the validator network.
+    pub validator_bit_rate_per_connection: usize,
+    /// Maximum size in bytes of a single message of the public gossip protocols.
+    pub public_max_message_size: u64,
+    /// Maximum bit-rate in bytes per second of the public gossip messages from a single peer.
+    pub public_bit_rate_per_peer: Option<usize>,
+    /// Time in milliseconds a peer opening the block sync protocol has to send a decodable
+    /// message, before it gets disconnected.
+    pub public_handshake_timeout_ms: u64,
+}
+
+impl Default for AlephNetworkConfig {
+    fn default() -> Self {
+        AlephNetworkConfig {
+            validator_bit_rate_per_connection: DEFAULT_VALIDATOR_BIT_RATE_PER_CONNECTION,
+            public_max_message_size: DEFAULT_PUBLIC_MAX_MESSAGE_SIZE,
+            public_bit_rate_per_peer: None,
+            public_handshake_timeout_ms: DEFAULT_PUBLIC_HANDSHAKE_TIMEOUT.as_millis() as u64,
+        }
+    }
+}
+
+impl AlephNetworkConfig {
+    pub fn limits(&self) -> NetworkLimits {
+        NetworkLimits {
+            validator: ValidatorNetworkLimits {
+                bit_rate_per_connection: self.validator_bit_rate_per_connection,
+            },
+            public: PublicNetworkLimits {
+                max_message_size: self.public_max_message_size,
+                bit_rate_per_peer: self.public_bit_rate_per_peer,
+                handshake_timeout: Duration::from_millis(self.public_handshake_timeout_ms),
+            },
+        }
+    }
+
+    /// Checks that the limits allow honest traffic through.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.limits().validate().map_err(ConfigError::Network)
+    }
+}
+
+/// All the local configuration of finality-aleph that can be loaded from a file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlephNodeConfig {
+    pub sync: AlephSyncConfig,
+    pub network: AlephNetworkConfig,
+    /// Push announcements of the finalized blocks to this HTTP endpoint, if provided.
+    pub head_push_endpoint: Option<String>,
+    /// The most megabytes of disk the sync captures, AlephBFT backups and shutdown reports can
+    /// take together, the oldest ones are evicted. Unlimited if not provided.
+    pub disk_quota_mb: Option<u64>,
+    /// Take a diagnostic snapshot whenever no block is finalized for this many seconds, if
+    /// provided.
+    pub finality_stall_timeout_secs: Option<u64>,
+    /// Serve a status page of sync at this address, e.g. `127.0.0.1:9616`, if provided.
+    pub status_page_address: Option<SocketAddr>,
+}
+
+impl AlephNodeConfig {
+    /// Reads the configuration from a JSON file, missing values are replaced with the defaults.
+    /// The result is validated.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read(path).map_err(ConfigError::Io)?;
+        let config: Self =
+            serde_json::from_slice(&contents).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Where the announcements of the finalized blocks are pushed, if anywhere.
+    pub fn head_push_endpoint(&self) -> Result<Option<HeadPushEndpoint>, ConfigError> {
+        self.head_push_endpoint
+            .as_deref()
+            .map(|endpoint| endpoint.parse().map_err(ConfigError::HeadPushEndpoint))
+            .transpose()
+    }
+
+    /// The disk quota in bytes, if any.
+    pub fn disk_quota(&self) -> Option<u64> {
+        self.disk_quota_mb.map(|mb| mb.saturating_mul(1024 * 1024))
+    }
+
+    /// After how long without finalizing a block finality is considered stalled, if watched.
+    pub fn finality_stall_timeout(&self) -> Option<Duration> {
+        self.finality_stall_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.sync.validate()?;
+        self.network.validate()?;
+        if self.disk_quota_mb == Some(0) {
+            return Err(ConfigError::ZeroDiskQuota);
+        }
+        if self.finality_stall_timeout_secs == Some(0) {
+            return Err(ConfigError::ZeroStallTimeout);
+        }
+        self.head_push_endpoint().map(|_| ())
+    }
+}
+
+/// What can be wrong with the configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(IoError),
+    Parse(String),
+    ZeroServingWindow,
+    BatchTooSmall(u32),
+    MessageTooSmall(u32),
+    ZeroResponseBlocks,
+    BroadcastPeriods(u64, u64),
+    EmptyCapture,
+    ZeroSnapshotInterval,
+    SnapshotExportWithoutInterval,
+    CustodyWithoutOperators,
+    ZeroForgiveness,
+    ZeroVerificationSample,
+    MalformedShimPeer(String),
+    ZeroRetainedSessions,
+    CompressionWithoutArchiving,
+    BlockSource(BlockSourceError),
+    MalformedForkId(String),
+    LightSyncConflict(&'static str),
+    PriorityKey(IoError),
+    EmptyPriorityKey,
+    Network(LimitsError),
+    HeadPushEndpoint(EndpointError),
+    ZeroDiskQuota,
+    ZeroStallTimeout,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use ConfigError::*;
+        match self {
+            Io(e) => write!(f, "cannot read the config file: {e}"),
+            Parse(e) => write!(f, "malformed config file: {e}"),
+            ZeroServingWindow => write!(f, "sync serving window cannot be zero"),
+            BatchTooSmall(bytes) => write!(
+                f,
+                "sync batch size of {bytes} bytes is below the minimum of {MIN_SYNC_MAX_BATCH_BYTES}"
+            ),
+            MessageTooSmall(bytes) => write!(
+                f,
+                "sync message size of {bytes} bytes is below the minimum of {MIN_SYNC_MAX_BATCH_BYTES}"
+            ),
+            ZeroResponseBlocks => write!(f, "sync responses have to contain at least one block"),
+            BroadcastPeriods(min, max) => write!(
+                f,
+                "minimum sync broadcast period of {min}ms is longer than the maximum of {max}ms"
+            ),
+            EmptyCapture => write!(f, "sync capture files cannot be empty or not kept at all"),
+            ZeroSnapshotInterval => write!(f, "snapshots cannot be triggered every zero sessions"),
+            SnapshotExportWithoutInterval => write!(
+                f,
+                "snapshot points cannot be exported without a snapshot interval"
+            ),
+            CustodyWithoutOperators => write!(
+                f,
+                "emergency custody cannot be required without any emergency operators"
+            ),
+            ZeroForgiveness => write!(
+                f,
+                "peers forgiven right away could never be penalized for misbehaving in sync"
+            ),
+            ZeroVerificationSample => write!(
+                f,
+                "cannot verify one in zero justifications below the trusted checkpoint"
+            ),
+            MalformedShimPeer(peer) => write!(f, "malformed identity of a V1 shim peer: {peer}"),
+            ZeroRetainedSessions => write!(
+                f,
+                "the justifications of the current session are always retained"
+            ),
+            CompressionWithoutArchiving => write!(
+                f,
+                "justifications are only compressed when archived for pruning, which keeping all of them never does"
+            ),
+            BlockSource(e) => write!(f, "invalid sync block source: {e}"),
+            MalformedForkId(fork_id) => write!(
+                f,
+                "fork id {fork_id:?} has to be non-empty and consist of letters, digits, '-' and '_'"
+            ),
+            LightSyncConflict(feature) => write!(
+                f,
+                "light sync cannot be combined with {feature}, which needs the block bodies"
+            ),
+            PriorityKey(e) => write!(f, "cannot read the sync priority key file: {e}"),
+            EmptyPriorityKey => write!(f, "the sync priority key file is empty"),
+            Network(e) => write!(f, "{e}"),
+            HeadPushEndpoint(e) => write!(f, "invalid head push endpoint: {e}"),
+            ZeroDiskQuota => write!(f, "the disk quota cannot be zero"),
+            ZeroStallTimeout => write!(f, "finality cannot stall after zero seconds"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sp_core::ed25519;
+
+    use super::{AlephNodeConfig, ConfigError, V1ShimPeerConfig};
+    use crate::{
+        aleph_primitives::AuthorityId,
+        sync::{substrate::JustificationRetention, ShimFeature},
+    };
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(AlephNodeConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn missing_values_are_defaulted() {
+        let config: AlephNodeConfig =
+            serde_json::from_str(r#"{"sync": {"serving_window": 100}}"#).expect("valid config");
+        assert_eq!(config.sync.serving_window, Some(100));
+        assert_eq!(config.network, AlephNodeConfig::default().network);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut config = AlephNodeConfig::default();
+        config.sync.max_broadcast_period_ms = Some(1000);
+        config.network.public_bit_rate_per_peer = Some(1024 * 1024);
+        config.sync.priority_key_path = Some("priority.key".into());
+        config.sync.priority_followers =
+            vec![AuthorityId::from(ed25519::Public::from_raw([1; 32]))];
+        config.sync.headers_first = true;
+        config.sync.warp_sync = true;
+        config.sync.parallel_download = true;
+        config.sync.session_pipeline = true;
+        config.sync.request_response = true;
+        config.sync.finalization_batch = 64;
+        config.sync.dry_run = true;
+        config.sync.light_sync = true;
+        config.sync.max_message_bytes = Some(32 * 1024 * 1024);
+        config.sync.max_response_blocks = Some(128);
+        config.sync.peer_score_decay = 2;
+        config.sync.peer_forgive_after_secs = 60;
+        config.sync.require_emergency_custody = true;
+        config.sync.emergency_operators =
+            vec![AuthorityId::from(ed25519::Public::from_raw([2; 32]))];
+        config.sync.trusted_checkpoint = Some(1_000_000);
+        config.sync.verification_sample = 100;
+        config.sync.v1_shim_peers = vec![V1ShimPeerConfig {
+            peer: "12D3KooWSCufgHzV4fCwRijfH2k3abrpAJxTKxEvN1FDuRXA2U9x".to_string(),
+            features: vec![ShimFeature::BlockResponses, ShimFeature::WarpResponses],
+        }];
+        config.sync.justification_retention = JustificationRetention::LastSessions(4);
+        config.sync.compress_justifications = true;
+        config.sync.block_source = Some("https://blocks.example.com/mainnet".to_string());
+        config.sync.fork_id = Some("hard-forked".to_string());
+        config.head_push_endpoint = Some("http://bridge.local:8080/heads".to_string());
+        config.disk_quota_mb = Some(4096);
+        config.finality_stall_timeout_secs = Some(120);
+        config.status_page_address = Some("127.0.0.1:9616".parse().expect("valid address"));
+        let serialized = serde_json::to_string(&config).expect("serializable");
+        let deserialized: AlephNodeConfig =
+            serde_json::from_str(&serialized).expect("deserializable");
+        assert_eq!(deserialized, config);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(serde_json::from_str::<AlephNodeConfig>(r#"{"sync": {"window": 100}}"#).is_err());
+    }
+
+    #[test]
+    fn rejects_inconsistent_values() {
+        let mut config = AlephNodeConfig::default();
+        config.sync.min_broadcast_period_ms = Some(2000);
+        config.sync.max_broadcast_period_ms = Some(1000);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::BroadcastPeriods(2000, 1000))
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.snapshot_export_path = Some("snapshots".into());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::SnapshotExportWithoutInterval)
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.max_message_bytes = Some(1024);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::MessageTooSmall(1024))
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.max_response_blocks = Some(0);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::ZeroResponseBlocks)
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.require_emergency_custody = true;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::CustodyWithoutOperators)
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.peer_forgive_after_secs = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::ZeroForgiveness)
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.verification_sample = 0;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::ZeroVerificationSample)
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.v1_shim_peers = vec![V1ShimPeerConfig {
+            peer: "not a peer".to_string(),
+            features: Vec::new(),
+        }];
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::MalformedShimPeer(_))
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.justification_retention = JustificationRetention::LastSessions(0);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::ZeroRetainedSessions)
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.compress_justifications = true;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::CompressionWithoutArchiving)
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.block_source = Some("http://blocks.example.com".to_string());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::BlockSource(_))
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.fork_id = Some("hard/forked".to_string());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::MalformedForkId(_))
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.sync.light_sync = true;
+        assert!(config.validate().is_ok());
+        config.sync.parallel_download = true;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::LightSyncConflict("parallel download"))
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.network.validator_bit_rate_per_connection = 0;
+        assert!(matches!(config.validate(), Err(ConfigError::Network(_))));
+        let mut config = AlephNodeConfig::default();
+        config.head_push_endpoint = Some("wss://bridge.local".to_string());
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::HeadPushEndpoint(_))
+        ));
+        let mut config = AlephNodeConfig::default();
+        config.disk_quota_mb = Some(0);
+        assert!(matches!(config.validate(), Err(ConfigError::ZeroDiskQuota)));
+        let mut config = AlephNodeConfig::default();
+        config.finality_stall_timeout_secs = Some(0);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::ZeroStallTimeout)
+        ));
+    }
+}
@@ -1,9 +1,8 @@
 use std::{convert::TryInto, sync::Arc};
 
-use parity_scale_codec::{Decode, Encode};
+pub use light_verifier::Signature;
 use sp_core::crypto::KeyTypeId;
 use sp_keystore::{Error as KeystoreError, Keystore};
-use sp_runtime::RuntimeAppPublic;
 
 use crate::{
     abft::{NodeCount, NodeIndex, SignatureSet},
@@ -17,15 +16,6 @@ pub enum Error {
     Conversion,
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, Hash, Decode, Encode)]
-pub struct Signature(AuthoritySignature);
-
-impl From<AuthoritySignature> for Signature {
-    fn from(authority_signature: AuthoritySignature) -> Signature {
-        Signature(authority_signature)
-    }
-}
-
 /// Ties an authority identification and a cryptography keystore together for use in
 /// signing that requires an authority.
 #[derive(Clone)]
@@ -69,14 +59,14 @@ impl AuthorityPen {
 
     /// Cryptographically signs the message.
     pub fn sign(&self, msg: &[u8]) -> Signature {
-        Signature(
-            self.keystore
-                .ed25519_sign(self.key_type_id, &self.authority_id.clone().into(), msg)
-                .expect("the keystore works")
-                .expect("we have the required key")
-                .try_into()
-                .expect("the bytes encode a signature"),
-        )
+        let signature: AuthoritySignature = self
+            .keystore
+            .ed25519_sign(self.key_type_id, &self.authority_id.clone().into(), msg)
+            .expect("the keystore works")
+            .expect("we have the required key")
+            .try_into()
+            .expect("the bytes encode a signature");
+        signature.into()
     }
 
     /// Return the associated AuthorityId.
@@ -87,7 +77,7 @@ impl AuthorityPen {
 
 /// Verify the signature given an authority id.
 pub fn verify(authority: &AuthorityId, message: &[u8], signature: &Signature) -> bool {
-    authority.verify(&message, &signature.0)
+    signature.verify(authority, message)
 }
 
 /// Holds the public authority keys for a session allowing for verification of messages from that
@@ -116,31 +106,15 @@ impl AuthorityVerifier {
         self.authorities.len().into()
     }
 
-    fn threshold(&self) -> usize {
-        2 * self.node_count().0 / 3 + 1
+    /// The key of the node with the given index, if there is such a node.
+    pub fn authority_id(&self, index: NodeIndex) -> Option<AuthorityId> {
+        self.authorities.get(index.0).cloned()
     }
 
     /// Verifies whether the given signature set is a correct and complete multisignature of the
     /// message. Completeness requires more than 2/3 of all authorities.
     pub fn is_complete(&self, msg: &[u8], partial: &SignatureSet<Signature>) -> bool {
-        let signature_count = partial.iter().count();
-        if signature_count < self.threshold() {
-            return false;
-        }
-        partial.iter().all(|(i, sgn)| self.verify(msg, sgn, i))
-    }
-}
-
-/// Old format of signatures, needed for backwards compatibility.
-#[derive(PartialEq, Eq, Clone, Debug, Decode, Encode)]
-pub struct SignatureV1 {
-    pub _id: NodeIndex,
-    pub sgn: AuthoritySignature,
-}
-
-impl From<SignatureV1> for Signature {
-    fn from(sig_v1: SignatureV1) -> Signature {
-        Signature(sig_v1.sgn)
+        light_verifier::is_complete(&self.authorities, msg, &partial.0)
     }
 }
 
@@ -1,13 +1,14 @@
-use std::default::Default;
+use std::{collections::VecDeque, default::Default};
 
 use futures::channel::mpsc;
 use log::{debug, error, warn};
 
 use crate::{
+    aleph_primitives::BlockNumber,
     data_io::{
         chain_info::{AuxFinalizationChainInfoProvider, CachedChainInfoProvider},
         status_provider::get_proposal_status,
-        AlephData, ChainInfoProvider,
+        AlephData, ChainInfoProvider, FinalizationDepthOffset,
     },
     mpsc::TrySendError,
     BlockId, SessionBoundaries,
@@ -16,9 +17,51 @@ use crate::{
 type InterpretersChainInfoProvider<CIP> =
     CachedChainInfoProvider<AuxFinalizationChainInfoProvider<CIP>>;
 
+// Holds the blocks decided by AlephBFT until they are at least `offset` blocks below the decided
+// head. Once the head reaches the end of the session all the held blocks are released, so the last
+// block of the session is always finalized.
+struct DecidedBlocks {
+    offset: FinalizationDepthOffset,
+    session_end: BlockNumber,
+    pending: VecDeque<BlockId>,
+}
+
+impl DecidedBlocks {
+    fn new(offset: FinalizationDepthOffset, session_end: BlockNumber) -> Self {
+        DecidedBlocks {
+            offset,
+            session_end,
+            pending: VecDeque::new(),
+        }
+    }
+
+    // Takes the newly decided blocks in ascending order and returns the ones that should be
+    // finalized now.
+    fn decided(&mut self, blocks: Vec<BlockId>) -> Vec<BlockId> {
+        let head = match blocks.last() {
+            Some(head) => head.number,
+            None => return Vec::new(),
+        };
+        self.pending.extend(blocks);
+        let mut ready = Vec::new();
+        while let Some(block) = self.pending.front() {
+            if head < self.session_end && block.number.saturating_add(self.offset.blocks()) > head {
+                break;
+            }
+            ready.extend(self.pending.pop_front());
+        }
+        ready
+    }
+
+    fn max_branch_len(&self) -> usize {
+        self.offset.max_branch_len()
+    }
+}
+
 /// Takes as input ordered `AlephData` from `AlephBFT` and pushes blocks that should be finalized
 /// to an output channel. The other end of the channel is held by the aggregator whose goal is to
-/// create multisignatures under the finalized blocks.
+/// create multisignatures under the finalized blocks. With a nonzero finalization depth offset
+/// the pushed blocks trail the decided head by that many blocks.
 pub struct OrderedDataInterpreter<CIP>
 where
     CIP: ChainInfoProvider,
@@ -26,6 +69,7 @@ where
     blocks_to_finalize_tx: mpsc::UnboundedSender<BlockId>,
     chain_info_provider: InterpretersChainInfoProvider<CIP>,
     last_finalized_by_aleph: BlockId,
+    decided_blocks: DecidedBlocks,
     session_boundaries: SessionBoundaries,
 }
 
@@ -58,6 +102,7 @@ where
         blocks_to_finalize_tx: mpsc::UnboundedSender<BlockId>,
         mut chain_info: CIP,
         session_boundaries: SessionBoundaries,
+        finalization_depth_offset: FinalizationDepthOffset,
     ) -> Self {
         let last_finalized_by_aleph =
             get_last_block_prev_session(session_boundaries.clone(), &mut chain_info);
@@ -70,6 +115,10 @@ where
             blocks_to_finalize_tx,
             chain_info_provider,
             last_finalized_by_aleph,
+            decided_blocks: DecidedBlocks::new(
+                finalization_depth_offset,
+                session_boundaries.last_block(),
+            ),
             session_boundaries,
         }
     }
@@ -88,7 +137,10 @@ where
 
     pub fn blocks_to_finalize_from_data(&mut self, new_data: AlephData) -> Vec<BlockId> {
         let unvalidated_proposal = new_data.head_proposal;
-        let proposal = match unvalidated_proposal.validate_bounds(&self.session_boundaries) {
+        let proposal = match unvalidated_proposal.validate_bounds(
+            &self.session_boundaries,
+            self.decided_blocks.max_branch_len(),
+        ) {
             Ok(proposal) => proposal,
             Err(error) => {
                 warn!(target: "aleph-finality", "Incorrect proposal {:?} passed through data availability, session bounds: {:?}, error: {:?}", unvalidated_proposal, self.session_boundaries, error);
@@ -116,14 +168,60 @@ where
     }
 
     pub fn data_finalized(&mut self, data: AlephData) {
-        for block in self.blocks_to_finalize_from_data(data) {
+        let decided = self.blocks_to_finalize_from_data(data);
+        for block in &decided {
             self.set_last_finalized(block.clone());
             self.chain_info_provider()
                 .inner()
                 .update_aux_finalized(block.clone());
+        }
+        for block in self.decided_blocks.decided(decided) {
             if let Err(err) = self.send_block_to_finalize(block) {
                 error!(target: "aleph-finality", "Error in sending a block from FinalizationHandler, {}", err);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DecidedBlocks;
+    use crate::{
+        aleph_primitives::{BlockHash, BlockNumber},
+        data_io::FinalizationDepthOffset,
+        BlockId,
+    };
+
+    const SESSION_END: BlockNumber = 29;
+
+    fn blocks(from: BlockNumber, to: BlockNumber) -> Vec<BlockId> {
+        (from..=to)
+            .map(|number| (BlockHash::random(), number).into())
+            .collect()
+    }
+
+    #[test]
+    fn releases_all_blocks_without_offset() {
+        let mut decided = DecidedBlocks::new(FinalizationDepthOffset::default(), SESSION_END);
+        let branch = blocks(1, 5);
+        assert_eq!(decided.decided(branch.clone()), branch);
+    }
+
+    #[test]
+    fn holds_blocks_above_offset() {
+        let mut decided = DecidedBlocks::new(FinalizationDepthOffset::new(3), SESSION_END);
+        let branch = blocks(1, 10);
+        assert!(decided.decided(branch[..3].to_vec()).is_empty());
+        assert_eq!(decided.decided(branch[3..5].to_vec()), branch[..2].to_vec());
+        assert!(decided.decided(Vec::new()).is_empty());
+        assert_eq!(decided.decided(branch[5..].to_vec()), branch[2..7].to_vec());
+    }
+
+    #[test]
+    fn releases_all_blocks_at_session_end() {
+        let mut decided = DecidedBlocks::new(FinalizationDepthOffset::new(5), SESSION_END);
+        let branch = blocks(SESSION_END - 6, SESSION_END);
+        assert_eq!(decided.decided(branch[..4].to_vec()), Vec::<BlockId>::new());
+        assert_eq!(decided.decided(branch[4..].to_vec()), branch);
+    }
+}
@@ -12,7 +12,7 @@ use sp_runtime::{
 
 use crate::{
     aleph_primitives::{BlockHash, BlockNumber},
-    data_io::{proposal::UnvalidatedAlephProposal, AlephData, MAX_DATA_BRANCH_LEN},
+    data_io::{proposal::UnvalidatedAlephProposal, AlephData, FinalizationDepthOffset},
     metrics::Checkpoint,
     BlockId, BlockMetrics, SessionBoundaries,
 };
@@ -59,6 +59,7 @@ pub fn get_proposal<B, C>(
     client: &C,
     best_block: BlockId,
     finalized_block: BlockId,
+    max_branch_len: usize,
 ) -> Result<AlephData, ()>
 where
     B: BlockT<Hash = BlockHash>,
@@ -69,7 +70,7 @@ where
     let mut branch = Vec::new();
     while curr_block.number > finalized_block.number {
         if curr_block.number - finalized_block.number
-            <= <BlockNumber>::saturated_from(MAX_DATA_BRANCH_LEN)
+            <= <BlockNumber>::saturated_from(max_branch_len)
         {
             branch.push(curr_block.hash);
         }
@@ -94,12 +95,14 @@ const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
 
 pub struct ChainTrackerConfig {
     pub refresh_interval: Duration,
+    pub finalization_depth_offset: FinalizationDepthOffset,
 }
 
 impl Default for ChainTrackerConfig {
     fn default() -> ChainTrackerConfig {
         ChainTrackerConfig {
             refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            finalization_depth_offset: FinalizationDepthOffset::default(),
         }
     }
 }
@@ -209,6 +212,7 @@ where
             &*self.client,
             best_block_in_session.clone(),
             finalized_block,
+            self.config.finalization_depth_offset.max_branch_len(),
         ) {
             *self.data_to_propose.lock() = Some(proposal);
         }
@@ -305,7 +309,7 @@ pub struct DataProvider {
 // 2. If the node does not know of any block in session `k` or if `best_block` is equal to the last finalized block
 //    then the node proposes `Empty`, otherwise the node proposes a branch extending from one block above
 //    last finalized till `best_block` with the restriction that the branch must be truncated to length
-//    at most MAX_DATA_BRANCH_LEN.
+//    at most MAX_DATA_BRANCH_LEN, extended by the finalization depth offset if there is one.
 impl DataProvider {
     pub async fn get_data(&mut self) -> Option<AlephData> {
         let data_to_propose = (*self.data_to_propose.lock()).take();
@@ -363,6 +367,7 @@ mod tests {
 
         let config = ChainTrackerConfig {
             refresh_interval: REFRESH_INTERVAL,
+            finalization_depth_offset: Default::default(),
         };
 
         let (chain_tracker, data_provider) = ChainTracker::new(
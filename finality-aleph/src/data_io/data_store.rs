@@ -4,7 +4,7 @@ use std::{
     hash::Hash,
     num::NonZeroUsize,
     sync::Arc,
-    time::{self, Duration},
+    time::{self, Duration, Instant},
 };
 
 use futures::{
@@ -21,12 +21,13 @@ use sc_client_api::{BlockchainEvents, HeaderBackend};
 use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
 
 use crate::{
+    abft::AbftMetrics,
     aleph_primitives::{BlockHash, BlockNumber},
     data_io::{
         chain_info::{CachedChainInfoProvider, ChainInfoProvider, SubstrateChainInfoProvider},
         proposal::{AlephProposal, ProposalStatus},
         status_provider::get_proposal_status,
-        AlephNetworkMessage,
+        AlephNetworkMessage, FinalizationDepthOffset,
     },
     network::data::{
         component::{Network as ComponentNetwork, Receiver, SimpleNetwork},
@@ -68,6 +69,8 @@ pub struct PendingMessageInfo<M: AlephNetworkMessage> {
     message: M,
     // Data items that we still wait for
     pending_proposals: HashSet<AlephProposal>,
+    // When was the message received.
+    received: Instant,
 }
 
 impl<M: AlephNetworkMessage> PendingMessageInfo<M> {
@@ -75,6 +78,7 @@ impl<M: AlephNetworkMessage> PendingMessageInfo<M> {
         PendingMessageInfo {
             message,
             pending_proposals: HashSet::new(),
+            received: Instant::now(),
         }
     }
 }
@@ -88,6 +92,8 @@ pub struct DataStoreConfig {
     // Specifies how much time must pass from receiving a given proposal for the first time, till we
     // perform a request for either a block or a justification required to let this proposal through.
     pub request_block_after: Duration,
+    // Proposals may reach further above the finalized block by this offset.
+    pub finalization_depth_offset: FinalizationDepthOffset,
 }
 
 impl Default for DataStoreConfig {
@@ -99,6 +105,7 @@ impl Default for DataStoreConfig {
             available_proposals_cache_capacity: NonZeroUsize::new(8000).unwrap(),
             periodic_maintenance_interval: Duration::from_secs(25),
             request_block_after: Duration::from_secs(20),
+            finalization_depth_offset: FinalizationDepthOffset::default(),
         }
     }
 }
@@ -175,6 +182,7 @@ where
     config: DataStoreConfig,
     messages_from_network: R,
     messages_for_aleph: UnboundedSender<Message>,
+    metrics: AbftMetrics,
 }
 
 impl<B, C, RB, Message, R> DataStore<B, C, RB, Message, R>
@@ -199,6 +207,7 @@ where
         block_requester: RB,
         config: DataStoreConfig,
         component_network: N,
+        metrics: AbftMetrics,
     ) -> (Self, impl DataNetwork<Message>) {
         let (messages_for_aleph, messages_from_data_store) = mpsc::unbounded();
         let (messages_to_network, messages_from_network) = component_network.into();
@@ -225,6 +234,7 @@ where
                 config,
                 messages_from_network,
                 messages_for_aleph,
+                metrics,
             },
             SimpleNetwork::new(messages_from_data_store, messages_to_network),
         )
@@ -537,6 +547,8 @@ where
         };
         message_info.pending_proposals.remove(proposal);
         if message_info.pending_proposals.is_empty() {
+            self.metrics
+                .report_data_availability_wait(message_info.received.elapsed());
             self.on_message_dependencies_resolved(message_info.message);
         } else {
             // We reinsert the message because it still has pending proposals.
@@ -607,7 +619,10 @@ where
         let mut proposals = Vec::new();
         for data in message.included_data() {
             let unvalidated_proposal = data.head_proposal;
-            match unvalidated_proposal.validate_bounds(&self.session_boundaries) {
+            match unvalidated_proposal.validate_bounds(
+                &self.session_boundaries,
+                self.config.finalization_depth_offset.max_branch_len(),
+            ) {
                 Ok(proposal) => proposals.push(proposal),
                 Err(error) => {
                     warn!(target: "aleph-data-store", "Message {:?} dropped as it contains \
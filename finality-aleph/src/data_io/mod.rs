@@ -1,6 +1,9 @@
 use std::{fmt::Debug, hash::Hash, num::NonZeroUsize};
 
 use parity_scale_codec::{Decode, Encode};
+use sp_runtime::SaturatedConversion;
+
+use crate::aleph_primitives::{BlockNumber, MAX_FINALIZATION_DEPTH_OFFSET};
 
 mod chain_info;
 mod data_interpreter;
@@ -11,13 +14,39 @@ mod status_provider;
 
 pub use chain_info::{ChainInfoProvider, SubstrateChainInfoProvider};
 pub use data_interpreter::OrderedDataInterpreter;
-pub use data_provider::{ChainTracker, DataProvider};
+pub use data_provider::{ChainTracker, ChainTrackerConfig, DataProvider};
 pub use data_store::{DataStore, DataStoreConfig};
 pub use proposal::UnvalidatedAlephProposal;
 
 // Maximum number of blocks above the last finalized allowed in an AlephBFT proposal.
 pub const MAX_DATA_BRANCH_LEN: usize = 7;
 
+/// How many blocks below the head decided by AlephBFT the finalized block is. The blocks above
+/// the finalized one are decided, but not irreversible yet, giving external auditors a buffer.
+/// The last block of a session is always finalized without delay.
+///
+/// It affects which blocks get justified, so it has to be the same for all the validators. That is
+/// why it is set on chain, and read from the state at the end of the previous session.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FinalizationDepthOffset(BlockNumber);
+
+impl FinalizationDepthOffset {
+    /// Offset of the given number of blocks, capped at `MAX_FINALIZATION_DEPTH_OFFSET`.
+    pub fn new(offset: BlockNumber) -> Self {
+        FinalizationDepthOffset(offset.min(MAX_FINALIZATION_DEPTH_OFFSET))
+    }
+
+    pub fn blocks(&self) -> BlockNumber {
+        self.0
+    }
+
+    // Maximum number of blocks above the last finalized allowed in a proposal. With a nonzero
+    // offset the decided blocks wait above the finalized one, so proposals have to reach above them.
+    pub fn max_branch_len(&self) -> usize {
+        MAX_DATA_BRANCH_LEN + self.0.saturated_into::<usize>()
+    }
+}
+
 /// The data ordered by the Aleph consensus.
 #[derive(Clone, Debug, Encode, Decode, Hash, PartialEq, Eq)]
 pub struct AlephData {
@@ -5,7 +5,6 @@ use sp_runtime::SaturatedConversion;
 
 use crate::{
     aleph_primitives::{BlockHash, BlockNumber},
-    data_io::MAX_DATA_BRANCH_LEN,
     BlockId, SessionBoundaries,
 };
 
@@ -59,10 +58,11 @@ impl UnvalidatedAlephProposal {
     pub(crate) fn validate_bounds(
         &self,
         session_boundaries: &SessionBoundaries,
+        max_branch_len: usize,
     ) -> Result<AlephProposal, ValidationError> {
         use ValidationError::*;
 
-        if self.branch.len() > MAX_DATA_BRANCH_LEN {
+        if self.branch.len() > max_branch_len {
             return Err(BranchTooLong {
                 branch_size: self.branch.len(),
             });
@@ -204,8 +204,9 @@ mod tests {
 
     use super::{UnvalidatedAlephProposal, ValidationError::*};
     use crate::{
-        aleph_primitives::BlockNumber, data_io::MAX_DATA_BRANCH_LEN, SessionBoundaryInfo,
-        SessionId, SessionPeriod,
+        aleph_primitives::BlockNumber,
+        data_io::{FinalizationDepthOffset, MAX_DATA_BRANCH_LEN},
+        SessionBoundaryInfo, SessionId, SessionPeriod,
     };
 
     #[test]
@@ -215,7 +216,7 @@ mod tests {
         let branch = vec![];
         let proposal = UnvalidatedAlephProposal::new(branch, session_boundaries.first_block());
         assert_eq!(
-            proposal.validate_bounds(&session_boundaries),
+            proposal.validate_bounds(&session_boundaries, MAX_DATA_BRANCH_LEN),
             Err(BranchEmpty)
         );
     }
@@ -229,7 +230,7 @@ mod tests {
         let branch_size = branch.len();
         let proposal = UnvalidatedAlephProposal::new(branch, session_end);
         assert_eq!(
-            proposal.validate_bounds(&session_boundaries),
+            proposal.validate_bounds(&session_boundaries, MAX_DATA_BRANCH_LEN),
             Err(BranchTooLong { branch_size })
         );
     }
@@ -244,7 +245,7 @@ mod tests {
 
         let proposal = UnvalidatedAlephProposal::new(branch.clone(), session_start);
         assert_eq!(
-            proposal.validate_bounds(&session_boundaries),
+            proposal.validate_bounds(&session_boundaries, MAX_DATA_BRANCH_LEN),
             Err(BlockOutsideSessionBoundaries {
                 session_start,
                 session_end,
@@ -255,7 +256,7 @@ mod tests {
 
         let proposal = UnvalidatedAlephProposal::new(branch, session_end + 1);
         assert_eq!(
-            proposal.validate_bounds(&session_boundaries),
+            proposal.validate_bounds(&session_boundaries, MAX_DATA_BRANCH_LEN),
             Err(BlockOutsideSessionBoundaries {
                 session_start,
                 session_end,
@@ -273,7 +274,7 @@ mod tests {
 
         let proposal = UnvalidatedAlephProposal::new(branch, 1);
         assert_eq!(
-            proposal.validate_bounds(&session_boundaries),
+            proposal.validate_bounds(&session_boundaries, MAX_DATA_BRANCH_LEN),
             Err(BlockNumberOutOfBounds {
                 branch_size: 2,
                 block_number: 1
@@ -289,11 +290,36 @@ mod tests {
         let branch = vec![H256::default(); MAX_DATA_BRANCH_LEN];
         let proposal =
             UnvalidatedAlephProposal::new(branch, (MAX_DATA_BRANCH_LEN + 1) as BlockNumber);
-        assert!(proposal.validate_bounds(&session_boundaries).is_ok());
+        assert!(proposal
+            .validate_bounds(&session_boundaries, MAX_DATA_BRANCH_LEN)
+            .is_ok());
 
         let branch = vec![H256::default(); 1];
         let proposal =
             UnvalidatedAlephProposal::new(branch, (MAX_DATA_BRANCH_LEN + 1) as BlockNumber);
-        assert!(proposal.validate_bounds(&session_boundaries).is_ok());
+        assert!(proposal
+            .validate_bounds(&session_boundaries, MAX_DATA_BRANCH_LEN)
+            .is_ok());
+    }
+
+    #[test]
+    fn longer_proposal_is_valid_with_finalization_depth_offset() {
+        let session_boundaries =
+            SessionBoundaryInfo::new(SessionPeriod(20)).boundaries_for_session(SessionId(1));
+        let session_end = session_boundaries.last_block();
+        let max_branch_len = FinalizationDepthOffset::new(3).max_branch_len();
+        let branch = vec![H256::default(); MAX_DATA_BRANCH_LEN + 3];
+        let proposal = UnvalidatedAlephProposal::new(branch, session_end);
+        assert!(proposal
+            .validate_bounds(&session_boundaries, max_branch_len)
+            .is_ok());
+
+        let branch = vec![H256::default(); MAX_DATA_BRANCH_LEN + 4];
+        let branch_size = branch.len();
+        let proposal = UnvalidatedAlephProposal::new(branch, session_end);
+        assert_eq!(
+            proposal.validate_bounds(&session_boundaries, max_branch_len),
+            Err(BranchTooLong { branch_size })
+        );
     }
 }
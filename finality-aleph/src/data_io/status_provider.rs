@@ -180,7 +180,9 @@ mod tests {
         let unvalidated = unvalidated_proposal_from_headers(headers);
         let session_boundaries = SessionBoundaryInfo::new(SessionPeriod(DUMMY_SESSION_LEN))
             .boundaries_for_session(SessionId(0));
-        unvalidated.validate_bounds(&session_boundaries).unwrap()
+        unvalidated
+            .validate_bounds(&session_boundaries, MAX_DATA_BRANCH_LEN)
+            .unwrap()
     }
 
     fn proposal_from_blocks(blocks: Vec<TBlock>) -> AlephProposal {
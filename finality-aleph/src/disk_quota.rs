@@ -0,0 +1,416 @@
+use std::{
+    fs,
+    io::{ErrorKind, Result as IoResult},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, warn};
+use substrate_prometheus_endpoint::{
+    register, CounterVec, Gauge, GaugeVec, Opts, PrometheusError, Registry, U64,
+};
+use tokio::{task::spawn_blocking, time::sleep};
+
+use crate::{shutdown_report::REPORTS_DIRECTORY, sync::capture_files};
+
+const LOG_TARGET: &str = "aleph-disk-quota";
+/// How often the usage is measured and the quota enforced.
+const ENFORCEMENT_PERIOD: Duration = Duration::from_secs(30);
+const KIND_LABEL: &str = "kind";
+
+/// The kinds of storage written by this crate that the quota covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StorageKind {
+    /// Sync capture files, evicted file by file.
+    Capture,
+    /// AlephBFT backups, evicted a whole session at a time.
+    Backup,
+    /// Shutdown reports, evicted file by file.
+    ShutdownReport,
+}
+
+impl StorageKind {
+    fn label(&self) -> &'static str {
+        use StorageKind::*;
+        match self {
+            Capture => "capture",
+            Backup => "backup",
+            ShutdownReport => "shutdown_report",
+        }
+    }
+
+    /// How many of the newest units are never evicted. The newest capture file is still being
+    /// written, and removing the backups of the current session, or the previous one still
+    /// finishing, could make us equivocate after a crash.
+    fn protected(&self) -> usize {
+        use StorageKind::*;
+        match self {
+            Capture => 1,
+            Backup => 2,
+            ShutdownReport => 0,
+        }
+    }
+}
+
+/// A piece of storage evicted as a whole, either a file or a directory.
+struct Unit {
+    path: PathBuf,
+    bytes: u64,
+    modified: SystemTime,
+}
+
+impl Unit {
+    fn file(path: PathBuf) -> IoResult<Self> {
+        let metadata = fs::metadata(&path)?;
+        Ok(Unit {
+            path,
+            bytes: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    /// The directory with all its contents, modified when anything in it was modified last.
+    fn directory(path: PathBuf) -> IoResult<Self> {
+        let mut unit = Unit {
+            bytes: 0,
+            modified: fs::metadata(&path)?.modified()?,
+            path,
+        };
+        let mut pending = vec![unit.path.clone()];
+        while let Some(directory) = pending.pop() {
+            for entry in fs::read_dir(directory)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                match metadata.is_dir() {
+                    true => pending.push(entry.path()),
+                    false => unit.bytes += metadata.len(),
+                }
+                unit.modified = unit.modified.max(metadata.modified()?);
+            }
+        }
+        Ok(unit)
+    }
+
+    fn remove(&self) -> IoResult<()> {
+        let result = match self.path.is_dir() {
+            true => fs::remove_dir_all(&self.path),
+            false => fs::remove_file(&self.path),
+        };
+        match result {
+            // Already removed by whoever wrote it, which is just as good.
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// A directory containing storage of a single kind.
+struct Area {
+    kind: StorageKind,
+    directory: PathBuf,
+}
+
+impl Area {
+    /// The units in the area, oldest first.
+    fn units(&self) -> IoResult<Vec<Unit>> {
+        use StorageKind::*;
+        let result = match self.kind {
+            Capture => capture_files(&self.directory)
+                .and_then(|files| files.into_iter().map(Unit::file).collect::<IoResult<_>>()),
+            Backup => session_directories(&self.directory).and_then(|directories| {
+                directories
+                    .into_iter()
+                    .map(Unit::directory)
+                    .collect::<IoResult<_>>()
+            }),
+            ShutdownReport => report_files(&self.directory),
+        };
+        match result {
+            // Nothing was written yet.
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            result => result,
+        }
+    }
+}
+
+/// The backup directories of the sessions, in the order of the sessions.
+fn session_directories(directory: &Path) -> IoResult<Vec<PathBuf>> {
+    let mut sessions: Vec<_> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .filter_map(|path| {
+            let session: u32 = path.file_name()?.to_str()?.parse().ok()?;
+            Some((session, path))
+        })
+        .collect();
+    sessions.sort();
+    Ok(sessions.into_iter().map(|(_, path)| path).collect())
+}
+
+fn report_files(directory: &Path) -> IoResult<Vec<Unit>> {
+    let mut reports = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .map(Unit::file)
+        .collect::<IoResult<Vec<_>>>()?;
+    reports.sort_by_key(|report| report.modified);
+    Ok(reports)
+}
+
+enum Metrics {
+    Prometheus {
+        limit: Gauge<U64>,
+        usage: GaugeVec<U64>,
+        evicted: CounterVec<U64>,
+    },
+    Noop,
+}
+
+impl Metrics {
+    fn new(registry: Option<Registry>) -> Result<Self, PrometheusError> {
+        let registry = match registry {
+            Some(registry) => registry,
+            None => return Ok(Metrics::Noop),
+        };
+        Ok(Metrics::Prometheus {
+            limit: register(
+                Gauge::new(
+                    "aleph_disk_quota_limit_bytes",
+                    "the disk quota of the storage written by finality",
+                )?,
+                &registry,
+            )?,
+            usage: register(
+                GaugeVec::new(
+                    Opts::new(
+                        "aleph_disk_quota_usage_bytes",
+                        "the disk space used by every kind of storage covered by the quota",
+                    ),
+                    &[KIND_LABEL],
+                )?,
+                &registry,
+            )?,
+            evicted: register(
+                CounterVec::new(
+                    Opts::new(
+                        "aleph_disk_quota_evicted_bytes",
+                        "the disk space freed by evicting every kind of storage",
+                    ),
+                    &[KIND_LABEL],
+                )?,
+                &registry,
+            )?,
+        })
+    }
+
+    fn report_limit(&self, bytes: u64) {
+        if let Metrics::Prometheus { limit, .. } = self {
+            limit.set(bytes);
+        }
+    }
+
+    fn report_usage(&self, kind: StorageKind, bytes: u64) {
+        if let Metrics::Prometheus { usage, .. } = self {
+            usage.with_label_values(&[kind.label()]).set(bytes);
+        }
+    }
+
+    fn report_evicted(&self, kind: StorageKind, bytes: u64) {
+        if let Metrics::Prometheus { evicted, .. } = self {
+            evicted.with_label_values(&[kind.label()]).inc_by(bytes);
+        }
+    }
+}
+
+/// Keeps the storage written by this crate within a limit, evicting the oldest files first, so
+/// that the optional diagnostics can be enabled in production without filling the disk.
+pub struct DiskQuota {
+    max_bytes: u64,
+    areas: Vec<Area>,
+    metrics: Metrics,
+}
+
+impl DiskQuota {
+    pub fn new(max_bytes: u64, registry: Option<Registry>) -> Self {
+        let metrics = Metrics::new(registry).unwrap_or_else(|e| {
+            warn!(
+                target: LOG_TARGET,
+                "Failed to register disk quota metrics: {}.", e
+            );
+            Metrics::Noop
+        });
+        metrics.report_limit(max_bytes);
+        DiskQuota {
+            max_bytes,
+            areas: Vec::new(),
+            metrics,
+        }
+    }
+
+    /// Covers the directory the sync capture files are written to.
+    pub fn with_captures(self, directory: PathBuf) -> Self {
+        self.with_area(StorageKind::Capture, directory)
+    }
+
+    /// Covers the AlephBFT backups and the shutdown reports kept next to them.
+    pub fn with_backups(self, backup_path: PathBuf) -> Self {
+        let reports = backup_path.join(REPORTS_DIRECTORY);
+        self.with_area(StorageKind::Backup, backup_path)
+            .with_area(StorageKind::ShutdownReport, reports)
+    }
+
+    fn with_area(mut self, kind: StorageKind, directory: PathBuf) -> Self {
+        self.areas.push(Area { kind, directory });
+        self
+    }
+
+    /// Measures the usage and evicts the oldest units until it fits within the quota. Returns
+    /// the usage afterwards, which can still exceed the quota if only protected units are left.
+    pub fn enforce(&self) -> u64 {
+        let mut usages = Vec::with_capacity(self.areas.len());
+        let mut candidates = Vec::new();
+        for (index, area) in self.areas.iter().enumerate() {
+            let units = match area.units() {
+                Ok(units) => units,
+                Err(e) => {
+                    warn!(
+                        target: LOG_TARGET,
+                        "Failed to measure the {} storage in {:?}: {}.",
+                        area.kind.label(),
+                        area.directory,
+                        e
+                    );
+                    Vec::new()
+                }
+            };
+            usages.push(units.iter().map(|unit| unit.bytes).sum::<u64>());
+            let evictable = units.len().saturating_sub(area.kind.protected());
+            candidates.extend(units.into_iter().take(evictable).map(|unit| (index, unit)));
+        }
+        let mut usage: u64 = usages.iter().sum();
+        // Stable, so units modified at the same time stay in the order of their areas.
+        candidates.sort_by_key(|(_, unit)| unit.modified);
+        for (index, unit) in candidates {
+            if usage <= self.max_bytes {
+                break;
+            }
+            let kind = self.areas[index].kind;
+            match unit.remove() {
+                Ok(()) => {
+                    debug!(
+                        target: LOG_TARGET,
+                        "Evicted {:?} of {} bytes to stay within the disk quota.",
+                        unit.path,
+                        unit.bytes
+                    );
+                    usage -= unit.bytes;
+                    usages[index] -= unit.bytes;
+                    self.metrics.report_evicted(kind, unit.bytes);
+                }
+                Err(e) => warn!(
+                    target: LOG_TARGET,
+                    "Failed to evict {:?}: {}.", unit.path, e
+                ),
+            }
+        }
+        for (area, usage) in self.areas.iter().zip(usages) {
+            self.metrics.report_usage(area.kind, usage);
+        }
+        if usage > self.max_bytes {
+            warn!(
+                target: LOG_TARGET,
+                "Using {} bytes of disk, over the quota of {}, but nothing more can be evicted.",
+                usage,
+                self.max_bytes
+            );
+        }
+        usage
+    }
+}
+
+/// Enforces the quota periodically, off the async threads.
+pub async fn run_disk_quota(quota: DiskQuota) {
+    let quota = Arc::new(quota);
+    loop {
+        let enforced = quota.clone();
+        if let Err(e) = spawn_blocking(move || enforced.enforce()).await {
+            warn!(
+                target: LOG_TARGET,
+                "Disk quota enforcement failed, stopping it: {}.", e
+            );
+            return;
+        }
+        sleep(ENFORCEMENT_PERIOD).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::DiskQuota;
+    use crate::{shutdown_report::REPORTS_DIRECTORY, testing::TestDirectory};
+
+    fn write(path: PathBuf, bytes: usize) {
+        fs::create_dir_all(path.parent().expect("has a parent")).expect("directory can be created");
+        fs::write(path, vec![0; bytes]).expect("writing works");
+    }
+
+    fn capture_file(directory: &Path, index: u64) -> PathBuf {
+        directory.join(format!("sync-capture-{index:010}.bin"))
+    }
+
+    #[test]
+    fn evicts_oldest_captures_but_not_the_current_one() {
+        let directory = TestDirectory::new("disk-quota");
+        for index in 0..4 {
+            write(capture_file(&directory, index), 100);
+        }
+        // Not a capture file, so not ours to evict.
+        write(directory.join("notes.txt"), 1000);
+        let quota = DiskQuota::new(250, None).with_captures(directory.to_path_buf());
+        assert_eq!(quota.enforce(), 200);
+        assert!(!capture_file(&directory, 1).exists());
+        assert!(capture_file(&directory, 2).exists());
+        assert!(directory.join("notes.txt").exists());
+
+        let quota = DiskQuota::new(0, None).with_captures(directory.to_path_buf());
+        assert_eq!(quota.enforce(), 100);
+        assert!(capture_file(&directory, 3).exists());
+    }
+
+    #[test]
+    fn evicts_whole_old_sessions_of_backups() {
+        let directory = TestDirectory::new("disk-quota");
+        for session in [3, 4, 12] {
+            write(directory.join(format!("{session}/0.abfts")), 100);
+            write(directory.join(format!("{session}/1.abfts")), 100);
+        }
+        write(directory.join(REPORTS_DIRECTORY).join("report.json"), 50);
+        let quota = DiskQuota::new(0, None).with_backups(directory.to_path_buf());
+        // The two latest sessions are kept, the shutdown report is not.
+        assert_eq!(quota.enforce(), 400);
+        assert!(!directory.join("3").exists());
+        assert!(directory.join("4/0.abfts").exists());
+        assert!(directory.join("12/1.abfts").exists());
+        assert!(!directory
+            .join(REPORTS_DIRECTORY)
+            .join("report.json")
+            .exists());
+    }
+
+    #[test]
+    fn ignores_missing_directories() {
+        let directory = TestDirectory::new("disk-quota");
+        let quota = DiskQuota::new(0, None)
+            .with_captures(directory.join("captures"))
+            .with_backups(directory.to_path_buf());
+        assert_eq!(quota.enforce(), 0);
+    }
+}
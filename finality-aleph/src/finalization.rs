@@ -1,8 +1,10 @@
 use core::result::Result;
-use std::{marker::PhantomData, sync::Arc, time::Instant};
+use std::{iter, marker::PhantomData, sync::Arc, time::Instant};
 
 use log::{debug, warn};
-use sc_client_api::{Backend, Finalizer, HeaderBackend, LockImportRun};
+use sc_client_api::{
+    backend::BlockImportOperation, Backend, Finalizer, HeaderBackend, LockImportRun,
+};
 use sp_blockchain::Error;
 use sp_runtime::{
     traits::{Block, Header},
@@ -12,6 +14,7 @@ use sp_runtime::{
 use crate::{
     aleph_primitives::{BlockHash, BlockNumber},
     metrics::Checkpoint,
+    sync::substrate::JustificationArchiving,
     BlockId, BlockIdentifier, BlockMetrics,
 };
 
@@ -27,6 +30,7 @@ where
 {
     client: Arc<C>,
     metrics: BlockMetrics,
+    archiving: Option<JustificationArchiving>,
     phantom: PhantomData<(B, BE)>,
 }
 
@@ -40,9 +44,20 @@ where
         AlephFinalizer {
             client,
             metrics,
+            archiving: None,
             phantom: PhantomData,
         }
     }
+
+    /// Stores the justifications the retention allows pruning in the archive, in the same
+    /// transaction that finalizes their blocks.
+    pub(crate) fn with_justification_archiving(
+        mut self,
+        archiving: JustificationArchiving,
+    ) -> Self {
+        self.archiving = Some(archiving);
+        self
+    }
 }
 
 impl<B, BE, C> BlockFinalizer<BlockId> for AlephFinalizer<B, BE, C>
@@ -53,6 +68,11 @@ where
     C: HeaderBackend<B> + LockImportRun<B, BE> + Finalizer<B, BE>,
 {
     fn finalize_block(&self, block: BlockId, justification: Justification) -> Result<(), Error> {
+        let archived = self
+            .archiving
+            .as_ref()
+            .filter(|archiving| archiving.archives(block.number))
+            .map(|archiving| archiving.entry(&block, &justification));
         let BlockId { number, hash } = block;
 
         let status = self.client.info();
@@ -65,8 +85,15 @@ where
 
         let update_res = self.client.lock_import_and_run(|import_op| {
             // NOTE: all other finalization logic should come here, inside the lock
-            self.client
-                .apply_finality(import_op, hash, Some(justification), true)
+            match archived {
+                Some(entry) => {
+                    import_op.op.insert_aux(iter::once(entry))?;
+                    self.client.apply_finality(import_op, hash, None, true)
+                }
+                None => self
+                    .client
+                    .apply_finality(import_op, hash, Some(justification), true),
+            }
         });
 
         let status = self.client.info();
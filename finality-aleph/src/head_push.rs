@@ -0,0 +1,478 @@
+use std::{
+    collections::HashSet,
+    fmt::{Display, Error as FmtError, Formatter},
+    io::Error as IoError,
+    marker::PhantomData,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{FutureExt, StreamExt};
+use log::{debug, warn};
+use parity_scale_codec::Encode;
+use sc_client_api::{Backend, FinalityNotification};
+use serde::Serialize;
+use sp_api::ProvideRuntimeApi;
+use sp_application_crypto::RuntimeAppPublic;
+use sp_keystore::Keystore;
+use sp_runtime::traits::Header;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{sleep_until, timeout, Instant},
+};
+
+use crate::{
+    aleph_primitives::{AlephSessionApi, Block, BlockHash, BlockNumber, KEY_TYPE},
+    crypto::AuthorityPen,
+    session::{SessionBoundaryInfo, SessionId},
+    ClientForAleph,
+};
+
+const LOG_TARGET: &str = "aleph-head-push";
+/// Separates the signatures in announcements from anything else signed with the same key.
+const ANNOUNCEMENT_CONTEXT: &[u8] = b"aleph-head-announcement";
+/// How long the endpoint has to accept an announcement.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+/// The delay before the first retry, doubled with every failure up to the maximum.
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How much of the response is read to find the status code.
+const MAX_STATUS_LINE: usize = 1024;
+
+/// What can be wrong with the address of the endpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EndpointError {
+    UnsupportedScheme,
+    MissingHost,
+    BadPort(String),
+}
+
+impl Display for EndpointError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use EndpointError::*;
+        match self {
+            UnsupportedScheme => write!(f, "only plain http:// endpoints are supported"),
+            MissingHost => write!(f, "the endpoint has no host"),
+            BadPort(port) => write!(f, "the endpoint port {port} is not a number"),
+        }
+    }
+}
+
+/// The HTTP endpoint the announcements of the finalized blocks are posted to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeadPushEndpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for HeadPushEndpoint {
+    type Err = EndpointError;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        use EndpointError::*;
+        let rest = address.strip_prefix("http://").ok_or(UnsupportedScheme)?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| BadPort(port.to_string()))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(MissingHost);
+        }
+        Ok(HeadPushEndpoint {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Display for HeadPushEndpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+/// Why an announcement did not get accepted.
+#[derive(Debug)]
+enum PushError {
+    Io(IoError),
+    Timeout,
+    MalformedResponse,
+    Status(u16),
+}
+
+impl Display for PushError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use PushError::*;
+        match self {
+            Io(e) => write!(f, "{e}"),
+            Timeout => write!(f, "no response within {}s", PUSH_TIMEOUT.as_secs()),
+            MalformedResponse => write!(f, "malformed response"),
+            Status(status) => write!(f, "response with status {status}"),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut result = String::from("0x");
+    for byte in bytes {
+        result.push_str(&format!("{byte:02x}"));
+    }
+    result
+}
+
+/// What gets signed in the announcement of the block.
+fn announcement_payload(hash: &BlockHash, number: BlockNumber, session: SessionId) -> Vec<u8> {
+    let mut payload = ANNOUNCEMENT_CONTEXT.to_vec();
+    (hash, number, session.0).encode_to(&mut payload);
+    payload
+}
+
+/// The announcement of a newly finalized block. Signed with the authority key of this node if it
+/// belongs to the validator set of the session, so that the receivers can check it against the
+/// set on chain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HeadAnnouncement {
+    number: BlockNumber,
+    hash: String,
+    session: u32,
+    /// The index of the signer in the validator set of the session.
+    authority_index: Option<usize>,
+    signer: Option<String>,
+    signature: Option<String>,
+}
+
+impl HeadAnnouncement {
+    fn new(
+        hash: BlockHash,
+        number: BlockNumber,
+        session: SessionId,
+        signer: Option<(usize, &AuthorityPen)>,
+    ) -> Self {
+        let (authority_index, signer, signature) = match signer {
+            Some((index, pen)) => (
+                Some(index),
+                Some(hex(&pen.authority_id().encode())),
+                Some(hex(&pen
+                    .sign(&announcement_payload(&hash, number, session))
+                    .encode())),
+            ),
+            None => (None, None, None),
+        };
+        HeadAnnouncement {
+            number,
+            hash: hex(hash.as_ref()),
+            session: session.0,
+            authority_index,
+            signer,
+            signature,
+        }
+    }
+}
+
+/// The status code from the first line of an HTTP response.
+fn status_code(response: &[u8]) -> Option<u16> {
+    let line = response.split(|byte| *byte == b'\n').next()?;
+    let line = std::str::from_utf8(line).ok()?;
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+async fn post(endpoint: &HeadPushEndpoint, body: &[u8]) -> Result<(), PushError> {
+    let mut stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port))
+        .await
+        .map_err(PushError::Io)?;
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        endpoint.path,
+        endpoint.host,
+        endpoint.port,
+        body.len()
+    );
+    stream
+        .write_all(head.as_bytes())
+        .await
+        .map_err(PushError::Io)?;
+    stream.write_all(body).await.map_err(PushError::Io)?;
+    let mut response = Vec::new();
+    let mut buffer = [0; MAX_STATUS_LINE];
+    while !response.contains(&b'\n') && response.len() < MAX_STATUS_LINE {
+        match stream.read(&mut buffer).await.map_err(PushError::Io)? {
+            0 => break,
+            read => response.extend_from_slice(&buffer[..read]),
+        }
+    }
+    match status_code(&response).ok_or(PushError::MalformedResponse)? {
+        200..=299 => Ok(()),
+        status => Err(PushError::Status(status)),
+    }
+}
+
+async fn push(
+    endpoint: &HeadPushEndpoint,
+    announcement: &HeadAnnouncement,
+) -> Result<(), PushError> {
+    let body = serde_json::to_vec(announcement).expect("serializing announcements works");
+    timeout(PUSH_TIMEOUT, post(endpoint, &body))
+        .await
+        .map_err(|_| PushError::Timeout)?
+}
+
+/// The delays between the retries, doubled with every failure and reset after a success.
+struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Backoff { next: MIN_BACKOFF }
+    }
+
+    fn reset(&mut self) {
+        self.next = MIN_BACKOFF;
+    }
+
+    /// How long to wait before the next attempt.
+    fn failed(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = self.next.saturating_mul(2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
+/// Announces the finalized blocks, signing them with our authority key if it is in the validator
+/// set at the block.
+struct Announcer<C, BE>
+where
+    C: ClientForAleph<Block, BE>,
+    C::Api: AlephSessionApi<Block>,
+    BE: Backend<Block>,
+{
+    client: Arc<C>,
+    keystore: Arc<dyn Keystore>,
+    session_info: SessionBoundaryInfo,
+    _phantom: PhantomData<BE>,
+}
+
+impl<C, BE> Announcer<C, BE>
+where
+    C: ClientForAleph<Block, BE>,
+    C::Api: AlephSessionApi<Block>,
+    BE: Backend<Block>,
+{
+    fn signer(&self, hash: BlockHash) -> Option<(usize, AuthorityPen)> {
+        let authorities = match self.client.runtime_api().authorities(hash) {
+            Ok(authorities) => authorities,
+            Err(e) => {
+                warn!(
+                    target: LOG_TARGET,
+                    "Cannot read the validator set at {:?}, announcing unsigned: {}.", hash, e
+                );
+                return None;
+            }
+        };
+        let our_keys: HashSet<_> = match self.keystore.keys(KEY_TYPE) {
+            Ok(keys) => keys.into_iter().collect(),
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Error accessing keystore: {}.", e);
+                return None;
+            }
+        };
+        let index = authorities
+            .iter()
+            .position(|key| our_keys.contains(&key.to_raw_vec()))?;
+        let pen = AuthorityPen::new(authorities[index].clone(), self.keystore.clone()).ok()?;
+        Some((index, pen))
+    }
+
+    fn announce(&self, notification: FinalityNotification<Block>) -> HeadAnnouncement {
+        let hash = notification.hash;
+        let number = *notification.header.number();
+        let session = self.session_info.session_id_from_block_num(number);
+        match self.signer(hash) {
+            Some((index, pen)) => HeadAnnouncement::new(hash, number, session, Some((index, &pen))),
+            None => HeadAnnouncement::new(hash, number, session, None),
+        }
+    }
+}
+
+/// Pushes an announcement of every newly finalized block to the endpoint. When the endpoint
+/// keeps failing, the announcement is retried with a growing delay, replaced by a newer one if
+/// more blocks got finalized in the meantime.
+pub async fn run_head_push<C, BE>(
+    client: Arc<C>,
+    keystore: Arc<dyn Keystore>,
+    session_info: SessionBoundaryInfo,
+    endpoint: HeadPushEndpoint,
+) where
+    C: ClientForAleph<Block, BE>,
+    C::Api: AlephSessionApi<Block>,
+    BE: Backend<Block>,
+{
+    let mut finalized = client.finality_notification_stream();
+    let announcer = Announcer {
+        client,
+        keystore,
+        session_info,
+        _phantom: PhantomData,
+    };
+    let mut backoff = Backoff::new();
+    let mut pending = None;
+    loop {
+        let mut announcement = match pending.take() {
+            Some(announcement) => announcement,
+            None => match finalized.next().await {
+                Some(notification) => announcer.announce(notification),
+                None => return,
+            },
+        };
+        // Only the newest head is interesting.
+        while let Some(Some(notification)) = finalized.next().now_or_never() {
+            announcement = announcer.announce(notification);
+        }
+        match push(&endpoint, &announcement).await {
+            Ok(()) => {
+                backoff.reset();
+                debug!(
+                    target: LOG_TARGET,
+                    "Announced finalized block #{} to {}.", announcement.number, endpoint
+                );
+            }
+            Err(e) => {
+                let delay = backoff.failed();
+                warn!(
+                    target: LOG_TARGET,
+                    "Failed to announce finalized block #{} to {}: {}, retrying in {}ms.",
+                    announcement.number,
+                    endpoint,
+                    e,
+                    delay.as_millis()
+                );
+                pending = Some(announcement);
+                let deadline = Instant::now() + delay;
+                loop {
+                    tokio::select! {
+                        _ = sleep_until(deadline) => break,
+                        maybe_notification = finalized.next() => match maybe_notification {
+                            Some(notification) => pending = Some(announcer.announce(notification)),
+                            None => return,
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parity_scale_codec::Decode;
+    use sp_keystore::{testing::MemoryKeystore, Keystore};
+
+    use super::{
+        announcement_payload, status_code, Backoff, EndpointError, HeadAnnouncement,
+        HeadPushEndpoint, MAX_BACKOFF, MIN_BACKOFF,
+    };
+    use crate::{
+        aleph_primitives::{AuthorityId, BlockHash, KEY_TYPE},
+        crypto::{verify, AuthorityPen, Signature},
+        session::SessionId,
+    };
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        let digits = hex.strip_prefix("0x").expect("prefixed");
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).expect("hex digits"))
+            .collect()
+    }
+
+    #[test]
+    fn parses_endpoints() {
+        assert_eq!(
+            "http://bridge.local:8080/heads".parse(),
+            Ok(HeadPushEndpoint {
+                host: "bridge.local".to_string(),
+                port: 8080,
+                path: "/heads".to_string(),
+            })
+        );
+        assert_eq!(
+            "http://10.0.0.1".parse(),
+            Ok(HeadPushEndpoint {
+                host: "10.0.0.1".to_string(),
+                port: 80,
+                path: "/".to_string(),
+            })
+        );
+        assert_eq!(
+            "https://bridge.local".parse::<HeadPushEndpoint>(),
+            Err(EndpointError::UnsupportedScheme)
+        );
+        assert_eq!(
+            "http://:80/".parse::<HeadPushEndpoint>(),
+            Err(EndpointError::MissingHost)
+        );
+        assert_eq!(
+            "http://bridge.local:http/".parse::<HeadPushEndpoint>(),
+            Err(EndpointError::BadPort("http".to_string()))
+        );
+    }
+
+    #[test]
+    fn signs_announcements_verifiably() {
+        let keystore = Arc::new(MemoryKeystore::new());
+        let key = AuthorityId::from(
+            keystore
+                .ed25519_generate_new(KEY_TYPE, None)
+                .expect("generating keys works"),
+        );
+        let pen = AuthorityPen::new(key.clone(), keystore).expect("the key was just generated");
+        let hash = BlockHash::repeat_byte(7);
+        let announcement = HeadAnnouncement::new(hash, 42, SessionId(3), Some((1, &pen)));
+        assert_eq!(announcement.authority_index, Some(1));
+        let signature =
+            Signature::decode(&mut &unhex(&announcement.signature.expect("signed"))[..])
+                .expect("a signature");
+        assert!(verify(
+            &key,
+            &announcement_payload(&hash, 42, SessionId(3)),
+            &signature
+        ));
+        assert!(!verify(
+            &key,
+            &announcement_payload(&hash, 43, SessionId(3)),
+            &signature
+        ));
+        let unsigned = HeadAnnouncement::new(hash, 42, SessionId(3), None);
+        assert_eq!(unsigned.signature, None);
+    }
+
+    #[test]
+    fn reads_status_codes_and_backs_off() {
+        assert_eq!(status_code(b"HTTP/1.1 204 No Content\r\n"), Some(204));
+        assert_eq!(status_code(b"garbage"), None);
+        let mut backoff = Backoff::new();
+        assert_eq!(backoff.failed(), MIN_BACKOFF);
+        assert_eq!(backoff.failed(), MIN_BACKOFF * 2);
+        for _ in 0..20 {
+            backoff.failed();
+        }
+        assert_eq!(backoff.failed(), MAX_BACKOFF);
+        backoff.reset();
+        assert_eq!(backoff.failed(), MIN_BACKOFF);
+    }
+}
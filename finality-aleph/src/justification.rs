@@ -0,0 +1,4 @@
+pub use light_verifier::{
+    backwards_compatible_decode, versioned_encode, AlephJustification, DecodeError,
+    EmergencyCustody, EmergencyReason,
+};
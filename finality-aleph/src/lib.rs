@@ -1,6 +1,8 @@
 use std::{
+    collections::HashSet,
     fmt::{Debug, Display, Error as FmtError, Formatter},
     hash::Hash,
+    net::{IpAddr, SocketAddr},
     path::PathBuf,
     sync::Arc,
 };
@@ -10,14 +12,15 @@ use futures::{
     channel::{mpsc, oneshot},
     Future,
 };
+use network_clique::{ConnectionHealth, PeerHealth};
 use parity_scale_codec::{Codec, Decode, Encode, Output};
 use primitives as aleph_primitives;
 use primitives::{AuthorityId, Block as AlephBlock, BlockHash, BlockNumber, Hash as AlephHash};
 use sc_client_api::{
-    Backend, BlockBackend, BlockchainEvents, Finalizer, LockImportRun, TransactionFor,
+    AuxStore, Backend, BlockBackend, BlockchainEvents, Finalizer, LockImportRun, TransactionFor,
 };
 use sc_consensus::BlockImport;
-use sc_network::NetworkService;
+use sc_network::{NetworkService, PeerId};
 use sc_network_sync::SyncingService;
 use sp_api::ProvideRuntimeApi;
 use sp_blockchain::{HeaderBackend, HeaderMetadata};
@@ -33,17 +36,20 @@ use crate::{
     },
     aggregation::{CurrentRmcNetworkData, LegacyRmcNetworkData},
     compatibility::{Version, Versioned},
-    network::data::split::Split,
-    session::{SessionBoundaries, SessionBoundaryInfo, SessionId},
+    network::{data::split::Split, tcp::AuthorityIdWrapper},
+    session::SessionBoundaries,
     VersionedTryFromError::{ExpectedNewGotOld, ExpectedOldGotNew},
 };
 
 mod abft;
 mod aggregation;
 mod compatibility;
+mod config;
 mod crypto;
 mod data_io;
+mod disk_quota;
 mod finalization;
+mod head_push;
 mod import;
 mod justification;
 mod metrics;
@@ -52,20 +58,78 @@ mod nodes;
 mod party;
 mod session;
 mod session_map;
+mod session_prefetch;
+mod shutdown_report;
+mod stall_watchdog;
+mod status_page;
 mod sync;
 #[cfg(test)]
 pub mod testing;
 
+#[cfg(feature = "simnet")]
+pub use crate::sync::{
+    run_simnet, Crash as SimnetCrash, FaultParseError as SimnetFaultParseError,
+    NodeReport as SimnetNodeReport, SessionRange as SimnetSessionRange, SimnetConfig, SimnetError,
+    SimnetReport,
+};
 pub use crate::{
+    config::{
+        AlephNetworkConfig, AlephNodeConfig, AlephSyncConfig, ConfigError as AlephConfigError,
+        V1ShimPeerConfig, DEFAULT_SYNC_CAPTURE_MAX_FILES, DEFAULT_SYNC_CAPTURE_MAX_FILE_MB,
+        DEFAULT_SYNC_FINALIZATION_BATCH, DEFAULT_SYNC_PEER_FORGIVE_AFTER_SECS,
+        DEFAULT_SYNC_PEER_SCORE_DECAY, DEFAULT_SYNC_VERIFICATION_SAMPLE,
+    },
+    head_push::{EndpointError as HeadPushEndpointError, HeadPushEndpoint},
     import::{AlephBlockImport, TracingBlockImport},
-    justification::AlephJustification,
+    justification::{
+        versioned_encode as encode_aleph_justification, AlephJustification, EmergencyCustody,
+        EmergencyReason,
+    },
     metrics::BlockMetrics,
-    network::{Protocol, ProtocolNaming},
-    nodes::run_validator_node,
-    session::SessionPeriod,
+    network::{
+        block_sync_requests_config, BlockSyncRequests, LimitsError as NetworkLimitsError,
+        NetworkLimits, Protocol, ProtocolNaming, PublicNetworkLimits, ValidatorNetworkLimits,
+        DEFAULT_PUBLIC_HANDSHAKE_TIMEOUT, DEFAULT_PUBLIC_MAX_MESSAGE_SIZE,
+        DEFAULT_VALIDATOR_BIT_RATE_PER_CONNECTION, MIN_PUBLIC_MAX_MESSAGE_SIZE,
+    },
+    nodes::{on_chain_session_info, replay_sync_capture, run_validator_node},
+    party::backup::{
+        check as check_abft_backup, BackupCorruption as AbftBackupCorruption,
+        BackupLoadError as AbftBackupLoadError, BackupReport as AbftBackupReport,
+        CorruptionPoint as AbftBackupCorruptionPoint,
+    },
+    session::{SessionBoundaryInfo, SessionId, SessionPeriod},
+    stall_watchdog::{ForestSummary, StallDiagnostic, StallDiagnostics},
     sync::{
-        substrate::{BlockImporter, Justification},
-        JustificationTranslator, SubstrateChainStatus,
+        capture_files as sync_capture_files,
+        substrate::{
+            archived_justification, compress_archived_justifications, BlockImporter,
+            BlockSource as SyncBlockSource, CustodyPolicy as EmergencyCustodyPolicy,
+            EmergencyAudit, EmergencyFinalization, InnerJustification, Justification,
+            JustificationPruningError, JustificationRetention, VerificationSampling,
+            EMERGENCY_AUDIT_LOG_TARGET,
+        },
+        BackfillProgress as SyncBackfillProgress, BodyBackfill, CaptureConfig as SyncCaptureConfig,
+        CaptureError as SyncCaptureError, CaptureReader as SyncCaptureReader,
+        CaptureRecord as SyncCaptureRecord, ChainCandidates as SyncChainCandidates,
+        ChainHints as SyncChainHints, ChainSelectionStrategy as SyncChainSelectionStrategy,
+        CounterValues as SyncCounterValues, Counters as SyncCounters,
+        Direction as SyncCaptureDirection, ExternallyHinted as SyncExternallyHinted,
+        FinalityStats as SyncFinalityStats, FinalityStatus as SyncFinalityStatus,
+        FinalizationHook as SyncFinalizationHook, FinalizationHooks,
+        FinalizedBlock as SyncFinalizedBlock, FinalizedId as SyncFinalizedId, ForestDump,
+        ForestDumps, ForkId as SyncForkId, ImportEvent as SyncImportEvent, ImportNotifications,
+        ImportedBlock as SyncImportedBlock, JustificationFetches, JustificationTranslator,
+        LocalLimits as SyncLimits, LongestChain as SyncLongestChain,
+        MostRecentlyJustified as SyncMostRecentlyJustified, NetworkFinalityView,
+        PeerFinality as SyncPeerFinality, PeerScore as SyncPeerScore,
+        PeerServing as SyncPeerServing, PeerTracing, Provenance, ProvenanceHistory,
+        ReplayError as SyncReplayError, ReplayReport, ScoreChangeReport as SyncScoreChange,
+        ShimFeature as SyncShimFeature, SnapshotPoint as SyncSnapshotPoint, SnapshotSubscription,
+        SnapshotTrigger as SyncSnapshotTrigger, SnapshotTriggers, StatusReports,
+        SubstrateChainStatus, SyncStatus, V1Shim, VertexContents, VertexDump, VertexInterest,
+        DEFAULT_HOOK_QUEUE as DEFAULT_SYNC_FINALIZATION_HOOK_QUEUE, MAX_SNAPSHOT_PAUSE,
+        MAX_TRACE_DURATION, PEER_TRACE_LOG_TARGET, SNAPSHOT_MARKER_FILE,
     },
 };
 
@@ -76,14 +140,11 @@ const STATUS_REPORT_INTERVAL: Duration = Duration::from_secs(20);
 pub fn peers_set_config(
     naming: ProtocolNaming,
     protocol: Protocol,
+    limits: &PublicNetworkLimits,
 ) -> sc_network::config::NonDefaultSetConfig {
     let mut config = sc_network::config::NonDefaultSetConfig::new(
         naming.protocol_name(&protocol),
-        // max_notification_size should be larger than the maximum possible honest message size (in bytes).
-        // Max size of alert is UNIT_SIZE * MAX_UNITS_IN_ALERT ~ 100 * 5000 = 50000 bytes
-        // Max size of parents response UNIT_SIZE * N_MEMBERS ~ 100 * N_MEMBERS
-        // When adding other (large) message types we need to make sure this limit is fine.
-        1024 * 1024,
+        limits.max_message_size,
     );
 
     config.set_config = sc_network::config::SetConfig::default();
@@ -206,6 +267,7 @@ pub trait ClientForAleph<B, BE>:
     + HeaderMetadata<B, Error = sp_blockchain::Error>
     + BlockchainEvents<B>
     + BlockBackend<B>
+    + AuxStore
 where
     BE: Backend<B>,
     B: Block,
@@ -223,7 +285,8 @@ where
         + HeaderMetadata<B, Error = sp_blockchain::Error>
         + BlockchainEvents<B>
         + BlockImport<B, Transaction = TransactionFor<BE, B>, Error = sp_consensus::Error>
-        + BlockBackend<B>,
+        + BlockBackend<B>
+        + AuxStore,
 {
 }
 
@@ -245,6 +308,10 @@ impl BlockId {
     pub fn new(hash: BlockHash, number: BlockNumber) -> Self {
         BlockId { hash, number }
     }
+
+    pub fn hash(&self) -> BlockHash {
+        self.hash
+    }
 }
 
 impl From<(BlockHash, BlockNumber)> for BlockId {
@@ -266,11 +333,142 @@ impl BlockIdentifier for BlockId {
 }
 
 #[derive(Clone)]
-pub struct RateLimiterConfig {
-    /// Maximum bit-rate per node in bytes per second of the alephbft validator network.
-    pub alephbft_bit_rate_per_connection: usize,
+pub struct SyncConfig {
+    /// Whether this node keeps the bodies of all finalized blocks and can serve them to peers.
+    pub archive_bodies: bool,
+    /// Local limits on the on-chain sync parameters.
+    pub limits: SyncLimits,
+    /// The fork of the chain we follow, peers following other forks are ignored.
+    pub fork_id: SyncForkId,
+    /// Where the peers that supplied data about blocks finalized by sync are recorded.
+    pub provenance: SyncProvenance,
+    /// Which peer, if any, has its sync messages traced in detail.
+    pub peer_tracing: SyncPeerTracing,
+    /// Where requests for dumps of the sync forest come from.
+    pub forest_dumps: SyncForestDumps,
+    /// Where requests for reports of the sync status come from.
+    pub status_reports: SyncStatusReports,
+    /// Where requests to fetch the justifications of finalized blocks from the peers come from.
+    pub justification_fetches: SyncJustificationFetches,
+    /// Where requests to backfill missing block bodies come from.
+    pub body_backfill: BodyBackfill,
+    /// Where the blocks imported with bodies from peers are announced.
+    pub import_notifications: SyncImportNotifications,
+    /// The hooks called after every block finalized by sync.
+    pub finalization_hooks: SyncFinalizationHooks,
+    /// Which branch sync extends and advertises, and which forks of peers it requests.
+    pub chain_selection: SyncChainSelection,
+    /// Where snapshots of the database are triggered every few finalized sessions.
+    pub snapshot_triggers: SyncSnapshotTriggers,
+    /// Where the long-term statistics of sync are counted, persisted across restarts.
+    pub counters: SyncCounters,
+    /// Where the states advertised by peers are aggregated into a view of the network.
+    pub network_view: SyncNetworkView,
+    /// Export the snapshot points to this directory for external tooling, if provided.
+    pub snapshot_export_path: Option<PathBuf>,
+    /// After this block is finalized the legacy version of the sync protocol is neither sent nor
+    /// accepted. Has to be the same for the whole network, so it comes from the chain spec.
+    pub legacy_cutoff: Option<BlockNumber>,
+    /// The peers on forks that only ever understand the legacy version of the sync protocol, with
+    /// the newer features translated for them.
+    pub v1_shim: SyncV1Shim,
+    /// Where to capture the sync traffic, if anywhere.
+    pub capture: Option<SyncCaptureConfig>,
+    /// The secret phrase of the key proving this node belongs to the infrastructure of a
+    /// validator, which then serves it with priority.
+    pub priority_key_phrase: Option<String>,
+    /// The keys of the followers belonging to our infrastructure, served with priority.
+    pub priority_followers: HashSet<AuthorityId>,
+    /// Whether to fetch the chains of headers and justifications before the bodies when far
+    /// behind.
+    pub headers_first: bool,
+    /// Whether to verify the chain of justifications ending the sessions first when sessions
+    /// behind.
+    pub warp_sync: bool,
+    /// Whether to download the gap below the network from several peers at once when far behind.
+    pub parallel_download: bool,
+    /// Whether to fetch and verify the next session ahead while importing the current one when
+    /// sessions behind.
+    pub session_pipeline: bool,
+    /// The incoming sync requests, if requests and responses are exchanged through the dedicated
+    /// request-response protocol rather than notifications.
+    pub requests: Option<BlockSyncRequests>,
+    /// How many justified blocks are finalized in a single database transaction at most.
+    pub finalization_batch: BlockNumber,
+    /// Whether justified blocks are only finalized virtually, in memory, leaving the database as
+    /// it was.
+    pub dry_run: bool,
+    /// Whether only the headers and justifications are followed, without ever downloading the
+    /// bodies, with the finalized headers kept in memory.
+    pub light_sync: bool,
+    /// Which chain of custody emergency justifications have to have.
+    pub emergency_custody: EmergencyCustodyPolicy,
+    /// Where the accepted emergency justifications are recorded.
+    pub emergency_audit: EmergencyAudit,
+    /// Which justifications below a trusted checkpoint are verified, all if not provided.
+    pub verification_sampling: Option<VerificationSampling>,
+    /// Which justifications of finalized blocks are kept, the others are pruned in the background.
+    pub justification_retention: JustificationRetention,
+    /// Whether the justifications that can be pruned are compressed before archiving.
+    pub compress_justifications: bool,
+    /// Where the missing bodies of finalized blocks are fetched from instead of peers, if anywhere.
+    pub block_source: Option<SyncBlockSource>,
 }
 
+/// The provenance of the blocks recently finalized by sync.
+pub type SyncProvenance = ProvenanceHistory<PeerId, BlockId>;
+
+/// The switch for detailed tracing of the sync messages of a single peer.
+pub type SyncPeerTracing = PeerTracing<PeerId>;
+
+/// The handle for requesting dumps of the sync forest.
+pub type SyncForestDumps = ForestDumps<PeerId, BlockId>;
+
+/// The handle for requesting reports of the sync status.
+pub type SyncStatusReports = StatusReports<PeerId, BlockId>;
+
+/// The handle for fetching the justifications of finalized blocks from the peers.
+pub type SyncJustificationFetches = JustificationFetches<BlockId, Justification>;
+
+/// The peers only ever getting the legacy version of the sync protocol.
+pub type SyncV1Shim = V1Shim<PeerId>;
+
+/// The announcements of blocks imported with bodies supplied by peers through sync.
+pub type SyncImportNotifications = ImportNotifications<PeerId, BlockId>;
+
+/// The hooks with side effects of the blocks finalized by sync.
+pub type SyncFinalizationHooks = FinalizationHooks<Justification>;
+
+/// The fork choice of sync, replaceable by chains building on this crate.
+pub type SyncChainSelection = Arc<dyn SyncChainSelectionStrategy<aleph_primitives::Header>>;
+
+/// The view of the finality of the network, aggregated from the states advertised by peers.
+pub type SyncNetworkView = NetworkFinalityView<PeerId>;
+
+/// The triggers of database snapshots at finality consistent points.
+pub type SyncSnapshotTriggers = SnapshotTriggers<BlockId>;
+
+/// A subscription to the triggers of database snapshots.
+pub type SyncSnapshotSubscription = SnapshotSubscription<BlockId>;
+
+/// The sync data exactly as it is sent over the network, for decoding sync captures.
+pub type SyncNetworkData = sync::VersionedNetworkData<AlephBlock, Justification>;
+
+/// How a replay of captured sync traffic went.
+pub type SyncReplayReport = ReplayReport<BlockId>;
+
+/// The connectivity of the validators we should be directly connected to.
+pub type ValidatorNetworkHealth = ConnectionHealth<AuthorityIdWrapper>;
+
+/// The connectivity of a single validator.
+pub type ValidatorPeerHealth = PeerHealth<AuthorityIdWrapper>;
+
+/// The most recent diagnostics of finality stalls.
+pub type FinalityStallDiagnostics = StallDiagnostics<PeerId, BlockId>;
+
+/// A diagnostic snapshot taken when finality stalled.
+pub type FinalityStallDiagnostic = StallDiagnostic<PeerId, BlockId>;
+
 pub struct AlephConfig<C, SC> {
     pub network: Arc<NetworkService<AlephBlock, AlephHash>>,
     pub sync_network: Arc<SyncingService<AlephBlock>>,
@@ -283,12 +481,29 @@ pub struct AlephConfig<C, SC> {
     pub justification_rx: mpsc::UnboundedReceiver<Justification>,
     pub metrics: BlockMetrics,
     pub registry: Option<Registry>,
+    /// The session length used unless the runtime reports its changes.
     pub session_period: SessionPeriod,
     pub millisecs_per_block: MillisecsPerBlock,
     pub unit_creation_delay: UnitCreationDelay,
     pub backup_saving_path: Option<PathBuf>,
     pub external_addresses: Vec<String>,
     pub validator_port: u16,
+    pub validator_listen_address: IpAddr,
+    pub validator_outgoing_address: Option<IpAddr>,
     pub protocol_naming: ProtocolNaming,
-    pub rate_limiter_config: RateLimiterConfig,
+    pub network_limits: NetworkLimits,
+    pub sync_config: SyncConfig,
+    /// Where the announcements of the finalized blocks are pushed, if anywhere.
+    pub head_push: Option<HeadPushEndpoint>,
+    /// The most bytes of disk the captures, backups and reports written by us can take, if
+    /// limited at all.
+    pub disk_quota: Option<u64>,
+    /// Where the connectivity of the validator network is recorded.
+    pub validator_network_health: ValidatorNetworkHealth,
+    /// After how long without finalizing a block a diagnostic snapshot is taken, if at all.
+    pub finality_stall_timeout: Option<Duration>,
+    /// Where the diagnostic snapshots of finality stalls are kept.
+    pub stall_diagnostics: FinalityStallDiagnostics,
+    /// Where the status page is served, if anywhere.
+    pub status_page: Option<SocketAddr>,
 }
@@ -10,7 +10,9 @@ use log::{trace, warn};
 use lru::LruCache;
 use parking_lot::Mutex;
 use sc_service::Arc;
-use substrate_prometheus_endpoint::{register, Gauge, PrometheusError, Registry, U64};
+use substrate_prometheus_endpoint::{
+    exponential_buckets, register, Gauge, Histogram, HistogramOpts, PrometheusError, Registry, U64,
+};
 
 use crate::aleph_primitives::BlockHash;
 
@@ -25,6 +27,9 @@ const LOG_TARGET: &str = "aleph-metrics";
 struct Inner {
     prev: HashMap<Checkpoint, Checkpoint>,
     gauges: HashMap<Checkpoint, Gauge<U64>>,
+    // Distributions of the times between the first checkpoint and the second one, the checkpoints
+    // do not have to be consecutive.
+    latencies: Vec<(Checkpoint, Checkpoint, Histogram)>,
     starts: HashMap<Checkpoint, LruCache<BlockHash, Instant>>,
 }
 
@@ -53,9 +58,38 @@ impl Inner {
             );
         }
 
+        let mut latencies = Vec::new();
+        for (from, to, name, help) in [
+            (
+                Imported,
+                Ordering,
+                "aleph_import_to_unit_creation",
+                "seconds between importing a block and proposing it in a unit",
+            ),
+            (
+                Ordering,
+                Ordered,
+                "aleph_unit_creation_to_ordering",
+                "seconds between proposing a block in a unit and ordering it",
+            ),
+            (
+                Ordered,
+                Finalized,
+                "aleph_ordering_to_finalization",
+                "seconds between ordering a block and finalizing it",
+            ),
+        ] {
+            let histogram = Histogram::with_opts(
+                // From 10ms to over five minutes.
+                HistogramOpts::new(name, help).buckets(exponential_buckets(0.01, 2.0, 16)?),
+            )?;
+            latencies.push((from, to, register(histogram, registry)?));
+        }
+
         Ok(Self {
             prev,
             gauges,
+            latencies,
             starts: keys
                 .iter()
                 .map(|k| {
@@ -114,6 +148,23 @@ impl Inner {
                     .set(duration.as_millis() as u64);
             }
         }
+
+        for (from, _, histogram) in self
+            .latencies
+            .iter()
+            .filter(|(_, to, _)| *to == checkpoint_type)
+        {
+            if let Some(start) = self
+                .starts
+                .get_mut(from)
+                .expect("All checkpoint types were initialized")
+                .get(&hash)
+            {
+                if let Some(latency) = checkpoint_time.checked_duration_since(*start) {
+                    histogram.observe(latency.as_secs_f64());
+                }
+            }
+        }
     }
 }
 
@@ -209,6 +260,33 @@ mod tests {
         check_reporting_with_memory_excess(&m, Checkpoint::Imported);
     }
 
+    #[test]
+    fn observes_latencies_between_checkpoints() {
+        let metrics = register_dummy_metrics();
+        let hash = BlockHash::random();
+        let imported = Instant::now();
+        metrics.report_block(hash, imported, Checkpoint::Imported);
+        metrics.report_block(hash, imported, Checkpoint::Ordering);
+        metrics.report_block(hash, imported, Checkpoint::Ordered);
+        metrics.report_block(hash, imported, Checkpoint::Aggregating);
+        metrics.report_block(
+            hash,
+            imported + Duration::from_secs(2),
+            Checkpoint::Finalized,
+        );
+        let inner = metrics
+            .inner
+            .as_ref()
+            .expect("There are some metrics")
+            .lock();
+        for (from, _, histogram) in &inner.latencies {
+            assert_eq!(histogram.get_sample_count(), 1);
+            if *from == Checkpoint::Ordered {
+                assert_eq!(histogram.get_sample_sum(), 2.0);
+            }
+        }
+    }
+
     #[test]
     fn given_not_monotonic_clock_when_report_block_is_called_repeatedly_code_does_not_panic() {
         let metrics = register_dummy_metrics();
@@ -0,0 +1,210 @@
+//! Tokens for giving up on work that nobody waits for anymore, e.g. because the peer it was for
+//! disconnected, before it is finished rather than after, and the events of the connections they
+//! are handed out for.
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use futures::future::pending;
+use log::warn;
+use parking_lot::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+const LOG_TARGET: &str = "aleph-network";
+/// How many connection events can wait for a slow listener before it starts missing them.
+const MAX_PENDING_EVENTS: usize = 1024;
+
+/// Tells whether the work it was handed out with is still wanted. Cheap to clone and to check,
+/// also from other threads, while the work is in progress.
+#[derive(Clone, Debug)]
+pub struct CancelToken {
+    // Our own flag is the last one, the earlier ones belong to the tokens we derive from.
+    flags: Vec<Arc<AtomicBool>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken {
+            flags: vec![Arc::new(AtomicBool::new(false))],
+        }
+    }
+
+    /// A token canceled together with this one, but which can also be canceled on its own.
+    pub fn child(&self) -> Self {
+        let mut flags = self.flags.clone();
+        flags.push(Arc::new(AtomicBool::new(false)));
+        CancelToken { flags }
+    }
+
+    /// Cancels this token and all the ones derived from it.
+    pub fn cancel(&self) {
+        if let Some(flag) = self.flags.last() {
+            flag.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.flags.iter().any(|flag| flag.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A connection to a peer opening or closing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent<P> {
+    Connected(P),
+    Disconnected(P),
+}
+
+/// The connection events of a network, from the moment they were obtained on.
+pub struct ConnectionEvents<P: Clone> {
+    receiver: Option<broadcast::Receiver<ConnectionEvent<P>>>,
+}
+
+impl<P: Clone> ConnectionEvents<P> {
+    /// The events of a network that does not track its connections, there are never any.
+    pub fn untracked() -> Self {
+        ConnectionEvents { receiver: None }
+    }
+
+    /// Waits for the next event. Events missed because of not listening for too long are
+    /// skipped.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancellation safe.
+    pub async fn next(&mut self) -> ConnectionEvent<P> {
+        loop {
+            let receiver = match &mut self.receiver {
+                Some(receiver) => receiver,
+                None => return pending().await,
+            };
+            match receiver.recv().await {
+                Ok(event) => return event,
+                Err(RecvError::Lagged(missed)) => warn!(
+                    target: LOG_TARGET,
+                    "Missed {} connection events, not listening for them fast enough.", missed
+                ),
+                Err(RecvError::Closed) => self.receiver = None,
+            }
+        }
+    }
+}
+
+/// The tokens of the open connections, each canceled once its connection closes.
+#[derive(Clone)]
+pub struct ConnectionTokens<P: Clone + Debug + Eq + Hash> {
+    tokens: Arc<Mutex<HashMap<P, CancelToken>>>,
+    events: broadcast::Sender<ConnectionEvent<P>>,
+}
+
+impl<P: Clone + Debug + Eq + Hash> ConnectionTokens<P> {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(MAX_PENDING_EVENTS);
+        ConnectionTokens {
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+            events,
+        }
+    }
+
+    pub fn opened(&self, peer: P) {
+        if let Some(token) = self.tokens.lock().insert(peer.clone(), CancelToken::new()) {
+            token.cancel();
+        }
+        // Nobody might be listening, which is fine.
+        let _ = self.events.send(ConnectionEvent::Connected(peer));
+    }
+
+    pub fn closed(&self, peer: &P) {
+        if let Some(token) = self.tokens.lock().remove(peer) {
+            token.cancel();
+            let _ = self
+                .events
+                .send(ConnectionEvent::Disconnected(peer.clone()));
+        }
+    }
+
+    /// The events of the connections opening and closing from now on.
+    pub fn events(&self) -> ConnectionEvents<P> {
+        ConnectionEvents {
+            receiver: Some(self.events.subscribe()),
+        }
+    }
+
+    /// The token of the connection to the peer. Peers not known to be connected, e.g. because
+    /// they only ever reached us through another protocol, get one that is never canceled.
+    pub fn token(&self, peer: &P) -> CancelToken {
+        self.tokens.lock().get(peer).cloned().unwrap_or_default()
+    }
+}
+
+impl<P: Clone + Debug + Eq + Hash> Default for ConnectionTokens<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CancelToken, ConnectionEvent::*, ConnectionEvents, ConnectionTokens};
+
+    #[test]
+    fn children_are_canceled_with_parents() {
+        let parent = CancelToken::new();
+        let child = parent.child();
+        let other_child = parent.child();
+        child.cancel();
+        assert!(child.is_canceled());
+        assert!(!other_child.is_canceled());
+        assert!(!parent.is_canceled());
+        parent.cancel();
+        assert!(other_child.is_canceled());
+    }
+
+    #[test]
+    fn closing_connections_cancels_their_tokens() {
+        let connections = ConnectionTokens::new();
+        connections.opened(1);
+        let token = connections.token(&1);
+        let work = token.child();
+        connections.opened(2);
+        connections.closed(&2);
+        assert!(!work.is_canceled());
+        connections.closed(&1);
+        assert!(token.is_canceled());
+        assert!(work.is_canceled());
+        // Reconnecting does not bring the old work back, and unknown peers are never canceled.
+        connections.opened(1);
+        assert!(work.is_canceled());
+        assert!(!connections.token(&1).is_canceled());
+        assert!(!connections.token(&3).is_canceled());
+    }
+
+    #[tokio::test]
+    async fn reports_connection_events() {
+        let connections = ConnectionTokens::new();
+        connections.opened(1);
+        let mut events = connections.events();
+        connections.opened(2);
+        connections.closed(&1);
+        // Closing a connection that is not open is not an event.
+        connections.closed(&3);
+        connections.closed(&2);
+        assert_eq!(events.next().await, Connected(2));
+        assert_eq!(events.next().await, Disconnected(1));
+        assert_eq!(events.next().await, Disconnected(2));
+        let mut untracked = ConnectionEvents::<u32>::untracked();
+        assert!(futures::FutureExt::now_or_never(untracked.next()).is_none());
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Error as FmtError, Formatter},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// How many of the first bytes of an undecodable message are kept for diagnostics.
+pub const MAX_RECORDED_BYTES: usize = 32;
+/// The size of the envelope of versioned messages, a two byte version followed by a four byte
+/// length of the payload.
+const ENVELOPE_SIZE: usize = 6;
+
+/// Why a peer most likely never sent us a message we could decode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LikelyCause {
+    /// Nothing arrived at all.
+    Silent,
+    /// The messages are versioned like ours, but their contents do not decode, as if they were
+    /// about the blocks of a different chain.
+    DifferentChain(u16),
+    /// The messages are not even versioned like ours, as if sent by an old version of the node.
+    OldVersion,
+}
+
+impl LikelyCause {
+    fn of(first_bytes: &[u8], length: usize) -> Self {
+        if first_bytes.len() < ENVELOPE_SIZE {
+            return LikelyCause::OldVersion;
+        }
+        let version = u16::from_le_bytes([first_bytes[0], first_bytes[1]]);
+        let declared = u32::from_le_bytes([
+            first_bytes[2],
+            first_bytes[3],
+            first_bytes[4],
+            first_bytes[5],
+        ]);
+        match declared as usize == length - ENVELOPE_SIZE {
+            true => LikelyCause::DifferentChain(version),
+            false => LikelyCause::OldVersion,
+        }
+    }
+}
+
+impl Display for LikelyCause {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use LikelyCause::*;
+        match self {
+            Silent => write!(f, "the peer sent nothing"),
+            DifferentChain(version) => write!(
+                f,
+                "the messages of version {version} do not decode, likely a different chain"
+            ),
+            OldVersion => write!(
+                f,
+                "the messages are not versioned like ours, likely an old version of the node"
+            ),
+        }
+    }
+}
+
+/// What we know about a peer that never sent a decodable message in time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub waited: Duration,
+    /// How many undecodable messages the peer sent.
+    pub undecodable: usize,
+    /// The first bytes of the first undecodable message, if any.
+    pub first_bytes: Vec<u8>,
+    pub cause: LikelyCause,
+}
+
+impl Display for Diagnosis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(
+            f,
+            "no decodable message in {}ms, {} undecodable ones",
+            self.waited.as_millis(),
+            self.undecodable
+        )?;
+        if !self.first_bytes.is_empty() {
+            write!(f, " starting with 0x")?;
+            for byte in &self.first_bytes {
+                write!(f, "{byte:02x}")?;
+            }
+        }
+        write!(f, ", {}", self.cause)
+    }
+}
+
+struct Pending {
+    opened: Instant,
+    undecodable: usize,
+    first_bytes: Vec<u8>,
+    cause: LikelyCause,
+}
+
+/// The peers that opened a stream, but did not send a decodable message yet, so that the ones
+/// that never do can be told apart and disconnected, instead of sitting among the peers silently.
+pub struct PendingHandshakes<P: Clone + Eq + Hash> {
+    timeout: Duration,
+    pending: HashMap<P, Pending>,
+}
+
+impl<P: Clone + Eq + Hash> PendingHandshakes<P> {
+    pub fn new(timeout: Duration) -> Self {
+        PendingHandshakes {
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// The peer opened a stream, it has until the timeout to send a decodable message.
+    pub fn opened(&mut self, peer: P, now: Instant) {
+        self.pending.insert(
+            peer,
+            Pending {
+                opened: now,
+                undecodable: 0,
+                first_bytes: Vec::new(),
+                cause: LikelyCause::Silent,
+            },
+        );
+    }
+
+    /// The peer sent a decodable message, so it speaks our protocol.
+    pub fn decoded(&mut self, peer: &P) {
+        self.pending.remove(peer);
+    }
+
+    /// The peer sent a message we could not decode, its first one is recorded.
+    pub fn undecodable(&mut self, peer: &P, data: &[u8]) {
+        if let Some(pending) = self.pending.get_mut(peer) {
+            if pending.undecodable == 0 {
+                pending.first_bytes = data.iter().take(MAX_RECORDED_BYTES).cloned().collect();
+                pending.cause = LikelyCause::of(&pending.first_bytes, data.len());
+            }
+            pending.undecodable += 1;
+        }
+    }
+
+    pub fn closed(&mut self, peer: &P) {
+        self.pending.remove(peer);
+    }
+
+    /// Forgets the peers that did not send a decodable message in time, returns what is known
+    /// about them.
+    pub fn timed_out(&mut self, now: Instant) -> Vec<(P, Diagnosis)> {
+        let timed_out: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.saturating_duration_since(pending.opened) >= self.timeout)
+            .map(|(peer, _)| peer.clone())
+            .collect();
+        timed_out
+            .into_iter()
+            .filter_map(|peer| {
+                let pending = self.pending.remove(&peer)?;
+                Some((
+                    peer,
+                    Diagnosis {
+                        waited: now.saturating_duration_since(pending.opened),
+                        undecodable: pending.undecodable,
+                        first_bytes: pending.first_bytes,
+                        cause: pending.cause,
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{LikelyCause, PendingHandshakes, MAX_RECORDED_BYTES};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn times_out_only_silent_peers() {
+        let mut handshakes = PendingHandshakes::new(TIMEOUT);
+        let now = Instant::now();
+        handshakes.opened(1, now);
+        handshakes.opened(2, now);
+        handshakes.opened(3, now);
+        handshakes.decoded(&2);
+        handshakes.closed(&3);
+        assert!(handshakes.timed_out(now + TIMEOUT / 2).is_empty());
+        let timed_out = handshakes.timed_out(now + TIMEOUT);
+        assert_eq!(timed_out.len(), 1);
+        let (peer, diagnosis) = &timed_out[0];
+        assert_eq!(*peer, 1);
+        assert_eq!(diagnosis.cause, LikelyCause::Silent);
+        assert_eq!(diagnosis.undecodable, 0);
+        assert!(handshakes.timed_out(now + TIMEOUT * 2).is_empty());
+    }
+
+    #[test]
+    fn diagnoses_undecodable_messages() {
+        let mut handshakes = PendingHandshakes::new(TIMEOUT);
+        let now = Instant::now();
+        handshakes.opened(1, now);
+        handshakes.opened(2, now);
+        let mut versioned = vec![3, 0];
+        versioned.extend_from_slice(&100u32.to_le_bytes());
+        versioned.extend_from_slice(&[7; 100]);
+        handshakes.undecodable(&1, &versioned);
+        handshakes.undecodable(&1, &[1, 2, 3]);
+        handshakes.undecodable(&2, &[1, 2, 3]);
+        let mut timed_out = handshakes.timed_out(now + TIMEOUT);
+        timed_out.sort_by_key(|(peer, _)| *peer);
+        let diagnosis = &timed_out[0].1;
+        assert_eq!(diagnosis.cause, LikelyCause::DifferentChain(3));
+        assert_eq!(diagnosis.undecodable, 2);
+        assert_eq!(diagnosis.first_bytes, versioned[..MAX_RECORDED_BYTES]);
+        assert_eq!(timed_out[1].1.cause, LikelyCause::OldVersion);
+    }
+}
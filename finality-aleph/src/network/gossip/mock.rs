@@ -9,7 +9,7 @@ use network_clique::mock::MockPublicKey;
 use parking_lot::Mutex;
 
 use crate::network::{
-    gossip::{Event, EventStream, NetworkSender, Protocol, RawNetwork},
+    gossip::{Event, EventStream, NetworkSender, Penalty, Protocol, RawNetwork},
     mock::Channel,
 };
 
@@ -54,6 +54,7 @@ pub struct MockRawNetwork {
     event_stream_taken_oneshot: Arc<Mutex<Option<oneshot::Sender<()>>>>,
     pub create_sender_errors: Arc<Mutex<VecDeque<MockSenderError>>>,
     pub send_errors: Arc<Mutex<VecDeque<MockSenderError>>>,
+    pub penalties: Arc<Mutex<Vec<(MockPublicKey, Protocol, Penalty)>>>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -100,6 +101,10 @@ impl RawNetwork for MockRawNetwork {
             error,
         })
     }
+
+    fn penalize(&self, peer_id: Self::PeerId, protocol: Protocol, penalty: Penalty) {
+        self.penalties.lock().push((peer_id, protocol, penalty));
+    }
 }
 
 impl MockRawNetwork {
@@ -110,6 +115,7 @@ impl MockRawNetwork {
             event_stream_taken_oneshot: Arc::new(Mutex::new(Some(oneshot_sender))),
             create_sender_errors: Arc::new(Mutex::new(VecDeque::new())),
             send_errors: Arc::new(Mutex::new(VecDeque::new())),
+            penalties: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
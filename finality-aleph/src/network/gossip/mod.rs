@@ -6,12 +6,15 @@ use std::{
 };
 
 use bytes::Bytes;
+use futures::future::BoxFuture;
 
-use crate::network::Data;
+use crate::network::{CancelToken, ConnectionEvents, Data};
 
+mod handshake;
 mod metrics;
 #[cfg(test)]
 pub mod mock;
+mod parked;
 mod service;
 
 pub use service::{Error, Service};
@@ -43,11 +46,77 @@ pub trait Network<D: Data>: Send + 'static {
     /// returned, retry appropriately.
     fn broadcast(&mut self, data: D) -> Result<(), Self::Error>;
 
+    /// Send a request to a peer. Networks with a dedicated request-response protocol might pair
+    /// it with the response, by default it is sent like any other data.
+    fn send_request(&mut self, data: D, peer_id: Self::PeerId) -> Result<(), Self::Error> {
+        self.send_to(data, peer_id)
+    }
+
+    /// Send a request to a random peer, preferably from a list, with the same guarantees as
+    /// `send_to_random`. By default it is sent like any other data.
+    fn send_request_to_random(
+        &mut self,
+        data: D,
+        peer_ids: HashSet<Self::PeerId>,
+    ) -> Result<(), Self::Error> {
+        self.send_to_random(data, peer_ids)
+    }
+
+    /// Send a response to the oldest unanswered request of the peer. By default it is sent like
+    /// any other data.
+    fn send_response(&mut self, data: D, peer_id: Self::PeerId) -> Result<(), Self::Error> {
+        self.send_to(data, peer_id)
+    }
+
+    /// Penalize a misbehaving peer. Might silently fail if we are not connected to them.
+    fn penalize(&mut self, peer_id: Self::PeerId, penalty: Penalty) -> Result<(), Self::Error>;
+
+    /// A token canceled once the current connection to the peer closes, for giving up on work
+    /// for the peer. By default the connections are not tracked and it is never canceled.
+    fn connection(&self, _peer_id: &Self::PeerId) -> CancelToken {
+        CancelToken::new()
+    }
+
+    /// The events of the connections to peers opening and closing from now on. By default the
+    /// connections are not tracked and there are never any.
+    fn connection_events(&self) -> ConnectionEvents<Self::PeerId> {
+        ConnectionEvents::untracked()
+    }
+
     /// Receive some data from the network, including information about who sent it.
     /// This method's implementation must be cancellation safe.
     async fn next(&mut self) -> Result<(D, Self::PeerId), Self::Error>;
 }
 
+/// Identifies a request among the ones sent to a peer, so that the response can be matched with
+/// it.
+pub type RequestId = u64;
+
+/// The response to a request, `None` if it did not arrive in time.
+pub type PendingResponse<D> = BoxFuture<'static, Option<D>>;
+
+/// Interface for gossip networks able to match responses with the requests they answer, instead
+/// of delivering them through `next` like any other data.
+pub trait RequestNetwork<D: Data>: Network<D> {
+    /// Send a request to a peer, returning its response. Returns `None` if the request cannot be
+    /// matched with its response, e.g. because the peer does not understand that, in which case
+    /// it is sent like with `send_request` and the response arrives through `next` as usual.
+    fn request(
+        &mut self,
+        data: D,
+        peer_id: Self::PeerId,
+    ) -> Result<Option<PendingResponse<D>>, Self::Error>;
+}
+
+/// What to do with a misbehaving peer.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum Penalty {
+    /// Close the connection, the peer can connect again.
+    Disconnect,
+    /// Close the connection and refuse the peer for a while.
+    Ban,
+}
+
 /// Protocols used by the network.
 #[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
 pub enum Protocol {
@@ -99,4 +168,7 @@ pub trait RawNetwork: Clone + Send + Sync + 'static {
         peer_id: Self::PeerId,
         protocol: Protocol,
     ) -> Result<Self::NetworkSender, Self::SenderError>;
+
+    /// Penalizes the peer for misbehaving in the given protocol.
+    fn penalize(&self, peer_id: Self::PeerId, protocol: Protocol, penalty: Penalty);
 }
@@ -0,0 +1,167 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// For how long after a disconnect messages for the peer are kept, and for how long a single
+/// message is kept at most.
+const PARKING_TTL: Duration = Duration::from_secs(10);
+/// How many messages are kept for a single peer, the oldest ones are dropped first.
+const MAX_PARKED_MESSAGES: usize = 100;
+/// How many disconnected peers messages are kept for, the ones that disconnected longest ago are
+/// forgotten first.
+const MAX_PARKED_PEERS: usize = 64;
+
+struct ParkedPeer<D> {
+    disconnected: Instant,
+    messages: VecDeque<(D, Instant)>,
+}
+
+impl<D> ParkedPeer<D> {
+    fn prune(&mut self, now: Instant) {
+        while let Some((_, parked)) = self.messages.front() {
+            if now.saturating_duration_since(*parked) < PARKING_TTL {
+                break;
+            }
+            self.messages.pop_front();
+        }
+    }
+}
+
+/// Messages for peers that disconnected only moments ago, kept so that they can be delivered if
+/// the peer reconnects quickly, instead of the peer having to request the data again.
+pub struct ParkedMessages<P: Clone + Eq + Hash, D> {
+    peers: HashMap<P, ParkedPeer<D>>,
+}
+
+impl<P: Clone + Eq + Hash, D> ParkedMessages<P, D> {
+    pub fn new() -> Self {
+        ParkedMessages {
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Starts keeping messages for the peer, which just disconnected.
+    pub fn disconnected(&mut self, peer: P, now: Instant) {
+        if !self.peers.contains_key(&peer) && self.peers.len() >= MAX_PARKED_PEERS {
+            let longest_gone = self
+                .peers
+                .iter()
+                .min_by_key(|(_, parked)| parked.disconnected)
+                .map(|(peer, _)| peer.clone());
+            if let Some(longest_gone) = longest_gone {
+                self.peers.remove(&longest_gone);
+            }
+        }
+        self.peers
+            .entry(peer)
+            .or_insert_with(|| ParkedPeer {
+                disconnected: now,
+                messages: VecDeque::new(),
+            })
+            .disconnected = now;
+    }
+
+    /// Keeps the message for the peer, if it disconnected recently enough. Returns whether the
+    /// message was kept.
+    pub fn park(&mut self, peer: &P, data: D, now: Instant) -> bool {
+        let parked = match self.peers.get_mut(peer) {
+            Some(parked) if now.saturating_duration_since(parked.disconnected) < PARKING_TTL => {
+                parked
+            }
+            _ => return false,
+        };
+        if parked.messages.len() >= MAX_PARKED_MESSAGES {
+            parked.messages.pop_front();
+        }
+        parked.messages.push_back((data, now));
+        true
+    }
+
+    /// The messages that are still fresh for the peer, which just reconnected, oldest first.
+    pub fn reconnected(&mut self, peer: &P, now: Instant) -> Vec<D> {
+        match self.peers.remove(peer) {
+            Some(mut parked) => {
+                parked.prune(now);
+                parked.messages.into_iter().map(|(data, _)| data).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Forgets the peers that did not reconnect in time and the messages that got too old.
+    pub fn prune(&mut self, now: Instant) {
+        self.peers.retain(|_, parked| {
+            parked.prune(now);
+            now.saturating_duration_since(parked.disconnected) < PARKING_TTL
+        });
+    }
+
+    /// How many messages are kept in total.
+    pub fn message_count(&self) -> usize {
+        self.peers
+            .values()
+            .map(|parked| parked.messages.len())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{ParkedMessages, MAX_PARKED_MESSAGES, MAX_PARKED_PEERS, PARKING_TTL};
+
+    #[test]
+    fn delivers_messages_on_quick_reconnect() {
+        let mut parked = ParkedMessages::new();
+        let now = Instant::now();
+        assert!(!parked.park(&1, 0, now));
+        parked.disconnected(1, now);
+        assert!(parked.park(&1, 1, now));
+        assert!(parked.park(&1, 2, now + Duration::from_secs(1)));
+        assert_eq!(parked.message_count(), 2);
+        assert_eq!(
+            parked.reconnected(&1, now + Duration::from_secs(2)),
+            vec![1, 2]
+        );
+        assert!(parked.reconnected(&1, now).is_empty());
+        assert!(!parked.park(&1, 3, now));
+    }
+
+    #[test]
+    fn drops_stale_messages_and_peers() {
+        let mut parked = ParkedMessages::new();
+        let now = Instant::now();
+        parked.disconnected(1, now);
+        parked.disconnected(2, now);
+        assert!(parked.park(&1, 1, now));
+        assert!(parked.park(&2, 2, now));
+        assert!(!parked.park(&1, 3, now + PARKING_TTL));
+        parked.disconnected(2, now + PARKING_TTL);
+        assert!(parked.park(&2, 4, now + PARKING_TTL));
+        parked.prune(now + PARKING_TTL);
+        assert_eq!(parked.message_count(), 1);
+        assert!(parked.reconnected(&1, now + PARKING_TTL).is_empty());
+        assert_eq!(parked.reconnected(&2, now + PARKING_TTL), vec![4]);
+    }
+
+    #[test]
+    fn bounds_kept_messages() {
+        let mut parked = ParkedMessages::new();
+        let now = Instant::now();
+        for peer in 0..=MAX_PARKED_PEERS {
+            parked.disconnected(peer, now + Duration::from_millis(peer as u64));
+            assert!(parked.park(&peer, peer, now));
+        }
+        assert_eq!(parked.message_count(), MAX_PARKED_PEERS);
+        assert!(!parked.park(&0, 0, now));
+        for message in 0..=MAX_PARKED_MESSAGES {
+            assert!(parked.park(&1, message, now));
+        }
+        let messages = parked.reconnected(&1, now);
+        assert_eq!(messages.len(), MAX_PARKED_MESSAGES);
+        assert_eq!(messages.last(), Some(&MAX_PARKED_MESSAGES));
+    }
+}
@@ -3,6 +3,9 @@ use std::{
     fmt::{Debug, Display, Error as FmtError, Formatter},
     future::Future,
     hash::Hash,
+    iter,
+    path::PathBuf,
+    time::{Duration, Instant},
 };
 
 use futures::{channel::mpsc, StreamExt};
@@ -13,14 +16,20 @@ use substrate_prometheus_endpoint::Registry;
 use tokio::time;
 
 const MAX_QUEUE_SIZE: usize = 1_000;
+/// How often the peers are checked for not sending a decodable message in time.
+const HANDSHAKE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 use crate::{
     network::{
+        cancellation::ConnectionTokens,
         gossip::{
-            metrics::Metrics, Event, EventStream, Network, NetworkSender, Protocol, RawNetwork,
+            handshake::PendingHandshakes, metrics::Metrics, parked::ParkedMessages, Event,
+            EventStream, Network, NetworkSender, Penalty, Protocol, RawNetwork,
         },
-        Data,
+        limits::{PeerRateLimits, PublicNetworkLimits},
+        CancelToken, ConnectionEvents, Data,
     },
+    shutdown_report::ShutdownRecorder,
     SpawnHandle, STATUS_REPORT_INTERVAL,
 };
 
@@ -30,6 +39,7 @@ enum Command<D: Data, P: Clone + Debug + Eq + Hash + Send + 'static> {
     Send(D, P),
     SendToRandom(D, HashSet<P>),
     Broadcast(D),
+    Penalize(P, Penalty),
 }
 
 /// A service managing all the direct interaction with the underlying network implementation. It
@@ -47,14 +57,21 @@ pub struct Service<N: RawNetwork, AD: Data, BSD: Data> {
     authentication_connected_peers: HashSet<N::PeerId>,
     authentication_peer_senders: HashMap<N::PeerId, mpsc::Sender<AD>>,
     block_sync_connected_peers: HashSet<N::PeerId>,
+    block_sync_connections: ConnectionTokens<N::PeerId>,
     block_sync_peer_senders: HashMap<N::PeerId, mpsc::Sender<BSD>>,
+    block_sync_parked: ParkedMessages<N::PeerId, BSD>,
+    block_sync_handshakes: PendingHandshakes<N::PeerId>,
+    peer_rate_limits: PeerRateLimits<N::PeerId>,
     spawn_handle: SpawnHandle,
     metrics: Metrics,
+    shutdown_recorder: ShutdownRecorder,
+    backup_path: Option<PathBuf>,
 }
 
 struct ServiceInterface<D: Data, P: Clone + Debug + Eq + Hash + Send + 'static> {
     messages_from_service: mpsc::UnboundedReceiver<(D, P)>,
     messages_for_service: mpsc::UnboundedSender<Command<D, P>>,
+    connections: ConnectionTokens<P>,
 }
 
 /// What can go wrong when receiving or sending data.
@@ -101,6 +118,20 @@ impl<D: Data, P: Clone + Debug + Eq + Hash + Send + 'static> Network<D> for Serv
             .map_err(|_| Error::ServiceStopped)
     }
 
+    fn penalize(&mut self, peer_id: Self::PeerId, penalty: Penalty) -> Result<(), Self::Error> {
+        self.messages_for_service
+            .unbounded_send(Command::Penalize(peer_id, penalty))
+            .map_err(|_| Error::ServiceStopped)
+    }
+
+    fn connection(&self, peer_id: &Self::PeerId) -> CancelToken {
+        self.connections.token(peer_id)
+    }
+
+    fn connection_events(&self) -> ConnectionEvents<Self::PeerId> {
+        self.connections.events()
+    }
+
     async fn next(&mut self) -> Result<(D, Self::PeerId), Self::Error> {
         self.messages_from_service
             .next()
@@ -116,10 +147,15 @@ enum SendError {
 }
 
 impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
+    /// Create a new service. When it exits, a shutdown report is saved under the backup path, if
+    /// provided. Messages from peers exceeding the public bit-rate limit are dropped, and peers
+    /// not sending a decodable block sync message within the handshake timeout are disconnected.
     pub fn new(
         network: N,
         spawn_handle: SpawnHandle,
         metrics_registry: Option<Registry>,
+        backup_path: Option<PathBuf>,
+        limits: PublicNetworkLimits,
     ) -> (
         Self,
         impl Network<AD, Error = Error, PeerId = N::PeerId>,
@@ -138,6 +174,8 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
                 Metrics::noop()
             }
         };
+        // Only the connections of block sync are tracked, nothing cancels authentication work.
+        let block_sync_connections = ConnectionTokens::new();
         (
             Service {
                 network,
@@ -147,18 +185,26 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
                 messages_for_block_sync_user,
                 spawn_handle,
                 metrics,
+                shutdown_recorder: ShutdownRecorder::new("gossip_network"),
+                backup_path,
                 authentication_connected_peers: HashSet::new(),
                 authentication_peer_senders: HashMap::new(),
                 block_sync_connected_peers: HashSet::new(),
+                block_sync_connections: block_sync_connections.clone(),
                 block_sync_peer_senders: HashMap::new(),
+                block_sync_parked: ParkedMessages::new(),
+                block_sync_handshakes: PendingHandshakes::new(limits.handshake_timeout),
+                peer_rate_limits: PeerRateLimits::new(limits.bit_rate_per_peer),
             },
             ServiceInterface {
                 messages_from_service: messages_from_authentication_service,
                 messages_for_service: messages_for_authentication_service,
+                connections: ConnectionTokens::new(),
             },
             ServiceInterface {
                 messages_from_service: messages_from_block_sync_service,
                 messages_for_service: messages_for_block_sync_service,
+                connections: block_sync_connections,
             },
         )
     }
@@ -273,6 +319,10 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
 
     fn send_authentication_data(&mut self, data: AD, peer_id: N::PeerId) {
         if let Err(e) = self.send_to_authentication_peer(data, peer_id.clone()) {
+            self.shutdown_recorder.record_error(
+                "authentication_send",
+                format!("failed to send to peer {peer_id:?}: {e:?}"),
+            );
             trace!(
                 target: LOG_TARGET,
                 "Failed to send to peer{:?}, {:?}",
@@ -283,7 +333,24 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
     }
 
     fn send_block_sync_data(&mut self, data: BSD, peer_id: N::PeerId) {
+        if !self.block_sync_connected_peers.contains(&peer_id)
+            && self
+                .block_sync_parked
+                .park(&peer_id, data.clone(), Instant::now())
+        {
+            self.shutdown_recorder.count("block_sync_parked");
+            trace!(
+                target: LOG_TARGET,
+                "Keeping message for recently disconnected peer {:?}.",
+                peer_id
+            );
+            return;
+        }
         if let Err(e) = self.send_to_block_sync_peer(data, peer_id.clone()) {
+            self.shutdown_recorder.record_error(
+                "block_sync_send",
+                format!("failed to send to peer {peer_id:?}: {e:?}"),
+            );
             trace!(
                 target: LOG_TARGET,
                 "Failed to send to peer{:?}, {:?}",
@@ -357,6 +424,15 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
         }
     }
 
+    fn penalize(&mut self, peer_id: N::PeerId, protocol: Protocol, penalty: Penalty) {
+        self.shutdown_recorder.count("penalties");
+        debug!(
+            target: LOG_TARGET,
+            "Applying {:?} to peer {:?} misbehaving in the {:?} protocol.", penalty, peer_id, protocol
+        );
+        self.network.penalize(peer_id, protocol, penalty);
+    }
+
     fn handle_network_event(&mut self, event: Event<N::PeerId>) -> Result<(), ()> {
         use Event::*;
         match event {
@@ -380,11 +456,26 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
                     Protocol::BlockSync => {
                         let (tx, rx) = mpsc::channel(MAX_QUEUE_SIZE);
                         self.block_sync_connected_peers.insert(peer.clone());
+                        self.block_sync_connections.opened(peer.clone());
                         self.block_sync_peer_senders.insert(peer.clone(), tx);
+                        self.block_sync_handshakes
+                            .opened(peer.clone(), Instant::now());
                         self.spawn_handle.spawn(
                             "aleph/network/sync_peer_sender",
-                            self.peer_sender(peer, rx, Protocol::BlockSync),
+                            self.peer_sender(peer.clone(), rx, Protocol::BlockSync),
                         );
+                        let parked = self.block_sync_parked.reconnected(&peer, Instant::now());
+                        if !parked.is_empty() {
+                            debug!(
+                                target: LOG_TARGET,
+                                "Delivering {} messages kept for reconnected peer {:?}.",
+                                parked.len(),
+                                peer
+                            );
+                        }
+                        for data in parked {
+                            self.send_block_sync_data(data, peer.clone());
+                        }
                     }
                 };
             }
@@ -402,12 +493,37 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
                     }
                     Protocol::BlockSync => {
                         self.block_sync_connected_peers.remove(&peer);
+                        self.block_sync_connections.closed(&peer);
                         self.block_sync_peer_senders.remove(&peer);
+                        self.block_sync_handshakes.closed(&peer);
+                        self.block_sync_parked
+                            .disconnected(peer.clone(), Instant::now());
                     }
                 }
+                if !self.authentication_connected_peers.contains(&peer)
+                    && !self.block_sync_connected_peers.contains(&peer)
+                {
+                    self.peer_rate_limits.remove(&peer);
+                }
             }
             Messages(peer_id, messages) => {
                 for (protocol, data) in messages.into_iter() {
+                    if !self
+                        .peer_rate_limits
+                        .allow(peer_id.clone(), data.len(), Instant::now())
+                    {
+                        self.shutdown_recorder.record_error(
+                            "rate_limited",
+                            format!("dropped message from {peer_id:?} above the bit-rate limit"),
+                        );
+                        debug!(
+                            target: LOG_TARGET,
+                            "Dropping {:?} message from {:?} above the bit-rate limit.",
+                            protocol,
+                            peer_id
+                        );
+                        continue;
+                    }
                     match protocol {
                         Protocol::Authentication => match AD::decode(&mut &data[..]) {
                             Ok(data) => self
@@ -415,6 +531,10 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
                                 .unbounded_send((data, peer_id.clone()))
                                 .map_err(|_| ())?,
                             Err(e) => {
+                                self.shutdown_recorder.record_error(
+                                    "authentication_decode",
+                                    format!("error decoding authentication protocol message: {e}"),
+                                );
                                 warn!(
                                     target: LOG_TARGET,
                                     "Error decoding authentication protocol message: {}", e
@@ -422,11 +542,18 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
                             }
                         },
                         Protocol::BlockSync => match BSD::decode(&mut &data[..]) {
-                            Ok(data) => self
-                                .messages_for_block_sync_user
-                                .unbounded_send((data, peer_id.clone()))
-                                .map_err(|_| ())?,
+                            Ok(data) => {
+                                self.block_sync_handshakes.decoded(&peer_id);
+                                self.messages_for_block_sync_user
+                                    .unbounded_send((data, peer_id.clone()))
+                                    .map_err(|_| ())?
+                            }
                             Err(e) => {
+                                self.block_sync_handshakes.undecodable(&peer_id, &data);
+                                self.shutdown_recorder.record_error(
+                                    "block_sync_decode",
+                                    format!("error decoding block sync protocol message: {e}"),
+                                );
                                 warn!(
                                     target: LOG_TARGET,
                                     "Error decoding block sync protocol message: {}", e
@@ -440,6 +567,22 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
         Ok(())
     }
 
+    /// Disconnects the block sync peers that did not send a decodable message in time, with the
+    /// likely reason recorded.
+    fn check_handshakes(&mut self) {
+        for (peer, diagnosis) in self.block_sync_handshakes.timed_out(Instant::now()) {
+            self.shutdown_recorder.record_error(
+                "block_sync_handshake_timeout",
+                format!("disconnected {peer:?}: {diagnosis}"),
+            );
+            warn!(
+                target: LOG_TARGET,
+                "Disconnecting block sync peer {:?}: {}.", peer, diagnosis
+            );
+            self.penalize(peer, Protocol::BlockSync, Penalty::Disconnect);
+        }
+    }
+
     fn status_report(&self) {
         let mut status = String::from("Network status report: ");
 
@@ -451,6 +594,10 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
             "block sync connected peers - {:?}; ",
             self.block_sync_connected_peers.len()
         ));
+        status.push_str(&format!(
+            "block sync messages kept for disconnected peers - {:?}; ",
+            self.block_sync_parked.message_count()
+        ));
 
         info!(target: LOG_TARGET, "{}", status);
     }
@@ -459,49 +606,75 @@ impl<N: RawNetwork, AD: Data, BSD: Data> Service<N, AD, BSD> {
         let mut events_from_network = self.network.event_stream();
 
         let mut status_ticker = time::interval(STATUS_REPORT_INTERVAL);
+        let mut handshake_ticker = time::interval(HANDSHAKE_CHECK_INTERVAL);
         loop {
             tokio::select! {
                 maybe_event = events_from_network.next_event() => match maybe_event {
-                    Some(event) => if self.handle_network_event(event).is_err() {
-                        error!(target: LOG_TARGET, "Cannot forward messages to user.");
-                        return;
+                    Some(event) => {
+                        self.shutdown_recorder.count("network_events");
+                        if self.handle_network_event(event).is_err() {
+                            error!(target: LOG_TARGET, "Cannot forward messages to user.");
+                            self.shutdown_recorder.exit_reason("cannot forward messages to user");
+                            return;
+                        }
                     },
                     None => {
                         error!(target: LOG_TARGET, "Network event stream ended.");
+                        self.shutdown_recorder.exit_reason("network event stream ended");
                         return;
                     }
                 },
-                maybe_message = self.messages_from_authentication_user.next() => match maybe_message {
-                    Some(Command::Broadcast(message)) => self.broadcast_authentication(message),
-                    Some(Command::SendToRandom(message, peer_ids)) => self.send_to_random_authentication(message, peer_ids),
-                    Some(Command::Send(message, peer_id)) => self.send_authentication_data(message, peer_id),
-                    None => {
-                        error!(target: LOG_TARGET, "Authentication user message stream ended.");
-                        return;
+                maybe_message = self.messages_from_authentication_user.next() => {
+                    self.shutdown_recorder.count("authentication_commands");
+                    match maybe_message {
+                        Some(Command::Broadcast(message)) => self.broadcast_authentication(message),
+                        Some(Command::SendToRandom(message, peer_ids)) => self.send_to_random_authentication(message, peer_ids),
+                        Some(Command::Send(message, peer_id)) => self.send_authentication_data(message, peer_id),
+                        Some(Command::Penalize(peer_id, penalty)) => self.penalize(peer_id, Protocol::Authentication, penalty),
+                        None => {
+                            error!(target: LOG_TARGET, "Authentication user message stream ended.");
+                            self.shutdown_recorder.exit_reason("authentication user message stream ended");
+                            return;
+                        }
                     }
                 },
-                maybe_message = self.messages_from_block_sync_user.next() => match maybe_message {
-                    Some(Command::Broadcast(message)) => self.broadcast_block_sync(message),
-                    Some(Command::SendToRandom(message, peer_ids)) => self.send_to_random_block_sync(message, peer_ids),
-                    Some(Command::Send(message, peer_id)) => self.send_block_sync_data(message, peer_id),
-                    None => {
-                        error!(target: LOG_TARGET, "Block sync user message stream ended.");
-                        return;
+                maybe_message = self.messages_from_block_sync_user.next() => {
+                    self.shutdown_recorder.count("block_sync_commands");
+                    match maybe_message {
+                        Some(Command::Broadcast(message)) => self.broadcast_block_sync(message),
+                        Some(Command::SendToRandom(message, peer_ids)) => self.send_to_random_block_sync(message, peer_ids),
+                        Some(Command::Send(message, peer_id)) => self.send_block_sync_data(message, peer_id),
+                        Some(Command::Penalize(peer_id, penalty)) => self.penalize(peer_id, Protocol::BlockSync, penalty),
+                        None => {
+                            error!(target: LOG_TARGET, "Block sync user message stream ended.");
+                            self.shutdown_recorder.exit_reason("block sync user message stream ended");
+                            return;
+                        }
                     }
                 },
                 _ = status_ticker.tick() => {
+                    self.block_sync_parked.prune(Instant::now());
                     self.status_report();
                 },
+                _ = handshake_ticker.tick() => self.check_handshakes(),
             }
         }
     }
 }
 
+impl<N: RawNetwork, AD: Data, BSD: Data> Drop for Service<N, AD, BSD> {
+    fn drop(&mut self) {
+        self.shutdown_recorder
+            .report(iter::empty())
+            .emit(self.backup_path.as_deref());
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashSet, iter};
+    use std::{collections::HashSet, iter, time::Duration};
 
-    use futures::channel::oneshot;
+    use futures::{channel::oneshot, FutureExt};
     use network_clique::mock::{random_peer_id, MockPublicKey};
     use parity_scale_codec::Encode;
     use sc_service::TaskManager;
@@ -511,8 +684,9 @@ mod tests {
     use crate::network::{
         gossip::{
             mock::{MockEvent, MockRawNetwork, MockSenderError},
-            Network,
+            Network, Penalty,
         },
+        limits::PublicNetworkLimits,
         mock::MockData,
         Protocol,
     };
@@ -527,11 +701,15 @@ mod tests {
         _task_manager: TaskManager,
         // If we drop the sync network, the underlying network service dies, stopping the whole
         // network.
-        _other_network: Box<dyn Network<MockData, Error = Error, PeerId = MockPublicKey>>,
+        other_network: Box<dyn Network<MockData, Error = Error, PeerId = MockPublicKey>>,
     }
 
     impl TestData {
         fn prepare() -> Self {
+            Self::prepare_with_limits(PublicNetworkLimits::default())
+        }
+
+        fn prepare_with_limits(limits: PublicNetworkLimits) -> Self {
             let task_manager = TaskManager::new(Handle::current(), None).unwrap();
 
             // Event stream will never be taken, so we can drop the receiver
@@ -539,8 +717,13 @@ mod tests {
 
             // Prepare service
             let network = MockRawNetwork::new(event_stream_oneshot_tx);
-            let (service, gossip_network, other_network) =
-                Service::new(network.clone(), task_manager.spawn_handle().into(), None);
+            let (service, gossip_network, other_network) = Service::new(
+                network.clone(),
+                task_manager.spawn_handle().into(),
+                None,
+                None,
+                limits,
+            );
             let gossip_network = Box::new(gossip_network);
             let other_network = Box::new(other_network);
 
@@ -550,7 +733,7 @@ mod tests {
                 service,
                 gossip_network,
                 _task_manager: task_manager,
-                _other_network: other_network,
+                other_network,
             }
         }
 
@@ -580,6 +763,10 @@ mod tests {
             self.gossip_network.broadcast(data)
         }
 
+        fn penalize(&mut self, peer_id: Self::PeerId, penalty: Penalty) -> Result<(), Self::Error> {
+            self.gossip_network.penalize(peer_id, penalty)
+        }
+
         async fn next(&mut self) -> Result<(MockData, Self::PeerId), Self::Error> {
             self.gossip_network.next().await
         }
@@ -673,6 +860,113 @@ mod tests {
         test_data.cleanup().await
     }
 
+    #[tokio::test]
+    async fn test_keeps_block_sync_messages_across_reconnect() {
+        let mut test_data = TestData::prepare();
+
+        let peer_id = random_peer_id();
+
+        test_data
+            .service
+            .handle_network_event(MockEvent::StreamOpened(
+                peer_id.clone(),
+                Protocol::BlockSync,
+            ))
+            .expect("Should handle");
+        test_data
+            .service
+            .handle_network_event(MockEvent::StreamClosed(
+                peer_id.clone(),
+                Protocol::BlockSync,
+            ))
+            .expect("Should handle");
+
+        let message = message(1);
+        test_data
+            .service
+            .send_block_sync_data(message.clone(), peer_id.clone());
+
+        test_data
+            .service
+            .handle_network_event(MockEvent::StreamOpened(
+                peer_id.clone(),
+                Protocol::BlockSync,
+            ))
+            .expect("Should handle");
+
+        assert_eq!(
+            test_data
+                .network
+                .send_message
+                .next()
+                .await
+                .expect("Should receive message"),
+            (message.encode(), peer_id, Protocol::BlockSync),
+        );
+
+        test_data.cleanup().await
+    }
+
+    #[tokio::test]
+    async fn test_cancels_block_sync_work_of_closed_streams() {
+        let mut test_data = TestData::prepare();
+
+        let peer_id = random_peer_id();
+
+        test_data
+            .service
+            .handle_network_event(MockEvent::StreamOpened(
+                peer_id.clone(),
+                Protocol::BlockSync,
+            ))
+            .expect("Should handle");
+        let connection = test_data.other_network.connection(&peer_id);
+        assert!(!connection.is_canceled());
+
+        test_data
+            .service
+            .handle_network_event(MockEvent::StreamClosed(
+                peer_id.clone(),
+                Protocol::BlockSync,
+            ))
+            .expect("Should handle");
+        assert!(connection.is_canceled());
+
+        test_data.cleanup().await
+    }
+
+    #[tokio::test]
+    async fn test_disconnects_block_sync_peer_without_decodable_messages() {
+        let mut test_data = TestData::prepare_with_limits(PublicNetworkLimits {
+            handshake_timeout: Duration::ZERO,
+            ..PublicNetworkLimits::default()
+        });
+
+        let peer_id = random_peer_id();
+        test_data
+            .service
+            .handle_network_event(MockEvent::StreamOpened(
+                peer_id.clone(),
+                Protocol::BlockSync,
+            ))
+            .expect("Should handle");
+        test_data
+            .service
+            .handle_network_event(MockEvent::Messages(
+                peer_id.clone(),
+                vec![(Protocol::BlockSync, vec![7, 0, 1, 0, 0, 0, 3].into())],
+            ))
+            .expect("Should handle");
+        test_data.service.check_handshakes();
+
+        assert_eq!(
+            *test_data.network.penalties.lock(),
+            vec![(peer_id, Protocol::BlockSync, Penalty::Disconnect)]
+        );
+
+        test_data.cleanup().await
+    }
+
     #[tokio::test]
     async fn test_create_sender_error() {
         let mut test_data = TestData::prepare();
@@ -779,6 +1073,36 @@ mod tests {
         test_data.cleanup().await
     }
 
+    #[tokio::test]
+    async fn test_notification_above_rate_dropped() {
+        let first_message = message(1);
+        let second_message = message(2);
+        let mut test_data = TestData::prepare_with_limits(PublicNetworkLimits {
+            bit_rate_per_peer: Some(first_message.encode().len()),
+            ..PublicNetworkLimits::default()
+        });
+
+        let peer_id = random_peer_id();
+        test_data
+            .service
+            .handle_network_event(MockEvent::Messages(
+                peer_id.clone(),
+                vec![
+                    (PROTOCOL, first_message.clone().encode().into()),
+                    (PROTOCOL, second_message.encode().into()),
+                ],
+            ))
+            .expect("Should handle");
+
+        let (received_message, received_peer_id) =
+            test_data.next().await.expect("Should receive message");
+        assert_eq!(received_message, first_message);
+        assert_eq!(received_peer_id, peer_id);
+        assert!(test_data.next().now_or_never().is_none());
+
+        test_data.cleanup().await
+    }
+
     #[tokio::test]
     async fn test_send_to_connected() {
         let mut test_data = TestData::prepare();
@@ -830,6 +1154,24 @@ mod tests {
         test_data.cleanup().await
     }
 
+    #[tokio::test]
+    async fn test_penalize() {
+        let mut test_data = TestData::prepare();
+
+        let peer_id = random_peer_id();
+
+        test_data
+            .service
+            .penalize(peer_id.clone(), PROTOCOL, Penalty::Ban);
+
+        assert_eq!(
+            *test_data.network.penalties.lock(),
+            vec![(peer_id, PROTOCOL, Penalty::Ban)]
+        );
+
+        test_data.cleanup().await
+    }
+
     #[tokio::test]
     async fn test_send_to_random_connected() {
         let mut test_data = TestData::prepare();
@@ -0,0 +1,195 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Error as FmtError, Formatter},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use rate_limiter::TokenBucket;
+
+/// The default maximum bit-rate per connection in bytes per second of the validator network.
+pub const DEFAULT_VALIDATOR_BIT_RATE_PER_CONNECTION: usize = 64 * 1024;
+/// The default maximum size in bytes of a single message of the public gossip protocols.
+// It should be larger than the maximum possible honest message size.
+// Max size of alert is UNIT_SIZE * MAX_UNITS_IN_ALERT ~ 100 * 5000 = 50000 bytes
+// Max size of parents response UNIT_SIZE * N_MEMBERS ~ 100 * N_MEMBERS
+// When adding other (large) message types we need to make sure this limit is fine.
+pub const DEFAULT_PUBLIC_MAX_MESSAGE_SIZE: u64 = 1024 * 1024;
+/// Lower public message size limits would make honest authentication messages undeliverable.
+pub const MIN_PUBLIC_MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+/// The default time a peer opening the block sync protocol has to send a decodable message. Honest
+/// peers broadcast their states every few seconds.
+pub const DEFAULT_PUBLIC_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Limits of the direct connections between validators, carrying the consensus traffic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValidatorNetworkLimits {
+    /// Maximum bit-rate per connection in bytes per second.
+    pub bit_rate_per_connection: usize,
+}
+
+impl Default for ValidatorNetworkLimits {
+    fn default() -> Self {
+        ValidatorNetworkLimits {
+            bit_rate_per_connection: DEFAULT_VALIDATOR_BIT_RATE_PER_CONNECTION,
+        }
+    }
+}
+
+/// Limits of the public gossip protocols, carrying discovery and block sync traffic from any
+/// peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicNetworkLimits {
+    /// Maximum size in bytes of a single message.
+    pub max_message_size: u64,
+    /// Maximum bit-rate in bytes per second of the messages received from a single peer, the
+    /// messages above it are dropped. No limit if `None`.
+    pub bit_rate_per_peer: Option<usize>,
+    /// How long a peer opening the block sync protocol has to send a decodable message, before
+    /// it gets disconnected.
+    pub handshake_timeout: Duration,
+}
+
+impl Default for PublicNetworkLimits {
+    fn default() -> Self {
+        PublicNetworkLimits {
+            max_message_size: DEFAULT_PUBLIC_MAX_MESSAGE_SIZE,
+            bit_rate_per_peer: None,
+            handshake_timeout: DEFAULT_PUBLIC_HANDSHAKE_TIMEOUT,
+        }
+    }
+}
+
+/// The limits of all the networking, separate for every class of connections, so that tightening
+/// the public facing ones does not throttle the traffic within the committee.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NetworkLimits {
+    pub validator: ValidatorNetworkLimits,
+    pub public: PublicNetworkLimits,
+}
+
+/// Ways in which the network limits can be wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitsError {
+    ZeroValidatorBitRate,
+    ZeroPublicBitRate,
+    PublicMessageSizeTooSmall(u64),
+    ZeroHandshakeTimeout,
+}
+
+impl Display for LimitsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use LimitsError::*;
+        match self {
+            ZeroValidatorBitRate => write!(f, "validator network bit-rate cannot be zero"),
+            ZeroPublicBitRate => write!(f, "public gossip bit-rate cannot be zero"),
+            PublicMessageSizeTooSmall(size) => write!(
+                f,
+                "public gossip message size limit of {size} bytes is below the minimum of \
+                 {MIN_PUBLIC_MAX_MESSAGE_SIZE}"
+            ),
+            ZeroHandshakeTimeout => write!(f, "public gossip handshake timeout cannot be zero"),
+        }
+    }
+}
+
+impl NetworkLimits {
+    /// Checks that the limits allow honest traffic through.
+    pub fn validate(&self) -> Result<(), LimitsError> {
+        use LimitsError::*;
+        if self.validator.bit_rate_per_connection == 0 {
+            return Err(ZeroValidatorBitRate);
+        }
+        if self.public.bit_rate_per_peer == Some(0) {
+            return Err(ZeroPublicBitRate);
+        }
+        if self.public.max_message_size < MIN_PUBLIC_MAX_MESSAGE_SIZE {
+            return Err(PublicMessageSizeTooSmall(self.public.max_message_size));
+        }
+        if self.public.handshake_timeout.is_zero() {
+            return Err(ZeroHandshakeTimeout);
+        }
+        Ok(())
+    }
+}
+
+/// Keeps track of how much data every peer sent, to drop messages from the ones exceeding the
+/// rate.
+pub struct PeerRateLimits<P: Hash + Eq> {
+    bit_rate_per_peer: Option<usize>,
+    buckets: HashMap<P, TokenBucket>,
+}
+
+impl<P: Hash + Eq> PeerRateLimits<P> {
+    pub fn new(bit_rate_per_peer: Option<usize>) -> Self {
+        PeerRateLimits {
+            bit_rate_per_peer,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Whether a message of the given size from the peer fits within the rate.
+    pub fn allow(&mut self, peer: P, size: usize, now: Instant) -> bool {
+        match self.bit_rate_per_peer {
+            Some(rate) => self
+                .buckets
+                .entry(peer)
+                .or_insert_with(|| TokenBucket::new_with_now(rate, now))
+                .rate_limit(size, now)
+                .is_none(),
+            None => true,
+        }
+    }
+
+    /// Forgets the peer, it is not connected anymore.
+    pub fn remove(&mut self, peer: &P) {
+        self.buckets.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{LimitsError, NetworkLimits, PeerRateLimits};
+
+    #[test]
+    fn default_limits_are_valid() {
+        assert_eq!(NetworkLimits::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_limits_blocking_honest_traffic() {
+        let mut limits = NetworkLimits::default();
+        limits.public.max_message_size = 1024;
+        assert_eq!(
+            limits.validate(),
+            Err(LimitsError::PublicMessageSizeTooSmall(1024))
+        );
+        let mut limits = NetworkLimits::default();
+        limits.public.bit_rate_per_peer = Some(0);
+        assert_eq!(limits.validate(), Err(LimitsError::ZeroPublicBitRate));
+        let mut limits = NetworkLimits::default();
+        limits.public.handshake_timeout = Duration::ZERO;
+        assert_eq!(limits.validate(), Err(LimitsError::ZeroHandshakeTimeout));
+    }
+
+    #[test]
+    fn drops_messages_above_rate() {
+        let mut rate_limits = PeerRateLimits::new(Some(1000));
+        let now = Instant::now();
+        assert!(rate_limits.allow(1, 600, now));
+        assert!(!rate_limits.allow(1, 600, now));
+        assert!(rate_limits.allow(2, 600, now));
+        assert!(rate_limits.allow(1, 600, now + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn unlimited_without_rate() {
+        let mut rate_limits = PeerRateLimits::new(None);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert!(rate_limits.allow(1, 1024 * 1024, now));
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::{sync::Arc, time::Duration};
 
 use futures::{channel::mpsc, StreamExt};
+use network_clique::mock::MockPublicKey;
 use parity_scale_codec::{Decode, Encode, Output};
 use sp_keystore::{testing::MemoryKeystore as Keystore, Keystore as _};
 use tokio::time::timeout;
@@ -8,9 +9,16 @@ use tokio::time::timeout;
 use crate::{
     aleph_primitives::KEY_TYPE,
     crypto::{AuthorityPen, AuthorityVerifier},
+    network::{AlephPeerId, ToAlephPeerId},
     AuthorityId, NodeIndex,
 };
 
+impl ToAlephPeerId for MockPublicKey {
+    fn to_aleph_peer_id(&self) -> Option<AlephPeerId> {
+        None
+    }
+}
+
 #[derive(Hash, Debug, Clone, PartialEq, Eq)]
 pub struct MockData {
     data: u32,
@@ -1,20 +1,36 @@
 use parity_scale_codec::Codec;
 
+mod cancellation;
 pub mod data;
 mod gossip;
+mod limits;
 #[cfg(test)]
 pub mod mock;
+mod peer_id;
+mod request_response;
 pub mod session;
 mod substrate;
 pub mod tcp;
 
+pub use cancellation::{CancelToken, ConnectionEvent, ConnectionEvents};
 #[cfg(test)]
 pub use gossip::mock::{MockEvent, MockRawNetwork};
 pub use gossip::{
-    Error as GossipError, Network as GossipNetwork, Protocol, Service as GossipService,
+    Error as GossipError, Network as GossipNetwork, Penalty, PendingResponse, Protocol, RequestId,
+    RequestNetwork, Service as GossipService,
+};
+pub use limits::{
+    LimitsError, NetworkLimits, PublicNetworkLimits, ValidatorNetworkLimits,
+    DEFAULT_PUBLIC_HANDSHAKE_TIMEOUT, DEFAULT_PUBLIC_MAX_MESSAGE_SIZE,
+    DEFAULT_VALIDATOR_BIT_RATE_PER_CONNECTION, MIN_PUBLIC_MAX_MESSAGE_SIZE,
 };
 use network_clique::{AddressingInformation, NetworkIdentity, PeerId};
-pub use substrate::{ProtocolNaming, SubstrateNetwork};
+pub use peer_id::{AlephPeerId, PeerIdentities, PeerIdentity, ToAlephPeerId};
+pub use request_response::RequestResponseNetwork;
+pub use substrate::{
+    block_sync_requests_config, BlockSyncRequests, ProtocolNaming, SubstrateNetwork,
+    SubstrateRequestResponse,
+};
 
 use crate::BlockIdentifier;
 
@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Error as FmtError, Formatter},
+    sync::Arc,
+};
+
+use network_clique::PeerId as _;
+use parking_lot::Mutex;
+use sc_network::PeerId as GossipPeerId;
+
+use crate::{aleph_primitives::AuthorityId, network::tcp::AuthorityIdWrapper};
+
+/// Any of the identifiers a node is known by. A single node can use all three at once, the
+/// validator network key being regenerated at every restart and the authority key every time
+/// the node joins a committee.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AlephPeerId {
+    /// The key signing the consensus data of a committee member.
+    Authority(AuthorityId),
+    /// The key identifying the node in the validator network.
+    Validator(AuthorityIdWrapper),
+    /// The identity of the node in the gossip network.
+    Gossip(GossipPeerId),
+}
+
+impl From<AuthorityId> for AlephPeerId {
+    fn from(id: AuthorityId) -> Self {
+        AlephPeerId::Authority(id)
+    }
+}
+
+impl From<AuthorityIdWrapper> for AlephPeerId {
+    fn from(id: AuthorityIdWrapper) -> Self {
+        AlephPeerId::Validator(id)
+    }
+}
+
+impl From<GossipPeerId> for AlephPeerId {
+    fn from(id: GossipPeerId) -> Self {
+        AlephPeerId::Gossip(id)
+    }
+}
+
+impl Display for AlephPeerId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use AlephPeerId::*;
+        match self {
+            Authority(id) => write!(
+                f,
+                "authority {}",
+                AuthorityIdWrapper::from(id.clone()).to_short_string()
+            ),
+            Validator(id) => write!(f, "validator {}", id.to_short_string()),
+            Gossip(id) => write!(f, "gossip {id}"),
+        }
+    }
+}
+
+/// Peer identifiers which might correspond to an `AlephPeerId`, so that the networks generic
+/// over their identifiers can record and describe them.
+pub trait ToAlephPeerId {
+    fn to_aleph_peer_id(&self) -> Option<AlephPeerId>;
+}
+
+impl ToAlephPeerId for AuthorityIdWrapper {
+    fn to_aleph_peer_id(&self) -> Option<AlephPeerId> {
+        Some(self.clone().into())
+    }
+}
+
+impl ToAlephPeerId for GossipPeerId {
+    fn to_aleph_peer_id(&self) -> Option<AlephPeerId> {
+        Some((*self).into())
+    }
+}
+
+/// All the identifiers known to belong to a single node.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    pub authority: Option<AuthorityId>,
+    pub validator: Option<AuthorityIdWrapper>,
+    pub gossip: Option<GossipPeerId>,
+}
+
+impl PeerIdentity {
+    fn ids(&self) -> Vec<AlephPeerId> {
+        let authority = self.authority.clone().map(AlephPeerId::from);
+        let validator = self.validator.clone().map(AlephPeerId::from);
+        let gossip = self.gossip.map(AlephPeerId::from);
+        [authority, validator, gossip]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// Sets the identifier of its kind, returns the one it replaced, if different.
+    fn set(&mut self, id: AlephPeerId) -> Option<AlephPeerId> {
+        use AlephPeerId::*;
+        match id {
+            Authority(id) => self
+                .authority
+                .replace(id.clone())
+                .filter(|old| old != &id)
+                .map(Authority),
+            Validator(id) => self
+                .validator
+                .replace(id.clone())
+                .filter(|old| old != &id)
+                .map(Validator),
+            Gossip(id) => self.gossip.replace(id).filter(|old| old != &id).map(Gossip),
+        }
+    }
+
+    fn unset(&mut self, id: &AlephPeerId) {
+        use AlephPeerId::*;
+        match id {
+            Authority(_) => self.authority = None,
+            Validator(_) => self.validator = None,
+            Gossip(_) => self.gossip = None,
+        }
+    }
+}
+
+/// Correlates the identifiers the same node uses in the validator and gossip networks. Links are
+/// only recorded once proven, e.g. by a signed authentication. Can be cloned and used from all
+/// the networks at once.
+#[derive(Clone, Default)]
+pub struct PeerIdentities {
+    identities: Arc<Mutex<HashMap<AlephPeerId, PeerIdentity>>>,
+}
+
+impl PeerIdentities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that both identifiers belong to the same node. Any identifier of the same kind the
+    /// node was known by before is forgotten, and `second` stops being linked to anything else.
+    pub fn link(&self, first: AlephPeerId, second: AlephPeerId) {
+        let mut identities = self.identities.lock();
+        // The second identifier moves to the node of the first one.
+        if let Some(mut previous) = identities.remove(&second) {
+            previous.unset(&second);
+            for id in previous.ids() {
+                identities.insert(id, previous.clone());
+            }
+        }
+        let mut identity = identities.get(&first).cloned().unwrap_or_default();
+        identity.set(first);
+        if let Some(replaced) = identity.set(second) {
+            identities.remove(&replaced);
+        }
+        for id in identity.ids() {
+            identities.insert(id, identity.clone());
+        }
+    }
+
+    /// Everything known about the node using the identifier.
+    pub fn identity(&self, id: &AlephPeerId) -> Option<PeerIdentity> {
+        self.identities.lock().get(id).cloned()
+    }
+
+    /// The identifier with all the others linked to it, for logs.
+    pub fn describe(&self, id: &AlephPeerId) -> String {
+        let others: Vec<_> = match self.identity(id) {
+            Some(identity) => identity
+                .ids()
+                .into_iter()
+                .filter(|other| other != id)
+                .map(|other| other.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+        match others.is_empty() {
+            true => id.to_string(),
+            false => format!("{} ({})", id, others.join(", ")),
+        }
+    }
+
+    /// Same as `describe`, for identifiers of any of the networks.
+    pub fn describe_peer<P: ToAlephPeerId + Display>(&self, peer: &P) -> String {
+        match peer.to_aleph_peer_id() {
+            Some(id) => self.describe(&id),
+            None => peer.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sc_network::PeerId as GossipPeerId;
+    use sp_core::ed25519::Public;
+
+    use super::{AlephPeerId, PeerIdentities};
+    use crate::{aleph_primitives::AuthorityId, network::tcp::AuthorityIdWrapper};
+
+    fn authority(seed: u8) -> AlephPeerId {
+        AuthorityId::from(Public::from_raw([seed; 32])).into()
+    }
+
+    fn validator(seed: u8) -> AlephPeerId {
+        AuthorityIdWrapper::from(AuthorityId::from(Public::from_raw([seed; 32]))).into()
+    }
+
+    #[test]
+    fn links_all_identifiers_of_a_node() {
+        let identities = PeerIdentities::new();
+        let gossip: AlephPeerId = GossipPeerId::random().into();
+        identities.link(authority(0), validator(1));
+        identities.link(validator(1), gossip.clone());
+        let identity = identities.identity(&gossip).expect("should be linked");
+        assert_eq!(identities.identity(&authority(0)), Some(identity.clone()));
+        assert_eq!(identity.ids(), vec![authority(0), validator(1), gossip]);
+    }
+
+    #[test]
+    fn replaces_identifiers_of_the_same_kind() {
+        let identities = PeerIdentities::new();
+        identities.link(authority(0), validator(1));
+        // The node restarted with a new validator network key.
+        identities.link(authority(0), validator(2));
+        assert!(identities.identity(&validator(1)).is_none());
+        let identity = identities
+            .identity(&validator(2))
+            .expect("should be linked");
+        assert_eq!(identity.ids(), vec![authority(0), validator(2)]);
+    }
+
+    #[test]
+    fn moves_identifiers_between_nodes() {
+        let identities = PeerIdentities::new();
+        identities.link(authority(0), validator(2));
+        identities.link(authority(1), validator(2));
+        let identity = identities.identity(&authority(0)).expect("still known");
+        assert_eq!(identity.ids(), vec![authority(0)]);
+        let identity = identities
+            .identity(&validator(2))
+            .expect("should be linked");
+        assert_eq!(identity.ids(), vec![authority(1), validator(2)]);
+    }
+
+    #[test]
+    fn describes_with_linked_identifiers() {
+        let identities = PeerIdentities::new();
+        assert_eq!(identities.describe(&validator(1)), validator(1).to_string());
+        identities.link(authority(0), validator(1));
+        assert_eq!(
+            identities.describe(&validator(1)),
+            format!("{} ({})", validator(1), authority(0))
+        );
+    }
+}
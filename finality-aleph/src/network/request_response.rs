@@ -0,0 +1,629 @@
+//! Requests and responses of a gossip network exchanged through a dedicated request-response
+//! protocol, with the rest of the data still going through the gossip network.
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{Debug, Display, Error as FmtError, Formatter},
+    hash::Hash,
+};
+
+use futures::{
+    future::{pending, BoxFuture},
+    stream::FuturesUnordered,
+    FutureExt, StreamExt,
+};
+use log::{debug, trace};
+use rand::{seq::IteratorRandom, thread_rng};
+use tokio::select;
+
+use crate::network::{
+    gossip::{Network, Penalty},
+    CancelToken, ConnectionEvents, Data,
+};
+
+const LOG_TARGET: &str = "aleph-network";
+
+/// How many of our requests wait for responses at once at most, further ones are sent as
+/// notifications.
+const MAX_PENDING_REQUESTS: usize = 64;
+
+/// How many requests of a single peer wait for our responses at most, the oldest ones are refused
+/// first.
+const MAX_UNANSWERED_REQUESTS_PER_PEER: usize = 8;
+
+/// Why a request did not get a response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RequestError<E> {
+    /// The request did not reach the peer through the protocol, e.g. because the peer does not
+    /// support it.
+    Unreachable(E),
+    /// The peer got the request, but did not answer it.
+    Unanswered(E),
+}
+
+impl<E: Display> Display for RequestError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use RequestError::*;
+        match self {
+            Unreachable(e) => write!(f, "request did not reach the peer: {e}"),
+            Unanswered(e) => write!(f, "request was not answered: {e}"),
+        }
+    }
+}
+
+/// The answering end of a single request.
+pub trait Responder: Send + 'static {
+    /// Answers the request.
+    fn respond(self, response: Vec<u8>);
+
+    /// Whether the requester stopped waiting for the response, e.g. because it timed out.
+    fn is_canceled(&self) -> bool;
+}
+
+/// Abstraction over a raw request-response protocol.
+#[async_trait::async_trait]
+pub trait RawRequestResponse: Send + 'static {
+    type Error: Display + Send + 'static;
+    type PeerId: Clone + Debug + Eq + Hash + Send + 'static;
+    type Responder: Responder;
+
+    /// The largest request the protocol carries.
+    fn max_request_size(&self) -> usize;
+
+    /// The largest response the protocol carries.
+    fn max_response_size(&self) -> usize;
+
+    /// Sends the request to the peer, the returned future resolves once the response arrives or
+    /// it becomes clear none will.
+    fn request(
+        &self,
+        peer_id: Self::PeerId,
+        request: Vec<u8>,
+    ) -> BoxFuture<'static, Result<Vec<u8>, RequestError<Self::Error>>>;
+
+    /// Retrieves the next request from the network, or returns `None` if none will come anymore.
+    /// This method's implementation must be cancellation safe.
+    async fn next_request(&mut self) -> Option<(Self::PeerId, Vec<u8>, Self::Responder)>;
+}
+
+/// How a request gets sent as a notification if it does not reach the peer.
+enum Fallback<D, P> {
+    Peer(D, P),
+    Random(D, HashSet<P>),
+}
+
+impl<D: Data, P: Clone + Debug + Eq + Hash + Send + 'static> Fallback<D, P> {
+    fn data(&self) -> &D {
+        match self {
+            Fallback::Peer(data, _) | Fallback::Random(data, _) => data,
+        }
+    }
+
+    fn send<N: Network<D, PeerId = P>>(self, network: &mut N) -> Result<(), N::Error> {
+        match self {
+            Fallback::Peer(data, peer_id) => network.send_to(data, peer_id),
+            Fallback::Random(data, peer_ids) => network.send_to_random(data, peer_ids),
+        }
+    }
+}
+
+type PendingRequest<D, P, E> =
+    BoxFuture<'static, (P, Result<Vec<u8>, RequestError<E>>, Fallback<D, P>)>;
+
+/// A gossip network sending requests and responses through a raw request-response protocol, so
+/// that every response answers the request it belongs to within its size limits and timeout.
+/// Without the protocol, or whenever a request or response cannot go through it, everything is
+/// sent through the wrapped network as usual.
+pub struct RequestResponseNetwork<D, N, R>
+where
+    D: Data,
+    N: Network<D>,
+    R: RawRequestResponse<PeerId = N::PeerId>,
+{
+    inner: N,
+    raw: Option<R>,
+    requests: FuturesUnordered<PendingRequest<D, N::PeerId, R::Error>>,
+    unanswered: HashMap<N::PeerId, VecDeque<R::Responder>>,
+}
+
+impl<D, N, R> RequestResponseNetwork<D, N, R>
+where
+    D: Data,
+    N: Network<D>,
+    R: RawRequestResponse<PeerId = N::PeerId>,
+{
+    /// Wraps the network, exchanging requests through the raw protocol if one is provided.
+    pub fn new(inner: N, raw: Option<R>) -> Self {
+        RequestResponseNetwork {
+            inner,
+            raw,
+            requests: FuturesUnordered::new(),
+            unanswered: HashMap::new(),
+        }
+    }
+
+    /// Sends the request through the raw protocol, or gives it back if it cannot go through it.
+    fn exchange(
+        &mut self,
+        peer_id: N::PeerId,
+        fallback: Fallback<D, N::PeerId>,
+    ) -> Result<(), Fallback<D, N::PeerId>> {
+        let raw = match &self.raw {
+            Some(raw) if self.requests.len() < MAX_PENDING_REQUESTS => raw,
+            _ => return Err(fallback),
+        };
+        let request = fallback.data().encode();
+        if request.len() > raw.max_request_size() {
+            trace!(
+                target: LOG_TARGET,
+                "Request to {:?} of {} bytes is too big for the request-response protocol.",
+                peer_id,
+                request.len()
+            );
+            return Err(fallback);
+        }
+        let response = raw.request(peer_id.clone(), request);
+        self.requests
+            .push(async move { (peer_id, response.await, fallback) }.boxed());
+        Ok(())
+    }
+
+    /// Remembers the request of the peer waiting for our response.
+    fn add_unanswered(&mut self, peer_id: N::PeerId, responder: R::Responder) {
+        self.unanswered.retain(|_, responders| {
+            responders.retain(|responder| !responder.is_canceled());
+            !responders.is_empty()
+        });
+        let responders = self.unanswered.entry(peer_id).or_default();
+        if responders.len() >= MAX_UNANSWERED_REQUESTS_PER_PEER {
+            // Dropping the responder refuses the request.
+            responders.pop_front();
+        }
+        responders.push_back(responder);
+    }
+
+    /// The responder to the oldest request of the peer still waiting for our response.
+    fn oldest_unanswered(&mut self, peer_id: &N::PeerId) -> Option<R::Responder> {
+        let responders = self.unanswered.get_mut(peer_id)?;
+        let responder = loop {
+            match responders.pop_front() {
+                Some(responder) if responder.is_canceled() => continue,
+                responder => break responder,
+            }
+        };
+        if responders.is_empty() {
+            self.unanswered.remove(peer_id);
+        }
+        responder
+    }
+}
+
+async fn next_request<R: RawRequestResponse>(
+    raw: &mut Option<R>,
+) -> Option<(R::PeerId, Vec<u8>, R::Responder)> {
+    match raw {
+        Some(raw) => raw.next_request().await,
+        None => pending().await,
+    }
+}
+
+#[async_trait::async_trait]
+impl<D, N, R> Network<D> for RequestResponseNetwork<D, N, R>
+where
+    D: Data,
+    N: Network<D>,
+    R: RawRequestResponse<PeerId = N::PeerId>,
+{
+    type Error = N::Error;
+    type PeerId = N::PeerId;
+
+    fn send_to(&mut self, data: D, peer_id: Self::PeerId) -> Result<(), Self::Error> {
+        self.inner.send_to(data, peer_id)
+    }
+
+    fn send_to_random(
+        &mut self,
+        data: D,
+        peer_ids: HashSet<Self::PeerId>,
+    ) -> Result<(), Self::Error> {
+        self.inner.send_to_random(data, peer_ids)
+    }
+
+    fn broadcast(&mut self, data: D) -> Result<(), Self::Error> {
+        self.inner.broadcast(data)
+    }
+
+    fn send_request(&mut self, data: D, peer_id: Self::PeerId) -> Result<(), Self::Error> {
+        match self.exchange(peer_id.clone(), Fallback::Peer(data, peer_id)) {
+            Ok(()) => Ok(()),
+            Err(fallback) => fallback.send(&mut self.inner),
+        }
+    }
+
+    fn send_request_to_random(
+        &mut self,
+        data: D,
+        peer_ids: HashSet<Self::PeerId>,
+    ) -> Result<(), Self::Error> {
+        let peer_id = match peer_ids.iter().choose(&mut thread_rng()) {
+            Some(peer_id) => peer_id.clone(),
+            None => return self.inner.send_to_random(data, peer_ids),
+        };
+        // If the chosen peer cannot be reached the gossip network picks one it is connected to.
+        match self.exchange(peer_id, Fallback::Random(data, peer_ids)) {
+            Ok(()) => Ok(()),
+            Err(fallback) => fallback.send(&mut self.inner),
+        }
+    }
+
+    fn send_response(&mut self, data: D, peer_id: Self::PeerId) -> Result<(), Self::Error> {
+        let (responder, max_response_size) = match (self.oldest_unanswered(&peer_id), &self.raw) {
+            (Some(responder), Some(raw)) => (responder, raw.max_response_size()),
+            _ => return self.inner.send_to(data, peer_id),
+        };
+        let response = data.encode();
+        if response.len() > max_response_size {
+            trace!(
+                target: LOG_TARGET,
+                "Response to {:?} of {} bytes is too big for the request-response protocol.",
+                peer_id,
+                response.len()
+            );
+            // Some other response might still fit.
+            self.unanswered
+                .entry(peer_id.clone())
+                .or_default()
+                .push_front(responder);
+            return self.inner.send_to(data, peer_id);
+        }
+        responder.respond(response);
+        Ok(())
+    }
+
+    fn penalize(&mut self, peer_id: Self::PeerId, penalty: Penalty) -> Result<(), Self::Error> {
+        self.unanswered.remove(&peer_id);
+        self.inner.penalize(peer_id, penalty)
+    }
+
+    fn connection(&self, peer_id: &Self::PeerId) -> CancelToken {
+        self.inner.connection(peer_id)
+    }
+
+    fn connection_events(&self) -> ConnectionEvents<Self::PeerId> {
+        self.inner.connection_events()
+    }
+
+    /// Retrieves next message from the network, including the requests and responses exchanged
+    /// through the raw protocol.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancellation safe.
+    async fn next(&mut self) -> Result<(D, Self::PeerId), Self::Error> {
+        loop {
+            select! {
+                result = self.inner.next() => return result,
+                Some((peer_id, response, fallback)) = self.requests.next() => match response {
+                    Ok(response) => match D::decode(&mut &response[..]) {
+                        Ok(data) => return Ok((data, peer_id)),
+                        Err(e) => debug!(
+                            target: LOG_TARGET,
+                            "Failed to decode the response from {:?}: {}.", peer_id, e
+                        ),
+                    },
+                    Err(RequestError::Unanswered(e)) => trace!(
+                        target: LOG_TARGET,
+                        "Request to {:?} was not answered: {}.", peer_id, e
+                    ),
+                    Err(RequestError::Unreachable(e)) => {
+                        trace!(
+                            target: LOG_TARGET,
+                            "Request to {:?} sent as a notification instead: {}.", peer_id, e
+                        );
+                        fallback.send(&mut self.inner)?;
+                    }
+                },
+                Some((peer_id, request, responder)) = next_request(&mut self.raw) => {
+                    match D::decode(&mut &request[..]) {
+                        Ok(data) => {
+                            self.add_unanswered(peer_id.clone(), responder);
+                            return Ok((data, peer_id));
+                        }
+                        // Dropping the responder refuses the request.
+                        Err(e) => debug!(
+                            target: LOG_TARGET,
+                            "Failed to decode the request from {:?}: {}.", peer_id, e
+                        ),
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use futures::{
+        channel::{mpsc, oneshot},
+        future::BoxFuture,
+        FutureExt, StreamExt,
+    };
+    use network_clique::mock::{random_peer_id, MockPublicKey};
+    use parity_scale_codec::Encode;
+    use tokio::time::timeout;
+
+    use super::{RawRequestResponse, RequestError, RequestResponseNetwork, Responder};
+    use crate::network::{
+        gossip::{Network, Penalty},
+        mock::MockData,
+    };
+
+    const MAX_SIZE: usize = 1024;
+
+    type ResponseSender = oneshot::Sender<Result<Vec<u8>, RequestError<&'static str>>>;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Sent {
+        To(MockData, MockPublicKey),
+        Random(MockData, HashSet<MockPublicKey>),
+        Broadcast(MockData),
+    }
+
+    struct MockNetwork {
+        sent: mpsc::UnboundedSender<Sent>,
+        received: mpsc::UnboundedReceiver<(MockData, MockPublicKey)>,
+    }
+
+    #[async_trait::async_trait]
+    impl Network<MockData> for MockNetwork {
+        type Error = &'static str;
+        type PeerId = MockPublicKey;
+
+        fn send_to(&mut self, data: MockData, peer_id: MockPublicKey) -> Result<(), Self::Error> {
+            self.sent
+                .unbounded_send(Sent::To(data, peer_id))
+                .map_err(|_| "closed")
+        }
+
+        fn send_to_random(
+            &mut self,
+            data: MockData,
+            peer_ids: HashSet<MockPublicKey>,
+        ) -> Result<(), Self::Error> {
+            self.sent
+                .unbounded_send(Sent::Random(data, peer_ids))
+                .map_err(|_| "closed")
+        }
+
+        fn broadcast(&mut self, data: MockData) -> Result<(), Self::Error> {
+            self.sent
+                .unbounded_send(Sent::Broadcast(data))
+                .map_err(|_| "closed")
+        }
+
+        fn penalize(&mut self, _: MockPublicKey, _: Penalty) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn next(&mut self) -> Result<(MockData, MockPublicKey), Self::Error> {
+            self.received.next().await.ok_or("closed")
+        }
+    }
+
+    impl Responder for oneshot::Sender<Vec<u8>> {
+        fn respond(self, response: Vec<u8>) {
+            let _ = self.send(response);
+        }
+
+        fn is_canceled(&self) -> bool {
+            oneshot::Sender::is_canceled(self)
+        }
+    }
+
+    struct MockRequestResponse {
+        requests: mpsc::UnboundedSender<(MockPublicKey, Vec<u8>, ResponseSender)>,
+        incoming: mpsc::UnboundedReceiver<(MockPublicKey, Vec<u8>, oneshot::Sender<Vec<u8>>)>,
+    }
+
+    #[async_trait::async_trait]
+    impl RawRequestResponse for MockRequestResponse {
+        type Error = &'static str;
+        type PeerId = MockPublicKey;
+        type Responder = oneshot::Sender<Vec<u8>>;
+
+        fn max_request_size(&self) -> usize {
+            MAX_SIZE
+        }
+
+        fn max_response_size(&self) -> usize {
+            MAX_SIZE
+        }
+
+        fn request(
+            &self,
+            peer_id: MockPublicKey,
+            request: Vec<u8>,
+        ) -> BoxFuture<'static, Result<Vec<u8>, RequestError<&'static str>>> {
+            let (sender, receiver) = oneshot::channel();
+            self.requests
+                .unbounded_send((peer_id, request, sender))
+                .expect("test keeps the receiver");
+            receiver
+                .map(|response| response.unwrap_or(Err(RequestError::Unanswered("dropped"))))
+                .boxed()
+        }
+
+        async fn next_request(
+            &mut self,
+        ) -> Option<(MockPublicKey, Vec<u8>, oneshot::Sender<Vec<u8>>)> {
+            self.incoming.next().await
+        }
+    }
+
+    struct TestData {
+        network: RequestResponseNetwork<MockData, MockNetwork, MockRequestResponse>,
+        sent: mpsc::UnboundedReceiver<Sent>,
+        _received: mpsc::UnboundedSender<(MockData, MockPublicKey)>,
+        requests: mpsc::UnboundedReceiver<(MockPublicKey, Vec<u8>, ResponseSender)>,
+        incoming: mpsc::UnboundedSender<(MockPublicKey, Vec<u8>, oneshot::Sender<Vec<u8>>)>,
+    }
+
+    impl TestData {
+        fn prepare(with_raw: bool) -> Self {
+            let (sent_tx, sent) = mpsc::unbounded();
+            let (_received, received) = mpsc::unbounded();
+            let (requests_tx, requests) = mpsc::unbounded();
+            let (incoming, incoming_rx) = mpsc::unbounded();
+            let raw = with_raw.then_some(MockRequestResponse {
+                requests: requests_tx,
+                incoming: incoming_rx,
+            });
+            TestData {
+                network: RequestResponseNetwork::new(
+                    MockNetwork {
+                        sent: sent_tx,
+                        received,
+                    },
+                    raw,
+                ),
+                sent,
+                _received,
+                requests,
+                incoming,
+            }
+        }
+
+        fn sent(&mut self) -> Option<Sent> {
+            self.sent.try_next().ok().flatten()
+        }
+
+        /// Lets the network handle whatever it received, without anything to return.
+        async fn idle(&mut self) {
+            assert!(
+                timeout(Duration::from_millis(50), self.network.next())
+                    .await
+                    .is_err(),
+                "nothing should come out"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn exchanges_requests() {
+        let mut test_data = TestData::prepare(true);
+        let peer_id = random_peer_id();
+        let request = MockData::new(1, 0);
+        let response = MockData::new(2, 0);
+        test_data
+            .network
+            .send_request(request.clone(), peer_id.clone())
+            .expect("sends");
+        let (to, payload, sender) = test_data.requests.next().await.expect("requested");
+        assert_eq!(to, peer_id);
+        assert_eq!(payload, request.encode());
+        sender.send(Ok(response.encode())).expect("waits");
+        assert_eq!(
+            test_data.network.next().await.expect("receives"),
+            (response, peer_id)
+        );
+        test_data
+            .network
+            .broadcast(request.clone())
+            .expect("broadcasts");
+        assert_eq!(test_data.sent(), Some(Sent::Broadcast(request)));
+    }
+
+    #[tokio::test]
+    async fn answers_requests_in_order() {
+        let mut test_data = TestData::prepare(true);
+        let peer_id = random_peer_id();
+        let (first_sender, first_receiver) = oneshot::channel();
+        let (second_sender, second_receiver) = oneshot::channel();
+        for (data, sender) in [(1, first_sender), (2, second_sender)] {
+            test_data
+                .incoming
+                .unbounded_send((peer_id.clone(), MockData::new(data, 0).encode(), sender))
+                .expect("receiving");
+            assert_eq!(
+                test_data.network.next().await.expect("receives"),
+                (MockData::new(data, 0), peer_id.clone())
+            );
+        }
+        // The first requester stops waiting, so the response goes to the second.
+        drop(first_receiver);
+        let response = MockData::new(3, 0);
+        test_data
+            .network
+            .send_response(response.clone(), peer_id.clone())
+            .expect("responds");
+        assert_eq!(second_receiver.await, Ok(response.encode()));
+        // Nothing left to answer, so further responses are notifications.
+        test_data
+            .network
+            .send_response(response.clone(), peer_id.clone())
+            .expect("responds");
+        assert_eq!(test_data.sent(), Some(Sent::To(response, peer_id)));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_notifications() {
+        let mut test_data = TestData::prepare(true);
+        let peer_ids: HashSet<_> = (0..3).map(|_| random_peer_id()).collect();
+        let request = MockData::new(1, 0);
+        test_data
+            .network
+            .send_request_to_random(request.clone(), peer_ids.clone())
+            .expect("sends");
+        let (to, _, sender) = test_data.requests.next().await.expect("requested");
+        assert!(peer_ids.contains(&to));
+        sender
+            .send(Err(RequestError::Unreachable("unsupported")))
+            .expect("waits");
+        test_data.idle().await;
+        assert_eq!(
+            test_data.sent(),
+            Some(Sent::Random(request.clone(), peer_ids))
+        );
+        // Unanswered requests are not resent.
+        let peer_id = random_peer_id();
+        test_data
+            .network
+            .send_request(request.clone(), peer_id.clone())
+            .expect("sends");
+        let (_, _, sender) = test_data.requests.next().await.expect("requested");
+        sender
+            .send(Err(RequestError::Unanswered("refused")))
+            .expect("waits");
+        test_data.idle().await;
+        assert_eq!(test_data.sent(), None);
+        // Too big for the protocol.
+        let big = MockData::new(2, MAX_SIZE);
+        test_data
+            .network
+            .send_request(big.clone(), peer_id.clone())
+            .expect("sends");
+        assert_eq!(test_data.sent(), Some(Sent::To(big, peer_id)));
+        assert!(test_data.requests.try_next().is_err());
+    }
+
+    #[tokio::test]
+    async fn passes_everything_through_without_protocol() {
+        let mut test_data = TestData::prepare(false);
+        let peer_id = random_peer_id();
+        let data = MockData::new(1, 0);
+        test_data
+            .network
+            .send_request(data.clone(), peer_id.clone())
+            .expect("sends");
+        test_data
+            .network
+            .send_response(data.clone(), peer_id.clone())
+            .expect("sends");
+        assert_eq!(
+            test_data.sent(),
+            Some(Sent::To(data.clone(), peer_id.clone()))
+        );
+        assert_eq!(test_data.sent(), Some(Sent::To(data, peer_id)));
+    }
+}
@@ -4,6 +4,7 @@ use parity_scale_codec::Encode;
 
 use crate::{
     abft::NodeCount,
+    aleph_primitives::AuthorityId,
     crypto::{AuthorityPen, AuthorityVerifier},
     network::{
         session::{AuthData, Authentication},
@@ -165,6 +166,11 @@ impl<A: AddressingInformation> Handler<A> {
         self.peers_by_node.get(node_id).cloned()
     }
 
+    /// Returns the authority key of the node with the given NodeIndex.
+    pub fn authority_id(&self, node_id: NodeIndex) -> Option<AuthorityId> {
+        self.authority_verifier.authority_id(node_id)
+    }
+
     /// Returns maping from NodeIndex to PeerId
     pub fn peers(&self) -> HashMap<NodeIndex, A::PeerId> {
         self.peers_by_node.clone()
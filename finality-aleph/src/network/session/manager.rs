@@ -9,13 +9,14 @@ use log::{debug, info};
 
 use crate::{
     abft::Recipient,
+    aleph_primitives::AuthorityId,
     crypto::{AuthorityPen, AuthorityVerifier},
     network::{
         session::{
             data::DataInSession, Authentication, Connections, Discovery, DiscoveryMessage,
             SessionHandler, SessionHandlerError,
         },
-        AddressingInformation, Data, NetworkIdentity, PeerId,
+        AddressingInformation, Data, NetworkIdentity, PeerIdentities, ToAlephPeerId,
     },
     NodeIndex, SessionId,
 };
@@ -30,6 +31,17 @@ pub enum ConnectionCommand<A: AddressingInformation> {
 // In practice D: Data and P: PeerId, but we cannot require that in type aliases.
 pub type AddressedData<D, P> = (D, P);
 
+/// Records that the authority uses the peer id in the validator network.
+fn link_authority<P: ToAlephPeerId>(
+    peer_identities: &PeerIdentities,
+    authority: AuthorityId,
+    peer_id: &P,
+) {
+    if let Some(peer_id) = peer_id.to_aleph_peer_id() {
+        peer_identities.link(authority.into(), peer_id);
+    }
+}
+
 struct Session<D: Data, A: AddressingInformation> {
     handler: SessionHandler<A>,
     discovery: Discovery<A>,
@@ -83,6 +95,7 @@ pub struct Manager<NI: NetworkIdentity, D: Data> {
     connections: Connections<NI::PeerId>,
     sessions: HashMap<SessionId, Session<D, NI::AddressingInformation>>,
     discovery_cooldown: Duration,
+    peer_identities: PeerIdentities,
 }
 
 /// Error when trying to forward data from the network to the user, should never be fatal.
@@ -92,14 +105,22 @@ pub enum SendError {
     NoSession,
 }
 
-impl<NI: NetworkIdentity, D: Data> Manager<NI, D> {
+impl<NI: NetworkIdentity, D: Data> Manager<NI, D>
+where
+    NI::PeerId: ToAlephPeerId,
+{
     /// Create a new connection manager.
-    pub fn new(network_identity: NI, discovery_cooldown: Duration) -> Self {
+    pub fn new(
+        network_identity: NI,
+        discovery_cooldown: Duration,
+        peer_identities: PeerIdentities,
+    ) -> Self {
         Manager {
             network_identity,
             connections: Connections::new(),
             sessions: HashMap::new(),
             discovery_cooldown,
+            peer_identities,
         }
     }
 
@@ -185,6 +206,11 @@ impl<NI: NetworkIdentity, D: Data> Manager<NI, D> {
         SessionHandlerError,
     > {
         let address = self.network_identity.identity();
+        link_authority(
+            &self.peer_identities,
+            pre_session.pen.authority_id(),
+            &address.peer_id(),
+        );
         let session = match self.sessions.get_mut(&pre_session.session_id) {
             Some(session) => session,
             None => {
@@ -308,12 +334,19 @@ impl<NI: NetworkIdentity, D: Data> Manager<NI, D> {
         message: DiscoveryMessage<NI::AddressingInformation>,
     ) -> ManagerActions<NI::AddressingInformation> {
         let session_id = message.session_id();
+        let creator = message.0.creator();
         match self.sessions.get_mut(&session_id) {
             Some(Session {
                 handler, discovery, ..
             }) => {
                 let (maybe_address, maybe_message) =
                     discovery.handle_authentication(message, handler);
+                // The address is only returned if the authentication was signed by the creator.
+                if let (Some(address), Some(authority)) =
+                    (&maybe_address, handler.authority_id(creator))
+                {
+                    link_authority(&self.peer_identities, authority, &address.peer_id());
+                }
                 let maybe_command = match (maybe_address, handler.is_validator()) {
                     (Some(address), true) => {
                         debug!(target: "aleph-network", "Adding addresses for session {:?} to reserved: {:?}", session_id, address);
@@ -374,7 +407,11 @@ impl<NI: NetworkIdentity, D: Data> Manager<NI, D> {
                     let peer_ids = peers
                         .iter()
                         .map(|(node_id, peer_id)| {
-                            format!("{:?}: {}", node_id, peer_id.to_short_string())
+                            format!(
+                                "{:?}: {}",
+                                node_id,
+                                self.peer_identities.describe_peer(peer_id)
+                            )
                         })
                         .collect::<Vec<_>>()
                         .join(", ");
@@ -439,7 +476,7 @@ mod tests {
         SendError,
     };
     use crate::{
-        network::{mock::crypto_basics, session::data::DataInSession},
+        network::{mock::crypto_basics, session::data::DataInSession, PeerIdentities},
         Recipient, SessionId,
     };
 
@@ -447,7 +484,7 @@ mod tests {
     const DISCOVERY_PERIOD: Duration = Duration::from_secs(60);
 
     fn build() -> Manager<MockAddressingInformation, i32> {
-        Manager::new(random_address(), DISCOVERY_PERIOD)
+        Manager::new(random_address(), DISCOVERY_PERIOD, PeerIdentities::new())
     }
 
     #[test]
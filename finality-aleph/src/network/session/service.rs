@@ -24,7 +24,7 @@ use crate::{
             },
             Network, SessionHandlerError, SessionManager, SessionSender, VersionedAuthentication,
         },
-        AddressingInformation, Data, GossipNetwork, NetworkIdentity,
+        AddressingInformation, Data, GossipNetwork, NetworkIdentity, PeerIdentities, ToAlephPeerId,
     },
     MillisecsPerBlock, NodeIndex, SessionId, SessionPeriod, STATUS_REPORT_INTERVAL,
 };
@@ -216,13 +216,14 @@ impl<
         GN: GossipNetwork<VersionedAuthentication<NI::AddressingInformation>>,
     > Service<D, NI, CN, GN>
 where
-    NI::PeerId: PublicKey,
+    NI::PeerId: PublicKey + ToAlephPeerId,
 {
     pub fn new(
         network_identity: NI,
         validator_network: CN,
         gossip_network: GN,
         config: Config,
+        peer_identities: PeerIdentities,
     ) -> (
         Service<D, NI, CN, GN>,
         impl SessionManager<D, Error = ManagerError>,
@@ -232,7 +233,7 @@ where
             maintenance_period,
             initial_delay,
         } = config;
-        let manager = Manager::new(network_identity, discovery_cooldown);
+        let manager = Manager::new(network_identity, discovery_cooldown, peer_identities);
         let (commands_for_service, commands_from_user) = mpsc::unbounded();
         let (messages_for_service, messages_from_user) = mpsc::unbounded();
         (
@@ -1,12 +1,20 @@
-use std::{collections::HashMap, fmt, iter, pin::Pin, sync::Arc};
+use std::{collections::HashMap, fmt, iter, pin::Pin, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use futures::stream::{Stream, StreamExt};
-use log::{error, trace};
+use futures::{
+    channel::oneshot,
+    future::BoxFuture,
+    stream::{Stream, StreamExt},
+    FutureExt,
+};
+use log::{error, info, trace};
 use sc_network::{
-    multiaddr::Protocol as MultiaddressProtocol, Event as SubstrateEvent, Multiaddr,
-    NetworkEventStream as _, NetworkNotification, NetworkPeers, NetworkService,
-    NetworkSyncForkRequest, NotificationSenderT, PeerId, ProtocolName,
+    config::{IncomingRequest, OutgoingResponse, RequestResponseConfig},
+    multiaddr::Protocol as MultiaddressProtocol,
+    request_responses::{IfDisconnected, RequestFailure},
+    Event as SubstrateEvent, Multiaddr, NetworkEventStream as _, NetworkNotification, NetworkPeers,
+    NetworkRequest, NetworkService, NetworkSyncForkRequest, NotificationSenderT, PeerId,
+    ProtocolName, ReputationChange,
 };
 use sc_network_common::{
     sync::{SyncEvent, SyncEventStream},
@@ -19,8 +27,9 @@ use tokio::select;
 use crate::{
     aleph_primitives::{BlockHash, BlockNumber},
     network::{
-        gossip::{Event, EventStream, NetworkSender, Protocol, RawNetwork},
-        RequestBlocks,
+        gossip::{Event, EventStream, NetworkSender, Penalty, Protocol, RawNetwork},
+        request_response::{RawRequestResponse, RequestError, Responder},
+        PeerIdentities, RequestBlocks,
     },
     BlockId,
 };
@@ -50,34 +59,59 @@ const LEGACY_AUTHENTICATION_PROTOCOL_NAME: &str = "/aleph/1";
 /// Name of the network protocol used by Aleph Zero to synchronize the block state.
 const BLOCK_SYNC_PROTOCOL_NAME: &str = "/sync/0";
 
+/// Name of the request-response protocol used by Aleph Zero to exchange block sync requests and
+/// their responses.
+const BLOCK_SYNC_REQUESTS_PROTOCOL_NAME: &str = "/sync/requests/0";
+
+/// The largest block sync request we accept, they carry at most a single justification.
+const MAX_BLOCK_SYNC_REQUEST_SIZE: u64 = 1024 * 1024;
+
+/// How long we wait for the response to a block sync request.
+const BLOCK_SYNC_REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How many incoming block sync requests wait to be received at most, further ones are refused.
+const BLOCK_SYNC_REQUEST_QUEUE_SIZE: usize = 64;
+
 /// Convert protocols to their names and vice versa.
 #[derive(Clone)]
 pub struct ProtocolNaming {
     authentication_name: ProtocolName,
     authentication_fallback_names: Vec<ProtocolName>,
     block_sync_name: ProtocolName,
+    block_sync_requests_name: ProtocolName,
     protocols_by_name: HashMap<ProtocolName, Protocol>,
 }
 
 impl ProtocolNaming {
-    /// Create a new protocol naming scheme with the given chain prefix.
-    pub fn new(chain_prefix: String) -> Self {
+    /// Create a new protocol naming scheme for the chain with the given genesis hash, and the
+    /// fork of it, if provided. The legacy names do not tell forks apart, so they are only used
+    /// without a fork.
+    pub fn new(genesis_hash: String, fork_id: Option<&str>) -> Self {
+        let chain_prefix = match fork_id {
+            Some(fork_id) => format!("/{genesis_hash}/{fork_id}"),
+            None => format!("/{genesis_hash}"),
+        };
         let authentication_name: ProtocolName =
             format!("{chain_prefix}{AUTHENTICATION_PROTOCOL_NAME}").into();
         let mut protocols_by_name = HashMap::new();
         protocols_by_name.insert(authentication_name.clone(), Protocol::Authentication);
-        let authentication_fallback_names: Vec<ProtocolName> =
-            vec![LEGACY_AUTHENTICATION_PROTOCOL_NAME.into()];
+        let authentication_fallback_names: Vec<ProtocolName> = match fork_id {
+            Some(_) => Vec::new(),
+            None => vec![LEGACY_AUTHENTICATION_PROTOCOL_NAME.into()],
+        };
         for protocol_name in &authentication_fallback_names {
             protocols_by_name.insert(protocol_name.clone(), Protocol::Authentication);
         }
         let block_sync_name: ProtocolName =
             format!("{chain_prefix}{BLOCK_SYNC_PROTOCOL_NAME}").into();
         protocols_by_name.insert(block_sync_name.clone(), Protocol::BlockSync);
+        let block_sync_requests_name =
+            format!("{chain_prefix}{BLOCK_SYNC_REQUESTS_PROTOCOL_NAME}").into();
         ProtocolNaming {
             authentication_name,
             authentication_fallback_names,
             block_sync_name,
+            block_sync_requests_name,
             protocols_by_name,
         }
     }
@@ -100,6 +134,11 @@ impl ProtocolNaming {
         }
     }
 
+    /// Returns the name of the request-response protocol carrying block sync requests.
+    pub fn block_sync_requests_name(&self) -> ProtocolName {
+        self.block_sync_requests_name.clone()
+    }
+
     /// Attempts to convert the protocol name to a protocol.
     fn to_protocol(&self, protocol_name: &str) -> Option<Protocol> {
         self.protocols_by_name.get(protocol_name).copied()
@@ -254,6 +293,7 @@ pub struct SubstrateNetwork<B: Block, H: ExHashT> {
     network: Arc<NetworkService<B, H>>,
     sync_network: Arc<SyncingService<B>>,
     naming: ProtocolNaming,
+    peer_identities: PeerIdentities,
 }
 
 impl<B: Block, H: ExHashT> SubstrateNetwork<B, H> {
@@ -262,11 +302,13 @@ impl<B: Block, H: ExHashT> SubstrateNetwork<B, H> {
         network: Arc<NetworkService<B, H>>,
         sync_network: Arc<SyncingService<B>>,
         naming: ProtocolNaming,
+        peer_identities: PeerIdentities,
     ) -> Self {
         SubstrateNetwork {
             network,
             sync_network,
             naming,
+            peer_identities,
         }
     }
 }
@@ -305,4 +347,176 @@ impl<B: Block, H: ExHashT> RawNetwork for SubstrateNetwork<B, H> {
             peer_id,
         })
     }
+
+    fn penalize(&self, peer_id: Self::PeerId, protocol: Protocol, penalty: Penalty) {
+        info!(
+            target: "aleph-network",
+            "Applying {:?} to {} misbehaving in the {:?} protocol.",
+            penalty,
+            self.peer_identities.describe(&peer_id.into()),
+            protocol
+        );
+        if penalty == Penalty::Ban {
+            // The peer set bans peers with fatal reputation changes for a while.
+            self.network.report_peer(
+                peer_id,
+                ReputationChange::new_fatal("Misbehaving in an aleph protocol"),
+            );
+        }
+        self.network
+            .disconnect_peer(peer_id, self.naming.protocol_name(&protocol));
+    }
+}
+
+impl Responder for oneshot::Sender<OutgoingResponse> {
+    fn respond(self, response: Vec<u8>) {
+        // The requester might have stopped waiting already, nothing to do then.
+        let _ = self.send(OutgoingResponse {
+            result: Ok(response),
+            reputation_changes: Vec::new(),
+            sent_feedback: None,
+        });
+    }
+
+    fn is_canceled(&self) -> bool {
+        oneshot::Sender::is_canceled(self)
+    }
+}
+
+/// The incoming block sync requests, arriving through the protocol configured along with them.
+#[derive(Clone)]
+pub struct BlockSyncRequests {
+    requests: async_channel::Receiver<IncomingRequest>,
+    max_response_size: u64,
+}
+
+/// Returns the config of the request-response protocol carrying block sync requests and
+/// responses of at most the given size, with the requests arriving through it.
+pub fn block_sync_requests_config(
+    naming: &ProtocolNaming,
+    max_response_size: u64,
+) -> (RequestResponseConfig, BlockSyncRequests) {
+    let (sender, requests) = async_channel::bounded(BLOCK_SYNC_REQUEST_QUEUE_SIZE);
+    let config = RequestResponseConfig {
+        name: naming.block_sync_requests_name(),
+        fallback_names: Vec::new(),
+        max_request_size: MAX_BLOCK_SYNC_REQUEST_SIZE,
+        max_response_size,
+        request_timeout: BLOCK_SYNC_REQUEST_TIMEOUT,
+        inbound_queue: Some(sender),
+    };
+    let requests = BlockSyncRequests {
+        requests,
+        max_response_size,
+    };
+    (config, requests)
+}
+
+/// The substrate request-response protocol carrying block sync requests.
+pub struct SubstrateRequestResponse<B: Block, H: ExHashT> {
+    network: Arc<NetworkService<B, H>>,
+    name: ProtocolName,
+    requests: BlockSyncRequests,
+}
+
+impl<B: Block, H: ExHashT> SubstrateRequestResponse<B, H> {
+    /// Create a new wrapper around the substrate network, receiving the given requests.
+    pub fn new(
+        network: Arc<NetworkService<B, H>>,
+        naming: &ProtocolNaming,
+        requests: BlockSyncRequests,
+    ) -> Self {
+        SubstrateRequestResponse {
+            network,
+            name: naming.block_sync_requests_name(),
+            requests,
+        }
+    }
+}
+
+#[async_trait]
+impl<B: Block, H: ExHashT> RawRequestResponse for SubstrateRequestResponse<B, H> {
+    type Error = RequestFailure;
+    type PeerId = PeerId;
+    type Responder = oneshot::Sender<OutgoingResponse>;
+
+    fn max_request_size(&self) -> usize {
+        MAX_BLOCK_SYNC_REQUEST_SIZE as usize
+    }
+
+    fn max_response_size(&self) -> usize {
+        self.requests.max_response_size as usize
+    }
+
+    fn request(
+        &self,
+        peer_id: PeerId,
+        request: Vec<u8>,
+    ) -> BoxFuture<'static, Result<Vec<u8>, RequestError<RequestFailure>>> {
+        let (sender, receiver) = oneshot::channel();
+        self.network.start_request(
+            peer_id,
+            self.name.clone(),
+            request,
+            sender,
+            IfDisconnected::ImmediateError,
+        );
+        async move {
+            match receiver.await {
+                Ok(Ok(response)) => Ok(response),
+                // The peer got the request, but decided not to answer it.
+                Ok(Err(e @ (RequestFailure::Refused | RequestFailure::Obsolete))) => {
+                    Err(RequestError::Unanswered(e))
+                }
+                Ok(Err(e)) => Err(RequestError::Unreachable(e)),
+                Err(_) => Err(RequestError::Unreachable(RequestFailure::Obsolete)),
+            }
+        }
+        .boxed()
+    }
+
+    async fn next_request(&mut self) -> Option<(PeerId, Vec<u8>, Self::Responder)> {
+        self.requests.requests.recv().await.ok().map(
+            |IncomingRequest {
+                 peer,
+                 payload,
+                 pending_response,
+             }| (peer, payload, pending_response),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProtocolNaming, LEGACY_AUTHENTICATION_PROTOCOL_NAME};
+    use crate::network::gossip::Protocol;
+
+    #[test]
+    fn fork_ids_separate_protocol_names() {
+        let original = ProtocolNaming::new("0x01".to_string(), None);
+        let forked = ProtocolNaming::new("0x01".to_string(), Some("hard-forked"));
+        assert_eq!(
+            forked.protocol_name(&Protocol::BlockSync).as_ref(),
+            "/0x01/hard-forked/sync/0"
+        );
+        assert_ne!(
+            original.protocol_name(&Protocol::Authentication),
+            forked.protocol_name(&Protocol::Authentication)
+        );
+        assert_ne!(
+            original.block_sync_requests_name(),
+            forked.block_sync_requests_name()
+        );
+        assert_eq!(
+            original.to_protocol(LEGACY_AUTHENTICATION_PROTOCOL_NAME),
+            Some(Protocol::Authentication)
+        );
+        assert!(forked
+            .fallback_protocol_names(&Protocol::Authentication)
+            .is_empty());
+        assert_eq!(
+            forked.to_protocol(LEGACY_AUTHENTICATION_PROTOCOL_NAME),
+            None
+        );
+    }
 }
@@ -1,11 +1,18 @@
-use std::{io::Error as IoError, iter, net::ToSocketAddrs as _};
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    iter,
+    net::{IpAddr, SocketAddr, ToSocketAddrs as _},
+    sync::Arc,
+};
 
 use derive_more::{AsRef, Display};
 use log::info;
 use network_clique::{Dialer, Listener, PeerId, PublicKey, SecretKey};
 use parity_scale_codec::{Decode, Encode};
+use parking_lot::Mutex;
 use sp_core::crypto::KeyTypeId;
-use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, ToSocketAddrs};
 
 use crate::{
     aleph_primitives::AuthorityId,
@@ -132,8 +139,58 @@ impl SignedTcpAddressingInformation {
     }
 }
 
+/// The addresses starting with the one after the last one we connected to, which comes last. We
+/// only dial peers again if the connection with them broke, so the address we used is the most
+/// likely to be broken.
+fn rotated(mut addresses: Vec<SocketAddr>, last_connected: Option<SocketAddr>) -> Vec<SocketAddr> {
+    if let Some(position) = last_connected.and_then(|last_connected| {
+        addresses
+            .iter()
+            .position(|address| *address == last_connected)
+    }) {
+        addresses.rotate_left(position + 1);
+    }
+    addresses
+}
+
 #[derive(Clone)]
-struct TcpDialer;
+struct TcpDialer {
+    /// The local address to make the connections from, chosen by the system if `None`.
+    outgoing_address: Option<IpAddr>,
+    /// The addresses of the peers we connected to most recently.
+    last_connected: Arc<Mutex<HashMap<AuthorityId, SocketAddr>>>,
+}
+
+impl TcpDialer {
+    /// Connects from the given local address to the first of the addresses of the same family
+    /// that accepts the connection.
+    async fn connect_from(
+        outgoing_address: IpAddr,
+        addresses: &[SocketAddr],
+    ) -> Result<TcpStream, IoError> {
+        let mut last_error = None;
+        for address in addresses
+            .iter()
+            .filter(|address| address.is_ipv4() == outgoing_address.is_ipv4())
+        {
+            let socket = match outgoing_address {
+                IpAddr::V4(_) => TcpSocket::new_v4()?,
+                IpAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+            socket.bind(SocketAddr::new(outgoing_address, 0))?;
+            match socket.connect(*address).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            IoError::new(
+                IoErrorKind::AddrNotAvailable,
+                "no address of the same family as the outgoing address",
+            )
+        }))
+    }
+}
 
 #[async_trait::async_trait]
 impl Dialer<SignedTcpAddressingInformation> for TcpDialer {
@@ -151,17 +208,27 @@ impl Dialer<SignedTcpAddressingInformation> for TcpDialer {
         let TcpAddressingInformation {
             primary_address,
             other_addresses,
-            ..
+            peer_id,
         } = addressing_information;
         let parsed_addresses: Vec<_> = iter::once(primary_address)
             .chain(other_addresses)
             .filter_map(|address| address.to_socket_addrs().ok())
             .flatten()
             .collect();
-        let stream = TcpStream::connect(&parsed_addresses[..]).await?;
+        let last_connected = self.last_connected.lock().get(&peer_id).copied();
+        let parsed_addresses = rotated(parsed_addresses, last_connected);
+        let stream = match self.outgoing_address {
+            Some(outgoing_address) => {
+                Self::connect_from(outgoing_address, &parsed_addresses).await?
+            }
+            None => TcpStream::connect(&parsed_addresses[..]).await?,
+        };
         if stream.set_linger(None).is_err() {
             info!(target: LOG_TARGET, "stream.set_linger(None) failed.");
         };
+        if let Ok(address) = stream.peer_addr() {
+            self.last_connected.lock().insert(peer_id, address);
+        }
         Ok(stream)
     }
 }
@@ -186,9 +253,12 @@ impl From<AddressingInformationError> for Error {
 }
 
 /// Create a new tcp network, including an identity that can be used for constructing
-/// authentications for other peers.
+/// authentications for other peers. The connections to other peers are made from the outgoing
+/// address, if provided, so that together with the listening addresses all the traffic can be
+/// kept on a single interface.
 pub async fn new_tcp_network<A: ToSocketAddrs>(
     listening_addresses: A,
+    outgoing_address: Option<IpAddr>,
     external_addresses: Vec<String>,
     authority_pen: &AuthorityPen,
 ) -> Result<
@@ -204,7 +274,37 @@ pub async fn new_tcp_network<A: ToSocketAddrs>(
 > {
     let listener = TcpListener::bind(listening_addresses).await?;
     let identity = SignedTcpAddressingInformation::new(external_addresses, authority_pen)?;
-    Ok((TcpDialer {}, listener, identity))
+    Ok((
+        TcpDialer {
+            outgoing_address,
+            last_connected: Arc::new(Mutex::new(HashMap::new())),
+        },
+        listener,
+        identity,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::rotated;
+
+    #[test]
+    fn starts_dialing_after_the_last_connected_address() {
+        let addresses: Vec<SocketAddr> = ["10.0.0.1:30343", "10.0.0.2:30343", "10.0.0.3:30343"]
+            .iter()
+            .map(|address| address.parse().expect("the address is fine"))
+            .collect();
+        assert_eq!(rotated(addresses.clone(), None), addresses);
+        assert_eq!(
+            rotated(addresses.clone(), Some(addresses[0])),
+            vec![addresses[1], addresses[2], addresses[0]]
+        );
+        assert_eq!(rotated(addresses.clone(), Some(addresses[2])), addresses);
+        let unknown = "10.0.0.4:30343".parse().expect("the address is fine");
+        assert_eq!(rotated(addresses.clone(), Some(unknown)), addresses);
+    }
 }
 
 #[cfg(test)]
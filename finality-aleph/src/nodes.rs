@@ -1,35 +1,61 @@
-use std::{marker::PhantomData, sync::Arc};
+use std::{marker::PhantomData, path::PathBuf, sync::Arc};
 
 use bip39::{Language, Mnemonic, MnemonicType};
-use futures::channel::oneshot;
-use log::{debug, error};
+use futures::{
+    channel::{mpsc, oneshot},
+    FutureExt, StreamExt,
+};
+use log::{debug, error, info, warn};
 use network_clique::{RateLimitingDialer, RateLimitingListener, Service, SpawnHandleT};
 use rate_limiter::SleepingRateLimiter;
 use sc_client_api::Backend;
+use sc_network::NetworkStateInfo;
+use sp_api::{ApiExt, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
 use sp_consensus::SelectChain;
 use sp_keystore::Keystore;
 
 use crate::{
-    aleph_primitives::Block,
+    abft::AbftMetrics,
+    aleph_primitives::{AlephSessionApi, Block, BlockHash, SyncParams},
+    config::DEFAULT_SYNC_FINALIZATION_BATCH,
     crypto::AuthorityPen,
+    disk_quota::{run_disk_quota, DiskQuota},
     finalization::AlephFinalizer,
+    head_push::run_head_push,
     network::{
         session::{ConnectionManager, ConnectionManagerConfig},
-        tcp::{new_tcp_network, KEY_TYPE},
-        GossipService, SubstrateNetwork,
+        tcp::{new_tcp_network, AuthorityIdWrapper, KEY_TYPE},
+        AlephPeerId, GossipService, PeerIdentities, RequestResponseNetwork, SubstrateNetwork,
+        SubstrateRequestResponse,
     },
     party::{
         impls::ChainStateImpl, manager::NodeSessionManagerImpl, ConsensusParty,
         ConsensusPartyParams,
     },
-    session::SessionBoundaryInfo,
-    session_map::{AuthorityProviderImpl, FinalityNotifierImpl, SessionMapUpdater},
+    session::{SessionBoundaryInfo, SessionPeriod},
+    session_map::{
+        AuthorityProvider, AuthorityProviderImpl, FinalityNotifierImpl, SessionMapUpdater,
+    },
+    session_prefetch::{BestBlockNotifierImpl, PrefetchedAuthorities, SessionPrefetcher},
+    stall_watchdog::StallWatchdog,
+    status_page::{run_status_page, StatusSources},
     sync::{
-        ChainStatus, DatabaseIO as SyncDatabaseIO, FinalizationStatus, Justification,
-        JustificationTranslator, OldSyncCompatibleRequestBlocks, Service as SyncService,
-        SubstrateChainStatusNotifier, SubstrateFinalizationInfo, VerifierCache,
+        run_counter_persistence, run_snapshot_exporter,
+        substrate::{
+            run_justification_pruning, AuxCounterStorage, AuxForestCheckpointStorage,
+            BlockImporter, CustodyPolicy, EmergencyAudit, HttpsBlockFetcher,
+            JustificationArchiving, JustificationPruner, JustificationRetention,
+        },
+        BlockFetcher, Capabilities, Capturing, ChainStatus, DatabaseIO as SyncDatabaseIO,
+        FinalizationStatus, Justification, JustificationTranslator, LocalLimits,
+        OldSyncCompatibleRequestBlocks, Params as SyncServiceParams,
+        PriorityConfig as SyncPriorityConfig, PriorityTicket, ReplayError, Replayer,
+        RolesConfig as SyncRolesConfig, Service as SyncService, SubstrateChainStatus,
+        SubstrateChainStatusNotifier, SubstrateFinalizationInfo, ValidatorTicket, VerifierCache,
+        VirtualFinality,
     },
-    AlephConfig,
+    AlephConfig, BlockMetrics, SyncReplayReport,
 };
 
 // How many sessions we remember.
@@ -43,6 +69,85 @@ pub fn new_pen(mnemonic: &str, keystore: Arc<dyn Keystore>) -> AuthorityPen {
         .expect("we just generated this key so everything should work")
 }
 
+fn on_chain_sync_params<C, BE>(client: &C, hash: BlockHash) -> SyncParams
+where
+    C: crate::ClientForAleph<Block, BE>,
+    C::Api: AlephSessionApi<Block>,
+    BE: Backend<Block>,
+{
+    let runtime_api = client.runtime_api();
+    match runtime_api.api_version::<dyn AlephSessionApi<Block>>(hash) {
+        Ok(Some(version)) if version >= 2 => {
+            runtime_api.sync_params(hash).unwrap_or_else(|e| {
+                error!(target: "aleph-party", "Failed to read sync params from runtime: {}, using defaults.", e);
+                SyncParams::default()
+            })
+        }
+        _ => {
+            debug!(target: "aleph-party", "Runtime does not provide sync params, using defaults.");
+            SyncParams::default()
+        }
+    }
+}
+
+/// Reads the sync parameters again at every finalized block, so that the ones set on chain, or
+/// brought in by a runtime upgrade, get to the sync service while the node runs. Only actual
+/// changes are sent.
+async fn run_sync_params_updates<C, BE>(
+    client: Arc<C>,
+    limits: LocalLimits,
+    mut current: SyncServiceParams,
+    updates: mpsc::UnboundedSender<SyncServiceParams>,
+) where
+    C: crate::ClientForAleph<Block, BE>,
+    C::Api: AlephSessionApi<Block>,
+    BE: Backend<Block>,
+{
+    let mut finalized = client.finality_notification_stream();
+    while let Some(mut notification) = finalized.next().await {
+        // Only the newest finalized block is interesting.
+        while let Some(Some(newer)) = finalized.next().now_or_never() {
+            notification = newer;
+        }
+        let params =
+            SyncServiceParams::new(on_chain_sync_params(&*client, notification.hash), &limits);
+        if params == current {
+            continue;
+        }
+        if updates.unbounded_send(params).is_err() {
+            debug!(target: "aleph-party", "Sync service stopped, no longer following the sync params.");
+            return;
+        }
+        current = params;
+    }
+}
+
+/// The session boundaries following the changes of the session length known at the top finalized
+/// block, the later ones are learnt by the session map updater.
+pub fn on_chain_session_info<C, BE>(
+    client: Arc<C>,
+    session_period: SessionPeriod,
+) -> SessionBoundaryInfo
+where
+    C: crate::ClientForAleph<Block, BE> + Send + Sync + 'static,
+    C::Api: AlephSessionApi<Block>,
+    BE: Backend<Block> + 'static,
+{
+    let session_info = SessionBoundaryInfo::new(session_period);
+    let finalized = client.info().finalized_number;
+    match AuthorityProviderImpl::new(client).session_period_changes(finalized) {
+        Some(changes) => {
+            if let Err(e) = session_info.update_periods(&changes) {
+                error!(target: "aleph-party", "Invalid session length changes in runtime: {}, using a fixed session length.", e);
+            }
+        }
+        None => {
+            debug!(target: "aleph-party", "Runtime does not provide session length changes, using a fixed session length.");
+        }
+    }
+    session_info
+}
+
 pub async fn run_validator_node<C, BE, SC>(aleph_config: AlephConfig<C, SC>)
 where
     C: crate::ClientForAleph<Block, BE> + Send + Sync + 'static,
@@ -68,8 +173,17 @@ where
         backup_saving_path,
         external_addresses,
         validator_port,
+        validator_listen_address,
+        validator_outgoing_address,
         protocol_naming,
-        rate_limiter_config,
+        network_limits,
+        sync_config,
+        head_push,
+        disk_quota,
+        validator_network_health,
+        finality_stall_timeout,
+        stall_diagnostics,
+        status_page,
     } = aleph_config;
 
     // We generate the phrase manually to only save the key in RAM, we don't want to have these
@@ -80,18 +194,32 @@ where
         keystore.clone(),
     );
 
-    debug!(target: "aleph-party", "Initializing rate-limiter for the validator-network with {} byte(s) per second.", rate_limiter_config.alephbft_bit_rate_per_connection);
+    debug!(target: "aleph-party", "Initializing rate-limiter for the validator-network with {} byte(s) per second.", network_limits.validator.bit_rate_per_connection);
+    debug!(target: "aleph-party", "Public gossip limits: {:?}.", network_limits.public);
+
+    debug!(target: "aleph-party", "Validator network listening on {}:{}, connecting from {:?}.", validator_listen_address, validator_port, validator_outgoing_address);
 
     let (dialer, listener, network_identity) = new_tcp_network(
-        ("0.0.0.0", validator_port),
+        (validator_listen_address, validator_port),
+        validator_outgoing_address,
         external_addresses,
         &network_authority_pen,
     )
     .await
     .expect("we should have working networking");
 
+    // Our own identities are known from the start, others get linked as they authenticate.
+    let peer_identities = PeerIdentities::new();
+    peer_identities.link(
+        AlephPeerId::Validator(AuthorityIdWrapper::from(
+            network_authority_pen.authority_id(),
+        )),
+        AlephPeerId::Gossip(network.local_peer_id()),
+    );
+    let validator_ticket = ValidatorTicket::new(&network_authority_pen, &network.local_peer_id());
+
     let alephbft_rate_limiter =
-        SleepingRateLimiter::new(rate_limiter_config.alephbft_bit_rate_per_connection);
+        SleepingRateLimiter::new(network_limits.validator.bit_rate_per_connection);
     let dialer = RateLimitingDialer::new(dialer, alephbft_rate_limiter.clone());
     let listener = RateLimitingListener::new(listener, alephbft_rate_limiter);
 
@@ -101,6 +229,7 @@ where
         network_authority_pen,
         spawn_handle.clone(),
         registry.clone(),
+        validator_network_health,
     );
     let (_validator_network_exit, exit) = oneshot::channel();
     spawn_handle.spawn("aleph/validator_network", async move {
@@ -109,46 +238,168 @@ where
     });
 
     let (gossip_network_service, authentication_network, block_sync_network) = GossipService::new(
-        SubstrateNetwork::new(network.clone(), sync_network.clone(), protocol_naming),
+        SubstrateNetwork::new(
+            network.clone(),
+            sync_network.clone(),
+            protocol_naming.clone(),
+            peer_identities.clone(),
+        ),
         spawn_handle.clone(),
         registry.clone(),
+        backup_saving_path.clone(),
+        network_limits.public,
     );
     let gossip_network_task = async move { gossip_network_service.run().await };
 
     let block_requester = sync_network.clone();
 
+    let session_info = on_chain_session_info(client.clone(), session_period);
     let map_updater = SessionMapUpdater::new(
         AuthorityProviderImpl::new(client.clone()),
         FinalityNotifierImpl::new(client.clone()),
-        session_period,
+        session_info.clone(),
     );
     let session_authorities = map_updater.readonly_session_map();
+    let prefetched_authorities = PrefetchedAuthorities::new();
+    let session_prefetcher = SessionPrefetcher::new(
+        AuthorityProviderImpl::new(client.clone()),
+        BestBlockNotifierImpl::new(client.clone()),
+        session_info.clone(),
+        map_updater.shared_session_map(),
+        prefetched_authorities.clone(),
+    );
     spawn_handle.spawn("aleph/updater", async move {
         debug!(target: "aleph-party", "SessionMapUpdater has started.");
         map_updater.run().await
     });
+    spawn_handle.spawn("aleph/session_prefetch", async move {
+        debug!(target: "aleph-party", "SessionPrefetcher has started.");
+        session_prefetcher.run().await
+    });
 
-    let chain_events = SubstrateChainStatusNotifier::new(
+    let virtual_finality = match sync_config.light_sync {
+        true => VirtualFinality::headers_only(session_info.clone()),
+        false => VirtualFinality::new(sync_config.dry_run, session_info.clone()),
+    };
+    if virtual_finality.is_headers_only() {
+        info!(target: "aleph-party", "Block sync follows only the headers, finalized headers are only kept in memory.");
+    } else if virtual_finality.is_enabled() {
+        warn!(target: "aleph-party", "Block sync runs dry, justified blocks are only finalized in memory.");
+    }
+    let chain_events = virtual_finality.notifier(SubstrateChainStatusNotifier::new(
         client.finality_notification_stream(),
         client.every_import_notification_stream(),
-    );
+    ));
 
-    let session_info = SessionBoundaryInfo::new(session_period);
     let genesis_header = match chain_status.finalized_at(0) {
         Ok(FinalizationStatus::FinalizedWithJustification(justification)) => {
             justification.header().clone()
         }
         _ => panic!("the genesis block should be finalized"),
     };
+    if let Some(sampling) = &sync_config.verification_sampling {
+        warn!(
+            target: "aleph-party",
+            "Verifying only one in {} justifications up to the trusted checkpoint #{}, a hundred forged ones would go unnoticed with probability {:.2e}.",
+            sampling.one_in(),
+            sampling.checkpoint(),
+            sampling.undetected_probability(100)
+        );
+    }
     let verifier = VerifierCache::new(
         session_info.clone(),
-        SubstrateFinalizationInfo::new(client.clone()),
+        virtual_finality.finalization_info(SubstrateFinalizationInfo::new(client.clone())),
         AuthorityProviderImpl::new(client.clone()),
         VERIFIER_CACHE_SIZE,
         genesis_header,
+        sync_config.emergency_custody,
+        sync_config.emergency_audit,
+    )
+    .with_prefetched_authorities(prefetched_authorities)
+    .with_verification_sampling(sync_config.verification_sampling);
+    let mut aleph_finalizer = AlephFinalizer::new(client.clone(), metrics.clone());
+    let retention = sync_config.justification_retention;
+    if retention != JustificationRetention::KeepAll {
+        match JustificationPruner::new(client.clone(), retention, session_info.clone()) {
+            Ok(pruner) => {
+                aleph_finalizer = aleph_finalizer.with_justification_archiving(
+                    JustificationArchiving::new(retention, session_info.clone())
+                        .with_compression(sync_config.compress_justifications),
+                );
+                spawn_handle.spawn(
+                    "aleph/justification_pruning",
+                    run_justification_pruning(pruner),
+                );
+                debug!(target: "aleph-party", "Justification pruning with retention {:?} has started.", retention);
+            }
+            Err(e) => {
+                error!(target: "aleph-party", "Failed to start justification pruning, keeping all justifications: {}.", e);
+            }
+        }
+    }
+    let finalizer = virtual_finality.finalizer(aleph_finalizer, chain_status.clone());
+    let database_io = SyncDatabaseIO::new(
+        virtual_finality.chain_status(chain_status.clone()),
+        finalizer,
+        import_queue_handle,
     );
-    let finalizer = AlephFinalizer::new(client.clone(), metrics.clone());
-    let database_io = SyncDatabaseIO::new(chain_status.clone(), finalizer, import_queue_handle);
+    let capabilities = match sync_config.archive_bodies {
+        true => Capabilities::BLOCK_BODIES.union(Capabilities::BODY_RANGES),
+        false => Capabilities::NONE,
+    };
+    let priority_ticket = sync_config.priority_key_phrase.as_ref().map(|phrase| {
+        let pen = new_pen(phrase, keystore.clone());
+        info!(target: "aleph-party", "Presenting sync priority tickets signed with key {:?}.", pen.authority_id());
+        PriorityTicket::new(&pen, &network.local_peer_id())
+    });
+    let sync_priority = SyncPriorityConfig {
+        ticket: priority_ticket,
+        followers: sync_config.priority_followers,
+    };
+    let sync_roles = SyncRolesConfig {
+        ticket: Some(validator_ticket),
+        identities: peer_identities.clone(),
+    };
+    let sync_params = SyncServiceParams::new(
+        on_chain_sync_params(&*client, client.info().finalized_hash),
+        &sync_config.limits,
+    );
+    debug!(target: "aleph-party", "Running block sync with {:?}.", sync_params);
+    let (sync_params_for_service, sync_params_updates) = mpsc::unbounded();
+    spawn_handle.spawn(
+        "aleph/sync_params",
+        run_sync_params_updates(
+            client.clone(),
+            sync_config.limits.clone(),
+            sync_params,
+            sync_params_for_service,
+        ),
+    );
+    let block_sync_network = RequestResponseNetwork::new(
+        block_sync_network,
+        sync_config.requests.map(|requests| {
+            SubstrateRequestResponse::new(network.clone(), &protocol_naming, requests)
+        }),
+    );
+    if let Some(max_bytes) = disk_quota {
+        let mut quota = DiskQuota::new(max_bytes, registry.clone());
+        if let Some(capture) = &sync_config.capture {
+            quota = quota.with_captures(capture.directory.clone());
+        }
+        if let Some(path) = &backup_saving_path {
+            quota = quota.with_backups(path.clone());
+        }
+        spawn_handle.spawn("aleph/disk_quota", run_disk_quota(quota));
+        debug!(target: "aleph-party", "Disk quota enforcement has started.");
+    }
+    let block_sync_network = Capturing::new(block_sync_network, sync_config.capture);
+    let status_reports = sync_config.status_reports.clone();
+    let forest_dumps = sync_config.forest_dumps.clone();
+    let network_view = sync_config.network_view.clone();
+    let block_fetcher = sync_config.block_source.map(|source| {
+        info!(target: "aleph-party", "Fetching missing block bodies from {} rather than peers.", source);
+        Arc::new(HttpsBlockFetcher::new(source)) as Arc<dyn BlockFetcher<Block>>
+    });
     let (sync_service, justifications_for_sync, request_block) = match SyncService::new(
         block_sync_network,
         chain_events,
@@ -157,6 +408,35 @@ where
         session_info.clone(),
         justification_rx,
         registry.clone(),
+        capabilities,
+        sync_config.fork_id,
+        sync_config.provenance,
+        sync_config.peer_tracing,
+        sync_config.forest_dumps,
+        sync_config.status_reports,
+        sync_config.justification_fetches,
+        sync_config.body_backfill,
+        block_fetcher,
+        sync_config.import_notifications,
+        sync_config.finalization_hooks,
+        sync_config.chain_selection,
+        sync_config.snapshot_triggers.clone(),
+        sync_config.counters.clone(),
+        sync_config.network_view,
+        sync_config.legacy_cutoff,
+        sync_config.v1_shim,
+        sync_priority,
+        sync_roles,
+        sync_config.headers_first,
+        sync_config.warp_sync,
+        sync_config.parallel_download,
+        sync_config.session_pipeline,
+        sync_config.light_sync,
+        sync_config.finalization_batch,
+        sync_params,
+        sync_params_updates,
+        backup_saving_path.clone(),
+        AuxForestCheckpointStorage::new(client.clone()),
     ) {
         Ok(x) => x,
         Err(e) => panic!("Failed to initialize Sync service: {e}"),
@@ -168,6 +448,7 @@ where
         validator_network,
         authentication_network,
         ConnectionManagerConfig::with_session_period(&session_period, &millisecs_per_block),
+        peer_identities,
     );
 
     let connection_manager_task = async move {
@@ -179,6 +460,27 @@ where
     spawn_handle.spawn("aleph/sync", sync_task);
     debug!(target: "aleph-party", "Sync has started.");
 
+    let counter_persistence =
+        run_counter_persistence(sync_config.counters, AuxCounterStorage::new(client.clone()));
+    spawn_handle.spawn("aleph/sync_counters", counter_persistence);
+
+    if let Some(path) = sync_config.snapshot_export_path {
+        let exporter = run_snapshot_exporter(sync_config.snapshot_triggers.subscribe(), path);
+        spawn_handle.spawn("aleph/sync_snapshot_exporter", exporter);
+        debug!(target: "aleph-party", "Sync snapshot exporter has started.");
+    }
+
+    if let Some(endpoint) = head_push {
+        let pusher = run_head_push(
+            client.clone(),
+            keystore.clone(),
+            session_info.clone(),
+            endpoint,
+        );
+        spawn_handle.spawn("aleph/head_push", pusher);
+        debug!(target: "aleph-party", "Head push has started.");
+    }
+
     spawn_handle.spawn("aleph/connection_manager", connection_manager_task);
     spawn_handle.spawn("aleph/gossip_network", gossip_network_task);
     debug!(target: "aleph-party", "Gossip network has started.");
@@ -186,6 +488,33 @@ where
     let compatible_block_request =
         OldSyncCompatibleRequestBlocks::new(block_requester.clone(), request_block);
 
+    let abft_metrics = AbftMetrics::new(registry).unwrap_or_else(|e| {
+        warn!(target: "aleph-party", "Failed to register AlephBFT metrics: {}.", e);
+        AbftMetrics::noop()
+    });
+    if let Some(address) = status_page {
+        let sources = StatusSources {
+            status_reports: status_reports.clone(),
+            forest_dumps: forest_dumps.clone(),
+            network_view: network_view.clone(),
+        };
+        spawn_handle.spawn("aleph/status_page", run_status_page(address, sources));
+        debug!(target: "aleph-party", "Status page has started.");
+    }
+    if let Some(stall_timeout) = finality_stall_timeout {
+        let watchdog = StallWatchdog::new(
+            FinalityNotifierImpl::new(client.clone()),
+            stall_timeout,
+            session_info.clone(),
+            status_reports,
+            forest_dumps,
+            network_view,
+            abft_metrics.clone(),
+            stall_diagnostics,
+        );
+        spawn_handle.spawn("aleph/stall_watchdog", watchdog.run());
+        debug!(target: "aleph-party", "Finality stall watchdog has started.");
+    }
     let party = ConsensusParty::new(ConsensusPartyParams {
         session_authorities,
         sync_state: block_requester,
@@ -197,12 +526,13 @@ where
         session_manager: NodeSessionManagerImpl::new(
             client,
             select_chain,
-            session_period,
+            session_info.clone(),
             unit_creation_delay,
             justifications_for_sync,
             JustificationTranslator::new(chain_status.clone()),
             compatible_block_request,
             metrics,
+            abft_metrics,
             spawn_handle,
             connection_manager,
             keystore,
@@ -214,3 +544,63 @@ where
     party.run().await;
     error!(target: "aleph-party", "Consensus party has finished unexpectedly.");
 }
+
+/// Replays the sync traffic captured in the files against the chain in the database, comparing
+/// the responses with the ones sent by the capturing node. The database should be a copy of the
+/// one of the capturing node from before the capture, the replay imports and finalizes blocks.
+pub async fn replay_sync_capture<C, BE>(
+    client: Arc<C>,
+    chain_status: SubstrateChainStatus,
+    import_queue_handle: BlockImporter,
+    session_info: SessionBoundaryInfo,
+    files: &[PathBuf],
+) -> Result<SyncReplayReport, ReplayError>
+where
+    C: crate::ClientForAleph<Block, BE> + Send + Sync + 'static,
+    C::Api: AlephSessionApi<Block>,
+    BE: Backend<Block> + 'static,
+{
+    let genesis_header = match chain_status.finalized_at(0) {
+        Ok(FinalizationStatus::FinalizedWithJustification(justification)) => {
+            justification.header().clone()
+        }
+        _ => {
+            return Err(ReplayError::Initialization(
+                "the genesis block is not finalized".to_string(),
+            ))
+        }
+    };
+    let verifier = VerifierCache::new(
+        session_info.clone(),
+        SubstrateFinalizationInfo::new(client.clone()),
+        AuthorityProviderImpl::new(client.clone()),
+        VERIFIER_CACHE_SIZE,
+        genesis_header,
+        CustodyPolicy::default(),
+        EmergencyAudit::new(),
+    );
+    let database_io = SyncDatabaseIO::new(
+        chain_status,
+        AlephFinalizer::new(client.clone(), BlockMetrics::noop()),
+        import_queue_handle,
+    );
+    let chain_events = SubstrateChainStatusNotifier::new(
+        client.finality_notification_stream(),
+        client.every_import_notification_stream(),
+    );
+    let sync_params = SyncServiceParams::new(
+        on_chain_sync_params(&*client, client.info().finalized_hash),
+        &LocalLimits::default(),
+    );
+    let mut replayer = Replayer::new(
+        database_io,
+        chain_events,
+        verifier,
+        session_info,
+        sync_params.serving_window,
+        DEFAULT_SYNC_FINALIZATION_BATCH,
+        sync_params.max_response_blocks,
+    )?;
+    replayer.replay_files(files).await?;
+    Ok(replayer.finish())
+}
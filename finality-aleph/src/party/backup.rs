@@ -1,6 +1,6 @@
 use std::{
     fmt, fs,
-    fs::File,
+    fs::{File, OpenOptions},
     io,
     io::{Cursor, Read, Write},
     path::{Path, PathBuf},
@@ -8,12 +8,21 @@ use std::{
 };
 
 use log::{debug, warn};
+use sp_core::hashing::twox_64;
 
 const BACKUP_FILE_EXTENSION: &str = ".abfts";
+/// Every backup file starts with this, followed by the format version. Files without it were
+/// written before the items were framed, they are loaded as they are, without any checks.
+const MAGIC: [u8; 8] = *b"ALEPHBAK";
+const FORMAT_VERSION: u16 = 1;
+const HEADER_BYTES: usize = MAGIC.len() + 2;
+/// Every record starts with the length and the checksum of the items in it.
+const RECORD_HEADER_BYTES: usize = 4 + 8;
 
 #[derive(Debug)]
 pub enum BackupLoadError {
     BackupIncomplete(Vec<usize>),
+    UnsupportedFormat(u16),
     IOError(io::Error),
 }
 
@@ -26,6 +35,9 @@ impl fmt::Display for BackupLoadError {
                     "Backup is not complete. Got backup for runs numbered: {backups:?}"
                 )
             }
+            BackupLoadError::UnsupportedFormat(version) => {
+                write!(f, "Backup has unsupported format version {version}")
+            }
             BackupLoadError::IOError(err) => {
                 write!(f, "Backup could not be loaded because of IO error: {err}")
             }
@@ -45,6 +57,186 @@ pub type Saver = Box<dyn Write + Send + Sync>;
 pub type Loader = Box<dyn Read + Send + Sync>;
 pub type ABFTBackup = (Saver, Loader);
 
+/// What was wrong with the part of a backup file that got cut off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackupCorruption {
+    /// The file ends in the middle of its header.
+    TruncatedHeader,
+    /// The file ends in the middle of a record.
+    TruncatedRecord,
+    /// The items of a record do not match its checksum.
+    BadChecksum,
+}
+
+impl fmt::Display for BackupCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use BackupCorruption::*;
+        match self {
+            TruncatedHeader => write!(f, "truncated header"),
+            TruncatedRecord => write!(f, "truncated record"),
+            BadChecksum => write!(f, "checksum mismatch"),
+        }
+    }
+}
+
+/// The first corrupted record of a backup, it and everything after it is dropped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorruptionPoint {
+    pub file: PathBuf,
+    pub offset: u64,
+    pub corruption: BackupCorruption,
+    /// How many bytes are dropped, in this file and the following ones.
+    pub dropped_bytes: u64,
+}
+
+/// What was recovered from the backup of a session.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BackupReport {
+    pub files: usize,
+    /// Files written before the items were framed, which cannot be checked.
+    pub unframed_files: usize,
+    pub records: usize,
+    /// The size of the recovered items.
+    pub bytes: u64,
+    pub corruption: Option<CorruptionPoint>,
+}
+
+impl fmt::Display for BackupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "recovered {} records of {} bytes from {} files",
+            self.records, self.bytes, self.files
+        )?;
+        if self.unframed_files > 0 {
+            write!(f, ", {} of them unchecked", self.unframed_files)?;
+        }
+        if let Some(point) = &self.corruption {
+            write!(
+                f,
+                ", {} at byte {} of {:?}, dropped {} bytes from there on",
+                point.corruption, point.offset, point.file, point.dropped_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes everything AlephBFT saves between flushes as a single record, preceded by its length
+/// and checksum, so that a record torn by a crash or corrupted on disk can be recognized.
+struct FramingSaver {
+    file: File,
+    items: Vec<u8>,
+}
+
+impl FramingSaver {
+    fn create(path: &Path) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        file.write_all(&header)?;
+        Ok(FramingSaver {
+            file,
+            items: Vec::new(),
+        })
+    }
+}
+
+impl Write for FramingSaver {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.items.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.items.is_empty() {
+            return Ok(());
+        }
+        let length: u32 = self
+            .items
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "record too long"))?;
+        let mut record = Vec::with_capacity(RECORD_HEADER_BYTES + self.items.len());
+        record.extend_from_slice(&length.to_le_bytes());
+        record.extend_from_slice(&twox_64(&self.items));
+        record.append(&mut self.items);
+        // A single write, so that a crash can only tear the last record.
+        self.file.write_all(&record)?;
+        self.file.flush()
+    }
+}
+
+/// The items read from a single backup file.
+struct ParsedFile {
+    items: Vec<u8>,
+    records: usize,
+    framed: bool,
+    /// Where the correct part of the file ends and what is wrong after it, if anything.
+    corruption: Option<(usize, BackupCorruption)>,
+}
+
+fn parse_file(contents: Vec<u8>) -> Result<ParsedFile, BackupLoadError> {
+    let torn_header = !contents.is_empty()
+        && contents.len() < HEADER_BYTES
+        && MAGIC.starts_with(&contents[..contents.len().min(MAGIC.len())]);
+    if torn_header {
+        return Ok(ParsedFile {
+            items: Vec::new(),
+            records: 0,
+            framed: true,
+            corruption: Some((0, BackupCorruption::TruncatedHeader)),
+        });
+    }
+    if !contents.starts_with(&MAGIC) {
+        return Ok(ParsedFile {
+            items: contents,
+            records: 0,
+            framed: false,
+            corruption: None,
+        });
+    }
+    let version = u16::from_le_bytes([contents[MAGIC.len()], contents[MAGIC.len() + 1]]);
+    if version != FORMAT_VERSION {
+        return Err(BackupLoadError::UnsupportedFormat(version));
+    }
+    let mut parsed = ParsedFile {
+        items: Vec::new(),
+        records: 0,
+        framed: true,
+        corruption: None,
+    };
+    let mut offset = HEADER_BYTES;
+    while offset < contents.len() {
+        let rest = &contents[offset..];
+        if rest.len() < RECORD_HEADER_BYTES {
+            parsed.corruption = Some((offset, BackupCorruption::TruncatedRecord));
+            break;
+        }
+        let length = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let items = match rest.get(RECORD_HEADER_BYTES..RECORD_HEADER_BYTES.saturating_add(length))
+        {
+            Some(items) => items,
+            None => {
+                parsed.corruption = Some((offset, BackupCorruption::TruncatedRecord));
+                break;
+            }
+        };
+        if twox_64(items)[..] != rest[4..RECORD_HEADER_BYTES] {
+            parsed.corruption = Some((offset, BackupCorruption::BadChecksum));
+            break;
+        }
+        parsed.items.extend_from_slice(items);
+        parsed.records += 1;
+        offset += RECORD_HEADER_BYTES + length;
+    }
+    Ok(parsed)
+}
+
+fn truncate(path: &Path, length: u64) -> io::Result<()> {
+    OpenOptions::new().write(true).open(path)?.set_len(length)
+}
+
 /// Find all `*.abfts` files at `session_path` and return their indexes sorted, if all are present.
 fn get_session_backup_idxs(session_path: &Path) -> Result<Vec<usize>, BackupLoadError> {
     fs::create_dir_all(session_path)?;
@@ -60,14 +252,51 @@ fn get_session_backup_idxs(session_path: &Path) -> Result<Vec<usize>, BackupLoad
     Ok(session_backups)
 }
 
-/// Load session backup at path `session_path` from all `session_idxs`.
-fn load_backup(session_path: &Path, session_idxs: &[usize]) -> Result<Loader, BackupLoadError> {
+/// Load session backup at path `session_path` from all `session_idxs`, checking the records of
+/// every file. Everything from the first corrupted record on is dropped, as AlephBFT cannot use
+/// the items after a missing one anyway. With `repair` the files are also cut short accordingly,
+/// so that the new ones continue from the recovered items.
+fn load_backup(
+    session_path: &Path,
+    session_idxs: &[usize],
+    repair: bool,
+) -> Result<(Vec<u8>, BackupReport), BackupLoadError> {
     let mut buffer = Vec::new();
+    let mut report = BackupReport::default();
     for index in session_idxs.iter() {
         let load_path = session_path.join(format!("{index}{BACKUP_FILE_EXTENSION}"));
-        File::open(load_path)?.read_to_end(&mut buffer)?;
+        let mut contents = Vec::new();
+        File::open(&load_path)?.read_to_end(&mut contents)?;
+        let file_bytes = contents.len() as u64;
+        report.files += 1;
+        if let Some(point) = &mut report.corruption {
+            point.dropped_bytes += file_bytes;
+            if repair {
+                truncate(&load_path, 0)?;
+            }
+            continue;
+        }
+        let parsed = parse_file(contents)?;
+        if !parsed.framed {
+            report.unframed_files += 1;
+        }
+        report.records += parsed.records;
+        report.bytes += parsed.items.len() as u64;
+        buffer.extend(parsed.items);
+        if let Some((offset, corruption)) = parsed.corruption {
+            let offset = offset as u64;
+            if repair {
+                truncate(&load_path, offset)?;
+            }
+            report.corruption = Some(CorruptionPoint {
+                file: load_path,
+                offset,
+                corruption,
+                dropped_bytes: file_bytes - offset,
+            });
+        }
     }
-    Ok(Box::new(Cursor::new(buffer)))
+    Ok((buffer, report))
 }
 
 /// Get path of next backup file in session.
@@ -108,16 +337,53 @@ pub fn rotate(
 
     let session_backup_idxs = get_session_backup_idxs(&session_path)?;
 
-    let backup_loader = load_backup(&session_path, &session_backup_idxs)?;
+    let (items, report) = load_backup(&session_path, &session_backup_idxs, true)?;
+    match report.corruption {
+        Some(_) => {
+            warn!(target: "aleph-party", "Repaired corrupted backup for session {:?}: {}", session_id, report)
+        }
+        None => {
+            debug!(target: "aleph-party", "Checked backup for session {:?}: {}", session_id, report)
+        }
+    }
+    let backup_loader = Box::new(Cursor::new(items));
 
     let next_backup_path = get_next_path(&session_path, &session_backup_idxs);
     debug!(target: "aleph-party", "Loaded backup for session {:?}. Creating new backup file at {:?}", session_id, next_backup_path);
-    let backup_saver = Box::new(File::create(next_backup_path)?);
+    let backup_saver = Box::new(FramingSaver::create(&next_backup_path)?);
 
     debug!(target: "aleph-party", "Backup rotation done for session {:?}", session_id);
     Ok((backup_saver, backup_loader))
 }
 
+/// Checks the backups of all the sessions in the backup directory, without running the node.
+///
+/// `backup_path` is the path to the backup directory (i.e. the argument to `--backup-saving-path`).
+/// With `repair` the corrupted files are cut short, exactly as they would be on startup.
+///
+/// Returns what was found for every session, in the order of the sessions.
+pub fn check(
+    backup_path: &Path,
+    repair: bool,
+) -> io::Result<Vec<(u32, Result<BackupReport, BackupLoadError>)>> {
+    let mut sessions: Vec<_> = fs::read_dir(backup_path)?
+        .filter_map(|r| r.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| u32::from_str(entry.file_name().to_str()?).ok())
+        .collect();
+    sessions.sort_unstable();
+    Ok(sessions
+        .into_iter()
+        .map(|session_id| {
+            let session_path = backup_path.join(session_id.to_string());
+            let report = get_session_backup_idxs(&session_path).and_then(|session_idxs| {
+                load_backup(&session_path, &session_idxs, repair).map(|(_, report)| report)
+            });
+            (session_id, report)
+        })
+        .collect())
+}
+
 /// Removes the backup directory for a session.
 ///
 /// `backup_path` is the path to the backup directory (i.e. the argument to `--backup-saving-path`).
@@ -142,3 +408,114 @@ pub fn remove(path: Option<PathBuf>, session_id: u32) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        path::{Path, PathBuf},
+    };
+
+    use super::{check, rotate, BackupCorruption, HEADER_BYTES, RECORD_HEADER_BYTES};
+    use crate::testing::TestDirectory;
+
+    const SESSION: u32 = 7;
+
+    fn save(directory: &Path, items: &[&[u8]]) -> Vec<u8> {
+        let (mut saver, mut loader) =
+            rotate(Some(directory.to_path_buf()), SESSION).expect("backup rotates");
+        for item in items {
+            saver.write_all(item).expect("saving works");
+            saver.flush().expect("flushing works");
+        }
+        let mut loaded = Vec::new();
+        loader.read_to_end(&mut loaded).expect("loading works");
+        loaded
+    }
+
+    fn file(directory: &Path, index: usize) -> PathBuf {
+        directory.join(format!("{SESSION}/{index}.abfts"))
+    }
+
+    #[test]
+    fn loads_saved_items() {
+        let directory = TestDirectory::new("backup");
+        assert!(save(&directory, &[b"first", b"second"]).is_empty());
+        assert_eq!(save(&directory, &[b"third"]), b"firstsecond".to_vec());
+        assert_eq!(save(&directory, &[]), b"firstsecondthird".to_vec());
+    }
+
+    #[test]
+    fn loads_unframed_files() {
+        let directory = TestDirectory::new("backup");
+        fs::create_dir_all(directory.join(SESSION.to_string())).expect("directory is created");
+        fs::write(file(&directory, 0), b"legacy").expect("file is written");
+        assert_eq!(save(&directory, &[b"framed"]), b"legacy".to_vec());
+        assert_eq!(save(&directory, &[]), b"legacyframed".to_vec());
+    }
+
+    #[test]
+    fn repairs_torn_records() {
+        let directory = TestDirectory::new("backup");
+        save(&directory, &[b"first", b"second"]);
+        save(&directory, &[b"third"]);
+        let torn = file(&directory, 0);
+        let length = fs::metadata(&torn).expect("file exists").len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&torn)
+            .and_then(|file| file.set_len(length - 2))
+            .expect("file is truncated");
+
+        let reports = check(&directory, false).expect("directory exists");
+        assert_eq!(reports.len(), 1);
+        let (session, report) = &reports[0];
+        assert_eq!(*session, SESSION);
+        let report = report.as_ref().expect("backup is complete");
+        assert_eq!(report.records, 1);
+        let point = report.corruption.as_ref().expect("corruption is found");
+        assert_eq!(point.file, torn);
+        assert_eq!(point.corruption, BackupCorruption::TruncatedRecord);
+        assert_eq!(
+            point.offset,
+            (HEADER_BYTES + RECORD_HEADER_BYTES + 5) as u64
+        );
+
+        assert_eq!(save(&directory, &[b"fourth"]), b"first".to_vec());
+        assert_eq!(
+            fs::metadata(&torn).expect("file exists").len(),
+            point.offset
+        );
+        assert_eq!(
+            fs::metadata(file(&directory, 1))
+                .expect("file exists")
+                .len(),
+            0
+        );
+        assert_eq!(save(&directory, &[]), b"firstfourth".to_vec());
+    }
+
+    #[test]
+    fn detects_corrupted_records() {
+        let directory = TestDirectory::new("backup");
+        save(&directory, &[b"first", b"second"]);
+        let corrupted = file(&directory, 0);
+        let mut contents = fs::read(&corrupted).expect("file exists");
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        fs::write(&corrupted, contents).expect("file is written");
+
+        let reports = check(&directory, false).expect("directory exists");
+        let point = reports[0]
+            .1
+            .as_ref()
+            .expect("backup is complete")
+            .corruption
+            .clone()
+            .expect("corruption is found");
+        assert_eq!(point.corruption, BackupCorruption::BadChecksum);
+        assert_eq!(point.dropped_bytes, (RECORD_HEADER_BYTES + 6) as u64);
+        assert_eq!(save(&directory, &[]), b"first".to_vec());
+    }
+}
@@ -66,7 +66,7 @@ where
     let number = client.number(hash).unwrap().unwrap();
     // The unwrap might actually fail if data availability is not implemented correctly.
     let justification = match justification_translator.translate(
-        AlephJustification::CommitteeMultisignature(multisignature),
+        AlephJustification::CommitteeMultisignature(multisignature.0),
         BlockId::new(hash, number),
     ) {
         Ok(justification) => justification,
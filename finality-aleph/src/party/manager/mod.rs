@@ -13,11 +13,14 @@ use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
 use crate::{
     abft::{
         current_create_aleph_config, legacy_create_aleph_config, run_current_member,
-        run_legacy_member, SpawnHandle,
+        run_legacy_member, AbftMetrics, SpawnHandle,
     },
     aleph_primitives::{AlephSessionApi, BlockHash, BlockNumber, KEY_TYPE},
     crypto::{AuthorityPen, AuthorityVerifier},
-    data_io::{ChainTracker, DataStore, OrderedDataInterpreter, SubstrateChainInfoProvider},
+    data_io::{
+        ChainTracker, ChainTrackerConfig, DataStore, DataStoreConfig, FinalizationDepthOffset,
+        OrderedDataInterpreter, SubstrateChainInfoProvider,
+    },
     mpsc,
     network::{
         data::{
@@ -31,7 +34,7 @@ use crate::{
     },
     sync::{substrate::Justification, JustificationSubmissions, JustificationTranslator},
     AuthorityId, BlockId, BlockMetrics, CurrentRmcNetworkData, Keychain, LegacyRmcNetworkData,
-    NodeIndex, SessionBoundaries, SessionBoundaryInfo, SessionId, SessionPeriod, UnitCreationDelay,
+    NodeIndex, SessionBoundaries, SessionBoundaryInfo, SessionId, UnitCreationDelay,
     VersionedNetworkData,
 };
 
@@ -79,6 +82,7 @@ where
     session_id: SessionId,
     data_network: N,
     session_boundaries: SessionBoundaries,
+    finalization_depth_offset: FinalizationDepthOffset,
     subtask_common: SubtaskCommon,
     data_provider: DataProvider,
     ordered_data_interpreter: OrderedDataInterpreter<SubstrateChainInfoProvider<B, C>>,
@@ -109,6 +113,7 @@ where
     justification_translator: JustificationTranslator,
     block_requester: RB,
     metrics: BlockMetrics,
+    abft_metrics: AbftMetrics,
     spawn_handle: SpawnHandle,
     session_manager: SM,
     keystore: Arc<dyn Keystore>,
@@ -131,12 +136,13 @@ where
     pub fn new(
         client: Arc<C>,
         select_chain: SC,
-        session_period: SessionPeriod,
+        session_info: SessionBoundaryInfo,
         unit_creation_delay: UnitCreationDelay,
         justifications_for_sync: JS,
         justification_translator: JustificationTranslator,
         block_requester: RB,
         metrics: BlockMetrics,
+        abft_metrics: AbftMetrics,
         spawn_handle: SpawnHandle,
         session_manager: SM,
         keystore: Arc<dyn Keystore>,
@@ -144,12 +150,13 @@ where
         Self {
             client,
             select_chain,
-            session_info: SessionBoundaryInfo::new(session_period),
+            session_info,
             unit_creation_delay,
             justifications_for_sync,
             justification_translator,
             block_requester,
             metrics,
+            abft_metrics,
             spawn_handle,
             session_manager,
             keystore,
@@ -167,6 +174,7 @@ where
             session_id,
             data_network,
             session_boundaries,
+            finalization_depth_offset,
             subtask_common,
             data_provider,
             ordered_data_interpreter,
@@ -187,8 +195,12 @@ where
             session_boundaries.clone(),
             self.client.clone(),
             self.block_requester.clone(),
-            Default::default(),
+            DataStoreConfig {
+                finalization_depth_offset,
+                ..Default::default()
+            },
             unfiltered_aleph_network,
+            self.abft_metrics.clone(),
         );
         Subtasks::new(
             exit_rx,
@@ -200,6 +212,7 @@ where
                 data_provider,
                 ordered_data_interpreter,
                 backup,
+                self.abft_metrics.clone(),
             ),
             aggregator::task(
                 subtask_common.clone(),
@@ -225,6 +238,7 @@ where
             session_id,
             data_network,
             session_boundaries,
+            finalization_depth_offset,
             subtask_common,
             data_provider,
             ordered_data_interpreter,
@@ -245,8 +259,12 @@ where
             session_boundaries.clone(),
             self.client.clone(),
             self.block_requester.clone(),
-            Default::default(),
+            DataStoreConfig {
+                finalization_depth_offset,
+                ..Default::default()
+            },
             unfiltered_aleph_network,
+            self.abft_metrics.clone(),
         );
         Subtasks::new(
             exit_rx,
@@ -258,6 +276,7 @@ where
                 data_provider,
                 ordered_data_interpreter,
                 backup,
+                self.abft_metrics.clone(),
             ),
             aggregator::task(
                 subtask_common.clone(),
@@ -293,11 +312,23 @@ where
         let session_boundaries = self.session_info.boundaries_for_session(session_id);
         let (blocks_for_aggregator, blocks_from_interpreter) = mpsc::unbounded();
 
+        let last_block_of_previous_session = session_boundaries.first_block().saturating_sub(1);
+        let last_block_of_previous_session_hash = self
+            .client
+            .block_hash(last_block_of_previous_session)
+            .expect("Previous session ended, the block should be present")
+            .expect("Previous session ended, we should have the hash.");
+        let finalization_depth_offset =
+            self.finalization_depth_offset(last_block_of_previous_session_hash);
+
         let (chain_tracker, data_provider) = ChainTracker::new(
             self.select_chain.clone(),
             self.client.clone(),
             session_boundaries.clone(),
-            Default::default(),
+            ChainTrackerConfig {
+                finalization_depth_offset,
+                ..Default::default()
+            },
             self.metrics.clone(),
         );
 
@@ -305,6 +336,7 @@ where
             blocks_for_aggregator,
             SubstrateChainInfoProvider::new(self.client.clone()),
             session_boundaries.clone(),
+            finalization_depth_offset,
         );
 
         let subtask_common = SubtaskCommon {
@@ -326,19 +358,13 @@ where
             Err(e) => panic!("Failed to start validator session: {e}"),
         };
 
-        let last_block_of_previous_session = session_boundaries.first_block().saturating_sub(1);
-        let last_block_of_previous_session_hash = self
-            .client
-            .block_hash(last_block_of_previous_session)
-            .expect("Previous session ended, the block should be present")
-            .expect("Previous session ended, we should have the hash.");
-
         let params = SubtasksParams {
             n_members: authorities.len(),
             node_id,
             session_id,
             data_network,
             session_boundaries,
+            finalization_depth_offset,
             subtask_common,
             data_provider,
             ordered_data_interpreter,
@@ -387,6 +413,29 @@ where
         }
     }
 
+    // The offset is read from the state at the end of the previous session, which all the members
+    // of the committee agree on, so that they justify the same blocks. Runtimes not reporting it
+    // finalize without delay.
+    fn finalization_depth_offset(
+        &self,
+        last_block_of_previous_session: BlockHash,
+    ) -> FinalizationDepthOffset {
+        match self
+            .client
+            .runtime_api()
+            .next_session_finalization_depth_offset(last_block_of_previous_session)
+        {
+            Ok(offset) => {
+                let finalization_depth_offset = FinalizationDepthOffset::new(offset);
+                if finalization_depth_offset.blocks() != offset {
+                    warn!(target: "aleph-party", "Finalization depth offset {} is too large, using {} instead.", offset, finalization_depth_offset.blocks());
+                }
+                finalization_depth_offset
+            }
+            Err(_) => FinalizationDepthOffset::default(),
+        }
+    }
+
     #[cfg(feature = "only_legacy")]
     fn only_legacy(&self) -> bool {
         std::env::var(ONLY_LEGACY_ENV)
@@ -1,6 +1,12 @@
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    sync::Arc,
+};
+
 use parity_scale_codec::{Decode, Encode};
+use parking_lot::RwLock;
 
-use crate::aleph_primitives::BlockNumber;
+use crate::aleph_primitives::{BlockNumber, SessionPeriodChange};
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct SessionBoundaries {
@@ -18,15 +24,147 @@ impl SessionBoundaries {
     }
 }
 
+/// The sessions starting with `session`, at block `first_block`, last `period` blocks each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct PeriodStart {
+    session: SessionId,
+    first_block: BlockNumber,
+    period: SessionPeriod,
+}
+
+impl From<&SessionPeriodChange> for PeriodStart {
+    fn from(change: &SessionPeriodChange) -> Self {
+        PeriodStart {
+            session: SessionId(change.session),
+            first_block: change.first_block,
+            period: SessionPeriod(change.period),
+        }
+    }
+}
+
+/// What can be wrong with the changes of the session length reported by the runtime.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeriodChangesError {
+    NotFromGenesis,
+    ZeroPeriod(SessionId),
+    /// The change does not start at the first block after the previous sessions, or is out of
+    /// order.
+    Misaligned(SessionId),
+}
+
+impl Display for PeriodChangesError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use PeriodChangesError::*;
+        match self {
+            NotFromGenesis => write!(f, "the first session length does not start at the genesis"),
+            ZeroPeriod(id) => write!(f, "the sessions starting with {} have no blocks", id.0),
+            Misaligned(id) => write!(
+                f,
+                "the change starting with session {} does not follow the previous sessions",
+                id.0
+            ),
+        }
+    }
+}
+
+/// Struct for getting the session boundaries. The length of the sessions can change over time,
+/// all the clones learn about the changes at once.
 #[derive(Clone, Debug)]
 pub struct SessionBoundaryInfo {
-    session_period: SessionPeriod,
+    /// In the order of sessions, the first one starting with the genesis.
+    periods: Arc<RwLock<Vec<PeriodStart>>>,
 }
 
-/// Struct for getting the session boundaries.
 impl SessionBoundaryInfo {
-    pub const fn new(session_period: SessionPeriod) -> Self {
-        Self { session_period }
+    /// All the sessions last `session_period` blocks, until changes are learnt.
+    pub fn new(session_period: SessionPeriod) -> Self {
+        Self {
+            periods: Arc::new(RwLock::new(vec![PeriodStart {
+                session: SessionId(0),
+                first_block: 0,
+                period: session_period,
+            }])),
+        }
+    }
+
+    /// Replaces the known changes of the session length with the ones reported by the runtime,
+    /// returns whether anything changed.
+    pub fn update_periods(
+        &self,
+        changes: &[SessionPeriodChange],
+    ) -> Result<bool, PeriodChangesError> {
+        use PeriodChangesError::*;
+        let periods: Vec<PeriodStart> = changes.iter().map(PeriodStart::from).collect();
+        match periods.first() {
+            Some(first) if first.session == SessionId(0) && first.first_block == 0 => {}
+            _ => return Err(NotFromGenesis),
+        }
+        for period in &periods {
+            if period.period.0 == 0 {
+                return Err(ZeroPeriod(period.session));
+            }
+        }
+        for pair in periods.windows(2) {
+            let (previous, next) = (pair[0], pair[1]);
+            let expected_first_block = next
+                .session
+                .0
+                .checked_sub(previous.session.0)
+                .filter(|sessions| *sessions > 0)
+                .and_then(|sessions| sessions.checked_mul(previous.period.0))
+                .and_then(|blocks| blocks.checked_add(previous.first_block));
+            if expected_first_block != Some(next.first_block) {
+                return Err(Misaligned(next.session));
+            }
+        }
+        let mut known = self.periods.write();
+        if *known == periods {
+            return Ok(false);
+        }
+        *known = periods;
+        Ok(true)
+    }
+
+    /// The known changes of the session length, in the form the runtime reports them.
+    pub fn period_changes(&self) -> Vec<SessionPeriodChange> {
+        self.periods
+            .read()
+            .iter()
+            .map(|period| SessionPeriodChange {
+                session: period.session.0,
+                first_block: period.first_block,
+                period: period.period.0,
+            })
+            .collect()
+    }
+
+    /// The current length of the sessions, i.e. of the ones after the last known change.
+    pub fn latest_period(&self) -> SessionPeriod {
+        self.periods
+            .read()
+            .last()
+            .expect("the first period starts with the genesis")
+            .period
+    }
+
+    fn period_of_session(&self, session_id: SessionId) -> PeriodStart {
+        *self
+            .periods
+            .read()
+            .iter()
+            .rev()
+            .find(|period| period.session <= session_id)
+            .expect("the first period starts with the genesis")
+    }
+
+    fn period_of_block(&self, n: BlockNumber) -> PeriodStart {
+        *self
+            .periods
+            .read()
+            .iter()
+            .rev()
+            .find(|period| period.first_block <= n)
+            .expect("the first period starts with the genesis")
     }
 
     pub fn boundaries_for_session(&self, session_id: SessionId) -> SessionBoundaries {
@@ -38,17 +176,19 @@ impl SessionBoundaryInfo {
 
     /// Returns session id of the session that block belongs to.
     pub fn session_id_from_block_num(&self, n: BlockNumber) -> SessionId {
-        SessionId(n / self.session_period.0)
+        let period = self.period_of_block(n);
+        SessionId(period.session.0 + (n - period.first_block) / period.period.0)
     }
 
     /// Returns block number which is the last block of the session.
     pub fn last_block_of_session(&self, session_id: SessionId) -> BlockNumber {
-        (session_id.0 + 1) * self.session_period.0 - 1
+        self.first_block_of_session(session_id.next()) - 1
     }
 
     /// Returns block number which is the first block of the session.
     pub fn first_block_of_session(&self, session_id: SessionId) -> BlockNumber {
-        session_id.0 * self.session_period.0
+        let period = self.period_of_session(session_id);
+        period.first_block + (session_id.0 - period.session.0) * period.period.0
     }
 }
 
@@ -56,8 +196,14 @@ impl SessionBoundaryInfo {
 pub mod testing {
     use sp_runtime::testing::UintAuthorityId;
 
+    use super::{SessionBoundaryInfo, SessionPeriod};
     use crate::aleph_primitives::SessionAuthorityData;
 
+    /// The session boundaries most of the tests use.
+    pub fn session_boundary_info() -> SessionBoundaryInfo {
+        SessionBoundaryInfo::new(SessionPeriod(20))
+    }
+
     pub fn authority_data(from: u32, to: u32) -> SessionAuthorityData {
         SessionAuthorityData::new(
             (from..to)
@@ -80,3 +226,64 @@ impl SessionId {
 
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Ord, PartialOrd, Encode, Decode)]
 pub struct SessionPeriod(pub u32);
+
+#[cfg(test)]
+mod tests {
+    use super::{PeriodChangesError, SessionBoundaryInfo, SessionId, SessionPeriod};
+    use crate::aleph_primitives::SessionPeriodChange;
+
+    fn change(session: u32, first_block: u32, period: u32) -> SessionPeriodChange {
+        SessionPeriodChange {
+            session,
+            first_block,
+            period,
+        }
+    }
+
+    #[test]
+    fn follows_period_changes() {
+        let info = SessionBoundaryInfo::new(SessionPeriod(10));
+        let clone = info.clone();
+        assert_eq!(info.session_id_from_block_num(25), SessionId(2));
+        assert_eq!(
+            clone.update_periods(&[change(0, 0, 10), change(2, 20, 5)]),
+            Ok(true)
+        );
+        assert_eq!(
+            clone.update_periods(&[change(0, 0, 10), change(2, 20, 5)]),
+            Ok(false)
+        );
+        assert_eq!(info.latest_period(), SessionPeriod(5));
+        assert_eq!(info.session_id_from_block_num(19), SessionId(1));
+        assert_eq!(info.session_id_from_block_num(20), SessionId(2));
+        assert_eq!(info.session_id_from_block_num(25), SessionId(3));
+        assert_eq!(info.last_block_of_session(SessionId(1)), 19);
+        assert_eq!(info.first_block_of_session(SessionId(3)), 25);
+        assert_eq!(info.last_block_of_session(SessionId(3)), 29);
+    }
+
+    #[test]
+    fn rejects_inconsistent_changes() {
+        use PeriodChangesError::*;
+        let info = SessionBoundaryInfo::new(SessionPeriod(10));
+        assert_eq!(info.update_periods(&[]), Err(NotFromGenesis));
+        assert_eq!(
+            info.update_periods(&[change(1, 10, 5)]),
+            Err(NotFromGenesis)
+        );
+        assert_eq!(
+            info.update_periods(&[change(0, 0, 10), change(2, 20, 0)]),
+            Err(ZeroPeriod(SessionId(2)))
+        );
+        assert_eq!(
+            info.update_periods(&[change(0, 0, 10), change(2, 25, 5)]),
+            Err(Misaligned(SessionId(2)))
+        );
+        assert_eq!(
+            info.update_periods(&[change(0, 0, 10), change(0, 0, 5)]),
+            Err(Misaligned(SessionId(0)))
+        );
+        assert_eq!(info.latest_period(), SessionPeriod(10));
+        assert_eq!(info.first_block_of_session(SessionId(3)), 30);
+    }
+}
@@ -1,9 +1,10 @@
 use std::{collections::HashMap, marker::PhantomData, sync::Arc};
 
 use futures::StreamExt;
-use log::{debug, error, trace};
+use log::{debug, error, info, trace, warn};
 use sc_client_api::{Backend, FinalityNotification};
 use sc_utils::mpsc::TracingUnboundedReceiver;
+use sp_api::ApiExt;
 use sp_runtime::traits::{Block, Header};
 use tokio::sync::{
     oneshot::{Receiver as OneShotReceiver, Sender as OneShotSender},
@@ -11,9 +12,11 @@ use tokio::sync::{
 };
 
 use crate::{
-    aleph_primitives::{AlephSessionApi, BlockHash, BlockNumber, SessionAuthorityData},
+    aleph_primitives::{
+        AlephSessionApi, BlockHash, BlockNumber, SessionAuthorityData, SessionPeriodChange,
+    },
     session::SessionBoundaryInfo,
-    ClientForAleph, SessionId, SessionPeriod,
+    ClientForAleph, SessionId,
 };
 
 const PRUNING_THRESHOLD: u32 = 10;
@@ -26,6 +29,13 @@ pub trait AuthorityProvider {
     fn authority_data(&self, block_number: BlockNumber) -> Option<SessionAuthorityData>;
     /// returns next session authority data where current session is for block
     fn next_authority_data(&self, block_number: BlockNumber) -> Option<SessionAuthorityData>;
+    /// returns all the changes of the session length known at block, if the runtime reports them
+    fn session_period_changes(
+        &self,
+        _block_number: BlockNumber,
+    ) -> Option<Vec<SessionPeriodChange>> {
+        None
+    }
 }
 
 /// Default implementation of authority provider trait.
@@ -113,6 +123,30 @@ where
                 .flatten(),
         }
     }
+
+    fn session_period_changes(
+        &self,
+        block_number: BlockNumber,
+    ) -> Option<Vec<SessionPeriodChange>> {
+        let block_hash = self.block_hash(block_number)?;
+        let runtime_api = self.client.runtime_api();
+        match runtime_api.api_version::<dyn AlephSessionApi<B>>(block_hash) {
+            Ok(Some(version)) if version >= 3 => {}
+            _ => return None,
+        }
+        match runtime_api.session_period_changes(block_hash) {
+            Ok(changes) => Some(changes),
+            Err(e) => {
+                error!(
+                    target: LOG_TARGET,
+                    "Error while retrieving session length changes at block #{}. {}",
+                    block_number,
+                    e
+                );
+                None
+            }
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -211,6 +245,20 @@ impl SharedSessionMap {
         guard.0.insert(id, authority_data)
     }
 
+    /// Inserts the authority data of the session unless it is already known, returns whether it
+    /// was inserted.
+    pub async fn insert_if_missing(
+        &mut self,
+        id: SessionId,
+        authority_data: SessionAuthorityData,
+    ) -> bool {
+        if self.0.read().await.0.contains_key(&id) {
+            return false;
+        }
+        self.update(id, authority_data).await;
+        true
+    }
+
     async fn prune_below(&mut self, id: SessionId) {
         let mut guard = self.0.write().await;
 
@@ -266,12 +314,18 @@ where
     AP: AuthorityProvider,
     FN: FinalityNotifier,
 {
-    pub fn new(authority_provider: AP, finality_notifier: FN, period: SessionPeriod) -> Self {
+    /// The session boundaries get updated whenever the runtime changes the session length, so the
+    /// clones of `session_info` used elsewhere follow the changes.
+    pub fn new(
+        authority_provider: AP,
+        finality_notifier: FN,
+        session_info: SessionBoundaryInfo,
+    ) -> Self {
         Self {
             session_map: SharedSessionMap::new(),
             authority_provider,
             finality_notifier,
-            session_info: SessionBoundaryInfo::new(period),
+            session_info,
         }
     }
 
@@ -280,6 +334,33 @@ where
         self.session_map.read_only()
     }
 
+    /// returns a view of the session map that can be inserted into
+    pub fn shared_session_map(&self) -> SharedSessionMap {
+        self.session_map.clone()
+    }
+
+    /// Learns about the changes of the session length known at the block, they have to be known a
+    /// session in advance, so before the boundaries of the next session are needed.
+    fn update_session_periods(&mut self, block_number: BlockNumber) {
+        let changes = match self.authority_provider.session_period_changes(block_number) {
+            Some(changes) => changes,
+            None => return,
+        };
+        match self.session_info.update_periods(&changes) {
+            Ok(true) => info!(
+                target: LOG_TARGET,
+                "Session length changes updated at block #{:?}, now {:?} blocks per session.",
+                block_number,
+                self.session_info.latest_period().0
+            ),
+            Ok(false) => {}
+            Err(e) => warn!(
+                target: LOG_TARGET,
+                "Ignoring session length changes reported at block #{:?}: {}.", block_number, e
+            ),
+        }
+    }
+
     /// Puts authority data for the next session into the session map
     async fn handle_first_block_of_session(&mut self, session_id: SessionId) {
         let first_block = self.session_info.first_block_of_session(session_id);
@@ -287,6 +368,7 @@ where
             target: LOG_TARGET,
             "Handling first block #{:?} of session {:?}", first_block, session_id.0
         );
+        self.update_session_periods(first_block);
 
         if let Some(authority_data) = self.authority_provider.next_authority_data(first_block) {
             self.session_map
@@ -317,6 +399,7 @@ where
     /// If previous authorities are still available in `AuthorityProvider`, also puts them in the session map.
     async fn catch_up(&mut self) -> SessionId {
         let last_finalized = self.finality_notifier.last_finalized();
+        self.update_session_periods(last_finalized);
 
         let current_session = self.session_info.session_id_from_block_num(last_finalized);
         let starting_session = SessionId(current_session.0.saturating_sub(PRUNING_THRESHOLD - 1));
@@ -368,17 +451,12 @@ where
                 last_finalized
             );
 
-            let session_id = self.session_info.session_id_from_block_num(last_finalized);
-
-            if last_updated >= session_id {
-                continue;
+            // The boundaries can change while handling the sessions, so they are checked anew
+            // after every one of them.
+            while last_updated < self.session_info.session_id_from_block_num(last_finalized) {
+                last_updated = last_updated.next();
+                self.handle_first_block_of_session(last_updated).await;
             }
-
-            for session in (last_updated.0 + 1)..=session_id.0 {
-                self.handle_first_block_of_session(SessionId(session)).await;
-            }
-
-            last_updated = session_id;
         }
     }
 }
@@ -392,7 +470,7 @@ mod tests {
     use tokio::sync::oneshot::error::TryRecvError;
 
     use super::*;
-    use crate::{aleph_primitives::BlockNumber, session::testing::authority_data};
+    use crate::{aleph_primitives::BlockNumber, session::testing::authority_data, SessionPeriod};
 
     const FIRST_THRESHOLD: u32 = PRUNING_THRESHOLD + 1;
     const SECOND_THRESHOLD: u32 = 2 * PRUNING_THRESHOLD + 1;
@@ -406,6 +484,7 @@ mod tests {
     struct MockProvider {
         pub session_map: HashMap<BlockNumber, SessionAuthorityData>,
         pub next_session_map: HashMap<BlockNumber, SessionAuthorityData>,
+        pub period_changes: HashMap<BlockNumber, Vec<SessionPeriodChange>>,
     }
 
     impl MockProvider {
@@ -413,14 +492,19 @@ mod tests {
             Self {
                 session_map: HashMap::new(),
                 next_session_map: HashMap::new(),
+                period_changes: HashMap::new(),
             }
         }
 
         fn add_session(&mut self, session_id: BlockNumber) {
+            self.add_session_starting_at(session_id, session_id);
+        }
+
+        fn add_session_starting_at(&mut self, session_id: u32, first_block: BlockNumber) {
             self.session_map
-                .insert(session_id, authority_data_for_session(session_id));
+                .insert(first_block, authority_data_for_session(session_id));
             self.next_session_map
-                .insert(session_id, authority_data_for_session(session_id + 1));
+                .insert(first_block, authority_data_for_session(session_id + 1));
         }
     }
     impl AuthorityProvider for MockProvider {
@@ -431,6 +515,13 @@ mod tests {
         fn next_authority_data(&self, block_number: BlockNumber) -> Option<SessionAuthorityData> {
             self.next_session_map.get(&block_number).cloned()
         }
+
+        fn session_period_changes(
+            &self,
+            block_number: BlockNumber,
+        ) -> Option<Vec<SessionPeriodChange>> {
+            self.period_changes.get(&block_number).cloned()
+        }
     }
 
     struct MockNotifier {
@@ -470,7 +561,11 @@ mod tests {
 
         mock_provider.add_session(0);
 
-        let updater = SessionMapUpdater::new(mock_provider, mock_notifier, SessionPeriod(1));
+        let updater = SessionMapUpdater::new(
+            mock_provider,
+            mock_notifier,
+            SessionBoundaryInfo::new(SessionPeriod(1)),
+        );
         let session_map = updater.readonly_session_map();
 
         let _handle = tokio::spawn(updater.run());
@@ -488,6 +583,57 @@ mod tests {
         );
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn follows_session_length_changes() {
+        let (sender, receiver) = tracing_unbounded("test", 1_000);
+        let mut mock_provider = MockProvider::new();
+        let mock_notificator = MockNotifier::new(receiver);
+
+        mock_provider.add_session(0);
+        mock_provider.add_session(1);
+        // From session 2 on the sessions last two blocks.
+        mock_provider.add_session_starting_at(2, 2);
+        mock_provider.add_session_starting_at(3, 4);
+        mock_provider.period_changes.insert(
+            1,
+            vec![
+                SessionPeriodChange {
+                    session: 0,
+                    first_block: 0,
+                    period: 1,
+                },
+                SessionPeriodChange {
+                    session: 2,
+                    first_block: 2,
+                    period: 2,
+                },
+            ],
+        );
+
+        let session_info = SessionBoundaryInfo::new(SessionPeriod(1));
+        let updater = SessionMapUpdater::new(mock_provider, mock_notificator, session_info.clone());
+        let session_map = updater.readonly_session_map();
+
+        for n in 1..6 {
+            sender.unbounded_send(n).unwrap();
+        }
+
+        let _handle = tokio::spawn(updater.run());
+
+        // wait a bit
+        Delay::new(Duration::from_millis(50)).await;
+
+        assert_eq!(session_info.session_id_from_block_num(5), SessionId(3));
+        assert_eq!(
+            session_map.get(SessionId(3)).await,
+            Some(authority_data(12, 16))
+        );
+        assert_eq!(
+            session_map.get(SessionId(4)).await,
+            Some(authority_data(16, 20))
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn updates_session_map_on_notifications() {
         let (sender, receiver) = tracing_unbounded("test", 1_000);
@@ -498,7 +644,11 @@ mod tests {
         mock_provider.add_session(1);
         mock_provider.add_session(2);
 
-        let updater = SessionMapUpdater::new(mock_provider, mock_notificator, SessionPeriod(1));
+        let updater = SessionMapUpdater::new(
+            mock_provider,
+            mock_notificator,
+            SessionBoundaryInfo::new(SessionPeriod(1)),
+        );
         let session_map = updater.readonly_session_map();
 
         for n in 1..3 {
@@ -540,7 +690,11 @@ mod tests {
 
         mock_notificator.last_finalized = 2;
 
-        let updater = SessionMapUpdater::new(mock_provider, mock_notificator, SessionPeriod(1));
+        let updater = SessionMapUpdater::new(
+            mock_provider,
+            mock_notificator,
+            SessionBoundaryInfo::new(SessionPeriod(1)),
+        );
         let session_map = updater.readonly_session_map();
 
         let _handle = tokio::spawn(updater.run());
@@ -578,7 +732,11 @@ mod tests {
 
         mock_notificator.last_finalized = 20;
 
-        let updater = SessionMapUpdater::new(mock_provider, mock_notificator, SessionPeriod(1));
+        let updater = SessionMapUpdater::new(
+            mock_provider,
+            mock_notificator,
+            SessionBoundaryInfo::new(SessionPeriod(1)),
+        );
         let session_map = updater.readonly_session_map();
 
         let _handle = tokio::spawn(updater.run());
@@ -611,7 +769,11 @@ mod tests {
         mock_provider.add_session(5);
         mock_notificator.last_finalized = 5;
 
-        let updater = SessionMapUpdater::new(mock_provider, mock_notificator, SessionPeriod(1));
+        let updater = SessionMapUpdater::new(
+            mock_provider,
+            mock_notificator,
+            SessionBoundaryInfo::new(SessionPeriod(1)),
+        );
         let session_map = updater.readonly_session_map();
 
         let _handle = tokio::spawn(updater.run());
@@ -647,7 +809,11 @@ mod tests {
             mock_provider.add_session(i);
         }
 
-        let updater = SessionMapUpdater::new(mock_provider, mock_notificator, SessionPeriod(1));
+        let updater = SessionMapUpdater::new(
+            mock_provider,
+            mock_notificator,
+            SessionBoundaryInfo::new(SessionPeriod(1)),
+        );
         let session_map = updater.readonly_session_map();
 
         let _handle = tokio::spawn(updater.run());
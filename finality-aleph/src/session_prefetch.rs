@@ -0,0 +1,330 @@
+use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+
+use futures::StreamExt;
+use log::{debug, warn};
+use parking_lot::Mutex;
+use sc_client_api::{Backend, BlockImportNotification};
+use sc_utils::mpsc::TracingUnboundedReceiver;
+use sp_runtime::traits::{Block, Header};
+
+use crate::{
+    aleph_primitives::{BlockNumber, SessionAuthorityData},
+    session::SessionBoundaryInfo,
+    session_map::{AuthorityProvider, SharedSessionMap},
+    ClientForAleph, SessionId,
+};
+
+const LOG_TARGET: &str = "aleph-session-prefetch";
+/// How many blocks before the end of a session the next one gets prepared for.
+pub const PREFETCH_BLOCKS: BlockNumber = 20;
+
+/// The authorities of upcoming sessions, fetched from the runtime ahead of time, so that the
+/// verifier does not have to wait for the runtime when the first justifications of a session
+/// arrive. Can be cloned and shared between the prefetcher and the verifier.
+#[derive(Clone, Default)]
+pub struct PrefetchedAuthorities {
+    sessions: Arc<Mutex<HashMap<SessionId, SessionAuthorityData>>>,
+}
+
+impl PrefetchedAuthorities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers the authorities of the session, forgetting the ones of earlier sessions.
+    pub fn insert(&self, session_id: SessionId, authority_data: SessionAuthorityData) {
+        let mut sessions = self.sessions.lock();
+        sessions.retain(|&id, _| id >= session_id);
+        sessions.insert(session_id, authority_data);
+    }
+
+    /// Returns the prefetched authorities of the session, if any, forgetting them.
+    pub fn take(&self, session_id: SessionId) -> Option<SessionAuthorityData> {
+        self.sessions.lock().remove(&session_id)
+    }
+}
+
+#[async_trait::async_trait]
+pub trait BestBlockNotifier {
+    /// The number of the next block that became the best one.
+    async fn next_best(&mut self) -> Option<BlockNumber>;
+    fn last_finalized(&self) -> BlockNumber;
+}
+
+/// Default implementation of best block notifier trait.
+pub struct BestBlockNotifierImpl<C, B, BE>
+where
+    C: ClientForAleph<B, BE> + Send + Sync + 'static,
+    B: Block,
+    B::Header: Header<Number = BlockNumber>,
+    BE: Backend<B> + 'static,
+{
+    notification_stream: TracingUnboundedReceiver<BlockImportNotification<B>>,
+    client: Arc<C>,
+    _phantom: PhantomData<(B, BE)>,
+}
+
+impl<C, B, BE> BestBlockNotifierImpl<C, B, BE>
+where
+    C: ClientForAleph<B, BE> + Send + Sync + 'static,
+    B: Block,
+    B::Header: Header<Number = BlockNumber>,
+    BE: Backend<B> + 'static,
+{
+    pub fn new(client: Arc<C>) -> Self {
+        Self {
+            notification_stream: client.import_notification_stream(),
+            client,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<C, B, BE> BestBlockNotifier for BestBlockNotifierImpl<C, B, BE>
+where
+    C: ClientForAleph<B, BE> + Send + Sync + 'static,
+    B: Block,
+    B::Header: Header<Number = BlockNumber>,
+    BE: Backend<B> + 'static,
+{
+    async fn next_best(&mut self) -> Option<BlockNumber> {
+        loop {
+            let notification = self.notification_stream.next().await?;
+            if notification.is_new_best {
+                return Some(*notification.header.number());
+            }
+        }
+    }
+
+    fn last_finalized(&self) -> BlockNumber {
+        self.client.info().finalized_number
+    }
+}
+
+/// Prepares for every session `PREFETCH_BLOCKS` blocks before it starts, according to the best
+/// block. The authorities of the session are fetched from the runtime and handed to the verifier,
+/// and put into the session map unless already there, which makes the party pre-establish the
+/// validator network connections of the session.
+///
+/// The authorities of a session are decided in the first block of the previous one, they are
+/// only fetched once that block is finalized, so that they never come from a fork.
+pub struct SessionPrefetcher<AP, BN>
+where
+    AP: AuthorityProvider,
+    BN: BestBlockNotifier,
+{
+    authority_provider: AP,
+    best_block_notifier: BN,
+    session_info: SessionBoundaryInfo,
+    session_map: SharedSessionMap,
+    prefetched: PrefetchedAuthorities,
+    last_prefetched: Option<SessionId>,
+}
+
+impl<AP, BN> SessionPrefetcher<AP, BN>
+where
+    AP: AuthorityProvider,
+    BN: BestBlockNotifier,
+{
+    pub fn new(
+        authority_provider: AP,
+        best_block_notifier: BN,
+        session_info: SessionBoundaryInfo,
+        session_map: SharedSessionMap,
+        prefetched: PrefetchedAuthorities,
+    ) -> Self {
+        Self {
+            authority_provider,
+            best_block_notifier,
+            session_info,
+            session_map,
+            prefetched,
+            last_prefetched: None,
+        }
+    }
+
+    async fn handle_best_block(&mut self, best: BlockNumber) {
+        let session_id = self.session_info.session_id_from_block_num(best);
+        let next_session_id = session_id.next();
+        if self.last_prefetched >= Some(next_session_id) {
+            return;
+        }
+        let last_block = self.session_info.last_block_of_session(session_id);
+        if last_block.saturating_sub(best) > PREFETCH_BLOCKS {
+            return;
+        }
+        let first_block = self.session_info.first_block_of_session(session_id);
+        if self.best_block_notifier.last_finalized() < first_block {
+            debug!(
+                target: LOG_TARGET,
+                "Not prefetching session {:?} yet, block #{} is not finalized.",
+                next_session_id.0,
+                first_block
+            );
+            return;
+        }
+        let authority_data = match self.authority_provider.next_authority_data(first_block) {
+            Some(authority_data) => authority_data,
+            None => {
+                warn!(
+                    target: LOG_TARGET,
+                    "Failed to prefetch the authorities of session {:?} at block #{}.",
+                    next_session_id.0,
+                    first_block
+                );
+                return;
+            }
+        };
+        debug!(
+            target: LOG_TARGET,
+            "Prefetched {} authorities of session {:?}, {} blocks ahead.",
+            authority_data.authorities().len(),
+            next_session_id.0,
+            last_block - best + 1
+        );
+        self.prefetched
+            .insert(next_session_id, authority_data.clone());
+        self.session_map
+            .insert_if_missing(next_session_id, authority_data)
+            .await;
+        self.last_prefetched = Some(next_session_id);
+    }
+
+    pub async fn run(mut self) {
+        while let Some(best) = self.best_block_notifier.next_best().await {
+            self.handle_best_block(best).await;
+        }
+        debug!(target: LOG_TARGET, "Best block notifications ended.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    use futures::{channel::mpsc, StreamExt};
+
+    use super::{BestBlockNotifier, PrefetchedAuthorities, SessionPrefetcher, PREFETCH_BLOCKS};
+    use crate::{
+        aleph_primitives::{BlockNumber, SessionAuthorityData},
+        session::{testing::authority_data, SessionBoundaryInfo},
+        session_map::{AuthorityProvider, SharedSessionMap},
+        SessionId, SessionPeriod,
+    };
+
+    const SESSION_PERIOD: u32 = 30;
+
+    struct MockProvider {
+        next_session_map: HashMap<BlockNumber, SessionAuthorityData>,
+    }
+
+    impl AuthorityProvider for MockProvider {
+        fn authority_data(&self, _: BlockNumber) -> Option<SessionAuthorityData> {
+            None
+        }
+
+        fn next_authority_data(&self, block_number: BlockNumber) -> Option<SessionAuthorityData> {
+            self.next_session_map.get(&block_number).cloned()
+        }
+    }
+
+    struct MockNotifier {
+        best: mpsc::UnboundedReceiver<BlockNumber>,
+        finalized: Arc<AtomicU32>,
+    }
+
+    #[async_trait::async_trait]
+    impl BestBlockNotifier for MockNotifier {
+        async fn next_best(&mut self) -> Option<BlockNumber> {
+            self.best.next().await
+        }
+
+        fn last_finalized(&self) -> BlockNumber {
+            self.finalized.load(Ordering::SeqCst)
+        }
+    }
+
+    fn authority_data_for_session(session_id: u32) -> SessionAuthorityData {
+        authority_data(session_id * 4, (session_id + 1) * 4)
+    }
+
+    fn setup() -> (
+        SessionPrefetcher<MockProvider, MockNotifier>,
+        mpsc::UnboundedSender<BlockNumber>,
+        Arc<AtomicU32>,
+        PrefetchedAuthorities,
+        SharedSessionMap,
+    ) {
+        let next_session_map = (0..3)
+            .map(|session| {
+                (
+                    session * SESSION_PERIOD,
+                    authority_data_for_session(session + 1),
+                )
+            })
+            .collect();
+        let (sender, best) = mpsc::unbounded();
+        let finalized = Arc::new(AtomicU32::new(0));
+        let prefetched = PrefetchedAuthorities::new();
+        let session_map = SharedSessionMap::new();
+        let prefetcher = SessionPrefetcher::new(
+            MockProvider { next_session_map },
+            MockNotifier {
+                best,
+                finalized: finalized.clone(),
+            },
+            SessionBoundaryInfo::new(SessionPeriod(SESSION_PERIOD)),
+            session_map.clone(),
+            prefetched.clone(),
+        );
+        (prefetcher, sender, finalized, prefetched, session_map)
+    }
+
+    #[tokio::test]
+    async fn prefetches_near_session_boundary() {
+        let (mut prefetcher, _sender, _finalized, prefetched, session_map) = setup();
+        prefetcher
+            .handle_best_block(SESSION_PERIOD - PREFETCH_BLOCKS - 2)
+            .await;
+        assert_eq!(prefetched.take(SessionId(1)), None);
+        prefetcher
+            .handle_best_block(SESSION_PERIOD - PREFETCH_BLOCKS)
+            .await;
+        assert_eq!(
+            prefetched.take(SessionId(1)),
+            Some(authority_data_for_session(1))
+        );
+        let mut insertion = session_map
+            .read_only()
+            .subscribe_to_insertion(SessionId(1))
+            .await;
+        assert_eq!(
+            insertion.try_recv().ok(),
+            Some(authority_data_for_session(1))
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_for_first_block_of_session_to_be_finalized() {
+        let (mut prefetcher, sender, finalized, prefetched, _session_map) = setup();
+        prefetcher.handle_best_block(2 * SESSION_PERIOD - 1).await;
+        assert_eq!(prefetched.take(SessionId(2)), None);
+        finalized.store(SESSION_PERIOD, Ordering::SeqCst);
+        sender
+            .unbounded_send(2 * SESSION_PERIOD - 1)
+            .expect("prefetcher listens");
+        drop(sender);
+        prefetcher.run().await;
+        assert_eq!(
+            prefetched.take(SessionId(2)),
+            Some(authority_data_for_session(2))
+        );
+    }
+}
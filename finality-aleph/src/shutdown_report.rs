@@ -0,0 +1,213 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Display,
+    fs,
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info};
+use serde::Serialize;
+
+const LOG_TARGET: &str = "aleph-shutdown-report";
+/// The directory, relative to the backup path, where reports are saved.
+pub const REPORTS_DIRECTORY: &str = "shutdown-reports";
+/// How many of the most recent errors a report contains.
+const MAX_LAST_ERRORS: usize = 16;
+/// How many of the most recently counted events are remembered, for diagnosing a running service.
+const MAX_LAST_EVENTS: usize = 32;
+/// How many unfinished requests a report lists, the rest are only counted.
+const MAX_UNFINISHED_REQUESTS: usize = 64;
+const SHUTDOWN_REASON: &str = "shutdown";
+
+/// The final report of a service, a consistent starting point for postmortems.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ShutdownReport {
+    pub service: String,
+    /// Why the service exited, either `shutdown` or the error that stopped it.
+    pub reason: String,
+    pub finished_at_unix_ms: u128,
+    pub uptime_secs: u64,
+    pub counters: BTreeMap<String, u64>,
+    pub error_counters: BTreeMap<String, u64>,
+    pub last_errors: Vec<String>,
+    pub unfinished_request_count: usize,
+    pub unfinished_requests: Vec<String>,
+}
+
+impl ShutdownReport {
+    fn file_name(&self) -> String {
+        format!("{}-{}.json", self.service, self.finished_at_unix_ms)
+    }
+
+    fn save(&self, backup_path: &Path) -> Result<PathBuf, IoError> {
+        let directory = backup_path.join(REPORTS_DIRECTORY);
+        fs::create_dir_all(&directory)?;
+        let path = directory.join(self.file_name());
+        let contents = serde_json::to_vec_pretty(self)
+            .map_err(|e| IoError::new(IoErrorKind::InvalidData, e))?;
+        fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    /// Logs the report and saves it in the reports directory under `backup_path`, if provided.
+    /// Any errors are logged and dropped, there is nothing else to be done at this point.
+    pub fn emit(&self, backup_path: Option<&Path>) {
+        match serde_json::to_string(self) {
+            Ok(report) => info!(target: LOG_TARGET, "Shutdown report: {}", report),
+            Err(e) => error!(target: LOG_TARGET, "Failed to serialize shutdown report: {}", e),
+        }
+        if let Some(backup_path) = backup_path {
+            match self.save(backup_path) {
+                Ok(path) => info!(target: LOG_TARGET, "Saved shutdown report to {:?}.", path),
+                Err(e) => error!(target: LOG_TARGET, "Failed to save shutdown report: {}", e),
+            }
+        }
+    }
+}
+
+fn owned_keys(counters: &BTreeMap<&'static str, u64>) -> BTreeMap<String, u64> {
+    counters
+        .iter()
+        .map(|(counter, value)| (counter.to_string(), *value))
+        .collect()
+}
+
+/// Collects the data for the shutdown report during the life of a service.
+pub struct ShutdownRecorder {
+    service: &'static str,
+    started: Instant,
+    counters: BTreeMap<&'static str, u64>,
+    error_counters: BTreeMap<&'static str, u64>,
+    last_errors: VecDeque<String>,
+    last_events: VecDeque<&'static str>,
+    exit_reason: Option<String>,
+}
+
+impl ShutdownRecorder {
+    pub fn new(service: &'static str) -> Self {
+        ShutdownRecorder {
+            service,
+            started: Instant::now(),
+            counters: BTreeMap::new(),
+            error_counters: BTreeMap::new(),
+            last_errors: VecDeque::new(),
+            last_events: VecDeque::new(),
+            exit_reason: None,
+        }
+    }
+
+    pub fn count(&mut self, counter: &'static str) {
+        *self.counters.entry(counter).or_default() += 1;
+        if self.last_events.len() == MAX_LAST_EVENTS {
+            self.last_events.pop_front();
+        }
+        self.last_events.push_back(counter);
+    }
+
+    /// The most recently counted events, the latest first.
+    pub fn last_events(&self) -> Vec<&'static str> {
+        self.last_events.iter().rev().copied().collect()
+    }
+
+    /// The descriptions of the most recent errors, the latest first.
+    pub fn last_errors(&self) -> Vec<String> {
+        self.last_errors.iter().rev().cloned().collect()
+    }
+
+    /// Counts an error with the given counter, and remembers its description as one of the
+    /// most recent errors.
+    pub fn record_error<E: Display>(&mut self, counter: &'static str, error: E) {
+        *self.error_counters.entry(counter).or_default() += 1;
+        if self.last_errors.len() == MAX_LAST_ERRORS {
+            self.last_errors.pop_front();
+        }
+        self.last_errors.push_back(error.to_string());
+    }
+
+    /// Records the reason of an exit other than a regular shutdown.
+    pub fn exit_reason<E: Display>(&mut self, reason: E) {
+        self.exit_reason = Some(reason.to_string());
+    }
+
+    pub fn report<I: Iterator<Item = String>>(&self, unfinished_requests: I) -> ShutdownReport {
+        let unfinished_requests: Vec<_> = unfinished_requests.collect();
+        ShutdownReport {
+            service: self.service.to_string(),
+            reason: self
+                .exit_reason
+                .clone()
+                .unwrap_or_else(|| SHUTDOWN_REASON.to_string()),
+            finished_at_unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_millis())
+                .unwrap_or_default(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            counters: owned_keys(&self.counters),
+            error_counters: owned_keys(&self.error_counters),
+            last_errors: self.last_errors.iter().cloned().collect(),
+            unfinished_request_count: unfinished_requests.len(),
+            unfinished_requests: unfinished_requests
+                .into_iter()
+                .take(MAX_UNFINISHED_REQUESTS)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, iter};
+
+    use super::{ShutdownRecorder, MAX_LAST_ERRORS, REPORTS_DIRECTORY};
+    use crate::testing::TestDirectory;
+
+    #[test]
+    fn reports_counters_and_last_errors() {
+        let mut recorder = ShutdownRecorder::new("test");
+        recorder.count("first");
+        recorder.count("second");
+        recorder.count("first");
+        for i in 0..MAX_LAST_ERRORS + 3 {
+            recorder.record_error("failing", i);
+        }
+        let report = recorder.report(iter::once("request".to_string()));
+        assert_eq!(report.reason, "shutdown");
+        assert_eq!(report.counters.get("first"), Some(&2));
+        assert_eq!(report.counters.get("second"), Some(&1));
+        assert_eq!(
+            report.error_counters.get("failing"),
+            Some(&(MAX_LAST_ERRORS as u64 + 3))
+        );
+        assert_eq!(report.last_errors.len(), MAX_LAST_ERRORS);
+        assert_eq!(report.last_errors.first(), Some(&"3".to_string()));
+        assert_eq!(report.unfinished_request_count, 1);
+        assert_eq!(recorder.last_events(), vec!["first", "second", "first"]);
+        assert_eq!(
+            recorder.last_errors().first(),
+            Some(&(MAX_LAST_ERRORS + 2).to_string())
+        );
+    }
+
+    #[test]
+    fn reports_exit_reason() {
+        let mut recorder = ShutdownRecorder::new("test");
+        recorder.exit_reason("stream ended");
+        assert_eq!(recorder.report(iter::empty()).reason, "stream ended");
+    }
+
+    #[test]
+    fn saves_report_next_to_backups() {
+        let backup_path = TestDirectory::new("shutdown-report");
+        let report = ShutdownRecorder::new("test").report(iter::empty());
+        let path = report.save(&backup_path).expect("saving works");
+        assert_eq!(
+            path.parent(),
+            Some(backup_path.join(REPORTS_DIRECTORY).as_path())
+        );
+        let saved: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).expect("file exists")).expect("valid json");
+        assert_eq!(saved["service"], "test");
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use tokio::time::timeout;
+
+use crate::{
+    abft::AbftMetrics,
+    aleph_primitives::BlockNumber,
+    session::SessionBoundaryInfo,
+    session_map::FinalityNotifier,
+    sync::{
+        FinalityStats, FinalityStatus, ForestDump, ForestDumps, NetworkFinalityView, PeerId,
+        StatusReports, SyncStatus, VertexContents, VertexInterest,
+    },
+    BlockIdentifier, SessionId,
+};
+
+const LOG_TARGET: &str = "aleph-stall-watchdog";
+/// How many of the most recent diagnostics are kept.
+const MAX_DIAGNOSTICS: usize = 16;
+
+/// The shape of the sync forest, without the individual vertices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForestSummary<BI: BlockIdentifier> {
+    pub root: BI,
+    pub highest_justified: BI,
+    pub vertices: usize,
+    /// How many of the vertices have justifications, but are not imported yet.
+    pub justified: usize,
+    /// How many of the vertices we want to import, but do not know the blocks of yet.
+    pub required: usize,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> From<&ForestDump<I, BI>> for ForestSummary<BI> {
+    fn from(dump: &ForestDump<I, BI>) -> Self {
+        let pending = || {
+            dump.vertices
+                .iter()
+                .filter(|vertex| vertex.interest != VertexInterest::Imported)
+        };
+        ForestSummary {
+            root: dump.root.clone(),
+            highest_justified: dump.highest_justified.clone(),
+            vertices: dump.vertices.len(),
+            justified: pending()
+                .filter(|vertex| vertex.contents == VertexContents::Justification)
+                .count(),
+            required: pending()
+                .filter(|vertex| vertex.interest != VertexInterest::Auxiliary)
+                .filter(|vertex| vertex.contents == VertexContents::Empty)
+                .count(),
+        }
+    }
+}
+
+/// A snapshot of everything that could explain why finality stalled, taken when it did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StallDiagnostic<I: PeerId, BI: BlockIdentifier> {
+    pub taken_at_unix_ms: u128,
+    /// How long no new block was finalized for.
+    pub stalled_for: Duration,
+    pub last_finalized: BlockNumber,
+    pub session: SessionId,
+    /// The round of AlephBFT in the current session, unknown without the metrics registered.
+    pub abft_round: Option<u64>,
+    pub network_status: FinalityStatus,
+    pub network_stats: Option<FinalityStats>,
+    /// The state of sync, including the peers and the last events it handled, missing if sync
+    /// did not respond, which usually means it is stuck itself.
+    pub sync: Option<SyncStatus<I, BI>>,
+    pub forest: Option<ForestSummary<BI>>,
+}
+
+/// The most recent diagnostics of finality stalls. Can be cloned and shared between the
+/// watchdog and whoever wants to read them.
+#[derive(Clone)]
+pub struct StallDiagnostics<I: PeerId, BI: BlockIdentifier> {
+    diagnostics: Arc<Mutex<VecDeque<StallDiagnostic<I, BI>>>>,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> StallDiagnostics<I, BI> {
+    pub fn new() -> Self {
+        StallDiagnostics {
+            diagnostics: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    fn record(&self, diagnostic: StallDiagnostic<I, BI>) {
+        let mut diagnostics = self.diagnostics.lock();
+        if diagnostics.len() == MAX_DIAGNOSTICS {
+            diagnostics.pop_front();
+        }
+        diagnostics.push_back(diagnostic);
+    }
+
+    /// The remembered diagnostics, the latest first.
+    pub fn recent(&self) -> Vec<StallDiagnostic<I, BI>> {
+        self.diagnostics.lock().iter().rev().cloned().collect()
+    }
+}
+
+impl<I: PeerId, BI: BlockIdentifier> Default for StallDiagnostics<I, BI> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Watches finality and takes a diagnostic snapshot whenever no new block was finalized for the
+/// configured time, and again every time that much more passes without progress. The snapshots
+/// are logged and kept in the shared diagnostics.
+pub struct StallWatchdog<FN, I, BI>
+where
+    FN: FinalityNotifier,
+    I: PeerId,
+    BI: BlockIdentifier,
+{
+    finality_notifier: FN,
+    stall_timeout: Duration,
+    session_info: SessionBoundaryInfo,
+    status_reports: StatusReports<I, BI>,
+    forest_dumps: ForestDumps<I, BI>,
+    network_view: NetworkFinalityView<I>,
+    abft_metrics: AbftMetrics,
+    diagnostics: StallDiagnostics<I, BI>,
+}
+
+impl<FN, I, BI> StallWatchdog<FN, I, BI>
+where
+    FN: FinalityNotifier,
+    I: PeerId,
+    BI: BlockIdentifier,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        finality_notifier: FN,
+        stall_timeout: Duration,
+        session_info: SessionBoundaryInfo,
+        status_reports: StatusReports<I, BI>,
+        forest_dumps: ForestDumps<I, BI>,
+        network_view: NetworkFinalityView<I>,
+        abft_metrics: AbftMetrics,
+        diagnostics: StallDiagnostics<I, BI>,
+    ) -> Self {
+        StallWatchdog {
+            finality_notifier,
+            stall_timeout,
+            session_info,
+            status_reports,
+            forest_dumps,
+            network_view,
+            abft_metrics,
+            diagnostics,
+        }
+    }
+
+    async fn diagnose(
+        &mut self,
+        last_finalized: BlockNumber,
+        stalled_for: Duration,
+    ) -> StallDiagnostic<I, BI> {
+        let now = Instant::now();
+        let sync = self.status_reports.report().await;
+        let forest = self
+            .forest_dumps
+            .dump()
+            .await
+            .map(|dump| ForestSummary::from(&dump));
+        StallDiagnostic {
+            taken_at_unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_millis())
+                .unwrap_or_default(),
+            stalled_for,
+            last_finalized,
+            session: self.session_info.session_id_from_block_num(last_finalized),
+            abft_round: self.abft_metrics.round(),
+            network_status: self.network_view.status(now),
+            network_stats: self.network_view.stats(now),
+            sync,
+            forest,
+        }
+    }
+
+    pub async fn run(mut self) {
+        let mut last_finalized = self.finality_notifier.last_finalized();
+        let mut last_progress = Instant::now();
+        let mut stalled = false;
+        loop {
+            match timeout(self.stall_timeout, self.finality_notifier.next()).await {
+                Ok(Some(number)) => {
+                    if stalled {
+                        info!(
+                            target: LOG_TARGET,
+                            "Finality resumed with block #{} after {:?}.",
+                            number,
+                            last_progress.elapsed()
+                        );
+                    }
+                    last_finalized = last_finalized.max(number);
+                    last_progress = Instant::now();
+                    stalled = false;
+                }
+                Ok(None) => {
+                    debug!(target: LOG_TARGET, "Finality notifications ended.");
+                    return;
+                }
+                Err(_) => {
+                    stalled = true;
+                    let diagnostic = self.diagnose(last_finalized, last_progress.elapsed()).await;
+                    warn!(
+                        target: LOG_TARGET,
+                        "No block was finalized for {:?}, the last one is #{}: {:?}",
+                        diagnostic.stalled_for,
+                        last_finalized,
+                        diagnostic
+                    );
+                    self.diagnostics.record(diagnostic);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::{channel::mpsc, StreamExt};
+    use tokio::time::sleep;
+
+    use super::{StallDiagnostic, StallDiagnostics, StallWatchdog, MAX_DIAGNOSTICS};
+    use crate::{
+        abft::AbftMetrics,
+        aleph_primitives::{BlockHash, BlockNumber},
+        session::SessionBoundaryInfo,
+        session_map::FinalityNotifier,
+        sync::{
+            FinalityStatus, ForestDump, ForestDumps, NetworkFinalityView, StatusReports,
+            SyncStatus, VertexContents, VertexDump, VertexInterest,
+        },
+        BlockId, SessionId, SessionPeriod,
+    };
+
+    const STALL_TIMEOUT: Duration = Duration::from_millis(20);
+
+    struct MockNotifier {
+        receiver: mpsc::UnboundedReceiver<BlockNumber>,
+    }
+
+    #[async_trait::async_trait]
+    impl FinalityNotifier for MockNotifier {
+        async fn next(&mut self) -> Option<BlockNumber> {
+            self.receiver.next().await
+        }
+
+        fn last_finalized(&self) -> BlockNumber {
+            35
+        }
+    }
+
+    fn block(number: BlockNumber) -> BlockId {
+        BlockId::new(BlockHash::random(), number)
+    }
+
+    fn status() -> SyncStatus<u32, BlockId> {
+        SyncStatus {
+            top_finalized: block(35),
+            favourite: block(40),
+            forks: 1,
+            pending_bodies: 0,
+            in_flight_requests: 2,
+            peers: Vec::new(),
+            peer_scores: Vec::new(),
+            serving: Vec::new(),
+            recent_events: vec!["handle_state", "broadcast"],
+            recent_errors: Vec::new(),
+        }
+    }
+
+    fn forest_dump() -> ForestDump<u32, BlockId> {
+        let vertex = |number, contents, interest| VertexDump {
+            id: block(number),
+            parent: None,
+            contents,
+            interest,
+            know_most: vec![7],
+        };
+        ForestDump {
+            root: block(35),
+            highest_justified: block(37),
+            vertices: vec![
+                vertex(36, VertexContents::Empty, VertexInterest::Required),
+                vertex(37, VertexContents::Justification, VertexInterest::Required),
+                vertex(38, VertexContents::Empty, VertexInterest::Auxiliary),
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn diagnoses_stalls_until_finality_resumes() {
+        let (sender, receiver) = mpsc::unbounded();
+        let status_reports = StatusReports::new();
+        let forest_dumps = ForestDumps::new();
+        let diagnostics = StallDiagnostics::new();
+        let watchdog = StallWatchdog::new(
+            MockNotifier { receiver },
+            STALL_TIMEOUT,
+            SessionBoundaryInfo::new(SessionPeriod(30)),
+            status_reports.clone(),
+            forest_dumps.clone(),
+            NetworkFinalityView::new(),
+            AbftMetrics::noop(),
+            diagnostics.clone(),
+        );
+        let responder = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    requests = status_reports.requests() => {
+                        for request in requests {
+                            let _ = request.send(status());
+                        }
+                    }
+                    requests = forest_dumps.requests() => {
+                        for request in requests {
+                            let _ = request.send(forest_dump());
+                        }
+                    }
+                }
+            }
+        });
+        let watchdog = tokio::spawn(watchdog.run());
+        while diagnostics.recent().is_empty() {
+            sleep(STALL_TIMEOUT).await;
+        }
+        sender.unbounded_send(36).expect("watchdog listens");
+        drop(sender);
+        watchdog.await.expect("should not panic");
+        responder.abort();
+
+        let diagnostic = diagnostics.recent().remove(0);
+        assert_eq!(diagnostic.last_finalized, 35);
+        assert_eq!(diagnostic.session, SessionId(1));
+        assert_eq!(diagnostic.abft_round, None);
+        assert!(diagnostic.stalled_for >= STALL_TIMEOUT);
+        let sync = diagnostic.sync.expect("sync responded");
+        assert_eq!(sync.recent_events, vec!["handle_state", "broadcast"]);
+        let forest = diagnostic.forest.expect("sync responded");
+        assert_eq!(
+            (forest.vertices, forest.justified, forest.required),
+            (3, 1, 1)
+        );
+    }
+
+    #[test]
+    fn keeps_latest_diagnostics() {
+        let diagnostics = StallDiagnostics::<u32, BlockId>::new();
+        for number in 0..MAX_DIAGNOSTICS as BlockNumber + 2 {
+            diagnostics.record(StallDiagnostic {
+                taken_at_unix_ms: 0,
+                stalled_for: STALL_TIMEOUT,
+                last_finalized: number,
+                session: SessionId(0),
+                abft_round: Some(3),
+                network_status: FinalityStatus::NoPeers,
+                network_stats: None,
+                sync: None,
+                forest: None,
+            });
+        }
+        let recent = diagnostics.recent();
+        assert_eq!(recent.len(), MAX_DIAGNOSTICS);
+        assert_eq!(recent[0].last_finalized, MAX_DIAGNOSTICS as BlockNumber + 1);
+    }
+}
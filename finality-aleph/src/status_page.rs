@@ -0,0 +1,328 @@
+use std::{convert::Infallible, fmt::Write, net::SocketAddr, time::Instant};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{debug, error};
+use sc_network::PeerId;
+use serde::Serialize;
+
+use crate::{
+    aleph_primitives::BlockNumber, stall_watchdog::ForestSummary, BlockId, SyncForestDumps,
+    SyncNetworkView, SyncStatus, SyncStatusReports,
+};
+
+const LOG_TARGET: &str = "aleph-status-page";
+/// How many of the peers are listed, the most recently updated ones.
+const MAX_PEERS: usize = 50;
+
+/// A block as shown on the page.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageBlock {
+    pub number: BlockNumber,
+    pub hash: String,
+}
+
+impl From<&BlockId> for PageBlock {
+    fn from(id: &BlockId) -> Self {
+        PageBlock {
+            number: id.number(),
+            hash: format!("{:?}", id.hash()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PagePeer {
+    pub peer: String,
+    pub finalized: BlockNumber,
+    pub age_secs: u64,
+    pub fresh: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageSync {
+    pub finalized: PageBlock,
+    pub favourite: PageBlock,
+    pub forks: usize,
+    pub pending_bodies: usize,
+    pub in_flight_requests: usize,
+    pub peers: Vec<PagePeer>,
+    pub recent_errors: Vec<String>,
+}
+
+impl From<SyncStatus<PeerId, BlockId>> for PageSync {
+    fn from(status: SyncStatus<PeerId, BlockId>) -> Self {
+        PageSync {
+            finalized: PageBlock::from(&status.top_finalized),
+            favourite: PageBlock::from(&status.favourite),
+            forks: status.forks,
+            pending_bodies: status.pending_bodies,
+            in_flight_requests: status.in_flight_requests,
+            peers: status
+                .peers
+                .into_iter()
+                .take(MAX_PEERS)
+                .map(|peer| PagePeer {
+                    peer: peer.peer.to_string(),
+                    finalized: peer.finalized,
+                    age_secs: peer.age.as_secs(),
+                    fresh: peer.fresh,
+                })
+                .collect(),
+            recent_errors: status.recent_errors,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageForest {
+    pub root: PageBlock,
+    pub highest_justified: PageBlock,
+    pub vertices: usize,
+    pub justified: usize,
+    pub required: usize,
+}
+
+impl From<ForestSummary<BlockId>> for PageForest {
+    fn from(summary: ForestSummary<BlockId>) -> Self {
+        PageForest {
+            root: PageBlock::from(&summary.root),
+            highest_justified: PageBlock::from(&summary.highest_justified),
+            vertices: summary.vertices,
+            justified: summary.justified,
+            required: summary.required,
+        }
+    }
+}
+
+/// Everything shown on the status page. The parts coming from sync are missing if it did not
+/// respond in time, which is worth knowing in itself.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusPage {
+    /// How we keep up with the finality of the network.
+    pub mode: String,
+    pub sync: Option<PageSync>,
+    pub forest: Option<PageForest>,
+}
+
+impl StatusPage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serializing the status page works")
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Aleph node status</title>\
+             <style>body{font-family:monospace}td,th{padding:0 1em;text-align:left}</style>\
+             </head><body>\n<h1>Aleph node status</h1>\n",
+        );
+        // Writing to a string cannot fail.
+        let _ = writeln!(html, "<p>Sync mode: {}</p>", escape(&self.mode));
+        match &self.sync {
+            Some(sync) => {
+                let _ = writeln!(
+                    html,
+                    "<h2>Sync</h2>\n<table>\n<tr><th>finalized</th><td>{}</td></tr>\n\
+                     <tr><th>favourite</th><td>{}</td></tr>\n<tr><th>forks</th><td>{}</td></tr>\n\
+                     <tr><th>pending bodies</th><td>{}</td></tr>\n\
+                     <tr><th>requests in flight</th><td>{}</td></tr>\n</table>",
+                    block_cell(&sync.finalized),
+                    block_cell(&sync.favourite),
+                    sync.forks,
+                    sync.pending_bodies,
+                    sync.in_flight_requests,
+                );
+                let _ = writeln!(
+                    html,
+                    "<h2>Peers</h2>\n<table>\n<tr><th>peer</th><th>finalized</th>\
+                     <th>updated</th><th>fresh</th></tr>"
+                );
+                for peer in &sync.peers {
+                    let _ = writeln!(
+                        html,
+                        "<tr><td>{}</td><td>{}</td><td>{}s ago</td><td>{}</td></tr>",
+                        escape(&peer.peer),
+                        peer.finalized,
+                        peer.age_secs,
+                        peer.fresh,
+                    );
+                }
+                let _ = writeln!(html, "</table>\n<h2>Recent errors</h2>\n<ul>");
+                for error in &sync.recent_errors {
+                    let _ = writeln!(html, "<li>{}</li>", escape(error));
+                }
+                let _ = writeln!(html, "</ul>");
+            }
+            None => {
+                let _ = writeln!(html, "<p>Sync did not respond in time.</p>");
+            }
+        }
+        if let Some(forest) = &self.forest {
+            let _ = writeln!(
+                html,
+                "<h2>Forest</h2>\n<table>\n<tr><th>root</th><td>{}</td></tr>\n\
+                 <tr><th>highest justified</th><td>{}</td></tr>\n\
+                 <tr><th>vertices</th><td>{}</td></tr>\n<tr><th>justified</th><td>{}</td></tr>\n\
+                 <tr><th>required</th><td>{}</td></tr>\n</table>",
+                block_cell(&forest.root),
+                block_cell(&forest.highest_justified),
+                forest.vertices,
+                forest.justified,
+                forest.required,
+            );
+        }
+        html.push_str("<p><a href=\"/status.json\">JSON</a></p>\n</body></html>\n");
+        html
+    }
+}
+
+fn block_cell(block: &PageBlock) -> String {
+    format!("#{} ({})", block.number, escape(&block.hash))
+}
+
+/// Escapes the text so that it cannot be interpreted as markup, the errors in particular can
+/// contain anything sent by peers.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// Where the contents of the status page come from.
+#[derive(Clone)]
+pub struct StatusSources {
+    pub status_reports: SyncStatusReports,
+    pub forest_dumps: SyncForestDumps,
+    pub network_view: SyncNetworkView,
+}
+
+impl StatusSources {
+    async fn page(&self) -> StatusPage {
+        let mode = self.network_view.status(Instant::now()).to_string();
+        let sync = self.status_reports.report().await.map(PageSync::from);
+        let forest = self
+            .forest_dumps
+            .dump()
+            .await
+            .map(|dump| PageForest::from(ForestSummary::from(&dump)));
+        StatusPage { mode, sync, forest }
+    }
+
+    async fn respond(&self, request: Request<Body>) -> Response<Body> {
+        if request.method() != Method::GET {
+            return status_response(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        let (content_type, body) = match request.uri().path() {
+            "/" => ("text/html; charset=utf-8", self.page().await.to_html()),
+            "/status.json" => ("application/json", self.page().await.to_json()),
+            _ => return status_response(StatusCode::NOT_FOUND),
+        };
+        Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .expect("the response is well formed")
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("the response is well formed")
+}
+
+/// Serves the status page at the address, the same way the Prometheus metrics are served, at
+/// `/` as HTML and at `/status.json` as JSON. Should be kept to local or trusted networks, every
+/// request asks sync for its status.
+pub async fn run_status_page(address: SocketAddr, sources: StatusSources) {
+    let make_service = make_service_fn(move |_| {
+        let sources = sources.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let sources = sources.clone();
+                async move { Ok::<_, Infallible>(sources.respond(request).await) }
+            }))
+        }
+    });
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            error!(target: LOG_TARGET, "Failed to bind the status page to {}: {}.", address, e);
+            return;
+        }
+    };
+    debug!(target: LOG_TARGET, "Serving the status page at {}.", address);
+    if let Err(e) = server.await {
+        error!(target: LOG_TARGET, "The status page server failed: {}.", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, PageBlock, PageSync, StatusPage};
+
+    fn page() -> StatusPage {
+        let block = PageBlock {
+            number: 7,
+            hash: "0x07".to_string(),
+        };
+        StatusPage {
+            mode: "in sync with the network".to_string(),
+            sync: Some(PageSync {
+                finalized: block.clone(),
+                favourite: block,
+                forks: 1,
+                pending_bodies: 0,
+                in_flight_requests: 2,
+                peers: Vec::new(),
+                recent_errors: vec!["bad <script>alert('x')</script> & more".to_string()],
+            }),
+            forest: None,
+        }
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape("<a href=\"x\">'&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+        let html = page().to_html();
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("bad &lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt; &amp; more"));
+        assert!(html.contains("#7 (0x07)"));
+    }
+
+    #[test]
+    fn renders_json() {
+        let json: serde_json::Value = serde_json::from_str(&page().to_json()).expect("valid json");
+        assert_eq!(json["mode"], "in sync with the network");
+        assert_eq!(json["sync"]["finalized"]["number"], 7);
+        assert_eq!(json["sync"]["inFlightRequests"], 2);
+        assert!(json["forest"].is_null());
+        let without_sync = StatusPage {
+            sync: None,
+            ..page()
+        };
+        assert!(without_sync
+            .to_html()
+            .contains("Sync did not respond in time."));
+    }
+}
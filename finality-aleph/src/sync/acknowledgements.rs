@@ -0,0 +1,241 @@
+use std::{
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+
+use crate::{sync::PeerId, BlockIdentifier};
+
+/// Distinguishes the acknowledgement requests sent by a single node.
+pub type AcknowledgementTag = u64;
+
+/// How many acknowledgements we wait for at once, the oldest requests are forgotten first.
+const MAX_PENDING_REQUESTS: usize = 256;
+/// How many peers we remember the delivered justifications of.
+const MAX_REMEMBERED_PEERS: usize = 256;
+/// How many recently delivered justifications we remember per peer.
+const MAX_DELIVERED_PER_PEER: usize = 16;
+/// A request not acknowledged for this long is considered unanswered.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(10);
+/// A peer leaving this many requests unanswered is considered not to support acknowledgements.
+const MAX_UNANSWERED: usize = 3;
+/// Peers not supporting acknowledgements are asked again after this long, they might have
+/// upgraded in the meantime.
+const UNSUPPORTED_RETRY: Duration = Duration::from_secs(600);
+/// For this long after the acknowledgement the justification is not resent to the peer. A few
+/// broadcast periods, so that a peer which got stuck anyway eventually receives it again.
+const DELIVERY_VALIDITY: Duration = Duration::from_secs(30);
+
+struct PendingRequest<I, BI> {
+    peer: I,
+    justifications: Vec<BI>,
+    sent: Instant,
+}
+
+#[derive(Default)]
+struct PeerDeliveries<BI> {
+    unsupported_since: Option<Instant>,
+    delivered: Vec<(BI, Instant)>,
+}
+
+/// Keeps track of the justifications sent directly to peers and acknowledged by them, so that
+/// they do not have to be resent while the peer is still catching up. Peers running older
+/// software never acknowledge anything, so they stop being asked after a few tries.
+pub struct Acknowledgements<I: PeerId, BI: BlockIdentifier> {
+    next_tag: AcknowledgementTag,
+    pending: LruCache<AcknowledgementTag, PendingRequest<I, BI>>,
+    peers: LruCache<I, PeerDeliveries<BI>>,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> Acknowledgements<I, BI> {
+    pub fn new() -> Self {
+        Acknowledgements {
+            next_tag: 0,
+            pending: LruCache::new(
+                NonZeroUsize::new(MAX_PENDING_REQUESTS).expect("the constant is nonzero"),
+            ),
+            peers: LruCache::new(
+                NonZeroUsize::new(MAX_REMEMBERED_PEERS).expect("the constant is nonzero"),
+            ),
+        }
+    }
+
+    fn peer_mut(&mut self, peer: &I) -> &mut PeerDeliveries<BI> {
+        if !self.peers.contains(peer) {
+            self.peers.put(peer.clone(), PeerDeliveries::default());
+        }
+        self.peers
+            .get_mut(peer)
+            .expect("the deliveries were just inserted")
+    }
+
+    /// Whether the peer left enough requests unanswered to be considered not supporting
+    /// acknowledgements. Forgets the unanswered requests in that case.
+    fn gave_up_answering(&mut self, peer: &I, now: Instant) -> bool {
+        let unanswered: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, request)| {
+                &request.peer == peer
+                    && now.saturating_duration_since(request.sent) >= ANSWER_TIMEOUT
+            })
+            .map(|(tag, _)| *tag)
+            .collect();
+        if unanswered.len() < MAX_UNANSWERED {
+            return false;
+        }
+        for tag in unanswered {
+            self.pending.pop(&tag);
+        }
+        true
+    }
+
+    /// Registers that the justifications were sent to the peer, returns the tag of the
+    /// acknowledgement to request, unless the peer is not expected to answer.
+    pub fn request(
+        &mut self,
+        peer: I,
+        justifications: Vec<BI>,
+        now: Instant,
+    ) -> Option<AcknowledgementTag> {
+        if justifications.is_empty() {
+            return None;
+        }
+        let deliveries = self.peer_mut(&peer);
+        match deliveries.unsupported_since {
+            Some(since) if now.saturating_duration_since(since) < UNSUPPORTED_RETRY => return None,
+            _ => deliveries.unsupported_since = None,
+        }
+        if self.gave_up_answering(&peer, now) {
+            self.peer_mut(&peer).unsupported_since = Some(now);
+            return None;
+        }
+        let tag = self.next_tag;
+        self.next_tag = self.next_tag.wrapping_add(1);
+        self.pending.put(
+            tag,
+            PendingRequest {
+                peer,
+                justifications,
+                sent: now,
+            },
+        );
+        Some(tag)
+    }
+
+    /// Registers the acknowledgement received from the peer. Returns whether it was expected.
+    pub fn acknowledged(&mut self, peer: I, tag: AcknowledgementTag, now: Instant) -> bool {
+        match self.pending.peek(&tag) {
+            Some(request) if request.peer == peer => {}
+            _ => return false,
+        }
+        let request = self.pending.pop(&tag).expect("the request was just found");
+        let deliveries = self.peer_mut(&peer);
+        deliveries.unsupported_since = None;
+        deliveries
+            .delivered
+            .retain(|(_, time)| now.saturating_duration_since(*time) < DELIVERY_VALIDITY);
+        for justification in request.justifications {
+            deliveries.delivered.retain(|(id, _)| id != &justification);
+            deliveries.delivered.push((justification, now));
+        }
+        let excess = deliveries
+            .delivered
+            .len()
+            .saturating_sub(MAX_DELIVERED_PER_PEER);
+        deliveries.delivered.drain(..excess);
+        true
+    }
+
+    /// Whether the peer recently acknowledged receiving the justification.
+    pub fn delivered(&self, peer: &I, justification: &BI, now: Instant) -> bool {
+        self.peers.peek(peer).map_or(false, |deliveries| {
+            deliveries.delivered.iter().any(|(id, time)| {
+                id == justification && now.saturating_duration_since(*time) < DELIVERY_VALIDITY
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{
+        Acknowledgements, ANSWER_TIMEOUT, DELIVERY_VALIDITY, MAX_UNANSWERED, UNSUPPORTED_RETRY,
+    };
+    use crate::sync::mock::{MockIdentifier, MockPeerId};
+
+    type MockAcknowledgements = Acknowledgements<MockPeerId, MockIdentifier>;
+
+    #[test]
+    fn remembers_acknowledged_justifications() {
+        let mut acknowledgements = MockAcknowledgements::new();
+        let now = Instant::now();
+        let id = MockIdentifier::new_random(7);
+        let tag = acknowledgements
+            .request(1, vec![id.clone()], now)
+            .expect("should request");
+        assert!(!acknowledgements.delivered(&1, &id, now));
+        assert!(acknowledgements.acknowledged(1, tag, now));
+        assert!(acknowledgements.delivered(&1, &id, now));
+        assert!(!acknowledgements.delivered(&2, &id, now));
+        assert!(!acknowledgements.delivered(&1, &id, now + DELIVERY_VALIDITY));
+    }
+
+    #[test]
+    fn rejects_unexpected_acknowledgements() {
+        let mut acknowledgements = MockAcknowledgements::new();
+        let now = Instant::now();
+        let id = MockIdentifier::new_random(7);
+        let tag = acknowledgements
+            .request(1, vec![id.clone()], now)
+            .expect("should request");
+        assert!(!acknowledgements.acknowledged(2, tag, now));
+        assert!(!acknowledgements.acknowledged(1, tag + 1, now));
+        assert!(acknowledgements.acknowledged(1, tag, now));
+        // Only acknowledged once.
+        assert!(!acknowledgements.acknowledged(1, tag, now));
+        assert!(!acknowledgements.delivered(&2, &id, now));
+    }
+
+    #[test]
+    fn stops_asking_peers_that_never_answer() {
+        let mut acknowledgements = MockAcknowledgements::new();
+        let mut now = Instant::now();
+        let id = MockIdentifier::new_random(7);
+        for _ in 0..MAX_UNANSWERED {
+            assert!(acknowledgements.request(1, vec![id.clone()], now).is_some());
+        }
+        now += ANSWER_TIMEOUT;
+        assert!(acknowledgements.request(1, vec![id.clone()], now).is_none());
+        assert!(acknowledgements.request(1, vec![id.clone()], now).is_none());
+        // Other peers are still asked.
+        assert!(acknowledgements.request(2, vec![id.clone()], now).is_some());
+        now += UNSUPPORTED_RETRY;
+        assert!(acknowledgements.request(1, vec![id], now).is_some());
+    }
+
+    #[test]
+    fn does_not_ask_for_nothing() {
+        let mut acknowledgements = MockAcknowledgements::new();
+        assert!(acknowledgements
+            .request(1, Vec::new(), Instant::now())
+            .is_none());
+    }
+
+    #[test]
+    fn keeps_asking_slow_peers() {
+        let mut acknowledgements = MockAcknowledgements::new();
+        let mut now = Instant::now();
+        let id = MockIdentifier::new_random(7);
+        for _ in 0..2 * MAX_UNANSWERED {
+            let tag = acknowledgements
+                .request(1, vec![id.clone()], now)
+                .expect("should request");
+            now += Duration::from_secs(1);
+            assert!(acknowledgements.acknowledged(1, tag, now));
+        }
+    }
+}
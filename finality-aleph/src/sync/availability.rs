@@ -0,0 +1,321 @@
+use std::{collections::HashSet, num::NonZeroUsize};
+
+use lru::LruCache;
+use parity_scale_codec::{Decode, Encode};
+
+use crate::{sync::PeerId, BlockNumber};
+
+/// How many peers we remember the availability of.
+const MAX_REMEMBERED_PEERS: usize = 256;
+/// How many ranges we accept from a single peer, the rest is ignored.
+const MAX_ENTRIES: usize = 64;
+
+/// Bits describing the kinds of historical data a node is able to serve.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// No historical data at all.
+    pub const NONE: Self = Capabilities(0);
+    /// Bodies of finalized blocks.
+    pub const BLOCK_BODIES: Self = Capabilities(1);
+    /// Answering requests for the bodies of ranges of finalized blocks.
+    pub const BODY_RANGES: Self = Capabilities(1 << 1);
+    /// Serving the followers belonging to our infrastructure with priority, once they present
+    /// their tickets.
+    pub const PRIORITY_SERVICE: Self = Capabilities(1 << 2);
+    /// Answering requests for chains of headers and justifications without the bodies. Every
+    /// node keeps all the headers, so this says nothing about historical data.
+    pub const HEADER_CHAINS: Self = Capabilities(1 << 3);
+    /// Answering requests for the justifications of the blocks ending sessions, used by nodes
+    /// warping ahead. Every node keeps them, so this says nothing about historical data either.
+    pub const WARP_PROOFS: Self = Capabilities(1 << 4);
+    /// Recognizing the validators among the peers from their tickets, to send them the requests
+    /// first. Says nothing about historical data.
+    pub const VALIDATOR_ROLES: Self = Capabilities(1 << 5);
+
+    /// Whether all the bits of `other` are also set in this.
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Capabilities containing bits from both this and `other`.
+    pub fn union(self, other: Self) -> Self {
+        Capabilities(self.0 | other.0)
+    }
+
+    /// Capabilities containing the bits of this that are not in `other`.
+    pub fn without(self, other: Self) -> Self {
+        Capabilities(self.0 & !other.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// An inclusive range of block numbers.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Range {
+    pub from: u32,
+    pub to: u32,
+}
+
+impl Range {
+    pub fn new(from: u32, to: u32) -> Self {
+        Range { from, to }
+    }
+
+    pub fn contains(&self, number: u32) -> bool {
+        self.from <= number && number <= self.to
+    }
+}
+
+/// Detailed description of the historical data a node is able to serve.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Availability {
+    /// Ranges of block numbers for which bodies are available.
+    pub bodies: Vec<Range>,
+}
+
+impl Availability {
+    /// The availability of a node with the given capabilities keeping all the finalized blocks
+    /// up to `top_finalized`.
+    pub fn for_archive(capabilities: Capabilities, top_finalized: BlockNumber) -> Self {
+        let bodies = match capabilities.contains(Capabilities::BLOCK_BODIES) {
+            true => vec![Range::new(0, top_finalized)],
+            false => Vec::new(),
+        };
+        Availability { bodies }
+    }
+
+    fn truncated(mut self) -> Self {
+        self.bodies.truncate(MAX_ENTRIES);
+        self
+    }
+
+    fn has_body(&self, number: BlockNumber) -> bool {
+        self.bodies.iter().any(|range| range.contains(number))
+    }
+}
+
+struct PeerRecord {
+    capabilities: Capabilities,
+    availability: Option<Availability>,
+}
+
+/// Remembers what historical data the peers announced they can serve, so that deep-history
+/// requests can be sent to the peers able to answer them.
+pub struct PeerAvailability<I: PeerId> {
+    peers: LruCache<I, PeerRecord>,
+}
+
+impl<I: PeerId> PeerAvailability<I> {
+    pub fn new() -> Self {
+        PeerAvailability {
+            peers: LruCache::new(
+                NonZeroUsize::new(MAX_REMEMBERED_PEERS).expect("the constant is nonzero"),
+            ),
+        }
+    }
+
+    /// Updates the capabilities announced by a peer.
+    /// Returns whether we should request the details of their availability, which is always the
+    /// case for peers able to serve anything more than headers, since the details change as the
+    /// chain grows.
+    pub fn update_capabilities(&mut self, peer: I, capabilities: Capabilities) -> bool {
+        match self.peers.get_mut(&peer) {
+            Some(record) if record.capabilities == capabilities => (),
+            _ => {
+                self.peers.put(
+                    peer,
+                    PeerRecord {
+                        capabilities,
+                        availability: None,
+                    },
+                );
+            }
+        }
+        !capabilities
+            .without(Capabilities::HEADER_CHAINS)
+            .without(Capabilities::WARP_PROOFS)
+            .without(Capabilities::VALIDATOR_ROLES)
+            .is_empty()
+    }
+
+    /// Updates the detailed availability of a peer. Ignored if the peer did not announce any
+    /// capabilities.
+    pub fn update_availability(&mut self, peer: I, availability: Availability) {
+        if let Some(record) = self.peers.get_mut(&peer) {
+            if !record.capabilities.is_empty() {
+                record.availability = Some(availability.truncated());
+            }
+        }
+    }
+
+    /// Forgets what the peer announced, it disconnected and has to announce it again.
+    pub fn forget_peer(&mut self, peer: &I) {
+        self.peers.pop(peer);
+    }
+
+    /// Peers that announced they are able to serve the body of the block with the given number.
+    pub fn peers_serving_body(&self, number: BlockNumber) -> HashSet<I> {
+        self.peers_with_body(number, Capabilities::BLOCK_BODIES)
+    }
+
+    /// Peers that announced they are able to serve the body of the block with the given number
+    /// in response to a body request.
+    pub fn peers_serving_body_ranges(&self, number: BlockNumber) -> HashSet<I> {
+        self.peers_with_body(
+            number,
+            Capabilities::BLOCK_BODIES.union(Capabilities::BODY_RANGES),
+        )
+    }
+
+    /// Peers that announced all the capabilities, regardless of the details of their availability.
+    pub fn peers_with(&self, capabilities: Capabilities) -> HashSet<I> {
+        self.peers
+            .iter()
+            .filter(|(_, record)| record.capabilities.contains(capabilities))
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+
+    fn peers_with_body(&self, number: BlockNumber, capabilities: Capabilities) -> HashSet<I> {
+        self.peers
+            .iter()
+            .filter(|(_, record)| {
+                record.capabilities.contains(capabilities)
+                    && record
+                        .availability
+                        .as_ref()
+                        .map(|availability| availability.has_body(number))
+                        .unwrap_or(false)
+            })
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{Availability, Capabilities, PeerAvailability, Range};
+
+    #[test]
+    fn combines_capabilities() {
+        let capabilities = Capabilities::BLOCK_BODIES.union(Capabilities::BODY_RANGES);
+        assert!(capabilities.contains(Capabilities::BLOCK_BODIES));
+        assert!(capabilities.contains(Capabilities::BODY_RANGES));
+        assert!(!capabilities.contains(Capabilities::HEADER_CHAINS));
+        assert!(capabilities.contains(Capabilities::NONE));
+        assert!(Capabilities::NONE.is_empty());
+    }
+
+    #[test]
+    fn requests_details_only_from_capable_peers() {
+        let mut peers = PeerAvailability::new();
+        assert!(!peers.update_capabilities(1, Capabilities::NONE));
+        assert!(peers.update_capabilities(2, Capabilities::BLOCK_BODIES));
+        assert!(peers.update_capabilities(2, Capabilities::BLOCK_BODIES));
+        assert!(!peers.update_capabilities(3, Capabilities::HEADER_CHAINS));
+        assert!(!peers.update_capabilities(
+            4,
+            Capabilities::HEADER_CHAINS.union(Capabilities::WARP_PROOFS)
+        ));
+        assert!(!peers.update_capabilities(5, Capabilities::VALIDATOR_ROLES));
+    }
+
+    #[test]
+    fn finds_peers_with_capabilities() {
+        let mut peers = PeerAvailability::new();
+        peers.update_capabilities(1, Capabilities::HEADER_CHAINS);
+        peers.update_capabilities(
+            2,
+            Capabilities::BLOCK_BODIES.union(Capabilities::HEADER_CHAINS),
+        );
+        peers.update_capabilities(3, Capabilities::BLOCK_BODIES);
+        assert_eq!(
+            peers.peers_with(Capabilities::HEADER_CHAINS),
+            HashSet::from([1, 2])
+        );
+        assert_eq!(
+            peers.peers_with(Capabilities::BLOCK_BODIES.without(Capabilities::BLOCK_BODIES)),
+            HashSet::from([1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn forgets_details_when_capabilities_change() {
+        let mut peers = PeerAvailability::new();
+        peers.update_capabilities(1, Capabilities::BLOCK_BODIES);
+        peers.update_availability(1, Availability::for_archive(Capabilities::BLOCK_BODIES, 10));
+        peers.update_capabilities(1, Capabilities::BLOCK_BODIES);
+        assert_eq!(peers.peers_serving_body(5), HashSet::from([1]));
+        peers.update_capabilities(
+            1,
+            Capabilities::BLOCK_BODIES.union(Capabilities::BODY_RANGES),
+        );
+        assert!(peers.peers_serving_body(5).is_empty());
+    }
+
+    #[test]
+    fn finds_peers_serving_bodies() {
+        let mut peers = PeerAvailability::new();
+        peers.update_capabilities(1, Capabilities::BLOCK_BODIES);
+        peers.update_availability(
+            1,
+            Availability::for_archive(Capabilities::BLOCK_BODIES, 100),
+        );
+        peers.update_capabilities(2, Capabilities::BLOCK_BODIES);
+        peers.update_availability(
+            2,
+            Availability {
+                bodies: vec![Range::new(50, 150)],
+            },
+        );
+        peers.update_capabilities(3, Capabilities::BODY_RANGES);
+        peers.update_availability(
+            3,
+            Availability {
+                bodies: vec![Range::new(0, 1000)],
+            },
+        );
+        assert_eq!(peers.peers_serving_body(10), HashSet::from([1]));
+        assert_eq!(peers.peers_serving_body(70), HashSet::from([1, 2]));
+        assert_eq!(peers.peers_serving_body(120), HashSet::from([2]));
+        assert!(peers.peers_serving_body(500).is_empty());
+    }
+
+    #[test]
+    fn finds_peers_serving_body_ranges() {
+        let mut peers = PeerAvailability::new();
+        let archive = Capabilities::BLOCK_BODIES.union(Capabilities::BODY_RANGES);
+        peers.update_capabilities(1, archive);
+        peers.update_availability(1, Availability::for_archive(archive, 100));
+        peers.update_capabilities(2, Capabilities::BLOCK_BODIES);
+        peers.update_availability(
+            2,
+            Availability::for_archive(Capabilities::BLOCK_BODIES, 100),
+        );
+        assert_eq!(peers.peers_serving_body(10), HashSet::from([1, 2]));
+        assert_eq!(peers.peers_serving_body_ranges(10), HashSet::from([1]));
+        assert!(peers.peers_serving_body_ranges(500).is_empty());
+    }
+
+    #[test]
+    fn ignores_availability_without_capabilities() {
+        let mut peers = PeerAvailability::new();
+        peers.update_availability(
+            1,
+            Availability::for_archive(Capabilities::BLOCK_BODIES, 100),
+        );
+        peers.update_capabilities(2, Capabilities::NONE);
+        peers.update_availability(
+            2,
+            Availability::for_archive(Capabilities::BLOCK_BODIES, 100),
+        );
+        assert!(peers.peers_serving_body(10).is_empty());
+    }
+}
@@ -0,0 +1,236 @@
+use std::{sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+
+use crate::{BlockIdentifier, BlockNumber};
+
+/// How often the backfill makes progress, rarely so that it does not compete with the regular
+/// sync.
+pub const BACKFILL_TICK: Duration = Duration::from_secs(1);
+/// How many finalized blocks are checked for a missing body in a single tick.
+const SCAN_BATCH: u32 = 256;
+/// The request for a missing body is repeated after this many ticks without an answer.
+const RETRY_TICKS: u32 = 10;
+
+/// How far the backfill of missing block bodies got.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BackfillProgress {
+    pub running: bool,
+    pub from: BlockNumber,
+    /// The last block to check, the top finalized block if `None`.
+    pub to: Option<BlockNumber>,
+    /// The next block to check.
+    pub next: BlockNumber,
+    /// How many requests for missing bodies were sent.
+    pub requests: u64,
+    /// How many missing bodies were fetched. A single response can carry many of them, so this
+    /// can exceed the number of requests.
+    pub fetched: u64,
+}
+
+/// Starts, stops and inspects the backfill of the bodies of finalized blocks missing from the
+/// database, e.g. after switching a node from pruning to archive. Can be cloned and used while
+/// sync is running.
+#[derive(Clone, Default)]
+pub struct BodyBackfill {
+    progress: Arc<Mutex<BackfillProgress>>,
+}
+
+impl BodyBackfill {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts checking the finalized blocks in the range, dropping any previous progress.
+    pub fn start(&self, from: BlockNumber, to: Option<BlockNumber>) {
+        *self.progress.lock() = BackfillProgress {
+            running: true,
+            from,
+            to,
+            next: from,
+            ..BackfillProgress::default()
+        };
+    }
+
+    /// Stops the backfill, the progress can still be inspected.
+    pub fn stop(&self) {
+        self.progress.lock().running = false;
+    }
+
+    pub fn progress(&self) -> BackfillProgress {
+        self.progress.lock().clone()
+    }
+}
+
+/// The part of the backfill living in the sync service, with at most one request in flight.
+pub struct BackfillTask<BI: BlockIdentifier> {
+    control: BodyBackfill,
+    awaited: Option<(BI, u32)>,
+}
+
+impl<BI: BlockIdentifier> BackfillTask<BI> {
+    pub fn new(control: BodyBackfill) -> Self {
+        BackfillTask {
+            control,
+            awaited: None,
+        }
+    }
+
+    /// Makes progress, checking whether blocks up to `top_finalized` have their bodies with
+    /// `missing`, which returns the identifier of the block with the given number if its body is
+    /// missing. Returns the block whose body should be requested now, if any.
+    pub fn tick<E>(
+        &mut self,
+        top_finalized: BlockNumber,
+        mut missing: impl FnMut(BlockNumber) -> Result<Option<BI>, E>,
+    ) -> Result<Option<BI>, E> {
+        let mut progress = self.control.progress.lock();
+        if !progress.running {
+            self.awaited = None;
+            return Ok(None);
+        }
+        if let Some((id, ticks)) = &mut self.awaited {
+            *ticks += 1;
+            if *ticks < RETRY_TICKS {
+                return Ok(None);
+            }
+            // The body might have arrived some other way in the meantime.
+            return Ok(match missing(id.number())? {
+                Some(id) => {
+                    progress.requests += 1;
+                    self.awaited = Some((id.clone(), 0));
+                    Some(id)
+                }
+                None => {
+                    self.awaited = None;
+                    None
+                }
+            });
+        }
+        let last = progress.to.unwrap_or(top_finalized).min(top_finalized);
+        let batch_end = progress.next.saturating_add(SCAN_BATCH);
+        while progress.next <= last && progress.next < batch_end {
+            let number = progress.next;
+            progress.next += 1;
+            if let Some(id) = missing(number)? {
+                progress.requests += 1;
+                self.awaited = Some((id.clone(), 0));
+                return Ok(Some(id));
+            }
+        }
+        if progress.next > last {
+            progress.running = false;
+        }
+        Ok(None)
+    }
+
+    /// Whether the backfill is running at all.
+    pub fn wants_anything(&self) -> bool {
+        self.control.progress.lock().running
+    }
+
+    pub fn stop(&mut self) {
+        self.awaited = None;
+        self.control.stop();
+    }
+
+    /// Whether the backfill would take the body of the block, if it turns out to be missing.
+    pub fn wants(&self, id: &BI) -> bool {
+        let progress = self.control.progress.lock();
+        progress.running
+            && id.number() >= progress.from
+            && progress.to.map_or(true, |to| id.number() <= to)
+    }
+
+    /// Records that the missing body of the block was fetched.
+    pub fn body_fetched(&mut self, id: &BI) {
+        if matches!(&self.awaited, Some((awaited, _)) if awaited == id) {
+            self.awaited = None;
+        }
+        self.control.progress.lock().fetched += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::{BackfillTask, BodyBackfill, RETRY_TICKS, SCAN_BATCH};
+    use crate::{sync::mock::MockIdentifier, BlockIdentifier};
+
+    fn missing_even(number: u32) -> Result<Option<MockIdentifier>, Infallible> {
+        Ok((number % 2 == 0).then(|| MockIdentifier::new_random(number)))
+    }
+
+    #[test]
+    fn idle_until_started() {
+        let mut task = BackfillTask::new(BodyBackfill::new());
+        assert_eq!(task.tick(100, missing_even), Ok(None));
+    }
+
+    #[test]
+    fn fetches_missing_bodies_one_at_a_time() {
+        let control = BodyBackfill::new();
+        let mut task = BackfillTask::new(control.clone());
+        control.start(1, Some(4));
+        let id = task
+            .tick(100, missing_even)
+            .expect("infallible")
+            .expect("block 2 is missing");
+        assert_eq!(id.number(), 2);
+        assert_eq!(task.tick(100, missing_even), Ok(None));
+        task.body_fetched(&id);
+        let id = task
+            .tick(100, missing_even)
+            .expect("infallible")
+            .expect("block 4 is missing");
+        assert_eq!(id.number(), 4);
+        task.body_fetched(&id);
+        assert_eq!(task.tick(100, missing_even), Ok(None));
+        let progress = control.progress();
+        assert!(!progress.running);
+        assert_eq!(progress.requests, 2);
+        assert_eq!(progress.fetched, 2);
+    }
+
+    #[test]
+    fn retries_unanswered_requests() {
+        let control = BodyBackfill::new();
+        let mut task = BackfillTask::new(control.clone());
+        control.start(0, None);
+        assert!(task.tick(10, missing_even).expect("infallible").is_some());
+        for _ in 1..RETRY_TICKS {
+            assert_eq!(task.tick(10, missing_even), Ok(None));
+        }
+        let id = task
+            .tick(10, missing_even)
+            .expect("infallible")
+            .expect("should retry");
+        assert_eq!(id.number(), 0);
+    }
+
+    #[test]
+    fn wants_bodies_in_range() {
+        let control = BodyBackfill::new();
+        let task = BackfillTask::new(control.clone());
+        let id = MockIdentifier::new_random(5);
+        assert!(!task.wants(&id));
+        control.start(3, Some(7));
+        assert!(task.wants(&id));
+        assert!(!task.wants(&MockIdentifier::new_random(8)));
+        control.stop();
+        assert!(!task.wants(&id));
+    }
+
+    #[test]
+    fn scans_in_batches() {
+        let control = BodyBackfill::new();
+        let mut task = BackfillTask::new(control.clone());
+        control.start(0, None);
+        let nothing_missing = |_| Ok::<_, Infallible>(None::<MockIdentifier>);
+        assert_eq!(task.tick(10 * SCAN_BATCH, nothing_missing), Ok(None));
+        let progress = control.progress();
+        assert!(progress.running);
+        assert_eq!(progress.next, SCAN_BATCH);
+    }
+}
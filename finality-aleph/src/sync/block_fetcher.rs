@@ -0,0 +1,208 @@
+use std::{
+    collections::HashSet,
+    fmt::{Debug, Display, Error as FmtError, Formatter},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::{pending, BoxFuture, FutureExt};
+
+use crate::sync::{Block, Header};
+
+/// How long the external source is not asked after a failed fetch, the peers are asked instead.
+const FAILURE_BACKOFF: Duration = Duration::from_secs(30);
+
+type BlockIdOf<B> = <<B as Block>::Header as Header>::Identifier;
+
+/// A source of the blocks of the finalized chain other than the peers, e.g. an exchange or a
+/// CDN serving archives over HTTPS. The blocks it returns are not trusted, only the ones matching
+/// headers we already know get imported.
+#[async_trait::async_trait]
+pub trait BlockFetcher<B: Block>: Send + Sync + 'static {
+    /// Fetches the blocks with the identifiers, the ones the source does not have can be left
+    /// out.
+    async fn fetch(&self, ids: Vec<BlockIdOf<B>>) -> Result<Vec<B>, String>;
+}
+
+/// What can go wrong with fetching from the external source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FetchError<BI> {
+    Source(String),
+    /// The source has none of the blocks, the peers have to be asked for them.
+    NothingFetched,
+    NotRequested(BI),
+    BodyMismatch(BI),
+}
+
+impl<BI: Debug> Display for FetchError<BI> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use FetchError::*;
+        match self {
+            Source(e) => write!(f, "the source failed: {e}"),
+            NothingFetched => write!(f, "the source has none of the blocks"),
+            NotRequested(id) => write!(f, "got block {id:?}, which was not requested"),
+            BodyMismatch(id) => write!(f, "the body of block {id:?} does not match its header"),
+        }
+    }
+}
+
+type PendingFetch<B> = BoxFuture<'static, (HashSet<BlockIdOf<B>>, Result<Vec<B>, String>)>;
+
+/// The part of the external source living in the sync service, with at most one fetch in
+/// flight. After a failed fetch the source is left alone for a while, so that the bodies keep
+/// coming from peers.
+pub struct ExternalBodies<B: Block> {
+    fetcher: Option<Arc<dyn BlockFetcher<B>>>,
+    in_flight: Option<PendingFetch<B>>,
+    backoff_until: Option<Instant>,
+}
+
+impl<B: Block> ExternalBodies<B> {
+    pub fn new(fetcher: Option<Arc<dyn BlockFetcher<B>>>) -> Self {
+        ExternalBodies {
+            fetcher,
+            in_flight: None,
+            backoff_until: None,
+        }
+    }
+
+    /// Whether the bodies should be fetched from the external source rather than from peers.
+    pub fn is_usable(&self, now: Instant) -> bool {
+        self.fetcher.is_some() && self.backoff_until.map_or(true, |until| now >= until)
+    }
+
+    /// Whether a fetch is in flight, nothing else should be requested until it is done.
+    pub fn is_fetching(&self) -> bool {
+        self.in_flight.is_some()
+    }
+
+    /// Starts fetching the blocks, unless there is no source or a fetch is already in flight.
+    pub fn start(&mut self, ids: Vec<BlockIdOf<B>>) {
+        let fetcher = match (&self.fetcher, &self.in_flight) {
+            (Some(fetcher), None) => fetcher.clone(),
+            _ => return,
+        };
+        self.in_flight = Some(
+            async move {
+                let requested = ids.iter().cloned().collect();
+                (requested, fetcher.fetch(ids).await)
+            }
+            .boxed(),
+        );
+    }
+
+    /// Waits for the fetch in flight to finish, returning the fetched blocks once they are all
+    /// checked against their headers. A single bad block discards the whole fetch, the source
+    /// cannot be trusted with the rest either.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancellation safe.
+    pub async fn next(&mut self) -> Result<Vec<B>, FetchError<BlockIdOf<B>>> {
+        let fetch = match &mut self.in_flight {
+            Some(fetch) => fetch,
+            None => return pending().await,
+        };
+        let (requested, result) = fetch.await;
+        self.in_flight = None;
+        let checked = result.map_err(FetchError::Source).and_then(|blocks| {
+            if blocks.is_empty() {
+                return Err(FetchError::NothingFetched);
+            }
+            for block in &blocks {
+                let id = block.header().id();
+                if !requested.contains(&id) {
+                    return Err(FetchError::NotRequested(id));
+                }
+                if !block.body_matches_header() {
+                    return Err(FetchError::BodyMismatch(id));
+                }
+            }
+            Ok(blocks)
+        });
+        if checked.is_err() {
+            self.backoff_until = Some(Instant::now() + FAILURE_BACKOFF);
+        }
+        checked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Instant};
+
+    use futures::FutureExt;
+
+    use super::{BlockFetcher, ExternalBodies, FetchError};
+    use crate::sync::{
+        mock::{MockBlock, MockHeader, MockIdentifier},
+        Header,
+    };
+
+    /// Returns the blocks it has, out of the ones asked for.
+    struct Archive(Vec<MockBlock>);
+
+    #[async_trait::async_trait]
+    impl BlockFetcher<MockBlock> for Archive {
+        async fn fetch(&self, ids: Vec<MockIdentifier>) -> Result<Vec<MockBlock>, String> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|block| ids.contains(&block.header().id()))
+                .cloned()
+                .collect())
+        }
+    }
+
+    struct Failing;
+
+    #[async_trait::async_trait]
+    impl BlockFetcher<MockBlock> for Failing {
+        async fn fetch(&self, _: Vec<MockIdentifier>) -> Result<Vec<MockBlock>, String> {
+            Err("unreachable".to_string())
+        }
+    }
+
+    fn blocks(correct: bool) -> Vec<MockBlock> {
+        let genesis = MockHeader::genesis();
+        let first = genesis.random_child();
+        let second = first.random_child();
+        vec![MockBlock::new(first, true), MockBlock::new(second, correct)]
+    }
+
+    #[tokio::test]
+    async fn fetches_matching_blocks() {
+        let archive = blocks(true);
+        let ids = archive.iter().map(|block| block.header().id()).collect();
+        let mut bodies = ExternalBodies::<MockBlock>::new(Some(Arc::new(Archive(archive.clone()))));
+        assert!(bodies.is_usable(Instant::now()));
+        assert!(bodies.next().now_or_never().is_none());
+        bodies.start(ids);
+        assert!(bodies.is_fetching());
+        assert_eq!(bodies.next().await, Ok(archive));
+        assert!(!bodies.is_fetching());
+        assert!(bodies.is_usable(Instant::now()));
+    }
+
+    #[tokio::test]
+    async fn backs_off_from_bad_sources() {
+        let archive = blocks(false);
+        let ids: Vec<_> = archive.iter().map(|block| block.header().id()).collect();
+        let mut bodies = ExternalBodies::<MockBlock>::new(Some(Arc::new(Archive(archive))));
+        bodies.start(ids.clone());
+        assert_eq!(
+            bodies.next().await,
+            Err(FetchError::BodyMismatch(ids[1].clone()))
+        );
+        assert!(!bodies.is_usable(Instant::now()));
+        let mut bodies = ExternalBodies::<MockBlock>::new(Some(Arc::new(Failing)));
+        bodies.start(ids.clone());
+        assert!(matches!(bodies.next().await, Err(FetchError::Source(_))));
+        assert!(!bodies.is_usable(Instant::now()));
+        let mut bodies = ExternalBodies::<MockBlock>::new(Some(Arc::new(Archive(Vec::new()))));
+        bodies.start(ids);
+        assert_eq!(bodies.next().await, Err(FetchError::NothingFetched));
+        assert!(!bodies.is_usable(Instant::now()));
+        assert!(!ExternalBodies::<MockBlock>::new(None).is_usable(Instant::now()));
+    }
+}
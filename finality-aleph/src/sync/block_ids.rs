@@ -0,0 +1,45 @@
+use crate::{BlockIdentifier, BlockNumber};
+
+/// The identifier of a block known to be finalized, because the chain status says so, or because
+/// it just got finalized. Not obtainable from a `CandidateId`, so that blocks we only have
+/// justifications for are never treated as finalized by accident.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct FinalizedId<BI: BlockIdentifier>(BI);
+
+impl<BI: BlockIdentifier> FinalizedId<BI> {
+    /// Only for blocks the chain status reports as finalized, or that the finalizer finalized.
+    pub fn new(id: BI) -> Self {
+        FinalizedId(id)
+    }
+
+    pub fn id(&self) -> &BI {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> BI {
+        self.0
+    }
+
+    pub fn number(&self) -> BlockNumber {
+        self.0.number()
+    }
+}
+
+/// The identifier of a block that might get finalized, e.g. one with a verified justification
+/// still waiting for its ancestors, or one announced by a peer.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct CandidateId<BI: BlockIdentifier>(BI);
+
+impl<BI: BlockIdentifier> CandidateId<BI> {
+    pub fn new(id: BI) -> Self {
+        CandidateId(id)
+    }
+
+    pub fn id(&self) -> &BI {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> BI {
+        self.0
+    }
+}
@@ -0,0 +1,527 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::{Display, Error as FmtError, Formatter},
+    fs::{self, File},
+    io::{BufReader, BufWriter, Error as IoError, ErrorKind, Read, Result as IoResult, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, warn};
+use parity_scale_codec::{Decode, Encode, Error as CodecError};
+
+use crate::{
+    network::{CancelToken, ConnectionEvents, Data, GossipNetwork, Penalty},
+    sync::{data::MAX_SYNC_MESSAGE_SIZE, LOG_TARGET},
+};
+
+/// Every capture file starts with this, followed by the format version.
+const MAGIC: [u8; 8] = *b"ALEPHCAP";
+const FORMAT_VERSION: u16 = 1;
+const FILE_PREFIX: &str = "sync-capture-";
+const FILE_EXTENSION: &str = "bin";
+/// Records above this size cannot come from a correct capture, they would contain a frame larger
+/// than any sync message plus a lot of peer ids.
+const MAX_RECORD_BYTES: u32 = MAX_SYNC_MESSAGE_SIZE + 1024 * 1024;
+/// How many records can wait for being written, further ones are dropped rather than slowing
+/// sync down.
+const MAX_QUEUED_RECORDS: usize = 1024;
+/// How often we warn about dropping records.
+const DROPPED_WARNING_INTERVAL: u64 = 1000;
+
+/// Where and how much of the sync traffic to capture.
+#[derive(Clone, Debug)]
+pub struct CaptureConfig {
+    /// The directory the capture files are written to.
+    pub directory: PathBuf,
+    /// The size after which a new capture file is started.
+    pub max_file_bytes: u64,
+    /// How many capture files are kept, the oldest ones are removed.
+    pub max_files: usize,
+}
+
+/// How the captured frame travelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum Direction {
+    /// Received from the single peer in the record.
+    Received,
+    /// Sent to the single peer in the record.
+    Sent,
+    /// Sent to one of the peers in the record, or any peer if there are none.
+    SentToRandom,
+    /// Broadcast to all the connected peers.
+    Broadcast,
+}
+
+/// A single captured frame, exactly as it is encoded on the wire.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct CaptureRecord {
+    pub timestamp_millis: u64,
+    pub direction: Direction,
+    pub peers: Vec<String>,
+    pub frame: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// Decodes the frame, usually as `VersionedNetworkData`.
+    pub fn decode_frame<D: Decode>(&self) -> Result<D, CodecError> {
+        D::decode(&mut &self.frame[..])
+    }
+}
+
+/// Ways in which reading a capture can fail.
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(IoError),
+    /// The file does not start like a capture file.
+    NotACapture,
+    UnsupportedFormat(u16),
+    RecordTooLong(u32),
+    Codec(CodecError),
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use CaptureError::*;
+        match self {
+            Io(e) => write!(f, "io error: {e}"),
+            NotACapture => write!(f, "not a sync capture file"),
+            UnsupportedFormat(version) => {
+                write!(f, "unsupported capture format version {version}")
+            }
+            RecordTooLong(length) => write!(f, "record of {length} bytes is too long"),
+            Codec(e) => write!(f, "malformed record: {e}"),
+        }
+    }
+}
+
+impl From<IoError> for CaptureError {
+    fn from(e: IoError) -> Self {
+        CaptureError::Io(e)
+    }
+}
+
+impl From<CodecError> for CaptureError {
+    fn from(e: CodecError) -> Self {
+        CaptureError::Codec(e)
+    }
+}
+
+fn file_index(path: &Path) -> Option<u64> {
+    if path.extension()? != FILE_EXTENSION {
+        return None;
+    }
+    path.file_stem()?
+        .to_str()?
+        .strip_prefix(FILE_PREFIX)?
+        .parse()
+        .ok()
+}
+
+/// The capture files in the directory, oldest first.
+pub fn capture_files(directory: &Path) -> IoResult<Vec<PathBuf>> {
+    let mut files: Vec<_> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter_map(|path| file_index(&path).map(|index| (index, path)))
+        .collect();
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Reads the records from a single capture file.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, CaptureError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, CaptureError> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(CaptureError::NotACapture);
+        }
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        match u16::from_le_bytes(version) {
+            FORMAT_VERSION => Ok(CaptureReader { reader }),
+            version => Err(CaptureError::UnsupportedFormat(version)),
+        }
+    }
+
+    fn read_record(&mut self) -> Result<Option<CaptureRecord>, CaptureError> {
+        let mut length = [0; 4];
+        // A clean end of the file can only happen between records.
+        match self.reader.read(&mut length[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut length[1..])?,
+        }
+        let length = u32::from_le_bytes(length);
+        if length > MAX_RECORD_BYTES {
+            return Err(CaptureError::RecordTooLong(length));
+        }
+        let mut record = vec![0; length as usize];
+        self.reader.read_exact(&mut record)?;
+        Ok(Some(CaptureRecord::decode(&mut &record[..])?))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Writes records to capture files in a directory, starting a new file when the current one gets
+/// too big and removing the oldest ones.
+struct RotatingWriter {
+    config: CaptureConfig,
+    files: VecDeque<PathBuf>,
+    next_index: u64,
+    current: Option<(BufWriter<File>, u64)>,
+}
+
+impl RotatingWriter {
+    fn new(config: CaptureConfig) -> IoResult<Self> {
+        fs::create_dir_all(&config.directory)?;
+        let files: VecDeque<_> = capture_files(&config.directory)?.into();
+        let next_index = files
+            .back()
+            .and_then(|path| file_index(path))
+            .map_or(0, |index| index + 1);
+        Ok(RotatingWriter {
+            config,
+            files,
+            next_index,
+            current: None,
+        })
+    }
+
+    fn rotate(&mut self) -> IoResult<()> {
+        if let Some((mut file, _)) = self.current.take() {
+            file.flush()?;
+        }
+        let path = self.config.directory.join(format!(
+            "{}{:010}.{}",
+            FILE_PREFIX, self.next_index, FILE_EXTENSION
+        ));
+        self.next_index += 1;
+        let mut file = BufWriter::new(File::create(&path)?);
+        file.write_all(&MAGIC)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        self.files.push_back(path);
+        while self.files.len() > self.config.max_files.max(1) {
+            if let Some(oldest) = self.files.pop_front() {
+                // Possibly already evicted to stay within the disk quota.
+                match fs::remove_file(&oldest) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => warn!(
+                        target: LOG_TARGET,
+                        "Failed to remove old capture file {:?}: {}.", oldest, e
+                    ),
+                    _ => (),
+                }
+            }
+        }
+        self.current = Some((file, (MAGIC.len() + 2) as u64));
+        Ok(())
+    }
+
+    fn write(&mut self, record: &CaptureRecord) -> IoResult<()> {
+        let encoded = record.encode();
+        let length: u32 = encoded
+            .len()
+            .try_into()
+            .map_err(|_| IoError::new(ErrorKind::InvalidData, "record too long"))?;
+        let full = match &self.current {
+            Some((_, written)) => *written >= self.config.max_file_bytes,
+            None => true,
+        };
+        if full {
+            self.rotate()?;
+        }
+        let (file, written) = self.current.as_mut().expect("we just rotated");
+        file.write_all(&length.to_le_bytes())?;
+        file.write_all(&encoded)?;
+        *written += 4 + encoded.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match &mut self.current {
+            Some((file, _)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the record and all the ones that are already waiting, then flushes.
+    fn write_pending(
+        &mut self,
+        record: CaptureRecord,
+        records: &Receiver<CaptureRecord>,
+    ) -> IoResult<()> {
+        self.write(&record)?;
+        for record in records.try_iter() {
+            self.write(&record)?;
+        }
+        self.flush()
+    }
+
+    fn run(mut self, records: Receiver<CaptureRecord>) {
+        while let Ok(record) = records.recv() {
+            if let Err(e) = self.write_pending(record, &records) {
+                warn!(
+                    target: LOG_TARGET,
+                    "Failed to write sync capture, stopping it: {}.", e
+                );
+                return;
+            }
+        }
+    }
+}
+
+/// Wraps a network, writing all the frames passing through it to capture files on a separate
+/// thread. Without a config it just passes the data through.
+pub struct Capturing<N> {
+    inner: N,
+    records: Option<SyncSender<CaptureRecord>>,
+    dropped: u64,
+}
+
+impl<N> Capturing<N> {
+    pub fn new(inner: N, config: Option<CaptureConfig>) -> Self {
+        let records = config.and_then(|config| {
+            let directory = config.directory.clone();
+            let writer = match RotatingWriter::new(config) {
+                Ok(writer) => writer,
+                Err(e) => {
+                    warn!(
+                        target: LOG_TARGET,
+                        "Failed to start sync capture in {:?}: {}.", directory, e
+                    );
+                    return None;
+                }
+            };
+            let (records_for_writer, records) = sync_channel(MAX_QUEUED_RECORDS);
+            if let Err(e) = thread::Builder::new()
+                .name("aleph-sync-capture".to_string())
+                .spawn(move || writer.run(records))
+            {
+                warn!(
+                    target: LOG_TARGET,
+                    "Failed to start sync capture thread: {}.", e
+                );
+                return None;
+            }
+            debug!(
+                target: LOG_TARGET,
+                "Capturing sync traffic in {:?}.", directory
+            );
+            Some(records_for_writer)
+        });
+        Capturing {
+            inner,
+            records,
+            dropped: 0,
+        }
+    }
+
+    fn capture<D: Encode>(&mut self, direction: Direction, peers: Vec<String>, data: &D) {
+        let records = match &self.records {
+            Some(records) => records,
+            None => return,
+        };
+        let timestamp_millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|timestamp| timestamp.as_millis() as u64)
+            .unwrap_or(0);
+        let record = CaptureRecord {
+            timestamp_millis,
+            direction,
+            peers,
+            frame: data.encode(),
+        };
+        match records.try_send(record) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                if self.dropped % DROPPED_WARNING_INTERVAL == 0 {
+                    warn!(
+                        target: LOG_TARGET,
+                        "Sync capture cannot keep up, {} records dropped so far.",
+                        self.dropped + 1
+                    );
+                }
+                self.dropped += 1;
+            }
+            Err(TrySendError::Disconnected(_)) => self.records = None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<D, N> GossipNetwork<D> for Capturing<N>
+where
+    D: Data,
+    N: GossipNetwork<D>,
+    N::PeerId: Display,
+{
+    type Error = N::Error;
+    type PeerId = N::PeerId;
+
+    fn send_to(&mut self, data: D, peer_id: Self::PeerId) -> Result<(), Self::Error> {
+        self.capture(Direction::Sent, vec![peer_id.to_string()], &data);
+        self.inner.send_to(data, peer_id)
+    }
+
+    fn send_to_random(
+        &mut self,
+        data: D,
+        peer_ids: HashSet<Self::PeerId>,
+    ) -> Result<(), Self::Error> {
+        let peers = peer_ids.iter().map(|peer| peer.to_string()).collect();
+        self.capture(Direction::SentToRandom, peers, &data);
+        self.inner.send_to_random(data, peer_ids)
+    }
+
+    fn broadcast(&mut self, data: D) -> Result<(), Self::Error> {
+        self.capture(Direction::Broadcast, Vec::new(), &data);
+        self.inner.broadcast(data)
+    }
+
+    fn send_request(&mut self, data: D, peer_id: Self::PeerId) -> Result<(), Self::Error> {
+        self.capture(Direction::Sent, vec![peer_id.to_string()], &data);
+        self.inner.send_request(data, peer_id)
+    }
+
+    fn send_request_to_random(
+        &mut self,
+        data: D,
+        peer_ids: HashSet<Self::PeerId>,
+    ) -> Result<(), Self::Error> {
+        let peers = peer_ids.iter().map(|peer| peer.to_string()).collect();
+        self.capture(Direction::SentToRandom, peers, &data);
+        self.inner.send_request_to_random(data, peer_ids)
+    }
+
+    fn send_response(&mut self, data: D, peer_id: Self::PeerId) -> Result<(), Self::Error> {
+        self.capture(Direction::Sent, vec![peer_id.to_string()], &data);
+        self.inner.send_response(data, peer_id)
+    }
+
+    fn penalize(&mut self, peer_id: Self::PeerId, penalty: Penalty) -> Result<(), Self::Error> {
+        self.inner.penalize(peer_id, penalty)
+    }
+
+    fn connection(&self, peer_id: &Self::PeerId) -> CancelToken {
+        self.inner.connection(peer_id)
+    }
+
+    fn connection_events(&self) -> ConnectionEvents<Self::PeerId> {
+        self.inner.connection_events()
+    }
+
+    /// Retrieves next message from the network.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancellation safe.
+    async fn next(&mut self) -> Result<(D, Self::PeerId), Self::Error> {
+        let (data, peer_id) = self.inner.next().await?;
+        self.capture(Direction::Received, vec![peer_id.to_string()], &data);
+        Ok((data, peer_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{
+        capture_files, CaptureConfig, CaptureError, CaptureReader, CaptureRecord, Direction,
+        RotatingWriter,
+    };
+    use crate::testing::TestDirectory;
+
+    fn record(frame: Vec<u8>) -> CaptureRecord {
+        CaptureRecord {
+            timestamp_millis: 43,
+            direction: Direction::Received,
+            peers: vec!["peer".to_string()],
+            frame,
+        }
+    }
+
+    fn read_all(directory: &Path) -> Vec<CaptureRecord> {
+        capture_files(directory)
+            .expect("directory exists")
+            .iter()
+            .flat_map(|path| CaptureReader::open(path).expect("file is a capture"))
+            .map(|record| record.expect("record is correct"))
+            .collect()
+    }
+
+    #[test]
+    fn reads_written_records() {
+        let directory = TestDirectory::new("sync-capture");
+        let config = CaptureConfig {
+            directory: directory.to_path_buf(),
+            max_file_bytes: 1024 * 1024,
+            max_files: 2,
+        };
+        let records: Vec<_> = (0..10).map(|i| record(vec![i; 100])).collect();
+        let mut writer = RotatingWriter::new(config).expect("directory can be created");
+        for record in &records {
+            writer.write(record).expect("writing works");
+        }
+        writer.flush().expect("flushing works");
+        assert_eq!(read_all(&directory), records);
+        let decoded: Vec<u8> = records[3].decode_frame().expect("frame is a vector");
+        assert_eq!(decoded, vec![3; 100]);
+    }
+
+    #[test]
+    fn rotates_and_removes_old_files() {
+        let directory = TestDirectory::new("sync-capture");
+        let config = CaptureConfig {
+            directory: directory.to_path_buf(),
+            max_file_bytes: 1000,
+            max_files: 3,
+        };
+        let records: Vec<_> = (0..20).map(|i| record(vec![i; 400])).collect();
+        let mut writer = RotatingWriter::new(config.clone()).expect("directory can be created");
+        for record in &records {
+            writer.write(record).expect("writing works");
+        }
+        writer.flush().expect("flushing works");
+        assert_eq!(
+            capture_files(&directory).expect("directory exists").len(),
+            3
+        );
+        assert_eq!(read_all(&directory), records[12..].to_vec());
+
+        // A restarted writer continues after the existing files.
+        let mut writer = RotatingWriter::new(config).expect("directory exists");
+        writer.write(&records[0]).expect("writing works");
+        writer.flush().expect("flushing works");
+        let read = read_all(&directory);
+        assert_eq!(read.last(), Some(&records[0]));
+        assert_eq!(read.len(), 6);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            CaptureReader::new(&b"definitely not a capture"[..]),
+            Err(CaptureError::NotACapture)
+        ));
+    }
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::{sync::Header, BlockIdentifier};
+
+/// The blocks our favourite block is chosen out of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainCandidates<H: Header> {
+    /// The best block according to the chain status, normally the tip of the longest chain.
+    pub best: H,
+    /// The highest imported block with a verified justification, not finalized yet, if any.
+    pub highest_justified: Option<H>,
+    pub top_finalized: H,
+}
+
+/// The fork choice of sync, i.e. which branch we extend and advertise to peers as our favourite,
+/// and which forks advertised by peers are worth requesting. The favourite block has to be
+/// imported, otherwise the best block is used instead.
+pub trait ChainSelectionStrategy<H: Header>: Send + Sync + 'static {
+    /// Our favourite block out of the candidates.
+    fn favourite(&self, candidates: ChainCandidates<H>) -> H;
+
+    /// Whether the favourite block of a peer, on a fork we do not know yet, is worth requesting.
+    fn wants_fork(&self, _header: &H) -> bool {
+        true
+    }
+}
+
+/// Extends the best block of the chain status, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LongestChain;
+
+impl<H: Header> ChainSelectionStrategy<H> for LongestChain {
+    fn favourite(&self, candidates: ChainCandidates<H>) -> H {
+        candidates.best
+    }
+}
+
+/// Extends the highest justified block, or the top finalized one if there is none, so that we
+/// never build on anything that might get abandoned once justifications arrive.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MostRecentlyJustified;
+
+impl<H: Header> ChainSelectionStrategy<H> for MostRecentlyJustified {
+    fn favourite(&self, candidates: ChainCandidates<H>) -> H {
+        candidates
+            .highest_justified
+            .unwrap_or(candidates.top_finalized)
+    }
+}
+
+/// The hints for the externally hinted strategy, e.g. from a component following another chain.
+/// Can be cloned and used while sync is running.
+#[derive(Clone)]
+pub struct ChainHints<H: Header> {
+    hint: Arc<Mutex<Option<H>>>,
+}
+
+impl<H: Header> ChainHints<H> {
+    pub fn new() -> Self {
+        ChainHints {
+            hint: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Hints the block to extend, replacing any previous hint.
+    pub fn hint(&self, header: H) {
+        *self.hint.lock() = Some(header);
+    }
+
+    pub fn clear(&self) {
+        *self.hint.lock() = None;
+    }
+
+    pub fn current(&self) -> Option<H> {
+        self.hint.lock().clone()
+    }
+}
+
+impl<H: Header> Default for ChainHints<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Extends the block hinted from outside, unless it is already finalized, falling back to
+/// another strategy without a hint. Only forks containing the hint are requested from peers
+/// while there is one.
+pub struct ExternallyHinted<H: Header, S: ChainSelectionStrategy<H>> {
+    hints: ChainHints<H>,
+    fallback: S,
+}
+
+impl<H: Header, S: ChainSelectionStrategy<H>> ExternallyHinted<H, S> {
+    pub fn new(hints: ChainHints<H>, fallback: S) -> Self {
+        ExternallyHinted { hints, fallback }
+    }
+}
+
+impl<H: Header, S: ChainSelectionStrategy<H>> ChainSelectionStrategy<H> for ExternallyHinted<H, S> {
+    fn favourite(&self, candidates: ChainCandidates<H>) -> H {
+        match self.hints.current() {
+            Some(hint) if hint.id().number() > candidates.top_finalized.id().number() => hint,
+            _ => self.fallback.favourite(candidates),
+        }
+    }
+
+    fn wants_fork(&self, header: &H) -> bool {
+        match self.hints.current() {
+            // The fork might only lead up to the hint, but we cannot tell from the top alone.
+            Some(hint) => header.id().number() >= hint.id().number(),
+            None => self.fallback.wants_fork(header),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ChainCandidates, ChainHints, ChainSelectionStrategy, ExternallyHinted, LongestChain,
+        MostRecentlyJustified,
+    };
+    use crate::sync::mock::MockHeader;
+
+    fn candidates() -> (ChainCandidates<MockHeader>, MockHeader) {
+        let top_finalized = MockHeader::genesis();
+        let justified = top_finalized.random_child();
+        let best = justified.random_child();
+        let fork = top_finalized.random_child().random_child().random_child();
+        let candidates = ChainCandidates {
+            best,
+            highest_justified: Some(justified),
+            top_finalized,
+        };
+        (candidates, fork)
+    }
+
+    #[test]
+    fn chooses_favourites() {
+        let (candidates, _) = candidates();
+        assert_eq!(LongestChain.favourite(candidates.clone()), candidates.best);
+        assert_eq!(
+            Some(MostRecentlyJustified.favourite(candidates.clone())),
+            candidates.highest_justified
+        );
+        let without_justified = ChainCandidates {
+            highest_justified: None,
+            ..candidates.clone()
+        };
+        assert_eq!(
+            MostRecentlyJustified.favourite(without_justified),
+            candidates.top_finalized
+        );
+    }
+
+    #[test]
+    fn follows_hints_above_finalized() {
+        let (candidates, fork) = candidates();
+        let hints = ChainHints::new();
+        let strategy = ExternallyHinted::new(hints.clone(), LongestChain);
+        assert_eq!(strategy.favourite(candidates.clone()), candidates.best);
+        assert!(strategy.wants_fork(&candidates.top_finalized));
+        hints.hint(fork.clone());
+        assert_eq!(strategy.favourite(candidates.clone()), fork);
+        assert!(strategy.wants_fork(&fork));
+        assert!(!strategy.wants_fork(&candidates.best));
+        // Finalized hints are stale.
+        hints.hint(candidates.top_finalized.clone());
+        assert_eq!(strategy.favourite(candidates.clone()), candidates.best);
+        hints.clear();
+        assert_eq!(strategy.favourite(candidates.clone()), candidates.best);
+    }
+}
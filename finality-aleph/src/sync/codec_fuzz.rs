@@ -0,0 +1,312 @@
+//! Randomized round-trips of the sync messages through their encodings, including truncated and
+//! corrupted ones. The codecs of the versioned data and of the responses are written by hand, so
+//! every variant in every version gets checked here against the derived encodings. Enabling the
+//! `fuzz` feature makes the runs much longer.
+
+use parity_scale_codec::{Decode, Encode};
+use rand::{rngs::ThreadRng, thread_rng, Rng};
+
+use crate::{
+    session::SessionId,
+    sync::{
+        availability::{Availability, Capabilities, Range},
+        compression::Compression,
+        data::{
+            BodyRequest, BranchKnowledge, ExtendedState, ForkId, NetworkData, NetworkDataV1,
+            ProtocolFeatures, Request, ResponseItem, State, VersionedNetworkData,
+            MAX_SYNC_MESSAGE_SIZE,
+        },
+        mock::{MockBlock, MockHeader, MockJustification},
+        priority::PriorityTicket,
+        roles::ValidatorTicket,
+        Header,
+    },
+    BlockNumber, Version,
+};
+
+type Data = NetworkData<MockBlock, MockJustification>;
+type Versioned = VersionedNetworkData<MockBlock, MockJustification>;
+
+/// How many random messages every property is checked on.
+const ITERATIONS: usize = match cfg!(feature = "fuzz") {
+    true => 100_000,
+    false => 300,
+};
+/// How many truncations and corruptions of every encoded message get decoded.
+const MUTATIONS: usize = 32;
+/// The most items in the vectors of generated messages.
+const MAX_ITEMS: usize = 5;
+/// The highest version the decoding understands, everything above is from the future.
+const HIGHEST_KNOWN_VERSION: u16 = 6;
+
+struct Generator {
+    rng: ThreadRng,
+}
+
+impl Generator {
+    fn new() -> Self {
+        Generator { rng: thread_rng() }
+    }
+
+    fn items<T>(&mut self, mut item: impl FnMut(&mut Self) -> T) -> Vec<T> {
+        let count = self.rng.gen_range(0..=MAX_ITEMS);
+        (0..count).map(|_| item(self)).collect()
+    }
+
+    /// Values of types wrapping raw bits, with unknown bits set as well.
+    fn from_raw<T: Decode>(&mut self, bytes: usize) -> T {
+        let raw: Vec<u8> = (0..bytes).map(|_| self.rng.gen()).collect();
+        T::decode(&mut &raw[..]).expect("raw bits always decode")
+    }
+
+    fn header(&mut self) -> MockHeader {
+        // Leaving room for the child.
+        let header = MockHeader::random_parentless(self.rng.gen_range(0..BlockNumber::MAX));
+        match self.rng.gen_bool(0.5) {
+            true => header.random_child(),
+            false => header,
+        }
+    }
+
+    fn justification(&mut self) -> MockJustification {
+        let header = self.header();
+        match self.rng.gen_bool(0.9) {
+            true => MockJustification::for_header(header),
+            false => MockJustification::invalid_for_header(header),
+        }
+    }
+
+    fn state(&mut self) -> State<MockJustification> {
+        State::new(self.justification())
+    }
+
+    fn request(&mut self) -> Request<MockJustification> {
+        let branch_knowledge = match self.rng.gen_bool(0.5) {
+            true => BranchKnowledge::LowestId(self.header().id()),
+            false => BranchKnowledge::TopImported(self.header().id()),
+        };
+        Request::new(self.header().id(), branch_knowledge, self.state())
+    }
+
+    fn response_item(&mut self) -> ResponseItem<MockBlock, MockJustification> {
+        match self.rng.gen_range(0..3) {
+            0 => ResponseItem::Justification(self.justification()),
+            1 => ResponseItem::Header(self.header()),
+            _ => ResponseItem::Block(MockBlock::new(self.header(), self.rng.gen())),
+        }
+    }
+
+    fn range(&mut self) -> Range {
+        Range::new(self.rng.gen(), self.rng.gen())
+    }
+
+    fn availability(&mut self) -> Availability {
+        Availability {
+            bodies: self.items(Self::range),
+        }
+    }
+
+    fn data(&mut self) -> Data {
+        match self.rng.gen_range(0..21) {
+            0 => Data::StateBroadcast(self.state()),
+            1 => Data::StateBroadcastResponse(
+                self.justification(),
+                match self.rng.gen_bool(0.5) {
+                    true => Some(self.justification()),
+                    false => None,
+                },
+            ),
+            2 => Data::Request(self.request()),
+            3 => Data::RequestResponse(self.items(Self::response_item)),
+            4 => Data::CapabilitiesAnnouncement(self.from_raw::<Capabilities>(4)),
+            5 => Data::AvailabilityRequest,
+            6 => Data::AvailabilityResponse(self.availability()),
+            7 => Data::AcknowledgementRequest(self.rng.gen()),
+            8 => Data::Acknowledgement(self.rng.gen()),
+            9 => Data::ExtendedStateBroadcast(ExtendedState::new(self.state(), self.header())),
+            10 => Data::BatchedStateBroadcastResponse(
+                self.justification(),
+                self.items(Self::justification),
+            ),
+            11 => Data::BodyRequest(BodyRequest::new(self.rng.gen(), self.rng.gen())),
+            // An ed25519 key and signature, any bytes decode as those.
+            12 => Data::PriorityTicket(self.from_raw::<PriorityTicket>(96)),
+            13 => Data::HeaderRequest(self.request()),
+            14 => Data::WarpRequest(SessionId(self.rng.gen())),
+            15 => Data::WarpResponse(self.items(Self::justification)),
+            16 => Data::ValidatorTicket(self.from_raw::<ValidatorTicket>(96)),
+            17 => Data::Announcement(self.header()),
+            18 => Data::CorrelatedRequest(self.rng.gen(), self.request()),
+            19 => Data::ForkIdAnnouncement(ForkId::new(Some("fuzzed"))),
+            _ => Data::CorrelatedResponse(self.rng.gen(), self.items(Self::response_item)),
+        }
+    }
+
+    /// The data in all the versions able to express it, including ones it would never be sent in.
+    fn versioned(&mut self, data: Data) -> Vec<Versioned> {
+        let mut result = Vec::new();
+        if let Ok(data) = NetworkDataV1::try_from(&data) {
+            result.push(VersionedNetworkData::V1(data));
+        }
+        let compressed = Compression::default()
+            .compress(&data.encode())
+            .expect("compression works");
+        result.push(VersionedNetworkData::V4(compressed));
+        result.push(VersionedNetworkData::V2(data.clone()));
+        result.push(VersionedNetworkData::V3(data.clone()));
+        result.push(VersionedNetworkData::V5(data.clone()));
+        result.push(VersionedNetworkData::V6(self.from_raw(4), data));
+        result
+    }
+
+    fn unknown_version(&mut self) -> Version {
+        match self.rng.gen_bool(0.1) {
+            true => Version(0),
+            false => Version(self.rng.gen_range(HIGHEST_KNOWN_VERSION + 1..=u16::MAX)),
+        }
+    }
+}
+
+/// Decodes the input, which must not panic whatever it is.
+fn decode(input: &[u8]) -> Option<Versioned> {
+    Versioned::decode(&mut &input[..]).ok()
+}
+
+#[test]
+fn generates_every_kind_of_data() {
+    let mut generator = Generator::new();
+    let mut seen = [false; 20];
+    for _ in 0..ITERATIONS.max(1000) {
+        // The variant index is the first byte of the derived encoding.
+        seen[generator.data().encode()[0] as usize] = true;
+    }
+    assert!(seen.iter().all(|seen| *seen), "not generated: {seen:?}");
+}
+
+#[test]
+fn decodes_everything_encoded_in_every_version() {
+    let mut generator = Generator::new();
+    for _ in 0..ITERATIONS {
+        let data = generator.data();
+        let plain = data.encode();
+        for versioned in generator.versioned(data) {
+            let encoded = versioned.encode();
+            let decoded = match decode(&encoded) {
+                Some(decoded) => decoded,
+                None => panic!("failed to decode {versioned:?}"),
+            };
+            assert_eq!(
+                decoded.encode(),
+                encoded,
+                "changed by decoding {versioned:?}"
+            );
+            assert_eq!(versioned.check_size(MAX_SYNC_MESSAGE_SIZE), Ok(()));
+            if let VersionedNetworkData::V4(compressed) = decoded {
+                let decompressed = Compression::default()
+                    .decompress(&compressed, MAX_SYNC_MESSAGE_SIZE as usize)
+                    .expect("decompression works");
+                assert_eq!(decompressed, plain);
+                let data = Data::decode(&mut &decompressed[..]).expect("decompressed data decodes");
+                assert_eq!(data.encode(), plain);
+            }
+        }
+    }
+}
+
+#[test]
+fn rejects_every_truncation() {
+    let mut generator = Generator::new();
+    for _ in 0..ITERATIONS {
+        let data = generator.data();
+        for versioned in generator.versioned(data) {
+            let encoded = versioned.encode();
+            // A strict prefix of an encoding cannot decode, since nothing in it is superfluous.
+            for _ in 0..MUTATIONS {
+                let length = generator.rng.gen_range(0..encoded.len());
+                assert!(
+                    decode(&encoded[..length]).is_none(),
+                    "decoded {length} bytes of {versioned:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn survives_corrupted_messages() {
+    let mut generator = Generator::new();
+    for _ in 0..ITERATIONS {
+        let data = generator.data();
+        for versioned in generator.versioned(data) {
+            let encoded = versioned.encode();
+            for _ in 0..MUTATIONS {
+                let mut corrupted = encoded.clone();
+                for _ in 0..generator.rng.gen_range(1..=4) {
+                    let position = generator.rng.gen_range(0..corrupted.len());
+                    corrupted[position] = generator.rng.gen();
+                }
+                if let Some(decoded) = decode(&corrupted) {
+                    // Whatever got decoded can be sent again.
+                    decode(&decoded.encode()).expect("decoded data encodes decodably");
+                }
+            }
+        }
+        let garbage: Vec<u8> = (0..generator.rng.gen_range(0..256))
+            .map(|_| generator.rng.gen())
+            .collect();
+        decode(&garbage);
+    }
+}
+
+#[test]
+fn falls_back_to_other_for_unknown_versions() {
+    let mut generator = Generator::new();
+    for _ in 0..ITERATIONS {
+        let version = generator.unknown_version();
+        let payload: Vec<u8> = (0..generator.rng.gen_range(0..1024))
+            .map(|_| generator.rng.gen())
+            .collect();
+        let encoded = Versioned::Other(version, payload.clone()).encode();
+        match decode(&encoded) {
+            Some(VersionedNetworkData::Other(decoded_version, decoded_payload)) => {
+                assert_eq!(decoded_version, version);
+                assert_eq!(decoded_payload, payload);
+            }
+            other => panic!("unexpected decoding {other:?}"),
+        }
+
+        // Known data relabeled as coming from the future is never interpreted, and anything after
+        // the declared payload is left for whoever reads next.
+        let data = generator.data();
+        let mut encoded = VersionedNetworkData::V5(data).encode();
+        let payload = encoded[Version(0).encoded_size() + 0u32.encoded_size()..].to_vec();
+        encoded.splice(..Version(0).encoded_size(), version.encode());
+        encoded.extend_from_slice(&[7; 3]);
+        let mut input = &encoded[..];
+        match Versioned::decode(&mut input) {
+            Ok(VersionedNetworkData::Other(decoded_version, decoded_payload)) => {
+                assert_eq!(decoded_version, version);
+                assert_eq!(decoded_payload, payload);
+            }
+            other => panic!("unexpected decoding {other:?}"),
+        }
+        assert_eq!(input, &[7u8; 3][..]);
+
+        // Payloads declared longer than they are get rejected before allocating for them.
+        let mut truncated = Versioned::Other(version, payload).encode();
+        truncated.pop();
+        assert!(decode(&truncated).is_none());
+    }
+}
+
+#[test]
+fn decodes_features_it_does_not_know() {
+    let mut generator = Generator::new();
+    let features: ProtocolFeatures = generator.from_raw(4);
+    let data = Data::StateBroadcast(generator.state());
+    let encoded = VersionedNetworkData::V6(features, data).encode();
+    match decode(&encoded) {
+        Some(VersionedNetworkData::V6(decoded, _)) => assert_eq!(decoded, features),
+        other => panic!("unexpected decoding {other:?}"),
+    }
+}
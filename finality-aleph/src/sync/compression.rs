@@ -0,0 +1,185 @@
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    io::{Error as IoError, Read},
+};
+
+use parity_scale_codec::{Decode, Encode};
+
+/// Identifies the codec a payload was compressed with, unique among the codecs.
+pub type CodecId = u8;
+
+/// The codec identifier of zstd.
+pub const ZSTD_CODEC: CodecId = 1;
+/// The zstd level used by default, a good tradeoff between speed and ratio for blocks.
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// What can go wrong when compressing or decompressing sync data.
+#[derive(Debug)]
+pub enum CompressionError {
+    NoCodec,
+    UnknownCodec(CodecId),
+    TooLarge(usize),
+    Codec(IoError),
+}
+
+impl Display for CompressionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use CompressionError::*;
+        match self {
+            NoCodec => write!(f, "no compression codec configured"),
+            UnknownCodec(id) => write!(f, "unknown compression codec {id}"),
+            TooLarge(limit) => write!(f, "decompressed data larger than {limit} bytes"),
+            Codec(e) => write!(f, "codec failure: {e}"),
+        }
+    }
+}
+
+/// A way of compressing sync payloads.
+pub trait CompressionCodec: Send + Sync {
+    /// The identifier sent alongside the compressed payloads.
+    fn id(&self) -> CodecId;
+
+    /// Compresses the data.
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError>;
+
+    /// Decompresses the data, failing as soon as the result gets larger than the limit, so that
+    /// small malicious payloads cannot make us allocate a lot.
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, CompressionError>;
+}
+
+/// The zstd codec.
+pub struct Zstd {
+    level: i32,
+}
+
+impl Zstd {
+    pub fn new(level: i32) -> Self {
+        Zstd { level }
+    }
+}
+
+impl Default for Zstd {
+    fn default() -> Self {
+        Zstd::new(DEFAULT_ZSTD_LEVEL)
+    }
+}
+
+impl CompressionCodec for Zstd {
+    fn id(&self) -> CodecId {
+        ZSTD_CODEC
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        zstd::bulk::compress(data, self.level).map_err(CompressionError::Codec)
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, CompressionError> {
+        let decoder = zstd::stream::read::Decoder::new(data).map_err(CompressionError::Codec)?;
+        let mut result = Vec::new();
+        // Reading a single byte past the limit is enough to know it was exceeded.
+        decoder
+            .take(limit as u64 + 1)
+            .read_to_end(&mut result)
+            .map_err(CompressionError::Codec)?;
+        match result.len() > limit {
+            true => Err(CompressionError::TooLarge(limit)),
+            false => Ok(result),
+        }
+    }
+}
+
+/// A payload compressed with the codec of the identifier.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct CompressedData {
+    codec: CodecId,
+    payload: Vec<u8>,
+}
+
+/// The codecs we can compress sync payloads with.
+pub struct Compression {
+    codecs: Vec<Box<dyn CompressionCodec>>,
+}
+
+impl Compression {
+    /// The first of the codecs is used for compressing, all of them for decompressing.
+    pub fn new(codecs: Vec<Box<dyn CompressionCodec>>) -> Self {
+        Compression { codecs }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<CompressedData, CompressionError> {
+        let codec = self.codecs.first().ok_or(CompressionError::NoCodec)?;
+        Ok(CompressedData {
+            codec: codec.id(),
+            payload: codec.compress(data)?,
+        })
+    }
+
+    pub fn decompress(
+        &self,
+        data: &CompressedData,
+        limit: usize,
+    ) -> Result<Vec<u8>, CompressionError> {
+        self.codecs
+            .iter()
+            .find(|codec| codec.id() == data.codec)
+            .ok_or(CompressionError::UnknownCodec(data.codec))?
+            .decompress(&data.payload, limit)
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new(vec![Box::<Zstd>::default()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompressedData, Compression, CompressionError, ZSTD_CODEC};
+
+    #[test]
+    fn decompresses_what_was_compressed() {
+        let compression = Compression::default();
+        let data: Vec<u8> = (0..10_000u32)
+            .flat_map(|i| (i % 17).to_le_bytes())
+            .collect();
+        let compressed = compression.compress(&data).expect("compression works");
+        assert!(compressed.payload.len() < data.len());
+        assert_eq!(
+            compression
+                .decompress(&compressed, data.len())
+                .expect("decompression works"),
+            data
+        );
+    }
+
+    #[test]
+    fn refuses_decompressing_past_limit() {
+        let compression = Compression::default();
+        let bomb = compression
+            .compress(&vec![0; 16 * 1024 * 1024])
+            .expect("compression works");
+        assert!(bomb.payload.len() < 4096);
+        assert!(matches!(
+            compression.decompress(&bomb, 1024 * 1024),
+            Err(CompressionError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_unknown_codecs() {
+        let compression = Compression::default();
+        let data = CompressedData {
+            codec: ZSTD_CODEC + 1,
+            payload: vec![0; 16],
+        };
+        assert!(matches!(
+            compression.decompress(&data, 1024),
+            Err(CompressionError::UnknownCodec(_))
+        ));
+        assert!(matches!(
+            Compression::new(Vec::new()).compress(&[0; 16]),
+            Err(CompressionError::NoCodec)
+        ));
+    }
+}
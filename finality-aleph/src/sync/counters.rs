@@ -0,0 +1,134 @@
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+use log::{debug, info, warn};
+use parity_scale_codec::{Decode, Encode};
+use parking_lot::Mutex;
+use tokio::time::sleep;
+
+use crate::sync::LOG_TARGET;
+
+/// How often the counters are persisted, increments since the last time are lost on a crash.
+pub const COUNTERS_PERSIST_PERIOD: Duration = Duration::from_secs(60);
+
+/// Cumulative statistics of sync, over all the runs of the node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct CounterValues {
+    /// How many blocks were finalized with justifications.
+    pub justifications_imported: u64,
+    /// How many bytes of responses were sent to the requests of peers.
+    pub bytes_served: u64,
+    /// How many times peers got banned for misbehaving in sync.
+    pub bans: u64,
+}
+
+impl CounterValues {
+    fn add(&mut self, other: &CounterValues) {
+        self.justifications_imported = self
+            .justifications_imported
+            .saturating_add(other.justifications_imported);
+        self.bytes_served = self.bytes_served.saturating_add(other.bytes_served);
+        self.bans = self.bans.saturating_add(other.bans);
+    }
+}
+
+/// The counters of sync surviving restarts, can be cloned and inspected while sync is running.
+#[derive(Clone, Default)]
+pub struct Counters {
+    values: Arc<Mutex<CounterValues>>,
+}
+
+impl Counters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current values, including the ones restored from previous runs.
+    pub fn values(&self) -> CounterValues {
+        *self.values.lock()
+    }
+
+    pub fn justification_imported(&self) {
+        let mut values = self.values.lock();
+        values.justifications_imported = values.justifications_imported.saturating_add(1);
+    }
+
+    pub fn bytes_served(&self, bytes: u64) {
+        let mut values = self.values.lock();
+        values.bytes_served = values.bytes_served.saturating_add(bytes);
+    }
+
+    pub fn banned(&self) {
+        let mut values = self.values.lock();
+        values.bans = values.bans.saturating_add(1);
+    }
+
+    /// Adds the values from previous runs, keeping whatever was counted already.
+    fn restore(&self, stored: &CounterValues) {
+        self.values.lock().add(stored);
+    }
+}
+
+/// Where the counters are persisted between the runs of the node.
+pub trait CounterStorage {
+    type Error: Display;
+
+    /// The values stored last time, `None` if they were never stored.
+    fn load(&self) -> Result<Option<CounterValues>, Self::Error>;
+
+    /// Replaces the stored values.
+    fn store(&self, values: &CounterValues) -> Result<(), Self::Error>;
+}
+
+/// Restores the counters from the storage and then persists them periodically. If the stored
+/// values cannot be read they are never overwritten, so that they are not lost for good.
+pub async fn run_counter_persistence<S: CounterStorage>(counters: Counters, storage: S) {
+    match storage.load() {
+        Ok(Some(stored)) => {
+            counters.restore(&stored);
+            info!(
+                target: LOG_TARGET,
+                "Restored sync counters from previous runs: {:?}.", stored
+            );
+        }
+        Ok(None) => debug!(target: LOG_TARGET, "No sync counters stored yet."),
+        Err(e) => {
+            warn!(
+                target: LOG_TARGET,
+                "Not persisting sync counters, failed to restore them: {}.", e
+            );
+            return;
+        }
+    }
+    loop {
+        sleep(COUNTERS_PERSIST_PERIOD).await;
+        if let Err(e) = storage.store(&counters.values()) {
+            warn!(target: LOG_TARGET, "Failed to persist sync counters: {}.", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CounterValues, Counters};
+
+    #[test]
+    fn keeps_counting_after_restore() {
+        let counters = Counters::new();
+        counters.justification_imported();
+        counters.bytes_served(100);
+        counters.restore(&CounterValues {
+            justifications_imported: 7,
+            bytes_served: 1000,
+            bans: 2,
+        });
+        counters.banned();
+        assert_eq!(
+            counters.values(),
+            CounterValues {
+                justifications_imported: 8,
+                bytes_served: 1100,
+                bans: 3,
+            }
+        );
+    }
+}
@@ -1,14 +1,42 @@
-use std::{collections::HashSet, marker::PhantomData, mem::size_of};
+use std::{
+    collections::HashSet,
+    fmt::{Display, Error as FmtError, Formatter},
+    hash::Hash,
+    marker::PhantomData,
+    mem::size_of,
+    num::NonZeroUsize,
+    thread,
+    time::{Duration, Instant},
+};
 
-use log::warn;
-use parity_scale_codec::{Decode, Encode, Error as CodecError, Input as CodecInput};
+use futures::{channel::oneshot, FutureExt};
+use log::{debug, error, info, warn};
+use lru::LruCache;
+use parity_scale_codec::{Compact, Decode, Encode, Error as CodecError, Input as CodecInput};
+use rand::{seq::IteratorRandom, thread_rng};
+use sp_core::hashing::blake2_256;
 use static_assertions::const_assert;
+use tokio::time::timeout;
 
 use crate::{
     aleph_primitives::MAX_BLOCK_SIZE,
-    network::GossipNetwork,
-    sync::{Block, BlockIdFor, Justification, LOG_TARGET},
-    Version,
+    network::{
+        CancelToken, ConnectionEvents, GossipNetwork, Penalty, PendingResponse, RequestId,
+        RequestNetwork,
+    },
+    session::SessionId,
+    sync::{
+        acknowledgements::AcknowledgementTag,
+        availability::{Availability, Capabilities},
+        compression::{CompressedData, Compression},
+        header_chain::workers,
+        metrics::{MessageDirection, Metrics},
+        priority::PriorityTicket,
+        roles::ValidatorTicket,
+        v1_shim::V1Shim,
+        Block, BlockIdFor, Header, Justification, LOG_TARGET,
+    },
+    BlockNumber, Version,
 };
 
 /// The representation of the database state to be sent to other nodes.
@@ -28,6 +56,35 @@ impl<J: Justification> State<J> {
     }
 }
 
+/// The state together with our favourite block, so that peers can tell whether we are on a
+/// competing fork without requesting anything.
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct ExtendedState<J: Justification> {
+    state: State<J>,
+    favourite_block: J::Header,
+}
+
+impl<J: Justification> ExtendedState<J> {
+    pub fn new(state: State<J>, favourite_block: J::Header) -> Self {
+        ExtendedState {
+            state,
+            favourite_block,
+        }
+    }
+
+    pub fn state(&self) -> &State<J> {
+        &self.state
+    }
+
+    pub fn favourite_block(&self) -> &J::Header {
+        &self.favourite_block
+    }
+
+    pub fn into_parts(self) -> (State<J>, J::Header) {
+        (self.state, self.favourite_block)
+    }
+}
+
 /// Represents one of the possible response_items we are sending over the network.
 #[derive(Clone, Debug, Encode, Decode)]
 pub enum ResponseItem<B, J>
@@ -57,13 +114,14 @@ where
             .collect()
     }
 
+    /// Only clones the justifications, leaving the potentially large blocks alone.
     pub fn justifications_from_response_items(
-        response_items: ResponseItems<B, J>,
+        response_items: &[ResponseItem<B, J>],
     ) -> Vec<J::Unverified> {
         response_items
-            .into_iter()
+            .iter()
             .filter_map(|item| match item {
-                Self::Justification(j) => Some(j),
+                Self::Justification(j) => Some(j.clone()),
                 _ => None,
             })
             .collect()
@@ -118,8 +176,33 @@ impl<J: Justification> Request<J> {
     }
 }
 
+/// The most blocks a single body request asks for, peers serve at most that many anyway.
+pub const MAX_BODY_REQUEST_BLOCKS: BlockNumber = 64;
+
+/// A request for the bodies of a range of finalized blocks, including both ends. Needs no
+/// knowledge of any branches, so that nodes missing old bodies can fetch them by number.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct BodyRequest {
+    from: BlockNumber,
+    to: BlockNumber,
+}
+
+impl BodyRequest {
+    pub fn new(from: BlockNumber, to: BlockNumber) -> Self {
+        BodyRequest { from, to }
+    }
+
+    pub fn from(&self) -> BlockNumber {
+        self.from
+    }
+
+    pub fn to(&self) -> BlockNumber {
+        self.to
+    }
+}
+
 /// Data to be sent over the network.
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, Encode)]
 pub enum NetworkDataV1<J: Justification> {
     /// A periodic state broadcast, so that neighbouring nodes can request what they are missing,
     /// send what we are missing, and sometimes just use the justifications to update their own
@@ -134,8 +217,113 @@ pub enum NetworkDataV1<J: Justification> {
     RequestResponse(Vec<J::Unverified>),
 }
 
+/// The most justifications sent in a single batched response, any more are ignored.
+pub const MAX_BATCHED_JUSTIFICATIONS: usize = 16;
+
+/// The most justifications sent in a single warp response, any more are ignored.
+pub const MAX_WARP_JUSTIFICATIONS: usize = 64;
+
+/// The most justifications a single message can contain, messages claiming more are rejected
+/// while decoding, before anything gets allocated for them.
+pub const MAX_MESSAGE_JUSTIFICATIONS: usize = 4 * 1024;
+
+/// The most headers a single message can contain, messages claiming more are rejected while
+/// decoding.
+pub const MAX_MESSAGE_HEADERS: usize = 16 * 1024;
+
+/// The most blocks a single message can contain, messages claiming more are rejected while
+/// decoding.
+pub const MAX_MESSAGE_BLOCKS: usize = 4 * 1024;
+
+/// Decodes a vector, failing early if it claims to be longer than the limit.
+fn decode_limited_vec<T: Decode, I: CodecInput>(
+    input: &mut I,
+    limit: usize,
+) -> Result<Vec<T>, CodecError> {
+    let length = <Compact<u32>>::decode(input)?.0 as usize;
+    if length > limit {
+        Err("Sync data contains a vector longer than the limit.")?;
+    }
+    // Still not preallocating, the items might be large and not there at all.
+    let mut result = Vec::new();
+    for _ in 0..length {
+        result.push(T::decode(input)?);
+    }
+    Ok(result)
+}
+
+/// How many items of every kind were counted so far.
+#[derive(Clone, Copy, Default)]
+struct ItemCounts {
+    justifications: usize,
+    headers: usize,
+    blocks: usize,
+}
+
+impl ItemCounts {
+    /// Counts the item, returns whether the counts are still within the message limits.
+    fn add<B, J>(&mut self, item: &ResponseItem<B, J>) -> bool
+    where
+        B: Block,
+        J: Justification<Header = B::Header>,
+    {
+        match item {
+            ResponseItem::Justification(_) => {
+                self.justifications += 1;
+                self.justifications <= MAX_MESSAGE_JUSTIFICATIONS
+            }
+            ResponseItem::Header(_) => {
+                self.headers += 1;
+                self.headers <= MAX_MESSAGE_HEADERS
+            }
+            ResponseItem::Block(_) => {
+                self.blocks += 1;
+                self.blocks <= MAX_MESSAGE_BLOCKS
+            }
+        }
+    }
+}
+
+/// Decodes response items, failing early if there are more items of any kind than a message can
+/// contain.
+fn decode_response_items<B, J, I>(input: &mut I) -> Result<ResponseItems<B, J>, CodecError>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+    I: CodecInput,
+{
+    let length = <Compact<u32>>::decode(input)?.0 as usize;
+    if length > MAX_MESSAGE_JUSTIFICATIONS + MAX_MESSAGE_HEADERS + MAX_MESSAGE_BLOCKS {
+        Err("Sync response claims more items than a message can contain.")?;
+    }
+    let mut counts = ItemCounts::default();
+    let mut result = Vec::new();
+    for _ in 0..length {
+        let item = ResponseItem::decode(input)?;
+        if !counts.add(&item) {
+            Err("Sync response contains more items of a kind than a message can contain.")?;
+        }
+        result.push(item);
+    }
+    Ok(result)
+}
+
+/// The number of the first response items that fit in a single message, at least one if there
+/// are any.
+pub fn limited_response_prefix<B, J>(response_items: &[ResponseItem<B, J>]) -> usize
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    let mut counts = ItemCounts::default();
+    response_items
+        .iter()
+        .position(|item| !counts.add(item))
+        .unwrap_or(response_items.len())
+}
+
 /// Data to be sent over the network.
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, Encode)]
 pub enum NetworkData<B: Block, J: Justification>
 where
     B: Block,
@@ -152,6 +340,356 @@ where
     Request(Request<J>),
     /// Response to the request for data.
     RequestResponse(ResponseItems<B, J>),
+    /// Announcement of the kinds of historical data the sender is able to serve. Only sent to
+    /// peers that announced understanding it in their features.
+    CapabilitiesAnnouncement(Capabilities),
+    /// A request for the details of the historical data the receiver is able to serve. Only sent
+    /// to peers that announced understanding it in their features.
+    AvailabilityRequest,
+    /// The details of the historical data the sender is able to serve.
+    AvailabilityResponse(Availability),
+    /// A request to acknowledge receiving everything the sender sent before it.
+    AcknowledgementRequest(AcknowledgementTag),
+    /// Acknowledges receiving everything sent before the request with the tag.
+    Acknowledgement(AcknowledgementTag),
+    /// A state broadcast extended with our favourite block.
+    ExtendedStateBroadcast(ExtendedState<J>),
+    /// Response to a state broadcast with consecutive justifications closing sessions, possibly
+    /// followed by our top one. Contains at most `MAX_BATCHED_JUSTIFICATIONS` justifications.
+    BatchedStateBroadcastResponse(J::Unverified, Vec<J::Unverified>),
+    /// A request for the bodies of finalized blocks, answered with a response containing only
+    /// blocks. Only sent to peers that announced serving such requests.
+    BodyRequest(BodyRequest),
+    /// A proof that the sender belongs to the infrastructure of the receiver. Only sent to peers
+    /// that announced priority service and understanding it in their features.
+    PriorityTicket(PriorityTicket),
+    /// A request for the same data as a regular request, answered with a response containing
+    /// only justifications and headers. Only sent to peers that announced serving such requests
+    /// and understanding them in their features.
+    HeaderRequest(Request<J>),
+    /// A request for the justifications of the blocks ending consecutive sessions, starting with
+    /// the given one. Only sent to peers that announced serving such requests and understanding
+    /// them in their features.
+    WarpRequest(SessionId),
+    /// Response to a warp request, contains at most `MAX_WARP_JUSTIFICATIONS` justifications.
+    WarpResponse(Vec<J::Unverified>),
+    /// A proof that the sender uses the key in the validator network, sent to all peers that
+    /// announced recognizing validators and understanding it in their features.
+    ValidatorTicket(ValidatorTicket),
+    /// Announcement of a new favourite block of the sender, so that peers learn about new blocks
+    /// through sync itself. Only sent to peers that announced understanding it in their features.
+    Announcement(J::Header),
+    /// A regular request with an identifier carried by the responses to it, so that they can be
+    /// matched with it. Only sent to peers that announced understanding it in their features.
+    CorrelatedRequest(RequestId, Request<J>),
+    /// Response to the correlated request with the identifier, long ones come in several parts.
+    CorrelatedResponse(RequestId, ResponseItems<B, J>),
+    /// Announcement of the fork of the chain the sender follows. Only sent to peers that
+    /// announced understanding it in their features.
+    ForkIdAnnouncement(ForkId),
+}
+
+/// Bits describing the optional features of the sync protocol a node understands, sent along
+/// with its state, so that every feature can be enabled independently of the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct ProtocolFeatures(u32);
+
+impl ProtocolFeatures {
+    pub const NONE: Self = ProtocolFeatures(0);
+    /// Acknowledgements and extended states, introduced with the third version.
+    pub const EXTENSIONS: Self = ProtocolFeatures(1);
+    /// Compressed data, introduced with the fourth version.
+    pub const COMPRESSION: Self = ProtocolFeatures(1 << 1);
+    /// Batched responses to states, introduced with the fifth version.
+    pub const BATCHES: Self = ProtocolFeatures(1 << 2);
+    /// Announcements of favourite blocks, never implied by any version.
+    pub const ANNOUNCEMENTS: Self = ProtocolFeatures(1 << 3);
+    /// Requests and responses carrying identifiers, never implied by any version.
+    pub const CORRELATION: Self = ProtocolFeatures(1 << 4);
+    /// Announcements of the fork of the chain, never implied by any version.
+    pub const FORK_IDS: Self = ProtocolFeatures(1 << 5);
+    /// Announcements of capabilities and the details of availability, never implied by any
+    /// version.
+    pub const CAPABILITIES: Self = ProtocolFeatures(1 << 6);
+    /// Tickets proving belonging to the infrastructure, never implied by any version.
+    pub const PRIORITY_TICKETS: Self = ProtocolFeatures(1 << 7);
+    /// Requests for just the headers of branches, never implied by any version.
+    pub const HEADER_REQUESTS: Self = ProtocolFeatures(1 << 8);
+    /// Requests for and responses with justifications ending sessions, never implied by any
+    /// version.
+    pub const WARP: Self = ProtocolFeatures(1 << 9);
+    /// Tickets proving using keys in the validator network, never implied by any version.
+    pub const VALIDATOR_TICKETS: Self = ProtocolFeatures(1 << 10);
+    /// All the features this node understands.
+    pub const SUPPORTED: Self = ProtocolFeatures(
+        Self::EXTENSIONS.0
+            | Self::COMPRESSION.0
+            | Self::BATCHES.0
+            | Self::ANNOUNCEMENTS.0
+            | Self::CORRELATION.0
+            | Self::FORK_IDS.0
+            | Self::CAPABILITIES.0
+            | Self::PRIORITY_TICKETS.0
+            | Self::HEADER_REQUESTS.0
+            | Self::WARP.0
+            | Self::VALIDATOR_TICKETS.0,
+    );
+
+    /// The features understood by every node sending data in the version, the versions older
+    /// than the sixth one do not announce their features.
+    fn implied_by(version: Version) -> Self {
+        let mut features = Self::NONE;
+        if version.0 >= 3 {
+            features = features.union(Self::EXTENSIONS);
+        }
+        if version.0 >= 4 {
+            features = features.union(Self::COMPRESSION);
+        }
+        if version.0 >= 5 {
+            features = features.union(Self::BATCHES);
+        }
+        features
+    }
+
+    /// Whether all the bits of `other` are also set in this.
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Features containing bits from both this and `other`.
+    pub fn union(self, other: Self) -> Self {
+        ProtocolFeatures(self.0 | other.0)
+    }
+
+    /// Features containing the bits set in both this and `other`.
+    pub fn intersection(self, other: Self) -> Self {
+        ProtocolFeatures(self.0 & other.0)
+    }
+}
+
+/// Tells apart the networks of a chain that hard forked, the two sides of which share the
+/// genesis block and so the names of the protocols, unless the fork is named. Peers announcing
+/// other forks get ignored by sync, even if the network connected us anyway.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct ForkId([u8; 32]);
+
+impl ForkId {
+    /// The identifier of the named fork, or of the original chain if there is no name.
+    pub fn new(name: Option<&str>) -> Self {
+        match name {
+            Some(name) => ForkId(blake2_256(name.as_bytes())),
+            None => ForkId::default(),
+        }
+    }
+}
+
+impl Display for ForkId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        if *self == ForkId::default() {
+            return write!(f, "the original chain");
+        }
+        write!(f, "fork 0x")?;
+        for byte in &self.0[..4] {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, "…")
+    }
+}
+
+impl<B: Block, J: Justification> NetworkData<B, J>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    /// The data in the oldest version still in use that can express it. Only the first four
+    /// kinds of data are understood by nodes supporting just the second version, everything
+    /// added later needs the third one, and batched responses need the fifth one. Body
+    /// requests were added after the third version, so every node understanding them also
+    /// understands it.
+    fn into_versioned(self) -> VersionedNetworkData<B, J> {
+        match self {
+            NetworkData::CapabilitiesAnnouncement(_)
+            | NetworkData::AvailabilityRequest
+            | NetworkData::AvailabilityResponse(_)
+            | NetworkData::AcknowledgementRequest(_)
+            | NetworkData::Acknowledgement(_)
+            | NetworkData::ExtendedStateBroadcast(_)
+            | NetworkData::BodyRequest(_)
+            | NetworkData::PriorityTicket(_)
+            | NetworkData::HeaderRequest(_)
+            | NetworkData::WarpRequest(_)
+            | NetworkData::WarpResponse(_)
+            | NetworkData::ValidatorTicket(_)
+            | NetworkData::Announcement(_)
+            | NetworkData::CorrelatedRequest(_, _)
+            | NetworkData::CorrelatedResponse(_, _)
+            | NetworkData::ForkIdAnnouncement(_) => VersionedNetworkData::V3(self),
+            NetworkData::BatchedStateBroadcastResponse(_, _) => VersionedNetworkData::V5(self),
+            data => VersionedNetworkData::V2(data),
+        }
+    }
+
+    /// The features a peer has to understand to get the data as it is.
+    fn required_features(&self) -> ProtocolFeatures {
+        match self {
+            NetworkData::AcknowledgementRequest(_)
+            | NetworkData::Acknowledgement(_)
+            | NetworkData::ExtendedStateBroadcast(_) => ProtocolFeatures::EXTENSIONS,
+            NetworkData::BatchedStateBroadcastResponse(_, _) => ProtocolFeatures::BATCHES,
+            NetworkData::Announcement(_) => ProtocolFeatures::ANNOUNCEMENTS,
+            NetworkData::CorrelatedRequest(_, _) | NetworkData::CorrelatedResponse(_, _) => {
+                ProtocolFeatures::CORRELATION
+            }
+            NetworkData::ForkIdAnnouncement(_) => ProtocolFeatures::FORK_IDS,
+            NetworkData::CapabilitiesAnnouncement(_)
+            | NetworkData::AvailabilityRequest
+            | NetworkData::AvailabilityResponse(_) => ProtocolFeatures::CAPABILITIES,
+            NetworkData::PriorityTicket(_) => ProtocolFeatures::PRIORITY_TICKETS,
+            NetworkData::HeaderRequest(_) => ProtocolFeatures::HEADER_REQUESTS,
+            NetworkData::WarpRequest(_) | NetworkData::WarpResponse(_) => ProtocolFeatures::WARP,
+            NetworkData::ValidatorTicket(_) => ProtocolFeatures::VALIDATOR_TICKETS,
+            _ => ProtocolFeatures::NONE,
+        }
+    }
+
+    /// Whether the data can only go to peers that announced understanding the features it
+    /// requires, as there is no equivalent for older nodes, which would fail to decode it.
+    /// Responses go to the peers that sent the requests, which shows they understand them.
+    fn needs_negotiation(&self) -> bool {
+        match self {
+            NetworkData::AvailabilityResponse(_)
+            | NetworkData::WarpResponse(_)
+            | NetworkData::CorrelatedResponse(_, _) => false,
+            data => !ProtocolFeatures::implied_by(Version(6)).contains(data.required_features()),
+        }
+    }
+
+    /// Whether the data is a state broadcast, which carries our features to the peers that
+    /// negotiate them.
+    fn is_state_broadcast(&self) -> bool {
+        matches!(
+            self,
+            NetworkData::StateBroadcast(_) | NetworkData::ExtendedStateBroadcast(_)
+        )
+    }
+
+    /// Whether the data can only be understood by nodes supporting batched responses.
+    fn is_batched(&self) -> bool {
+        matches!(self, NetworkData::BatchedStateBroadcastResponse(_, _))
+    }
+
+    /// The equivalent of the data for nodes supporting only the second version, if it is not
+    /// already understood by them.
+    fn without_extensions(&self) -> Option<Self> {
+        match self {
+            NetworkData::ExtendedStateBroadcast(extended) => {
+                Some(NetworkData::StateBroadcast(extended.state().clone()))
+            }
+            // The first two justifications are the ones the peer would have got anyway.
+            NetworkData::BatchedStateBroadcastResponse(justification, justifications) => {
+                Some(NetworkData::StateBroadcastResponse(
+                    justification.clone(),
+                    justifications.first().cloned(),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// The identifiers of all the justifications the data carries.
+    pub fn justification_ids(&self) -> Vec<BlockIdFor<J>> {
+        match self {
+            NetworkData::StateBroadcastResponse(justification, maybe_justification) => {
+                std::iter::once(justification)
+                    .chain(maybe_justification)
+                    .map(|justification| justification.id())
+                    .collect()
+            }
+            NetworkData::BatchedStateBroadcastResponse(justification, justifications) => {
+                std::iter::once(justification)
+                    .chain(justifications)
+                    .map(|justification| justification.id())
+                    .collect()
+            }
+            NetworkData::RequestResponse(response_items)
+            | NetworkData::CorrelatedResponse(_, response_items) => response_items
+                .iter()
+                .filter_map(|item| match item {
+                    ResponseItem::Justification(justification) => Some(justification.id()),
+                    _ => None,
+                })
+                .collect(),
+            NetworkData::WarpResponse(justifications) => justifications
+                .iter()
+                .map(|justification| justification.id())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl<J: Justification> Decode for NetworkDataV1<J> {
+    fn decode<I: CodecInput>(input: &mut I) -> Result<Self, CodecError> {
+        Ok(match input.read_byte()? {
+            0 => NetworkDataV1::StateBroadcast(State::decode(input)?),
+            1 => NetworkDataV1::StateBroadcastResponse(
+                J::Unverified::decode(input)?,
+                Option::decode(input)?,
+            ),
+            2 => NetworkDataV1::Request(Request::decode(input)?),
+            3 => NetworkDataV1::RequestResponse(decode_limited_vec(
+                input,
+                MAX_MESSAGE_JUSTIFICATIONS,
+            )?),
+            _ => Err("Sync data v1 has an unknown variant.")?,
+        })
+    }
+}
+
+// Written by hand to bound the lengths of the vectors before decoding them, the variant indices
+// are the ones the derived encoding uses.
+impl<B: Block, J: Justification> Decode for NetworkData<B, J>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    fn decode<I: CodecInput>(input: &mut I) -> Result<Self, CodecError> {
+        Ok(match input.read_byte()? {
+            0 => NetworkData::StateBroadcast(State::decode(input)?),
+            1 => NetworkData::StateBroadcastResponse(
+                J::Unverified::decode(input)?,
+                Option::decode(input)?,
+            ),
+            2 => NetworkData::Request(Request::decode(input)?),
+            3 => NetworkData::RequestResponse(decode_response_items(input)?),
+            4 => NetworkData::CapabilitiesAnnouncement(Capabilities::decode(input)?),
+            5 => NetworkData::AvailabilityRequest,
+            6 => NetworkData::AvailabilityResponse(Availability::decode(input)?),
+            7 => NetworkData::AcknowledgementRequest(AcknowledgementTag::decode(input)?),
+            8 => NetworkData::Acknowledgement(AcknowledgementTag::decode(input)?),
+            9 => NetworkData::ExtendedStateBroadcast(ExtendedState::decode(input)?),
+            10 => NetworkData::BatchedStateBroadcastResponse(
+                J::Unverified::decode(input)?,
+                decode_limited_vec(input, MAX_MESSAGE_JUSTIFICATIONS)?,
+            ),
+            11 => NetworkData::BodyRequest(BodyRequest::decode(input)?),
+            12 => NetworkData::PriorityTicket(PriorityTicket::decode(input)?),
+            13 => NetworkData::HeaderRequest(Request::decode(input)?),
+            14 => NetworkData::WarpRequest(SessionId::decode(input)?),
+            15 => NetworkData::WarpResponse(decode_limited_vec(input, MAX_MESSAGE_JUSTIFICATIONS)?),
+            16 => NetworkData::ValidatorTicket(ValidatorTicket::decode(input)?),
+            17 => NetworkData::Announcement(J::Header::decode(input)?),
+            18 => {
+                NetworkData::CorrelatedRequest(RequestId::decode(input)?, Request::decode(input)?)
+            }
+            19 => NetworkData::CorrelatedResponse(
+                RequestId::decode(input)?,
+                decode_response_items(input)?,
+            ),
+            20 => NetworkData::ForkIdAnnouncement(ForkId::decode(input)?),
+            _ => Err("Sync data has an unknown variant.")?,
+        })
+    }
 }
 
 impl<B: Block, J: Justification> From<NetworkDataV1<J>> for NetworkData<B, J>
@@ -173,22 +711,57 @@ where
     }
 }
 
-impl<B: Block, J: Justification> From<NetworkData<B, J>> for NetworkDataV1<J>
+/// The data has no equivalent in the first version of the protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoV1Equivalent;
+
+// Works on a reference, since the data is sent in the newer version as well, and the legacy
+// equivalent usually needs only a small part of it.
+impl<B: Block, J: Justification> TryFrom<&NetworkData<B, J>> for NetworkDataV1<J>
 where
     B: Block,
     J: Justification<Header = B::Header>,
 {
-    fn from(data: NetworkData<B, J>) -> Self {
-        match data {
-            NetworkData::StateBroadcast(state) => NetworkDataV1::StateBroadcast(state),
+    type Error = NoV1Equivalent;
+
+    fn try_from(data: &NetworkData<B, J>) -> Result<Self, Self::Error> {
+        Ok(match data {
+            NetworkData::StateBroadcast(state) => NetworkDataV1::StateBroadcast(state.clone()),
+            NetworkData::ExtendedStateBroadcast(extended) => {
+                NetworkDataV1::StateBroadcast(extended.state().clone())
+            }
             NetworkData::StateBroadcastResponse(justification, maybe_justification) => {
-                NetworkDataV1::StateBroadcastResponse(justification, maybe_justification)
+                NetworkDataV1::StateBroadcastResponse(
+                    justification.clone(),
+                    maybe_justification.clone(),
+                )
             }
-            NetworkData::Request(request) => NetworkDataV1::Request(request),
+            NetworkData::BatchedStateBroadcastResponse(justification, justifications) => {
+                NetworkDataV1::StateBroadcastResponse(
+                    justification.clone(),
+                    justifications.first().cloned(),
+                )
+            }
+            NetworkData::Request(request) => NetworkDataV1::Request(request.clone()),
             NetworkData::RequestResponse(response_items) => NetworkDataV1::RequestResponse(
                 ResponseItem::justifications_from_response_items(response_items),
             ),
-        }
+            NetworkData::CapabilitiesAnnouncement(_)
+            | NetworkData::AvailabilityRequest
+            | NetworkData::AvailabilityResponse(_)
+            | NetworkData::AcknowledgementRequest(_)
+            | NetworkData::Acknowledgement(_)
+            | NetworkData::BodyRequest(_)
+            | NetworkData::PriorityTicket(_)
+            | NetworkData::HeaderRequest(_)
+            | NetworkData::WarpRequest(_)
+            | NetworkData::WarpResponse(_)
+            | NetworkData::ValidatorTicket(_)
+            | NetworkData::Announcement(_)
+            | NetworkData::CorrelatedRequest(_, _)
+            | NetworkData::CorrelatedResponse(_, _)
+            | NetworkData::ForkIdAnnouncement(_) => return Err(NoV1Equivalent),
+        })
     }
 }
 
@@ -203,6 +776,20 @@ where
     Other(Version, Vec<u8>),
     V1(NetworkDataV1<J>),
     V2(NetworkData<B, J>),
+    /// The second version extended with acknowledgements and extended states. Only these are
+    /// sent in it, everything else is understood by older nodes in the second version.
+    V3(NetworkData<B, J>),
+    /// Data of the third version, compressed. Only sent to peers that demonstrated understanding
+    /// it, and mostly when it is large. Can contain batched responses for peers that demonstrated
+    /// understanding the fifth version.
+    V4(CompressedData),
+    /// The third version extended with batched responses. Peers that demonstrated understanding
+    /// it get all the extended data in it, so that they keep demonstrating it back.
+    V5(NetworkData<B, J>),
+    /// The fifth version preceded by the features the sender understands. Only states are sent
+    /// in it, to the peers that demonstrated understanding it, which then get every message in
+    /// the richest encoding both sides understand.
+    V6(ProtocolFeatures, NetworkData<B, J>),
 }
 
 // We need 32 bits, since blocks can be quite sizeable.
@@ -210,21 +797,118 @@ type ByteCount = u32;
 
 // We agreed to 15mb + some wiggle room for sync message.
 // Maximum block size is 5mb so we have spare for at least 3 blocks.
-pub const MAX_SYNC_MESSAGE_SIZE: u32 = 15 * 1024 * 1024 + 1024;
-const_assert!(MAX_SYNC_MESSAGE_SIZE > 3 * MAX_BLOCK_SIZE);
+pub const DEFAULT_SYNC_MESSAGE_SIZE: u32 = 15 * 1024 * 1024 + 1024;
+const_assert!(DEFAULT_SYNC_MESSAGE_SIZE > 3 * MAX_BLOCK_SIZE);
+/// No configuration can raise the size of sync messages above this, anything larger is neither
+/// encoded nor decoded.
+pub const MAX_SYNC_MESSAGE_SIZE: u32 = 64 * 1024 * 1024;
+const_assert!(MAX_SYNC_MESSAGE_SIZE >= DEFAULT_SYNC_MESSAGE_SIZE);
+/// Data encoded as at least this many bytes gets compressed for peers that understand it.
+const COMPRESSION_THRESHOLD: usize = 16 * 1024;
+
+/// The sync message was refused, because its payload is larger than the maximum size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageTooBig {
+    pub version: Version,
+    pub size: usize,
+    pub limit: u32,
+}
 
-fn encode_with_version(version: Version, payload: &[u8]) -> Vec<u8> {
-    let size = payload.len().try_into().unwrap_or(ByteCount::MAX);
+impl Display for MessageTooBig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(
+            f,
+            "sync message v{:?} of {} bytes exceeds the maximum of {}",
+            self.version, self.size, self.limit
+        )
+    }
+}
 
-    if size > MAX_SYNC_MESSAGE_SIZE {
-        warn!(
-            target: LOG_TARGET,
-            "Versioned sync message v{:?} too big during Encode. Size is {:?}. Should be {:?} at max.",
+fn checked_byte_count(
+    version: Version,
+    size: usize,
+    limit: u32,
+) -> Result<ByteCount, MessageTooBig> {
+    ByteCount::try_from(size)
+        .ok()
+        .filter(|byte_count| *byte_count <= limit.min(MAX_SYNC_MESSAGE_SIZE))
+        .ok_or(MessageTooBig {
             version,
-            payload.len(),
-            MAX_SYNC_MESSAGE_SIZE
-        );
+            size,
+            limit,
+        })
+}
+
+/// Responses smaller than this many bytes are encoded on the calling thread, spawning workers
+/// would cost more than it saves.
+const PARALLEL_ENCODING_THRESHOLD: usize = 1024 * 1024;
+
+/// Encodes the items of a response concurrently into separate buffers, which are then
+/// concatenated, producing exactly what the sequential encoding would.
+fn encode_response_in_parallel<B, J>(
+    response_items: &[ResponseItem<B, J>],
+    workers: usize,
+) -> Vec<u8>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    // The enum variant index, without the length of the empty vector following it.
+    let mut result = NetworkData::<B, J>::RequestResponse(Vec::new()).encode();
+    result.truncate(result.len() - Compact(0u32).encoded_size());
+    let chunk_length = (response_items.len() + workers - 1) / workers;
+    let buffers: Vec<Vec<u8>> = thread::scope(|scope| {
+        let handles: Vec<_> = response_items
+            .chunks(chunk_length.max(1))
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut buffer = Vec::with_capacity(chunk.iter().map(Encode::size_hint).sum());
+                    for item in chunk {
+                        item.encode_to(&mut buffer);
+                    }
+                    buffer
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("encoding does not panic"))
+            .collect()
+    });
+    let length = Compact(response_items.len() as u32);
+    result.reserve(length.size_hint() + buffers.iter().map(Vec::len).sum::<usize>());
+    length.encode_to(&mut result);
+    for buffer in buffers {
+        result.extend_from_slice(&buffer);
+    }
+    result
+}
+
+/// Encodes the data, large responses with all the available threads.
+fn encode_network_data<B, J>(data: &NetworkData<B, J>) -> Vec<u8>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    let workers = workers();
+    match data {
+        NetworkData::RequestResponse(response_items)
+            if workers > 1
+                && response_items.len() > 1
+                && data.size_hint() >= PARALLEL_ENCODING_THRESHOLD =>
+        {
+            encode_response_in_parallel(response_items, workers)
+        }
+        data => data.encode(),
     }
+}
+
+fn encode_with_version(
+    version: Version,
+    payload: &[u8],
+    limit: u32,
+) -> Result<Vec<u8>, MessageTooBig> {
+    let size = checked_byte_count(version, payload.len(), limit)?;
 
     let mut result = Vec::with_capacity(version.size_hint() + size.size_hint() + payload.len());
 
@@ -232,7 +916,40 @@ fn encode_with_version(version: Version, payload: &[u8]) -> Vec<u8> {
     size.encode_to(&mut result);
     result.extend_from_slice(payload);
 
-    result
+    Ok(result)
+}
+
+impl<B, J> VersionedNetworkData<B, J>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    fn version(&self) -> Version {
+        use VersionedNetworkData::*;
+        match self {
+            Other(version, _) => *version,
+            V1(_) => Version(1),
+            V2(_) => Version(2),
+            V3(_) => Version(3),
+            V4(_) => Version(4),
+            V5(_) => Version(5),
+            V6(_, _) => Version(6),
+        }
+    }
+
+    /// Checks that the data fits in a single sync message of at most `limit` bytes, without
+    /// encoding it.
+    pub fn check_size(&self, limit: u32) -> Result<(), MessageTooBig> {
+        use VersionedNetworkData::*;
+        let size = match self {
+            Other(_, payload) => payload.len(),
+            V1(data) => data.encoded_size(),
+            V2(data) | V3(data) | V5(data) => data.encoded_size(),
+            V4(data) => data.encoded_size(),
+            V6(features, data) => features.encoded_size() + data.encoded_size(),
+        };
+        checked_byte_count(self.version(), size, limit).map(|_| ())
+    }
 }
 
 impl<B, J> Encode for VersionedNetworkData<B, J>
@@ -249,17 +966,35 @@ where
             + match self {
                 Other(_, payload) => payload.len(),
                 V1(data) => data.size_hint(),
-                V2(data) => data.size_hint(),
+                V2(data) | V3(data) | V5(data) => data.size_hint(),
+                V4(data) => data.size_hint(),
+                V6(features, data) => features.size_hint() + data.size_hint(),
             }
     }
 
     fn encode(&self) -> Vec<u8> {
         use VersionedNetworkData::*;
-        match self {
-            Other(version, payload) => encode_with_version(*version, payload),
-            V1(data) => encode_with_version(Version(1), &data.encode()),
-            V2(data) => encode_with_version(Version(2), &data.encode()),
-        }
+        // The configured limit was already checked by the version wrapper.
+        let limit = MAX_SYNC_MESSAGE_SIZE;
+        let encoded = match self {
+            Other(version, payload) => encode_with_version(*version, payload, limit),
+            V1(data) => encode_with_version(Version(1), &data.encode(), limit),
+            V2(data) => encode_with_version(Version(2), &encode_network_data(data), limit),
+            V3(data) => encode_with_version(Version(3), &encode_network_data(data), limit),
+            V4(data) => encode_with_version(Version(4), &data.encode(), limit),
+            V5(data) => encode_with_version(Version(5), &encode_network_data(data), limit),
+            V6(features, data) => {
+                let mut payload = features.encode();
+                payload.extend_from_slice(&encode_network_data(data));
+                encode_with_version(Version(6), &payload, limit)
+            }
+        };
+        encoded.unwrap_or_else(|e| {
+            // The version wrapper refuses such data before it gets here, and a truncated message
+            // could not be decoded by anyone anyway.
+            error!(target: LOG_TARGET, "Not encoding sync data: {}.", e);
+            Vec::new()
+        })
     }
 }
 
@@ -275,10 +1010,23 @@ where
         match version {
             Version(1) => Ok(V1(NetworkDataV1::decode(input)?)),
             Version(2) => Ok(V2(NetworkData::decode(input)?)),
+            Version(3) => Ok(V3(NetworkData::decode(input)?)),
+            // Decompressed only by the version wrapper, where the peer is known.
+            Version(4) => Ok(V4(CompressedData::decode(input)?)),
+            Version(5) => Ok(V5(NetworkData::decode(input)?)),
+            Version(6) => Ok(V6(
+                ProtocolFeatures::decode(input)?,
+                NetworkData::decode(input)?,
+            )),
             _ => {
                 if num_bytes > MAX_SYNC_MESSAGE_SIZE {
                     Err("Sync message has unknown version and is encoded as more than the maximum size.")?;
                 };
+                // Do not allocate for a payload that is not there.
+                if matches!(input.remaining_len()?, Some(remaining) if remaining < num_bytes as usize)
+                {
+                    Err("Sync message has unknown version and is shorter than declared.")?;
+                }
                 let mut payload = vec![0; num_bytes as usize];
                 input.read(payload.as_mut_slice())?;
                 Ok(Other(version, payload))
@@ -287,87 +1035,1715 @@ where
     }
 }
 
-/// Wrap around a network to avoid thinking about versioning.
-pub struct VersionWrapper<B, J, N>
-where
-    N: GossipNetwork<VersionedNetworkData<B, J>>,
-    B: Block,
-    J: Justification<Header = B::Header>,
-{
-    inner: N,
-    _phantom: PhantomData<(B, J)>,
+/// What can go wrong when using the versioned network.
+#[derive(Debug)]
+pub enum VersionedNetworkError<E> {
+    Network(E),
+    MessageTooBig(MessageTooBig),
 }
 
-impl<B, J, N> VersionWrapper<B, J, N>
-where
-    N: GossipNetwork<VersionedNetworkData<B, J>>,
-    B: Block,
-    J: Justification<Header = B::Header>,
-{
-    /// Wrap the inner network.
-    pub fn new(inner: N) -> Self {
-        VersionWrapper {
-            inner,
-            _phantom: PhantomData,
+impl<E: Display> Display for VersionedNetworkError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use VersionedNetworkError::*;
+        match self {
+            Network(e) => write!(f, "{e}"),
+            MessageTooBig(e) => write!(f, "{e}"),
         }
     }
 }
 
-#[async_trait::async_trait]
-impl<B, J, N> GossipNetwork<NetworkData<B, J>> for VersionWrapper<B, J, N>
+fn checked<B, J, E>(
+    data: VersionedNetworkData<B, J>,
+    limit: u32,
+) -> Result<VersionedNetworkData<B, J>, VersionedNetworkError<E>>
 where
-    N: GossipNetwork<VersionedNetworkData<B, J>>,
     B: Block,
     J: Justification<Header = B::Header>,
 {
-    type Error = N::Error;
-    type PeerId = N::PeerId;
+    data.check_size(limit)
+        .map_err(VersionedNetworkError::MessageTooBig)?;
+    Ok(data)
+}
 
-    fn send_to(
-        &mut self,
-        data: NetworkData<B, J>,
-        peer_id: Self::PeerId,
-    ) -> Result<(), Self::Error> {
-        self.inner.send_to(
-            VersionedNetworkData::V1(data.clone().into()),
-            peer_id.clone(),
-        )?;
-        self.inner.send_to(VersionedNetworkData::V2(data), peer_id)
-    }
+/// Decides when the first version of the protocol is retired. The cutoff is the same for the
+/// whole network, so all the nodes stop using the legacy format at the same block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct LegacyCutoff {
+    cutoff: Option<BlockNumber>,
+    retired: bool,
+}
 
-    fn send_to_random(
-        &mut self,
-        data: NetworkData<B, J>,
-        peer_ids: HashSet<Self::PeerId>,
-    ) -> Result<(), Self::Error> {
-        self.inner.send_to_random(
-            VersionedNetworkData::V1(data.clone().into()),
-            peer_ids.clone(),
-        )?;
-        self.inner
-            .send_to_random(VersionedNetworkData::V2(data), peer_ids)
+impl LegacyCutoff {
+    fn new(cutoff: Option<BlockNumber>) -> Self {
+        LegacyCutoff {
+            cutoff,
+            retired: false,
+        }
     }
 
-    fn broadcast(&mut self, data: NetworkData<B, J>) -> Result<(), Self::Error> {
-        self.inner
-            .broadcast(VersionedNetworkData::V1(data.clone().into()))?;
-        self.inner.broadcast(VersionedNetworkData::V2(data))
+    /// Whether the first version should still be sent and accepted.
+    fn legacy_enabled(&self) -> bool {
+        !self.retired
     }
 
-    /// Retrieves next message from the network.
-    ///
-    /// # Cancel safety
-    ///
-    /// This method is cancellation safe.
-    async fn next(&mut self) -> Result<(NetworkData<B, J>, Self::PeerId), Self::Error> {
-        loop {
-            match self.inner.next().await? {
-                (VersionedNetworkData::Other(version, _), _) => {
-                    warn!(target: LOG_TARGET, "Received sync data of unsupported version {:?}, this node might be running outdated software.", version)
-                }
-                (VersionedNetworkData::V1(data), peer_id) => return Ok((data.into(), peer_id)),
-                (VersionedNetworkData::V2(data), peer_id) => return Ok((data, peer_id)),
+    /// Returns whether the first version got retired just now.
+    fn update(&mut self, top_finalized: BlockNumber) -> bool {
+        match self.cutoff {
+            Some(cutoff) if !self.retired && top_finalized >= cutoff => {
+                self.retired = true;
+                true
             }
+            _ => false,
+        }
+    }
+}
+
+/// Peers not heard from in a version for this long are not assumed to understand it anymore.
+/// Much longer than the longest break between state broadcasts, even suppressed ones.
+const VERSION_NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(120);
+/// How many peers we remember the versions of.
+const MAX_NEGOTIATED_PEERS: usize = 1024;
+/// Peers that did not demonstrate understanding a version newer than the second are sent data in
+/// it once in this long, so that nodes supporting it can recognize each other.
+const PROBE_PERIOD: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DemonstratedVersion {
+    version: Version,
+    features: ProtocolFeatures,
+    last_seen: Instant,
+}
+
+/// The highest versions of the protocol peers demonstrated understanding of by sending data in
+/// them. Nodes supporting the second version always send it, so receiving only the first one
+/// for a while means the peer runs older software. Peers sending the sixth version tell us their
+/// features, for older ones the features are implied by the version.
+struct PeerVersions<I: Clone + Eq + Hash> {
+    started: Instant,
+    peers: LruCache<I, DemonstratedVersion>,
+    probes: LruCache<(I, u16), Instant>,
+}
+
+impl<I: Clone + Eq + Hash> PeerVersions<I> {
+    fn new(now: Instant) -> Self {
+        PeerVersions {
+            started: now,
+            peers: LruCache::new(
+                NonZeroUsize::new(MAX_NEGOTIATED_PEERS).expect("the constant is nonzero"),
+            ),
+            probes: LruCache::new(
+                NonZeroUsize::new(MAX_NEGOTIATED_PEERS).expect("the constant is nonzero"),
+            ),
         }
     }
+
+    fn is_recent(demonstrated: &DemonstratedVersion, now: Instant) -> bool {
+        now.saturating_duration_since(demonstrated.last_seen) < VERSION_NEGOTIATION_TIMEOUT
+    }
+
+    /// Records that the peer sent data in the version.
+    fn received(&mut self, peer: I, version: Version, now: Instant) {
+        self.demonstrated(
+            peer,
+            DemonstratedVersion {
+                version,
+                features: ProtocolFeatures::implied_by(version),
+                last_seen: now,
+            },
+            now,
+        )
+    }
+
+    /// Records that the peer sent its features in the sixth version.
+    fn received_features(&mut self, peer: I, features: ProtocolFeatures, now: Instant) {
+        self.demonstrated(
+            peer,
+            DemonstratedVersion {
+                version: Version(6),
+                features,
+                last_seen: now,
+            },
+            now,
+        )
+    }
+
+    fn demonstrated(&mut self, peer: I, demonstrated: DemonstratedVersion, now: Instant) {
+        let version = demonstrated.version;
+        match self.peers.get_mut(&peer) {
+            // The older versions sent alongside do not lower what the peer understands.
+            Some(known) if known.version.0 > version.0 && Self::is_recent(known, now) => {}
+            Some(known) => *known = demonstrated,
+            None => {
+                self.peers.put(peer, demonstrated);
+            }
+        }
+    }
+
+    /// The highest version the peer recently demonstrated understanding of.
+    fn version(&self, peer: &I, now: Instant) -> Option<Version> {
+        self.peers
+            .peek(peer)
+            .filter(|demonstrated| Self::is_recent(demonstrated, now))
+            .map(|demonstrated| demonstrated.version)
+    }
+
+    /// Whether the peer recently demonstrated understanding the second version.
+    fn understands_current(&self, peer: &I, now: Instant) -> bool {
+        matches!(self.version(peer, now), Some(Version(version)) if version >= 2)
+    }
+
+    /// The features both we and the peer understand, according to what it recently sent.
+    fn features(&self, peer: &I, now: Instant) -> ProtocolFeatures {
+        self.peers
+            .peek(peer)
+            .filter(|demonstrated| Self::is_recent(demonstrated, now))
+            .map_or(ProtocolFeatures::NONE, |demonstrated| {
+                demonstrated
+                    .features
+                    .intersection(ProtocolFeatures::SUPPORTED)
+            })
+    }
+
+    /// Whether the peer recently told us its features in the sixth version.
+    fn negotiates_features(&self, peer: &I, now: Instant) -> bool {
+        matches!(self.version(peer, now), Some(Version(version)) if version >= 6)
+    }
+
+    /// Whether the peer recently demonstrated understanding the third version.
+    fn understands_extensions(&self, peer: &I, now: Instant) -> bool {
+        self.features(peer, now)
+            .contains(ProtocolFeatures::EXTENSIONS)
+    }
+
+    /// Whether the peer recently demonstrated understanding compressed data.
+    fn understands_compression(&self, peer: &I, now: Instant) -> bool {
+        self.features(peer, now)
+            .contains(ProtocolFeatures::COMPRESSION)
+    }
+
+    /// Whether the peer recently demonstrated understanding batched responses.
+    fn understands_batches(&self, peer: &I, now: Instant) -> bool {
+        self.features(peer, now).contains(ProtocolFeatures::BATCHES)
+    }
+
+    /// Whether the peer recently announced understanding all the features, e.g. the ones needed
+    /// for announcements of favourite blocks.
+    fn understands_features(&self, peer: &I, features: ProtocolFeatures, now: Instant) -> bool {
+        self.features(peer, now).contains(features)
+    }
+
+    /// Whether the peer recently announced understanding requests and responses with identifiers.
+    fn understands_correlation(&self, peer: &I, now: Instant) -> bool {
+        self.features(peer, now)
+            .contains(ProtocolFeatures::CORRELATION)
+    }
+
+    /// Whether the peer should be sent data in the version, even though it did not demonstrate
+    /// understanding it. If this returns `true` the peer is considered probed.
+    fn probe(&mut self, peer: &I, version: Version, now: Instant) -> bool {
+        let key = (peer.clone(), version.0);
+        if matches!(self.probes.peek(&key), Some(probed) if now.saturating_duration_since(*probed) < PROBE_PERIOD)
+        {
+            return false;
+        }
+        self.probes.put(key, now);
+        true
+    }
+
+    /// All the peers recently heard from, with the highest versions they demonstrated.
+    fn recent_peers(&self, now: Instant) -> Vec<(I, Version)> {
+        self.peers
+            .iter()
+            .filter(|(_, demonstrated)| Self::is_recent(demonstrated, now))
+            .map(|(peer, demonstrated)| (peer.clone(), demonstrated.version))
+            .collect()
+    }
+
+    /// Whether all the connected peers had enough time to demonstrate their versions.
+    fn settled(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) >= VERSION_NEGOTIATION_TIMEOUT
+    }
+
+    /// The peers that recently sent data only in the first version.
+    fn legacy_peers(&self, now: Instant) -> Vec<I> {
+        self.peers
+            .iter()
+            .filter(|(_, demonstrated)| {
+                demonstrated.version == Version(1) && Self::is_recent(demonstrated, now)
+            })
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+}
+
+/// How many peers we remember the last requests sent to.
+const MAX_TIMED_REQUESTS: usize = 256;
+/// Responses arriving later than this after the request are not considered answers to it.
+const REQUEST_ROUND_TRIP_EXPIRY: Duration = Duration::from_secs(30);
+
+/// When requests were last sent to peers, to measure how long they take to respond. Responses
+/// do not identify the requests they answer, so matching them is only approximate, and a request
+/// sent to a random peer is timed for all of the candidates.
+struct RequestTimes<I: Clone + Eq + Hash> {
+    sent: LruCache<I, Instant>,
+}
+
+impl<I: Clone + Eq + Hash> RequestTimes<I> {
+    fn new() -> Self {
+        RequestTimes {
+            sent: LruCache::new(
+                NonZeroUsize::new(MAX_TIMED_REQUESTS).expect("the constant is nonzero"),
+            ),
+        }
+    }
+
+    fn sent(&mut self, peer: I, now: Instant) {
+        self.sent.put(peer, now);
+    }
+
+    /// The round trip of the last request sent to the peer, if it did not expire.
+    fn responded(&mut self, peer: &I, now: Instant) -> Option<Duration> {
+        let round_trip = now.saturating_duration_since(self.sent.pop(peer)?);
+        match round_trip < REQUEST_ROUND_TRIP_EXPIRY {
+            true => Some(round_trip),
+            false => None,
+        }
+    }
+}
+
+/// How many requests can wait for their responses at once, the oldest ones are given up on first.
+const MAX_PENDING_REQUESTS: usize = 256;
+/// How many answered requests are remembered, to recognize the later parts of their responses.
+const MAX_ANSWERED_REQUESTS: usize = 256;
+
+/// What became of a response carrying the identifier of a request.
+#[derive(Debug, PartialEq, Eq)]
+enum Correlated<D> {
+    /// It went to the requester waiting for it.
+    Delivered,
+    /// It answers a request that got answered already, or whose requester stopped waiting, so it
+    /// is just data now.
+    Late(D),
+    /// It answers nothing we asked the peer about.
+    Unsolicited,
+}
+
+/// The requests waiting for responses identifying them, with the receivers of the responses held
+/// by the requesters.
+struct PendingRequests<I: Clone + Eq + Hash, D> {
+    next_id: RequestId,
+    waiting: LruCache<(I, RequestId), oneshot::Sender<D>>,
+    answered: LruCache<(I, RequestId), ()>,
+}
+
+impl<I: Clone + Eq + Hash, D> PendingRequests<I, D> {
+    fn new() -> Self {
+        PendingRequests {
+            next_id: 0,
+            waiting: LruCache::new(
+                NonZeroUsize::new(MAX_PENDING_REQUESTS).expect("the constant is nonzero"),
+            ),
+            answered: LruCache::new(
+                NonZeroUsize::new(MAX_ANSWERED_REQUESTS).expect("the constant is nonzero"),
+            ),
+        }
+    }
+
+    /// The identifier for a new request to the peer, with the receiver of its response. Once
+    /// too many requests wait, the receiver of the oldest one learns it will not get anything.
+    fn register(&mut self, peer: I) -> (RequestId, oneshot::Receiver<D>) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let (sender, receiver) = oneshot::channel();
+        self.waiting.put((peer, id), sender);
+        (id, receiver)
+    }
+
+    /// Hands the response from the peer to whoever waits for it.
+    fn respond(&mut self, peer: I, id: RequestId, response: D) -> Correlated<D> {
+        let key = (peer, id);
+        let result = match self.waiting.pop(&key) {
+            Some(sender) => match sender.send(response) {
+                Ok(()) => Correlated::Delivered,
+                Err(response) => Correlated::Late(response),
+            },
+            None if self.answered.contains(&key) => Correlated::Late(response),
+            None => return Correlated::Unsolicited,
+        };
+        self.answered.put(key, ());
+        result
+    }
+}
+
+/// Wrap around a network to avoid thinking about versioning.
+/// Data that would not fit in a single message of the configured size is refused instead of being
+/// sent, and compressed data is never decompressed beyond it.
+/// While the first version is in use, data is sent in both versions only to peers that did not
+/// demonstrate understanding the second one, or to everyone until the peers had the time to do so.
+/// Large data is compressed for the peers that demonstrated understanding compressed data.
+/// Peers telling us their features in states get every message in the richest encoding both
+/// sides understand, each feature on its own rather than implied by a version.
+/// All the messages passing through are reported to the metrics, as sent or received once each.
+/// Requests to peers understanding identifiers can be matched with their responses, which then go
+/// to the requesters instead of arriving like any other data.
+/// Peers behind the V1 shim only ever get the first version, also after the legacy cutoff, with
+/// the features configured for them translated into sequences of its messages.
+pub struct VersionWrapper<B, J, N>
+where
+    N: GossipNetwork<VersionedNetworkData<B, J>>,
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    inner: N,
+    legacy: LegacyCutoff,
+    versions: PeerVersions<N::PeerId>,
+    compression: Compression,
+    max_message_size: u32,
+    metrics: Metrics,
+    requests: RequestTimes<N::PeerId>,
+    pending: PendingRequests<N::PeerId, NetworkData<B, J>>,
+    shim: V1Shim<N::PeerId>,
+    _phantom: PhantomData<(B, J)>,
+}
+
+impl<B, J, N> VersionWrapper<B, J, N>
+where
+    N: GossipNetwork<VersionedNetworkData<B, J>>,
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    /// Wrap the inner network. Once the legacy cutoff block is finalized, the first version of
+    /// the protocol is neither sent nor accepted anymore. Used forever without a cutoff.
+    /// Messages are at most `max_message_size` bytes, but never more than `MAX_SYNC_MESSAGE_SIZE`.
+    pub fn new(
+        inner: N,
+        legacy_cutoff: Option<BlockNumber>,
+        max_message_size: u32,
+        metrics: Metrics,
+    ) -> Self {
+        VersionWrapper {
+            inner,
+            legacy: LegacyCutoff::new(legacy_cutoff),
+            versions: PeerVersions::new(Instant::now()),
+            compression: Compression::default(),
+            max_message_size: max_message_size.min(MAX_SYNC_MESSAGE_SIZE),
+            metrics,
+            requests: RequestTimes::new(),
+            pending: PendingRequests::new(),
+            shim: V1Shim::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Talks to the peers of the shim only in the first version of the protocol.
+    pub fn with_v1_shim(mut self, shim: V1Shim<N::PeerId>) -> Self {
+        self.shim = shim;
+        self
+    }
+
+    /// Informs about the highest finalized block, to retire the first version of the protocol
+    /// at the right moment.
+    pub fn update_top_finalized(&mut self, number: BlockNumber) {
+        if self.legacy.update(number) {
+            info!(
+                target: LOG_TARGET,
+                "Retired the legacy version of the sync protocol at block {}.", number
+            );
+        }
+    }
+
+    /// Whether the peer recently announced understanding all the features, so that it can be
+    /// sent the data requiring them.
+    pub fn understands_features(&self, peer_id: &N::PeerId, features: ProtocolFeatures) -> bool {
+        self.versions
+            .understands_features(peer_id, features, Instant::now())
+    }
+}
+
+impl<B, J, N> VersionWrapper<B, J, N>
+where
+    N: GossipNetwork<VersionedNetworkData<B, J>>,
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    /// Whether the peer can get the data, i.e. it does not need negotiated features, or the peer
+    /// announced understanding them.
+    fn can_receive(&self, data: &NetworkData<B, J>, peer_id: &N::PeerId, now: Instant) -> bool {
+        !data.needs_negotiation()
+            || self
+                .versions
+                .understands_features(peer_id, data.required_features(), now)
+    }
+
+    fn random_receiver(
+        &self,
+        data: &NetworkData<B, J>,
+        peer_ids: &HashSet<N::PeerId>,
+        now: Instant,
+    ) -> Option<N::PeerId> {
+        peer_ids
+            .iter()
+            .filter(|peer_id| self.can_receive(data, peer_id, now))
+            .choose(&mut thread_rng())
+            .cloned()
+    }
+
+    fn report_sent(&mut self, data: &NetworkData<B, J>, peer_ids: &[&N::PeerId]) {
+        self.metrics.report_message(MessageDirection::Sent, data);
+        if let NetworkData::Request(_)
+        | NetworkData::BodyRequest(_)
+        | NetworkData::HeaderRequest(_)
+        | NetworkData::WarpRequest(_)
+        | NetworkData::CorrelatedRequest(_, _) = data
+        {
+            let now = Instant::now();
+            for peer_id in peer_ids {
+                self.requests.sent((*peer_id).clone(), now);
+            }
+        }
+    }
+
+    fn report_received(&mut self, data: &NetworkData<B, J>, peer_id: &N::PeerId) {
+        self.metrics
+            .report_message(MessageDirection::Received, data);
+        if let NetworkData::RequestResponse(_)
+        | NetworkData::WarpResponse(_)
+        | NetworkData::CorrelatedResponse(_, _) = data
+        {
+            if let Some(round_trip) = self.requests.responded(peer_id, Instant::now()) {
+                self.metrics.report_request_round_trip(round_trip);
+            }
+        }
+    }
+
+    /// Retrieves next message from the network, in whichever version it came.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancellation safe.
+    async fn receive(
+        &mut self,
+    ) -> Result<(NetworkData<B, J>, N::PeerId), VersionedNetworkError<N::Error>> {
+        loop {
+            match self
+                .inner
+                .next()
+                .await
+                .map_err(VersionedNetworkError::Network)?
+            {
+                (VersionedNetworkData::Other(version, _), _) => {
+                    warn!(target: LOG_TARGET, "Received sync data of unsupported version {:?}, this node might be running outdated software.", version)
+                }
+                (VersionedNetworkData::V1(_), peer_id)
+                    if !self.legacy.legacy_enabled() && !self.shim.is_shimmed(&peer_id) =>
+                {
+                    debug!(target: LOG_TARGET, "Rejecting legacy sync data from {:?}, the legacy version is retired.", peer_id)
+                }
+                (VersionedNetworkData::V1(data), peer_id) => {
+                    self.versions
+                        .received(peer_id.clone(), Version(1), Instant::now());
+                    let data = data.into();
+                    self.report_received(&data, &peer_id);
+                    return Ok((data, peer_id));
+                }
+                (VersionedNetworkData::V2(data), peer_id) => {
+                    self.versions
+                        .received(peer_id.clone(), Version(2), Instant::now());
+                    self.report_received(&data, &peer_id);
+                    return Ok((data, peer_id));
+                }
+                (VersionedNetworkData::V3(data), peer_id) => {
+                    self.versions
+                        .received(peer_id.clone(), Version(3), Instant::now());
+                    self.report_received(&data, &peer_id);
+                    return Ok((data, peer_id));
+                }
+                (VersionedNetworkData::V5(data), peer_id) => {
+                    self.versions
+                        .received(peer_id.clone(), Version(5), Instant::now());
+                    self.report_received(&data, &peer_id);
+                    return Ok((data, peer_id));
+                }
+                (VersionedNetworkData::V6(features, data), peer_id) => {
+                    self.versions
+                        .received_features(peer_id.clone(), features, Instant::now());
+                    self.report_received(&data, &peer_id);
+                    return Ok((data, peer_id));
+                }
+                (VersionedNetworkData::V4(data), peer_id) => {
+                    // Never more than what could be sent uncompressed.
+                    let decompressed = match self
+                        .compression
+                        .decompress(&data, self.max_message_size as usize)
+                    {
+                        Ok(decompressed) => decompressed,
+                        Err(e) => {
+                            debug!(target: LOG_TARGET, "Failed to decompress sync data from {:?}: {}.", peer_id, e);
+                            continue;
+                        }
+                    };
+                    match NetworkData::decode(&mut &decompressed[..]) {
+                        Ok(data) => {
+                            self.versions
+                                .received(peer_id.clone(), Version(4), Instant::now());
+                            self.report_received(&data, &peer_id);
+                            return Ok((data, peer_id));
+                        }
+                        Err(e) => {
+                            debug!(target: LOG_TARGET, "Failed to decode compressed sync data from {:?}: {}.", peer_id, e)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The data in the first version of the protocol, if it is still used and has an equivalent.
+    fn legacy_data(&self, data: &NetworkData<B, J>) -> Option<NetworkDataV1<J>> {
+        match self.legacy.legacy_enabled() {
+            true => data.try_into().ok(),
+            false => None,
+        }
+    }
+
+    /// Sends the peer of the shim the messages of the first version its features translate the
+    /// data into, if any.
+    fn send_shimmed(
+        &mut self,
+        data: &NetworkData<B, J>,
+        peer_id: N::PeerId,
+    ) -> Result<(), VersionedNetworkError<N::Error>> {
+        for message in self.shim.translate(data, &peer_id) {
+            let message = checked(VersionedNetworkData::V1(message), self.max_message_size)?;
+            self.inner
+                .send_to(message, peer_id.clone())
+                .map_err(VersionedNetworkError::Network)?;
+        }
+        Ok(())
+    }
+
+    /// The data compressed in the fourth version, or in the version it would be sent in anyway
+    /// if compressing fails.
+    fn compressed(&self, data: NetworkData<B, J>) -> VersionedNetworkData<B, J> {
+        match self.compression.compress(&encode_network_data(&data)) {
+            Ok(compressed) => VersionedNetworkData::V4(compressed),
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Failed to compress sync data: {}.", e);
+                data.into_versioned()
+            }
+        }
+    }
+
+    fn send_compressed(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer_id: N::PeerId,
+    ) -> Result<(), VersionedNetworkError<N::Error>> {
+        let data = checked(self.compressed(data), self.max_message_size)?;
+        self.inner
+            .send_to(data, peer_id)
+            .map_err(VersionedNetworkError::Network)
+    }
+
+    /// Sends the data in the newest version, compressed if it is large and the peer understands
+    /// it. States go with our features to the peers negotiating them.
+    fn send_latest(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer_id: N::PeerId,
+    ) -> Result<(), VersionedNetworkError<N::Error>> {
+        let now = Instant::now();
+        if self.versions.understands_compression(&peer_id, now)
+            && data.size_hint() >= COMPRESSION_THRESHOLD
+        {
+            return self.send_compressed(data, peer_id);
+        }
+        let data =
+            match self.versions.negotiates_features(&peer_id, now) && data.is_state_broadcast() {
+                true => VersionedNetworkData::V6(ProtocolFeatures::SUPPORTED, data),
+                false => VersionedNetworkData::V5(data),
+            };
+        let data = checked(data, self.max_message_size)?;
+        self.inner
+            .send_to(data, peer_id)
+            .map_err(VersionedNetworkError::Network)
+    }
+
+    fn send_plain(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer_id: N::PeerId,
+    ) -> Result<(), VersionedNetworkError<N::Error>> {
+        use VersionedNetworkError::*;
+        let now = Instant::now();
+        if self.versions.understands_compression(&peer_id, now)
+            && data.size_hint() >= COMPRESSION_THRESHOLD
+        {
+            return self.send_compressed(data, peer_id);
+        }
+        // Checked before sending anything, so that we never send only the older version.
+        let legacy = match self.versions.understands_current(&peer_id, now) {
+            true => None,
+            false => self.legacy_data(&data),
+        };
+        let new = checked(data.into_versioned(), self.max_message_size)?;
+        if let Some(data) = legacy {
+            self.inner
+                .send_to(
+                    checked(VersionedNetworkData::V1(data), self.max_message_size)?,
+                    peer_id.clone(),
+                )
+                .map_err(Network)?;
+        }
+        self.inner.send_to(new, peer_id).map_err(Network)
+    }
+
+    /// Sends the data to a peer which might not understand it, with the equivalent understood
+    /// by older nodes. Once in a while the data is sent anyway, so that the peer can show it
+    /// understands it by answering in the same version. The same goes for compression and
+    /// batches, and peers understanding them always get this data in the version they
+    /// demonstrated, to keep demonstrating it. Batched data is only probed with in the fifth
+    /// version, older nodes would fail to decode it anyway. Peers negotiating features get the
+    /// data if they understand it, and the fallback otherwise, without any probing, while the
+    /// ones understanding batches get probed with our features in states.
+    fn send_extended(
+        &mut self,
+        data: NetworkData<B, J>,
+        fallback: NetworkData<B, J>,
+        peer_id: N::PeerId,
+    ) -> Result<(), VersionedNetworkError<N::Error>> {
+        let now = Instant::now();
+        if self.versions.negotiates_features(&peer_id, now) {
+            let data = match self
+                .versions
+                .features(&peer_id, now)
+                .contains(data.required_features())
+            {
+                true => data,
+                false => fallback,
+            };
+            return self.send_latest(data, peer_id);
+        }
+        if self.versions.understands_batches(&peer_id, now) {
+            if data.is_state_broadcast() && self.versions.probe(&peer_id, Version(6), now) {
+                let probe = checked(
+                    VersionedNetworkData::V6(ProtocolFeatures::SUPPORTED, data.clone()),
+                    self.max_message_size,
+                )?;
+                self.inner
+                    .send_to(probe, peer_id.clone())
+                    .map_err(VersionedNetworkError::Network)?;
+            }
+            return self.send_latest(data, peer_id);
+        }
+        if self.versions.understands_compression(&peer_id, now) {
+            if self.versions.probe(&peer_id, Version(5), now) {
+                let probe = checked(
+                    VersionedNetworkData::V5(data.clone()),
+                    self.max_message_size,
+                )?;
+                self.inner
+                    .send_to(probe, peer_id.clone())
+                    .map_err(VersionedNetworkError::Network)?;
+            }
+            return match data.is_batched() {
+                true => self.send_plain(fallback, peer_id),
+                false => self.send_compressed(data, peer_id),
+            };
+        }
+        if data.is_batched() {
+            return self.send_plain(fallback, peer_id);
+        }
+        if self.versions.understands_extensions(&peer_id, now) {
+            if self.versions.probe(&peer_id, Version(4), now) {
+                self.send_compressed(data.clone(), peer_id.clone())?;
+            }
+            return self.send_plain(data, peer_id);
+        }
+        // Nodes running the legacy version are certainly too old.
+        if self.versions.understands_current(&peer_id, now)
+            && self.versions.probe(&peer_id, Version(3), now)
+        {
+            self.send_plain(data, peer_id.clone())?;
+        }
+        self.send_plain(fallback, peer_id)
+    }
+
+    fn broadcast_plain(
+        &mut self,
+        data: NetworkData<B, J>,
+    ) -> Result<(), VersionedNetworkError<N::Error>> {
+        use VersionedNetworkError::*;
+        let now = Instant::now();
+        let legacy = self.legacy_data(&data);
+        let new = checked(data.into_versioned(), self.max_message_size)?;
+        if let Some(data) = legacy {
+            let data = checked(VersionedNetworkData::V1(data), self.max_message_size)?;
+            match self.versions.settled(now) {
+                // Only the peers that never sent anything in the current version need the legacy
+                // one, and all of them had enough time to show up.
+                true => {
+                    for peer_id in self.versions.legacy_peers(now) {
+                        if self.shim.is_shimmed(&peer_id) {
+                            continue;
+                        }
+                        self.inner.send_to(data.clone(), peer_id).map_err(Network)?;
+                    }
+                }
+                false => self.inner.broadcast(data).map_err(Network)?,
+            }
+        }
+        self.inner.broadcast(new).map_err(Network)
+    }
+
+    /// The data in the single version it can go to the peer in as a request or a response, if
+    /// it can. Only peers understanding the current version get exchanges, others might need the
+    /// legacy equivalent, and data with extensions has to go through probing as usual.
+    fn exchanged(
+        &self,
+        data: &NetworkData<B, J>,
+        peer_id: &N::PeerId,
+    ) -> Option<Result<VersionedNetworkData<B, J>, VersionedNetworkError<N::Error>>> {
+        let now = Instant::now();
+        if data.without_extensions().is_some()
+            || self.shim.is_shimmed(peer_id)
+            || !self.versions.understands_current(peer_id, now)
+            || !self.can_receive(data, peer_id, now)
+        {
+            return None;
+        }
+        let data = match self.versions.understands_compression(peer_id, now)
+            && data.size_hint() >= COMPRESSION_THRESHOLD
+        {
+            true => self.compressed(data.clone()),
+            false => data.clone().into_versioned(),
+        };
+        Some(checked(data, self.max_message_size))
+    }
+}
+
+#[async_trait::async_trait]
+impl<B, J, N> GossipNetwork<NetworkData<B, J>> for VersionWrapper<B, J, N>
+where
+    N: GossipNetwork<VersionedNetworkData<B, J>>,
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    type Error = VersionedNetworkError<N::Error>;
+    type PeerId = N::PeerId;
+
+    fn send_to(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer_id: Self::PeerId,
+    ) -> Result<(), Self::Error> {
+        self.report_sent(&data, &[&peer_id]);
+        if self.shim.is_shimmed(&peer_id) {
+            return self.send_shimmed(&data, peer_id);
+        }
+        if !self.can_receive(&data, &peer_id, Instant::now()) {
+            debug!(target: LOG_TARGET, "Not sending data needing features {:?} to {:?}, it did not announce understanding them.", data.required_features(), peer_id);
+            return Ok(());
+        }
+        match data.without_extensions() {
+            Some(fallback) => self.send_extended(data, fallback, peer_id),
+            None => self.send_plain(data, peer_id),
+        }
+    }
+
+    fn send_to_random(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer_ids: HashSet<Self::PeerId>,
+    ) -> Result<(), Self::Error> {
+        use VersionedNetworkError::*;
+        self.report_sent(&data, &peer_ids.iter().collect::<Vec<_>>());
+        // Not worth probing, the random peer gets whatever all of them understand.
+        let data = data.without_extensions().unwrap_or(data);
+        let now = Instant::now();
+        if data.needs_negotiation() {
+            // The inner network falls back to any peer if none of the given ones is connected, so
+            // the peer is chosen here, only among the ones understanding the data.
+            let peer_id = match self.random_receiver(&data, &peer_ids, now) {
+                Some(peer_id) => peer_id,
+                None => {
+                    debug!(target: LOG_TARGET, "Not sending data needing features {:?}, none of the peers announced understanding them.", data.required_features());
+                    return Ok(());
+                }
+            };
+            let new = checked(data.into_versioned(), self.max_message_size)?;
+            return self.inner.send_to(new, peer_id).map_err(Network);
+        }
+        let current: HashSet<_> = peer_ids
+            .iter()
+            .filter(|peer_id| self.versions.understands_current(peer_id, now))
+            .cloned()
+            .collect();
+        // Choosing from the peers that understand the current version, so that the data has to
+        // be sent only once.
+        if !current.is_empty() {
+            let new = checked(data.into_versioned(), self.max_message_size)?;
+            return self.inner.send_to_random(new, current).map_err(Network);
+        }
+        // After the cutoff only the peers of the shim still get the legacy equivalent.
+        let shimmed: HashSet<_> = peer_ids
+            .iter()
+            .filter(|peer_id| self.shim.is_shimmed(peer_id))
+            .cloned()
+            .collect();
+        let legacy = match self.legacy_data(&data) {
+            Some(legacy) => Some((legacy, peer_ids.clone())),
+            None if !shimmed.is_empty() => (&data).try_into().ok().map(|legacy| (legacy, shimmed)),
+            None => None,
+        };
+        let new = checked(data.into_versioned(), self.max_message_size)?;
+        if let Some((data, legacy_peer_ids)) = legacy {
+            self.inner
+                .send_to_random(
+                    checked(VersionedNetworkData::V1(data), self.max_message_size)?,
+                    legacy_peer_ids,
+                )
+                .map_err(Network)?;
+        }
+        self.inner.send_to_random(new, peer_ids).map_err(Network)
+    }
+
+    fn send_request(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer_id: Self::PeerId,
+    ) -> Result<(), Self::Error> {
+        match self.exchanged(&data, &peer_id) {
+            Some(exchanged) => {
+                self.report_sent(&data, &[&peer_id]);
+                self.inner
+                    .send_request(exchanged?, peer_id)
+                    .map_err(VersionedNetworkError::Network)
+            }
+            None => self.send_to(data, peer_id),
+        }
+    }
+
+    fn send_request_to_random(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer_ids: HashSet<Self::PeerId>,
+    ) -> Result<(), Self::Error> {
+        let now = Instant::now();
+        let current: HashSet<_> = peer_ids
+            .iter()
+            .filter(|peer_id| {
+                self.versions.understands_current(peer_id, now)
+                    && self.can_receive(&data, peer_id, now)
+            })
+            .cloned()
+            .collect();
+        if current.is_empty() || data.without_extensions().is_some() {
+            return self.send_to_random(data, peer_ids);
+        }
+        self.report_sent(&data, &peer_ids.iter().collect::<Vec<_>>());
+        if data.needs_negotiation() {
+            // Same as above, the choice cannot be left to the inner network.
+            if let Some(peer_id) = self.random_receiver(&data, &current, now) {
+                let new = checked(data.into_versioned(), self.max_message_size)?;
+                return self
+                    .inner
+                    .send_request(new, peer_id)
+                    .map_err(VersionedNetworkError::Network);
+            }
+        }
+        // The random peer might not understand compression, requests are small anyway.
+        let new = checked(data.into_versioned(), self.max_message_size)?;
+        self.inner
+            .send_request_to_random(new, current)
+            .map_err(VersionedNetworkError::Network)
+    }
+
+    fn send_response(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer_id: Self::PeerId,
+    ) -> Result<(), Self::Error> {
+        match self.exchanged(&data, &peer_id) {
+            Some(exchanged) => {
+                self.report_sent(&data, &[&peer_id]);
+                self.inner
+                    .send_response(exchanged?, peer_id)
+                    .map_err(VersionedNetworkError::Network)
+            }
+            None => self.send_to(data, peer_id),
+        }
+    }
+
+    fn broadcast(&mut self, data: NetworkData<B, J>) -> Result<(), Self::Error> {
+        self.report_sent(&data, &[]);
+        // Until everybody had the time to show up they might also get the legacy broadcast.
+        let now = Instant::now();
+        for (peer_id, _) in self.versions.recent_peers(now) {
+            if self.shim.is_shimmed(&peer_id) {
+                self.send_shimmed(&data, peer_id)?;
+            }
+        }
+        if data.needs_negotiation() {
+            // Older nodes would fail to decode it, and learn about the blocks otherwise anyway.
+            for (peer_id, _) in self.versions.recent_peers(now) {
+                if !self.shim.is_shimmed(&peer_id) && self.can_receive(&data, &peer_id, now) {
+                    self.send_plain(data.clone(), peer_id)?;
+                }
+            }
+            return Ok(());
+        }
+        let fallback = match data.without_extensions() {
+            Some(fallback) => fallback,
+            None => return self.broadcast_plain(data),
+        };
+        let peers: Vec<_> = self
+            .versions
+            .recent_peers(now)
+            .into_iter()
+            .filter(|(peer_id, _)| !self.shim.is_shimmed(peer_id))
+            .collect();
+        if !self.versions.settled(now) {
+            // Some peers might not have shown up yet, so everybody gets the fallback.
+            let required = match data.is_batched() {
+                true => 5,
+                false => 3,
+            };
+            for (peer_id, version) in peers {
+                if version.0 >= required {
+                    self.send_plain(data.clone(), peer_id)?;
+                }
+            }
+            return self.broadcast_plain(fallback);
+        }
+        // Peers that connected only recently get our data once they send theirs, which all
+        // nodes do periodically.
+        for (peer_id, _) in peers {
+            self.send_extended(data.clone(), fallback.clone(), peer_id)?;
+        }
+        Ok(())
+    }
+
+    fn penalize(&mut self, peer_id: Self::PeerId, penalty: Penalty) -> Result<(), Self::Error> {
+        self.inner
+            .penalize(peer_id, penalty)
+            .map_err(VersionedNetworkError::Network)
+    }
+
+    fn connection(&self, peer_id: &Self::PeerId) -> CancelToken {
+        self.inner.connection(peer_id)
+    }
+
+    fn connection_events(&self) -> ConnectionEvents<Self::PeerId> {
+        self.inner.connection_events()
+    }
+
+    /// Retrieves next message from the network, responses matched with requests go to the
+    /// requesters instead.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancellation safe.
+    async fn next(&mut self) -> Result<(NetworkData<B, J>, Self::PeerId), Self::Error> {
+        loop {
+            match self.receive().await? {
+                (NetworkData::CorrelatedResponse(id, response_items), peer_id) => {
+                    let response = NetworkData::RequestResponse(response_items);
+                    match self.pending.respond(peer_id.clone(), id, response) {
+                        Correlated::Delivered => {}
+                        Correlated::Late(response) => return Ok((response, peer_id)),
+                        Correlated::Unsolicited => {
+                            debug!(target: LOG_TARGET, "Dropping a response from {:?} to request {}, which we did not send.", peer_id, id)
+                        }
+                    }
+                }
+                received => return Ok(received),
+            }
+        }
+    }
+}
+
+impl<B, J, N> RequestNetwork<NetworkData<B, J>> for VersionWrapper<B, J, N>
+where
+    N: GossipNetwork<VersionedNetworkData<B, J>>,
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    /// Only regular requests to peers understanding identifiers get matched with their
+    /// responses, which are given up on after `REQUEST_ROUND_TRIP_EXPIRY`.
+    fn request(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer_id: Self::PeerId,
+    ) -> Result<Option<PendingResponse<NetworkData<B, J>>>, Self::Error> {
+        let request = match data {
+            NetworkData::Request(request)
+                if self
+                    .versions
+                    .understands_correlation(&peer_id, Instant::now()) =>
+            {
+                request
+            }
+            data => return self.send_request(data, peer_id).map(|()| None),
+        };
+        let (id, response) = self.pending.register(peer_id.clone());
+        self.send_to(NetworkData::CorrelatedRequest(id, request), peer_id)?;
+        Ok(Some(
+            async move {
+                match timeout(REQUEST_ROUND_TRIP_EXPIRY, response).await {
+                    Ok(Ok(response)) => Some(response),
+                    _ => None,
+                }
+            }
+            .boxed(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        convert::Infallible,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use futures::future::pending;
+    use parity_scale_codec::{Compact, Decode, Encode};
+    use sp_keystore::{testing::MemoryKeystore, Keystore};
+
+    use super::{
+        encode_network_data, encode_response_in_parallel, limited_response_prefix, BodyRequest,
+        BranchKnowledge, Compression, Correlated, ExtendedState, ForkId, LegacyCutoff,
+        MessageTooBig, NetworkData, NetworkDataV1, PeerVersions, PendingRequests, ProtocolFeatures,
+        Request, RequestTimes, ResponseItem, State, VersionWrapper, VersionedNetworkData,
+        DEFAULT_SYNC_MESSAGE_SIZE, MAX_MESSAGE_BLOCKS, MAX_MESSAGE_JUSTIFICATIONS,
+        MAX_PENDING_REQUESTS, MAX_SYNC_MESSAGE_SIZE, PROBE_PERIOD, REQUEST_ROUND_TRIP_EXPIRY,
+        VERSION_NEGOTIATION_TIMEOUT,
+    };
+    use crate::{
+        aleph_primitives::{AuthorityId, KEY_TYPE},
+        crypto::AuthorityPen,
+        network::{GossipNetwork, Penalty},
+        session::SessionId,
+        sync::{
+            availability::{Availability, Capabilities},
+            metrics::Metrics,
+            mock::{MockBlock, MockHeader, MockIdentifier, MockJustification, MockPeerId},
+            priority::PriorityTicket,
+            roles::ValidatorTicket,
+            Header,
+        },
+        BlockNumber, Version,
+    };
+
+    type Data = VersionedNetworkData<MockBlock, MockJustification>;
+    type MockData = NetworkData<MockBlock, MockJustification>;
+
+    /// Records everything sent through it with the peers it could go to, none for broadcasts.
+    /// Never receives anything.
+    #[derive(Default)]
+    struct RecordingNetwork {
+        sent: Vec<(Data, Vec<MockPeerId>)>,
+    }
+
+    #[async_trait::async_trait]
+    impl GossipNetwork<Data> for RecordingNetwork {
+        type Error = Infallible;
+        type PeerId = MockPeerId;
+
+        fn send_to(&mut self, data: Data, peer_id: MockPeerId) -> Result<(), Self::Error> {
+            self.sent.push((data, vec![peer_id]));
+            Ok(())
+        }
+
+        fn send_to_random(
+            &mut self,
+            data: Data,
+            peer_ids: HashSet<MockPeerId>,
+        ) -> Result<(), Self::Error> {
+            let mut peer_ids: Vec<_> = peer_ids.into_iter().collect();
+            peer_ids.sort();
+            self.sent.push((data, peer_ids));
+            Ok(())
+        }
+
+        fn broadcast(&mut self, data: Data) -> Result<(), Self::Error> {
+            self.sent.push((data, Vec::new()));
+            Ok(())
+        }
+
+        fn penalize(&mut self, _: MockPeerId, _: Penalty) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn next(&mut self) -> Result<(Data, MockPeerId), Self::Error> {
+            pending().await
+        }
+    }
+
+    /// A wrapper knowing a peer running only the second version and one that announced
+    /// understanding all our features.
+    fn wrapper_with_peers() -> VersionWrapper<MockBlock, MockJustification, RecordingNetwork> {
+        let mut wrapper = VersionWrapper::new(
+            RecordingNetwork::default(),
+            None,
+            DEFAULT_SYNC_MESSAGE_SIZE,
+            Metrics::noop(),
+        );
+        let now = Instant::now();
+        wrapper.versions.received(1, Version(2), now);
+        wrapper
+            .versions
+            .received_features(2, ProtocolFeatures::SUPPORTED, now);
+        wrapper
+    }
+
+    fn pen(seed: &str) -> AuthorityPen {
+        let keystore = Arc::new(MemoryKeystore::new());
+        let key = keystore
+            .ed25519_generate_new(KEY_TYPE, Some(seed))
+            .expect("generating keys works");
+        AuthorityPen::new(AuthorityId::from(key), keystore).expect("the key was just generated")
+    }
+
+    /// The versions and the peers of everything sent so far.
+    fn sent(
+        wrapper: &mut VersionWrapper<MockBlock, MockJustification, RecordingNetwork>,
+    ) -> Vec<(Version, Vec<MockPeerId>)> {
+        wrapper
+            .inner
+            .sent
+            .drain(..)
+            .map(|(data, peer_ids)| (data.version(), peer_ids))
+            .collect()
+    }
+
+    #[test]
+    fn retires_legacy_at_cutoff() {
+        let mut legacy = LegacyCutoff::new(Some(10));
+        assert!(!legacy.update(9));
+        assert!(legacy.legacy_enabled());
+        assert!(legacy.update(12));
+        assert!(!legacy.legacy_enabled());
+        assert!(!legacy.update(13));
+        let mut forever = LegacyCutoff::new(None);
+        assert!(!forever.update(BlockNumber::MAX));
+        assert!(forever.legacy_enabled());
+    }
+
+    #[test]
+    fn negotiates_highest_demonstrated_version() {
+        let now = Instant::now();
+        let mut versions = PeerVersions::<MockPeerId>::new(now);
+        assert!(!versions.understands_current(&1, now));
+        versions.received(1, Version(1), now);
+        assert!(!versions.understands_current(&1, now));
+        versions.received(1, Version(2), now);
+        assert!(versions.understands_current(&1, now));
+        // Sent alongside the current version by nodes still supporting the legacy one.
+        versions.received(1, Version(1), now);
+        assert_eq!(versions.version(&1, now), Some(Version(2)));
+        versions.received(2, Version(1), now);
+        assert_eq!(versions.legacy_peers(now), vec![2]);
+    }
+
+    #[test]
+    fn falls_back_after_timeout() {
+        let now = Instant::now();
+        let mut versions = PeerVersions::<MockPeerId>::new(now);
+        assert!(!versions.settled(now));
+        versions.received(1, Version(2), now);
+        let later = now + VERSION_NEGOTIATION_TIMEOUT;
+        assert!(versions.settled(later));
+        assert!(!versions.understands_current(&1, later));
+        // A downgraded peer is recognized once the current version was not seen for a while.
+        versions.received(1, Version(1), later - Duration::from_secs(1));
+        assert!(versions.understands_current(&1, later - Duration::from_secs(1)));
+        versions.received(1, Version(1), later);
+        assert_eq!(versions.legacy_peers(later), vec![1]);
+    }
+
+    #[test]
+    fn times_requests_until_expiry() {
+        let mut requests = RequestTimes::<MockPeerId>::new();
+        let now = Instant::now();
+        let round_trip = Duration::from_millis(300);
+        requests.sent(1, now);
+        requests.sent(2, now);
+        assert_eq!(requests.responded(&1, now + round_trip), Some(round_trip));
+        // Only the first response counts.
+        assert_eq!(requests.responded(&1, now + round_trip), None);
+        assert_eq!(requests.responded(&3, now + round_trip), None);
+        assert_eq!(
+            requests.responded(&2, now + REQUEST_ROUND_TRIP_EXPIRY),
+            None
+        );
+    }
+
+    #[test]
+    fn matches_responses_with_requests() {
+        let mut pending = PendingRequests::<MockPeerId, u32>::new();
+        let (first, mut first_response) = pending.register(1);
+        let (second, mut second_response) = pending.register(1);
+        assert_ne!(first, second);
+        // Only the peer that got the request can answer it.
+        assert_eq!(pending.respond(2, first, 7), Correlated::Unsolicited);
+        assert_eq!(pending.respond(1, second, 8), Correlated::Delivered);
+        assert_eq!(second_response.try_recv(), Ok(Some(8)));
+        assert_eq!(first_response.try_recv(), Ok(None));
+        // The later parts of a response are just data.
+        assert_eq!(pending.respond(1, second, 9), Correlated::Late(9));
+        drop(first_response);
+        assert_eq!(pending.respond(1, first, 10), Correlated::Late(10));
+    }
+
+    #[test]
+    fn gives_up_on_the_oldest_requests() {
+        let mut pending = PendingRequests::<MockPeerId, u32>::new();
+        let (oldest, mut oldest_response) = pending.register(1);
+        for _ in 0..MAX_PENDING_REQUESTS {
+            pending.register(1);
+        }
+        assert!(oldest_response.try_recv().is_err());
+        assert_eq!(pending.respond(1, oldest, 7), Correlated::Unsolicited);
+    }
+
+    #[test]
+    fn probes_peers_for_newer_versions() {
+        let now = Instant::now();
+        let mut versions = PeerVersions::<MockPeerId>::new(now);
+        versions.received(1, Version(2), now);
+        assert!(!versions.understands_extensions(&1, now));
+        assert!(versions.probe(&1, Version(3), now));
+        assert!(!versions.probe(&1, Version(3), now + Duration::from_secs(1)));
+        assert!(versions.probe(&1, Version(3), now + PROBE_PERIOD));
+        versions.received(1, Version(3), now);
+        assert!(versions.understands_extensions(&1, now));
+        assert!(versions.understands_current(&1, now));
+        // Probing for another version does not wait for the previous probe.
+        assert!(!versions.understands_compression(&1, now));
+        assert!(versions.probe(&1, Version(4), now + PROBE_PERIOD));
+        versions.received(2, Version(2), now);
+        let mut peers = versions.recent_peers(now);
+        peers.sort_by_key(|(peer, _)| *peer);
+        assert_eq!(peers, vec![(1, Version(3)), (2, Version(2))]);
+    }
+
+    #[test]
+    fn fork_ids_tell_forks_apart() {
+        let original = ForkId::new(None);
+        let forked = ForkId::new(Some("hard-forked"));
+        assert_eq!(original, ForkId::default());
+        assert_ne!(original, forked);
+        assert_eq!(forked, ForkId::new(Some("hard-forked")));
+        assert_eq!(original.to_string(), "the original chain");
+        assert!(forked.to_string().starts_with("fork 0x"));
+    }
+
+    #[test]
+    fn negotiates_features_independently_of_versions() {
+        let now = Instant::now();
+        let mut versions = PeerVersions::<MockPeerId>::new(now);
+        versions.received(1, Version(5), now);
+        assert!(versions.understands_batches(&1, now));
+        assert!(!versions.understands_features(&1, ProtocolFeatures::ANNOUNCEMENTS, now));
+        assert!(!versions.understands_correlation(&1, now));
+        assert!(!versions.negotiates_features(&1, now));
+        versions.received_features(
+            1,
+            ProtocolFeatures::EXTENSIONS.union(ProtocolFeatures::ANNOUNCEMENTS),
+            now,
+        );
+        // Data in older versions sent alongside does not bring back the implied features.
+        versions.received(1, Version(5), now);
+        assert!(versions.negotiates_features(&1, now));
+        assert!(versions.understands_extensions(&1, now));
+        assert!(versions.understands_features(&1, ProtocolFeatures::ANNOUNCEMENTS, now));
+        assert!(!versions.understands_compression(&1, now));
+        assert!(!versions.understands_batches(&1, now));
+        // Features we do not know about are ignored.
+        versions.received_features(2, ProtocolFeatures(u32::MAX), now);
+        assert_eq!(versions.features(&2, now), ProtocolFeatures::SUPPORTED);
+        let later = now + VERSION_NEGOTIATION_TIMEOUT;
+        assert_eq!(versions.features(&1, later), ProtocolFeatures::NONE);
+    }
+
+    #[test]
+    fn sends_features_with_state_in_sixth_version() {
+        let header = MockHeader::random_parentless(0);
+        let state = State::new(MockJustification::for_header(header));
+        let features = ProtocolFeatures::EXTENSIONS.union(ProtocolFeatures::COMPRESSION);
+        let data = Data::V6(
+            features,
+            NetworkData::<MockBlock, MockJustification>::StateBroadcast(state),
+        );
+        match Data::decode(&mut &data.encode()[..]) {
+            Ok(Data::V6(decoded, NetworkData::StateBroadcast(_))) => {
+                assert_eq!(decoded, features)
+            }
+            other => panic!("unexpected decoding {other:?}"),
+        }
+    }
+
+    #[test]
+    fn sends_extended_state_in_third_version_only() {
+        let header = MockHeader::random_parentless(0);
+        let favourite_block = header.random_branch().nth(5).expect("branch is infinite");
+        let state = State::new(MockJustification::for_header(header));
+        let extended = NetworkData::<MockBlock, MockJustification>::ExtendedStateBroadcast(
+            ExtendedState::new(state, favourite_block.clone()),
+        );
+        match Data::decode(&mut &extended.clone().into_versioned().encode()[..]) {
+            Ok(Data::V3(NetworkData::ExtendedStateBroadcast(decoded))) => {
+                assert_eq!(decoded.favourite_block(), &favourite_block)
+            }
+            other => panic!("unexpected decoding {other:?}"),
+        }
+        let fallback = extended
+            .without_extensions()
+            .expect("extended state has a fallback");
+        assert!(matches!(
+            fallback.into_versioned(),
+            Data::V2(NetworkData::StateBroadcast(_))
+        ));
+    }
+
+    #[test]
+    fn sends_batched_responses_in_fifth_version_only() {
+        let justifications: Vec<_> = MockHeader::random_parentless(0)
+            .random_branch()
+            .take(4)
+            .map(MockJustification::for_header)
+            .collect();
+        let batched = NetworkData::<MockBlock, MockJustification>::BatchedStateBroadcastResponse(
+            justifications[0].clone(),
+            justifications[1..].to_vec(),
+        );
+        assert_eq!(
+            batched.justification_ids(),
+            justifications
+                .iter()
+                .map(|justification| justification.id())
+                .collect::<Vec<_>>()
+        );
+        match Data::decode(&mut &batched.clone().into_versioned().encode()[..]) {
+            Ok(Data::V5(NetworkData::BatchedStateBroadcastResponse(first, rest))) => {
+                assert_eq!(first, justifications[0]);
+                assert_eq!(rest, justifications[1..]);
+            }
+            other => panic!("unexpected decoding {other:?}"),
+        }
+        match batched
+            .without_extensions()
+            .expect("batched response has a fallback")
+            .into_versioned()
+        {
+            Data::V2(NetworkData::StateBroadcastResponse(first, second)) => {
+                assert_eq!(first, justifications[0]);
+                assert_eq!(second, Some(justifications[1].clone()));
+            }
+            other => panic!("unexpected fallback {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decompresses_compressed_data_of_third_version() {
+        let response_items: Vec<_> = MockIdentifier::new_random(0)
+            .random_branch()
+            .take(100)
+            .map(ResponseItem::<MockBlock, MockJustification>::Header)
+            .collect();
+        let data = NetworkData::RequestResponse(response_items);
+        let compression = Compression::default();
+        let compressed = compression
+            .compress(&encode_network_data(&data))
+            .expect("compression works");
+        let encoded = Data::V4(compressed).encode();
+        assert_eq!(Version::decode(&mut &encoded[..]).ok(), Some(Version(4)));
+        let compressed = match Data::decode(&mut &encoded[..]) {
+            Ok(Data::V4(compressed)) => compressed,
+            other => panic!("unexpected decoding {other:?}"),
+        };
+        let decompressed = compression
+            .decompress(&compressed, MAX_SYNC_MESSAGE_SIZE as usize)
+            .expect("decompression works");
+        assert_eq!(decompressed, data.encode());
+    }
+
+    #[test]
+    fn encodes_responses_in_parallel_same_as_sequentially() {
+        let headers: Vec<_> = MockIdentifier::new_random(0)
+            .random_branch()
+            .take(37)
+            .collect();
+        let mut response_items: Vec<ResponseItem<MockBlock, MockJustification>> = headers
+            .iter()
+            .cloned()
+            .map(|header| ResponseItem::Block(MockBlock::new(header, true)))
+            .collect();
+        response_items.extend(headers.into_iter().map(ResponseItem::Header));
+        let expected = NetworkData::RequestResponse(response_items.clone()).encode();
+        for workers in [1, 2, 5, 100] {
+            assert_eq!(
+                encode_response_in_parallel(&response_items, workers),
+                expected
+            );
+        }
+        assert_eq!(
+            encode_response_in_parallel::<MockBlock, MockJustification>(&[], 4),
+            NetworkData::<MockBlock, MockJustification>::RequestResponse(Vec::new()).encode()
+        );
+    }
+
+    #[test]
+    fn decodes_what_the_derived_encoding_produces() {
+        let header = MockHeader::random_parentless(0);
+        let justification = MockJustification::for_header(header.clone());
+        let state = State::new(justification.clone());
+        let request = Request::new(
+            header.id(),
+            BranchKnowledge::TopImported(header.id()),
+            state.clone(),
+        );
+        let all_data: Vec<NetworkData<MockBlock, MockJustification>> = vec![
+            NetworkData::StateBroadcast(state.clone()),
+            NetworkData::StateBroadcastResponse(justification.clone(), None),
+            NetworkData::Request(request.clone()),
+            NetworkData::RequestResponse(vec![
+                ResponseItem::Justification(justification.clone()),
+                ResponseItem::Header(header.clone()),
+                ResponseItem::Block(MockBlock::new(header.clone(), true)),
+            ]),
+            NetworkData::CapabilitiesAnnouncement(Capabilities::BODY_RANGES),
+            NetworkData::AvailabilityRequest,
+            NetworkData::AvailabilityResponse(Availability::default()),
+            NetworkData::AcknowledgementRequest(7),
+            NetworkData::Acknowledgement(7),
+            NetworkData::ExtendedStateBroadcast(ExtendedState::new(state, header)),
+            NetworkData::BatchedStateBroadcastResponse(
+                justification.clone(),
+                vec![justification.clone()],
+            ),
+            NetworkData::BodyRequest(BodyRequest::new(1, 5)),
+            NetworkData::HeaderRequest(request.clone()),
+            NetworkData::WarpRequest(SessionId(3)),
+            NetworkData::WarpResponse(vec![justification.clone()]),
+            NetworkData::Announcement(MockHeader::random_parentless(3)),
+            NetworkData::ForkIdAnnouncement(ForkId::new(Some("hard-forked"))),
+            NetworkData::CorrelatedRequest(7, request),
+            NetworkData::CorrelatedResponse(
+                7,
+                vec![
+                    ResponseItem::Justification(justification),
+                    ResponseItem::Header(MockHeader::random_parentless(3)),
+                ],
+            ),
+        ];
+        for data in all_data {
+            let encoded = data.encode();
+            let decoded = NetworkData::<MockBlock, MockJustification>::decode(&mut &encoded[..])
+                .expect("decodes");
+            assert_eq!(decoded.encode(), encoded);
+        }
+        assert!(NetworkData::<MockBlock, MockJustification>::decode(&mut &[20u8][..]).is_err());
+    }
+
+    #[test]
+    fn rejects_vectors_over_the_message_limits() {
+        // A response claiming ten million items, without any of them.
+        let mut encoded =
+            NetworkData::<MockBlock, MockJustification>::RequestResponse(Vec::new()).encode();
+        encoded.truncate(encoded.len() - Compact(0u32).encoded_size());
+        Compact(10_000_000u32).encode_to(&mut encoded);
+        assert!(NetworkData::<MockBlock, MockJustification>::decode(&mut &encoded[..]).is_err());
+        let mut encoded = NetworkDataV1::<MockJustification>::RequestResponse(Vec::new()).encode();
+        encoded.truncate(encoded.len() - Compact(0u32).encoded_size());
+        Compact(MAX_MESSAGE_JUSTIFICATIONS as u32 + 1).encode_to(&mut encoded);
+        assert!(NetworkDataV1::<MockJustification>::decode(&mut &encoded[..]).is_err());
+
+        let blocks: Vec<_> = MockIdentifier::new_random(0)
+            .random_branch()
+            .take(MAX_MESSAGE_BLOCKS + 1)
+            .map(|header| ResponseItem::Block(MockBlock::new(header, true)))
+            .collect();
+        let too_many =
+            NetworkData::<MockBlock, MockJustification>::RequestResponse(blocks.clone()).encode();
+        assert!(NetworkData::<MockBlock, MockJustification>::decode(&mut &too_many[..]).is_err());
+        assert_eq!(limited_response_prefix(&blocks), MAX_MESSAGE_BLOCKS);
+        let at_limit = NetworkData::<MockBlock, MockJustification>::RequestResponse(
+            blocks[..MAX_MESSAGE_BLOCKS].to_vec(),
+        )
+        .encode();
+        assert!(NetworkData::<MockBlock, MockJustification>::decode(&mut &at_limit[..]).is_ok());
+    }
+
+    #[test]
+    fn refuses_oversized_messages() {
+        let size = MAX_SYNC_MESSAGE_SIZE as usize + 1;
+        let data = Data::Other(Version(7), vec![0; size]);
+        assert_eq!(
+            data.check_size(u32::MAX),
+            Err(MessageTooBig {
+                version: Version(7),
+                size,
+                limit: u32::MAX,
+            })
+        );
+        assert!(data.encode().is_empty());
+    }
+
+    #[test]
+    fn encodes_messages_at_the_limit() {
+        let data = Data::Other(Version(7), vec![0; MAX_SYNC_MESSAGE_SIZE as usize]);
+        assert_eq!(data.check_size(MAX_SYNC_MESSAGE_SIZE), Ok(()));
+        assert_eq!(data.encode().len(), data.size_hint());
+    }
+
+    #[test]
+    fn refuses_messages_above_the_configured_limit() {
+        let data = Data::Other(Version(7), vec![0; DEFAULT_SYNC_MESSAGE_SIZE as usize]);
+        assert_eq!(data.check_size(DEFAULT_SYNC_MESSAGE_SIZE), Ok(()));
+        assert_eq!(
+            data.check_size(DEFAULT_SYNC_MESSAGE_SIZE - 1),
+            Err(MessageTooBig {
+                version: Version(7),
+                size: DEFAULT_SYNC_MESSAGE_SIZE as usize,
+                limit: DEFAULT_SYNC_MESSAGE_SIZE - 1,
+            })
+        );
+        // Only the absolute limit applies to the encoding itself.
+        assert!(!data.encode().is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_messages_of_unknown_version() {
+        let mut encoded = Data::Other(Version(7), vec![7; 1024]).encode();
+        encoded.truncate(encoded.len() - 1);
+        assert!(Data::decode(&mut &encoded[..]).is_err());
+    }
+
+    #[test]
+    fn sends_only_acknowledgements_in_third_version() {
+        let acknowledgement = NetworkData::<MockBlock, MockJustification>::Acknowledgement(7);
+        let encoded = acknowledgement.into_versioned().encode();
+        assert_eq!(Version::decode(&mut &encoded[..]).ok(), Some(Version(3)));
+        match Data::decode(&mut &encoded[..]) {
+            Ok(Data::V3(NetworkData::Acknowledgement(7))) => {}
+            other => panic!("unexpected decoding {other:?}"),
+        }
+        let request = NetworkData::<MockBlock, MockJustification>::AvailabilityRequest;
+        assert!(matches!(request.into_versioned(), Data::V3(_)));
+        let request =
+            NetworkData::<MockBlock, MockJustification>::BodyRequest(BodyRequest::new(7, 12));
+        assert!(matches!(request.into_versioned(), Data::V3(_)));
+        let request = NetworkData::<MockBlock, MockJustification>::HeaderRequest(Request::new(
+            MockHeader::random_parentless(7).id(),
+            BranchKnowledge::LowestId(MockHeader::random_parentless(5).id()),
+            State::new(MockJustification::for_header(
+                MockHeader::random_parentless(3),
+            )),
+        ));
+        assert!(request.needs_negotiation());
+        assert!(matches!(request.clone().into_versioned(), Data::V3(_)));
+        NetworkDataV1::try_from(&request)
+            .expect_err("header requests are not in the first version");
+        let request = NetworkData::<MockBlock, MockJustification>::WarpRequest(SessionId(3));
+        NetworkDataV1::try_from(&request).expect_err("warp requests are not in the first version");
+        assert!(request.needs_negotiation());
+        assert!(matches!(request.into_versioned(), Data::V3(_)));
+        let response = NetworkData::<MockBlock, MockJustification>::WarpResponse(Vec::new());
+        assert!(!response.needs_negotiation());
+        assert!(matches!(response.into_versioned(), Data::V3(_)));
+    }
+
+    #[test]
+    fn sends_capabilities_only_to_peers_understanding_them() {
+        let mut wrapper = wrapper_with_peers();
+        let announcement = MockData::CapabilitiesAnnouncement(Capabilities::HEADER_CHAINS);
+        assert!(announcement.needs_negotiation());
+        wrapper.broadcast(announcement).expect("recording works");
+        assert_eq!(sent(&mut wrapper), vec![(Version(3), vec![2])]);
+        wrapper
+            .send_to(MockData::AvailabilityRequest, 1)
+            .expect("recording works");
+        assert!(sent(&mut wrapper).is_empty());
+        wrapper
+            .send_to(MockData::AvailabilityRequest, 2)
+            .expect("recording works");
+        assert_eq!(sent(&mut wrapper), vec![(Version(3), vec![2])]);
+        wrapper
+            .send_to_random(MockData::AvailabilityRequest, HashSet::from([1]))
+            .expect("recording works");
+        assert!(sent(&mut wrapper).is_empty());
+        wrapper
+            .send_to_random(MockData::AvailabilityRequest, HashSet::from([1, 2]))
+            .expect("recording works");
+        assert_eq!(sent(&mut wrapper), vec![(Version(3), vec![2])]);
+        // The requester shows it understands the response by asking.
+        let response = MockData::AvailabilityResponse(Availability::default());
+        assert!(!response.needs_negotiation());
+        assert!(matches!(response.into_versioned(), Data::V3(_)));
+    }
+
+    #[test]
+    fn sends_priority_tickets_only_to_peers_understanding_them() {
+        let mut wrapper = wrapper_with_peers();
+        let ticket = MockData::PriorityTicket(PriorityTicket::new(&pen("//Follower"), &1u32));
+        assert!(ticket.needs_negotiation());
+        assert!(matches!(ticket.clone().into_versioned(), Data::V3(_)));
+        wrapper.send_to(ticket.clone(), 1).expect("recording works");
+        assert!(sent(&mut wrapper).is_empty());
+        wrapper.send_to(ticket, 2).expect("recording works");
+        assert_eq!(sent(&mut wrapper), vec![(Version(3), vec![2])]);
+    }
+
+    #[test]
+    fn sends_validator_tickets_only_to_peers_understanding_them() {
+        let mut wrapper = wrapper_with_peers();
+        let ticket = MockData::ValidatorTicket(ValidatorTicket::new(&pen("//Validator"), &1u32));
+        assert!(ticket.needs_negotiation());
+        assert!(matches!(ticket.clone().into_versioned(), Data::V3(_)));
+        wrapper.send_to(ticket.clone(), 1).expect("recording works");
+        assert!(sent(&mut wrapper).is_empty());
+        wrapper.send_to(ticket, 2).expect("recording works");
+        assert_eq!(sent(&mut wrapper), vec![(Version(3), vec![2])]);
+    }
+
+    #[test]
+    fn sends_header_requests_only_to_peers_understanding_them() {
+        let mut wrapper = wrapper_with_peers();
+        let request = MockData::HeaderRequest(Request::new(
+            MockHeader::random_parentless(7).id(),
+            BranchKnowledge::LowestId(MockHeader::random_parentless(5).id()),
+            State::new(MockJustification::for_header(
+                MockHeader::random_parentless(3),
+            )),
+        ));
+        assert!(!wrapper.understands_features(&1, ProtocolFeatures::HEADER_REQUESTS));
+        assert!(wrapper.understands_features(&2, ProtocolFeatures::HEADER_REQUESTS));
+        wrapper
+            .send_request_to_random(request.clone(), HashSet::from([1]))
+            .expect("recording works");
+        assert!(sent(&mut wrapper).is_empty());
+        wrapper
+            .send_request_to_random(request, HashSet::from([1, 2]))
+            .expect("recording works");
+        assert_eq!(sent(&mut wrapper), vec![(Version(3), vec![2])]);
+    }
+
+    #[test]
+    fn sends_warp_requests_only_to_peers_understanding_them() {
+        let mut wrapper = wrapper_with_peers();
+        assert!(!wrapper.understands_features(&1, ProtocolFeatures::WARP));
+        wrapper
+            .send_to(MockData::WarpRequest(SessionId(3)), 1)
+            .expect("recording works");
+        assert!(sent(&mut wrapper).is_empty());
+        wrapper
+            .send_to(MockData::WarpRequest(SessionId(3)), 2)
+            .expect("recording works");
+        assert_eq!(sent(&mut wrapper), vec![(Version(3), vec![2])]);
+    }
 }
@@ -0,0 +1,597 @@
+use std::{
+    cmp::max,
+    collections::BTreeMap,
+    fmt::{Debug, Display, Error as FmtError, Formatter},
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    StreamExt,
+};
+use log::debug;
+use parking_lot::Mutex;
+use tokio::select;
+
+use crate::{
+    session::SessionBoundaryInfo,
+    sync::{
+        substrate::FinalizationInfo, Block, BlockIdFor, BlockStatus, ChainStatus,
+        ChainStatusNotification, ChainStatusNotifier, FinalizationStatus, FinalizedId, Finalizer,
+        Header, Justification, LOG_TARGET,
+    },
+    BlockIdentifier, BlockNumber,
+};
+
+/// The blocks finalized only virtually, above the top finalized one in the database.
+struct VirtualFinalized<J: Justification> {
+    session_info: SessionBoundaryInfo,
+    /// Whether only the headers are followed, so the justified blocks are not in the database.
+    headers_only: bool,
+    /// Every virtually finalized block, one per number.
+    finalized: BTreeMap<BlockNumber, BlockIdFor<J>>,
+    /// The justifications of the last blocks of sessions and of the top virtually finalized
+    /// block, like the ones a real finalization would keep.
+    justifications: BTreeMap<BlockNumber, J>,
+    notifications: UnboundedSender<J::Header>,
+    receiver: Option<UnboundedReceiver<J::Header>>,
+}
+
+impl<J: Justification> VirtualFinalized<J> {
+    fn top(&self) -> Option<&J> {
+        self.justifications.values().next_back()
+    }
+
+    fn justification(&self, id: &BlockIdFor<J>) -> Option<J> {
+        self.justifications
+            .get(&id.number())
+            .filter(|justification| justification.header().id() == *id)
+            .cloned()
+    }
+
+    fn finalize(&mut self, justification: J, path: Vec<BlockIdFor<J>>) {
+        if let Some(number) = self.top().map(|top| top.header().id().number()) {
+            let session = self.session_info.session_id_from_block_num(number);
+            if self.session_info.last_block_of_session(session) != number {
+                self.justifications.remove(&number);
+            }
+        }
+        for id in path {
+            self.finalized.insert(id.number(), id);
+        }
+        let header = justification.header().clone();
+        self.justifications
+            .insert(header.id().number(), justification);
+        // Nobody listening just means the notifications are not needed.
+        let _ = self.notifications.unbounded_send(header);
+    }
+}
+
+/// The finality of a dry run, in which sync works as usual and serves data to peers, but the
+/// justified blocks are only finalized virtually, leaving the database as it was. When disabled
+/// everything is passed through to the database.
+///
+/// The virtually finalized blocks are only kept in memory, so they are forgotten on restart.
+/// Every one of them takes a few dozen bytes, which bounds how long a dry run can sensibly last.
+///
+/// Light sync finalizes virtually as well, but following only the headers it has neither the
+/// justified blocks nor their ancestors in the database. Only the justifications are kept then,
+/// of the last blocks of sessions and of the top finalized block.
+#[derive(Clone)]
+pub struct VirtualFinality<J: Justification> {
+    state: Option<Arc<Mutex<VirtualFinalized<J>>>>,
+}
+
+impl<J: Justification> VirtualFinality<J> {
+    pub fn new(enabled: bool, session_info: SessionBoundaryInfo) -> Self {
+        Self::with_state(enabled, false, session_info)
+    }
+
+    /// The finality of light sync, which finalizes the justified blocks without them being in the
+    /// database, relying on sync to only finalize the ones descending from the top finalized
+    /// block.
+    pub fn headers_only(session_info: SessionBoundaryInfo) -> Self {
+        Self::with_state(true, true, session_info)
+    }
+
+    fn with_state(enabled: bool, headers_only: bool, session_info: SessionBoundaryInfo) -> Self {
+        let state = enabled.then(|| {
+            let (notifications, receiver) = unbounded();
+            Arc::new(Mutex::new(VirtualFinalized {
+                session_info,
+                headers_only,
+                finalized: BTreeMap::new(),
+                justifications: BTreeMap::new(),
+                notifications,
+                receiver: Some(receiver),
+            }))
+        });
+        VirtualFinality { state }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.is_some()
+    }
+
+    pub fn is_headers_only(&self) -> bool {
+        self.state
+            .as_ref()
+            .map_or(false, |state| state.lock().headers_only)
+    }
+
+    fn top(&self) -> Option<J> {
+        self.state
+            .as_ref()
+            .and_then(|state| state.lock().top().cloned())
+    }
+
+    /// The chain status with the virtually finalized blocks on top of the database.
+    pub fn chain_status<CS>(&self, chain_status: CS) -> DryRunChainStatus<J, CS> {
+        DryRunChainStatus {
+            inner: chain_status,
+            finality: self.clone(),
+        }
+    }
+
+    /// The finalizer only finalizing virtually, if enabled. The chain status is used to find the
+    /// blocks finalized as the ancestors of the justified ones.
+    pub fn finalizer<B, F, CS>(
+        &self,
+        finalizer: F,
+        chain_status: CS,
+    ) -> DryRunFinalizer<B, J, F, CS>
+    where
+        B: Block<Header = J::Header>,
+        CS: ChainStatus<B, J>,
+    {
+        DryRunFinalizer {
+            inner: finalizer,
+            chain_status,
+            finality: self.clone(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// The notifier also announcing the virtually finalized blocks, only the first one created
+    /// gets the announcements.
+    pub fn notifier<N>(&self, notifier: N) -> DryRunNotifier<J::Header, N> {
+        DryRunNotifier {
+            inner: notifier,
+            finalized: self
+                .state
+                .as_ref()
+                .and_then(|state| state.lock().receiver.take()),
+        }
+    }
+
+    /// The finalization info counting the virtually finalized blocks too, unless following only
+    /// the headers. The authorities of later sessions cannot be read from the database then, so
+    /// they have to be learned from the headers ending the sessions, through warp sync.
+    pub fn finalization_info<FI>(&self, finalization_info: FI) -> DryRunFinalizationInfo<J, FI> {
+        DryRunFinalizationInfo {
+            inner: finalization_info,
+            finality: self.clone(),
+        }
+    }
+}
+
+/// `ChainStatus` seeing the virtually finalized blocks as finalized.
+#[derive(Clone)]
+pub struct DryRunChainStatus<J: Justification, CS> {
+    inner: CS,
+    finality: VirtualFinality<J>,
+}
+
+impl<B, J, CS> ChainStatus<B, J> for DryRunChainStatus<J, CS>
+where
+    J: Justification,
+    B: Block<Header = J::Header>,
+    CS: ChainStatus<B, J>,
+{
+    type Error = CS::Error;
+
+    fn status_of(&self, id: BlockIdFor<J>) -> Result<BlockStatus<J>, Self::Error> {
+        if let Some(state) = &self.finality.state {
+            if let Some(justification) = state.lock().justification(&id) {
+                return Ok(BlockStatus::Justified(justification));
+            }
+        }
+        self.inner.status_of(id)
+    }
+
+    fn block(&self, id: BlockIdFor<J>) -> Result<Option<B>, Self::Error> {
+        self.inner.block(id)
+    }
+
+    fn finalized_at(&self, number: BlockNumber) -> Result<FinalizationStatus<J>, Self::Error> {
+        use FinalizationStatus::*;
+        let id = match &self.finality.state {
+            Some(state) => {
+                let state = state.lock();
+                if let Some(justification) = state.justifications.get(&number) {
+                    return Ok(FinalizedWithJustification(justification.clone()));
+                }
+                state.finalized.get(&number).cloned()
+            }
+            None => None,
+        };
+        match id {
+            Some(id) => Ok(match self.inner.status_of(id)? {
+                BlockStatus::Justified(justification) => {
+                    FinalizedByDescendant(justification.header().clone())
+                }
+                BlockStatus::Present(header) => FinalizedByDescendant(header),
+                BlockStatus::Unknown => NotFinalized,
+            }),
+            None => self.inner.finalized_at(number),
+        }
+    }
+
+    fn best_block(&self) -> Result<J::Header, Self::Error> {
+        if let Some(state) = &self.finality.state {
+            let state = state.lock();
+            if state.headers_only {
+                // Nothing gets imported, the top finalized header is the best one we have.
+                if let Some(top) = state.top() {
+                    return Ok(top.header().clone());
+                }
+            }
+        }
+        self.inner.best_block()
+    }
+
+    fn top_finalized(&self) -> Result<J, Self::Error> {
+        match self.finality.top() {
+            Some(top) => Ok(top),
+            None => self.inner.top_finalized(),
+        }
+    }
+
+    fn children(&self, id: BlockIdFor<J>) -> Result<Vec<J::Header>, Self::Error> {
+        self.inner.children(id)
+    }
+}
+
+/// What can go wrong when finalizing, virtually or not.
+#[derive(Debug)]
+pub enum DryRunError<FE, CSE, BI> {
+    Finalizer(FE),
+    ChainStatus(CSE),
+    /// An ancestor of the justified block is missing from the database.
+    MissingAncestor(BI),
+    /// The justified block does not descend from the top finalized one.
+    NotDescendant(BI),
+}
+
+impl<FE: Display, CSE: Display, BI: Debug> Display for DryRunError<FE, CSE, BI> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use DryRunError::*;
+        match self {
+            Finalizer(e) => write!(f, "{e}"),
+            ChainStatus(e) => write!(f, "chain status error: {e}"),
+            MissingAncestor(id) => write!(f, "ancestor {id:?} of the justified block is missing"),
+            NotDescendant(id) => write!(
+                f,
+                "justified block {id:?} does not descend from the top finalized one"
+            ),
+        }
+    }
+}
+
+/// `Finalizer` finalizing only virtually, if the dry run is enabled.
+pub struct DryRunFinalizer<B, J: Justification, F, CS> {
+    inner: F,
+    chain_status: CS,
+    finality: VirtualFinality<J>,
+    phantom: PhantomData<B>,
+}
+
+impl<B, J, F, CS> DryRunFinalizer<B, J, F, CS>
+where
+    J: Justification,
+    B: Block<Header = J::Header>,
+    F: Finalizer<J>,
+    CS: ChainStatus<B, J>,
+{
+    fn top_finalized_id(&self) -> Result<BlockIdFor<J>, CS::Error> {
+        Ok(match self.finality.top() {
+            Some(top) => top,
+            None => self.chain_status.top_finalized()?,
+        }
+        .header()
+        .id())
+    }
+
+    /// The descending chain of blocks from the justified one down to right above the top
+    /// finalized one.
+    fn path(
+        &self,
+        justification: &J,
+    ) -> Result<Vec<BlockIdFor<J>>, DryRunError<F::Error, CS::Error, BlockIdFor<J>>> {
+        let top = self.top_finalized_id().map_err(DryRunError::ChainStatus)?;
+        let mut path = Vec::new();
+        let mut id = justification.header().id();
+        while id.number() > top.number() {
+            let header = match self
+                .chain_status
+                .status_of(id.clone())
+                .map_err(DryRunError::ChainStatus)?
+            {
+                BlockStatus::Justified(justification) => justification.header().clone(),
+                BlockStatus::Present(header) => header,
+                BlockStatus::Unknown => return Err(DryRunError::MissingAncestor(id)),
+            };
+            path.push(id);
+            id = match header.parent_id() {
+                Some(parent_id) => parent_id,
+                None => return Err(DryRunError::NotDescendant(justification.header().id())),
+            };
+        }
+        match id == top {
+            true => Ok(path),
+            false => Err(DryRunError::NotDescendant(justification.header().id())),
+        }
+    }
+}
+
+impl<B, J, F, CS> Finalizer<J> for DryRunFinalizer<B, J, F, CS>
+where
+    J: Justification,
+    B: Block<Header = J::Header>,
+    F: Finalizer<J>,
+    CS: ChainStatus<B, J>,
+{
+    type Error = DryRunError<F::Error, CS::Error, BlockIdFor<J>>;
+
+    fn finalize(&self, justification: J) -> Result<FinalizedId<BlockIdFor<J>>, Self::Error> {
+        let state = match &self.finality.state {
+            Some(state) => state,
+            None => {
+                return self
+                    .inner
+                    .finalize(justification)
+                    .map_err(DryRunError::Finalizer)
+            }
+        };
+        let path = match self.finality.is_headers_only() {
+            true => {
+                let top = self.top_finalized_id().map_err(DryRunError::ChainStatus)?;
+                if justification.header().id().number() <= top.number() {
+                    return Err(DryRunError::NotDescendant(justification.header().id()));
+                }
+                Vec::new()
+            }
+            false => self.path(&justification)?,
+        };
+        debug!(
+            target: LOG_TARGET,
+            "Virtually finalized {} blocks up to {:?}.",
+            path.len(),
+            justification.header().id()
+        );
+        let id = justification.header().id();
+        state.lock().finalize(justification, path);
+        Ok(FinalizedId::new(id))
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        match error {
+            DryRunError::Finalizer(e) => F::is_transient(e),
+            _ => false,
+        }
+    }
+}
+
+/// What can go wrong when waiting for the next notification of a dry run.
+#[derive(Debug)]
+pub enum DryRunNotifierError<E> {
+    Notifier(E),
+    FinalizerGone,
+}
+
+impl<E: Display> Display for DryRunNotifierError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use DryRunNotifierError::*;
+        match self {
+            Notifier(e) => write!(f, "{e}"),
+            FinalizerGone => write!(f, "virtual finalization notifications have ended"),
+        }
+    }
+}
+
+/// `ChainStatusNotifier` also announcing the virtually finalized blocks.
+pub struct DryRunNotifier<H: Header, N> {
+    inner: N,
+    finalized: Option<UnboundedReceiver<H>>,
+}
+
+#[async_trait::async_trait]
+impl<H: Header, N: ChainStatusNotifier<H> + Send> ChainStatusNotifier<H> for DryRunNotifier<H, N> {
+    type Error = DryRunNotifierError<N::Error>;
+
+    async fn next(&mut self) -> Result<ChainStatusNotification<H>, Self::Error> {
+        let DryRunNotifier { inner, finalized } = self;
+        match finalized {
+            Some(finalized) => select! {
+                notification = inner.next() => notification.map_err(DryRunNotifierError::Notifier),
+                maybe_header = finalized.next() => maybe_header
+                    .map(ChainStatusNotification::BlockFinalized)
+                    .ok_or(DryRunNotifierError::FinalizerGone),
+            },
+            None => inner.next().await.map_err(DryRunNotifierError::Notifier),
+        }
+    }
+}
+
+/// `FinalizationInfo` counting the virtually finalized blocks.
+pub struct DryRunFinalizationInfo<J: Justification, FI> {
+    inner: FI,
+    finality: VirtualFinality<J>,
+}
+
+impl<J: Justification, FI: FinalizationInfo> FinalizationInfo for DryRunFinalizationInfo<J, FI> {
+    fn finalized_number(&self) -> BlockNumber {
+        let finalized = self.inner.finalized_number();
+        match self.finality.top() {
+            Some(top) if !self.finality.is_headers_only() => {
+                max(finalized, top.header().id().number())
+            }
+            _ => finalized,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualFinality;
+    use crate::{
+        session::testing::session_boundary_info,
+        sync::{
+            mock::{Backend, MockBlock, MockHeader, MockJustification},
+            BlockImport, BlockStatus, ChainStatus, ChainStatusNotifier, FinalizationStatus,
+            Finalizer, Header, Justification,
+        },
+    };
+
+    fn setup(
+        enabled: bool,
+        length: usize,
+    ) -> (
+        Backend,
+        impl ChainStatusNotifier<MockHeader>,
+        VirtualFinality<MockJustification>,
+        Vec<MockHeader>,
+    ) {
+        let (mut backend, notifier) = Backend::setup(session_boundary_info());
+        let genesis = backend.top_finalized().expect("genesis").header().clone();
+        let branch: Vec<_> = genesis.random_branch().take(length).collect();
+        for header in &branch {
+            backend.import_block(MockBlock::new(header.clone(), true));
+        }
+        (
+            backend,
+            notifier,
+            VirtualFinality::new(enabled, session_boundary_info()),
+            branch,
+        )
+    }
+
+    #[test]
+    fn finalizes_only_virtually() {
+        let (mut backend, _keep, finality, branch) = setup(true, 30);
+        let fork: Vec<_> = branch[2].random_branch().take(3).collect();
+        for header in &fork {
+            backend.import_block(MockBlock::new(header.clone(), true));
+        }
+        let chain_status = finality.chain_status(backend.clone());
+        let finalizer = finality.finalizer(backend.clone(), backend.clone());
+        finalizer
+            .finalize(MockJustification::for_header(branch[4].clone()))
+            .expect("the block is justified");
+        assert_eq!(
+            backend
+                .top_finalized()
+                .expect("genesis")
+                .header()
+                .id()
+                .number(),
+            0
+        );
+        assert_eq!(
+            chain_status
+                .top_finalized()
+                .expect("virtually finalized")
+                .header(),
+            &branch[4]
+        );
+        match chain_status.finalized_at(3).expect("status") {
+            FinalizationStatus::FinalizedByDescendant(header) => assert_eq!(header, branch[2]),
+            _ => panic!("the ancestor should be finalized"),
+        }
+        assert!(matches!(
+            chain_status.finalized_at(6).expect("status"),
+            FinalizationStatus::NotFinalized
+        ));
+        // Justifications not descending from the top finalized block are rejected.
+        assert!(finalizer
+            .finalize(MockJustification::for_header(fork[2].clone()))
+            .is_err());
+    }
+
+    #[test]
+    fn keeps_justifications_of_session_ends() {
+        let (backend, _keep, finality, branch) = setup(true, 30);
+        let chain_status = finality.chain_status(backend.clone());
+        let finalizer = finality.finalizer(backend.clone(), backend.clone());
+        for number in [19, 22, 25] {
+            finalizer
+                .finalize(MockJustification::for_header(branch[number - 1].clone()))
+                .expect("the block is justified");
+        }
+        assert!(matches!(
+            chain_status.status_of(branch[18].id()).expect("status"),
+            BlockStatus::Justified(_)
+        ));
+        assert!(matches!(
+            chain_status.finalized_at(19).expect("status"),
+            FinalizationStatus::FinalizedWithJustification(_)
+        ));
+        assert!(matches!(
+            chain_status.finalized_at(22).expect("status"),
+            FinalizationStatus::FinalizedByDescendant(_)
+        ));
+        assert!(matches!(
+            chain_status.finalized_at(25).expect("status"),
+            FinalizationStatus::FinalizedWithJustification(_)
+        ));
+    }
+
+    #[test]
+    fn finalizes_headers_missing_from_database() {
+        let (backend, _keep, _, _) = setup(false, 0);
+        let finality = VirtualFinality::headers_only(session_boundary_info());
+        let genesis = backend.top_finalized().expect("genesis").header().clone();
+        let branch: Vec<_> = genesis.random_branch().take(25).collect();
+        let chain_status = finality.chain_status(backend.clone());
+        let finalizer = finality.finalizer(backend.clone(), backend.clone());
+        for number in [19, 22] {
+            finalizer
+                .finalize(MockJustification::for_header(branch[number - 1].clone()))
+                .expect("the block is justified");
+        }
+        assert_eq!(
+            chain_status
+                .top_finalized()
+                .expect("virtually finalized")
+                .header(),
+            &branch[21]
+        );
+        assert_eq!(chain_status.best_block().expect("best block"), branch[21]);
+        assert!(matches!(
+            chain_status.status_of(branch[18].id()).expect("status"),
+            BlockStatus::Justified(_)
+        ));
+        // Without the database only the justifications of session ends and the top are known.
+        assert!(matches!(
+            chain_status.finalized_at(20).expect("status"),
+            FinalizationStatus::NotFinalized
+        ));
+        assert!(finalizer
+            .finalize(MockJustification::for_header(branch[20].clone()))
+            .is_err());
+    }
+
+    #[test]
+    fn passes_through_when_disabled() {
+        let (backend, _keep, finality, branch) = setup(false, 5);
+        finality
+            .finalizer(backend.clone(), backend.clone())
+            .finalize(MockJustification::for_header(branch[4].clone()))
+            .expect("the block is justified");
+        assert_eq!(
+            backend.top_finalized().expect("finalized").header(),
+            &branch[4]
+        );
+    }
+}
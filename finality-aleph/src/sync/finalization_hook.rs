@@ -0,0 +1,164 @@
+use std::{future::Future, sync::Arc};
+
+use log::warn;
+use parking_lot::Mutex;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::{
+    session::SessionId,
+    sync::{BlockIdFor, FinalizedId, Justification, LOG_TARGET},
+};
+
+/// How many finalized blocks can wait for a hook before it starts missing them.
+pub const DEFAULT_HOOK_QUEUE: usize = 1024;
+
+/// A block that has just been finalized through sync.
+#[derive(Clone, Debug)]
+pub struct FinalizedBlock<J: Justification> {
+    pub id: FinalizedId<BlockIdFor<J>>,
+    pub session: SessionId,
+    /// The header of the block, which nodes following only the headers cannot get from their
+    /// database.
+    pub header: J::Header,
+    /// The justification the block got finalized with, for blocks finalized in a batch that is
+    /// the one of the highest block in it.
+    pub justification: J,
+}
+
+/// Side effects of finalizing blocks through sync, e.g. invalidating caches, notifying or
+/// indexing. Every hook gets the finalized blocks in order, away from sync, so a slow hook only
+/// delays itself.
+#[async_trait::async_trait]
+pub trait FinalizationHook<J: Justification>: Send + 'static {
+    async fn finalized(&mut self, block: FinalizedBlock<J>);
+}
+
+/// The hooks called after blocks get finalized through sync. Every hook has its own bounded
+/// queue, and misses the blocks finalized while it is full instead of holding sync back. Can be
+/// cloned and registered with while sync is running.
+#[derive(Clone)]
+pub struct FinalizationHooks<J: Justification> {
+    queues: Arc<Mutex<Vec<mpsc::Sender<FinalizedBlock<J>>>>>,
+}
+
+impl<J: Justification> FinalizationHooks<J> {
+    pub fn new() -> Self {
+        FinalizationHooks {
+            queues: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Registers the hook with a queue of at most `queue` blocks, returning the task calling it,
+    /// which has to be spawned. The hook is forgotten once the task is dropped.
+    pub fn register<H: FinalizationHook<J>>(
+        &self,
+        mut hook: H,
+        queue: usize,
+    ) -> impl Future<Output = ()> + Send {
+        let (sender, mut receiver) = mpsc::channel(queue.max(1));
+        self.queues.lock().push(sender);
+        async move {
+            while let Some(block) = receiver.recv().await {
+                hook.finalized(block).await;
+            }
+        }
+    }
+
+    pub fn hooks(&self) -> usize {
+        self.queues.lock().len()
+    }
+
+    /// Queues the finalized block for all the hooks, returns how many of them missed it, because
+    /// their queues were full.
+    pub fn notify(&self, block: FinalizedBlock<J>) -> usize {
+        let mut missed = 0;
+        self.queues
+            .lock()
+            .retain(|queue| match queue.try_send(block.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    missed += 1;
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            });
+        if missed > 0 {
+            warn!(
+                target: LOG_TARGET,
+                "{} finalization hooks missed block {:?}, they cannot keep up.", missed, block.id
+            );
+        }
+        missed
+    }
+}
+
+impl<J: Justification> Default for FinalizationHooks<J> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{channel::mpsc, StreamExt};
+
+    use super::{FinalizationHook, FinalizationHooks, FinalizedBlock};
+    use crate::{
+        session::SessionId,
+        sync::{
+            mock::{MockHeader, MockJustification},
+            FinalizedId, Header,
+        },
+    };
+
+    struct Recorder {
+        finalized: mpsc::UnboundedSender<FinalizedBlock<MockJustification>>,
+    }
+
+    #[async_trait::async_trait]
+    impl FinalizationHook<MockJustification> for Recorder {
+        async fn finalized(&mut self, block: FinalizedBlock<MockJustification>) {
+            self.finalized
+                .unbounded_send(block)
+                .expect("the test listens");
+        }
+    }
+
+    fn finalized_block(number: u32) -> FinalizedBlock<MockJustification> {
+        let header = MockHeader::random_parentless(number);
+        FinalizedBlock {
+            id: FinalizedId::new(header.id()),
+            session: SessionId(0),
+            header: header.clone(),
+            justification: MockJustification::for_header(header),
+        }
+    }
+
+    #[tokio::test]
+    async fn calls_hooks_in_order_of_finalization() {
+        let hooks = FinalizationHooks::new();
+        let (finalized, mut received) = mpsc::unbounded();
+        tokio::spawn(hooks.register(Recorder { finalized }, 8));
+        for number in 1..=3 {
+            assert_eq!(hooks.notify(finalized_block(number)), 0);
+        }
+        for number in 1..=3 {
+            let block = received.next().await.expect("the hook runs");
+            assert_eq!(block.id.number(), number);
+        }
+    }
+
+    #[test]
+    fn full_queues_miss_blocks() {
+        let hooks = FinalizationHooks::new();
+        let (finalized, _received) = mpsc::unbounded();
+        // Never spawned, so nothing leaves the queue.
+        let task = hooks.register(Recorder { finalized }, 2);
+        assert_eq!(hooks.notify(finalized_block(1)), 0);
+        assert_eq!(hooks.notify(finalized_block(2)), 0);
+        assert_eq!(hooks.notify(finalized_block(3)), 1);
+        drop(task);
+        assert_eq!(hooks.notify(finalized_block(4)), 0);
+        assert_eq!(hooks.hooks(), 0);
+    }
+}
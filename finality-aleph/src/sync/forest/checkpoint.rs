@@ -0,0 +1,61 @@
+use std::fmt::Display;
+
+use parity_scale_codec::{Decode, Encode};
+
+use crate::{
+    sync::{BlockIdFor, Header, Justification},
+    BlockIdentifier, BlockNumber,
+};
+
+/// A vertex of the forest as kept in a checkpoint, with just enough information to recreate it.
+#[derive(Clone, Debug, Encode, Decode)]
+pub enum VertexCheckpoint<J: Justification> {
+    /// Only the identifier of a block we want by itself.
+    Wanted(BlockIdFor<J>),
+    /// A block with a known parent, that was not imported yet.
+    Header {
+        id: BlockIdFor<J>,
+        parent: BlockIdFor<J>,
+        explicitly_required: bool,
+    },
+    /// A justified block, the justification has to be verified again when restoring.
+    Justification(J::Unverified),
+}
+
+impl<J: Justification> VertexCheckpoint<J> {
+    fn number(&self) -> BlockNumber {
+        match self {
+            VertexCheckpoint::Wanted(id) | VertexCheckpoint::Header { id, .. } => id.number(),
+            VertexCheckpoint::Justification(justification) => justification.id().number(),
+        }
+    }
+}
+
+/// The part of the forest that is not in the database, so that the branches we were downloading
+/// do not have to be discovered again after a restart. The peers that know about the blocks are
+/// not kept, they announce themselves again anyway.
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct ForestCheckpoint<J: Justification> {
+    /// The highest finalized block when the checkpoint was made.
+    pub root: BlockIdFor<J>,
+    /// Sorted by block number, so that parents are restored before their children.
+    pub vertices: Vec<VertexCheckpoint<J>>,
+}
+
+impl<J: Justification> ForestCheckpoint<J> {
+    pub fn new(root: BlockIdFor<J>, mut vertices: Vec<VertexCheckpoint<J>>) -> Self {
+        vertices.sort_by_key(|vertex| vertex.number());
+        ForestCheckpoint { root, vertices }
+    }
+}
+
+/// Where the forest checkpoints are persisted between the runs of the node.
+pub trait ForestCheckpointStorage<J: Justification> {
+    type Error: Display;
+
+    /// The checkpoint stored last time, `None` if there is none.
+    fn load(&self) -> Result<Option<ForestCheckpoint<J>>, Self::Error>;
+
+    /// Replaces the stored checkpoint.
+    fn store(&self, checkpoint: &ForestCheckpoint<J>) -> Result<(), Self::Error>;
+}
@@ -0,0 +1,217 @@
+use std::{
+    fmt::{Display, Write},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use tokio::{sync::Notify, time::timeout};
+
+use crate::{sync::PeerId, BlockIdentifier};
+
+/// How long to wait for the sync service to answer a dump request.
+const DUMP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How much we know about the block a vertex refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VertexContents {
+    /// Only the identifier.
+    Empty,
+    /// The header.
+    Header,
+    /// The header and a justification.
+    Justification,
+}
+
+/// Whether we want the block a vertex refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VertexInterest {
+    /// Only kept because of its descendants, not needed by itself.
+    Auxiliary,
+    /// An ancestor of something we want to import.
+    Required,
+    /// Wanted by itself, so requested from peers.
+    ExplicitlyRequired,
+    /// Already in the database.
+    Imported,
+}
+
+impl VertexInterest {
+    fn color(&self) -> &'static str {
+        use VertexInterest::*;
+        match self {
+            Auxiliary => "gray",
+            Required => "orange",
+            ExplicitlyRequired => "red",
+            Imported => "darkgreen",
+        }
+    }
+}
+
+/// A snapshot of a single vertex of the forest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexDump<I: PeerId, BI: BlockIdentifier> {
+    pub id: BI,
+    /// Missing if we do not know the header yet.
+    pub parent: Option<BI>,
+    pub contents: VertexContents,
+    pub interest: VertexInterest,
+    /// The peers we think know most about the block.
+    pub know_most: Vec<I>,
+}
+
+/// A snapshot of the whole forest, the vertices are sorted by block number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForestDump<I: PeerId, BI: BlockIdentifier> {
+    /// The highest finalized block, all the trees grow from it.
+    pub root: BI,
+    pub highest_justified: BI,
+    pub vertices: Vec<VertexDump<I, BI>>,
+}
+
+fn quoted<BI: Display>(id: &BI) -> String {
+    format!("\"{}\"", id.to_string().replace('"', "\\\""))
+}
+
+impl<I: PeerId, BI: BlockIdentifier + Display> ForestDump<I, BI> {
+    /// The forest in the graphviz DOT format, with the edges pointing from children to parents.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph forest {\n    rankdir=BT;\n");
+        // Writing to a string cannot fail.
+        let _ = writeln!(
+            dot,
+            "    {} [label={}, shape=box, style=bold];",
+            quoted(&self.root),
+            quoted(&format!("{}\\nfinalized", self.root))
+        );
+        for vertex in &self.vertices {
+            let shape = match vertex.contents {
+                VertexContents::Empty => "ellipse",
+                VertexContents::Header => "box",
+                VertexContents::Justification => "doubleoctagon",
+            };
+            let mut label = format!(
+                "{}\\n{:?}, {:?}, {} holder(s)",
+                vertex.id,
+                vertex.contents,
+                vertex.interest,
+                vertex.know_most.len()
+            );
+            if vertex.id == self.highest_justified {
+                label.push_str("\\nhighest justified");
+            }
+            let _ = writeln!(
+                dot,
+                "    {} [label={}, shape={}, color={}];",
+                quoted(&vertex.id),
+                quoted(&label),
+                shape,
+                vertex.interest.color()
+            );
+            if let Some(parent) = &vertex.parent {
+                let _ = writeln!(dot, "    {} -> {};", quoted(&vertex.id), quoted(parent));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Lets anyone holding it ask the running sync service for a dump of its forest.
+#[derive(Clone)]
+pub struct ForestDumps<I: PeerId, BI: BlockIdentifier> {
+    pending: Arc<Mutex<Vec<oneshot::Sender<ForestDump<I, BI>>>>>,
+    requested: Arc<Notify>,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> ForestDumps<I, BI> {
+    pub fn new() -> Self {
+        ForestDumps {
+            pending: Arc::new(Mutex::new(Vec::new())),
+            requested: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Asks for a dump of the forest, returns `None` if sync did not respond in time.
+    pub async fn dump(&self) -> Option<ForestDump<I, BI>> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().push(sender);
+        self.requested.notify_one();
+        timeout(DUMP_TIMEOUT, receiver).await.ok()?.ok()
+    }
+
+    /// Waits for dump requests, returns where to send the dumps.
+    pub async fn requests(&self) -> Vec<oneshot::Sender<ForestDump<I, BI>>> {
+        self.requested.notified().await;
+        std::mem::take(&mut *self.pending.lock())
+    }
+}
+
+impl<I: PeerId, BI: BlockIdentifier> Default for ForestDumps<I, BI> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ForestDump, ForestDumps, VertexContents, VertexDump, VertexInterest};
+    use crate::sync::{
+        mock::{MockIdentifier, MockPeerId},
+        Header,
+    };
+
+    fn dump() -> ForestDump<MockPeerId, MockIdentifier> {
+        let root = MockIdentifier::new_random(0);
+        let child = root.random_child();
+        let grandchild = child.random_child();
+        ForestDump {
+            root: root.clone(),
+            highest_justified: child.id(),
+            vertices: vec![
+                VertexDump {
+                    id: child.id(),
+                    parent: Some(root),
+                    contents: VertexContents::Justification,
+                    interest: VertexInterest::Imported,
+                    know_most: Vec::new(),
+                },
+                VertexDump {
+                    id: grandchild.id(),
+                    parent: None,
+                    contents: VertexContents::Empty,
+                    interest: VertexInterest::ExplicitlyRequired,
+                    know_most: vec![7],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn dot_contains_all_vertices_and_edges() {
+        let dump = dump();
+        let dot = dump.to_dot();
+        assert!(dot.starts_with("digraph forest {"));
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\";",
+            dump.vertices[0].id, dump.root
+        )));
+        assert!(dot.contains(&format!("\"{}\" [label=", dump.vertices[1].id)));
+        assert!(dot.contains("highest justified"));
+        assert_eq!(dot.matches("->").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn answers_dump_requests() {
+        let dumps = ForestDumps::new();
+        let answering = dumps.clone();
+        let answer = tokio::spawn(async move {
+            for sender in answering.requests().await {
+                let _ = sender.send(dump());
+            }
+        });
+        assert!(dumps.dump().await.is_some());
+        answer.await.expect("should not panic");
+    }
+}
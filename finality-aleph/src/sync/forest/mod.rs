@@ -14,8 +14,14 @@ use crate::{
     BlockIdentifier,
 };
 
+mod checkpoint;
+mod dump;
+mod overlay;
 mod vertex;
 
+pub use checkpoint::{ForestCheckpoint, ForestCheckpointStorage, VertexCheckpoint};
+pub use dump::{ForestDump, ForestDumps, VertexContents, VertexDump, VertexInterest};
+pub use overlay::JustificationOverlay;
 use vertex::Vertex;
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -135,7 +141,7 @@ impl<I: PeerId, J: Justification> VertexWithChildren<I, J> {
 // ever need worst case scenario.
 //
 // At least one session must fit into the Forest.
-const MAX_DEPTH: u32 = 1800;
+pub const MAX_DEPTH: u32 = 1800;
 const_assert!(DEFAULT_SESSION_PERIOD <= MAX_DEPTH);
 
 pub struct Forest<I, J>
@@ -145,10 +151,12 @@ where
 {
     vertices: HashMap<BlockIdFor<J>, VertexWithChildren<I, J>>,
     highest_justified: BlockIdFor<J>,
-    justified_blocks: HashMap<u32, BlockIdFor<J>>,
+    justifications: JustificationOverlay<J>,
     root_id: BlockIdFor<J>,
     root_children: HashSet<BlockIdFor<J>>,
     compost_bin: HashSet<BlockIdFor<J>>,
+    pruning_queue: VecDeque<BlockIdFor<J>>,
+    headers_only: bool,
 }
 
 type Edge<J> = (BlockIdFor<J>, BlockIdFor<J>);
@@ -171,10 +179,12 @@ where
         let mut forest = Self {
             vertices: HashMap::new(),
             highest_justified: top_finalized.clone(),
-            justified_blocks: HashMap::new(),
+            justifications: JustificationOverlay::new(),
             root_id: top_finalized.clone(),
             root_children: HashSet::new(),
             compost_bin: HashSet::new(),
+            pruning_queue: VecDeque::new(),
+            headers_only: false,
         };
 
         // Populate the forest
@@ -194,21 +204,47 @@ where
         Ok(forest)
     }
 
+    /// Treats the blocks as imported as soon as their headers connect to the top finalized
+    /// block, for following the chain without ever getting the bodies.
+    pub fn headers_only(mut self) -> Self {
+        self.headers_only = true;
+        self
+    }
+
     fn special_state(&self, id: &BlockIdFor<J>) -> Option<SpecialState> {
         use SpecialState::*;
         if id == &self.root_id {
             Some(HighestFinalized)
         } else if id.number() <= self.root_id.number() {
             Some(BelowMinimal)
-        } else if id.number() > self.root_id.number() + MAX_DEPTH {
+        } else if id.number() > self.highest_holdable() {
             Some(TooNew)
-        } else if self.compost_bin.contains(id) {
+        } else if self.compost_bin.contains(id) || self.descends_from_pruned(id) {
             Some(HopelessFork)
         } else {
             None
         }
     }
 
+    /// Whether the vertex is a descendant of one that is already pruned, but not removed yet.
+    /// Only possible while pruning is pending, so usually no ancestors have to be checked.
+    fn descends_from_pruned(&self, id: &BlockIdFor<J>) -> bool {
+        if self.pruning_queue.is_empty() {
+            return false;
+        }
+        let mut parent = self.vertices.get(id).and_then(|v| v.vertex.parent());
+        while let Some(id) = parent {
+            if id == &self.root_id {
+                return false;
+            }
+            if id.number() <= self.root_id.number() || self.compost_bin.contains(id) {
+                return true;
+            }
+            parent = self.vertices.get(id).and_then(|v| v.vertex.parent());
+        }
+        false
+    }
+
     fn get_mut(&mut self, id: &BlockIdFor<J>) -> VertexHandleMut<I, J> {
         use VertexHandleMut::*;
         if let Some(state) = self.special_state(id) {
@@ -338,10 +374,23 @@ where
         required: bool,
     ) -> Result<bool, Error> {
         let (id, parent_id) = self.process_header(header)?;
+        self.update_edge(id, parent_id, holder, required)
+    }
+
+    fn update_edge(
+        &mut self,
+        id: BlockIdFor<J>,
+        parent_id: BlockIdFor<J>,
+        holder: Option<I>,
+        required: bool,
+    ) -> Result<bool, Error> {
         self.insert_id(id.clone(), holder.clone())?;
         if let VertexHandleMut::Candidate(mut entry) = self.get_mut(&id) {
             entry.get_mut().vertex.insert_header(parent_id, holder);
             self.connect_parent(&id);
+            if self.headers_only {
+                self.import_connected(id.clone());
+            }
         }
         match required {
             true => Ok(self.set_explicitly_required(&id)),
@@ -349,6 +398,55 @@ where
         }
     }
 
+    /// Marks the block as imported if its parent is, and then the same for its descendants, in the
+    /// headers-only mode, where the connected headers are all there is to import.
+    fn import_connected(&mut self, id: BlockIdFor<J>) {
+        use SpecialState::*;
+        use VertexHandle::*;
+        let mut queue = VecDeque::from([id]);
+        while let Some(id) = queue.pop_front() {
+            let parent_id = match self.get(&id) {
+                Candidate(vertex) if !vertex.vertex.imported() => match vertex.vertex.parent() {
+                    Some(parent_id) => parent_id.clone(),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            let parent_imported = match self.get(&parent_id) {
+                Special(HighestFinalized) => true,
+                Candidate(parent) => parent.vertex.imported(),
+                _ => false,
+            };
+            if !parent_imported {
+                continue;
+            }
+            if let VertexHandleMut::Candidate(mut entry) = self.get_mut(&id) {
+                let VertexWithChildren { vertex, children } = entry.get_mut();
+                vertex.insert_body(parent_id);
+                let justified = vertex.justified_block();
+                queue.extend(children.iter().cloned());
+                if justified {
+                    self.justifications.block_imported(&id);
+                }
+            }
+        }
+    }
+
+    /// Restores a vertex from a checkpoint made before a restart, returns whether it became a new
+    /// explicitly required. Justifications are not restored here, since they have to be verified
+    /// again.
+    pub fn restore_vertex(&mut self, vertex: VertexCheckpoint<J>) -> Result<bool, Error> {
+        match vertex {
+            VertexCheckpoint::Wanted(id) => self.update_block_identifier(&id, None, true),
+            VertexCheckpoint::Header {
+                id,
+                parent,
+                explicitly_required,
+            } => self.update_edge(id, parent, None, explicitly_required),
+            VertexCheckpoint::Justification(_) => Ok(false),
+        }
+    }
+
     /// Updates the vertex related to the provided header marking it as imported.
     /// Returns errors when it's impossible to do consistently.
     pub fn update_body(&mut self, header: &J::Header) -> Result<(), Error> {
@@ -372,7 +470,7 @@ where
                 let vertex = &mut entry.get_mut().vertex;
                 vertex.insert_body(parent_id.clone());
                 if vertex.justified_block() {
-                    self.justified_blocks.insert(id.number(), id.clone());
+                    self.justifications.block_imported(&id);
                 }
                 Ok(())
             }
@@ -408,9 +506,11 @@ where
         Ok(match self.get_mut(&id) {
             VertexHandleMut::Candidate(mut entry) => {
                 let vertex = &mut entry.get_mut().vertex;
-                vertex.insert_justification(parent_id, justification, holder);
-                if vertex.justified_block() {
-                    self.justified_blocks.insert(id.number(), id.clone());
+                vertex.insert_justification(parent_id, holder);
+                let imported = vertex.justified_block();
+                self.justifications.insert(justification);
+                if imported {
+                    self.justifications.block_imported(&id);
                 }
                 self.try_update_highest_justified(id.clone())
             }
@@ -418,13 +518,38 @@ where
         })
     }
 
+    /// Marks the vertex as a hopeless fork, it and its descendants are removed later, a few at a
+    /// time, by `prune_pending`.
     fn prune(&mut self, id: &BlockIdFor<J>) {
-        if let Some(VertexWithChildren { children, .. }) = self.vertices.remove(id) {
-            self.compost_bin.insert(id.clone());
-            for child in children {
-                self.prune(&child);
+        if self.vertices.contains_key(id) && self.compost_bin.insert(id.clone()) {
+            self.pruning_queue.push_back(id.clone());
+        }
+    }
+
+    /// Removes at most `budget` of the vertices waiting to be pruned, so that pruning huge forks
+    /// does not block everything else. Returns whether any are still waiting.
+    pub fn prune_pending(&mut self, budget: usize) -> bool {
+        for _ in 0..budget {
+            let id = match self.pruning_queue.pop_front() {
+                Some(id) => id,
+                None => break,
+            };
+            self.justifications.remove(&id);
+            if let Some(VertexWithChildren { children, .. }) = self.vertices.remove(&id) {
+                for child in children {
+                    self.prune(&child);
+                }
+            }
+            if id.number() <= self.root_id.number() {
+                self.compost_bin.remove(&id);
             }
         }
+        self.pruning_pending()
+    }
+
+    /// Whether there are vertices waiting to be pruned.
+    pub fn pruning_pending(&self) -> bool {
+        !self.pruning_queue.is_empty()
     }
 
     fn prune_level(&mut self, level: u32) {
@@ -437,27 +562,54 @@ where
         for id in to_prune.into_iter() {
             self.prune(&id);
         }
-        self.compost_bin.retain(|k| k.number() > level);
-        self.justified_blocks.retain(|k, _| k > &level);
+        // The vertices still waiting to be removed have to stay in the compost bin, so that they
+        // are not queued again.
+        let vertices = &self.vertices;
+        self.compost_bin
+            .retain(|k| k.number() > level || vertices.contains_key(k));
+        self.justifications.prune(level);
     }
 
     /// Attempt to finalize one block, returns the correct justification if successful.
     pub fn try_finalize(&mut self, number: &u32) -> Option<J> {
-        if let Some(id) = self.justified_blocks.get(number) {
-            if let Some(VertexWithChildren { vertex, children }) = self.vertices.remove(id) {
-                match vertex.ready() {
-                    // should always match, as the id is taken from self.justified_blocks
-                    Ok(justification) => {
-                        self.root_id = id.clone();
-                        self.root_children = children;
-                        self.prune_level(self.root_id.number());
-                        return Some(justification);
-                    }
-                    Err(_vertex) => panic!("Block sync justified_blocks cache corrupted, please restart the Node and contact the developers"),
-                }
-            }
+        if !self.finalizable(number) {
+            return None;
         }
-        None
+        let justification = self.justifications.take(number)?;
+        let id = justification.header().id();
+        let VertexWithChildren { children, .. } = self.vertices.remove(&id)?;
+        self.root_id = id;
+        self.root_children = children;
+        self.prune_level(self.root_id.number());
+        Some(justification)
+    }
+
+    /// Whether the block at the height is justified and imported, so that it could be finalized.
+    pub fn finalizable(&self, number: &u32) -> bool {
+        self.justifications
+            .finalizable(number)
+            .map_or(false, |id| self.vertices.contains_key(id))
+    }
+
+    /// The justification of the block at the height, if it could be finalized.
+    pub fn finalizable_justification(&self, number: &u32) -> Option<&J> {
+        match self.finalizable(number) {
+            true => self
+                .justifications
+                .finalizable(number)
+                .and_then(|id| self.justifications.get(id)),
+            false => None,
+        }
+    }
+
+    /// The verified justifications waiting for their ancestors to get finalized.
+    pub fn justification_overlay(&self) -> &JustificationOverlay<J> {
+        &self.justifications
+    }
+
+    /// The height of the highest justified block we know of, imported or not.
+    pub fn highest_justified_number(&self) -> u32 {
+        self.highest_justified.number()
     }
 
     /// Returns the BranchKnowledge regarding the given block id,
@@ -533,6 +685,78 @@ where
         }
     }
 
+    /// A snapshot of all the vertices, for debugging.
+    pub fn dump(&self) -> ForestDump<I, BlockIdFor<J>> {
+        let mut vertices: Vec<_> = self
+            .vertices
+            .iter()
+            .map(|(id, VertexWithChildren { vertex, .. })| VertexDump {
+                id: id.clone(),
+                parent: vertex.parent().cloned(),
+                contents: vertex.contents(),
+                interest: vertex.interest(),
+                know_most: vertex.know_most().iter().cloned().collect(),
+            })
+            .collect();
+        vertices.sort_by_key(|vertex| vertex.id.number());
+        ForestDump {
+            root: self.root_id.clone(),
+            highest_justified: self.highest_justified.clone(),
+            vertices,
+        }
+    }
+
+    /// The vertices that would be lost on a restart, to be restored afterwards.
+    pub fn checkpoint(&self) -> ForestCheckpoint<J> {
+        ForestCheckpoint::new(
+            self.root_id.clone(),
+            self.vertices
+                .iter()
+                .filter(|(id, _)| !self.compost_bin.contains(id))
+                .filter_map(|(id, VertexWithChildren { vertex, .. })| {
+                    vertex.checkpoint(id, self.justifications.get(id))
+                })
+                .collect(),
+        )
+    }
+
+    /// How many blocks have known headers, but are still waiting for their bodies, e.g. after
+    /// fetching the chain of headers first.
+    pub fn pending_bodies(&self) -> usize {
+        self.vertices
+            .values()
+            .filter(|VertexWithChildren { vertex, .. }| vertex.body_pending())
+            .count()
+    }
+
+    /// Forgets the peer as knowing most about any of the blocks, e.g. because it disconnected,
+    /// so that requests for them go to other peers.
+    pub fn forget_peer(&mut self, peer: &I) {
+        for VertexWithChildren { vertex, .. } in self.vertices.values_mut() {
+            vertex.remove_block_holder(peer);
+        }
+    }
+
+    /// How many branches the forest holds, i.e. how many of its blocks have no known children.
+    pub fn forks(&self) -> usize {
+        self.vertices
+            .iter()
+            .filter(|(id, VertexWithChildren { children, .. })| {
+                children.is_empty() && !self.compost_bin.contains(id)
+            })
+            .count()
+    }
+
+    /// The number of the highest blocks that fit in the forest.
+    pub fn highest_holdable(&self) -> BlockNumber {
+        self.root_id.number() + MAX_DEPTH
+    }
+
+    /// Whether the block is low enough to fit in the forest.
+    pub fn can_hold(&self, id: &BlockIdFor<J>) -> bool {
+        !matches!(self.special_state(id), Some(SpecialState::TooNew))
+    }
+
     /// Whether we would like to eventually import this block.
     pub fn importable(&self, id: &BlockIdFor<J>) -> bool {
         use VertexHandle::Candidate;
@@ -557,23 +781,20 @@ where
 
 #[cfg(test)]
 mod tests {
-    use super::{Error, Forest, Interest::*, MAX_DEPTH};
+    use super::{Error, Forest, Interest::*, VertexContents, VertexInterest, MAX_DEPTH};
     use crate::{
-        session::SessionBoundaryInfo,
+        session::testing::session_boundary_info,
         sync::{
             data::BranchKnowledge::*,
             mock::{Backend, MockHeader, MockJustification, MockPeerId},
             ChainStatus, Header, Justification,
         },
-        SessionPeriod,
     };
 
     type MockForest = Forest<MockPeerId, MockJustification>;
 
-    const SESSION_BOUNDARY_INFO: SessionBoundaryInfo = SessionBoundaryInfo::new(SessionPeriod(20));
-
     fn setup() -> (MockHeader, MockForest) {
-        let (backend, _) = Backend::setup(SESSION_BOUNDARY_INFO);
+        let (backend, _) = Backend::setup(session_boundary_info());
         let header = backend
             .top_finalized()
             .expect("should return genesis")
@@ -591,6 +812,25 @@ mod tests {
         assert!(!forest.importable(&initial_header.id()));
     }
 
+    #[test]
+    fn counts_forks() {
+        let (initial_header, mut forest) = setup();
+        assert_eq!(forest.forks(), 0);
+        let peer_id = rand::random();
+        let child = initial_header.random_child();
+        let grandchild = child.random_child();
+        for header in [&child, &grandchild] {
+            forest
+                .update_header(header, Some(peer_id), true)
+                .expect("it's not too high");
+        }
+        assert_eq!(forest.forks(), 1);
+        forest
+            .update_header(&initial_header.random_child(), Some(peer_id), true)
+            .expect("it's not too high");
+        assert_eq!(forest.forks(), 2);
+    }
+
     #[test]
     fn accepts_first_unimportant_id() {
         let (initial_header, mut forest) = setup();
@@ -623,6 +863,27 @@ mod tests {
             .expect("it's not too high"));
     }
 
+    #[test]
+    fn forgets_disconnected_peers() {
+        let (initial_header, mut forest) = setup();
+        let child = initial_header.random_child();
+        let peer_id = rand::random();
+        let other_peer_id = peer_id + 1;
+        for peer in [peer_id, other_peer_id] {
+            forest
+                .update_block_identifier(&child.id(), Some(peer), true)
+                .expect("it's not too high");
+        }
+        forest.forget_peer(&peer_id);
+        match forest.request_interest(&child.id()) {
+            Required { know_most, .. } => {
+                assert!(!know_most.contains(&peer_id));
+                assert!(know_most.contains(&other_peer_id));
+            }
+            other_state => panic!("Expected top required, got {other_state:?}."),
+        }
+    }
+
     #[test]
     fn rejects_too_high_id() {
         let (initial_header, mut forest) = setup();
@@ -637,6 +898,19 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn holds_only_ids_within_depth() {
+        let (initial_header, forest) = setup();
+        let mut branch = initial_header.random_branch();
+        let highest_held = branch
+            .nth(MAX_DEPTH as usize - 1)
+            .expect("the branch is infinite");
+        let too_high = branch.next().expect("the branch is infinite");
+        assert!(forest.can_hold(&highest_held.id()));
+        assert!(!forest.can_hold(&too_high.id()));
+        assert_eq!(forest.highest_holdable(), highest_held.id().number());
+    }
+
     #[test]
     fn accepts_first_unimportant_header() {
         let (initial_header, mut forest) = setup();
@@ -767,6 +1041,35 @@ mod tests {
         assert_eq!(forest.try_finalize(&1).expect("the block is ready"), child);
     }
 
+    #[test]
+    fn finalizes_connected_headers_without_bodies() {
+        let (initial_header, forest) = setup();
+        let mut forest = forest.headers_only();
+        let branch: Vec<_> = initial_header.random_branch().take(3).collect();
+        let justification = MockJustification::for_header(branch[2].clone());
+        assert!(forest
+            .update_justification(justification.clone(), None)
+            .expect("header was correct"));
+        forest
+            .update_header(&branch[1], None, false)
+            .expect("header was correct");
+        assert!(!forest.finalizable(&3));
+        match forest.request_interest(&branch[2].id()) {
+            HighestJustified {
+                branch_knowledge, ..
+            } => assert_eq!(branch_knowledge, LowestId(branch[0].id())),
+            other_state => panic!("Expected highest justified, got {other_state:?}."),
+        }
+        forest
+            .update_header(&branch[0], None, false)
+            .expect("header was correct");
+        assert!(forest.finalizable(&3));
+        assert_eq!(
+            forest.try_finalize(&3).expect("the block is ready"),
+            justification
+        );
+    }
+
     #[test]
     fn required_becomes_highest_finalized() {
         let (initial_header, mut forest) = setup();
@@ -1123,6 +1426,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn prunes_huge_branch_incrementally() {
+        let (initial_header, mut forest) = setup();
+        let fork: Vec<_> = initial_header
+            .random_branch()
+            .take(HUGE_BRANCH_LENGTH)
+            .collect();
+        for header in &fork {
+            let peer_id = rand::random();
+            assert!(forest
+                .update_header(header, Some(peer_id), true)
+                .expect("header was correct"));
+        }
+        let child = MockJustification::for_header(initial_header.random_child());
+        let peer_id = rand::random();
+        assert!(forest
+            .update_justification(child.clone(), Some(peer_id))
+            .expect("header was correct"));
+        forest
+            .update_body(child.header())
+            .expect("header was correct");
+        assert_eq!(forest.try_finalize(&1).expect("the block is ready"), child);
+        assert!(forest.pruning_pending());
+        let budget = 100;
+        let mut steps = 0;
+        while forest.prune_pending(budget) {
+            steps += 1;
+            let top = fork.last().expect("the fork is not empty").id();
+            assert_eq!(forest.request_interest(&top), Uninterested);
+            assert!(!forest.importable(&top));
+        }
+        assert_eq!(steps, HUGE_BRANCH_LENGTH / budget - 1);
+        assert!(forest.dump().vertices.is_empty());
+    }
+
     #[test]
     fn updates_interest_on_huge_branch() {
         let (initial_header, mut forest) = setup();
@@ -1156,4 +1494,93 @@ mod tests {
             assert!(forest.importable(&header.id()));
         }
     }
+
+    #[test]
+    fn dumps_vertices() {
+        let (initial_header, mut forest) = setup();
+        let child = initial_header.random_child();
+        let grandchild = child.random_child();
+        let peer_id = rand::random();
+        assert!(forest
+            .update_header(&grandchild, Some(peer_id), true)
+            .expect("header was correct"));
+        let dump = forest.dump();
+        assert_eq!(dump.root, initial_header.id());
+        assert_eq!(dump.vertices.len(), 2);
+        let child_dump = &dump.vertices[0];
+        assert_eq!(child_dump.id, child.id());
+        assert_eq!(child_dump.parent, None);
+        assert_eq!(child_dump.contents, VertexContents::Empty);
+        assert_eq!(child_dump.interest, VertexInterest::Required);
+        let grandchild_dump = &dump.vertices[1];
+        assert_eq!(grandchild_dump.id, grandchild.id());
+        assert_eq!(grandchild_dump.parent, Some(child.id()));
+        assert_eq!(grandchild_dump.contents, VertexContents::Header);
+        assert_eq!(grandchild_dump.interest, VertexInterest::ExplicitlyRequired);
+        assert_eq!(grandchild_dump.know_most, vec![peer_id]);
+    }
+
+    #[test]
+    fn restores_from_checkpoint() {
+        let (initial_header, mut forest) = setup();
+        let branch: Vec<_> = initial_header.random_branch().take(5).collect();
+        let peer_id = rand::random();
+        for header in &branch[2..4] {
+            assert!(!forest
+                .update_header(header, Some(peer_id), false)
+                .expect("header was correct"));
+        }
+        assert!(forest
+            .update_header(&branch[4], Some(peer_id), true)
+            .expect("header was correct"));
+        let fork = initial_header.random_child();
+        assert!(!forest
+            .update_header(&fork, Some(peer_id), false)
+            .expect("header was correct"));
+        let wanted = initial_header.random_child();
+        assert!(forest
+            .update_block_identifier(&wanted.id(), Some(peer_id), true)
+            .expect("it's not too high"));
+        let checkpoint = forest.checkpoint();
+        assert_eq!(checkpoint.root, initial_header.id());
+        // The empty parent of the branch gets recreated by its child.
+        assert_eq!(checkpoint.vertices.len(), 5);
+
+        let (_, mut restored) = setup();
+        let mut newly_required = 0;
+        for vertex in checkpoint.vertices {
+            if restored.restore_vertex(vertex).expect("vertex fits") {
+                newly_required += 1;
+            }
+        }
+        assert_eq!(newly_required, 2);
+        let summary = |forest: &MockForest| {
+            let mut summary: Vec<_> = forest
+                .dump()
+                .vertices
+                .into_iter()
+                .map(|vertex| {
+                    (
+                        vertex.id.to_string(),
+                        vertex.parent,
+                        vertex.contents,
+                        vertex.interest,
+                    )
+                })
+                .collect();
+            summary.sort_by(|a, b| a.0.cmp(&b.0));
+            summary
+        };
+        assert_eq!(summary(&restored), summary(&forest));
+        match restored.request_interest(&branch[4].id()) {
+            Required {
+                know_most,
+                branch_knowledge,
+            } => {
+                assert!(know_most.is_empty());
+                assert_eq!(branch_knowledge, LowestId(branch[1].id()));
+            }
+            other_state => panic!("Expected required, got {other_state:?}."),
+        }
+    }
 }
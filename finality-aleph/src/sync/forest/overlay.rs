@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use crate::{
+    sync::{BlockIdFor, Header, Justification},
+    BlockIdentifier, BlockNumber,
+};
+
+/// The verified justifications of blocks above the top finalized one, kept until their ancestors
+/// get finalized. Once its block is imported, a justification can be applied, but only in order
+/// of block numbers, right after the block below it got finalized.
+pub struct JustificationOverlay<J: Justification> {
+    justifications: HashMap<BlockIdFor<J>, J>,
+    /// The imported blocks with justifications, by number.
+    imported: HashMap<BlockNumber, BlockIdFor<J>>,
+}
+
+impl<J: Justification> JustificationOverlay<J> {
+    pub fn new() -> Self {
+        JustificationOverlay {
+            justifications: HashMap::new(),
+            imported: HashMap::new(),
+        }
+    }
+
+    /// Keeps the justification, unless there already is one for its block.
+    /// Returns whether it was kept.
+    pub fn insert(&mut self, justification: J) -> bool {
+        let id = justification.header().id();
+        if self.justifications.contains_key(&id) {
+            return false;
+        }
+        self.justifications.insert(id, justification);
+        true
+    }
+
+    /// The justification of the block, if there is one.
+    pub fn get(&self, id: &BlockIdFor<J>) -> Option<&J> {
+        self.justifications.get(id)
+    }
+
+    /// Records that the block got imported, returns whether it has a justification, i.e. whether
+    /// it can be finalized once the blocks below are.
+    pub fn block_imported(&mut self, id: &BlockIdFor<J>) -> bool {
+        if !self.justifications.contains_key(id) {
+            return false;
+        }
+        self.imported.insert(id.number(), id.clone());
+        true
+    }
+
+    /// The imported block at the height with a justification, if there is one.
+    pub fn finalizable(&self, number: &BlockNumber) -> Option<&BlockIdFor<J>> {
+        self.imported.get(number)
+    }
+
+    /// The justification of the highest imported block with one, if there is any.
+    pub fn highest_imported(&self) -> Option<&J> {
+        self.imported
+            .iter()
+            .max_by_key(|(number, _)| **number)
+            .and_then(|(_, id)| self.justifications.get(id))
+    }
+
+    /// Takes out the justification of the imported block at the height, to be applied.
+    pub fn take(&mut self, number: &BlockNumber) -> Option<J> {
+        let id = self.imported.remove(number)?;
+        self.justifications.remove(&id)
+    }
+
+    /// Forgets the justification of the block, e.g. because the block got pruned.
+    pub fn remove(&mut self, id: &BlockIdFor<J>) {
+        if self.justifications.remove(id).is_some() && self.imported.get(&id.number()) == Some(id) {
+            self.imported.remove(&id.number());
+        }
+    }
+
+    /// Forgets all the justifications up to the finalized height.
+    pub fn prune(&mut self, finalized: BlockNumber) {
+        self.justifications.retain(|id, _| id.number() > finalized);
+        self.imported.retain(|number, _| number > &finalized);
+    }
+
+    /// How many justifications wait for their ancestors to get finalized.
+    pub fn depth(&self) -> usize {
+        self.justifications.len()
+    }
+}
+
+impl<J: Justification> Default for JustificationOverlay<J> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JustificationOverlay;
+    use crate::{
+        sync::{
+            mock::{MockHeader, MockJustification},
+            Header,
+        },
+        BlockIdentifier,
+    };
+
+    fn justifications(length: usize) -> Vec<MockJustification> {
+        MockHeader::random_parentless(0)
+            .random_branch()
+            .take(length)
+            .map(MockJustification::for_header)
+            .collect()
+    }
+
+    #[test]
+    fn applies_in_order_once_imported() {
+        let mut overlay = JustificationOverlay::new();
+        let justifications = justifications(3);
+        for justification in &justifications {
+            assert!(overlay.insert(justification.clone()));
+        }
+        assert!(!overlay.insert(justifications[0].clone()));
+        assert_eq!(overlay.depth(), 3);
+        assert!(overlay.finalizable(&1).is_none());
+        assert_eq!(overlay.take(&1), None);
+        for justification in &justifications {
+            assert!(overlay.block_imported(&justification.header().id()));
+        }
+        assert_eq!(
+            overlay.get(&justifications[0].header().id()),
+            Some(&justifications[0])
+        );
+        assert_eq!(overlay.take(&1), Some(justifications[0].clone()));
+        assert_eq!(overlay.take(&1), None);
+        assert_eq!(overlay.depth(), 2);
+        assert_eq!(
+            overlay.finalizable(&2),
+            Some(&justifications[1].header().id())
+        );
+    }
+
+    #[test]
+    fn forgets_pruned_justifications() {
+        let mut overlay = JustificationOverlay::new();
+        let justifications = justifications(3);
+        for justification in &justifications {
+            overlay.insert(justification.clone());
+            overlay.block_imported(&justification.header().id());
+        }
+        let removed = justifications[2].header().id();
+        overlay.remove(&removed);
+        assert!(overlay.get(&removed).is_none());
+        assert!(overlay.finalizable(&removed.number()).is_none());
+        overlay.prune(1);
+        assert_eq!(overlay.depth(), 1);
+        assert!(overlay.take(&1).is_none());
+        assert_eq!(overlay.take(&2), Some(justifications[1].clone()));
+        assert!(!overlay.block_imported(&MockHeader::random_parentless(5).id()));
+    }
+}
@@ -1,6 +1,12 @@
 use std::collections::HashSet;
 
-use crate::sync::{BlockIdFor, Justification, PeerId};
+use crate::sync::{
+    forest::{
+        checkpoint::VertexCheckpoint,
+        dump::{VertexContents, VertexInterest},
+    },
+    BlockIdFor, Justification, PeerId,
+};
 
 #[derive(Clone, Debug, Copy, PartialEq, Eq)]
 enum Importance {
@@ -24,10 +30,10 @@ enum InnerVertex<J: Justification> {
         importance: HeaderImportance,
         parent: BlockIdFor<J>,
     },
-    /// Vertex with added Header and Justification.
+    /// Vertex with added Header and Justification, the justification itself is kept in the
+    /// justification overlay.
     Justification {
         imported: bool,
-        justification: J,
         parent: BlockIdFor<J>,
     },
 }
@@ -84,6 +90,22 @@ impl<I: PeerId, J: Justification> Vertex<I, J> {
         )
     }
 
+    /// Whether the header of the referenced block is known, but we still want its body.
+    pub fn body_pending(&self) -> bool {
+        use Importance::*;
+        use InnerVertex::*;
+        matches!(
+            self.inner,
+            Header {
+                importance: HeaderImportance::Unimported(Required | ExplicitlyRequired),
+                ..
+            } | Justification {
+                imported: false,
+                ..
+            }
+        )
+    }
+
     /// Whether the vertex is imported.
     pub fn imported(&self) -> bool {
         use InnerVertex::*;
@@ -104,19 +126,6 @@ impl<I: PeerId, J: Justification> Vertex<I, J> {
         )
     }
 
-    /// Deconstructs the vertex into a justification if it is ready to be imported,
-    /// i.e. the related block has already been imported, otherwise returns it.
-    pub fn ready(self) -> Result<J, Self> {
-        match self.inner {
-            InnerVertex::Justification {
-                imported: true,
-                justification,
-                ..
-            } => Ok(justification),
-            _ => Err(self),
-        }
-    }
-
     /// The parent of the vertex, if known.
     pub fn parent(&self) -> Option<&BlockIdFor<J>> {
         match &self.inner {
@@ -126,6 +135,59 @@ impl<I: PeerId, J: Justification> Vertex<I, J> {
         }
     }
 
+    /// How much we know about the referenced block.
+    pub fn contents(&self) -> VertexContents {
+        match self.inner {
+            InnerVertex::Empty { .. } => VertexContents::Empty,
+            InnerVertex::Header { .. } => VertexContents::Header,
+            InnerVertex::Justification { .. } => VertexContents::Justification,
+        }
+    }
+
+    /// Whether we want the referenced block.
+    pub fn interest(&self) -> VertexInterest {
+        match (self.imported(), self.requestable(), self.importable()) {
+            (true, _, _) => VertexInterest::Imported,
+            (false, true, _) => VertexInterest::ExplicitlyRequired,
+            (false, false, true) => VertexInterest::Required,
+            (false, false, false) => VertexInterest::Auxiliary,
+        }
+    }
+
+    /// What has to be kept to restore the vertex after a restart, if anything. Imported blocks
+    /// are in the database already, but their justifications are not, unless finalized, so the
+    /// justification of the block has to be provided for justified vertices.
+    pub fn checkpoint(
+        &self,
+        id: &BlockIdFor<J>,
+        justification: Option<&J>,
+    ) -> Option<VertexCheckpoint<J>> {
+        use HeaderImportance::*;
+        use Importance::*;
+        use InnerVertex::*;
+        match &self.inner {
+            Empty {
+                required: ExplicitlyRequired,
+            } => Some(VertexCheckpoint::Wanted(id.clone())),
+            Header {
+                importance: Unimported(importance),
+                parent,
+            } => Some(VertexCheckpoint::Header {
+                id: id.clone(),
+                parent: parent.clone(),
+                explicitly_required: importance == &ExplicitlyRequired,
+            }),
+            Justification { .. } => justification.map(|justification| {
+                VertexCheckpoint::Justification(justification.clone().into_unverified())
+            }),
+            Empty { .. }
+            | Header {
+                importance: Imported,
+                ..
+            } => None,
+        }
+    }
+
     /// The list of peers which know most about the data this vertex refers to.
     pub fn know_most(&self) -> &HashSet<I> {
         &self.know_most
@@ -197,6 +259,11 @@ impl<I: PeerId, J: Justification> Vertex<I, J> {
         }
     }
 
+    /// Forgets that the peer knows most about the block, e.g. because it disconnected.
+    pub fn remove_block_holder(&mut self, holder: &I) {
+        self.know_most.remove(holder);
+    }
+
     /// Adds the information the header provides to the vertex.
     pub fn insert_header(&mut self, parent: BlockIdFor<J>, holder: Option<I>) {
         self.add_block_holder(holder);
@@ -223,12 +290,10 @@ impl<I: PeerId, J: Justification> Vertex<I, J> {
             Justification {
                 imported: false,
                 parent,
-                justification,
             } => {
                 self.inner = Justification {
                     imported: true,
                     parent: parent.clone(),
-                    justification: justification.clone(),
                 };
                 true
             }
@@ -236,13 +301,8 @@ impl<I: PeerId, J: Justification> Vertex<I, J> {
         }
     }
 
-    /// Adds a justification to the vertex.
-    pub fn insert_justification(
-        &mut self,
-        parent: BlockIdFor<J>,
-        justification: J,
-        holder: Option<I>,
-    ) {
+    /// Marks the vertex as justified, the justification itself has to be kept elsewhere.
+    pub fn insert_justification(&mut self, parent: BlockIdFor<J>, holder: Option<I>) {
         use InnerVertex::*;
         match self.inner {
             Empty { .. }
@@ -253,7 +313,6 @@ impl<I: PeerId, J: Justification> Vertex<I, J> {
                 self.inner = Justification {
                     imported: false,
                     parent,
-                    justification,
                 };
                 self.know_most = holder.into_iter().collect();
             }
@@ -264,7 +323,6 @@ impl<I: PeerId, J: Justification> Vertex<I, J> {
                 self.inner = Justification {
                     imported: true,
                     parent,
-                    justification,
                 };
                 self.know_most = holder.into_iter().collect();
             }
@@ -295,7 +353,7 @@ mod tests {
         assert!(!vertex.imported());
         assert!(vertex.parent().is_none());
         assert!(vertex.know_most().is_empty());
-        assert_eq!(vertex.clone().ready(), Err(vertex));
+        assert!(!vertex.justified_block());
     }
 
     #[test]
@@ -361,7 +419,7 @@ mod tests {
         assert!(!vertex.imported());
         assert_eq!(vertex.parent(), Some(&parent));
         assert!(vertex.know_most().contains(&peer_id));
-        assert_eq!(vertex.clone().ready(), Err(vertex));
+        assert!(!vertex.justified_block());
     }
 
     #[test]
@@ -388,6 +446,18 @@ mod tests {
         assert!(vertex.importable());
     }
 
+    #[test]
+    fn required_header_waits_for_body() {
+        let mut vertex = MockVertex::new();
+        assert!(vertex.set_required());
+        assert!(!vertex.body_pending());
+        let parent = MockIdentifier::new_random(43);
+        vertex.insert_header(parent.clone(), None);
+        assert!(vertex.body_pending());
+        vertex.insert_body(parent);
+        assert!(!vertex.body_pending());
+    }
+
     #[test]
     fn header_set_explicitly_required() {
         let mut vertex = MockVertex::new();
@@ -437,7 +507,7 @@ mod tests {
         assert!(!vertex.requestable());
         assert!(vertex.imported());
         assert_eq!(vertex.parent(), Some(&parent));
-        assert_eq!(vertex.clone().ready(), Err(vertex));
+        assert!(!vertex.justified_block());
     }
 
     #[test]
@@ -451,7 +521,7 @@ mod tests {
         assert!(!vertex.requestable());
         assert!(vertex.imported());
         assert_eq!(vertex.parent(), Some(&parent));
-        assert_eq!(vertex.clone().ready(), Err(vertex));
+        assert!(!vertex.justified_block());
     }
 
     #[test]
@@ -492,15 +562,14 @@ mod tests {
         let parent_header = MockHeader::random_parentless(0);
         let header = parent_header.random_child();
         let parent = header.parent_id().expect("born of a parent");
-        let justification = MockJustification::for_header(header);
         let peer_id = rand::random();
-        vertex.insert_justification(parent.clone(), justification, Some(peer_id));
+        vertex.insert_justification(parent.clone(), Some(peer_id));
         assert!(vertex.importable());
         assert!(!vertex.requestable());
         assert!(!vertex.imported());
         assert_eq!(vertex.parent(), Some(&parent));
         assert!(vertex.know_most().contains(&peer_id));
-        assert_eq!(vertex.clone().ready(), Err(vertex));
+        assert!(!vertex.justified_block());
     }
 
     #[test]
@@ -509,16 +578,15 @@ mod tests {
         let parent_header = MockHeader::random_parentless(0);
         let header = parent_header.random_child();
         let parent = header.parent_id().expect("born of a parent");
-        let justification = MockJustification::for_header(header);
         let peer_id = rand::random();
         vertex.insert_header(parent.clone(), Some(peer_id));
-        vertex.insert_justification(parent.clone(), justification, None);
+        vertex.insert_justification(parent.clone(), None);
         assert!(vertex.importable());
         assert!(!vertex.requestable());
         assert!(!vertex.imported());
         assert_eq!(vertex.parent(), Some(&parent));
         assert!(vertex.know_most().is_empty());
-        assert_eq!(vertex.clone().ready(), Err(vertex));
+        assert!(!vertex.justified_block());
     }
 
     #[test]
@@ -527,14 +595,13 @@ mod tests {
         let parent_header = MockHeader::random_parentless(0);
         let header = parent_header.random_child();
         let parent = header.parent_id().expect("born of a parent");
-        let justification = MockJustification::for_header(header);
         assert!(!vertex.insert_body(parent.clone()));
-        vertex.insert_justification(parent.clone(), justification.clone(), None);
+        vertex.insert_justification(parent.clone(), None);
         assert!(!vertex.importable());
         assert!(!vertex.requestable());
         assert!(vertex.imported());
         assert_eq!(vertex.parent(), Some(&parent));
-        assert_eq!(vertex.ready(), Ok(justification));
+        assert!(vertex.justified_block());
     }
 
     #[test]
@@ -543,9 +610,8 @@ mod tests {
         let parent_header = MockHeader::random_parentless(0);
         let header = parent_header.random_child();
         let parent = header.parent_id().expect("born of a parent");
-        let justification = MockJustification::for_header(header);
         let peer_id = rand::random();
-        vertex.insert_justification(parent, justification, Some(peer_id));
+        vertex.insert_justification(parent, Some(peer_id));
         assert!(!vertex.set_required());
         assert!(vertex.importable());
         assert!(!vertex.requestable());
@@ -557,9 +623,8 @@ mod tests {
         let parent_header = MockHeader::random_parentless(0);
         let header = parent_header.random_child();
         let parent = header.parent_id().expect("born of a parent");
-        let justification = MockJustification::for_header(header);
         let peer_id = rand::random();
-        vertex.insert_justification(parent, justification, Some(peer_id));
+        vertex.insert_justification(parent, Some(peer_id));
         assert!(!vertex.set_explicitly_required());
         assert!(vertex.importable());
         assert!(!vertex.requestable());
@@ -571,10 +636,9 @@ mod tests {
         let parent_header = MockHeader::random_parentless(0);
         let header = parent_header.random_child();
         let parent = header.parent_id().expect("born of a parent");
-        let justification = MockJustification::for_header(header);
         let peer_id = rand::random();
         assert!(vertex.set_required());
-        vertex.insert_justification(parent, justification, Some(peer_id));
+        vertex.insert_justification(parent, Some(peer_id));
         assert!(vertex.importable());
         assert!(!vertex.requestable());
     }
@@ -585,10 +649,9 @@ mod tests {
         let parent_header = MockHeader::random_parentless(0);
         let header = parent_header.random_child();
         let parent = header.parent_id().expect("born of a parent");
-        let justification = MockJustification::for_header(header);
         let peer_id = rand::random();
         assert!(vertex.set_explicitly_required());
-        vertex.insert_justification(parent, justification, Some(peer_id));
+        vertex.insert_justification(parent, Some(peer_id));
         assert!(vertex.importable());
         assert!(!vertex.requestable());
     }
@@ -599,13 +662,12 @@ mod tests {
         let parent_header = MockHeader::random_parentless(0);
         let header = parent_header.random_child();
         let parent = header.parent_id().expect("born of a parent");
-        let justification = MockJustification::for_header(header);
-        vertex.insert_justification(parent.clone(), justification.clone(), None);
+        vertex.insert_justification(parent.clone(), None);
         assert!(vertex.insert_body(parent.clone()));
         assert!(!vertex.importable());
         assert!(!vertex.requestable());
         assert!(vertex.imported());
         assert_eq!(vertex.parent(), Some(&parent));
-        assert_eq!(vertex.ready(), Ok(justification));
+        assert!(vertex.justified_block());
     }
 }
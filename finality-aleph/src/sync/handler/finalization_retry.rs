@@ -0,0 +1,79 @@
+use std::{
+    cmp::min,
+    time::{Duration, Instant},
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// When to try finalizing again after the database failed with a transient error. Every failure
+/// in a row doubles the wait, up to a limit.
+pub struct FinalizationRetry {
+    backoff: Duration,
+    next_attempt: Option<Instant>,
+}
+
+impl FinalizationRetry {
+    pub fn new() -> Self {
+        FinalizationRetry {
+            backoff: INITIAL_BACKOFF,
+            next_attempt: None,
+        }
+    }
+
+    /// Whether finalizing can be attempted, i.e. we are not waiting out a backoff.
+    pub fn ready(&self, now: Instant) -> bool {
+        self.next_attempt.map_or(true, |at| at <= now)
+    }
+
+    /// When the next attempt should be made, if finalizing failed.
+    pub fn next_attempt(&self) -> Option<Instant> {
+        self.next_attempt
+    }
+
+    /// Records a transient failure, returns how long to wait before trying again.
+    pub fn failed(&mut self, now: Instant) -> Duration {
+        let backoff = self.backoff;
+        self.next_attempt = Some(now + backoff);
+        self.backoff = min(backoff * 2, MAX_BACKOFF);
+        backoff
+    }
+
+    /// Records that finalizing went through, or failed in a way retrying would not help.
+    pub fn reset(&mut self) {
+        self.backoff = INITIAL_BACKOFF;
+        self.next_attempt = None;
+    }
+}
+
+impl Default for FinalizationRetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{FinalizationRetry, INITIAL_BACKOFF, MAX_BACKOFF};
+
+    #[test]
+    fn backs_off_exponentially_until_reset() {
+        let mut retry = FinalizationRetry::new();
+        let now = Instant::now();
+        assert!(retry.ready(now));
+        assert_eq!(retry.failed(now), INITIAL_BACKOFF);
+        assert!(!retry.ready(now));
+        assert!(retry.ready(now + INITIAL_BACKOFF));
+        assert_eq!(retry.failed(now), INITIAL_BACKOFF * 2);
+        for _ in 0..10 {
+            retry.failed(now);
+        }
+        assert_eq!(retry.failed(now), MAX_BACKOFF);
+        retry.reset();
+        assert!(retry.ready(now));
+        assert_eq!(retry.next_attempt(), None);
+        assert_eq!(retry.failed(now), INITIAL_BACKOFF);
+    }
+}
@@ -1,27 +1,41 @@
 use core::marker::PhantomData;
 use std::{
-    cmp::max,
-    collections::VecDeque,
+    cmp::{max, min},
+    collections::{HashSet, VecDeque},
     fmt::{Debug, Display, Error as FmtError, Formatter},
     iter,
+    sync::Arc,
+    time::Instant,
 };
 
 use crate::{
+    network::CancelToken,
     session::{SessionBoundaryInfo, SessionId},
     sync::{
-        data::{NetworkData, Request, State},
+        chain_selection::{ChainCandidates, ChainSelectionStrategy, LongestChain},
+        data::{
+            BodyRequest, NetworkData, Request, State, MAX_BATCHED_JUSTIFICATIONS,
+            MAX_BODY_REQUEST_BLOCKS, MAX_WARP_JUSTIFICATIONS,
+        },
+        finalization_hook::{FinalizationHooks, FinalizedBlock},
         forest::{
-            Error as ForestError, Forest, InitializationError as ForestInitializationError,
-            Interest,
+            Error as ForestError, Forest, ForestCheckpoint, ForestDump,
+            InitializationError as ForestInitializationError, Interest, VertexCheckpoint,
+            MAX_DEPTH,
         },
         handler::request_handler::{RequestHandler, RequestHandlerError},
-        Block, BlockIdFor, BlockImport, ChainStatus, Finalizer, Header, Justification, PeerId,
-        Verifier,
+        header_chain::{verify_descending_chain_of_refs, ChainError},
+        metrics::Metrics,
+        provenance::{ProvenanceHistory, ProvenanceTracker},
+        Block, BlockIdFor, BlockImport, BlockStatus, CandidateId, ChainStatus, FinalizationStatus,
+        FinalizedId, Finalizer, Header, Justification, OffloadedCheck, PeerId, Verifier,
     },
     BlockIdentifier, BlockNumber,
 };
 
+mod finalization_retry;
 mod request_handler;
+use finalization_retry::FinalizationRetry;
 pub use request_handler::Action;
 
 use crate::sync::data::{ResponseItem, ResponseItems};
@@ -59,6 +73,30 @@ where
     }
 }
 
+/// What got restored from a forest checkpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RestoredForest<BI: BlockIdentifier> {
+    /// How many vertices got restored.
+    pub vertices: usize,
+    /// How many vertices no longer fit the forest, or had justifications failing verification.
+    pub skipped: usize,
+    /// The blocks we want by themselves, which have to be requested again.
+    pub wanted: Vec<BI>,
+    /// The new highest justified block, if any.
+    pub highest_justified: Option<CandidateId<BI>>,
+}
+
+impl<BI: BlockIdentifier> Default for RestoredForest<BI> {
+    fn default() -> Self {
+        RestoredForest {
+            vertices: 0,
+            skipped: 0,
+            wanted: Vec::new(),
+            highest_justified: None,
+        }
+    }
+}
+
 /// A handle for requesting Interest.
 pub struct InterestProvider<'a, I, J>
 where
@@ -191,6 +229,64 @@ impl MissedImportData {
     }
 }
 
+/// Checks that every run of consecutive headers in the response forms a descending chain,
+/// which is how they are sent by the request handler. A run can also pass through a block with
+/// a justification in the response, as the header of such a block comes with the justification.
+fn verify_header_chains<B, J>(response_items: &ResponseItems<B, J>) -> Result<(), ChainError<J>>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    let justified: HashSet<_> = response_items
+        .iter()
+        .filter_map(|item| match item {
+            ResponseItem::Justification(justification) => Some(justification.id()),
+            _ => None,
+        })
+        .collect();
+    let mut headers: Vec<&J::Header> = Vec::new();
+    for item in response_items.iter().map(Some).chain(iter::once(None)) {
+        match item {
+            Some(ResponseItem::Header(header)) => {
+                let through_justified = headers.last().map_or(false, |last| {
+                    last.parent_id()
+                        .map_or(false, |parent| justified.contains(&parent))
+                });
+                if through_justified {
+                    verify_descending_chain_of_refs(&headers)?;
+                    headers.clear();
+                }
+                headers.push(header)
+            }
+            _ if headers.is_empty() => (),
+            _ => {
+                verify_descending_chain_of_refs(&headers)?;
+                headers.clear();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the justifications are strictly increasing by block number, which is how they are
+/// sent in all the responses, so that each can be applied as it comes. Returns the ids of the
+/// first two out of order otherwise.
+fn verify_justification_order<'a, J: Justification + 'a>(
+    justifications: impl IntoIterator<Item = &'a J::Unverified>,
+) -> Result<(), (BlockIdFor<J>, BlockIdFor<J>)> {
+    let mut previous: Option<BlockIdFor<J>> = None;
+    for justification in justifications {
+        let id = justification.id();
+        if let Some(previous) = previous {
+            if id.number() <= previous.number() {
+                return Err((previous, id));
+            }
+        }
+        previous = Some(id);
+    }
+    Ok(())
+}
+
 /// Handler for data incoming from the network.
 pub struct Handler<B, I, J, CS, V, F, BI>
 where
@@ -209,6 +305,15 @@ where
     session_info: SessionBoundaryInfo,
     block_importer: BI,
     missed_import_data: MissedImportData,
+    serving_window: BlockNumber,
+    finalization_batch: BlockNumber,
+    max_response_blocks: Option<usize>,
+    provenance: ProvenanceTracker<I, BlockIdFor<J>>,
+    finalization_hooks: FinalizationHooks<J>,
+    finalization_retry: FinalizationRetry,
+    chain_selection: Arc<dyn ChainSelectionStrategy<J::Header>>,
+    headers_only: bool,
+    metrics: Metrics,
     phantom: PhantomData<B>,
 }
 
@@ -222,7 +327,7 @@ where
     /// A response for the peer that sent us the data.
     Response(NetworkData<B, J>),
     /// A request for the highest justified block that should be performed periodically.
-    HighestJustified(BlockIdFor<J>),
+    HighestJustified(CandidateId<BlockIdFor<J>>),
     /// Do nothing.
     Noop,
 }
@@ -238,14 +343,27 @@ where
             other_justification,
         ))
     }
+
+    fn batched_response(mut justifications: Vec<J::Unverified>) -> Self {
+        match justifications.is_empty() {
+            true => Self::Noop,
+            false => {
+                let justification = justifications.remove(0);
+                Self::Response(NetworkData::BatchedStateBroadcastResponse(
+                    justification,
+                    justifications,
+                ))
+            }
+        }
+    }
 }
 
-impl<B, J> From<Option<BlockIdFor<J>>> for HandleStateAction<B, J>
+impl<B, J> From<Option<CandidateId<BlockIdFor<J>>>> for HandleStateAction<B, J>
 where
     B: Block,
     J: Justification<Header = B::Header>,
 {
-    fn from(value: Option<BlockIdFor<J>>) -> Self {
+    fn from(value: Option<CandidateId<BlockIdFor<J>>>) -> Self {
         match value {
             Some(id) => Self::HighestJustified(id),
             None => Self::Noop,
@@ -269,9 +387,15 @@ where
     Forest(ForestError),
     ForestInitialization(ForestInitializationError<B, J, CS>),
     RequestHandlerError(RequestHandlerError<J, CS::Error>),
+    HeaderChain(ChainError<J>),
+    UnorderedJustifications(BlockIdFor<J>, BlockIdFor<J>),
     MissingJustification,
     BlockNotImportable,
     HeaderNotRequired,
+    OutsideServingWindow,
+    JustificationTooNew(BlockIdFor<J>),
+    UnfinalizedCheckpointRoot(BlockIdFor<J>),
+    Canceled,
 }
 
 impl<B, J, CS, V, F> Display for Error<B, J, CS, V, F>
@@ -291,6 +415,11 @@ where
             Forest(e) => write!(f, "forest error: {e}"),
             ForestInitialization(e) => write!(f, "forest initialization error: {e}"),
             RequestHandlerError(e) => write!(f, "request handler error: {e}"),
+            HeaderChain(e) => write!(f, "invalid header chain: {e}"),
+            UnorderedJustifications(previous, next) => write!(
+                f,
+                "justification of block {next:?} follows the one of block {previous:?}, they should be strictly increasing"
+            ),
             MissingJustification => write!(
                 f,
                 "justification for the last block of a past session missing"
@@ -299,6 +428,18 @@ where
                 write!(f, "cannot import a block that we do not consider required")
             }
             HeaderNotRequired => write!(f, "header was not required, but it should have been"),
+            OutsideServingWindow => write!(f, "requester is too far behind to be served"),
+            JustificationTooNew(id) => {
+                write!(
+                    f,
+                    "justification of block {id:?} does not fit in the forest yet"
+                )
+            }
+            UnfinalizedCheckpointRoot(id) => write!(
+                f,
+                "forest checkpoint made on top of block {id:?}, which we do not consider finalized"
+            ),
+            Canceled => write!(f, "nobody waits for the result anymore"),
         }
     }
 }
@@ -342,7 +483,10 @@ where
     F: Finalizer<J>,
 {
     fn from(e: RequestHandlerError<J, CS::Error>) -> Self {
-        Error::RequestHandlerError(e)
+        match e {
+            RequestHandlerError::Canceled => Error::Canceled,
+            e => Error::RequestHandlerError(e),
+        }
     }
 }
 
@@ -370,10 +514,21 @@ where
     BI: BlockImport<B>,
 {
     /// New handler with the provided chain interfaces.
+    /// Requests from peers more than `serving_window` blocks behind us are refused.
+    /// At most `finalization_batch` blocks are finalized in a single database transaction, and
+    /// at most `max_response_blocks` blocks are served in a single response, if limited.
+    /// The peers that supplied data about finalized blocks are recorded in the provenance history.
+    /// The finalization hooks are called with every block finalized.
     pub fn new(
         database_io: DatabaseIO<B, J, CS, F, BI>,
         verifier: V,
         session_info: SessionBoundaryInfo,
+        serving_window: BlockNumber,
+        finalization_batch: BlockNumber,
+        max_response_blocks: Option<usize>,
+        provenance: ProvenanceHistory<I, BlockIdFor<J>>,
+        finalization_hooks: FinalizationHooks<J>,
+        metrics: Metrics,
     ) -> Result<Self, <Self as HandlerTypes>::Error> {
         let DatabaseIO {
             chain_status,
@@ -390,11 +545,78 @@ where
             session_info,
             block_importer,
             missed_import_data: MissedImportData::new(),
+            serving_window,
+            finalization_batch: max(finalization_batch, 1),
+            max_response_blocks,
+            provenance: ProvenanceTracker::new(provenance),
+            finalization_hooks,
+            finalization_retry: FinalizationRetry::new(),
+            chain_selection: Arc::new(LongestChain),
+            headers_only: false,
+            metrics,
             phantom: PhantomData,
         })
     }
 
-    fn try_finalize(&mut self) -> Result<(), <Self as HandlerTypes>::Error> {
+    /// Finalizes the blocks from `from` to `to`, all of which have to be finalizable, in a single
+    /// database transaction. Only the justification of the highest one is kept in the database,
+    /// the lower blocks get finalized as its ancestors.
+    ///
+    /// If the database fails with a transient error the justifications are kept, and finalizing
+    /// is retried after a backoff, otherwise they are dropped.
+    fn finalize_batch(
+        &mut self,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<(), <Self as HandlerTypes>::Error> {
+        let justification = match (from..=to)
+            .rev()
+            .find_map(|number| self.forest.finalizable_justification(&number))
+        {
+            Some(justification) => justification.clone(),
+            None => return Ok(()),
+        };
+        let start = Instant::now();
+        let result = self.finalizer.finalize(justification.clone());
+        if let Err(e) = &result {
+            if F::is_transient(e) {
+                self.finalization_retry.failed(Instant::now());
+                return result.map_err(Error::Finalizer);
+            }
+        }
+        self.finalization_retry.reset();
+        let mut finalized = Vec::new();
+        for number in from..=justification.header().id().number() {
+            if let Some(justification) = self.forest.try_finalize(&number) {
+                finalized.push(justification.header().clone());
+            }
+        }
+        result.map_err(Error::Finalizer)?;
+        self.metrics
+            .report_finalization_commit(start.elapsed(), finalized.len());
+        for header in finalized {
+            // Finalizing the top of the batch finalized all the blocks below it.
+            let id = FinalizedId::new(header.id());
+            let session = self.session_info.session_id_from_block_num(id.number());
+            self.provenance.finalized(id.id().clone());
+            self.finalization_hooks.notify(FinalizedBlock {
+                id,
+                session,
+                header,
+                justification: justification.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Finalizes the justified and imported blocks right above the top finalized one, in batches
+    /// of at most `finalization_batch` blocks, with the last blocks of sessions always ending a
+    /// batch, so that their justifications are kept. A batch that is not full waits for more
+    /// blocks, unless `force` is set or no higher justified block is known.
+    fn finalize_batches(&mut self, force: bool) -> Result<(), <Self as HandlerTypes>::Error> {
+        if !self.finalization_retry.ready(Instant::now()) {
+            return Ok(());
+        }
         let mut number = self
             .chain_status
             .top_finalized()
@@ -404,29 +626,66 @@ where
             .number()
             + 1;
         loop {
-            while let Some(justification) = self.forest.try_finalize(&number) {
-                self.finalizer
-                    .finalize(justification)
-                    .map_err(Error::Finalizer)?;
-                number += 1;
-            }
-            number = self
+            let session_end = self
                 .session_info
                 .last_block_of_session(self.session_info.session_id_from_block_num(number));
-            match self.forest.try_finalize(&number) {
-                Some(justification) => {
-                    self.finalizer
-                        .finalize(justification)
-                        .map_err(Error::Finalizer)?;
-                    number += 1;
-                }
-                None => {
-                    self.missed_import_data
-                        .try_sync(&self.chain_status, &mut self.forest)?;
-                    return Ok(());
+            let batch_end = min(
+                session_end,
+                number.saturating_add(self.finalization_batch - 1),
+            );
+            let mut next = number;
+            while next <= batch_end && self.forest.finalizable(&next) {
+                next += 1;
+            }
+            if next > number {
+                let top = next - 1;
+                let complete = force
+                    || top == batch_end
+                    || self.forest.highest_justified_number() <= top
+                    || self.forest.finalizable(&session_end);
+                if !complete {
+                    break;
                 }
-            };
+                self.finalize_batch(number, top)?;
+                number = next;
+                continue;
+            }
+            // Skip the justification gap, the last block of the session is always justified.
+            if !self.forest.finalizable(&session_end) {
+                break;
+            }
+            self.finalize_batch(session_end, session_end)?;
+            number = session_end + 1;
         }
+        self.missed_import_data
+            .try_sync(&self.chain_status, &mut self.forest)?;
+        self.metrics
+            .report_justification_overlay_depth(self.forest.justification_overlay().depth());
+        Ok(())
+    }
+
+    fn try_finalize(&mut self) -> Result<(), <Self as HandlerTypes>::Error> {
+        self.finalize_batches(false)
+    }
+
+    /// Finalizes all the finalizable blocks, including the ones waiting for their batches to fill.
+    pub fn finalize_pending(&mut self) -> Result<(), <Self as HandlerTypes>::Error> {
+        self.finalize_batches(true)
+    }
+
+    /// When finalizing should be tried again, if it failed with a transient error.
+    pub fn finalization_retry_at(&self) -> Option<Instant> {
+        self.finalization_retry.next_attempt()
+    }
+
+    /// Tries finalizing again, after it failed with a transient error.
+    pub fn retry_finalization(&mut self) -> Result<(), <Self as HandlerTypes>::Error> {
+        self.try_finalize()
+    }
+
+    /// The number of the highest justified block we know of.
+    pub fn highest_justified_number(&self) -> BlockNumber {
+        self.forest.highest_justified_number()
     }
 
     /// Inform the handler that a block has been imported.
@@ -434,30 +693,111 @@ where
         &mut self,
         header: J::Header,
     ) -> Result<(), <Self as HandlerTypes>::Error> {
-        if let Err(e) = self.forest.update_body(&header) {
-            if matches!(e, ForestError::TooNew) {
-                self.missed_import_data
-                    .update(header.id().number(), &self.chain_status)
-                    .map_err(Error::ChainStatus)?;
+        match self.forest.update_body(&header) {
+            Ok(()) => (),
+            // Imports extending the chain, in particular of blocks we authored ourselves, can get
+            // here before the ones of their ancestors.
+            Err(ForestError::ParentNotImported) => self.catch_up_branch(&header)?,
+            Err(e) => {
+                if matches!(e, ForestError::TooNew) {
+                    self.missed_import_data
+                        .update(header.id().number(), &self.chain_status)
+                        .map_err(Error::ChainStatus)?;
+                }
+                return Err(e.into());
             }
-            return Err(e.into());
         }
         self.try_finalize()
     }
 
+    /// Marks the whole branch leading to the imported block as imported in the forest, using the
+    /// chain status, so that we stop being interested in it right away instead of waiting for
+    /// the notifications about the ancestors.
+    fn catch_up_branch(&mut self, header: &J::Header) -> Result<(), <Self as HandlerTypes>::Error> {
+        let mut branch = vec![header.clone()];
+        let mut parent_id = header.parent_id();
+        while let Some(id) = parent_id {
+            if self.forest.skippable(&id) {
+                break;
+            }
+            let parent = match self
+                .chain_status
+                .status_of(id)
+                .map_err(Error::ChainStatus)?
+            {
+                BlockStatus::Justified(justification) => justification.header().clone(),
+                BlockStatus::Present(parent) => parent,
+                BlockStatus::Unknown => return Err(ForestError::ParentNotImported.into()),
+            };
+            parent_id = parent.parent_id();
+            branch.push(parent);
+        }
+        for header in branch.iter().rev() {
+            self.forest.update_body(header)?;
+        }
+        Ok(())
+    }
+
     /// Handle a request for potentially substantial amounts of data.
     ///
     /// Returns what action we should take in response to the request.
     /// We either do nothing, request new interesting block to us or send a response containing
     /// path of justifications, blocks and headers. We try to be as helpful as we can, sometimes
     /// including more information from what was requested, sometimes ignoring their requested id
-    /// if we know it makes sense.
+    /// if we know it makes sense. Assembling the response stops once the token is canceled.
     pub fn handle_request(
         &mut self,
         request: Request<J>,
+        cancel: &CancelToken,
+    ) -> Result<Action<B, J>, <Self as HandlerTypes>::Error> {
+        let top_finalized = self
+            .chain_status
+            .top_finalized()
+            .map_err(Error::ChainStatus)?
+            .header()
+            .id()
+            .number();
+        let requester_top_finalized = request.state().top_justification().id().number();
+        if requester_top_finalized.saturating_add(self.serving_window) < top_finalized {
+            return Err(Error::OutsideServingWindow);
+        }
+        self.handle_priority_request(request, cancel)
+    }
+
+    /// Handle a request from a peer entitled to priority service, serving it regardless of how
+    /// far behind the peer is.
+    pub fn handle_priority_request(
+        &mut self,
+        request: Request<J>,
+        cancel: &CancelToken,
     ) -> Result<Action<B, J>, <Self as HandlerTypes>::Error> {
-        let request_handler = RequestHandler::new(&self.chain_status, &self.session_info);
+        self.request_action(request, true, cancel)
+    }
+
+    /// Handle a request for the chain of justifications and headers leading to a block, without
+    /// the bodies. All nodes keep the headers and they are cheap to send, so these are served
+    /// regardless of how far behind the peer is.
+    pub fn handle_headers_request(
+        &mut self,
+        request: Request<J>,
+        cancel: &CancelToken,
+    ) -> Result<Action<B, J>, <Self as HandlerTypes>::Error> {
+        self.request_action(request, false, cancel)
+    }
 
+    fn request_action(
+        &mut self,
+        request: Request<J>,
+        bodies: bool,
+        cancel: &CancelToken,
+    ) -> Result<Action<B, J>, <Self as HandlerTypes>::Error> {
+        let request_handler = RequestHandler::new(&self.chain_status, &self.session_info)
+            .with_max_blocks(self.max_response_blocks)
+            .with_cancellation(cancel.clone());
+        let request_handler = match bodies {
+            true => request_handler,
+            false => request_handler.without_bodies(),
+        };
         Ok(match request_handler.action(request)? {
             Action::RequestBlock(id)
                 if !self.forest.update_block_identifier(&id, None, true)? =>
@@ -474,19 +814,34 @@ where
         &mut self,
         justification: J::Unverified,
         maybe_peer: Option<I>,
-    ) -> Result<Option<BlockIdFor<J>>, <Self as HandlerTypes>::Error> {
+    ) -> Result<Option<CandidateId<BlockIdFor<J>>>, <Self as HandlerTypes>::Error> {
         let justification = self
             .verifier
             .verify(justification)
             .map_err(Error::Verifier)?;
+        self.insert_justification(justification, maybe_peer)
+    }
+
+    /// Puts an already verified justification into the forest and finalizes what we can.
+    /// Return `Some(id)` if this justification was higher than the previously known highest justification.
+    fn insert_justification(
+        &mut self,
+        justification: J,
+        maybe_peer: Option<I>,
+    ) -> Result<Option<CandidateId<BlockIdFor<J>>>, <Self as HandlerTypes>::Error> {
         let id = justification.header().id();
         let maybe_id = match self
             .forest
-            .update_justification(justification, maybe_peer)?
+            .update_justification(justification, maybe_peer.clone())
         {
-            true => Some(id),
-            false => None,
+            Ok(true) => Some(CandidateId::new(id.clone())),
+            Ok(false) => None,
+            Err(ForestError::TooNew) => return Err(Error::JustificationTooNew(id)),
+            Err(e) => return Err(e.into()),
         };
+        if let Some(peer) = maybe_peer {
+            self.provenance.justification_supplied(id, peer);
+        }
         self.try_finalize()?;
         Ok(maybe_id)
     }
@@ -496,10 +851,21 @@ where
     pub fn handle_justification_from_user(
         &mut self,
         justification: J::Unverified,
-    ) -> Result<Option<BlockIdFor<J>>, <Self as HandlerTypes>::Error> {
+    ) -> Result<Option<CandidateId<BlockIdFor<J>>>, <Self as HandlerTypes>::Error> {
         self.handle_justification(justification, None)
     }
 
+    /// Verifies the justification of an already finalized block that was asked for explicitly,
+    /// without putting it into the forest.
+    pub fn verify_finalized(
+        &mut self,
+        justification: J::Unverified,
+    ) -> Result<J, <Self as HandlerTypes>::Error> {
+        self.verifier
+            .verify_finalized(justification)
+            .map_err(Error::Verifier)
+    }
+
     /// Handle a state response returning the id of the new highest justified block
     /// if there is some, and possibly an error.
     ///
@@ -511,8 +877,16 @@ where
         justification: J::Unverified,
         maybe_justification: Option<J::Unverified>,
         peer: I,
-    ) -> (Option<BlockIdFor<J>>, Option<<Self as HandlerTypes>::Error>) {
+    ) -> (
+        Option<CandidateId<BlockIdFor<J>>>,
+        Option<<Self as HandlerTypes>::Error>,
+    ) {
         let mut maybe_id = None;
+        if let Err((previous, next)) = verify_justification_order::<J>(
+            iter::once(&justification).chain(maybe_justification.as_ref()),
+        ) {
+            return (None, Some(Error::UnorderedJustifications(previous, next)));
+        }
 
         for justification in iter::once(justification).chain(maybe_justification) {
             maybe_id = match self.handle_justification(justification, Some(peer.clone())) {
@@ -524,6 +898,122 @@ where
         (maybe_id, None)
     }
 
+    /// Handle a batched state response returning the ids of all the newly justified blocks in
+    /// order, and possibly an error.
+    ///
+    /// Just like with state responses, if an error is returned the batch might have been
+    /// processed partially.
+    pub fn handle_batched_state_response(
+        &mut self,
+        justifications: Vec<J::Unverified>,
+        peer: I,
+    ) -> (
+        Vec<CandidateId<BlockIdFor<J>>>,
+        Option<<Self as HandlerTypes>::Error>,
+    ) {
+        if let Err((previous, next)) = verify_justification_order::<J>(&justifications) {
+            return (
+                Vec::new(),
+                Some(Error::UnorderedJustifications(previous, next)),
+            );
+        }
+        let mut ids = Vec::new();
+        for justification in justifications {
+            match self.handle_justification(justification, Some(peer.clone())) {
+                Ok(Some(id)) => ids.push(id),
+                Ok(None) => {}
+                Err(e) => return (ids, Some(e)),
+            }
+        }
+        (ids, None)
+    }
+
+    /// Handle a warp response, verifying the justifications closing consecutive sessions as a
+    /// chain, so that the authorities of every session are learned from the previous one.
+    /// Returns the id of the highest verified justification, and possibly an error.
+    ///
+    /// All the verified justifications are passed to the forest, until the first one too far
+    /// ahead for it to hold. A verification error takes precedence over that.
+    pub fn handle_warp_response(
+        &mut self,
+        justifications: Vec<J::Unverified>,
+        peer: I,
+    ) -> (
+        Option<CandidateId<BlockIdFor<J>>>,
+        Option<<Self as HandlerTypes>::Error>,
+    ) {
+        if let Err((previous, next)) = verify_justification_order::<J>(&justifications) {
+            return (None, Some(Error::UnorderedJustifications(previous, next)));
+        }
+        let (verified, maybe_error) = self.verifier.verify_session_chain(justifications);
+        let highest_verified = verified
+            .last()
+            .map(|justification| CandidateId::new(justification.header().id()));
+        let mut insertion_error = None;
+        for justification in verified {
+            if let Err(e) = self.insert_justification(justification, Some(peer.clone())) {
+                insertion_error = Some(e);
+                break;
+            }
+        }
+        (
+            highest_verified,
+            maybe_error.map(Error::Verifier).or(insertion_error),
+        )
+    }
+
+    /// Handle a warp request, returning the justifications of the blocks ending the sessions from
+    /// `from` on, as far as we finalized them, at most `MAX_WARP_JUSTIFICATIONS`.
+    pub fn handle_warp_request(
+        &self,
+        from: SessionId,
+    ) -> Result<Vec<J::Unverified>, <Self as HandlerTypes>::Error> {
+        let top_number = self
+            .chain_status
+            .top_finalized()
+            .map_err(Error::ChainStatus)?
+            .header()
+            .id()
+            .number();
+        let top_session = self.session_info.session_id_from_block_num(top_number);
+        let mut session = from;
+        let mut justifications = Vec::new();
+        while session < top_session && justifications.len() < MAX_WARP_JUSTIFICATIONS {
+            justifications.push(self.last_justification_unverified(session)?);
+            session = session.next();
+        }
+        Ok(justifications)
+    }
+
+    /// The expensive parts of verifying the justifications in the response, to run elsewhere
+    /// before handling it, with the positions of the justifications they are for.
+    pub fn offload_verification(
+        &mut self,
+        response_items: &ResponseItems<B, J>,
+    ) -> Vec<(usize, OffloadedCheck)> {
+        response_items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| match item {
+                ResponseItem::Justification(justification) => self
+                    .verifier
+                    .offload(justification)
+                    .map(|check| (index, check)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Records that the justifications at the positions in the response passed their offloaded
+    /// checks, so that handling the response does not repeat them.
+    pub fn passed_checks(&mut self, response_items: &ResponseItems<B, J>, passed: &[usize]) {
+        for index in passed {
+            if let Some(ResponseItem::Justification(justification)) = response_items.get(*index) {
+                self.verifier.passed_check(justification);
+            }
+        }
+    }
+
     /// Handle a request response returning the id of the new highest justified block
     /// if there is some, and possibly an error.
     ///
@@ -533,14 +1023,66 @@ where
     ///
     /// Note that this method does not verify nor import blocks. The received blocks
     /// are stored in a buffer, and might be silently discarded in the future
-    /// if the import fails.
+    /// if the import fails. Consecutive headers are checked to form a chain, and the
+    /// justifications to be strictly increasing, before anything is processed, the whole
+    /// response is dropped if they are not. Once the token is canceled the remaining items are
+    /// neither verified nor passed for import.
     pub fn handle_request_response(
         &mut self,
         response_items: ResponseItems<B, J>,
         peer: I,
-    ) -> (Option<BlockIdFor<J>>, Option<<Self as HandlerTypes>::Error>) {
+        cancel: &CancelToken,
+    ) -> (
+        Option<CandidateId<BlockIdFor<J>>>,
+        Option<<Self as HandlerTypes>::Error>,
+    ) {
+        let mut imported = 0;
+        let (highest_justified, mut error) =
+            self.import_response_items(response_items, peer, cancel, &mut imported);
+        self.metrics.report_blocks_imported_from_response(imported);
+        // Catching up is attributed to the session we were finalizing at the time.
+        if let Ok(top_finalized) = self.chain_status.top_finalized() {
+            let session = self
+                .session_info
+                .session_id_from_block_num(top_finalized.header().id().number());
+            self.metrics.report_catch_up_blocks(imported, session);
+        }
+        if self.headers_only {
+            // The headers are all there is to import, and might have connected justified blocks.
+            if let Err(e) = self.try_finalize() {
+                error = error.or(Some(e));
+            }
+        }
+        (highest_justified, error)
+    }
+
+    /// Handles the items in order, counting the blocks passed for import, until the first error.
+    fn import_response_items(
+        &mut self,
+        response_items: ResponseItems<B, J>,
+        peer: I,
+        cancel: &CancelToken,
+        imported: &mut usize,
+    ) -> (
+        Option<CandidateId<BlockIdFor<J>>>,
+        Option<<Self as HandlerTypes>::Error>,
+    ) {
+        if let Err(e) = verify_header_chains(&response_items) {
+            return (None, Some(Error::HeaderChain(e)));
+        }
+        if let Err((previous, next)) =
+            verify_justification_order::<J>(response_items.iter().filter_map(|item| match item {
+                ResponseItem::Justification(justification) => Some(justification),
+                _ => None,
+            }))
+        {
+            return (None, Some(Error::UnorderedJustifications(previous, next)));
+        }
         let mut highest_justified = None;
         for item in response_items {
+            if cancel.is_canceled() {
+                return (highest_justified, Some(Error::Canceled));
+            }
             match item {
                 ResponseItem::Justification(j) => {
                     match self.handle_justification(j, Some(peer.clone())) {
@@ -565,7 +1107,11 @@ where
                         continue;
                     }
                     match self.forest.importable(&b.header().id()) {
-                        true => self.block_importer.import_block(b),
+                        true => {
+                            self.provenance.body_supplied(b.header().id(), peer.clone());
+                            self.block_importer.import_block(b);
+                            *imported += 1;
+                        }
                         false => return (highest_justified, Some(Error::BlockNotImportable)),
                     };
                 }
@@ -622,19 +1168,50 @@ where
                     None,
                 )),
             },
-            // remote lags one session behind
-            Some(1) => Ok(HandleStateAction::response(
-                self.last_justification_unverified(remote_session)?,
-                Some(local_top.into_unverified()),
-            )),
-            // remote lags multiple sessions behind
-            Some(2..) => Ok(HandleStateAction::response(
-                self.last_justification_unverified(remote_session)?,
-                Some(self.last_justification_unverified(SessionId(remote_session.0 + 1))?),
+            // remote lags some sessions behind, we can send a batch of justifications
+            Some(_) => Ok(HandleStateAction::batched_response(
+                self.catch_up_justifications(remote_top_number, local_top)?,
             )),
         }
     }
 
+    /// The justifications closing consecutive sessions, starting from the one of the remote top
+    /// finalized block, followed by our top one if there is still room. Justifications the
+    /// remote already has are skipped, and the batch ends before the ones too far ahead for the
+    /// remote to hold.
+    fn catch_up_justifications(
+        &self,
+        remote_top_number: BlockNumber,
+        local_top: J,
+    ) -> Result<Vec<J::Unverified>, <Self as HandlerTypes>::Error> {
+        let horizon = remote_top_number.saturating_add(MAX_DEPTH);
+        let local_top_number = local_top.header().id().number();
+        let local_session = self
+            .session_info
+            .session_id_from_block_num(local_top_number);
+        let mut session = self
+            .session_info
+            .session_id_from_block_num(remote_top_number);
+        let mut justifications = Vec::new();
+        while session.0 < local_session.0 && justifications.len() < MAX_BATCHED_JUSTIFICATIONS {
+            let last_block = self.session_info.last_block_of_session(session);
+            if last_block > horizon {
+                return Ok(justifications);
+            }
+            if last_block > remote_top_number {
+                justifications.push(self.last_justification_unverified(session)?);
+            }
+            session = SessionId(session.0 + 1);
+        }
+        if session == local_session
+            && justifications.len() < MAX_BATCHED_JUSTIFICATIONS
+            && local_top_number <= horizon
+        {
+            justifications.push(local_top.into_unverified());
+        }
+        Ok(justifications)
+    }
+
     /// The current state of our database.
     pub fn state(&self) -> Result<State<J>, <Self as HandlerTypes>::Error> {
         let top_justification = self
@@ -645,75 +1222,382 @@ where
         Ok(State::new(top_justification))
     }
 
-    /// A handle for requesting Interest.
-    pub fn interest_provider(&self) -> InterestProvider<I, J> {
-        InterestProvider {
-            forest: &self.forest,
+    /// Replaces the default strategy of extending the best block.
+    pub fn with_chain_selection(
+        mut self,
+        chain_selection: Arc<dyn ChainSelectionStrategy<J::Header>>,
+    ) -> Self {
+        self.chain_selection = chain_selection;
+        self
+    }
+
+    /// Follows only the headers, treating the blocks as imported once their headers connect to
+    /// the top finalized block, so that they get finalized without the bodies.
+    pub fn with_headers_only(mut self) -> Self {
+        self.forest = self.forest.headers_only();
+        self.headers_only = true;
+        self
+    }
+
+    /// Replaces how far below our top finalized block requests are served.
+    pub fn set_serving_window(&mut self, serving_window: BlockNumber) {
+        self.serving_window = serving_window;
+    }
+
+    /// The block we are currently building on, for peers to learn about our fork, as chosen by
+    /// the chain selection strategy.
+    pub fn favourite_block(&self) -> Result<J::Header, <Self as HandlerTypes>::Error> {
+        let best = self.chain_status.best_block().map_err(Error::ChainStatus)?;
+        let top_finalized = self
+            .chain_status
+            .top_finalized()
+            .map_err(Error::ChainStatus)?
+            .header()
+            .clone();
+        let highest_justified = self
+            .forest
+            .justification_overlay()
+            .highest_imported()
+            .map(|justification| justification.header().clone());
+        let favourite = self.chain_selection.favourite(ChainCandidates {
+            best: best.clone(),
+            highest_justified,
+            top_finalized,
+        });
+        if favourite.id() == best.id() {
+            return Ok(best);
+        }
+        match self
+            .chain_status
+            .status_of(favourite.id())
+            .map_err(Error::ChainStatus)?
+        {
+            BlockStatus::Unknown => Ok(best),
+            _ => Ok(favourite),
         }
     }
 
-    /// Handle an internal block request.
-    /// Returns `true` if this was the first time something indicated interest in this block.
-    pub fn handle_internal_request(
+    /// Handle the favourite block of a peer, either from its extended state or announced by it,
+    /// marking it as interesting in the forest. Returns whether we should request it, because it
+    /// is on a fork we did not know about.
+    pub fn handle_favourite_block(
         &mut self,
-        id: &BlockIdFor<J>,
+        header: J::Header,
+        peer: I,
     ) -> Result<bool, <Self as HandlerTypes>::Error> {
-        let should_request = self.forest.update_block_identifier(id, None, true)?;
-
-        Ok(should_request)
+        let id = header.id();
+        if self.forest.skippable(&id)
+            || !self.forest.can_hold(&id)
+            || !self.chain_selection.wants_fork(&header)
+        {
+            return Ok(false);
+        }
+        self.forest
+            .update_header(&header, Some(peer), true)
+            .map_err(Error::Forest)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::collections::HashSet;
 
-    use super::{DatabaseIO, HandleStateAction, HandleStateAction::*, Handler};
-    use crate::{
-        session::{SessionBoundaryInfo, SessionId},
-        sync::{
-            data::{BranchKnowledge::*, NetworkData, Request, ResponseItem, ResponseItems, State},
-            forest::Interest,
-            handler::Action,
-            mock::{Backend, MockBlock, MockHeader, MockIdentifier, MockJustification, MockPeerId},
-            Block, BlockImport, ChainStatus,
-            ChainStatusNotification::*,
-            ChainStatusNotifier, Header, Justification,
-        },
-        BlockIdentifier, BlockNumber, SessionPeriod,
-    };
+    /// Forgets what the peer told us it knows, as it disconnected.
+    pub fn peer_disconnected(&mut self, peer: &I) {
+        self.forest.forget_peer(peer);
+    }
 
-    type TestHandler =
-        Handler<MockBlock, MockPeerId, MockJustification, Backend, Backend, Backend, Backend>;
-    type MockResponseItems = ResponseItems<MockBlock, MockJustification>;
+    /// How many blocks have known headers, but still wait for their bodies.
+    pub fn pending_bodies(&self) -> usize {
+        self.forest.pending_bodies()
+    }
 
-    const SESSION_BOUNDARY_INFO: SessionBoundaryInfo = SessionBoundaryInfo::new(SessionPeriod(20));
+    /// How many tips of the trees in the forest there are.
+    pub fn forks(&self) -> usize {
+        self.forest.forks()
+    }
 
-    fn setup() -> (
-        TestHandler,
-        Backend,
-        impl ChainStatusNotifier<MockHeader>,
-        MockIdentifier,
-    ) {
-        let (backend, notifier) = Backend::setup(SESSION_BOUNDARY_INFO);
-        let verifier = backend.clone();
-        let database_io = DatabaseIO::new(backend.clone(), backend.clone(), backend.clone());
-        let handler =
-            Handler::new(database_io, verifier, SESSION_BOUNDARY_INFO).expect("mock backend works");
-        let genesis = backend.top_finalized().expect("genesis").header().id();
-        (handler, backend, notifier, genesis)
+    /// The part of the forest that is not in the database, to be restored after a restart.
+    pub fn forest_checkpoint(&self) -> ForestCheckpoint<J> {
+        self.forest.checkpoint()
     }
 
-    fn import_branch(backend: &mut Backend, branch_length: usize) -> Vec<MockHeader> {
-        let result: Vec<_> = backend
-            .top_finalized()
-            .expect("mock backend works")
-            .header()
-            .random_branch()
-            .take(branch_length)
-            .collect();
-        for header in &result {
-            backend.import_block(MockBlock::new(header.clone(), true));
+    /// Restores the forest from a checkpoint made by a previous run. The checkpoint is rejected
+    /// if it was made on top of a block we do not consider finalized, e.g. on another fork or
+    /// with a database that got replaced since. Justifications are verified again, and whatever
+    /// no longer fits the forest or fails verification is skipped.
+    pub fn restore_forest(
+        &mut self,
+        checkpoint: ForestCheckpoint<J>,
+    ) -> Result<RestoredForest<BlockIdFor<J>>, <Self as HandlerTypes>::Error> {
+        let root = checkpoint.root;
+        let root_finalized = match self
+            .chain_status
+            .finalized_at(root.number())
+            .map_err(Error::ChainStatus)?
+        {
+            FinalizationStatus::FinalizedWithJustification(justification) => {
+                justification.header().id() == root
+            }
+            FinalizationStatus::FinalizedByDescendant(header) => header.id() == root,
+            FinalizationStatus::NotFinalized => false,
+        };
+        if !root_finalized {
+            return Err(Error::UnfinalizedCheckpointRoot(root));
+        }
+        let mut restored = RestoredForest::default();
+        for vertex in checkpoint.vertices {
+            let outcome = match vertex {
+                VertexCheckpoint::Justification(justification) => self
+                    .handle_justification(justification, None)
+                    .map(|maybe_id| {
+                        if maybe_id.is_some() {
+                            restored.highest_justified = maybe_id;
+                        }
+                        None
+                    }),
+                VertexCheckpoint::Wanted(ref id) | VertexCheckpoint::Header { ref id, .. } => {
+                    let id = id.clone();
+                    self.forest
+                        .restore_vertex(vertex)
+                        .map(|required| required.then_some(id))
+                        .map_err(Error::Forest)
+                }
+            };
+            match outcome {
+                Ok(maybe_wanted) => {
+                    restored.vertices += 1;
+                    restored.wanted.extend(maybe_wanted);
+                }
+                Err(_) => restored.skipped += 1,
+            }
+        }
+        Ok(restored)
+    }
+
+    /// A handle for requesting Interest.
+    pub fn interest_provider(&self) -> InterestProvider<I, J> {
+        InterestProvider {
+            forest: &self.forest,
+        }
+    }
+
+    /// The identifier of the finalized block with the given number, if it is finalized.
+    fn finalized_id(
+        &self,
+        number: BlockNumber,
+    ) -> Result<Option<FinalizedId<BlockIdFor<J>>>, <Self as HandlerTypes>::Error> {
+        use FinalizationStatus::*;
+        Ok(
+            match self
+                .chain_status
+                .finalized_at(number)
+                .map_err(Error::ChainStatus)?
+            {
+                FinalizedWithJustification(justification) => {
+                    Some(FinalizedId::new(justification.header().id()))
+                }
+                FinalizedByDescendant(header) => Some(FinalizedId::new(header.id())),
+                NotFinalized => None,
+            },
+        )
+    }
+
+    /// The identifier of the finalized block with the given number, if its body is missing from
+    /// the database.
+    pub fn missing_body(
+        &self,
+        number: BlockNumber,
+    ) -> Result<Option<FinalizedId<BlockIdFor<J>>>, <Self as HandlerTypes>::Error> {
+        let id = match self.finalized_id(number)? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        match self
+            .chain_status
+            .block(id.id().clone())
+            .map_err(Error::ChainStatus)?
+        {
+            Some(_) => Ok(None),
+            None => Ok(Some(id)),
+        }
+    }
+
+    /// Handle a request for the bodies of finalized blocks, returning the ones we have, at most
+    /// `MAX_BODY_REQUEST_BLOCKS` from the bottom of the range. Missing bodies are skipped.
+    pub fn handle_body_request(
+        &self,
+        request: BodyRequest,
+    ) -> Result<ResponseItems<B, J>, <Self as HandlerTypes>::Error> {
+        let last = request
+            .to()
+            .min(request.from().saturating_add(MAX_BODY_REQUEST_BLOCKS - 1));
+        let mut response_items = Vec::new();
+        for number in request.from()..=last {
+            let id = match self.finalized_id(number)? {
+                Some(id) => id.into_inner(),
+                None => break,
+            };
+            if let Some(block) = self.chain_status.block(id).map_err(Error::ChainStatus)? {
+                response_items.push(ResponseItem::Block(block));
+            }
+        }
+        Ok(response_items)
+    }
+
+    /// The state to put in a request for the body of an old finalized block, pretending we only
+    /// know the justification right before its session, so that the response reaches down to it.
+    pub fn backfill_state(
+        &self,
+        id: &BlockIdFor<J>,
+    ) -> Result<State<J>, <Self as HandlerTypes>::Error> {
+        use Error::*;
+        let session = self.session_info.session_id_from_block_num(id.number());
+        let top_justification = match session.0.checked_sub(1) {
+            Some(previous) => self.last_justification_unverified(SessionId(previous))?,
+            None => self
+                .chain_status
+                .finalized_at(0)
+                .map_err(ChainStatus)?
+                .has_justification()
+                .ok_or(MissingJustification)?
+                .into_unverified(),
+        };
+        Ok(State::new(top_justification))
+    }
+
+    /// The peers that supplied the body of the block through sync, empty if it came from
+    /// elsewhere.
+    pub fn bodies_from(&self, id: &BlockIdFor<J>) -> Vec<I> {
+        self.provenance.bodies_from(id)
+    }
+
+    /// Imports a block supplied by the peer without going through the forest. Either the body of
+    /// an already finalized block, which was missing from the database, or a block from the range
+    /// download, which the forest learns about once it gets imported.
+    pub fn import_block_from(&mut self, block: B, peer: I) {
+        self.provenance.body_supplied(block.header().id(), peer);
+        self.block_importer.import_block(block)
+    }
+
+    /// Imports the missing body of an already finalized block fetched from an external source,
+    /// so no peer gets credited with supplying it.
+    pub fn import_fetched_block(&mut self, block: B) {
+        self.block_importer.import_block(block)
+    }
+
+    /// A snapshot of the forest, for debugging.
+    pub fn forest_dump(&self) -> ForestDump<I, BlockIdFor<J>> {
+        self.forest.dump()
+    }
+
+    /// Removes at most `budget` of the stale forest vertices, returns whether any are left.
+    pub fn prune_forest(&mut self, budget: usize) -> bool {
+        self.forest.prune_pending(budget)
+    }
+
+    /// Whether there are stale forest vertices waiting to be removed.
+    pub fn forest_pruning_pending(&self) -> bool {
+        self.forest.pruning_pending()
+    }
+
+    /// The number of the highest blocks the handler can accept data about.
+    pub fn highest_holdable(&self) -> BlockNumber {
+        self.forest.highest_holdable()
+    }
+
+    /// Whether the block is low enough for the handler to accept data about it.
+    pub fn can_hold(&self, id: &BlockIdFor<J>) -> bool {
+        self.forest.can_hold(id)
+    }
+
+    /// Handle an internal block request.
+    /// Returns `true` if this was the first time something indicated interest in this block.
+    pub fn handle_internal_request(
+        &mut self,
+        id: &BlockIdFor<J>,
+    ) -> Result<bool, <Self as HandlerTypes>::Error> {
+        let should_request = self.forest.update_block_identifier(id, None, true)?;
+
+        Ok(should_request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, iter, thread, time::Instant};
+
+    use futures::FutureExt;
+
+    use super::{
+        verify_header_chains, DatabaseIO, Error, HandleStateAction, HandleStateAction::*, Handler,
+    };
+    use crate::{
+        network::CancelToken,
+        session::{testing::session_boundary_info, SessionId},
+        sync::{
+            data::{
+                BodyRequest, BranchKnowledge::*, NetworkData, Request, ResponseItem, ResponseItems,
+                State, MAX_BATCHED_JUSTIFICATIONS, MAX_BODY_REQUEST_BLOCKS,
+            },
+            finalization_hook::{FinalizationHook, FinalizationHooks, FinalizedBlock},
+            forest::Interest,
+            handler::Action,
+            metrics::Metrics,
+            mock::{Backend, MockBlock, MockHeader, MockIdentifier, MockJustification, MockPeerId},
+            provenance::{Provenance, ProvenanceHistory},
+            Block, BlockImport, ChainStatus,
+            ChainStatusNotification::*,
+            ChainStatusNotifier, Header, Justification, VirtualFinality,
+        },
+        BlockIdentifier, BlockNumber,
+    };
+
+    type TestHandler =
+        Handler<MockBlock, MockPeerId, MockJustification, Backend, Backend, Backend, Backend>;
+    type MockResponseItems = ResponseItems<MockBlock, MockJustification>;
+
+    fn setup() -> (
+        TestHandler,
+        Backend,
+        impl ChainStatusNotifier<MockHeader>,
+        MockIdentifier,
+    ) {
+        setup_with_serving_window(BlockNumber::MAX)
+    }
+
+    fn setup_with_serving_window(
+        serving_window: BlockNumber,
+    ) -> (
+        TestHandler,
+        Backend,
+        impl ChainStatusNotifier<MockHeader>,
+        MockIdentifier,
+    ) {
+        let (backend, notifier) = Backend::setup(session_boundary_info());
+        let verifier = backend.clone();
+        let database_io = DatabaseIO::new(backend.clone(), backend.clone(), backend.clone());
+        let handler = Handler::new(
+            database_io,
+            verifier,
+            session_boundary_info(),
+            serving_window,
+            1,
+            None,
+            ProvenanceHistory::new(),
+            FinalizationHooks::new(),
+            Metrics::noop(),
+        )
+        .expect("mock backend works");
+        let genesis = backend.top_finalized().expect("genesis").header().id();
+        (handler, backend, notifier, genesis)
+    }
+
+    fn import_branch(backend: &mut Backend, branch_length: usize) -> Vec<MockHeader> {
+        let result: Vec<_> = backend
+            .top_finalized()
+            .expect("mock backend works")
+            .header()
+            .random_branch()
+            .take(branch_length)
+            .collect();
+        for header in &result {
+            backend.import_block(MockBlock::new(header.clone(), true));
         }
         result
     }
@@ -779,6 +1663,7 @@ mod tests {
                 .map(ResponseItem::Header)
                 .collect(),
             peer_id,
+            &CancelToken::new(),
         );
 
         assert!(
@@ -918,15 +1803,123 @@ mod tests {
                 justifications: true,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(response.clone(), 7);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(response.clone(), 7, &CancelToken::new());
         assert!(maybe_id.is_some());
         assert!(maybe_error.is_none());
         mark_branch_imported(&mut handler, &mut notifier, &branch).await;
-        let (maybe_id, maybe_error) = handler.handle_request_response(response, 8);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(response, 8, &CancelToken::new());
         assert!(maybe_id.is_none());
         assert!(maybe_error.is_none());
     }
 
+    #[test]
+    fn stops_handling_canceled_response() {
+        let (mut handler, _backend, _notifier, genesis) = setup();
+        let branch = grow_light_branch(&mut handler, &genesis, 15, 4);
+        let response = branch_response(
+            branch,
+            BranchResponseContent {
+                headers: false,
+                blocks: true,
+                justifications: true,
+            },
+        );
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let (maybe_id, maybe_error) = handler.handle_request_response(response, 7, &cancel);
+        assert!(maybe_id.is_none());
+        assert!(matches!(maybe_error, Some(Error::Canceled)));
+    }
+
+    #[test]
+    fn offloads_checks_of_response_justifications() {
+        let (mut handler, _backend, _notifier, genesis) = setup();
+        let branch = grow_light_branch(&mut handler, &genesis, 15, 4);
+        let mut response = branch_response(
+            branch.clone(),
+            BranchResponseContent {
+                headers: false,
+                blocks: true,
+                justifications: true,
+            },
+        );
+        response.push(ResponseItem::Justification(
+            MockJustification::invalid_for_header(branch[0].clone()),
+        ));
+        let (positions, results): (Vec<_>, Vec<_>) = handler
+            .offload_verification(&response)
+            .into_iter()
+            .map(|(position, check)| (position, check()))
+            .unzip();
+        let expected_positions: Vec<_> = (branch.len()..=2 * branch.len()).collect();
+        assert_eq!(positions, expected_positions);
+        assert!(results[..branch.len()].iter().all(|passed| *passed));
+        assert!(!results[branch.len()]);
+    }
+
+    #[test]
+    fn rejects_response_with_broken_header_chain() {
+        let (mut handler, _backend, _notifier, genesis) = setup();
+        let branch = grow_light_branch(&mut handler, &genesis, 15, 4);
+        let mut response = branch_response(
+            branch,
+            BranchResponseContent {
+                headers: true,
+                blocks: false,
+                justifications: false,
+            },
+        );
+        response.swap(3, 7);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(response, 7, &CancelToken::new());
+        assert!(maybe_id.is_none());
+        assert!(matches!(maybe_error, Some(Error::HeaderChain(_))));
+    }
+
+    #[test]
+    fn counts_pending_bodies_of_known_headers() {
+        let (mut handler, _backend, _notifier, genesis) = setup();
+        assert_eq!(handler.pending_bodies(), 0);
+        grow_light_branch(&mut handler, &genesis, 15, 4);
+        assert_eq!(handler.pending_bodies(), 15);
+    }
+
+    #[test]
+    fn restores_forest_from_checkpoint() {
+        let (mut handler, _backend, _notifier, genesis) = setup();
+        let branch = grow_light_branch(&mut handler, &genesis, 15, 4);
+        let justified = branch[5].id();
+        handler
+            .handle_justification_from_user(MockJustification::for_header(branch[5].clone()))
+            .expect("should work");
+        let checkpoint = handler.forest_checkpoint();
+        assert_eq!(checkpoint.vertices.len(), 15);
+
+        let (mut restarted, _backend, _notifier, _) = setup();
+        let restored = restarted
+            .restore_forest(checkpoint.clone())
+            .expect("the root is finalized");
+        assert_eq!(restored.vertices, 15);
+        assert_eq!(restored.skipped, 0);
+        assert_eq!(restored.wanted, vec![branch[14].id()]);
+        assert_eq!(
+            restored.highest_justified,
+            Some(CandidateId::new(justified))
+        );
+        assert_eq!(restarted.pending_bodies(), 15);
+
+        let (mut elsewhere, _backend, _notifier, _) = setup();
+        let mut foreign = checkpoint;
+        foreign.root = genesis.random_child().id();
+        match elsewhere.restore_forest(foreign) {
+            Err(Error::UnfinalizedCheckpointRoot(id)) => assert_eq!(id.number(), 1),
+            other => panic!("expected an unfinalized root error, got {other:?}"),
+        }
+        assert_eq!(elsewhere.pending_bodies(), 0);
+    }
+
     #[tokio::test]
     async fn accepts_long_response_after_handling_short_one() {
         let (mut handler, _backend, mut notifier, genesis) = setup();
@@ -940,7 +1933,8 @@ mod tests {
                 justifications: false,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(short_response, 2);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(short_response, 2, &CancelToken::new());
         assert!(maybe_id.is_none());
         assert!(maybe_error.is_none());
         mark_branch_imported(&mut handler, &mut notifier, &branch[..15].to_vec()).await;
@@ -953,7 +1947,8 @@ mod tests {
                 justifications: false,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(mid_response, 3);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(mid_response, 3, &CancelToken::new());
         assert!(maybe_id.is_none());
         assert!(maybe_error.is_none());
         mark_branch_imported(&mut handler, &mut notifier, &branch[15..].to_vec()).await;
@@ -973,7 +1968,8 @@ mod tests {
                 justifications: true,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(short_response, 2);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(short_response, 2, &CancelToken::new());
         assert!(maybe_id.is_some());
         assert!(maybe_error.is_none());
         mark_branch_imported(&mut handler, &mut notifier, &branch[..15].to_vec()).await;
@@ -988,7 +1984,8 @@ mod tests {
                 justifications: false,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(mid_response, 3);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(mid_response, 3, &CancelToken::new());
         assert!(maybe_id.is_none());
         assert!(maybe_error.is_none());
         mark_branch_imported(&mut handler, &mut notifier, &branch[15..25].to_vec()).await;
@@ -1002,7 +1999,8 @@ mod tests {
                 justifications: false,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(long_response_blocks_only, 2);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(long_response_blocks_only, 2, &CancelToken::new());
         assert!(maybe_id.is_none());
         assert!(maybe_error.is_none());
         mark_branch_imported(&mut handler, &mut notifier, &branch[25..].to_vec()).await;
@@ -1017,7 +2015,8 @@ mod tests {
                 justifications: true,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(full_response.clone(), 2);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(full_response.clone(), 2, &CancelToken::new());
         assert!(maybe_id.is_some());
         assert!(maybe_error.is_none());
         consume_branch_finalized_notifications(&mut notifier, &branch[15..].to_vec()).await;
@@ -1029,7 +2028,7 @@ mod tests {
         let mut bottom = genesis;
         let peer_id = 0;
         for session in 0.. {
-            let top = SESSION_BOUNDARY_INFO.last_block_of_session(SessionId(session));
+            let top = session_boundary_info().last_block_of_session(SessionId(session));
             let branch = grow_light_branch_till(&mut handler, &bottom, &top, peer_id);
             bottom = branch.last().expect("should not be empty").id();
             // import blocks
@@ -1041,7 +2040,8 @@ mod tests {
                     justifications: false,
                 },
             );
-            let (maybe_id, maybe_error) = handler.handle_request_response(response_items, peer_id);
+            let (maybe_id, maybe_error) =
+                handler.handle_request_response(response_items, peer_id, &CancelToken::new());
             assert!(maybe_id.is_none(), "should not import justification");
             assert!(maybe_error.is_none(), "should work");
             mark_branch_imported(&mut handler, &mut notifier, &branch).await;
@@ -1058,7 +2058,8 @@ mod tests {
                     justifications: true,
                 },
             );
-            let (maybe_id, maybe_error) = handler.handle_request_response(response_items, peer_id);
+            let (maybe_id, maybe_error) =
+                handler.handle_request_response(response_items, peer_id, &CancelToken::new());
             assert!(maybe_id.is_some(), "should import justification");
             assert!(maybe_error.is_none(), "should work");
             // get notification about finalized end-of-session block
@@ -1074,9 +2075,10 @@ mod tests {
     #[tokio::test]
     async fn skips_justification_gap_with_last_of_current_session_only() {
         let (mut handler, _backend, mut notifier, genesis) = setup();
-        let last_block_of_first_session = SESSION_BOUNDARY_INFO.last_block_of_session(SessionId(0));
+        let last_block_of_first_session =
+            session_boundary_info().last_block_of_session(SessionId(0));
         let last_block_of_second_session =
-            SESSION_BOUNDARY_INFO.last_block_of_session(SessionId(1));
+            session_boundary_info().last_block_of_session(SessionId(1));
         let peer_id = 0;
         let branch_low = grow_light_branch_till(
             &mut handler,
@@ -1104,7 +2106,8 @@ mod tests {
                 justifications: false,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(response_items, peer_id);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(response_items, peer_id, &CancelToken::new());
         assert!(maybe_id.is_none(), "should not import justification");
         assert!(maybe_error.is_none(), "should work");
 
@@ -1127,10 +2130,11 @@ mod tests {
                 justifications: true,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(all_but_two, peer_id);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(all_but_two, peer_id, &CancelToken::new());
         let highest = branch_high.last().expect("should not be empty").id();
         assert_eq!(
-            Some(highest.clone()),
+            Some(CandidateId::new(highest.clone())),
             maybe_id,
             "should import justifications"
         );
@@ -1191,10 +2195,11 @@ mod tests {
                 justifications: true,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(response, 7);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(response, 7, &CancelToken::new());
         assert_eq!(
             maybe_id,
-            Some(top_main),
+            Some(CandidateId::new(top_main)),
             "should create new highest justified"
         );
         assert!(maybe_error.is_none(), "should work");
@@ -1242,10 +2247,11 @@ mod tests {
                 justifications: true,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(response, 7);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(response, 7, &CancelToken::new());
         assert_eq!(
             maybe_id,
-            Some(top_main),
+            Some(CandidateId::new(top_main)),
             "should create new highest justified"
         );
         assert!(maybe_error.is_none(), "should work");
@@ -1279,7 +2285,8 @@ mod tests {
                 justifications: false,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(response, 12);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(response, 12, &CancelToken::new());
         assert!(
             maybe_id.is_none(),
             "should not create new highest justified"
@@ -1322,10 +2329,11 @@ mod tests {
                 justifications: true,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(response, 7);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(response, 7, &CancelToken::new());
         assert_eq!(
             maybe_id,
-            Some(top_main),
+            Some(CandidateId::new(top_main)),
             "should create new highest justified"
         );
         assert!(maybe_error.is_none(), "should work");
@@ -1378,7 +2386,8 @@ mod tests {
                 justifications: false,
             },
         );
-        let (maybe_id, maybe_error) = handler.handle_request_response(response, 12);
+        let (maybe_id, maybe_error) =
+            handler.handle_request_response(response, 12, &CancelToken::new());
         assert!(
             maybe_id.is_none(),
             "should not create new highest justified"
@@ -1403,6 +2412,11 @@ mod tests {
         loop {
             // syncing peer broadcasts the state
             let state = syncing_handler.state().expect("should work");
+            let top_session = session_boundary_info()
+                .session_id_from_block_num(state.top_justification().id().number());
+            // the peer refuses requests further than that
+            let upper_limit =
+                session_boundary_info().last_block_of_session(SessionId(top_session.0 + 1));
 
             // peer responds
             let response = match handler
@@ -1413,47 +2427,69 @@ mod tests {
                 HighestJustified(_) => panic!("should not request anything from the syncing peer"),
                 Noop => break,
             };
-            let (justification, maybe_justification) = match response {
+            // syncing peer processes the response
+            let (justifications, maybe_error) = match response {
                 NetworkData::StateBroadcastResponse(justification, maybe_justification) => {
-                    (justification, maybe_justification)
+                    let justifications: Vec<_> = iter::once(justification.clone())
+                        .chain(maybe_justification.clone())
+                        .collect();
+                    let (_, maybe_error) = syncing_handler.handle_state_response(
+                        justification,
+                        maybe_justification,
+                        peer_id,
+                    );
+                    (justifications, maybe_error)
+                }
+                NetworkData::BatchedStateBroadcastResponse(justification, justifications) => {
+                    let justifications: Vec<_> =
+                        iter::once(justification).chain(justifications).collect();
+                    let (_, maybe_error) = syncing_handler
+                        .handle_batched_state_response(justifications.clone(), peer_id);
+                    (justifications, maybe_error)
                 }
                 _ => panic!("should create state broadcast response"),
             };
-
-            // syncing peer processes the response and sends a request
-            let mut target_id = justification.header().id();
-            if let Some(justification) = &maybe_justification {
-                target_id = justification.header().id();
-            }
-            let (maybe_id, maybe_error) =
-                syncing_handler.handle_state_response(justification, maybe_justification, peer_id);
             assert!(maybe_error.is_none(), "should work");
-            match maybe_id {
-                Some(id) => assert_eq!(id, target_id, "should start requesting target_id"),
-                None => panic!("should request"),
-            };
+
+            // syncing peer sends a request for the highest block the peer will serve
+            let target_id = justifications
+                .iter()
+                .map(|justification| justification.header().id())
+                .filter(|id| id.number() <= upper_limit)
+                .last()
+                .expect("the first justification is always within the limit");
             let branch_knowledge = match syncing_handler.interest_provider().get(&target_id) {
                 Interest::HighestJustified {
                     know_most,
                     branch_knowledge,
+                }
+                | Interest::Required {
+                    know_most,
+                    branch_knowledge,
                 } => {
                     assert!(know_most.contains(&peer_id), "should come from the peer");
                     branch_knowledge
                 }
-                _ => panic!("should be highly interested"),
+                _ => panic!("should be interested"),
             };
             let state = syncing_handler.state().expect("should work");
             let request = Request::new(target_id.clone(), branch_knowledge, state);
 
             // peer responds
-            let response_items = match handler.handle_request(request).expect("should work") {
+            let response_items = match handler
+                .handle_request(request, &CancelToken::new())
+                .expect("should work")
+            {
                 Action::Response(items) => items,
                 _ => panic!("should prepare response"),
             };
 
             // syncing peer processes the response
-            let (maybe_id, maybe_error) =
-                syncing_handler.handle_request_response(response_items.clone(), peer_id);
+            let (maybe_id, maybe_error) = syncing_handler.handle_request_response(
+                response_items.clone(),
+                peer_id,
+                &CancelToken::new(),
+            );
             assert!(maybe_error.is_none(), "should work");
             assert!(maybe_id.is_none(), "should already know about target_id");
 
@@ -1483,6 +2519,36 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn serves_bodies_of_finalized_blocks_by_number() {
+        let (mut handler, mut backend, mut notifier, genesis) = setup();
+        grow_trunk(&mut handler, &mut backend, &mut notifier, &genesis, 100).await;
+        let numbers = |request| -> Vec<BlockNumber> {
+            handler
+                .handle_body_request(request)
+                .expect("should work")
+                .into_iter()
+                .map(|item| match item {
+                    ResponseItem::Block(block) => block.header().id().number(),
+                    _ => panic!("should respond only with blocks"),
+                })
+                .collect()
+        };
+        assert_eq!(
+            numbers(BodyRequest::new(3, 20)),
+            (3..=20).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            numbers(BodyRequest::new(90, 200)),
+            (90..=100).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            numbers(BodyRequest::new(20, BlockNumber::MAX)),
+            (20..20 + MAX_BODY_REQUEST_BLOCKS).collect::<Vec<_>>()
+        );
+        assert!(numbers(BodyRequest::new(5, 4)).is_empty());
+    }
+
     #[test]
     fn finalizes_imported_and_justified() {
         let (mut handler, mut backend, _keep, _genesis) = setup();
@@ -1496,7 +2562,7 @@ mod tests {
             handler
                 .handle_justification(justification.clone().into_unverified(), Some(peer))
                 .expect("correct justification"),
-            Some(justification.id())
+            Some(CandidateId::new(justification.id()))
         );
         assert_eq!(
             backend.top_finalized().expect("mock backend works"),
@@ -1504,6 +2570,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn records_provenance_of_finalized_blocks() {
+        let (mut backend, _keep) = Backend::setup(session_boundary_info());
+        let verifier = backend.clone();
+        let database_io = DatabaseIO::new(backend.clone(), backend.clone(), backend.clone());
+        let provenance = ProvenanceHistory::new();
+        let mut handler: TestHandler = Handler::new(
+            database_io,
+            verifier,
+            session_boundary_info(),
+            BlockNumber::MAX,
+            1,
+            None,
+            provenance.clone(),
+            FinalizationHooks::new(),
+            Metrics::noop(),
+        )
+        .expect("mock backend works");
+        let header = import_branch(&mut backend, 1)[0].clone();
+        handler
+            .block_imported(header.clone())
+            .expect("importing in order");
+        let justification = MockJustification::for_header(header);
+        let peer = rand::random();
+        handler
+            .handle_justification(justification.clone().into_unverified(), Some(peer))
+            .expect("correct justification");
+        assert_eq!(
+            provenance.of(&justification.id()),
+            Some(Provenance {
+                block: justification.id(),
+                justification_from: Some(peer),
+                bodies_from: Vec::new(),
+            })
+        );
+    }
+
     #[test]
     fn requests_missing_justifications_without_blocks() {
         let (mut handler, mut backend, _keep, _genesis) = setup();
@@ -1519,11 +2622,63 @@ mod tests {
                 handler
                     .handle_justification(justification.clone().into_unverified(), Some(peer))
                     .expect("correct justification"),
-                Some(justification.id())
+                Some(CandidateId::new(justification.id()))
             );
         }
     }
 
+    #[test]
+    fn reports_too_new_justification() {
+        let (mut handler, backend, _keep, _genesis) = setup();
+        let too_new = backend
+            .top_finalized()
+            .expect("mock backend works")
+            .header()
+            .random_branch()
+            .find(|header| !handler.can_hold(&header.id()))
+            .expect("the branch is infinite");
+        let justification = MockJustification::for_header(too_new);
+        match handler.handle_justification_from_user(justification.clone().into_unverified()) {
+            Err(Error::JustificationTooNew(id)) => assert_eq!(id, justification.id()),
+            other => panic!("expected a too new justification error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn requests_unknown_favourite_blocks() {
+        let (mut handler, mut backend, _keep, genesis) = setup();
+        let peer: MockPeerId = rand::random();
+        let imported = import_branch(&mut backend, 1)[0].clone();
+        handler
+            .block_imported(imported.clone())
+            .expect("importing in order");
+        assert!(!handler
+            .handle_favourite_block(imported, peer)
+            .expect("should work"));
+        let fork_top = genesis
+            .random_branch()
+            .nth(3)
+            .expect("the branch is infinite");
+        assert!(handler
+            .handle_favourite_block(fork_top.clone(), peer)
+            .expect("should work"));
+        // Only requested once.
+        assert!(!handler
+            .handle_favourite_block(fork_top.clone(), peer)
+            .expect("should work"));
+        match handler.interest_provider().get(&fork_top.id()) {
+            Interest::Required { know_most, .. } => assert!(know_most.contains(&peer)),
+            other => panic!("expected the fork to be required, got {other:?}"),
+        }
+        let too_new = genesis
+            .random_branch()
+            .find(|header| !handler.can_hold(&header.id()))
+            .expect("the branch is infinite");
+        assert!(!handler
+            .handle_favourite_block(too_new, peer)
+            .expect("should work"));
+    }
+
     #[test]
     fn requests_missing_justifications_with_blocks() {
         let (mut handler, mut backend, _keep, _genesis) = setup();
@@ -1544,14 +2699,14 @@ mod tests {
                 handler
                     .handle_justification(justification.clone().into_unverified(), Some(peer))
                     .expect("correct justification"),
-                Some(justification.id())
+                Some(CandidateId::new(justification.id()))
             );
         }
     }
 
     #[test]
     fn initializes_forest_properly() {
-        let (mut backend, _keep) = Backend::setup(SESSION_BOUNDARY_INFO);
+        let (mut backend, _keep) = Backend::setup(session_boundary_info());
         let header = import_branch(&mut backend, 1)[0].clone();
         // header already imported, Handler should initialize Forest properly
         let verifier = backend.clone();
@@ -1559,7 +2714,13 @@ mod tests {
         let mut handler = Handler::new(
             database_io,
             verifier,
-            SessionBoundaryInfo::new(SessionPeriod(20)),
+            session_boundary_info(),
+            BlockNumber::MAX,
+            1,
+            None,
+            ProvenanceHistory::new(),
+            FinalizationHooks::new(),
+            Metrics::noop(),
         )
         .expect("mock backend works");
         let justification = MockJustification::for_header(header);
@@ -1568,7 +2729,7 @@ mod tests {
             handler
                 .handle_justification(justification.clone().into_unverified(), Some(peer))
                 .expect("correct justification"),
-            Some(justification.id())
+            Some(CandidateId::new(justification.id()))
         );
         // should be auto-finalized, if Forest knows about imported body
         assert_eq!(
@@ -1587,7 +2748,7 @@ mod tests {
             .handle_justification(justification.clone().into_unverified(), Some(peer))
             .expect("correct justification")
         {
-            Some(id) => assert_eq!(id, header.id()),
+            Some(id) => assert_eq!(id.into_inner(), header.id()),
             None => panic!("expected an id, got nothing"),
         }
         handler.block_imported(header).expect("importing in order");
@@ -1598,16 +2759,319 @@ mod tests {
     }
 
     #[test]
-    fn handles_state_with_large_difference() {
+    fn catches_up_on_branch_of_imported_block() {
         let (mut handler, mut backend, _keep, _genesis) = setup();
-        let initial_state = handler.state().expect("state works");
+        let branch = import_branch(&mut backend, 5);
+        let top = branch.last().expect("branch not empty").clone();
+        let justification = MockJustification::for_header(top.clone());
         let peer = rand::random();
-        let justifications: Vec<MockJustification> = import_branch(&mut backend, 43)
-            .into_iter()
-            .map(MockJustification::for_header)
+        handler
+            .handle_justification(justification.clone().into_unverified(), Some(peer))
+            .expect("correct justification");
+        assert!(
+            handler.interest_provider().get(&branch[0].id()) != Interest::Uninterested,
+            "should be interested in the branch"
+        );
+        // only the top import reaches the handler
+        handler
+            .block_imported(top)
+            .expect("should catch up on the branch");
+        for header in &branch {
+            assert_eq!(
+                handler.interest_provider().get(&header.id()),
+                Interest::Uninterested,
+                "should be uninterested"
+            );
+        }
+        assert_eq!(
+            backend.top_finalized().expect("mock backend works"),
+            justification
+        );
+    }
+
+    #[test]
+    fn retries_finalization_after_transient_errors() {
+        let (mut handler, mut backend, _keep, genesis) = setup();
+        let header = import_branch(&mut backend, 1)[0].clone();
+        let justification = MockJustification::for_header(header.clone());
+        handler
+            .handle_justification(justification.clone().into_unverified(), None)
+            .expect("correct justification");
+        backend.fail_finalizations(1);
+        assert!(matches!(
+            handler.block_imported(header),
+            Err(Error::Finalizer(_))
+        ));
+        assert_eq!(
+            backend
+                .top_finalized()
+                .expect("mock backend works")
+                .header()
+                .id(),
+            genesis
+        );
+        let retry_at = handler
+            .finalization_retry_at()
+            .expect("finalization should be retried");
+        // Nothing is attempted before the backoff passes.
+        handler.finalize_pending().expect("waits for the backoff");
+        thread::sleep(retry_at.saturating_duration_since(Instant::now()));
+        handler.retry_finalization().expect("finalization works");
+        assert_eq!(
+            backend.top_finalized().expect("mock backend works"),
+            justification
+        );
+        assert_eq!(handler.finalization_retry_at(), None);
+    }
+
+    #[test]
+    fn finalizes_in_batches() {
+        let (mut backend, _keep) = Backend::setup(session_boundary_info());
+        let verifier = backend.clone();
+        let database_io = DatabaseIO::new(backend.clone(), backend.clone(), backend.clone());
+        let mut handler: TestHandler = Handler::new(
+            database_io,
+            verifier,
+            session_boundary_info(),
+            BlockNumber::MAX,
+            8,
+            None,
+            ProvenanceHistory::new(),
+            FinalizationHooks::new(),
+            Metrics::noop(),
+        )
+        .expect("mock backend works");
+        let branch = import_branch(&mut backend, 25);
+        let peer = rand::random();
+        for header in &branch {
+            let justification = MockJustification::for_header(header.clone());
+            handler
+                .handle_justification(justification.into_unverified(), Some(peer))
+                .expect("correct justification");
+        }
+        let mut finalized = Vec::new();
+        for header in branch.iter().take(24) {
+            handler
+                .block_imported(header.clone())
+                .expect("importing in order");
+            let top = backend
+                .top_finalized()
+                .expect("mock backend works")
+                .header()
+                .id()
+                .number();
+            if finalized.last() != Some(&top) {
+                finalized.push(top);
+            }
+        }
+        // Full batches, then the end of the session.
+        assert_eq!(finalized, vec![0, 8, 16, 19]);
+        handler.finalize_pending().expect("mock backend works");
+        assert_eq!(
+            backend
+                .top_finalized()
+                .expect("mock backend works")
+                .header()
+                .id()
+                .number(),
+            24
+        );
+        handler
+            .block_imported(branch[24].clone())
+            .expect("importing in order");
+        // No higher justification is known, so the batch is not waiting for more blocks.
+        assert_eq!(
+            backend
+                .top_finalized()
+                .expect("mock backend works")
+                .header()
+                .id(),
+            branch[24].id()
+        );
+    }
+
+    struct RecordingHook(
+        futures::channel::mpsc::UnboundedSender<FinalizedBlock<MockJustification>>,
+    );
+
+    #[async_trait::async_trait]
+    impl FinalizationHook<MockJustification> for RecordingHook {
+        async fn finalized(&mut self, block: FinalizedBlock<MockJustification>) {
+            self.0.unbounded_send(block).expect("the test listens");
+        }
+    }
+
+    #[test]
+    fn calls_finalization_hooks_with_every_finalized_block() {
+        let (mut backend, _keep) = Backend::setup(session_boundary_info());
+        let verifier = backend.clone();
+        let database_io = DatabaseIO::new(backend.clone(), backend.clone(), backend.clone());
+        let hooks = FinalizationHooks::new();
+        let (finalized, mut received) = futures::channel::mpsc::unbounded();
+        let mut hook = Box::pin(hooks.register(RecordingHook(finalized), 64));
+        let mut handler: TestHandler = Handler::new(
+            database_io,
+            verifier,
+            session_boundary_info(),
+            BlockNumber::MAX,
+            8,
+            None,
+            ProvenanceHistory::new(),
+            hooks,
+            Metrics::noop(),
+        )
+        .expect("mock backend works");
+        let branch = import_branch(&mut backend, 10);
+        let peer = rand::random();
+        for header in &branch {
+            let justification = MockJustification::for_header(header.clone());
+            handler
+                .handle_justification(justification.into_unverified(), Some(peer))
+                .expect("correct justification");
+        }
+        for header in &branch {
+            handler
+                .block_imported(header.clone())
+                .expect("importing in order");
+        }
+        // Runs the hook on everything queued.
+        assert!(hook.as_mut().now_or_never().is_none());
+        let mut blocks = Vec::new();
+        while let Ok(Some(block)) = received.try_next() {
+            blocks.push(block);
+        }
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|block| block.id.id().clone())
+                .collect::<Vec<_>>(),
+            branch.iter().map(|header| header.id()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|block| block.header.clone())
+                .collect::<Vec<_>>(),
+            branch
+        );
+        // Finalized in two batches, with the justifications of their highest blocks.
+        for block in &blocks {
+            let top = match block.id.number() {
+                1..=8 => &branch[7],
+                _ => &branch[9],
+            };
+            assert_eq!(block.justification.header().id(), top.id());
+            assert_eq!(block.session, SessionId(0));
+        }
+    }
+
+    #[test]
+    fn finalizes_headers_without_bodies() {
+        let (backend, _keep) = Backend::setup(session_boundary_info());
+        let finality = VirtualFinality::headers_only(session_boundary_info());
+        let verifier = backend.clone();
+        let database_io = DatabaseIO::new(
+            finality.chain_status(backend.clone()),
+            finality.finalizer(backend.clone(), backend.clone()),
+            backend.clone(),
+        );
+        let hooks = FinalizationHooks::new();
+        let (finalized, mut received) = futures::channel::mpsc::unbounded();
+        let mut hook = Box::pin(hooks.register(RecordingHook(finalized), 8));
+        let mut handler = Handler::new(
+            database_io,
+            verifier,
+            session_boundary_info(),
+            BlockNumber::MAX,
+            1,
+            None,
+            ProvenanceHistory::new(),
+            hooks,
+            Metrics::noop(),
+        )
+        .expect("mock backend works")
+        .with_headers_only();
+        let genesis = backend
+            .top_finalized()
+            .expect("mock backend works")
+            .header()
+            .clone();
+        let branch: Vec<_> = genesis.random_branch().take(19).collect();
+        let peer: MockPeerId = rand::random();
+        handler
+            .handle_justification(
+                MockJustification::for_header(branch[18].clone()).into_unverified(),
+                Some(peer),
+            )
+            .expect("correct justification");
+        let response = branch[..18]
+            .iter()
+            .cloned()
+            .rev()
+            .map(ResponseItem::Header)
+            .collect();
+        let (_, maybe_error) = handler.handle_request_response(response, peer, &CancelToken::new());
+        assert!(maybe_error.is_none(), "should work, got {maybe_error:?}");
+        assert_eq!(
+            handler
+                .state()
+                .expect("state works")
+                .top_justification()
+                .id(),
+            branch[18].id()
+        );
+        // Only finalized virtually, the database knows nothing about the blocks.
+        assert_eq!(
+            backend
+                .top_finalized()
+                .expect("mock backend works")
+                .header()
+                .id()
+                .number(),
+            0
+        );
+        assert!(hook.as_mut().now_or_never().is_none());
+        let block = received
+            .try_next()
+            .expect("the hook got the block")
+            .expect("the hook is running");
+        assert_eq!(block.header, branch[18]);
+    }
+
+    #[test]
+    fn rejects_invalid_justifications_in_state() {
+        let (mut handler, mut backend, _keep, genesis) = setup();
+        let header = import_branch(&mut backend, 1)[0].clone();
+        handler
+            .block_imported(header.clone())
+            .expect("importing in order");
+        let state = State::new(MockJustification::invalid_for_header(header));
+        assert!(matches!(
+            handler.handle_state(state, rand::random()),
+            Err(Error::Verifier(_))
+        ));
+        assert_eq!(
+            backend
+                .top_finalized()
+                .expect("mock backend works")
+                .header()
+                .id(),
+            genesis
+        );
+    }
+
+    #[test]
+    fn handles_state_with_large_difference() {
+        let (mut handler, mut backend, _keep, _genesis) = setup();
+        let initial_state = handler.state().expect("state works");
+        let peer = rand::random();
+        let justifications: Vec<MockJustification> = import_branch(&mut backend, 43)
+            .into_iter()
+            .map(MockJustification::for_header)
             .collect();
         let last_from_first_session = justifications[18].clone().into_unverified();
         let last_from_second_session = justifications[38].clone().into_unverified();
+        let top = justifications[42].clone().into_unverified();
         for justification in justifications.into_iter() {
             handler
                 .block_imported(justification.header().clone())
@@ -1620,12 +3084,12 @@ mod tests {
             .handle_state(initial_state, peer)
             .expect("correct justification")
         {
-            HandleStateAction::Response(NetworkData::StateBroadcastResponse(
+            HandleStateAction::Response(NetworkData::BatchedStateBroadcastResponse(
                 justification,
-                maybe_justification,
+                justifications,
             )) => {
                 assert_eq!(justification, last_from_first_session);
-                assert_eq!(maybe_justification, Some(last_from_second_session));
+                assert_eq!(justifications, vec![last_from_second_session, top]);
             }
             other_action => panic!("expected a response with justifications, got {other_action:?}"),
         }
@@ -1654,17 +3118,178 @@ mod tests {
             .handle_state(initial_state, peer)
             .expect("correct justification")
         {
-            HandleStateAction::Response(NetworkData::StateBroadcastResponse(
+            HandleStateAction::Response(NetworkData::BatchedStateBroadcastResponse(
                 justification,
-                maybe_justification,
+                justifications,
             )) => {
                 assert_eq!(justification, last_from_first_session);
-                assert_eq!(maybe_justification, Some(top));
+                assert_eq!(justifications, vec![top]);
             }
             other_action => panic!("expected a response with justifications, got {other_action:?}"),
         }
     }
 
+    #[test]
+    fn skips_justifications_the_remote_has_in_batches() {
+        let (mut handler, mut backend, _keep, _genesis) = setup();
+        let peer = rand::random();
+        let justifications: Vec<MockJustification> = import_branch(&mut backend, 403)
+            .into_iter()
+            .map(MockJustification::for_header)
+            .collect();
+        for justification in justifications.iter() {
+            handler
+                .block_imported(justification.header().clone())
+                .expect("importing in order");
+            handler
+                .handle_justification(justification.clone().into_unverified(), Some(peer))
+                .expect("correct justification");
+        }
+        // The remote finalized the first session already.
+        let remote_state = State::new(justifications[18].clone().into_unverified());
+        let expected: Vec<_> = (1..=MAX_BATCHED_JUSTIFICATIONS)
+            .map(|session| justifications[session * 20 + 18].clone().into_unverified())
+            .collect();
+        match handler
+            .handle_state(remote_state, peer)
+            .expect("correct justification")
+        {
+            HandleStateAction::Response(NetworkData::BatchedStateBroadcastResponse(
+                justification,
+                justifications,
+            )) => {
+                assert_eq!(
+                    iter::once(justification)
+                        .chain(justifications)
+                        .collect::<Vec<_>>(),
+                    expected
+                );
+            }
+            other_action => panic!("expected a response with justifications, got {other_action:?}"),
+        }
+    }
+
+    #[test]
+    fn serves_warp_requests() {
+        let (mut handler, mut backend, _keep, _genesis) = setup();
+        let peer = rand::random();
+        let justifications: Vec<MockJustification> = import_branch(&mut backend, 103)
+            .into_iter()
+            .map(MockJustification::for_header)
+            .collect();
+        for justification in justifications.iter() {
+            handler
+                .block_imported(justification.header().clone())
+                .expect("importing in order");
+            handler
+                .handle_justification(justification.clone().into_unverified(), Some(peer))
+                .expect("correct justification");
+        }
+        let expected: Vec<_> = (1..5)
+            .map(|session| justifications[session * 20 + 18].clone().into_unverified())
+            .collect();
+        assert_eq!(
+            handler
+                .handle_warp_request(SessionId(1))
+                .expect("finalized blocks have justifications"),
+            expected
+        );
+        // The top session is not finished yet.
+        assert!(handler
+            .handle_warp_request(SessionId(5))
+            .expect("nothing to read")
+            .is_empty());
+    }
+
+    #[test]
+    fn handles_warp_responses() {
+        let (mut handler, mut backend, _keep, _genesis) = setup();
+        let peer = rand::random();
+        let headers = import_branch(&mut backend, 63);
+        for header in headers.iter() {
+            handler
+                .block_imported(header.clone())
+                .expect("importing in order");
+        }
+        let justification_of = |number: usize| {
+            MockJustification::for_header(headers[number - 1].clone()).into_unverified()
+        };
+        // The verifier does not know the authorities of the third session yet.
+        let (highest, maybe_error) =
+            handler.handle_warp_response(vec![justification_of(19), justification_of(59)], peer);
+        assert_eq!(highest, Some(CandidateId::new(headers[18].id())));
+        assert!(matches!(maybe_error, Some(Error::Verifier(_))));
+        let (highest, maybe_error) = handler.handle_warp_response(vec![justification_of(39)], peer);
+        assert_eq!(highest, Some(CandidateId::new(headers[38].id())));
+        assert!(maybe_error.is_none());
+        assert_eq!(
+            backend
+                .top_finalized()
+                .expect("mock backend works")
+                .header()
+                .id(),
+            headers[38].id()
+        );
+    }
+
+    #[test]
+    fn rejects_unordered_justifications() {
+        let (mut handler, mut backend, _keep, _genesis) = setup();
+        let peer = rand::random();
+        let headers = import_branch(&mut backend, 63);
+        for header in headers.iter() {
+            handler
+                .block_imported(header.clone())
+                .expect("importing in order");
+        }
+        let justification_of = |number: usize| {
+            MockJustification::for_header(headers[number - 1].clone()).into_unverified()
+        };
+        let (ids, maybe_error) = handler.handle_batched_state_response(
+            vec![
+                justification_of(19),
+                justification_of(39),
+                justification_of(39),
+            ],
+            peer,
+        );
+        assert!(ids.is_empty());
+        assert!(matches!(
+            maybe_error,
+            Some(Error::UnorderedJustifications(_, _))
+        ));
+        let (highest, maybe_error) =
+            handler.handle_warp_response(vec![justification_of(39), justification_of(19)], peer);
+        assert_eq!(highest, None);
+        assert!(matches!(
+            maybe_error,
+            Some(Error::UnorderedJustifications(_, _))
+        ));
+        let (maybe_id, maybe_error) = handler.handle_request_response(
+            vec![
+                ResponseItem::Justification(justification_of(5)),
+                ResponseItem::Justification(justification_of(3)),
+            ],
+            peer,
+            &CancelToken::new(),
+        );
+        assert_eq!(maybe_id, None);
+        assert!(matches!(
+            maybe_error,
+            Some(Error::UnorderedJustifications(_, _))
+        ));
+        // Nothing got finalized.
+        assert_eq!(
+            backend
+                .top_finalized()
+                .expect("mock backend works")
+                .header()
+                .id()
+                .number(),
+            0
+        );
+    }
+
     #[test]
     fn handles_state_with_small_difference() {
         let (mut handler, mut backend, _keep, _genesis) = setup();
@@ -1750,12 +3375,96 @@ mod tests {
         let requested_id = justifications.last().unwrap().header().id();
         let request = Request::new(requested_id.clone(), LowestId(requested_id), initial_state);
 
-        match handler.handle_request(request).expect("correct request") {
+        match handler
+            .handle_request(request, &CancelToken::new())
+            .expect("correct request")
+        {
             Action::Noop => {}
             other_action => panic!("expected a response with justifications, got {other_action:?}"),
         }
     }
 
+    #[test]
+    fn refuses_request_outside_serving_window() {
+        let (mut handler, mut backend, _keep, _genesis) = setup_with_serving_window(10);
+        let initial_state = handler.state().expect("state works");
+
+        let (justifications, _) = setup_request_tests(&mut handler, &mut backend, 100, 100);
+
+        let requested_id = justifications.last().unwrap().header().id();
+        let request = Request::new(requested_id.clone(), LowestId(requested_id), initial_state);
+
+        assert!(matches!(
+            handler.handle_request(request, &CancelToken::new()),
+            Err(Error::OutsideServingWindow)
+        ));
+    }
+
+    #[test]
+    fn serves_request_within_widened_serving_window() {
+        let (mut handler, mut backend, _keep, _genesis) = setup_with_serving_window(10);
+        let initial_state = handler.state().expect("state works");
+
+        let (justifications, _) = setup_request_tests(&mut handler, &mut backend, 100, 100);
+        handler.set_serving_window(1000);
+
+        let requested_id = justifications.last().unwrap().header().id();
+        let request = Request::new(requested_id.clone(), LowestId(requested_id), initial_state);
+
+        assert!(handler.handle_request(request, &CancelToken::new()).is_ok());
+    }
+
+    #[test]
+    fn serves_priority_request_outside_serving_window() {
+        let (mut handler, mut backend, _keep, _genesis) = setup_with_serving_window(10);
+        let initial_state = handler.state().expect("state works");
+
+        let (justifications, _) = setup_request_tests(&mut handler, &mut backend, 100, 100);
+
+        let requested_id = justifications.last().unwrap().header().id();
+        let request = Request::new(requested_id.clone(), LowestId(requested_id), initial_state);
+
+        assert!(handler
+            .handle_priority_request(request, &CancelToken::new())
+            .is_ok());
+    }
+
+    #[test]
+    fn serves_headers_request_outside_serving_window() {
+        use SimplifiedItem::*;
+        let (mut handler, mut backend, _keep, _genesis) = setup_with_serving_window(10);
+        let initial_state = handler.state().expect("state works");
+
+        let (_, blocks) = setup_request_tests(&mut handler, &mut backend, 100, 20);
+
+        let requested_id = blocks[30].clone().id();
+        let lowest_id = blocks[25].clone().id();
+
+        // request block #31, with the last known header equal to block #26
+        let request = Request::new(requested_id, LowestId(lowest_id), initial_state);
+
+        // the justification ending the session first, then only the missing headers, in a single
+        // descending chain passing through the justified block, earlier justifications are not
+        // needed to finalize the target
+        let expected_response_items: Vec<_> = iter::once(J(19))
+            .chain((20..=26).rev().map(H))
+            .chain((10..=18).rev().map(H))
+            .collect();
+        match handler
+            .handle_headers_request(request, &CancelToken::new())
+            .expect("correct request")
+        {
+            Action::Response(response_items) => {
+                assert!(verify_header_chains(&response_items).is_ok());
+                assert_eq!(
+                    SimplifiedItem::from_response_items(response_items),
+                    expected_response_items
+                )
+            }
+            other_action => panic!("expected a response with headers, got {other_action:?}"),
+        }
+    }
+
     #[derive(Debug, Eq, PartialEq)]
     enum SimplifiedItem {
         J(BlockNumber),
@@ -1849,7 +3558,10 @@ mod tests {
             B(30),
             B(31),
         ];
-        match handler.handle_request(request).expect("correct request") {
+        match handler
+            .handle_request(request, &CancelToken::new())
+            .expect("correct request")
+        {
             Action::Response(response_items) => {
                 assert_eq!(
                     SimplifiedItem::from_response_items(response_items),
@@ -1860,6 +3572,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn limits_blocks_in_response() {
+        use SimplifiedItem::*;
+        let (mut handler, mut backend, _keep, _genesis) = setup();
+        handler.max_response_blocks = Some(3);
+        let initial_state = handler.state().expect("state works");
+
+        let (_, blocks) = setup_request_tests(&mut handler, &mut backend, 100, 20);
+
+        let requested_id = blocks[30].clone().id();
+        let top_imported = blocks[25].clone().id();
+        let request = Request::new(requested_id, TopImported(top_imported), initial_state);
+
+        // the lowest blocks are served, the requester asks for the rest later
+        let expected_response_items = vec![J(19), B(27), B(28), B(29)];
+
+        match handler
+            .handle_request(request, &CancelToken::new())
+            .expect("correct request")
+        {
+            Action::Response(response_items) => {
+                assert_eq!(
+                    SimplifiedItem::from_response_items(response_items),
+                    expected_response_items
+                )
+            }
+            other_action => panic!("expected a response with blocks, got {other_action:?}"),
+        }
+    }
+
     #[test]
     fn handles_request_with_unknown_id() {
         let (mut handler, mut backend, _keep, _genesis) = setup();
@@ -1873,7 +3615,10 @@ mod tests {
 
         let request = Request::new(requested_id.clone(), LowestId(lowest_id), state);
 
-        match handler.handle_request(request).expect("correct request") {
+        match handler
+            .handle_request(request, &CancelToken::new())
+            .expect("correct request")
+        {
             Action::RequestBlock(id) => assert_eq!(id, requested_id),
             other_action => panic!("expected a response with justifications, got {other_action:?}"),
         }
@@ -1893,25 +3638,14 @@ mod tests {
         // request block #31, with the top imported block equal to block #26
         let request = Request::new(requested_id, TopImported(top_imported), initial_state);
 
-        let expected_response_items = vec![
-            J(1),
-            J(2),
-            J(3),
-            J(4),
-            J(5),
-            J(6),
-            J(7),
-            J(8),
-            J(9),
-            J(19),
-            B(27),
-            B(28),
-            B(29),
-            B(30),
-            B(31),
-        ];
+        // the requester has all the blocks up to #26 already, so it only needs the justification
+        // ending the session to finalize them
+        let expected_response_items = vec![J(19), B(27), B(28), B(29), B(30), B(31)];
 
-        match handler.handle_request(request).expect("correct request") {
+        match handler
+            .handle_request(request, &CancelToken::new())
+            .expect("correct request")
+        {
             Action::Response(response_items) => {
                 assert_eq!(
                     SimplifiedItem::from_response_items(response_items),
@@ -1922,6 +3656,85 @@ mod tests {
         }
     }
 
+    #[test]
+    fn sends_only_target_justification_when_all_imported() {
+        use SimplifiedItem::*;
+        let (mut handler, mut backend, _keep, _genesis) = setup();
+        let initial_state = handler.state().expect("state works");
+
+        let (_, blocks) = setup_request_tests(&mut handler, &mut backend, 100, 20);
+
+        // request the justified block #9, which the requester already imported
+        let requested_id = blocks[8].clone().id();
+        let request = Request::new(
+            requested_id.clone(),
+            TopImported(requested_id),
+            initial_state,
+        );
+
+        match handler
+            .handle_request(request, &CancelToken::new())
+            .expect("correct request")
+        {
+            Action::Response(response_items) => {
+                assert_eq!(
+                    SimplifiedItem::from_response_items(response_items),
+                    vec![J(9)]
+                )
+            }
+            other_action => panic!("expected a response with justifications, got {other_action:?}"),
+        }
+    }
+
+    #[test]
+    fn sends_requested_justification_not_ending_session() {
+        use SimplifiedItem::*;
+        let (mut handler, mut backend, _keep, _genesis) = setup();
+        let initial_state = handler.state().expect("state works");
+
+        let (_, blocks) = setup_request_tests(&mut handler, &mut backend, 100, 20);
+
+        // request the justification of block #5, which would be redundant on the way to any
+        // other target
+        let requested_id = blocks[4].clone().id();
+        let request = Request::new(
+            requested_id.clone(),
+            TopImported(requested_id),
+            initial_state,
+        );
+
+        match handler
+            .handle_headers_request(request, &CancelToken::new())
+            .expect("correct request")
+        {
+            Action::Response(response_items) => {
+                assert_eq!(
+                    SimplifiedItem::from_response_items(response_items),
+                    vec![J(5), J(9)]
+                )
+            }
+            other_action => panic!("expected a response with justifications, got {other_action:?}"),
+        }
+    }
+
+    #[test]
+    fn gives_up_on_canceled_request() {
+        let (mut handler, mut backend, _keep, _genesis) = setup();
+        let initial_state = handler.state().expect("state works");
+
+        let (_, blocks) = setup_request_tests(&mut handler, &mut backend, 100, 20);
+
+        let requested_id = blocks[30].clone().id();
+        let request = Request::new(requested_id.clone(), LowestId(requested_id), initial_state);
+        let cancel = CancelToken::new();
+        cancel.cancel();
+
+        assert!(matches!(
+            handler.handle_request(request, &cancel),
+            Err(Error::Canceled)
+        ));
+    }
+
     #[test]
     fn handles_new_internal_request() {
         let (mut handler, mut backend, _keep, _genesis) = setup();
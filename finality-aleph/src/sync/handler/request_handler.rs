@@ -4,6 +4,7 @@ use std::fmt::{Display, Formatter};
 use primitives::BlockNumber;
 
 use crate::{
+    network::CancelToken,
     session::{SessionBoundaryInfo, SessionId},
     sync::{
         data::{BranchKnowledge, ResponseItem},
@@ -20,6 +21,7 @@ pub enum RequestHandlerError<J: Justification, T: Display> {
     RootMismatch,
     LastBlockOfSessionNotJustified,
     ChainStatusError(T),
+    Canceled,
 }
 
 impl<J: Justification, T: Display> From<T> for RequestHandlerError<J, T> {
@@ -41,6 +43,7 @@ impl<J: Justification, T: Display> Display for RequestHandlerError<J, T> {
             RequestHandlerError::LastBlockOfSessionNotJustified => {
                 write!(f, "last block of finalized session not justified")
             }
+            RequestHandlerError::Canceled => write!(f, "the request was canceled"),
         }
     }
 }
@@ -101,6 +104,7 @@ where
     pre_chunk: PreChunk<B, J>,
     state: State,
     head: HeadOfChunk<J>,
+    bodies: bool,
 }
 
 impl<B, J> StepResult<B, J>
@@ -108,11 +112,12 @@ where
     B: Block,
     J: Justification<Header = B::Header>,
 {
-    fn new(head: HeadOfChunk<J>, state: State) -> Self {
+    fn new(head: HeadOfChunk<J>, state: State, bodies: bool) -> Self {
         Self {
             pre_chunk: PreChunk::new(&head),
             state,
             head,
+            bodies,
         }
     }
 
@@ -125,11 +130,21 @@ where
         chain_status: &CS,
     ) -> Result<bool, RequestHandlerError<J, CS::Error>> {
         match self.state {
-            State::EverythingButHeader => self.add_block(self.head.id(), chain_status)?,
-            State::Everything if self.head.is_justification() => {
+            State::EverythingButHeader if self.bodies => {
                 self.add_block(self.head.id(), chain_status)?
             }
-            State::Everything => self.add_block_and_header(self.head.id(), chain_status)?,
+            State::Everything if self.bodies && self.head.is_justification() => {
+                self.add_block(self.head.id(), chain_status)?
+            }
+            State::Everything if self.bodies => {
+                self.add_block_and_header(self.head.id(), chain_status)?
+            }
+            // The justification heading the chunk already contains the header.
+            State::Everything => {
+                if let HeadOfChunk::Header(header) = &self.head {
+                    self.pre_chunk.add_header(header.clone());
+                }
+            }
             _ => {}
         }
 
@@ -189,6 +204,11 @@ where
         self.state = State::OnlyJustification;
     }
 
+    /// The number of the justified block, if the justification is all the chunk contains.
+    pub fn lone_justification(&self) -> Option<BlockNumber> {
+        self.pre_chunk.lone_justification()
+    }
+
     pub fn finish(self) -> (Chunk<B, J>, State, HeadOfChunk<J>) {
         let chunk = self.pre_chunk.into_chunk();
 
@@ -278,6 +298,16 @@ where
         chunks
     }
 
+    fn lone_justification(&self) -> Option<BlockNumber> {
+        match (
+            &self.just,
+            self.blocks.is_empty() && self.headers.is_empty(),
+        ) {
+            (Some(j), true) => Some(j.header().id().number()),
+            _ => None,
+        }
+    }
+
     pub fn add_block(&mut self, b: B) {
         self.blocks.push(b);
     }
@@ -286,6 +316,10 @@ where
         self.headers.push(b.header().clone());
         self.blocks.push(b);
     }
+
+    pub fn add_header(&mut self, h: J::Header) {
+        self.headers.push(h);
+    }
 }
 
 pub struct RequestHandler<'a, B, J, CS>
@@ -296,6 +330,9 @@ where
 {
     chain_status: &'a CS,
     session_info: &'a SessionBoundaryInfo,
+    bodies: bool,
+    max_blocks: Option<usize>,
+    cancel: CancelToken,
     _phantom: PhantomData<(B, J)>,
 }
 
@@ -319,16 +356,63 @@ where
         Self {
             chain_status,
             session_info,
+            bodies: true,
+            max_blocks: None,
+            cancel: CancelToken::new(),
             _phantom: PhantomData,
         }
     }
 
+    /// Responds with at most this many blocks, the requester asks for the following ones later.
+    pub fn with_max_blocks(mut self, max_blocks: Option<usize>) -> Self {
+        self.max_blocks = max_blocks;
+        self
+    }
+
+    /// Responds with the justifications and headers only, for requesters fetching the chain of
+    /// headers before the bodies.
+    pub fn without_bodies(mut self) -> Self {
+        self.bodies = false;
+        self
+    }
+
+    /// Gives up on assembling the response as soon as the token is canceled.
+    pub fn with_cancellation(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    fn check_canceled(&self) -> HandlerResult<(), Self> {
+        match self.cancel.is_canceled() {
+            true => Err(RequestHandlerError::Canceled),
+            false => Ok(()),
+        }
+    }
+
     fn upper_limit(&self, id: BlockIdFor<J>) -> BlockNumber {
         let session = self.session_info.session_id_from_block_num(id.number());
         self.session_info
             .last_block_of_session(SessionId(session.0 + 1))
     }
 
+    fn ends_session(&self, number: BlockNumber) -> bool {
+        let session = self.session_info.session_id_from_block_num(number);
+        self.session_info.last_block_of_session(session) == number
+    }
+
+    /// Whether the chunk can be left out of the response. Below the top imported block of the
+    /// requester only justifications are sent, and out of those only the ones ending sessions are
+    /// needed to finalize the target, the following justifications finalize the rest. The
+    /// justification of the target itself is always sent, as it might have been asked for
+    /// explicitly.
+    fn is_redundant(&self, result: &StepResult<B, J>, first: bool, target: BlockNumber) -> bool {
+        !first
+            && matches!(
+                result.lone_justification(),
+                Some(number) if number != target && !self.ends_session(number)
+            )
+    }
+
     fn is_result_complete(
         &self,
         result: &mut StepResult<B, J>,
@@ -362,9 +446,13 @@ where
         if from.id() == *to {
             return Ok(None);
         }
-        let mut result = StepResult::new(from, state);
+        let mut result = StepResult::new(from, state, self.bodies);
 
-        while !self.is_result_complete(&mut result, branch_knowledge, to)? {}
+        // Every step reads a block from the database, which is what makes large responses
+        // expensive.
+        while !self.is_result_complete(&mut result, branch_knowledge, to)? {
+            self.check_canceled()?;
+        }
 
         Ok(Some(result))
     }
@@ -372,6 +460,7 @@ where
     fn response_items(
         self,
         mut head: HeadOfChunk<J>,
+        target: BlockNumber,
         branch_knowledge: BranchKnowledge<J>,
         to: BlockIdFor<J>,
     ) -> HandlerResult<Vec<ResponseItem<B, J>>, Self> {
@@ -379,16 +468,44 @@ where
         let mut state = State::EverythingButHeader;
 
         while let Some(result) = self.step(state, head, &to, &branch_knowledge)? {
+            self.check_canceled()?;
+            let redundant = self.is_redundant(&result, response_items.is_empty(), target);
             let (chunk, new_state, new_head) = result.finish();
 
             state = new_state;
             head = new_head;
-            response_items.push(chunk);
+            if !redundant {
+                response_items.push(chunk);
+            }
         }
 
-        response_items.reverse();
-
-        Ok(response_items.into_iter().flatten().collect())
+        // Blocks and justifications have to be applied in ascending order, while headers are
+        // easiest to check as one descending chain. Without blocks the justifications go first,
+        // the headers of their blocks keep the chain together.
+        let mut response_items: Vec<_> = match self.bodies {
+            true => response_items.into_iter().rev().flatten().collect(),
+            false => {
+                let (mut justifications, headers): (Vec<_>, Vec<_>) = response_items
+                    .into_iter()
+                    .flatten()
+                    .partition(|item| matches!(item, ResponseItem::Justification(_)));
+                justifications.reverse();
+                justifications.into_iter().chain(headers).collect()
+            }
+        };
+        // Cutting off the top keeps the response a valid, just shorter, one.
+        if let Some(max_blocks) = self.max_blocks {
+            let cut = response_items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| matches!(item, ResponseItem::Block(_)))
+                .nth(max_blocks)
+                .map(|(index, _)| index);
+            if let Some(cut) = cut {
+                response_items.truncate(cut);
+            }
+        }
+        Ok(response_items)
     }
 
     fn adjust_head(
@@ -434,6 +551,7 @@ where
 
         let response_items = self.response_items(
             head,
+            target.number(),
             request.branch_knowledge().clone(),
             top_justification.id(),
         )?;
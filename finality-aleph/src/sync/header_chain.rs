@@ -0,0 +1,224 @@
+use std::{
+    borrow::Borrow,
+    fmt::{Display, Error as FmtError, Formatter},
+    num::NonZeroUsize,
+    thread,
+};
+
+use crate::{
+    sync::{BlockIdFor, Header, Justification},
+    BlockIdentifier,
+};
+
+/// Chains shorter than this are verified on the calling thread, spawning workers would cost
+/// more than it saves.
+const PARALLEL_THRESHOLD: usize = 256;
+
+/// What can be wrong with a chain of headers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error<I: BlockIdentifier> {
+    /// The header does not contain a parent id at all.
+    MissingParent(I),
+    /// The parent of the first header is not the second one.
+    BrokenLink(I, I),
+    /// The header does not carry a seal.
+    Unsealed(I),
+}
+
+impl<I: BlockIdentifier> Display for Error<I> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use Error::*;
+        match self {
+            MissingParent(id) => write!(f, "header {id:?} has no parent"),
+            BrokenLink(child, parent) => {
+                write!(f, "header {child:?} is not a child of header {parent:?}")
+            }
+            Unsealed(id) => write!(f, "header {id:?} is not sealed"),
+        }
+    }
+}
+
+/// Checks a single link of a descending chain, i.e. whether `parent` is the parent of `child`.
+fn check_link<H: Header>(child: &H, parent: &H) -> Result<(), Error<H::Identifier>> {
+    let parent_id = child
+        .parent_id()
+        .ok_or_else(|| Error::MissingParent(child.id()))?;
+    match parent_id == parent.id() {
+        true => Ok(()),
+        false => Err(Error::BrokenLink(child.id(), parent.id())),
+    }
+}
+
+fn check_seal<H: Header>(header: &H) -> Result<(), Error<H::Identifier>> {
+    match header.is_sealed() {
+        true => Ok(()),
+        false => Err(Error::Unsealed(header.id())),
+    }
+}
+
+/// Verifies a contiguous part of the chain, including the link to the header directly following
+/// it, if there is one.
+fn verify_segment<H: Header, R: Borrow<H>>(
+    segment: &[R],
+    next: Option<&R>,
+) -> Result<(), Error<H::Identifier>> {
+    for header in segment {
+        check_seal(header.borrow())?;
+    }
+    for pair in segment.windows(2) {
+        check_link(pair[0].borrow(), pair[1].borrow())?;
+    }
+    match (segment.last(), next) {
+        (Some(last), Some(next)) => check_link(last.borrow(), next.borrow()),
+        _ => Ok(()),
+    }
+}
+
+/// How many threads are worth using for parallel work.
+pub fn workers() -> usize {
+    thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+}
+
+/// Verifies that the headers form a descending chain, i.e. every header is the parent of the one
+/// preceding it, and that all of them are sealed.
+///
+/// Long chains are split into segments verified concurrently, with every segment additionally
+/// checking the link to the first header of the following one, so no boundary is skipped.
+/// If there are multiple problems with the chain the reported one is the lowest-indexed one.
+pub fn verify_descending_chain<H: Header>(headers: &[H]) -> Result<(), Error<H::Identifier>> {
+    verify_chain::<H, H>(headers)
+}
+
+/// Like `verify_descending_chain`, for headers that are not owned, so that they do not have to
+/// be cloned just to be verified.
+pub fn verify_descending_chain_of_refs<H: Header>(
+    headers: &[&H],
+) -> Result<(), Error<H::Identifier>> {
+    verify_chain::<H, &H>(headers)
+}
+
+fn verify_chain<H: Header, R: Borrow<H> + Sync>(headers: &[R]) -> Result<(), Error<H::Identifier>> {
+    let workers = workers();
+    if headers.len() < PARALLEL_THRESHOLD || workers < 2 {
+        return verify_segment::<H, R>(headers, None);
+    }
+    let segment_length = (headers.len() + workers - 1) / workers;
+    thread::scope(|scope| {
+        let handles: Vec<_> = headers
+            .chunks(segment_length)
+            .enumerate()
+            .map(|(index, segment)| {
+                let next = headers.get((index + 1) * segment_length);
+                scope.spawn(move || verify_segment::<H, R>(segment, next))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("header verification does not panic"))
+            .collect()
+    })
+}
+
+/// Convenience alias for errors about chains of headers that justifications refer to.
+pub type ChainError<J> = Error<BlockIdFor<J>>;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        verify_descending_chain, verify_descending_chain_of_refs, Error, PARALLEL_THRESHOLD,
+    };
+    use crate::sync::{
+        mock::{MockHeader, MockIdentifier},
+        Header,
+    };
+
+    fn descending_branch(length: usize) -> Vec<MockHeader> {
+        let mut branch: Vec<_> = MockIdentifier::new_random(0)
+            .random_branch()
+            .take(length)
+            .collect();
+        branch.reverse();
+        branch
+    }
+
+    #[test]
+    fn accepts_empty_chain() {
+        assert_eq!(verify_descending_chain::<MockHeader>(&[]), Ok(()));
+    }
+
+    #[test]
+    fn accepts_short_chain() {
+        assert_eq!(verify_descending_chain(&descending_branch(10)), Ok(()));
+    }
+
+    #[test]
+    fn accepts_long_chain() {
+        let branch = descending_branch(5 * PARALLEL_THRESHOLD + 7);
+        assert_eq!(verify_descending_chain(&branch), Ok(()));
+    }
+
+    #[test]
+    fn verifies_borrowed_headers() {
+        let mut branch = descending_branch(2 * PARALLEL_THRESHOLD);
+        let headers: Vec<_> = branch.iter().collect();
+        assert_eq!(verify_descending_chain_of_refs(&headers), Ok(()));
+        branch.swap(0, 1);
+        let headers: Vec<_> = branch.iter().collect();
+        assert!(matches!(
+            verify_descending_chain_of_refs(&headers),
+            Err(Error::BrokenLink(..))
+        ));
+    }
+
+    #[test]
+    fn rejects_ascending_chain() {
+        let mut branch = descending_branch(10);
+        branch.reverse();
+        assert!(matches!(
+            verify_descending_chain(&branch),
+            Err(Error::BrokenLink(..))
+        ));
+    }
+
+    #[test]
+    fn rejects_parentless_header() {
+        let mut branch = descending_branch(10);
+        let parentless = MockHeader::random_parentless(branch[5].id().number());
+        branch[5] = parentless.clone();
+        assert_eq!(
+            verify_descending_chain(&branch),
+            Err(Error::BrokenLink(branch[4].id(), parentless.id()))
+        );
+    }
+
+    #[test]
+    fn finds_broken_link_at_every_position_of_long_chain() {
+        let length = 3 * PARALLEL_THRESHOLD;
+        let branch = descending_branch(length);
+        for broken in (1..length).step_by(PARALLEL_THRESHOLD / 4) {
+            let mut chain = branch.clone();
+            chain[broken] =
+                MockHeader::random_parentless(chain[broken].id().number()).random_child();
+            assert!(
+                matches!(verify_descending_chain(&chain), Err(Error::BrokenLink(..))),
+                "should detect broken link at {broken}"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_lowest_index_error() {
+        let length = 3 * PARALLEL_THRESHOLD;
+        let mut branch = descending_branch(length);
+        let early = MockHeader::random_parentless(branch[3].id().number()).random_child();
+        let late = MockHeader::random_parentless(branch[length - 3].id().number()).random_child();
+        branch[3] = early.clone();
+        branch[length - 3] = late;
+        assert_eq!(
+            verify_descending_chain(&branch),
+            Err(Error::BrokenLink(branch[2].id(), early.id()))
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+
+use crate::{sync::PeerId, BlockIdentifier};
+
+/// How many notifications a subscriber can fall behind by before it starts missing them.
+const DEFAULT_IMPORT_BUFFER: usize = 256;
+
+/// A block with a body supplied by peers through sync, that has just been imported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportedBlock<I: PeerId, BI: BlockIdentifier> {
+    pub block: BI,
+    /// The peers that supplied the body, at least one.
+    pub bodies_from: Vec<I>,
+}
+
+/// What a subscriber gets from the import notifications.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImportEvent<I: PeerId, BI: BlockIdentifier> {
+    Imported(ImportedBlock<I, BI>),
+    /// The subscriber was too slow and this many notifications were dropped. The following ones
+    /// are delivered normally.
+    Missed(u64),
+}
+
+/// Fans the notifications about blocks imported through sync out to any number of subscribers.
+/// Every subscriber has its own bounded buffer, so a slow one only loses its own notifications
+/// and never holds sync back. Can be cloned and subscribed to while sync is running.
+#[derive(Clone)]
+pub struct ImportNotifications<I: PeerId, BI: BlockIdentifier> {
+    sender: broadcast::Sender<ImportedBlock<I, BI>>,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> ImportNotifications<I, BI> {
+    /// Subscribers can fall behind by `buffer` notifications before they miss any.
+    pub fn new(buffer: usize) -> Self {
+        let (sender, _) = broadcast::channel(buffer.max(1));
+        ImportNotifications { sender }
+    }
+
+    pub fn subscribe(&self) -> ImportSubscription<I, BI> {
+        ImportSubscription {
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// Sends the notification to all the current subscribers, if any.
+    pub fn notify(&self, imported: ImportedBlock<I, BI>) {
+        // Failing only means nobody is subscribed at the moment.
+        let _ = self.sender.send(imported);
+    }
+}
+
+impl<I: PeerId, BI: BlockIdentifier> Default for ImportNotifications<I, BI> {
+    fn default() -> Self {
+        Self::new(DEFAULT_IMPORT_BUFFER)
+    }
+}
+
+/// A single subscription to the import notifications.
+pub struct ImportSubscription<I: PeerId, BI: BlockIdentifier> {
+    receiver: broadcast::Receiver<ImportedBlock<I, BI>>,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> ImportSubscription<I, BI> {
+    /// The next notification, `None` once all the notification handles are dropped.
+    pub async fn next(&mut self) -> Option<ImportEvent<I, BI>> {
+        match self.receiver.recv().await {
+            Ok(imported) => Some(ImportEvent::Imported(imported)),
+            Err(RecvError::Lagged(missed)) => Some(ImportEvent::Missed(missed)),
+            Err(RecvError::Closed) => None,
+        }
+    }
+
+    /// The next notification if one is already waiting, for consumers that cannot await.
+    pub fn try_next(&mut self) -> Option<ImportEvent<I, BI>> {
+        match self.receiver.try_recv() {
+            Ok(imported) => Some(ImportEvent::Imported(imported)),
+            Err(TryRecvError::Lagged(missed)) => Some(ImportEvent::Missed(missed)),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportEvent, ImportNotifications, ImportedBlock};
+    use crate::sync::mock::{MockIdentifier, MockPeerId};
+
+    fn imported(number: u32) -> ImportedBlock<MockPeerId, MockIdentifier> {
+        ImportedBlock {
+            block: MockIdentifier::new_random(number),
+            bodies_from: vec![number],
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_to_all_subscribers() {
+        let notifications = ImportNotifications::new(16);
+        let mut first = notifications.subscribe();
+        let mut second = notifications.subscribe();
+        assert_eq!(notifications.subscribers(), 2);
+        let block = imported(1);
+        notifications.notify(block.clone());
+        assert_eq!(
+            first.next().await,
+            Some(ImportEvent::Imported(block.clone()))
+        );
+        assert_eq!(second.try_next(), Some(ImportEvent::Imported(block)));
+        assert_eq!(second.try_next(), None);
+    }
+
+    #[test]
+    fn slow_subscriber_misses_notifications() {
+        let notifications = ImportNotifications::new(2);
+        let mut slow = notifications.subscribe();
+        let blocks: Vec<_> = (0..5).map(imported).collect();
+        for block in &blocks {
+            notifications.notify(block.clone());
+        }
+        assert_eq!(slow.try_next(), Some(ImportEvent::Missed(3)));
+        assert_eq!(
+            slow.try_next(),
+            Some(ImportEvent::Imported(blocks[3].clone()))
+        );
+        assert_eq!(
+            slow.try_next(),
+            Some(ImportEvent::Imported(blocks[4].clone()))
+        );
+        assert_eq!(slow.try_next(), None);
+    }
+
+    #[tokio::test]
+    async fn ends_when_notifications_dropped() {
+        let notifications = ImportNotifications::<MockPeerId, MockIdentifier>::new(2);
+        let mut subscription = notifications.subscribe();
+        drop(notifications);
+        assert_eq!(subscription.next().await, None);
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use rand::{seq::IteratorRandom, thread_rng};
+
+use crate::{sync::PeerId, BlockIdentifier, BlockNumber};
+
+/// How many requested blocks are tracked at most, the ones requested longest ago are forgotten
+/// first.
+const MAX_TRACKED_REQUESTS: usize = 1024;
+
+/// The latest request for a block.
+struct InFlight<I: PeerId> {
+    /// `None` if the network chose the peer.
+    peer: Option<I>,
+    sent_at: Instant,
+    answered: bool,
+    /// The peers that left earlier requests for the block unanswered.
+    timed_out: HashSet<I>,
+}
+
+/// A request that got no response before it was retried.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimedOut<I: PeerId> {
+    /// `None` if the network chose the peer.
+    pub peer: Option<I>,
+    pub after: Duration,
+}
+
+/// Keeps track of the requests for blocks waiting for responses, so that the retries go to peers
+/// that did not leave the earlier ones unanswered.
+pub struct InFlightRequests<I: PeerId, BI: BlockIdentifier> {
+    requests: HashMap<BI, InFlight<I>>,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> InFlightRequests<I, BI> {
+    pub fn new() -> Self {
+        InFlightRequests {
+            requests: HashMap::new(),
+        }
+    }
+
+    /// A random peer out of the candidates to request the block from, preferably one that did
+    /// not leave an earlier request for it unanswered. `None` if there are no candidates.
+    pub fn choose_peer(&self, id: &BI, candidates: &HashSet<I>) -> Option<I> {
+        let mut rng = thread_rng();
+        let request = self.requests.get(id);
+        let unanswered = |peer: &I| {
+            request.map_or(false, |request| {
+                request.timed_out.contains(peer)
+                    || (!request.answered && request.peer.as_ref() == Some(peer))
+            })
+        };
+        candidates
+            .iter()
+            .filter(|peer| !unanswered(peer))
+            .choose(&mut rng)
+            .or_else(|| candidates.iter().choose(&mut rng))
+            .cloned()
+    }
+
+    /// Records the request for the block sent to the peer, or to whoever the network chose if
+    /// `None`. Returns the previous request for the block, if it got no response.
+    pub fn sent(&mut self, id: BI, peer: Option<I>, now: Instant) -> Option<TimedOut<I>> {
+        let (timed_out, previous) = match self.requests.remove(&id) {
+            Some(previous) if previous.answered => (previous.timed_out, None),
+            Some(InFlight {
+                peer,
+                sent_at,
+                mut timed_out,
+                ..
+            }) => {
+                timed_out.extend(peer.clone());
+                let after = now.saturating_duration_since(sent_at);
+                (timed_out, Some(TimedOut { peer, after }))
+            }
+            None => {
+                if self.requests.len() >= MAX_TRACKED_REQUESTS {
+                    if let Some(oldest) = self
+                        .requests
+                        .iter()
+                        .min_by_key(|(_, request)| request.sent_at)
+                        .map(|(id, _)| id.clone())
+                    {
+                        self.requests.remove(&oldest);
+                    }
+                }
+                (HashSet::new(), None)
+            }
+        };
+        self.requests.insert(
+            id,
+            InFlight {
+                peer,
+                sent_at: now,
+                answered: false,
+                timed_out,
+            },
+        );
+        previous
+    }
+
+    /// Records a response from the peer, answering the requests sent to it, and the ones for
+    /// which the network chose the peer, as it might have been this one.
+    pub fn responded(&mut self, peer: &I) {
+        for request in self.requests.values_mut() {
+            if request.peer.as_ref().map_or(true, |asked| asked == peer) {
+                request.answered = true;
+            }
+        }
+    }
+
+    /// Records a response from the peer matched with the request for the block, answering just
+    /// that request, unless it went to someone else since.
+    pub fn answered(&mut self, id: &BI, peer: &I) {
+        if let Some(request) = self.requests.get_mut(id) {
+            if request.peer.as_ref().map_or(true, |asked| asked == peer) {
+                request.answered = true;
+            }
+        }
+    }
+
+    /// Gives up on the requests sent to the peer which disconnected, so that retrying them does
+    /// not count as a timeout, and avoids the peer for them later. Returns the blocks they were
+    /// for, to request again elsewhere.
+    pub fn disconnected(&mut self, peer: &I) -> Vec<BI> {
+        let mut abandoned = Vec::new();
+        for (id, request) in self.requests.iter_mut() {
+            if request.answered || request.peer.as_ref() != Some(peer) {
+                continue;
+            }
+            request.answered = true;
+            request.timed_out.insert(peer.clone());
+            abandoned.push(id.clone());
+        }
+        abandoned
+    }
+
+    /// How many of the tracked requests are still waiting for responses.
+    pub fn waiting(&self) -> usize {
+        self.requests
+            .values()
+            .filter(|request| !request.answered)
+            .count()
+    }
+
+    /// Forgets the request for the block, e.g. because the block got imported, returns whether
+    /// there was one.
+    pub fn cancel(&mut self, id: &BI) -> bool {
+        self.requests.remove(id).is_some()
+    }
+
+    /// Forgets the requests for the blocks at or below the finalized number.
+    pub fn prune(&mut self, finalized: BlockNumber) {
+        self.requests.retain(|id, _| id.number() > finalized);
+    }
+}
+
+impl<I: PeerId, BI: BlockIdentifier> Default for InFlightRequests<I, BI> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        time::{Duration, Instant},
+    };
+
+    use super::{InFlightRequests, TimedOut};
+    use crate::sync::mock::MockIdentifier;
+
+    #[test]
+    fn rotates_peers_after_timeouts() {
+        let mut requests = InFlightRequests::<u32, MockIdentifier>::new();
+        let id = MockIdentifier::new_random(7);
+        let candidates: HashSet<_> = [1, 2].into_iter().collect();
+        let now = Instant::now();
+        let first = requests.choose_peer(&id, &candidates).expect("candidates");
+        assert_eq!(requests.sent(id.clone(), Some(first), now), None);
+        let later = now + Duration::from_secs(1);
+        let second = requests.choose_peer(&id, &candidates).expect("candidates");
+        assert_ne!(first, second);
+        assert_eq!(
+            requests.sent(id.clone(), Some(second), later),
+            Some(TimedOut {
+                peer: Some(first),
+                after: Duration::from_secs(1),
+            })
+        );
+        // Everybody timed out, so anybody will do.
+        assert!(requests.choose_peer(&id, &candidates).is_some());
+        assert_eq!(requests.choose_peer(&id, &HashSet::new()), None);
+    }
+
+    #[test]
+    fn answered_requests_do_not_time_out() {
+        let mut requests = InFlightRequests::<u32, MockIdentifier>::new();
+        let id = MockIdentifier::new_random(7);
+        let random = MockIdentifier::new_random(8);
+        let now = Instant::now();
+        requests.sent(id.clone(), Some(1), now);
+        requests.sent(random.clone(), None, now);
+        assert_eq!(requests.waiting(), 2);
+        requests.responded(&2);
+        assert_eq!(requests.waiting(), 1);
+        assert!(requests.sent(id.clone(), Some(1), now).is_some());
+        // Peer 2 might have been the one chosen by the network.
+        assert_eq!(requests.sent(random, None, now), None);
+        requests.responded(&1);
+        assert_eq!(requests.sent(id.clone(), Some(1), now), None);
+    }
+
+    #[test]
+    fn matched_responses_answer_only_their_requests() {
+        let mut requests = InFlightRequests::<u32, MockIdentifier>::new();
+        let id = MockIdentifier::new_random(7);
+        let other = MockIdentifier::new_random(8);
+        let now = Instant::now();
+        requests.sent(id.clone(), Some(1), now);
+        requests.sent(other.clone(), Some(1), now);
+        requests.answered(&id, &1);
+        assert_eq!(requests.waiting(), 1);
+        assert_eq!(requests.sent(id.clone(), Some(2), now), None);
+        // A late response from the first peer does not answer the retry.
+        requests.answered(&id, &1);
+        assert!(requests.sent(id, Some(2), now).is_some());
+        assert!(requests.sent(other, Some(1), now).is_some());
+    }
+
+    #[test]
+    fn forgets_canceled_and_finalized_requests() {
+        let mut requests = InFlightRequests::<u32, MockIdentifier>::new();
+        let imported = MockIdentifier::new_random(7);
+        let finalized = MockIdentifier::new_random(8);
+        let now = Instant::now();
+        requests.sent(imported.clone(), Some(1), now);
+        requests.sent(finalized.clone(), Some(1), now);
+        assert!(requests.cancel(&imported));
+        assert!(!requests.cancel(&imported));
+        assert_eq!(requests.sent(imported, Some(1), now), None);
+        requests.prune(8);
+        assert_eq!(requests.sent(finalized, Some(1), now), None);
+    }
+
+    #[test]
+    fn abandons_requests_of_disconnected_peers() {
+        let mut requests = InFlightRequests::<u32, MockIdentifier>::new();
+        let id = MockIdentifier::new_random(7);
+        let other = MockIdentifier::new_random(8);
+        let random = MockIdentifier::new_random(9);
+        let now = Instant::now();
+        requests.sent(id.clone(), Some(1), now);
+        requests.sent(other.clone(), Some(2), now);
+        requests.sent(random, None, now);
+        assert_eq!(requests.disconnected(&1), vec![id.clone()]);
+        assert_eq!(requests.waiting(), 2);
+        // The retry does not time out the disconnected peer, but avoids it.
+        let candidates: HashSet<_> = [1, 2].into_iter().collect();
+        assert_eq!(requests.choose_peer(&id, &candidates), Some(2));
+        assert_eq!(requests.sent(id, Some(2), now), None);
+        assert!(requests.disconnected(&3).is_empty());
+    }
+}
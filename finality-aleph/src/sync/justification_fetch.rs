@@ -0,0 +1,68 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use tokio::{sync::Notify, time::timeout};
+
+use crate::BlockIdentifier;
+
+/// How long to wait for the sync service to get a justification from the peers.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Lets anyone holding it ask the running sync service to get the justification of a finalized
+/// block from the peers, when it is not stored locally.
+#[derive(Clone)]
+pub struct JustificationFetches<BI: BlockIdentifier, UJ> {
+    pending: Arc<Mutex<Vec<(BI, oneshot::Sender<UJ>)>>>,
+    requested: Arc<Notify>,
+}
+
+impl<BI: BlockIdentifier, UJ> JustificationFetches<BI, UJ> {
+    pub fn new() -> Self {
+        JustificationFetches {
+            pending: Arc::new(Mutex::new(Vec::new())),
+            requested: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Asks for the verified justification of the block, returns `None` if no peer sent it in
+    /// time.
+    pub async fn fetch(&self, id: BI) -> Option<UJ> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().push((id, sender));
+        self.requested.notify_one();
+        timeout(FETCH_TIMEOUT, receiver).await.ok()?.ok()
+    }
+
+    /// Waits for fetch requests, returns the blocks with where to send their justifications.
+    pub async fn requests(&self) -> Vec<(BI, oneshot::Sender<UJ>)> {
+        self.requested.notified().await;
+        std::mem::take(&mut *self.pending.lock())
+    }
+}
+
+impl<BI: BlockIdentifier, UJ> Default for JustificationFetches<BI, UJ> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JustificationFetches;
+    use crate::{sync::mock::MockIdentifier, BlockIdentifier};
+
+    #[tokio::test]
+    async fn answers_fetch_requests() {
+        let fetches = JustificationFetches::<MockIdentifier, u32>::new();
+        let answering = fetches.clone();
+        let id = MockIdentifier::new_random(7);
+        let answer = tokio::spawn(async move {
+            for (id, sender) in answering.requests().await {
+                let _ = sender.send(id.number());
+            }
+        });
+        assert_eq!(fetches.fetch(id).await, Some(7));
+        answer.await.expect("should not panic");
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+
+use crate::{
+    session::{SessionBoundaryInfo, SessionId},
+    BlockIdentifier,
+};
+
+/// How many imported blocks wait for their justifications at most, the ones imported longest ago
+/// are forgotten first.
+const MAX_TRACKED_IMPORTS: usize = 4096;
+
+/// The distribution of the times from import to justification of the blocks of a session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionLatencies {
+    pub session: SessionId,
+    /// How many blocks were measured, only the ones we saw imported count.
+    pub blocks: usize,
+    pub median: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Display for SessionLatencies {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(
+            f,
+            "session {}: {} blocks justified after {}ms at the median, {}ms at the 90th percentile, {}ms at the 99th percentile and {}ms at most",
+            self.session.0,
+            self.blocks,
+            self.median.as_millis(),
+            self.p90.as_millis(),
+            self.p99.as_millis(),
+            self.max.as_millis()
+        )
+    }
+}
+
+/// Measures how long after their import the justifications of blocks get available locally, for
+/// every block and summarized for every session.
+pub struct JustificationLatencies<BI: BlockIdentifier> {
+    session_info: SessionBoundaryInfo,
+    imported: LruCache<BI, Instant>,
+    session: Option<(SessionId, Vec<Duration>)>,
+}
+
+impl<BI: BlockIdentifier> JustificationLatencies<BI> {
+    pub fn new(session_info: SessionBoundaryInfo) -> Self {
+        JustificationLatencies {
+            session_info,
+            imported: LruCache::new(
+                NonZeroUsize::new(MAX_TRACKED_IMPORTS).expect("the constant is nonzero"),
+            ),
+            session: None,
+        }
+    }
+
+    /// Records the import of the block, only the first one counts.
+    pub fn imported(&mut self, id: BI, now: Instant) {
+        if !self.imported.contains(&id) {
+            self.imported.put(id, now);
+        }
+    }
+
+    fn summarize(session: SessionId, mut latencies: Vec<Duration>) -> Option<SessionLatencies> {
+        latencies.sort_unstable();
+        let max = *latencies.last()?;
+        let at = |percentile: usize| latencies[(latencies.len() - 1) * percentile / 100];
+        Some(SessionLatencies {
+            session,
+            blocks: latencies.len(),
+            median: at(50),
+            p90: at(90),
+            p99: at(99),
+            max,
+        })
+    }
+
+    /// Records that the justification of the block is available, returns how long after the
+    /// import, if we saw it, and the summary of the session, if the block is the last one we
+    /// expect in it. A session is also summarized once a block of a later one gets justified.
+    pub fn justified(
+        &mut self,
+        id: &BI,
+        now: Instant,
+    ) -> (Option<Duration>, Option<SessionLatencies>) {
+        let session = self.session_info.session_id_from_block_num(id.number());
+        let mut summary = None;
+        match self.session.as_ref().map(|(current, _)| *current) {
+            Some(current) if current == session => {}
+            Some(current) if current > session => return (None, None),
+            _ => {
+                if let Some((previous, latencies)) = self.session.take() {
+                    summary = Self::summarize(previous, latencies);
+                }
+                self.session = Some((session, Vec::new()));
+            }
+        }
+        let latency = self
+            .imported
+            .pop(id)
+            .map(|imported| now.saturating_duration_since(imported));
+        if let (Some(latency), Some((_, latencies))) = (latency, &mut self.session) {
+            latencies.push(latency);
+        }
+        if self.session_info.last_block_of_session(session) == id.number() {
+            if let Some((session, latencies)) = self.session.take() {
+                // Both sessions can only end at once if they are a single block long, then only
+                // the current one gets reported.
+                summary = Self::summarize(session, latencies).or(summary);
+            }
+        }
+        (latency, summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{JustificationLatencies, SessionLatencies};
+    use crate::{
+        session::{testing::session_boundary_info, SessionId},
+        sync::{mock::MockIdentifier, Header},
+    };
+
+    #[test]
+    fn measures_latencies_of_single_blocks() {
+        let mut latencies = JustificationLatencies::new(session_boundary_info());
+        let now = Instant::now();
+        let block = MockIdentifier::new_random(3);
+        assert_eq!(latencies.justified(&block, now), (None, None));
+        let block = MockIdentifier::new_random(4);
+        latencies.imported(block.clone(), now);
+        latencies.imported(block.clone(), now + Duration::from_secs(1));
+        assert_eq!(
+            latencies.justified(&block, now + Duration::from_secs(2)),
+            (Some(Duration::from_secs(2)), None)
+        );
+        assert_eq!(
+            latencies.justified(&block, now + Duration::from_secs(3)),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn summarizes_sessions() {
+        let mut latencies = JustificationLatencies::new(session_boundary_info());
+        let now = Instant::now();
+        let branch: Vec<_> = MockIdentifier::new_random(0)
+            .random_branch()
+            .take(23)
+            .map(|header| header.id())
+            .collect();
+        let mut summary = None;
+        for (latency, id) in branch.iter().take(19).enumerate() {
+            latencies.imported(id.clone(), now);
+            let latency = Duration::from_millis(latency as u64 * 10);
+            let (measured, maybe_summary) = latencies.justified(id, now + latency);
+            assert_eq!(measured, Some(latency));
+            summary = summary.or(maybe_summary);
+        }
+        // The last block of the first session comes with the summary.
+        assert_eq!(
+            summary,
+            Some(SessionLatencies {
+                session: SessionId(0),
+                blocks: 19,
+                median: Duration::from_millis(90),
+                p90: Duration::from_millis(160),
+                p99: Duration::from_millis(170),
+                max: Duration::from_millis(180),
+            })
+        );
+        for id in &branch[19..] {
+            latencies.imported(id.clone(), now);
+            assert_eq!(latencies.justified(id, now).1, None);
+        }
+        // The second session never gets its last block justified, but it is summarized anyway.
+        let later = MockIdentifier::new_random(
+            session_boundary_info().first_block_of_session(SessionId(2)),
+        );
+        let summary = latencies.justified(&later, now).1.expect("a session ended");
+        assert_eq!(summary.session, SessionId(1));
+        assert_eq!(summary.blocks, 4);
+        assert_eq!(latencies.justified(&branch[5], now), (None, None));
+    }
+}
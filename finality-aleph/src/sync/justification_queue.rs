@@ -0,0 +1,182 @@
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Error as FmtError, Formatter},
+};
+
+/// Where the justifications waiting for import came from. Every lane has its own capacity and
+/// its own share of the handling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lane {
+    /// Responses to the requests we sent, most likely what we need to get finalization going.
+    Requested,
+    /// Justifications submitted locally, e.g. by the committee or through RPC.
+    User,
+    /// Justifications pushed to us by peers unprompted.
+    Gossip,
+}
+
+const LANES: [Lane; 3] = [Lane::Requested, Lane::User, Lane::Gossip];
+
+impl Lane {
+    fn index(&self) -> usize {
+        use Lane::*;
+        match self {
+            Requested => 0,
+            User => 1,
+            Gossip => 2,
+        }
+    }
+
+    /// How many items can wait in the lane at most.
+    pub fn capacity(&self) -> usize {
+        use Lane::*;
+        match self {
+            Requested => 64,
+            User => 256,
+            Gossip => 128,
+        }
+    }
+
+    /// How many items from the lane get handled in a single round, as long as it has any.
+    fn quota(&self) -> usize {
+        use Lane::*;
+        match self {
+            Requested => 4,
+            User => 2,
+            Gossip => 1,
+        }
+    }
+}
+
+impl Display for Lane {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use Lane::*;
+        match self {
+            Requested => write!(f, "requested"),
+            User => write!(f, "user"),
+            Gossip => write!(f, "gossip"),
+        }
+    }
+}
+
+/// The lane was at its capacity, so the item was dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LaneFull(pub Lane);
+
+impl Display for LaneFull {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(
+            f,
+            "{} items are waiting in the {} lane already",
+            self.0.capacity(),
+            self.0
+        )
+    }
+}
+
+/// Justifications waiting for import, in separate lanes depending on where they came from. The
+/// lanes are handled in rounds, with every lane getting its quota in every round, the requested
+/// ones first, so that a flood of gossip can neither starve the justifications we asked for, nor
+/// the ones submitted locally.
+pub struct JustificationQueue<T> {
+    lanes: [VecDeque<T>; 3],
+    handled: [usize; 3],
+}
+
+impl<T> JustificationQueue<T> {
+    pub fn new() -> Self {
+        JustificationQueue {
+            lanes: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            handled: [0; 3],
+        }
+    }
+
+    /// Whether the lane can take another item.
+    pub fn has_room(&self, lane: Lane) -> bool {
+        self.lanes[lane.index()].len() < lane.capacity()
+    }
+
+    /// Queues the item in the lane, unless the lane is full.
+    pub fn push(&mut self, lane: Lane, item: T) -> Result<(), LaneFull> {
+        if !self.has_room(lane) {
+            return Err(LaneFull(lane));
+        }
+        self.lanes[lane.index()].push_back(item);
+        Ok(())
+    }
+
+    fn pop_within_quota(&mut self) -> Option<(Lane, T)> {
+        for lane in LANES {
+            let index = lane.index();
+            if self.handled[index] >= lane.quota() {
+                continue;
+            }
+            if let Some(item) = self.lanes[index].pop_front() {
+                self.handled[index] += 1;
+                return Some((lane, item));
+            }
+        }
+        None
+    }
+
+    /// The next item to handle, together with the lane it waited in.
+    pub fn pop(&mut self) -> Option<(Lane, T)> {
+        if self.is_empty() {
+            return None;
+        }
+        self.pop_within_quota().or_else(|| {
+            // All the lanes with items used up their quotas, a new round starts.
+            self.handled = [0; 3];
+            self.pop_within_quota()
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(|lane| lane.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JustificationQueue, Lane, LaneFull};
+
+    #[test]
+    fn gossip_does_not_starve_requested() {
+        let mut queue = JustificationQueue::new();
+        for item in 0..Lane::Gossip.capacity() {
+            queue.push(Lane::Gossip, item).expect("room for gossip");
+        }
+        assert_eq!(queue.push(Lane::Gossip, 0), Err(LaneFull(Lane::Gossip)));
+        assert_eq!(queue.pop(), Some((Lane::Gossip, 0)));
+        queue.push(Lane::Requested, 7).expect("room for requested");
+        queue.push(Lane::User, 8).expect("room for user");
+        // Handled right after the gossip already taken in this round.
+        assert_eq!(queue.pop(), Some((Lane::Requested, 7)));
+        assert_eq!(queue.pop(), Some((Lane::User, 8)));
+        assert_eq!(queue.pop(), Some((Lane::Gossip, 1)));
+    }
+
+    #[test]
+    fn lanes_get_their_quotas() {
+        let mut queue = JustificationQueue::new();
+        for item in 0..10 {
+            queue
+                .push(Lane::Requested, item)
+                .expect("room for requested");
+            queue.push(Lane::Gossip, item).expect("room for gossip");
+        }
+        let lanes: Vec<_> = (0..10)
+            .map(|_| queue.pop().expect("items are waiting").0)
+            .collect();
+        use Lane::*;
+        assert_eq!(
+            lanes,
+            vec![
+                Requested, Requested, Requested, Requested, Gossip, Requested, Requested,
+                Requested, Requested, Gossip
+            ]
+        );
+        while queue.pop().is_some() {}
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use crate::BlockNumber;
+
+/// How long the backlog of queued requests and justifications can take to work through, before
+/// the less important work in it gets shed.
+pub const LATENCY_BUDGET: Duration = Duration::from_millis(500);
+/// How far behind our top finalized block a peer has to be for its requests to be cold, i.e. to
+/// need data deep in the database.
+const COLD_DISTANCE: BlockNumber = 1024;
+
+/// Work that can be skipped while the service is behind, so that the work keeping finalization
+/// of the tip going does not wait for it.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ShedWork {
+    /// Verifying justifications of already finalized blocks, gossiped by peers.
+    HistoricalVerification,
+    /// Serving requests of peers far behind us.
+    ColdRequest,
+}
+
+impl ShedWork {
+    /// How many budgets the backlog has to be overdue by for the work to be shed, the least
+    /// important work goes first.
+    fn overdue_budgets(&self) -> u32 {
+        use ShedWork::*;
+        match self {
+            HistoricalVerification => 1,
+            ColdRequest => 2,
+        }
+    }
+}
+
+/// Keeps track of for how long the service has had a backlog of work, to decide when to shed
+/// the less important parts of it.
+pub struct LatencyBudget {
+    budget: Duration,
+    backlogged_since: Option<Instant>,
+    top_finalized: BlockNumber,
+}
+
+impl LatencyBudget {
+    pub fn new(budget: Duration, top_finalized: BlockNumber) -> Self {
+        LatencyBudget {
+            budget,
+            backlogged_since: None,
+            top_finalized,
+        }
+    }
+
+    /// Notes whether there is any backlog at the start of an iteration of the service loop.
+    pub fn iteration(&mut self, backlogged: bool, now: Instant) {
+        match (backlogged, self.backlogged_since) {
+            (false, _) => self.backlogged_since = None,
+            (true, None) => self.backlogged_since = Some(now),
+            (true, Some(_)) => {}
+        }
+    }
+
+    pub fn finalized(&mut self, number: BlockNumber) {
+        self.top_finalized = self.top_finalized.max(number);
+    }
+
+    /// Whether the work should be skipped, because the backlog was not worked through in time.
+    pub fn sheds(&self, work: ShedWork, now: Instant) -> bool {
+        match self.backlogged_since {
+            Some(since) => {
+                now.saturating_duration_since(since) > self.budget * work.overdue_budgets()
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the justification is of an already finalized block.
+    pub fn is_historical(&self, number: BlockNumber) -> bool {
+        number <= self.top_finalized
+    }
+
+    /// Whether serving a request of a peer with this top finalized block is cold.
+    pub fn is_cold(&self, requester_finalized: BlockNumber) -> bool {
+        requester_finalized.saturating_add(COLD_DISTANCE) < self.top_finalized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{LatencyBudget, ShedWork::*, COLD_DISTANCE};
+
+    const BUDGET: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn sheds_least_important_work_first() {
+        let mut budget = LatencyBudget::new(BUDGET, 0);
+        let start = Instant::now();
+        budget.iteration(true, start);
+        assert!(!budget.sheds(HistoricalVerification, start + BUDGET));
+        // Still the same backlog.
+        budget.iteration(true, start + BUDGET);
+        let later = start + BUDGET + Duration::from_millis(1);
+        assert!(budget.sheds(HistoricalVerification, later));
+        assert!(!budget.sheds(ColdRequest, later));
+        let much_later = start + BUDGET * 2 + Duration::from_millis(1);
+        assert!(budget.sheds(ColdRequest, much_later));
+        // Once the backlog is worked through, nothing is shed.
+        budget.iteration(false, much_later);
+        assert!(!budget.sheds(HistoricalVerification, much_later + BUDGET * 3));
+    }
+
+    #[test]
+    fn classifies_work() {
+        let mut budget = LatencyBudget::new(BUDGET, 10);
+        assert!(budget.is_historical(10));
+        assert!(!budget.is_historical(11));
+        budget.finalized(COLD_DISTANCE + 20);
+        // Finalization never goes back.
+        budget.finalized(5);
+        assert!(budget.is_historical(11));
+        assert!(budget.is_cold(19));
+        assert!(!budget.is_cold(20));
+    }
+}
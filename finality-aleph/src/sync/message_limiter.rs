@@ -9,6 +9,7 @@ const MSG_BYTES_LIMIT: usize = MAX_SYNC_MESSAGE_SIZE as usize;
 pub struct Limiter<'a, D: Encode, const LIMIT: usize> {
     msg: &'a [D],
     start_index: usize,
+    limit: usize,
 }
 
 pub type MsgLimiter<'a, D> = Limiter<'a, D, MSG_BYTES_LIMIT>;
@@ -28,9 +29,15 @@ impl Display for Error {
 
 impl<'a, D: Encode, const LIMIT: usize> Limiter<'a, D, LIMIT> {
     pub fn new(msg: &'a [D]) -> Self {
+        Self::with_limit(msg, LIMIT)
+    }
+
+    /// A limiter using a lower limit than the static one. Limits above the static one are ignored.
+    pub fn with_limit(msg: &'a [D], limit: usize) -> Self {
         Self {
             msg,
             start_index: 0,
+            limit: limit.min(LIMIT),
         }
     }
 
@@ -50,14 +57,15 @@ impl<'a, D: Encode, const LIMIT: usize> Limiter<'a, D, LIMIT> {
         let mut idx = self.start_index;
         let mut encoded_sum = 0;
 
-        while idx < self.msg.len() && encoded_sum <= LIMIT {
+        while idx < self.msg.len() && encoded_sum <= self.limit {
             encoded_sum += self.msg[idx].encoded_size();
             idx += 1;
         }
 
         // encoded size of the msg[start_index..idx] may be larger than the limit. Trim last items
         // until the encoded size fits into the limit.
-        while idx > self.start_index && self.msg[self.start_index..idx].encoded_size() > LIMIT {
+        while idx > self.start_index && self.msg[self.start_index..idx].encoded_size() > self.limit
+        {
             idx -= 1;
         }
 
@@ -110,6 +118,18 @@ mod tests {
         assert_eq!(Ok(Some(&v[..])), lim.next_largest_msg())
     }
     #[test]
+    fn respects_the_lower_limit() {
+        let v = vec![sized(1), sized(2), sized(3)];
+
+        let mut lim = TestLimiter::with_limit(&v, 5);
+
+        assert_eq!(Ok(Some(&v[..2])), lim.next_largest_msg());
+
+        let mut lim = TestLimiter::with_limit(&v, 100);
+
+        assert_eq!(Ok(Some(&v[..])), lim.next_largest_msg())
+    }
+    #[test]
     fn takes_all_that_fits_into_limit() {
         let v = vec![sized(1), sized(2), sized(7)];
 
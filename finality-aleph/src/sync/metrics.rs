@@ -1,10 +1,27 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
-use substrate_prometheus_endpoint::{register, Counter, PrometheusError, Registry, U64};
+use parity_scale_codec::Encode;
+use substrate_prometheus_endpoint::{
+    exponential_buckets, register, Counter, CounterVec, Gauge, GaugeVec, Histogram, HistogramOpts,
+    HistogramVec, Opts, PrometheusError, Registry, U64,
+};
+
+use crate::{
+    session::SessionId,
+    sync::{
+        data::NetworkData,
+        justification_latency::SessionLatencies,
+        network_view::{FinalityStats, FinalityStatus},
+        serving_stats::{ServingOutcome, ALL_SERVING_OUTCOMES},
+        Block, Justification,
+    },
+};
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum Event {
     Broadcast,
+    BroadcastSuppressed,
+    Announce,
     SendRequest,
     SendTo,
     HandleState,
@@ -16,14 +33,38 @@ pub enum Event {
     HandleStateResponse,
     HandleJustificationFromUser,
     HandleInternalRequest,
+    JustificationShed,
+    JustificationRecovered,
+    PushSessionEndJustification,
+    BackfillRequest,
+    BackfillBody,
+    ExternalFetch,
+    RangeRequest,
+    RangeBlock,
+    OversizedMessage,
+    SplitResponse,
+    PeerMisbehavior,
+    PeerPenalty,
+    AcknowledgementRequest,
+    Acknowledgement,
+    ResendSkipped,
+    SnapshotTrigger,
+    RequestDropped,
+    RequestTimeout,
+    JustificationsDropped,
+    JustificationFetch,
+    HistoricalVerificationShed,
+    ColdRequestShed,
 }
 
 use Event::*;
 
 impl Event {
-    fn name(&self) -> &str {
+    pub fn name(&self) -> &'static str {
         match self {
             Broadcast => "broadcast",
+            BroadcastSuppressed => "broadcast_suppressed",
+            Announce => "announce",
             SendRequest => "send_request",
             SendTo => "send_to",
             HandleState => "handle_state",
@@ -35,12 +76,36 @@ impl Event {
             HandleStateResponse => "handle_state_response",
             HandleJustificationFromUser => "handle_justification_from_user",
             HandleInternalRequest => "handle_internal_request",
+            JustificationShed => "justification_shed",
+            JustificationRecovered => "justification_recovered",
+            PushSessionEndJustification => "push_session_end_justification",
+            BackfillRequest => "backfill_request",
+            BackfillBody => "backfill_body",
+            ExternalFetch => "external_fetch",
+            RangeRequest => "range_request",
+            RangeBlock => "range_block",
+            OversizedMessage => "oversized_message",
+            SplitResponse => "split_response",
+            PeerMisbehavior => "peer_misbehavior",
+            PeerPenalty => "peer_penalty",
+            AcknowledgementRequest => "acknowledgement_request",
+            Acknowledgement => "acknowledgement",
+            ResendSkipped => "resend_skipped",
+            SnapshotTrigger => "snapshot_trigger",
+            RequestDropped => "request_dropped",
+            RequestTimeout => "request_timeout",
+            JustificationsDropped => "justifications_dropped",
+            JustificationFetch => "justification_fetch",
+            HistoricalVerificationShed => "historical_verification_shed",
+            ColdRequestShed => "cold_request_shed",
         }
     }
 }
 
-const ALL_EVENTS: [Event; 12] = [
+const ALL_EVENTS: [Event; 36] = [
     Broadcast,
+    BroadcastSuppressed,
+    Announce,
     SendRequest,
     SendTo,
     HandleState,
@@ -52,10 +117,33 @@ const ALL_EVENTS: [Event; 12] = [
     HandleStateResponse,
     HandleJustificationFromUser,
     HandleInternalRequest,
+    JustificationShed,
+    JustificationRecovered,
+    PushSessionEndJustification,
+    BackfillRequest,
+    BackfillBody,
+    ExternalFetch,
+    RangeRequest,
+    RangeBlock,
+    OversizedMessage,
+    SplitResponse,
+    PeerMisbehavior,
+    PeerPenalty,
+    AcknowledgementRequest,
+    Acknowledgement,
+    ResendSkipped,
+    SnapshotTrigger,
+    RequestDropped,
+    RequestTimeout,
+    JustificationsDropped,
+    JustificationFetch,
+    HistoricalVerificationShed,
+    ColdRequestShed,
 ];
 
-const ERRORING_EVENTS: [Event; 9] = [
+const ERRORING_EVENTS: [Event; 17] = [
     Broadcast,
+    Announce,
     SendRequest,
     SendTo,
     HandleState,
@@ -64,12 +152,212 @@ const ERRORING_EVENTS: [Event; 9] = [
     HandleBlockImported,
     HandleJustificationFromUser,
     HandleInternalRequest,
+    PushSessionEndJustification,
+    BackfillRequest,
+    BackfillBody,
+    ExternalFetch,
+    RangeRequest,
+    PeerPenalty,
+    JustificationFetch,
+];
+
+/// The kinds of sync messages, one per variant of the network data.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum MessageKind {
+    StateBroadcast,
+    StateBroadcastResponse,
+    Request,
+    RequestResponse,
+    CapabilitiesAnnouncement,
+    AvailabilityRequest,
+    AvailabilityResponse,
+    AcknowledgementRequest,
+    Acknowledgement,
+    ExtendedStateBroadcast,
+    BatchedStateBroadcastResponse,
+    BodyRequest,
+    PriorityTicket,
+    HeaderRequest,
+    WarpRequest,
+    WarpResponse,
+    ValidatorTicket,
+    Announcement,
+    CorrelatedRequest,
+    CorrelatedResponse,
+    ForkIdAnnouncement,
+}
+
+impl MessageKind {
+    pub fn of<B, J>(data: &NetworkData<B, J>) -> Self
+    where
+        B: Block,
+        J: Justification<Header = B::Header>,
+    {
+        use NetworkData::*;
+        match data {
+            StateBroadcast(_) => MessageKind::StateBroadcast,
+            StateBroadcastResponse(_, _) => MessageKind::StateBroadcastResponse,
+            Request(_) => MessageKind::Request,
+            RequestResponse(_) => MessageKind::RequestResponse,
+            CapabilitiesAnnouncement(_) => MessageKind::CapabilitiesAnnouncement,
+            AvailabilityRequest => MessageKind::AvailabilityRequest,
+            AvailabilityResponse(_) => MessageKind::AvailabilityResponse,
+            AcknowledgementRequest(_) => MessageKind::AcknowledgementRequest,
+            Acknowledgement(_) => MessageKind::Acknowledgement,
+            ExtendedStateBroadcast(_) => MessageKind::ExtendedStateBroadcast,
+            BatchedStateBroadcastResponse(_, _) => MessageKind::BatchedStateBroadcastResponse,
+            BodyRequest(_) => MessageKind::BodyRequest,
+            PriorityTicket(_) => MessageKind::PriorityTicket,
+            HeaderRequest(_) => MessageKind::HeaderRequest,
+            WarpRequest(_) => MessageKind::WarpRequest,
+            WarpResponse(_) => MessageKind::WarpResponse,
+            ValidatorTicket(_) => MessageKind::ValidatorTicket,
+            Announcement(_) => MessageKind::Announcement,
+            CorrelatedRequest(_, _) => MessageKind::CorrelatedRequest,
+            CorrelatedResponse(_, _) => MessageKind::CorrelatedResponse,
+            ForkIdAnnouncement(_) => MessageKind::ForkIdAnnouncement,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        use MessageKind::*;
+        match self {
+            StateBroadcast => "state_broadcast",
+            StateBroadcastResponse => "state_broadcast_response",
+            Request => "request",
+            RequestResponse => "request_response",
+            CapabilitiesAnnouncement => "capabilities_announcement",
+            AvailabilityRequest => "availability_request",
+            AvailabilityResponse => "availability_response",
+            AcknowledgementRequest => "acknowledgement_request",
+            Acknowledgement => "acknowledgement",
+            ExtendedStateBroadcast => "extended_state_broadcast",
+            BatchedStateBroadcastResponse => "batched_state_broadcast_response",
+            BodyRequest => "body_request",
+            PriorityTicket => "priority_ticket",
+            HeaderRequest => "header_request",
+            WarpRequest => "warp_request",
+            WarpResponse => "warp_response",
+            ValidatorTicket => "validator_ticket",
+            Announcement => "announcement",
+            CorrelatedRequest => "correlated_request",
+            CorrelatedResponse => "correlated_response",
+            ForkIdAnnouncement => "fork_id_announcement",
+        }
+    }
+}
+
+const ALL_MESSAGE_KINDS: [MessageKind; 21] = [
+    MessageKind::StateBroadcast,
+    MessageKind::StateBroadcastResponse,
+    MessageKind::Request,
+    MessageKind::RequestResponse,
+    MessageKind::CapabilitiesAnnouncement,
+    MessageKind::AvailabilityRequest,
+    MessageKind::AvailabilityResponse,
+    MessageKind::AcknowledgementRequest,
+    MessageKind::Acknowledgement,
+    MessageKind::ExtendedStateBroadcast,
+    MessageKind::BatchedStateBroadcastResponse,
+    MessageKind::BodyRequest,
+    MessageKind::PriorityTicket,
+    MessageKind::HeaderRequest,
+    MessageKind::WarpRequest,
+    MessageKind::WarpResponse,
+    MessageKind::ValidatorTicket,
+    MessageKind::Announcement,
+    MessageKind::CorrelatedRequest,
+    MessageKind::CorrelatedResponse,
+    MessageKind::ForkIdAnnouncement,
 ];
 
+/// Whether a sync message was sent or received.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum MessageDirection {
+    Sent,
+    Received,
+}
+
+impl MessageDirection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageDirection::Sent => "sent",
+            MessageDirection::Received => "received",
+        }
+    }
+}
+
+const ALL_MESSAGE_DIRECTIONS: [MessageDirection; 2] =
+    [MessageDirection::Sent, MessageDirection::Received];
+
+fn histogram(
+    name: String,
+    help: String,
+    buckets: Vec<f64>,
+    registry: &Registry,
+) -> Result<Histogram, PrometheusError> {
+    register(
+        Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets))?,
+        registry,
+    )
+}
+
+fn gauge(name: &str, help: &str, registry: &Registry) -> Result<Gauge<U64>, PrometheusError> {
+    register(Gauge::new(name, help)?, registry)
+}
+
+/// How many recent sessions get their own label values, the label of a session is its index
+/// modulo this, so the cardinality stays bounded and a new session takes over the label of the
+/// one this many sessions before.
+const SESSION_LABEL_WINDOW: u32 = 8;
+
+const SESSION_LABEL: &str = "session_slot";
+
+fn session_slot(session: SessionId) -> String {
+    (session.0 % SESSION_LABEL_WINDOW).to_string()
+}
+
+fn session_counter(
+    name: &str,
+    help: &str,
+    registry: &Registry,
+) -> Result<CounterVec<U64>, PrometheusError> {
+    register(
+        CounterVec::new(Opts::new(name, help), &[SESSION_LABEL])?,
+        registry,
+    )
+}
+
+#[derive(Clone)]
 pub enum Metrics {
     Prometheus {
         calls: HashMap<Event, Counter<U64>>,
         errors: HashMap<Event, Counter<U64>>,
+        messages: HashMap<(MessageDirection, MessageKind), Counter<U64>>,
+        message_sizes: HashMap<(MessageDirection, MessageKind), Histogram>,
+        request_round_trips: Histogram,
+        blocks_imported_per_response: Histogram,
+        network_peers: Gauge<U64>,
+        network_median_finalized: Gauge<U64>,
+        network_lag_median: Gauge<U64>,
+        network_lag_p90: Gauge<U64>,
+        network_lag_max: Gauge<U64>,
+        network_stalled: Gauge<U64>,
+        justification_latencies: Histogram,
+        finalization_commits: Histogram,
+        blocks_per_finalization_commit: Histogram,
+        session_latency_session: Gauge<U64>,
+        session_latency_median: Gauge<U64>,
+        session_latency_p90: Gauge<U64>,
+        session_latency_p99: Gauge<U64>,
+        session_latency_max: Gauge<U64>,
+        justification_overlay_depth: Gauge<U64>,
+        slot_sessions: GaugeVec<U64>,
+        session_justification_latencies: HistogramVec,
+        session_justifications: CounterVec<U64>,
+        session_catch_up_blocks: CounterVec<U64>,
+        served_requests: HashMap<ServingOutcome, Counter<U64>>,
+        request_serving_times: Histogram,
     },
     Noop,
 }
@@ -106,13 +394,237 @@ impl Metrics {
                 )?,
             );
         }
-        Ok(Metrics::Prometheus { calls, errors })
+        let mut messages = HashMap::new();
+        let mut message_sizes = HashMap::new();
+        for direction in ALL_MESSAGE_DIRECTIONS {
+            for kind in ALL_MESSAGE_KINDS {
+                let name = format!("aleph_sync_{}_{}", direction.name(), kind.name());
+                messages.insert(
+                    (direction, kind),
+                    register(
+                        Counter::new(
+                            name.clone(),
+                            format!("number of {} messages {}", kind.name(), direction.name()),
+                        )?,
+                        &registry,
+                    )?,
+                );
+                message_sizes.insert(
+                    (direction, kind),
+                    histogram(
+                        format!("{name}_bytes"),
+                        format!(
+                            "encoded sizes of {} messages {}",
+                            kind.name(),
+                            direction.name()
+                        ),
+                        // From 64 bytes to 16MiB, more than the largest message.
+                        exponential_buckets(64.0, 2.0, 19)?,
+                        &registry,
+                    )?,
+                );
+            }
+        }
+        let request_round_trips = histogram(
+            "aleph_sync_request_round_trip".to_string(),
+            "seconds between sending a request and receiving a response".to_string(),
+            exponential_buckets(0.001, 1.5, 25)?,
+            &registry,
+        )?;
+        let blocks_imported_per_response = histogram(
+            "aleph_sync_blocks_imported_per_response".to_string(),
+            "number of blocks imported from a single request response".to_string(),
+            exponential_buckets(1.0, 2.0, 10)?,
+            &registry,
+        )?;
+        let justification_latencies = histogram(
+            "aleph_sync_justification_latency".to_string(),
+            "seconds between importing a block and its justification being available".to_string(),
+            exponential_buckets(0.1, 1.5, 20)?,
+            &registry,
+        )?;
+        let finalization_commits = histogram(
+            "aleph_sync_finalization_commit".to_string(),
+            "seconds taken by committing a single finalization database transaction".to_string(),
+            exponential_buckets(0.0005, 2.0, 16)?,
+            &registry,
+        )?;
+        let blocks_per_finalization_commit = histogram(
+            "aleph_sync_blocks_per_finalization_commit".to_string(),
+            "number of blocks finalized in a single database transaction".to_string(),
+            exponential_buckets(1.0, 2.0, 12)?,
+            &registry,
+        )?;
+        let mut served_requests = HashMap::new();
+        for outcome in ALL_SERVING_OUTCOMES {
+            served_requests.insert(
+                outcome,
+                register(
+                    Counter::new(
+                        format!("aleph_sync_requests_{}", outcome.name()),
+                        format!("number of requests from peers {}", outcome.name()),
+                    )?,
+                    &registry,
+                )?,
+            );
+        }
+        let request_serving_times = histogram(
+            "aleph_sync_request_serving_time".to_string(),
+            "seconds taken by handling a single request from a peer".to_string(),
+            exponential_buckets(0.0001, 2.0, 18)?,
+            &registry,
+        )?;
+        let session_justification_latencies = register(
+            HistogramVec::new(
+                HistogramOpts::new(
+                    "aleph_sync_session_justification_latency",
+                    "seconds between importing a block and its justification being available, by session slot",
+                )
+                .buckets(exponential_buckets(0.1, 1.5, 20)?),
+                &[SESSION_LABEL],
+            )?,
+            &registry,
+        )?;
+        Ok(Metrics::Prometheus {
+            calls,
+            errors,
+            messages,
+            message_sizes,
+            request_round_trips,
+            blocks_imported_per_response,
+            justification_latencies,
+            finalization_commits,
+            blocks_per_finalization_commit,
+            network_peers: gauge(
+                "aleph_sync_network_peers",
+                "number of peers that advertised their states recently",
+                &registry,
+            )?,
+            network_median_finalized: gauge(
+                "aleph_sync_network_median_finalized",
+                "median of the top finalized blocks advertised by the peers",
+                &registry,
+            )?,
+            network_lag_median: gauge(
+                "aleph_sync_network_lag_median",
+                "median of how many blocks the peers are behind the highest finalized one",
+                &registry,
+            )?,
+            network_lag_p90: gauge(
+                "aleph_sync_network_lag_p90",
+                "90th percentile of how many blocks the peers are behind the highest finalized one",
+                &registry,
+            )?,
+            network_lag_max: gauge(
+                "aleph_sync_network_lag_max",
+                "how many blocks the furthest peer is behind the highest finalized one",
+                &registry,
+            )?,
+            network_stalled: gauge(
+                "aleph_sync_network_stalled",
+                "whether the finality of the network looks stalled",
+                &registry,
+            )?,
+            session_latency_session: gauge(
+                "aleph_sync_session_justification_latency_session",
+                "the last session with the justification latencies summarized",
+                &registry,
+            )?,
+            session_latency_median: gauge(
+                "aleph_sync_session_justification_latency_median_ms",
+                "median of the justification latencies in the last summarized session",
+                &registry,
+            )?,
+            session_latency_p90: gauge(
+                "aleph_sync_session_justification_latency_p90_ms",
+                "90th percentile of the justification latencies in the last summarized session",
+                &registry,
+            )?,
+            session_latency_p99: gauge(
+                "aleph_sync_session_justification_latency_p99_ms",
+                "99th percentile of the justification latencies in the last summarized session",
+                &registry,
+            )?,
+            session_latency_max: gauge(
+                "aleph_sync_session_justification_latency_max_ms",
+                "maximum of the justification latencies in the last summarized session",
+                &registry,
+            )?,
+            justification_overlay_depth: gauge(
+                "aleph_sync_justification_overlay_depth",
+                "number of verified justifications waiting for their ancestors to get finalized",
+                &registry,
+            )?,
+            slot_sessions: register(
+                GaugeVec::new(
+                    Opts::new(
+                        "aleph_sync_session_slot_session",
+                        "the session currently reported under the session slot",
+                    ),
+                    &[SESSION_LABEL],
+                )?,
+                &registry,
+            )?,
+            session_justification_latencies,
+            session_justifications: session_counter(
+                "aleph_sync_session_justifications",
+                "number of justifications imported, by session slot of the justified block",
+                &registry,
+            )?,
+            session_catch_up_blocks: session_counter(
+                "aleph_sync_session_catch_up_blocks",
+                "number of blocks imported from request responses, by session slot of the top finalized block",
+                &registry,
+            )?,
+            served_requests,
+            request_serving_times,
+        })
     }
 
     pub fn noop() -> Self {
         Metrics::Noop
     }
 
+    /// Returns the label of the session, taking the slot over from an older session and clearing
+    /// what it reported, or nothing if a newer session already took over the slot.
+    fn session_label(&self, session: SessionId) -> Option<String> {
+        let (
+            slot_sessions,
+            session_justification_latencies,
+            session_justifications,
+            session_catch_up_blocks,
+        ) = match self {
+            Metrics::Prometheus {
+                slot_sessions,
+                session_justification_latencies,
+                session_justifications,
+                session_catch_up_blocks,
+                ..
+            } => (
+                slot_sessions,
+                session_justification_latencies,
+                session_justifications,
+                session_catch_up_blocks,
+            ),
+            Metrics::Noop => return None,
+        };
+        let label = session_slot(session);
+        let slot_session = slot_sessions.with_label_values(&[label.as_str()]);
+        let current = slot_session.get();
+        let session = u64::from(session.0);
+        if current > session {
+            return None;
+        }
+        if current < session {
+            // Missing values just mean nothing was reported for the slot yet.
+            let _ = session_justification_latencies.remove_label_values(&[label.as_str()]);
+            let _ = session_justifications.remove_label_values(&[label.as_str()]);
+            let _ = session_catch_up_blocks.remove_label_values(&[label.as_str()]);
+            slot_session.set(session);
+        }
+        Some(label)
+    }
+
     pub fn report_event(&self, event: Event) {
         if let Metrics::Prometheus { calls, .. } = self {
             if let Some(counter) = calls.get(&event) {
@@ -128,4 +640,204 @@ impl Metrics {
             }
         }
     }
+
+    /// Reports a message sent or received, the encoded size is only computed when needed.
+    pub fn report_message<B, J>(&self, direction: MessageDirection, data: &NetworkData<B, J>)
+    where
+        B: Block,
+        J: Justification<Header = B::Header>,
+    {
+        if let Metrics::Prometheus {
+            messages,
+            message_sizes,
+            ..
+        } = self
+        {
+            let key = (direction, MessageKind::of(data));
+            if let Some(counter) = messages.get(&key) {
+                counter.inc();
+            }
+            if let Some(histogram) = message_sizes.get(&key) {
+                histogram.observe(data.encoded_size() as f64);
+            }
+        }
+    }
+
+    pub fn report_request_round_trip(&self, round_trip: Duration) {
+        if let Metrics::Prometheus {
+            request_round_trips,
+            ..
+        } = self
+        {
+            request_round_trips.observe(round_trip.as_secs_f64());
+        }
+    }
+
+    pub fn report_blocks_imported_from_response(&self, blocks: usize) {
+        if let Metrics::Prometheus {
+            blocks_imported_per_response,
+            ..
+        } = self
+        {
+            blocks_imported_per_response.observe(blocks as f64);
+        }
+    }
+
+    pub fn report_network_view(&self, stats: Option<FinalityStats>, status: FinalityStatus) {
+        if let Metrics::Prometheus {
+            network_peers,
+            network_median_finalized,
+            network_lag_median,
+            network_lag_p90,
+            network_lag_max,
+            network_stalled,
+            ..
+        } = self
+        {
+            network_peers.set(stats.map_or(0, |stats| stats.peers as u64));
+            network_median_finalized.set(stats.map_or(0, |stats| stats.median_finalized.into()));
+            network_lag_median.set(stats.map_or(0, |stats| stats.lag_median.into()));
+            network_lag_p90.set(stats.map_or(0, |stats| stats.lag_p90.into()));
+            network_lag_max.set(stats.map_or(0, |stats| stats.lag_max.into()));
+            network_stalled.set(matches!(status, FinalityStatus::NetworkStalled(_)).into());
+        }
+    }
+
+    pub fn report_justification_latency(&self, latency: Duration, session: SessionId) {
+        if let Metrics::Prometheus {
+            justification_latencies,
+            session_justification_latencies,
+            ..
+        } = self
+        {
+            justification_latencies.observe(latency.as_secs_f64());
+            if let Some(label) = self.session_label(session) {
+                session_justification_latencies
+                    .with_label_values(&[label.as_str()])
+                    .observe(latency.as_secs_f64());
+            }
+        }
+    }
+
+    pub fn report_justification_imported(&self, session: SessionId) {
+        if let Metrics::Prometheus {
+            session_justifications,
+            ..
+        } = self
+        {
+            if let Some(label) = self.session_label(session) {
+                session_justifications
+                    .with_label_values(&[label.as_str()])
+                    .inc();
+            }
+        }
+    }
+
+    pub fn report_catch_up_blocks(&self, blocks: usize, session: SessionId) {
+        if let Metrics::Prometheus {
+            session_catch_up_blocks,
+            ..
+        } = self
+        {
+            if blocks == 0 {
+                return;
+            }
+            if let Some(label) = self.session_label(session) {
+                session_catch_up_blocks
+                    .with_label_values(&[label.as_str()])
+                    .inc_by(blocks as u64);
+            }
+        }
+    }
+
+    pub fn report_request_served(&self, outcome: ServingOutcome, serving_time: Duration) {
+        if let Metrics::Prometheus {
+            served_requests,
+            request_serving_times,
+            ..
+        } = self
+        {
+            if let Some(counter) = served_requests.get(&outcome) {
+                counter.inc();
+            }
+            if outcome != ServingOutcome::LimitExceeded {
+                request_serving_times.observe(serving_time.as_secs_f64());
+            }
+        }
+    }
+
+    pub fn report_finalization_commit(&self, duration: Duration, blocks: usize) {
+        if let Metrics::Prometheus {
+            finalization_commits,
+            blocks_per_finalization_commit,
+            ..
+        } = self
+        {
+            finalization_commits.observe(duration.as_secs_f64());
+            blocks_per_finalization_commit.observe(blocks as f64);
+        }
+    }
+
+    pub fn report_justification_overlay_depth(&self, depth: usize) {
+        if let Metrics::Prometheus {
+            justification_overlay_depth,
+            ..
+        } = self
+        {
+            justification_overlay_depth.set(depth as u64);
+        }
+    }
+
+    pub fn report_session_latencies(&self, latencies: &SessionLatencies) {
+        if let Metrics::Prometheus {
+            session_latency_session,
+            session_latency_median,
+            session_latency_p90,
+            session_latency_p99,
+            session_latency_max,
+            ..
+        } = self
+        {
+            let millis = |latency: Duration| latency.as_millis().try_into().unwrap_or(u64::MAX);
+            session_latency_session.set(latencies.session.0.into());
+            session_latency_median.set(millis(latencies.median));
+            session_latency_p90.set(millis(latencies.p90));
+            session_latency_p99.set(millis(latencies.p99));
+            session_latency_max.set(millis(latencies.max));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use substrate_prometheus_endpoint::Registry;
+
+    use super::{Metrics, SESSION_LABEL_WINDOW};
+    use crate::session::SessionId;
+
+    fn justifications(metrics: &Metrics, slot: &str) -> u64 {
+        match metrics {
+            Metrics::Prometheus {
+                session_justifications,
+                ..
+            } => session_justifications.with_label_values(&[slot]).get(),
+            Metrics::Noop => panic!("metrics should be registered"),
+        }
+    }
+
+    #[test]
+    fn sessions_take_over_slots_only_from_older_ones() {
+        let metrics = Metrics::new(Some(Registry::new())).expect("metrics register");
+        let session = SessionId(3);
+        let later = SessionId(3 + SESSION_LABEL_WINDOW);
+        metrics.report_justification_imported(session);
+        metrics.report_justification_imported(session);
+        assert_eq!(justifications(&metrics, "3"), 2);
+        metrics.report_justification_imported(later);
+        assert_eq!(justifications(&metrics, "3"), 1);
+        // Reports of the older session would mix with the newer one, so they get dropped.
+        metrics.report_justification_imported(session);
+        assert_eq!(justifications(&metrics, "3"), 1);
+        assert_eq!(justifications(&metrics, "4"), 0);
+    }
 }
@@ -13,7 +13,7 @@ use crate::{
     sync::{
         mock::{MockBlock, MockHeader, MockIdentifier, MockJustification, MockNotification},
         Block, BlockImport, BlockStatus, ChainStatus, ChainStatusNotifier, FinalizationStatus,
-        Finalizer, Header, Justification as JustificationT, Verifier,
+        FinalizedId, Finalizer, Header, Justification as JustificationT, OffloadedCheck, Verifier,
     },
     BlockIdentifier,
 };
@@ -24,6 +24,7 @@ struct BackendStorage {
     blockchain: HashMap<MockIdentifier, MockBlock>,
     finalized: Vec<MockIdentifier>,
     prune_candidates: HashSet<MockIdentifier>,
+    failing_finalizations: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -95,6 +96,7 @@ impl Backend {
             blockchain: HashMap::from([(id.clone(), block)]),
             finalized: vec![id],
             prune_candidates: HashSet::new(),
+            failing_finalizations: 0,
         }));
 
         Self {
@@ -103,6 +105,11 @@ impl Backend {
         }
     }
 
+    /// Makes the next `count` finalizations fail with a transient error.
+    pub fn fail_finalizations(&self, count: usize) {
+        self.inner.lock().failing_finalizations = count;
+    }
+
     fn notify_imported(&self, header: MockHeader) {
         self.notification_sender
             .unbounded_send(MockNotification::BlockImported(header))
@@ -158,12 +165,19 @@ impl Display for FinalizerError {
 impl Finalizer<MockJustification> for Backend {
     type Error = FinalizerError;
 
-    fn finalize(&self, justification: MockJustification) -> Result<(), Self::Error> {
+    fn finalize(
+        &self,
+        justification: MockJustification,
+    ) -> Result<FinalizedId<MockIdentifier>, Self::Error> {
         if !justification.is_correct {
             panic!("finalizing block with an incorrect justification: {justification:?}");
         }
 
         let mut storage = self.inner.lock();
+        if storage.failing_finalizations > 0 {
+            storage.failing_finalizations -= 1;
+            return Err(FinalizerError);
+        }
 
         let header = justification.header();
         let parent_id = match justification.header().parent_id() {
@@ -189,27 +203,26 @@ impl Finalizer<MockJustification> for Backend {
             None => 0,
         };
 
-        // Check if the previous block was finalized, or this is the last block of the current
-        // session
+        // Check if the block is above the top finalized one, but not beyond the last block of the
+        // current session, the blocks in between get finalized with it
         let allowed_numbers = match storage.finalized.last() {
-            Some(id) => [
-                id.number + 1,
-                storage.session_boundary_info.last_block_of_session(
-                    storage
-                        .session_boundary_info
-                        .session_id_from_block_num(id.number + 1),
-                ),
-            ],
-            None => [
-                0,
-                storage
+            Some(id) => {
+                id.number + 1
+                    ..=storage.session_boundary_info.last_block_of_session(
+                        storage
+                            .session_boundary_info
+                            .session_id_from_block_num(id.number + 1),
+                    )
+            }
+            None => {
+                0..=storage
                     .session_boundary_info
-                    .last_block_of_session(SessionId(0)),
-            ],
+                    .last_block_of_session(SessionId(0))
+            }
         };
 
         if !allowed_numbers.contains(&finalizing_id.number) {
-            panic!("finalizing a block that is not a child of top finalized (round {:?}), nor the last of a session (round {:?}): round {:?}", allowed_numbers[0], allowed_numbers[1], finalizing_id.number);
+            panic!("finalizing a block that is not above the top finalized (round {:?}), or beyond the last of a session (round {:?}): round {:?}", allowed_numbers.start(), allowed_numbers.end(), finalizing_id.number);
         }
 
         let mut blocks_to_finalize = VecDeque::new();
@@ -258,7 +271,11 @@ impl Finalizer<MockJustification> for Backend {
 
         self.notify_finalized(header);
 
-        Ok(())
+        Ok(FinalizedId::new(finalizing_id))
+    }
+
+    fn is_transient(_: &Self::Error) -> bool {
+        true
     }
 }
 
@@ -374,12 +391,13 @@ impl ChainStatus<MockBlock, MockJustification> for Backend {
             BlockStatus::Unknown => Err(StatusError),
             _ => {
                 let storage = self.inner.lock();
-                for (stored_id, block) in storage.blockchain.iter() {
-                    if stored_id.number() == id.number + 1 {
-                        return Ok(Vec::from([block.header().clone()]));
-                    }
-                }
-                Ok(Vec::new())
+                Ok(storage
+                    .blockchain
+                    .values()
+                    .map(|block| block.header())
+                    .filter(|header| header.parent_id().as_ref() == Some(&id))
+                    .cloned()
+                    .collect())
             }
         }
     }
@@ -427,4 +445,13 @@ impl Verifier<MockJustification> for Backend {
             false => Err(Self::Error::IncorrectJustification),
         }
     }
+
+    fn is_invalid(error: &Self::Error) -> bool {
+        matches!(error, VerifierError::IncorrectJustification)
+    }
+
+    fn offload(&mut self, justification: &MockJustification) -> Option<OffloadedCheck> {
+        let is_correct = justification.is_correct;
+        Some(Box::new(move || is_correct))
+    }
 }
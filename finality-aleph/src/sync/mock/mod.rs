@@ -1,6 +1,10 @@
-use std::hash::Hash;
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    hash::Hash,
+};
 
 use parity_scale_codec::{Decode, Encode};
+use rand::RngCore;
 use sp_core::H256;
 
 use crate::{
@@ -46,6 +50,12 @@ impl MockIdentifier {
     }
 }
 
+impl Display for MockIdentifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(f, "#{} ({})", self.number, self.hash)
+    }
+}
+
 impl BlockIdentifier for MockIdentifier {
     fn number(&self) -> u32 {
         self.number
@@ -78,6 +88,16 @@ impl MockHeader {
         self.id.random_child()
     }
 
+    /// A child with the hash taken from the generator, so that seeded ones always make the same.
+    pub fn seeded_child<R: RngCore>(&self, rng: &mut R) -> Self {
+        let mut hash = [0; 32];
+        rng.fill_bytes(&mut hash);
+        MockHeader {
+            id: MockIdentifier::new(self.id.number + 1, MockHash::from(hash)),
+            parent: Some(self.id.clone()),
+        }
+    }
+
     pub fn random_branch(&self) -> impl Iterator<Item = Self> {
         self.id.random_branch()
     }
@@ -152,6 +172,10 @@ impl Block for MockBlock {
     fn header(&self) -> &Self::Header {
         &self.header
     }
+
+    fn body_matches_header(&self) -> bool {
+        self.verify()
+    }
 }
 
 #[derive(Clone, Hash, Debug, PartialEq, Eq, Encode, Decode)]
@@ -167,6 +191,13 @@ impl MockJustification {
             is_correct: true,
         }
     }
+
+    pub fn invalid_for_header(header: MockHeader) -> Self {
+        Self {
+            header,
+            is_correct: false,
+        }
+    }
 }
 
 impl Header for MockJustification {
@@ -6,26 +6,116 @@ use std::{
 
 use parity_scale_codec::Codec;
 
+mod acknowledgements;
+mod availability;
+mod backfill;
+mod block_fetcher;
+mod block_ids;
+mod capture;
+mod chain_selection;
+#[cfg(test)]
+mod codec_fuzz;
 mod compatibility;
+mod compression;
+mod counters;
 mod data;
+mod dry_run;
+mod finalization_hook;
 mod forest;
 mod handler;
+mod header_chain;
+mod imports;
+mod in_flight;
+mod justification_fetch;
+mod justification_latency;
+mod justification_queue;
+mod latency_budget;
 mod message_limiter;
 mod metrics;
-#[cfg(test)]
+#[cfg(any(test, feature = "simnet"))]
+#[cfg_attr(not(test), allow(dead_code))]
 mod mock;
+mod network_view;
+mod params;
+mod peer_rating;
+mod peer_trace;
+mod priority;
+mod provenance;
+mod range_download;
+mod replay;
+mod request_queue;
+mod roles;
 mod service;
+mod serving_stats;
+mod session_pipeline;
+mod shed;
+#[cfg(feature = "simnet")]
+mod simnet;
+mod snapshot;
+mod status;
 pub mod substrate;
+mod suppression;
 mod task_queue;
 mod tasks;
+#[cfg(test)]
+mod testing;
 mod ticker;
-
+mod v1_shim;
+mod verification_pool;
+mod warp;
+
+pub use availability::Capabilities;
+pub use backfill::{BackfillProgress, BodyBackfill};
+pub use block_fetcher::BlockFetcher;
+pub use block_ids::{CandidateId, FinalizedId};
+pub use capture::{
+    capture_files, CaptureConfig, CaptureError, CaptureReader, CaptureRecord, Capturing, Direction,
+};
+pub use chain_selection::{
+    ChainCandidates, ChainHints, ChainSelectionStrategy, ExternallyHinted, LongestChain,
+    MostRecentlyJustified,
+};
 pub use compatibility::OldSyncCompatibleRequestBlocks;
-pub use service::{DatabaseIO, Service};
+pub use counters::{run_counter_persistence, CounterValues, Counters};
+pub use data::{ForkId, VersionedNetworkData};
+pub use dry_run::VirtualFinality;
+pub use finalization_hook::{
+    FinalizationHook, FinalizationHooks, FinalizedBlock, DEFAULT_HOOK_QUEUE,
+};
+pub use forest::{ForestDump, ForestDumps, VertexContents, VertexDump, VertexInterest};
+pub use imports::{ImportEvent, ImportNotifications, ImportSubscription, ImportedBlock};
+pub use justification_fetch::JustificationFetches;
+pub use network_view::{
+    FinalityStats, FinalityStatus, NetworkFinalityView, PeerFinality, BEHIND_THRESHOLD,
+    NETWORK_STALL_TIMEOUT,
+};
+pub use params::{LocalLimits, Params};
+pub use peer_rating::{
+    PeerScore, RatingParams, ScoreChangeReport, DEFAULT_FORGIVE_AFTER,
+    DEFAULT_SCORE_DECAY_PER_SECOND,
+};
+pub use peer_trace::{PeerTracing, MAX_TRACE_DURATION, PEER_TRACE_LOG_TARGET};
+pub use priority::{PriorityConfig, PriorityTicket};
+pub use provenance::{Provenance, ProvenanceHistory};
+pub use replay::{Decision, Divergence, ReplayError, ReplayReport, Replayer};
+pub use roles::{RolesConfig, ValidatorTicket};
+pub use service::{DatabaseIO, Service, SyncEvent};
+pub use serving_stats::PeerServing;
+#[cfg(feature = "simnet")]
+pub use simnet::{
+    run_simnet, Crash, FaultParseError, NodeReport, SessionRange, SimnetConfig, SimnetError,
+    SimnetReport,
+};
+pub use snapshot::{
+    run_snapshot_exporter, SnapshotHold, SnapshotPoint, SnapshotSubscription, SnapshotTrigger,
+    SnapshotTriggers, MAX_SNAPSHOT_PAUSE, SNAPSHOT_MARKER_FILE,
+};
+pub use status::{StatusReports, SyncStatus};
 pub use substrate::{
     Justification as SubstrateJustification, JustificationTranslator, SessionVerifier,
     SubstrateChainStatus, SubstrateChainStatusNotifier, SubstrateFinalizationInfo, VerifierCache,
 };
+pub use v1_shim::{ShimFeature, V1Shim};
 
 use crate::BlockIdentifier;
 
@@ -45,6 +135,12 @@ pub trait Header: Clone + Codec + Debug + Send + Sync + 'static {
 
     /// The identifier of this block's parent.
     fn parent_id(&self) -> Option<Self::Identifier>;
+
+    /// Whether the header carries a seal. This is only a structural check, the seal itself is
+    /// verified during import.
+    fn is_sealed(&self) -> bool {
+        true
+    }
 }
 
 /// The block, including a header.
@@ -53,6 +149,10 @@ pub trait Block: Clone + Codec + Debug + Send + Sync + 'static {
 
     /// The header of the block.
     fn header(&self) -> &Self::Header;
+
+    /// Whether the body is the one the header commits to, so that blocks from sources we do not
+    /// trust can be checked against headers we already know.
+    fn body_matches_header(&self) -> bool;
 }
 
 /// The block importer.
@@ -77,6 +177,10 @@ pub trait Justification: Clone + Send + Sync + Debug + 'static {
     fn into_unverified(self) -> Self::Unverified;
 }
 
+/// The expensive part of verifying a justification, split off to run on a worker thread. Returns
+/// whether the justification passed it.
+pub type OffloadedCheck = Box<dyn FnOnce() -> bool + Send>;
+
 /// A verifier of justifications.
 pub trait Verifier<J: Justification> {
     type Error: Display;
@@ -84,6 +188,45 @@ pub trait Verifier<J: Justification> {
     /// Verifies the raw justification and returns a full justification if successful, otherwise an
     /// error.
     fn verify(&mut self, justification: J::Unverified) -> Result<J, Self::Error>;
+
+    /// Whether the error proves the justification is forged or broken, rather than that we cannot
+    /// verify it at the moment, e.g. because we do not know the authorities yet.
+    fn is_invalid(error: &Self::Error) -> bool;
+
+    /// Verifies justifications of the last blocks of consecutive sessions, starting with a session
+    /// we know the authorities of. Implementations can learn the authorities of every following
+    /// session from the justified block ending the previous one, the default just verifies every
+    /// justification separately. Returns the justifications verified before the first error.
+    fn verify_session_chain(
+        &mut self,
+        justifications: Vec<J::Unverified>,
+    ) -> (Vec<J>, Option<Self::Error>) {
+        let mut verified = Vec::new();
+        for justification in justifications {
+            match self.verify(justification) {
+                Ok(justification) => verified.push(justification),
+                Err(e) => return (verified, Some(e)),
+            }
+        }
+        (verified, None)
+    }
+
+    /// Verifies the justification of a block we have already finalized, which can be far below
+    /// the sessions kept for verifying incoming justifications, and is never sampled out. The
+    /// default just verifies it as any other.
+    fn verify_finalized(&mut self, justification: J::Unverified) -> Result<J, Self::Error> {
+        self.verify(justification)
+    }
+
+    /// The expensive part of verifying the justification, to run elsewhere ahead of `verify`, if
+    /// there is any. The default keeps all the work in `verify`.
+    fn offload(&mut self, _justification: &J::Unverified) -> Option<OffloadedCheck> {
+        None
+    }
+
+    /// Records that the justification passed its offloaded check, so that verifying it later
+    /// skips the check, for as long as the implementation remembers it.
+    fn passed_check(&mut self, _justification: &J::Unverified) {}
 }
 
 /// A facility for finalizing blocks using justifications.
@@ -91,8 +234,12 @@ pub trait Finalizer<J: Justification> {
     type Error: Display;
 
     /// Finalize a block using this justification. Since the justification contains the header, we
-    /// don't need to additionally specify the block.
-    fn finalize(&self, justification: J) -> Result<(), Self::Error>;
+    /// don't need to additionally specify the block. Returns the identifier of the finalized block.
+    fn finalize(&self, justification: J) -> Result<FinalizedId<BlockIdFor<J>>, Self::Error>;
+
+    /// Whether the error is likely to go away on its own, e.g. an I/O error or a lock held for
+    /// too long, rather than prove the block cannot be finalized, so finalizing is worth retrying.
+    fn is_transient(error: &Self::Error) -> bool;
 }
 
 /// A notification about the chain status changing.
@@ -0,0 +1,311 @@
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use crate::{sync::PeerId, BlockNumber};
+
+/// How many peers we keep the states of, the ones that sent them longest ago are forgotten first.
+const MAX_VIEWED_PEERS: usize = 1024;
+/// States older than this are not counted, the peer most likely disconnected.
+const STATE_EXPIRY: Duration = Duration::from_secs(60);
+/// We are behind when further than this many blocks below the median of the peers.
+pub const BEHIND_THRESHOLD: BlockNumber = 30;
+/// The network is stalled when the median of the peers does not advance for this long.
+pub const NETWORK_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Statistics of the top finalized blocks advertised by the peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FinalityStats {
+    /// How many peers advertised their states recently.
+    pub peers: usize,
+    pub our_finalized: BlockNumber,
+    pub median_finalized: BlockNumber,
+    pub highest_finalized: BlockNumber,
+    /// How many blocks the peers are behind the highest finalized one, at the median, the 90th
+    /// percentile and at most.
+    pub lag_median: BlockNumber,
+    pub lag_p90: BlockNumber,
+    pub lag_max: BlockNumber,
+    /// For how long the median has not advanced.
+    pub median_unchanged_for: Duration,
+}
+
+/// Whether finality is progressing, distinguishing us lagging from the whole network stalling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FinalityStatus {
+    /// No peer advertised its state recently.
+    NoPeers,
+    InSync,
+    /// We are this many blocks below the median of the peers.
+    Behind(BlockNumber),
+    /// The median of the peers has not advanced for this long, while we are not behind.
+    NetworkStalled(Duration),
+}
+
+impl Display for FinalityStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use FinalityStatus::*;
+        match self {
+            NoPeers => write!(f, "no peers advertised their states recently"),
+            InSync => write!(f, "in sync with the network"),
+            Behind(blocks) => write!(f, "{blocks} blocks behind the network"),
+            NetworkStalled(duration) => write!(
+                f,
+                "finality of the network stalled for {} seconds",
+                duration.as_secs()
+            ),
+        }
+    }
+}
+
+/// The last state advertised by a peer and how long ago.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerFinality<I: PeerId> {
+    pub peer: I,
+    pub finalized: BlockNumber,
+    pub age: Duration,
+    /// Whether the state is fresh enough to be counted in the statistics.
+    pub fresh: bool,
+}
+
+struct PeerState {
+    finalized: BlockNumber,
+    updated: Instant,
+}
+
+struct View<I: PeerId> {
+    peers: LruCache<I, PeerState>,
+    our_finalized: BlockNumber,
+    /// The last median and since when it has not advanced.
+    median: Option<(BlockNumber, Instant)>,
+}
+
+impl<I: PeerId> View<I> {
+    /// The finalized numbers of the peers with fresh states, sorted.
+    fn fresh_finalized(&self, now: Instant) -> Vec<BlockNumber> {
+        let mut finalized: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, state)| now.saturating_duration_since(state.updated) < STATE_EXPIRY)
+            .map(|(_, state)| state.finalized)
+            .collect();
+        finalized.sort_unstable();
+        finalized
+    }
+
+    fn update_median(&mut self, now: Instant) {
+        let finalized = self.fresh_finalized(now);
+        let median = match finalized.get(finalized.len().saturating_sub(1) / 2) {
+            Some(median) => *median,
+            None => return,
+        };
+        match self.median {
+            Some((previous, _)) if previous >= median => {}
+            _ => self.median = Some((median, now)),
+        }
+    }
+}
+
+/// Aggregates the states advertised by all the peers into a view of the finality of the whole
+/// network. The states are not verified, but a minority of lying peers cannot move the median.
+/// Can be cloned and inspected while sync is running.
+#[derive(Clone)]
+pub struct NetworkFinalityView<I: PeerId> {
+    view: Arc<Mutex<View<I>>>,
+}
+
+impl<I: PeerId> NetworkFinalityView<I> {
+    pub fn new() -> Self {
+        NetworkFinalityView {
+            view: Arc::new(Mutex::new(View {
+                peers: LruCache::new(
+                    NonZeroUsize::new(MAX_VIEWED_PEERS).expect("the constant is nonzero"),
+                ),
+                our_finalized: 0,
+                median: None,
+            })),
+        }
+    }
+
+    /// Records the top finalized block advertised by the peer.
+    pub fn peer_state(&self, peer: I, finalized: BlockNumber, now: Instant) {
+        let mut view = self.view.lock();
+        view.peers.put(
+            peer,
+            PeerState {
+                finalized,
+                updated: now,
+            },
+        );
+        view.update_median(now);
+    }
+
+    /// Records our own top finalized block.
+    pub fn our_finalized(&self, finalized: BlockNumber) {
+        self.view.lock().our_finalized = finalized;
+    }
+
+    /// The statistics of the peers that advertised their states recently, if there are any.
+    pub fn stats(&self, now: Instant) -> Option<FinalityStats> {
+        let view = self.view.lock();
+        let finalized = view.fresh_finalized(now);
+        let highest_finalized = *finalized.last()?;
+        let lag_at = |percentile: usize| {
+            let index = (finalized.len() - 1) * (100 - percentile) / 100;
+            highest_finalized - finalized[index]
+        };
+        let (median_finalized, since) = view
+            .median
+            .expect("the median is known once there are fresh states");
+        Some(FinalityStats {
+            peers: finalized.len(),
+            our_finalized: view.our_finalized,
+            median_finalized,
+            highest_finalized,
+            lag_median: lag_at(50),
+            lag_p90: lag_at(90),
+            lag_max: highest_finalized - finalized[0],
+            median_unchanged_for: now.saturating_duration_since(since),
+        })
+    }
+
+    /// The last states of all the peers we remember, the most recently updated first.
+    pub fn peers(&self, now: Instant) -> Vec<PeerFinality<I>> {
+        let mut peers: Vec<_> = self
+            .view
+            .lock()
+            .peers
+            .iter()
+            .map(|(peer, state)| {
+                let age = now.saturating_duration_since(state.updated);
+                PeerFinality {
+                    peer: peer.clone(),
+                    finalized: state.finalized,
+                    age,
+                    fresh: age < STATE_EXPIRY,
+                }
+            })
+            .collect();
+        peers.sort_by_key(|peer| peer.age);
+        peers
+    }
+
+    /// Whether finality is progressing, judging by the recent states of the peers.
+    pub fn status(&self, now: Instant) -> FinalityStatus {
+        let stats = match self.stats(now) {
+            Some(stats) => stats,
+            None => return FinalityStatus::NoPeers,
+        };
+        let behind = stats.median_finalized.saturating_sub(stats.our_finalized);
+        if behind > BEHIND_THRESHOLD {
+            return FinalityStatus::Behind(behind);
+        }
+        if stats.median_unchanged_for >= NETWORK_STALL_TIMEOUT {
+            return FinalityStatus::NetworkStalled(stats.median_unchanged_for);
+        }
+        FinalityStatus::InSync
+    }
+}
+
+impl<I: PeerId> Default for NetworkFinalityView<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{
+        FinalityStatus, NetworkFinalityView, PeerFinality, BEHIND_THRESHOLD, NETWORK_STALL_TIMEOUT,
+        STATE_EXPIRY,
+    };
+
+    #[test]
+    fn computes_lag_percentiles() {
+        let view = NetworkFinalityView::new();
+        let now = Instant::now();
+        for peer in 0..10 {
+            view.peer_state(peer, 100 + peer * 10, now);
+        }
+        view.our_finalized(190);
+        let stats = view.stats(now).expect("there are peers");
+        assert_eq!(stats.peers, 10);
+        assert_eq!(stats.our_finalized, 190);
+        assert_eq!(stats.median_finalized, 140);
+        assert_eq!(stats.highest_finalized, 190);
+        assert_eq!(stats.lag_median, 50);
+        assert_eq!(stats.lag_p90, 90);
+        assert_eq!(stats.lag_max, 90);
+    }
+
+    #[test]
+    fn reports_freshness_of_peers() {
+        let view = NetworkFinalityView::new();
+        let now = Instant::now();
+        view.peer_state(1, 100, now);
+        view.peer_state(2, 120, now + STATE_EXPIRY);
+        assert_eq!(
+            view.peers(now + STATE_EXPIRY),
+            vec![
+                PeerFinality {
+                    peer: 2,
+                    finalized: 120,
+                    age: Duration::ZERO,
+                    fresh: true,
+                },
+                PeerFinality {
+                    peer: 1,
+                    finalized: 100,
+                    age: STATE_EXPIRY,
+                    fresh: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn forgets_stale_states() {
+        let view = NetworkFinalityView::new();
+        let now = Instant::now();
+        view.peer_state(1, 100, now);
+        assert_eq!(view.status(now + STATE_EXPIRY), FinalityStatus::NoPeers);
+        assert!(view.stats(now + STATE_EXPIRY).is_none());
+    }
+
+    #[test]
+    fn distinguishes_being_behind_from_network_stall() {
+        let view = NetworkFinalityView::new();
+        let now = Instant::now();
+        for peer in 0..3 {
+            view.peer_state(peer, 1000, now);
+        }
+        view.our_finalized(1000 - BEHIND_THRESHOLD - 1);
+        assert_eq!(
+            view.status(now),
+            FinalityStatus::Behind(BEHIND_THRESHOLD + 1)
+        );
+        view.our_finalized(1000);
+        assert_eq!(view.status(now), FinalityStatus::InSync);
+        let later = now + NETWORK_STALL_TIMEOUT;
+        for peer in 0..3 {
+            view.peer_state(peer, 1000, later);
+        }
+        assert_eq!(
+            view.status(later),
+            FinalityStatus::NetworkStalled(NETWORK_STALL_TIMEOUT)
+        );
+        let even_later = later + Duration::from_secs(1);
+        for peer in 0..2 {
+            view.peer_state(peer, 1001, even_later);
+        }
+        assert_eq!(view.status(even_later), FinalityStatus::InSync);
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use primitives::{BlockNumber, SyncParams, MIN_SYNC_MAX_BATCH_BYTES};
+
+use crate::sync::{
+    data::{DEFAULT_SYNC_MESSAGE_SIZE, MAX_SYNC_MESSAGE_SIZE},
+    peer_rating::RatingParams,
+};
+
+/// Local limits on the sync parameters. They can only make the on-chain parameters stricter,
+/// values looser than the on-chain ones are ignored. The message limits are not on chain, they
+/// replace the defaults, but are clamped to sane values. Neither is the rating of peers, which is
+/// used as is.
+#[derive(Clone, Debug, Default)]
+pub struct LocalLimits {
+    pub serving_window: Option<BlockNumber>,
+    pub max_batch_bytes: Option<u32>,
+    pub min_broadcast_period: Option<Duration>,
+    pub max_broadcast_period: Option<Duration>,
+    pub max_message_bytes: Option<u32>,
+    pub max_response_blocks: Option<u32>,
+    pub peer_rating: RatingParams,
+}
+
+/// The sync parameters in effect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Params {
+    /// How far below our top finalized block we serve requests.
+    pub serving_window: BlockNumber,
+    /// Maximal size of a single response message.
+    pub max_batch_bytes: usize,
+    /// Minimal time between two broadcasts.
+    pub broadcast_cooldown: Duration,
+    /// Maximal time between two broadcasts.
+    pub broadcast_period: Duration,
+    /// Maximal size of any single message, sent or received.
+    pub max_message_bytes: u32,
+    /// Maximal number of blocks served in response to a single request, no limit if `None`.
+    pub max_response_blocks: Option<usize>,
+    /// How quickly the misbehavior of peers gets forgiven.
+    pub peer_rating: RatingParams,
+}
+
+impl Params {
+    /// Combines the on-chain parameters with the local limits. Invalid on-chain parameters are
+    /// replaced with the defaults.
+    ///
+    /// The broadcast bounds can only be narrowed, so the cooldown can only get longer and the
+    /// period shorter, both staying within the on-chain bounds.
+    pub fn new(on_chain: SyncParams, local: &LocalLimits) -> Self {
+        let on_chain = match on_chain.is_valid() {
+            true => on_chain,
+            false => SyncParams::default(),
+        };
+        let serving_window = local
+            .serving_window
+            .map_or(on_chain.serving_window, |window| {
+                window.min(on_chain.serving_window)
+            });
+        let max_message_bytes = local
+            .max_message_bytes
+            .unwrap_or(DEFAULT_SYNC_MESSAGE_SIZE)
+            .clamp(MIN_SYNC_MAX_BATCH_BYTES, MAX_SYNC_MESSAGE_SIZE);
+        let max_batch_bytes = local
+            .max_batch_bytes
+            .map_or(on_chain.max_batch_bytes, |bytes| {
+                bytes.min(on_chain.max_batch_bytes)
+            })
+            .clamp(MIN_SYNC_MAX_BATCH_BYTES, max_message_bytes)
+            as usize;
+        // A response has to contain at least a single block to make progress.
+        let max_response_blocks = local
+            .max_response_blocks
+            .map(|blocks| blocks.max(1) as usize);
+        let min_period = Duration::from_millis(on_chain.min_broadcast_period_ms);
+        let max_period = Duration::from_millis(on_chain.max_broadcast_period_ms);
+        let broadcast_cooldown = local
+            .min_broadcast_period
+            .map_or(min_period, |period| period.max(min_period))
+            .min(max_period);
+        let broadcast_period = local
+            .max_broadcast_period
+            .map_or(max_period, |period| period.min(max_period))
+            .max(broadcast_cooldown);
+        Params {
+            serving_window,
+            max_batch_bytes,
+            broadcast_cooldown,
+            broadcast_period,
+            max_message_bytes,
+            max_response_blocks,
+            peer_rating: local.peer_rating,
+        }
+    }
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params::new(SyncParams::default(), &LocalLimits::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use primitives::{SyncParams, MIN_SYNC_MAX_BATCH_BYTES};
+
+    use super::{LocalLimits, Params};
+    use crate::sync::{
+        data::{DEFAULT_SYNC_MESSAGE_SIZE, MAX_SYNC_MESSAGE_SIZE},
+        peer_rating::RatingParams,
+    };
+
+    fn on_chain() -> SyncParams {
+        SyncParams {
+            serving_window: 1000,
+            max_batch_bytes: 10 * 1024 * 1024,
+            min_broadcast_period_ms: 1000,
+            max_broadcast_period_ms: 4000,
+        }
+    }
+
+    #[test]
+    fn uses_on_chain_params_without_local_limits() {
+        let params = Params::new(on_chain(), &LocalLimits::default());
+        assert_eq!(
+            params,
+            Params {
+                serving_window: 1000,
+                max_batch_bytes: 10 * 1024 * 1024,
+                broadcast_cooldown: Duration::from_millis(1000),
+                broadcast_period: Duration::from_millis(4000),
+                max_message_bytes: DEFAULT_SYNC_MESSAGE_SIZE,
+                max_response_blocks: None,
+                peer_rating: RatingParams::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn local_limits_tighten() {
+        let local = LocalLimits {
+            serving_window: Some(100),
+            max_batch_bytes: Some(8 * 1024 * 1024),
+            min_broadcast_period: Some(Duration::from_millis(2000)),
+            max_broadcast_period: Some(Duration::from_millis(3000)),
+            ..Default::default()
+        };
+        let params = Params::new(on_chain(), &local);
+        assert_eq!(
+            params,
+            Params {
+                serving_window: 100,
+                max_batch_bytes: 8 * 1024 * 1024,
+                broadcast_cooldown: Duration::from_millis(2000),
+                broadcast_period: Duration::from_millis(3000),
+                max_message_bytes: DEFAULT_SYNC_MESSAGE_SIZE,
+                max_response_blocks: None,
+                peer_rating: RatingParams::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn local_limits_do_not_loosen() {
+        let local = LocalLimits {
+            serving_window: Some(100_000),
+            max_batch_bytes: Some(MAX_SYNC_MESSAGE_SIZE * 2),
+            min_broadcast_period: Some(Duration::from_millis(10)),
+            max_broadcast_period: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert_eq!(
+            Params::new(on_chain(), &local),
+            Params::new(on_chain(), &LocalLimits::default())
+        );
+    }
+
+    #[test]
+    fn clamps_message_limits() {
+        let local = LocalLimits {
+            max_message_bytes: Some(MAX_SYNC_MESSAGE_SIZE * 2),
+            max_response_blocks: Some(0),
+            ..Default::default()
+        };
+        let params = Params::new(on_chain(), &local);
+        assert_eq!(params.max_message_bytes, MAX_SYNC_MESSAGE_SIZE);
+        assert_eq!(params.max_response_blocks, Some(1));
+        let local = LocalLimits {
+            max_message_bytes: Some(1024),
+            ..Default::default()
+        };
+        let params = Params::new(on_chain(), &local);
+        assert_eq!(params.max_message_bytes, MIN_SYNC_MAX_BATCH_BYTES);
+        // Responses never get larger than any message.
+        assert_eq!(params.max_batch_bytes, MIN_SYNC_MAX_BATCH_BYTES as usize);
+    }
+
+    #[test]
+    fn broadcast_bounds_stay_consistent() {
+        let local = LocalLimits {
+            min_broadcast_period: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let params = Params::new(on_chain(), &local);
+        assert_eq!(params.broadcast_cooldown, Duration::from_millis(4000));
+        assert_eq!(params.broadcast_period, Duration::from_millis(4000));
+    }
+
+    #[test]
+    fn ignores_invalid_on_chain_params() {
+        let invalid = SyncParams {
+            min_broadcast_period_ms: 5000,
+            max_broadcast_period_ms: 1000,
+            ..on_chain()
+        };
+        assert_eq!(
+            Params::new(invalid, &LocalLimits::default()),
+            Params::default()
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Error as FmtError, Formatter},
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+
+use crate::{network::Penalty, sync::PeerId};
+
+/// How many peers we keep the score of, the ones that misbehaved longest ago are forgotten first.
+const MAX_RATED_PEERS: usize = 1024;
+/// A peer reaching this score gets disconnected.
+const DISCONNECT_SCORE: u32 = 100;
+/// A peer reaching this score gets banned.
+const BAN_SCORE: u32 = 200;
+/// How many recent score changes are remembered for every peer.
+const MAX_HISTORY: usize = 16;
+/// By default this many points of the score are forgiven every second.
+pub const DEFAULT_SCORE_DECAY_PER_SECOND: u32 = 1;
+/// By default a peer not misbehaving for this long is forgiven completely.
+pub const DEFAULT_FORGIVE_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// How quickly the misbehavior of peers gets forgiven.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RatingParams {
+    /// How many points of the score are forgiven every second.
+    pub decay_per_second: u32,
+    /// After this long without misbehaving the score of a peer is cleared, so that a peer
+    /// having trouble once, e.g. restarting in the middle of a response, starts over.
+    pub forgive_after: Duration,
+}
+
+impl Default for RatingParams {
+    fn default() -> Self {
+        RatingParams {
+            decay_per_second: DEFAULT_SCORE_DECAY_PER_SECOND,
+            forgive_after: DEFAULT_FORGIVE_AFTER,
+        }
+    }
+}
+
+/// Ways in which a peer can misbehave in the sync protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// Sent a justification with a bad signature.
+    InvalidJustification,
+    /// Sent data that is broken by itself, e.g. headers not forming a chain.
+    MalformedData,
+    /// Sent a response larger than any honest node would.
+    OversizedResponse,
+    /// Sent blocks we did not ask for and cannot use.
+    UnrequestedData,
+    /// Sent requests faster than allowed.
+    RequestFlood,
+}
+
+impl Misbehavior {
+    fn cost(&self) -> u32 {
+        use Misbehavior::*;
+        match self {
+            InvalidJustification | MalformedData => 50,
+            OversizedResponse => 30,
+            // Might happen to honest peers when our forest changed in the meantime.
+            UnrequestedData => 10,
+            // Honest peers catching up might send bursts, so only sustained flooding gets punished.
+            RequestFlood => 5,
+        }
+    }
+}
+
+impl Display for Misbehavior {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use Misbehavior::*;
+        match self {
+            InvalidJustification => write!(f, "invalid justification"),
+            MalformedData => write!(f, "malformed data"),
+            OversizedResponse => write!(f, "oversized response"),
+            UnrequestedData => write!(f, "unrequested data"),
+            RequestFlood => write!(f, "request flood"),
+        }
+    }
+}
+
+struct ScoreChange {
+    misbehavior: Misbehavior,
+    score: u32,
+    penalty: Option<Penalty>,
+    at: Instant,
+}
+
+/// A past misbehavior of a peer and what it cost.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScoreChangeReport {
+    pub misbehavior: Misbehavior,
+    /// The score of the peer right after the misbehavior.
+    pub score: u32,
+    pub penalty: Option<Penalty>,
+    /// How long ago the peer misbehaved.
+    pub age: Duration,
+}
+
+/// The current score of a peer together with its recent misbehavior.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerScore<I: PeerId> {
+    pub peer: I,
+    pub score: u32,
+    /// The most recent first.
+    pub history: Vec<ScoreChangeReport>,
+}
+
+struct Rating {
+    score: u32,
+    updated: Instant,
+    disconnected: bool,
+    history: VecDeque<ScoreChange>,
+}
+
+impl Rating {
+    fn new(now: Instant) -> Self {
+        Rating {
+            score: 0,
+            updated: now,
+            disconnected: false,
+            history: VecDeque::new(),
+        }
+    }
+
+    fn forgiven(&self, now: Instant, params: &RatingParams) -> bool {
+        self.history.back().map_or(true, |change| {
+            now.saturating_duration_since(change.at) >= params.forgive_after
+        })
+    }
+
+    /// The score after the decay, and how many whole seconds of decay it took into account.
+    fn decayed(&self, now: Instant, params: &RatingParams) -> (u32, u64) {
+        let seconds = now.saturating_duration_since(self.updated).as_secs();
+        if self.forgiven(now, params) {
+            return (0, seconds);
+        }
+        let forgiven = seconds
+            .saturating_mul(params.decay_per_second.into())
+            .try_into()
+            .unwrap_or(u32::MAX);
+        (self.score.saturating_sub(forgiven), seconds)
+    }
+
+    fn decay(&mut self, now: Instant, params: &RatingParams) {
+        let (score, seconds) = self.decayed(now, params);
+        self.score = score;
+        // Only whole seconds are consumed, so frequent reports do not stop the decay.
+        self.updated += Duration::from_secs(seconds);
+        if self.score < DISCONNECT_SCORE {
+            self.disconnected = false;
+        }
+    }
+
+    fn record(&mut self, misbehavior: Misbehavior, penalty: Option<Penalty>, now: Instant) {
+        if self.history.len() >= MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(ScoreChange {
+            misbehavior,
+            score: self.score,
+            penalty,
+            at: now,
+        });
+    }
+}
+
+/// Scores the peers misbehaving in the sync protocol, deciding when to penalize them. The scores
+/// decay over time and are cleared after a while without misbehavior, so that occasional
+/// mistakes of honest peers are forgiven.
+pub struct PeerRatings<I: PeerId> {
+    ratings: LruCache<I, Rating>,
+    params: RatingParams,
+}
+
+impl<I: PeerId> PeerRatings<I> {
+    pub fn new(params: RatingParams) -> Self {
+        PeerRatings {
+            ratings: LruCache::new(
+                NonZeroUsize::new(MAX_RATED_PEERS).expect("the constant is nonzero"),
+            ),
+            params,
+        }
+    }
+
+    /// Records the misbehavior of the peer, returns the penalty it deserves, if any. A peer is
+    /// disconnected only once until its score decays below the threshold again, and starts over
+    /// after getting banned, only its history is kept.
+    pub fn report(&mut self, peer: I, misbehavior: Misbehavior, now: Instant) -> Option<Penalty> {
+        if !self.ratings.contains(&peer) {
+            self.ratings.put(peer.clone(), Rating::new(now));
+        }
+        let rating = self
+            .ratings
+            .get_mut(&peer)
+            .expect("the rating was just inserted");
+        rating.decay(now, &self.params);
+        rating.score = rating.score.saturating_add(misbehavior.cost());
+        let penalty = if rating.score >= BAN_SCORE {
+            Some(Penalty::Ban)
+        } else if rating.score >= DISCONNECT_SCORE && !rating.disconnected {
+            rating.disconnected = true;
+            Some(Penalty::Disconnect)
+        } else {
+            None
+        };
+        rating.record(misbehavior, penalty, now);
+        if penalty == Some(Penalty::Ban) {
+            rating.score = 0;
+            rating.disconnected = false;
+        }
+        penalty
+    }
+
+    /// The current scores of the rated peers with their recent misbehavior, the most recently
+    /// misbehaving first.
+    pub fn scores(&self, now: Instant) -> Vec<PeerScore<I>> {
+        self.ratings
+            .iter()
+            .map(|(peer, rating)| PeerScore {
+                peer: peer.clone(),
+                score: rating.decayed(now, &self.params).0,
+                history: rating
+                    .history
+                    .iter()
+                    .rev()
+                    .map(|change| ScoreChangeReport {
+                        misbehavior: change.misbehavior,
+                        score: change.score,
+                        penalty: change.penalty,
+                        age: now.saturating_duration_since(change.at),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Misbehavior, PeerRatings, RatingParams, ScoreChangeReport, MAX_RATED_PEERS};
+    use crate::{network::Penalty, sync::mock::MockPeerId};
+
+    #[test]
+    fn disconnects_then_bans() {
+        let mut ratings = PeerRatings::<MockPeerId>::new(RatingParams::default());
+        let now = Instant::now();
+        let peer = 7;
+        assert_eq!(
+            ratings.report(peer, Misbehavior::InvalidJustification, now),
+            None
+        );
+        assert_eq!(
+            ratings.report(peer, Misbehavior::InvalidJustification, now),
+            Some(Penalty::Disconnect)
+        );
+        assert_eq!(
+            ratings.report(peer, Misbehavior::InvalidJustification, now),
+            None
+        );
+        assert_eq!(
+            ratings.report(peer, Misbehavior::InvalidJustification, now),
+            Some(Penalty::Ban)
+        );
+        // Starts over after the ban.
+        assert_eq!(
+            ratings.report(peer, Misbehavior::InvalidJustification, now),
+            None
+        );
+    }
+
+    #[test]
+    fn rates_peers_separately() {
+        let mut ratings = PeerRatings::<MockPeerId>::new(RatingParams::default());
+        let now = Instant::now();
+        assert_eq!(ratings.report(1, Misbehavior::MalformedData, now), None);
+        assert_eq!(ratings.report(2, Misbehavior::MalformedData, now), None);
+        assert_eq!(
+            ratings.report(1, Misbehavior::MalformedData, now),
+            Some(Penalty::Disconnect)
+        );
+    }
+
+    #[test]
+    fn forgives_over_time() {
+        let mut ratings = PeerRatings::<MockPeerId>::new(RatingParams::default());
+        let mut now = Instant::now();
+        let peer = 3;
+        for _ in 0..20 {
+            assert_eq!(
+                ratings.report(peer, Misbehavior::UnrequestedData, now),
+                None
+            );
+            now += Duration::from_secs(10);
+        }
+    }
+
+    #[test]
+    fn disconnects_again_after_decay() {
+        let mut ratings = PeerRatings::<MockPeerId>::new(RatingParams::default());
+        let now = Instant::now();
+        let peer = 5;
+        ratings.report(peer, Misbehavior::MalformedData, now);
+        assert_eq!(
+            ratings.report(peer, Misbehavior::MalformedData, now),
+            Some(Penalty::Disconnect)
+        );
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            ratings.report(peer, Misbehavior::MalformedData, later),
+            Some(Penalty::Disconnect)
+        );
+    }
+
+    #[test]
+    fn forgives_completely_after_a_quiet_period() {
+        let params = RatingParams {
+            decay_per_second: 0,
+            forgive_after: Duration::from_secs(60),
+        };
+        let mut ratings = PeerRatings::<MockPeerId>::new(params);
+        let now = Instant::now();
+        let peer = 4;
+        ratings.report(peer, Misbehavior::MalformedData, now);
+        // Without decay the score stays until the peer is forgiven.
+        let later = now + Duration::from_secs(59);
+        assert_eq!(
+            ratings.report(peer, Misbehavior::MalformedData, later),
+            Some(Penalty::Disconnect)
+        );
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(ratings.scores(much_later)[0].score, 0);
+        assert_eq!(
+            ratings.report(peer, Misbehavior::MalformedData, much_later),
+            None
+        );
+    }
+
+    #[test]
+    fn reports_score_history() {
+        let mut ratings = PeerRatings::<MockPeerId>::new(RatingParams::default());
+        let now = Instant::now();
+        ratings.report(1, Misbehavior::MalformedData, now);
+        ratings.report(2, Misbehavior::UnrequestedData, now);
+        ratings.report(1, Misbehavior::InvalidJustification, now);
+        let later = now + Duration::from_secs(10);
+        let scores = ratings.scores(later);
+        assert_eq!(scores.len(), 2);
+        assert_eq!(scores[0].peer, 1);
+        assert_eq!(scores[0].score, 90);
+        assert_eq!(
+            scores[0].history,
+            vec![
+                ScoreChangeReport {
+                    misbehavior: Misbehavior::InvalidJustification,
+                    score: 100,
+                    penalty: Some(Penalty::Disconnect),
+                    age: Duration::from_secs(10),
+                },
+                ScoreChangeReport {
+                    misbehavior: Misbehavior::MalformedData,
+                    score: 50,
+                    penalty: None,
+                    age: Duration::from_secs(10),
+                },
+            ]
+        );
+        assert_eq!(scores[1].peer, 2);
+        assert_eq!(scores[1].score, 0);
+    }
+
+    #[test]
+    fn remembers_limited_number_of_peers() {
+        let mut ratings = PeerRatings::<MockPeerId>::new(RatingParams::default());
+        let now = Instant::now();
+        for peer in 0..=MAX_RATED_PEERS as MockPeerId {
+            ratings.report(peer, Misbehavior::MalformedData, now);
+        }
+        // The first peer was forgotten, so this is its first misbehavior again.
+        assert_eq!(ratings.report(0, Misbehavior::MalformedData, now), None);
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    sync::{
+        data::{BranchKnowledge, NetworkData, Request, ResponseItem},
+        Block, Header, Justification, PeerId,
+    },
+    BlockIdentifier,
+};
+
+/// The log target the messages exchanged with the traced peer are written to.
+pub const PEER_TRACE_LOG_TARGET: &str = "aleph-sync-peer-trace";
+/// The longest a single peer can be traced for, so that a forgotten trace stops on its own.
+pub const MAX_TRACE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Controls which peer, if any, has all its sync messages traced. Can be cloned and switched
+/// while sync is running.
+#[derive(Clone)]
+pub struct PeerTracing<I: PeerId> {
+    traced: Arc<Mutex<Option<(I, Instant)>>>,
+}
+
+impl<I: PeerId> PeerTracing<I> {
+    pub fn new() -> Self {
+        PeerTracing {
+            traced: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Starts tracing the peer, instead of any previously traced one, for the given duration,
+    /// capped at `MAX_TRACE_DURATION`. Returns the duration the peer is actually traced for.
+    pub fn enable(&self, peer: I, duration: Duration) -> Duration {
+        let duration = duration.min(MAX_TRACE_DURATION);
+        *self.traced.lock() = Some((peer, Instant::now() + duration));
+        duration
+    }
+
+    /// Stops tracing.
+    pub fn disable(&self) {
+        *self.traced.lock() = None;
+    }
+
+    /// The currently traced peer, if the trace did not expire yet.
+    pub fn traced_peer(&self) -> Option<I> {
+        let mut traced = self.traced.lock();
+        match &*traced {
+            Some((peer, until)) if Instant::now() < *until => Some(peer.clone()),
+            Some(_) => {
+                *traced = None;
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Whether the messages exchanged with the peer should be traced.
+    pub fn is_traced(&self, peer: &I) -> bool {
+        self.traced_peer().as_ref() == Some(peer)
+    }
+}
+
+impl<I: PeerId> Default for PeerTracing<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn request_summary<J: Justification>(request: &Request<J>) -> String {
+    let branch_knowledge = match request.branch_knowledge() {
+        BranchKnowledge::LowestId(id) => format!("lowest known {id:?}"),
+        BranchKnowledge::TopImported(id) => format!("top imported {id:?}"),
+    };
+    format!(
+        "{:?}, {}, top finalized {:?}",
+        request.target_id(),
+        branch_knowledge,
+        request.state().top_justification().id()
+    )
+}
+
+fn items_summary<B, J>(items: &[ResponseItem<B, J>]) -> String
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    let (mut justifications, mut headers, mut blocks) = (0, 0, 0);
+    for item in items {
+        match item {
+            ResponseItem::Justification(_) => justifications += 1,
+            ResponseItem::Header(_) => headers += 1,
+            ResponseItem::Block(_) => blocks += 1,
+        }
+    }
+    let highest = items
+        .iter()
+        .map(|item| match item {
+            ResponseItem::Justification(justification) => justification.id(),
+            ResponseItem::Header(header) => header.id(),
+            ResponseItem::Block(block) => block.header().id(),
+        })
+        .max_by_key(|id| id.number());
+    format!(
+        "{justifications} justifications, {headers} headers and {blocks} blocks, highest \
+         {highest:?}"
+    )
+}
+
+/// A short, human readable description of the data, without the potentially huge contents.
+pub fn summary<B, J>(data: &NetworkData<B, J>) -> String
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    use NetworkData::*;
+    match data {
+        StateBroadcast(state) => format!(
+            "state broadcast with top finalized {:?}",
+            state.top_justification().id()
+        ),
+        ExtendedStateBroadcast(extended) => format!(
+            "state broadcast with top finalized {:?} and favourite {:?}",
+            extended.state().top_justification().id(),
+            extended.favourite_block().id()
+        ),
+        StateBroadcastResponse(justification, maybe_justification) => format!(
+            "state broadcast response with justifications of {:?} and {:?}",
+            justification.id(),
+            maybe_justification
+                .as_ref()
+                .map(|justification| justification.id())
+        ),
+        BatchedStateBroadcastResponse(justification, justifications) => format!(
+            "batched state broadcast response with justifications of {:?} and {} more up to {:?}",
+            justification.id(),
+            justifications.len(),
+            justifications
+                .last()
+                .map(|justification| justification.id())
+        ),
+        Request(request) => format!("request for {}", request_summary(request)),
+        HeaderRequest(request) => {
+            format!("request for the headers of {}", request_summary(request))
+        }
+        CorrelatedRequest(id, request) => {
+            format!("request {id} for {}", request_summary(request))
+        }
+        RequestResponse(items) => format!("request response with {}", items_summary(items)),
+        CorrelatedResponse(id, items) => {
+            format!("response to request {id} with {}", items_summary(items))
+        }
+        CapabilitiesAnnouncement(capabilities) => {
+            format!("capabilities announcement {capabilities:?}")
+        }
+        AvailabilityRequest => "availability request".to_string(),
+        BodyRequest(request) => format!(
+            "request for the bodies of blocks {} to {}",
+            request.from(),
+            request.to()
+        ),
+        AvailabilityResponse(availability) => format!("availability response {availability:?}"),
+        AcknowledgementRequest(tag) => format!("acknowledgement request {tag}"),
+        Acknowledgement(tag) => format!("acknowledgement {tag}"),
+        PriorityTicket(ticket) => format!("priority ticket with key {:?}", ticket.key()),
+        WarpRequest(session) => format!("warp request from session {}", session.0),
+        WarpResponse(justifications) => format!(
+            "warp response with {} justifications up to {:?}",
+            justifications.len(),
+            justifications
+                .last()
+                .map(|justification| justification.id())
+        ),
+        ValidatorTicket(ticket) => format!("validator ticket with key {:?}", ticket.key()),
+        Announcement(header) => format!("announcement of {:?}", header.id()),
+        ForkIdAnnouncement(fork_id) => format!("announcement of {fork_id}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{PeerTracing, MAX_TRACE_DURATION};
+    use crate::sync::mock::MockPeerId;
+
+    #[test]
+    fn traces_only_the_chosen_peer() {
+        let tracing = PeerTracing::<MockPeerId>::new();
+        assert!(!tracing.is_traced(&1));
+        tracing.enable(1, Duration::from_secs(60));
+        assert!(tracing.is_traced(&1));
+        assert!(!tracing.is_traced(&2));
+        tracing.enable(2, Duration::from_secs(60));
+        assert!(!tracing.is_traced(&1));
+        assert!(tracing.is_traced(&2));
+        tracing.disable();
+        assert_eq!(tracing.traced_peer(), None);
+    }
+
+    #[test]
+    fn trace_expires() {
+        let tracing = PeerTracing::<MockPeerId>::new();
+        tracing.enable(1, Duration::ZERO);
+        assert!(!tracing.is_traced(&1));
+        assert_eq!(
+            tracing.enable(1, MAX_TRACE_DURATION * 2),
+            MAX_TRACE_DURATION
+        );
+        assert!(tracing.is_traced(&1));
+    }
+}
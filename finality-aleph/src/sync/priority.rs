@@ -0,0 +1,190 @@
+use std::{
+    collections::HashSet,
+    fmt::{Display, Error as FmtError, Formatter},
+    num::NonZeroUsize,
+};
+
+use lru::LruCache;
+use parity_scale_codec::{Decode, Encode};
+use sc_network::PeerId as GossipPeerId;
+
+use crate::{
+    aleph_primitives::AuthorityId,
+    crypto::{verify, AuthorityPen, Signature},
+    sync::PeerId,
+};
+
+/// Separates the signatures in tickets from anything else signed with the same key.
+const TICKET_CONTEXT: &[u8] = b"aleph-sync-priority-ticket";
+/// How many authenticated followers we remember, the ones that authenticated longest ago are
+/// forgotten first. They authenticate again with every capabilities announcement anyway.
+const MAX_PRIORITY_PEERS: usize = 256;
+
+/// Peer identifiers that can be signed over, so that tickets cannot be used by other peers.
+pub trait TicketSubject {
+    fn ticket_subject(&self) -> Vec<u8>;
+}
+
+impl TicketSubject for GossipPeerId {
+    fn ticket_subject(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+}
+
+#[cfg(test)]
+impl TicketSubject for u32 {
+    fn ticket_subject(&self) -> Vec<u8> {
+        self.encode()
+    }
+}
+
+fn ticket_message(subject: &[u8]) -> Vec<u8> {
+    let mut message = TICKET_CONTEXT.to_vec();
+    message.extend_from_slice(subject);
+    message
+}
+
+/// A proof that the sending peer belongs to the infrastructure of a validator, entitling it to
+/// priority service from that validator.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct PriorityTicket {
+    key: AuthorityId,
+    signature: Signature,
+}
+
+impl PriorityTicket {
+    /// A ticket for the peer with the identifier, signed with the key of the pen.
+    pub fn new<P: TicketSubject>(pen: &AuthorityPen, peer_id: &P) -> Self {
+        PriorityTicket {
+            key: pen.authority_id(),
+            signature: pen.sign(&ticket_message(&peer_id.ticket_subject())),
+        }
+    }
+
+    pub fn key(&self) -> &AuthorityId {
+        &self.key
+    }
+}
+
+/// Reasons for rejecting a priority ticket.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TicketError {
+    /// The key is not one of the followers we serve with priority.
+    UnknownKey,
+    /// The signature does not match the key and the sending peer.
+    BadSignature,
+}
+
+impl Display for TicketError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use TicketError::*;
+        match self {
+            UnknownKey => write!(f, "the key does not belong to any of our followers"),
+            BadSignature => write!(f, "the signature does not match the key and the peer"),
+        }
+    }
+}
+
+/// How this node takes part in priority service, both as a follower and as a validator.
+#[derive(Clone, Debug, Default)]
+pub struct PriorityConfig {
+    /// The ticket we send to validators offering priority service, if we are a follower.
+    pub ticket: Option<PriorityTicket>,
+    /// The keys of the followers we serve with priority.
+    pub followers: HashSet<AuthorityId>,
+}
+
+/// The peers that proved belonging to the infrastructure of this validator.
+pub struct PriorityPeers<I: PeerId> {
+    followers: HashSet<AuthorityId>,
+    authenticated: LruCache<I, AuthorityId>,
+}
+
+impl<I: PeerId + TicketSubject> PriorityPeers<I> {
+    pub fn new(followers: HashSet<AuthorityId>) -> Self {
+        PriorityPeers {
+            followers,
+            authenticated: LruCache::new(
+                NonZeroUsize::new(MAX_PRIORITY_PEERS).expect("the constant is nonzero"),
+            ),
+        }
+    }
+
+    /// Whether any followers are served with priority at all.
+    pub fn serves_followers(&self) -> bool {
+        !self.followers.is_empty()
+    }
+
+    /// Checks the ticket sent by the peer, and if it is valid serves the peer with priority from
+    /// now on. Returns whether the peer was not authenticated with this key before.
+    pub fn authenticate(&mut self, peer: I, ticket: &PriorityTicket) -> Result<bool, TicketError> {
+        if !self.followers.contains(&ticket.key) {
+            return Err(TicketError::UnknownKey);
+        }
+        if !verify(
+            &ticket.key,
+            &ticket_message(&peer.ticket_subject()),
+            &ticket.signature,
+        ) {
+            return Err(TicketError::BadSignature);
+        }
+        Ok(self.authenticated.put(peer, ticket.key.clone()).as_ref() != Some(&ticket.key))
+    }
+
+    /// Whether the peer is served with priority.
+    pub fn is_priority(&self, peer: &I) -> bool {
+        self.authenticated.contains(peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use sp_keystore::{testing::MemoryKeystore, Keystore};
+
+    use super::{PriorityPeers, PriorityTicket, TicketError};
+    use crate::{
+        aleph_primitives::{AuthorityId, KEY_TYPE},
+        crypto::AuthorityPen,
+    };
+
+    fn pen(seed: &str) -> AuthorityPen {
+        let keystore = Arc::new(MemoryKeystore::new());
+        let key = keystore
+            .ed25519_generate_new(KEY_TYPE, Some(seed))
+            .expect("generating keys works");
+        AuthorityPen::new(AuthorityId::from(key), keystore).expect("the key was just generated")
+    }
+
+    #[test]
+    fn authenticates_followers() {
+        let follower = pen("//Follower");
+        let mut peers = PriorityPeers::new(HashSet::from([follower.authority_id()]));
+        assert!(peers.serves_followers());
+        let ticket = PriorityTicket::new(&follower, &7u32);
+        assert!(!peers.is_priority(&7));
+        assert_eq!(peers.authenticate(7, &ticket), Ok(true));
+        assert_eq!(peers.authenticate(7, &ticket), Ok(false));
+        assert!(peers.is_priority(&7));
+    }
+
+    #[test]
+    fn rejects_tickets_of_other_peers_and_keys() {
+        let follower = pen("//Follower");
+        let stranger = pen("//Stranger");
+        let mut peers = PriorityPeers::new(HashSet::from([follower.authority_id()]));
+        let ticket = PriorityTicket::new(&follower, &7u32);
+        assert_eq!(
+            peers.authenticate(8, &ticket),
+            Err(TicketError::BadSignature)
+        );
+        assert_eq!(
+            peers.authenticate(7, &PriorityTicket::new(&stranger, &7u32)),
+            Err(TicketError::UnknownKey)
+        );
+        assert!(!peers.is_priority(&7));
+        assert!(!peers.is_priority(&8));
+        assert!(!PriorityPeers::<u32>::new(HashSet::new()).serves_followers());
+    }
+}
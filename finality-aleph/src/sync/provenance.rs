@@ -0,0 +1,227 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+
+use crate::{sync::PeerId, BlockIdentifier};
+
+/// How many finalized blocks we remember the provenance of.
+const MAX_HISTORY: usize = 1024;
+/// How many blocks that are not finalized yet we track, data about further ones is not attributed.
+const MAX_PENDING: usize = 4096;
+/// How many peers that supplied the body of a single block we remember.
+const MAX_BODY_SUPPLIERS: usize = 4;
+
+/// The peers that supplied the data which allowed us to finalize a block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provenance<I: PeerId, BI: BlockIdentifier> {
+    pub block: BI,
+    /// `None` if the justification did not come from a peer, e.g. it was produced locally.
+    pub justification_from: Option<I>,
+    /// Empty if the body did not come from a peer, e.g. the block was produced locally.
+    pub bodies_from: Vec<I>,
+}
+
+/// The provenance of the most recently finalized blocks, can be cloned and inspected while sync
+/// is running.
+#[derive(Clone)]
+pub struct ProvenanceHistory<I: PeerId, BI: BlockIdentifier> {
+    history: Arc<Mutex<VecDeque<Provenance<I, BI>>>>,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> ProvenanceHistory<I, BI> {
+    pub fn new() -> Self {
+        ProvenanceHistory {
+            history: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    fn record(&self, provenance: Provenance<I, BI>) {
+        let mut history = self.history.lock();
+        if history.len() == MAX_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(provenance);
+    }
+
+    /// The provenance of at most `limit` most recently finalized blocks, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<Provenance<I, BI>> {
+        self.history
+            .lock()
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// The provenance of the block, if it was finalized recently enough. Useful for attributing
+    /// bad data that was only detected after the import.
+    pub fn of(&self, block: &BI) -> Option<Provenance<I, BI>> {
+        self.history
+            .lock()
+            .iter()
+            .rev()
+            .find(|provenance| &provenance.block == block)
+            .cloned()
+    }
+}
+
+impl<I: PeerId, BI: BlockIdentifier> Default for ProvenanceHistory<I, BI> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Suppliers<I: PeerId> {
+    justification: Option<I>,
+    bodies: Vec<I>,
+}
+
+impl<I: PeerId> Suppliers<I> {
+    fn new() -> Self {
+        Suppliers {
+            justification: None,
+            bodies: Vec::new(),
+        }
+    }
+}
+
+/// Tracks which peers supplied data about blocks that are not finalized yet, and records it in
+/// the history once they get finalized.
+pub struct ProvenanceTracker<I: PeerId, BI: BlockIdentifier> {
+    pending: HashMap<BI, Suppliers<I>>,
+    history: ProvenanceHistory<I, BI>,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> ProvenanceTracker<I, BI> {
+    pub fn new(history: ProvenanceHistory<I, BI>) -> Self {
+        ProvenanceTracker {
+            pending: HashMap::new(),
+            history,
+        }
+    }
+
+    fn suppliers(&mut self, block: BI) -> Option<&mut Suppliers<I>> {
+        if self.pending.len() >= MAX_PENDING && !self.pending.contains_key(&block) {
+            return None;
+        }
+        Some(self.pending.entry(block).or_insert_with(Suppliers::new))
+    }
+
+    /// Remembers that the peer supplied a correct justification of the block, unless some other
+    /// peer did so earlier.
+    pub fn justification_supplied(&mut self, block: BI, peer: I) {
+        if let Some(suppliers) = self.suppliers(block) {
+            suppliers.justification.get_or_insert(peer);
+        }
+    }
+
+    /// Remembers that the peer supplied the body of the block.
+    pub fn body_supplied(&mut self, block: BI, peer: I) {
+        if let Some(suppliers) = self.suppliers(block) {
+            if suppliers.bodies.len() < MAX_BODY_SUPPLIERS && !suppliers.bodies.contains(&peer) {
+                suppliers.bodies.push(peer);
+            }
+        }
+    }
+
+    /// The peers that supplied the body of the block, if it is not finalized yet.
+    pub fn bodies_from(&self, block: &BI) -> Vec<I> {
+        self.pending
+            .get(block)
+            .map(|suppliers| suppliers.bodies.clone())
+            .unwrap_or_default()
+    }
+
+    /// Records the provenance of the finalized block in the history, and forgets about all the
+    /// blocks that cannot be finalized anymore.
+    pub fn finalized(&mut self, block: BI) {
+        let suppliers = self.pending.remove(&block).unwrap_or_else(Suppliers::new);
+        let number = block.number();
+        self.pending.retain(|id, _| id.number() > number);
+        self.history.record(Provenance {
+            block,
+            justification_from: suppliers.justification,
+            bodies_from: suppliers.bodies,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Provenance, ProvenanceHistory, ProvenanceTracker, MAX_HISTORY};
+    use crate::sync::{
+        mock::{MockIdentifier, MockPeerId},
+        Header,
+    };
+
+    fn branch(length: usize) -> Vec<MockIdentifier> {
+        MockIdentifier::new_random(0)
+            .random_branch()
+            .take(length)
+            .map(|header| header.id())
+            .collect()
+    }
+
+    #[test]
+    fn records_suppliers_of_finalized_blocks() {
+        let history = ProvenanceHistory::<MockPeerId, _>::new();
+        let mut tracker = ProvenanceTracker::new(history.clone());
+        let ids = branch(2);
+        tracker.body_supplied(ids[0].clone(), 1);
+        tracker.body_supplied(ids[0].clone(), 2);
+        tracker.body_supplied(ids[0].clone(), 1);
+        tracker.justification_supplied(ids[0].clone(), 3);
+        tracker.justification_supplied(ids[0].clone(), 4);
+        assert_eq!(tracker.bodies_from(&ids[0]), vec![1, 2]);
+        tracker.finalized(ids[0].clone());
+        assert!(tracker.bodies_from(&ids[0]).is_empty());
+        tracker.finalized(ids[1].clone());
+        let expected = Provenance {
+            block: ids[0].clone(),
+            justification_from: Some(3),
+            bodies_from: vec![1, 2],
+        };
+        assert_eq!(history.of(&ids[0]), Some(expected.clone()));
+        assert_eq!(
+            history.recent(10),
+            vec![
+                Provenance {
+                    block: ids[1].clone(),
+                    justification_from: None,
+                    bodies_from: Vec::new(),
+                },
+                expected,
+            ]
+        );
+    }
+
+    #[test]
+    fn forgets_blocks_that_cannot_be_finalized() {
+        let history = ProvenanceHistory::<MockPeerId, _>::new();
+        let mut tracker = ProvenanceTracker::new(history.clone());
+        let ids = branch(3);
+        let fork = MockIdentifier::new_random(1);
+        tracker.justification_supplied(fork.clone(), 1);
+        tracker.justification_supplied(ids[2].clone(), 2);
+        tracker.finalized(ids[1].clone());
+        assert_eq!(tracker.pending.len(), 1);
+        assert!(history.of(&fork).is_none());
+    }
+
+    #[test]
+    fn remembers_limited_history() {
+        let history = ProvenanceHistory::<MockPeerId, _>::new();
+        let mut tracker = ProvenanceTracker::new(history.clone());
+        let ids = branch(MAX_HISTORY + 1);
+        for id in &ids {
+            tracker.finalized(id.clone());
+        }
+        assert!(history.of(&ids[0]).is_none());
+        assert_eq!(history.recent(usize::MAX).len(), MAX_HISTORY);
+        assert_eq!(history.recent(1)[0].block, ids[MAX_HISTORY]);
+    }
+}
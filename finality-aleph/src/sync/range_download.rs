@@ -0,0 +1,351 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    sync::{
+        data::{BodyRequest, MAX_BODY_REQUEST_BLOCKS},
+        Block, Header, PeerId,
+    },
+    BlockIdentifier, BlockNumber,
+};
+
+/// How often the download makes progress, issuing the requests for the ranges that are due.
+pub const RANGE_DOWNLOAD_TICK: Duration = Duration::from_millis(500);
+/// How many blocks below the network we have to be for the gap to be downloaded in parallel.
+pub const PARALLEL_DOWNLOAD_DISTANCE: BlockNumber = 4 * MAX_BODY_REQUEST_BLOCKS;
+/// How many blocks a range has, as many as a peer serves in response to a single body request.
+const RANGE_LENGTH: BlockNumber = MAX_BODY_REQUEST_BLOCKS;
+/// How many ranges right above the blocks passed for import are downloaded at once, each from
+/// a different peer. Bounds how many downloaded blocks wait for the ones below them.
+const MAX_RANGES: BlockNumber = 8;
+/// How long a peer has to deliver a range before it is requested from someone else.
+const RANGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type IdFor<B> = <<B as Block>::Header as Header>::Identifier;
+
+/// The first block of the range containing the block with the given number. The ranges do not
+/// move when the download advances, so that the partially downloaded ones stay useful.
+fn range_start(number: BlockNumber) -> BlockNumber {
+    number.saturating_sub(1) / RANGE_LENGTH * RANGE_LENGTH + 1
+}
+
+struct Assignment<I: PeerId> {
+    peer: I,
+    /// The last block of the range requested, lower than the end of the range if the target was.
+    to: BlockNumber,
+    sent_at: Instant,
+}
+
+/// Downloads the gap between our top finalized block and the finalized blocks of the network
+/// when far behind, splitting it into ranges requested from several peers at once. The blocks
+/// are passed for import in order, and the ranges that time out or do not extend the chain are
+/// requested again from someone else.
+pub struct RangeDownload<I: PeerId, B: Block> {
+    /// The last block passed for import, or the top finalized one if higher, the download
+    /// continues right above it. `None` when not downloading.
+    base: Option<IdFor<B>>,
+    /// The highest block to download.
+    target: BlockNumber,
+    /// The requested ranges, by their first blocks.
+    assigned: HashMap<BlockNumber, Assignment<I>>,
+    /// The peers that failed to deliver the ranges, by their first blocks.
+    failed: HashMap<BlockNumber, HashSet<I>>,
+    /// The downloaded blocks waiting for the ones below them, with the peers that sent them.
+    downloaded: BTreeMap<BlockNumber, (I, B)>,
+}
+
+impl<I: PeerId, B: Block> RangeDownload<I, B> {
+    pub fn new() -> Self {
+        RangeDownload {
+            base: None,
+            target: 0,
+            assigned: HashMap::new(),
+            failed: HashMap::new(),
+            downloaded: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the gap is being downloaded.
+    pub fn active(&self) -> bool {
+        self.base.is_some()
+    }
+
+    /// How many ranges wait for their responses.
+    pub fn in_flight(&self) -> usize {
+        self.assigned.len()
+    }
+
+    fn stop(&mut self) {
+        self.base = None;
+        self.assigned.clear();
+        self.failed.clear();
+        self.downloaded.clear();
+    }
+
+    /// Updates the download with our top finalized block and the finalized number of the
+    /// network. Starts it once `PARALLEL_DOWNLOAD_DISTANCE` blocks behind, and stops it once
+    /// the network is reached. Nothing above `highest_holdable` is downloaded, as we could not
+    /// handle it.
+    pub fn update(
+        &mut self,
+        top_finalized: IdFor<B>,
+        network_finalized: BlockNumber,
+        highest_holdable: BlockNumber,
+    ) {
+        let rebase = match &self.base {
+            Some(base) => base.number() < top_finalized.number(),
+            None => {
+                network_finalized.saturating_sub(top_finalized.number())
+                    >= PARALLEL_DOWNLOAD_DISTANCE
+            }
+        };
+        if rebase {
+            self.base = Some(top_finalized);
+        }
+        let base = match &self.base {
+            Some(base) => base.number(),
+            None => return,
+        };
+        if base >= network_finalized {
+            self.stop();
+            return;
+        }
+        self.target = network_finalized.min(highest_holdable);
+        self.downloaded = self.downloaded.split_off(&(base + 1));
+        let finished = |from: &BlockNumber| from.saturating_add(RANGE_LENGTH - 1) <= base;
+        self.assigned.retain(|from, _| !finished(from));
+        self.failed.retain(|from, _| !finished(from));
+    }
+
+    /// The requests to send now, each to a different peer out of the candidates, given with
+    /// their finalized numbers. Peers only get ranges they have finalized, preferably ones they
+    /// did not fail to deliver before.
+    pub fn requests(
+        &mut self,
+        candidates: &[(I, BlockNumber)],
+        now: Instant,
+    ) -> Vec<(I, BodyRequest)> {
+        let base = match &self.base {
+            Some(base) => base.number(),
+            None => return Vec::new(),
+        };
+        let timed_out: Vec<_> = self
+            .assigned
+            .iter()
+            .filter(|(_, assignment)| {
+                now.saturating_duration_since(assignment.sent_at) >= RANGE_TIMEOUT
+            })
+            .map(|(from, _)| *from)
+            .collect();
+        for from in timed_out {
+            if let Some(assignment) = self.assigned.remove(&from) {
+                self.failed.entry(from).or_default().insert(assignment.peer);
+            }
+        }
+        let mut busy: HashSet<_> = self
+            .assigned
+            .values()
+            .map(|assignment| assignment.peer.clone())
+            .collect();
+        let mut requests = Vec::new();
+        let first = range_start(base + 1);
+        for from in (0..MAX_RANGES).map(|range| first + range * RANGE_LENGTH) {
+            if from > self.target {
+                break;
+            }
+            if self.assigned.contains_key(&from) {
+                continue;
+            }
+            let to = from.saturating_add(RANGE_LENGTH - 1).min(self.target);
+            let mut missing =
+                (from.max(base + 1)..=to).filter(|number| !self.downloaded.contains_key(number));
+            let missing = match missing.next() {
+                Some(missing) => missing,
+                None => continue,
+            };
+            let eligible: Vec<_> = candidates
+                .iter()
+                .filter(|(peer, finalized)| *finalized >= to && !busy.contains(peer))
+                .map(|(peer, _)| peer)
+                .collect();
+            let failed = self.failed.get(&from);
+            let peer = match eligible
+                .iter()
+                .find(|peer| failed.map_or(true, |failed| !failed.contains(**peer)))
+                .or_else(|| eligible.first())
+            {
+                Some(peer) => (*peer).clone(),
+                None => break,
+            };
+            busy.insert(peer.clone());
+            self.assigned.insert(
+                from,
+                Assignment {
+                    peer: peer.clone(),
+                    to,
+                    sent_at: now,
+                },
+            );
+            requests.push((peer, BodyRequest::new(missing, to)));
+        }
+        requests
+    }
+
+    /// Takes the block if it belongs to a range requested from the peer, gives it back
+    /// otherwise.
+    pub fn downloaded(&mut self, peer: &I, block: B) -> Option<B> {
+        let base = match &self.base {
+            Some(base) => base.number(),
+            None => return Some(block),
+        };
+        let number = block.header().id().number();
+        let from = range_start(number);
+        let to = match self.assigned.get(&from) {
+            Some(assignment) if &assignment.peer == peer && number <= assignment.to => {
+                assignment.to
+            }
+            _ => return Some(block),
+        };
+        if number > base {
+            self.downloaded.insert(number, (peer.clone(), block));
+        }
+        if (from.max(base + 1)..=to).all(|n| self.downloaded.contains_key(&n)) {
+            self.assigned.remove(&from);
+        }
+        None
+    }
+
+    /// The downloaded blocks right above the base, in order, to be passed for import. A block
+    /// not extending the chain gets the rest of its range dropped and requested again, from
+    /// someone else.
+    pub fn ready(&mut self) -> Vec<(I, B)> {
+        let mut ready = Vec::new();
+        let base = match &mut self.base {
+            Some(base) => base,
+            None => return ready,
+        };
+        while let Some((peer, block)) = self.downloaded.remove(&(base.number() + 1)) {
+            let header = block.header();
+            if header.parent_id().as_ref() != Some(base) {
+                let from = range_start(header.id().number());
+                let end = from.saturating_add(RANGE_LENGTH);
+                self.downloaded
+                    .retain(|number, _| *number < from || *number >= end);
+                self.failed.entry(from).or_default().insert(peer);
+                break;
+            }
+            *base = header.id();
+            ready.push((peer, block));
+        }
+        ready
+    }
+}
+
+impl<I: PeerId, B: Block> Default for RangeDownload<I, B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{RangeDownload, PARALLEL_DOWNLOAD_DISTANCE, RANGE_LENGTH, RANGE_TIMEOUT};
+    use crate::sync::{
+        data::BodyRequest,
+        mock::{MockBlock, MockHeader, MockPeerId},
+        Header,
+    };
+
+    fn chain(length: usize) -> Vec<MockBlock> {
+        MockHeader::genesis()
+            .random_branch()
+            .take(length)
+            .map(|header| MockBlock::new(header, true))
+            .collect()
+    }
+
+    #[test]
+    fn starts_only_when_far_behind() {
+        let mut download = RangeDownload::<MockPeerId, MockBlock>::new();
+        let genesis = MockHeader::genesis().id();
+        download.update(genesis.clone(), PARALLEL_DOWNLOAD_DISTANCE - 1, u32::MAX);
+        assert!(!download.active());
+        download.update(genesis.clone(), PARALLEL_DOWNLOAD_DISTANCE, u32::MAX);
+        assert!(download.active());
+        download.update(genesis, 0, u32::MAX);
+        assert!(!download.active());
+    }
+
+    #[test]
+    fn downloads_ranges_in_parallel_and_imports_in_order() {
+        let mut download = RangeDownload::<MockPeerId, MockBlock>::new();
+        let blocks = chain(3 * RANGE_LENGTH as usize);
+        download.update(
+            MockHeader::genesis().id(),
+            PARALLEL_DOWNLOAD_DISTANCE,
+            u32::MAX,
+        );
+        let now = Instant::now();
+        let requests = download.requests(&[(1, 1000), (2, 1000), (3, 1000)], now);
+        assert_eq!(
+            requests,
+            vec![
+                (1, BodyRequest::new(1, RANGE_LENGTH)),
+                (2, BodyRequest::new(RANGE_LENGTH + 1, 2 * RANGE_LENGTH)),
+                (3, BodyRequest::new(2 * RANGE_LENGTH + 1, 3 * RANGE_LENGTH)),
+            ]
+        );
+        // All the peers are busy.
+        assert!(download.requests(&[(1, 1000)], now).is_empty());
+        let range = |index: usize| {
+            blocks[index * RANGE_LENGTH as usize..(index + 1) * RANGE_LENGTH as usize].to_vec()
+        };
+        for block in range(1) {
+            assert!(download.downloaded(&2, block).is_none());
+        }
+        // Nothing connects to the base yet.
+        assert!(download.ready().is_empty());
+        // Not requested from this peer.
+        assert!(download.downloaded(&3, blocks[0].clone()).is_some());
+        for block in range(0) {
+            assert!(download.downloaded(&1, block).is_none());
+        }
+        let ready: Vec<_> = download
+            .ready()
+            .into_iter()
+            .map(|(_, block)| block)
+            .collect();
+        assert_eq!(ready, blocks[..2 * RANGE_LENGTH as usize].to_vec());
+        assert_eq!(download.in_flight(), 1);
+    }
+
+    #[test]
+    fn reissues_timed_out_and_broken_ranges() {
+        let mut download = RangeDownload::<MockPeerId, MockBlock>::new();
+        let blocks = chain(RANGE_LENGTH as usize);
+        download.update(
+            MockHeader::genesis().id(),
+            PARALLEL_DOWNLOAD_DISTANCE,
+            u32::MAX,
+        );
+        let now = Instant::now();
+        let first = download.requests(&[(1, 1000)], now);
+        assert_eq!(first, vec![(1, BodyRequest::new(1, RANGE_LENGTH))]);
+        let later = now + RANGE_TIMEOUT;
+        let second = download.requests(&[(1, 1000), (2, 1000)], later);
+        assert_eq!(second[0], (2, BodyRequest::new(1, RANGE_LENGTH)));
+        // A block from another chain, so the range has to be downloaded again.
+        let broken = MockBlock::new(MockHeader::random_parentless(1), true);
+        assert!(download.downloaded(&2, broken).is_none());
+        for block in blocks.iter().skip(1).cloned() {
+            assert!(download.downloaded(&2, block).is_none());
+        }
+        assert!(download.ready().is_empty());
+        let third = download.requests(&[(2, 1000), (3, 1000)], later);
+        assert_eq!(third[0], (3, BodyRequest::new(1, RANGE_LENGTH)));
+    }
+}
@@ -0,0 +1,685 @@
+//! Replays of captured sync traffic against a freshly constructed handler. The frames received by
+//! the capturing node are fed to the handler the way the sync service would feed them, and the
+//! responses it decides on are compared with the ones the capturing node actually sent, so that
+//! changes in the decisions show up on real traffic. Only responses are compared, the requests
+//! and broadcasts depend on timers and randomness. Priority service is not reproduced, so requests
+//! of priority peers from outside the serving window get refused in the replay.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Display, Error as FmtError, Formatter},
+    iter,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+use parity_scale_codec::Decode;
+use tokio::time::timeout;
+
+use crate::{
+    network::CancelToken,
+    session::SessionBoundaryInfo,
+    sync::{
+        capture::{CaptureError, CaptureReader, CaptureRecord, Direction},
+        compression::Compression,
+        data::{
+            NetworkData, Request, ResponseItem, State, VersionedNetworkData, MAX_SYNC_MESSAGE_SIZE,
+        },
+        finalization_hook::FinalizationHooks,
+        handler::{Action, DatabaseIO, HandleStateAction, Handler, HandlerTypes},
+        metrics::Metrics,
+        provenance::ProvenanceHistory,
+        Block, BlockIdFor, BlockImport, ChainStatus, ChainStatusNotification, ChainStatusNotifier,
+        Finalizer, Header, Justification, Verifier, LOG_TARGET,
+    },
+    BlockIdentifier, BlockNumber,
+};
+
+/// The longest we wait for the blocks from a response to get imported, if the capturing node
+/// waited that long for the next frame.
+const MAX_SETTLE_TIME: Duration = Duration::from_secs(1);
+/// How many divergences are described in the report, further ones are only counted.
+const MAX_DESCRIBED_DIVERGENCES: usize = 100;
+
+/// A single item of a response to a peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision<BI: BlockIdentifier> {
+    /// A justification sent in response to a state broadcast.
+    StateJustification(BI),
+    Justification(BI),
+    Header(BI),
+    Block(BI),
+    /// A justification sent in response to a warp request.
+    WarpJustification(BI),
+}
+
+impl<BI: BlockIdentifier> Display for Decision<BI> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use Decision::*;
+        match self {
+            StateJustification(id) => write!(f, "state response justification of {id:?}"),
+            Justification(id) => write!(f, "justification of {id:?}"),
+            Header(id) => write!(f, "header of {id:?}"),
+            Block(id) => write!(f, "block {id:?}"),
+            WarpJustification(id) => write!(f, "warp justification of {id:?}"),
+        }
+    }
+}
+
+/// The items of the response, nothing if the data is not a response.
+fn decisions<B, J>(data: NetworkData<B, J>) -> Vec<Decision<BlockIdFor<J>>>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+{
+    use NetworkData::*;
+    match data {
+        StateBroadcastResponse(justification, maybe_justification) => iter::once(justification)
+            .chain(maybe_justification)
+            .map(|justification| Decision::StateJustification(justification.id()))
+            .collect(),
+        BatchedStateBroadcastResponse(justification, justifications) => iter::once(justification)
+            .chain(justifications)
+            .map(|justification| Decision::StateJustification(justification.id()))
+            .collect(),
+        RequestResponse(response_items) | CorrelatedResponse(_, response_items) => response_items
+            .into_iter()
+            .map(|item| match item {
+                ResponseItem::Justification(justification) => {
+                    Decision::Justification(justification.id())
+                }
+                ResponseItem::Header(header) => Decision::Header(header.id()),
+                ResponseItem::Block(block) => Decision::Block(block.header().id()),
+            })
+            .collect(),
+        WarpResponse(justifications) => justifications
+            .into_iter()
+            .map(|justification| Decision::WarpJustification(justification.id()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// A point at which the replay decided differently than the capturing node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence<BI: BlockIdentifier> {
+    /// The index of the record at which the divergence got noticed.
+    pub record: usize,
+    pub peer: String,
+    /// What the capturing node sent, if anything.
+    pub original: Option<Decision<BI>>,
+    /// What the replay decided to send, if anything.
+    pub replayed: Option<Decision<BI>>,
+}
+
+impl<BI: BlockIdentifier> Display for Divergence<BI> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        let describe = |decision: &Option<Decision<BI>>| match decision {
+            Some(decision) => decision.to_string(),
+            None => "nothing".to_string(),
+        };
+        write!(
+            f,
+            "record {}, peer {}: sent {}, replayed {}",
+            self.record,
+            self.peer,
+            describe(&self.original),
+            describe(&self.replayed)
+        )
+    }
+}
+
+/// How the replay went.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayReport<BI: BlockIdentifier> {
+    pub records: usize,
+    /// The frames received by the capturing node, which got fed to the handler.
+    pub received: usize,
+    /// The frames that could not be interpreted, e.g. because of unknown versions.
+    pub undecodable: usize,
+    /// How many times the handler refused the data, the capturing node might have as well.
+    pub handler_errors: usize,
+    /// The response items identical in the capture and in the replay.
+    pub matched: usize,
+    pub mismatched: usize,
+    /// The response items sent by the capturing node, but not by the replay.
+    pub missing: usize,
+    /// The response items sent by the replay, but not by the capturing node.
+    pub extra: usize,
+    /// The first divergences, in the order they got noticed.
+    pub divergences: Vec<Divergence<BI>>,
+}
+
+impl<BI: BlockIdentifier> ReplayReport<BI> {
+    fn new() -> Self {
+        ReplayReport {
+            records: 0,
+            received: 0,
+            undecodable: 0,
+            handler_errors: 0,
+            matched: 0,
+            mismatched: 0,
+            missing: 0,
+            extra: 0,
+            divergences: Vec::new(),
+        }
+    }
+
+    fn diverged(&mut self, divergence: Divergence<BI>) {
+        match (&divergence.original, &divergence.replayed) {
+            (Some(_), Some(_)) => self.mismatched += 1,
+            (Some(_), None) => self.missing += 1,
+            (None, _) => self.extra += 1,
+        }
+        if self.divergences.len() < MAX_DESCRIBED_DIVERGENCES {
+            self.divergences.push(divergence);
+        }
+    }
+
+    /// Whether the replay decided exactly as the capturing node did.
+    pub fn identical(&self) -> bool {
+        self.mismatched == 0 && self.missing == 0 && self.extra == 0
+    }
+}
+
+impl<BI: BlockIdentifier> Display for ReplayReport<BI> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(
+            f,
+            "replayed {} records, {} received frames, {} undecodable, {} refused by the handler; \
+            {} response items matched, {} mismatched, {} missing, {} extra",
+            self.records,
+            self.received,
+            self.undecodable,
+            self.handler_errors,
+            self.matched,
+            self.mismatched,
+            self.missing,
+            self.extra
+        )?;
+        for divergence in &self.divergences {
+            write!(f, "\n{divergence}")?;
+        }
+        let undescribed = self.mismatched + self.missing + self.extra - self.divergences.len();
+        if undescribed > 0 {
+            write!(f, "\n...and {undescribed} more divergences")?;
+        }
+        Ok(())
+    }
+}
+
+/// Ways in which a replay can fail.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The handler could not be set up over the database.
+    Initialization(String),
+    Capture(CaptureError),
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use ReplayError::*;
+        match self {
+            Initialization(e) => write!(f, "failed to set up the handler: {e}"),
+            Capture(e) => write!(f, "failed to read the capture: {e}"),
+        }
+    }
+}
+
+impl From<CaptureError> for ReplayError {
+    fn from(e: CaptureError) -> Self {
+        ReplayError::Capture(e)
+    }
+}
+
+/// Feeds captured frames to a handler over a database, which should be a copy of the one of the
+/// capturing node from before the capture, since the replay imports and finalizes blocks.
+pub struct Replayer<B, J, CS, V, F, BI, CN>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+    CS: ChainStatus<B, J>,
+    V: Verifier<J>,
+    F: Finalizer<J>,
+    BI: BlockImport<B>,
+    CN: ChainStatusNotifier<J::Header>,
+{
+    handler: Handler<B, String, J, CS, V, F, BI>,
+    chain_events: CN,
+    compression: Compression,
+    original: HashMap<String, VecDeque<Decision<BlockIdFor<J>>>>,
+    replayed: HashMap<String, VecDeque<Decision<BlockIdFor<J>>>>,
+    last_timestamp: Option<u64>,
+    imports_pending: bool,
+    report: ReplayReport<BlockIdFor<J>>,
+}
+
+impl<B, J, CS, V, F, BI, CN> Replayer<B, J, CS, V, F, BI, CN>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+    CS: ChainStatus<B, J>,
+    V: Verifier<J>,
+    F: Finalizer<J>,
+    BI: BlockImport<B>,
+    CN: ChainStatusNotifier<J::Header>,
+{
+    /// A replayer with a handler constructed the way the sync service constructs it.
+    pub fn new(
+        database_io: DatabaseIO<B, J, CS, F, BI>,
+        chain_events: CN,
+        verifier: V,
+        session_info: SessionBoundaryInfo,
+        serving_window: BlockNumber,
+        finalization_batch: BlockNumber,
+        max_response_blocks: Option<usize>,
+    ) -> Result<Self, ReplayError> {
+        let handler = Handler::new(
+            database_io,
+            verifier,
+            session_info,
+            serving_window,
+            finalization_batch,
+            max_response_blocks,
+            ProvenanceHistory::new(),
+            FinalizationHooks::new(),
+            Metrics::noop(),
+        )
+        .map_err(|e| ReplayError::Initialization(e.to_string()))?;
+        Ok(Replayer {
+            handler,
+            chain_events,
+            compression: Compression::default(),
+            original: HashMap::new(),
+            replayed: HashMap::new(),
+            last_timestamp: None,
+            imports_pending: false,
+            report: ReplayReport::new(),
+        })
+    }
+
+    /// Replays all the records of the capture files, in order.
+    pub async fn replay_files(&mut self, files: &[PathBuf]) -> Result<(), ReplayError> {
+        for path in files {
+            for record in CaptureReader::open(path)? {
+                self.replay(record?).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replays a single record, the records have to be replayed in the order they got captured.
+    pub async fn replay(&mut self, record: CaptureRecord) {
+        let index = self.report.records;
+        self.report.records += 1;
+        let gap = match self.last_timestamp {
+            Some(last) => Duration::from_millis(record.timestamp_millis.saturating_sub(last)),
+            None => Duration::ZERO,
+        };
+        self.last_timestamp = Some(record.timestamp_millis);
+        // The imports had the time until the next frame on the capturing node as well.
+        let settle_time = match self.imports_pending {
+            true => gap.min(MAX_SETTLE_TIME),
+            false => Duration::ZERO,
+        };
+        self.imports_pending = false;
+        self.settle(settle_time).await;
+        let data = match self.interpret(&record) {
+            Some(data) => data,
+            None => {
+                self.report.undecodable += 1;
+                return;
+            }
+        };
+        match (record.direction, &record.peers[..]) {
+            (Direction::Received, [peer]) => {
+                self.report.received += 1;
+                self.handle_received(data, peer.clone());
+                self.compare(index, peer);
+            }
+            (Direction::Sent, [peer]) => {
+                let decisions = decisions(data);
+                if !decisions.is_empty() {
+                    self.original
+                        .entry(peer.clone())
+                        .or_default()
+                        .extend(decisions);
+                    self.compare(index, peer);
+                }
+            }
+            // Broadcasts and random requests are not responses.
+            _ => (),
+        }
+    }
+
+    /// Ends the replay, everything not sent by one of the sides by now counts as divergent.
+    pub fn finish(mut self) -> ReplayReport<BlockIdFor<J>> {
+        let record = self.report.records;
+        let mut peers: Vec<_> = self
+            .original
+            .keys()
+            .chain(self.replayed.keys())
+            .cloned()
+            .collect();
+        peers.sort();
+        peers.dedup();
+        for peer in peers {
+            for original in self.original.remove(&peer).unwrap_or_default() {
+                self.report.diverged(Divergence {
+                    record,
+                    peer: peer.clone(),
+                    original: Some(original),
+                    replayed: None,
+                });
+            }
+            for replayed in self.replayed.remove(&peer).unwrap_or_default() {
+                self.report.diverged(Divergence {
+                    record,
+                    peer: peer.clone(),
+                    original: None,
+                    replayed: Some(replayed),
+                });
+            }
+        }
+        self.report
+    }
+
+    /// Unwraps the frame the way the versioned network does.
+    fn interpret(&self, record: &CaptureRecord) -> Option<NetworkData<B, J>> {
+        use VersionedNetworkData::*;
+        match record.decode_frame::<VersionedNetworkData<B, J>>().ok()? {
+            Other(_, _) => None,
+            V1(data) => Some(data.into()),
+            V2(data) | V3(data) | V5(data) | V6(_, data) => Some(data),
+            V4(compressed) => {
+                let decompressed = self
+                    .compression
+                    .decompress(&compressed, MAX_SYNC_MESSAGE_SIZE as usize)
+                    .ok()?;
+                NetworkData::decode(&mut &decompressed[..]).ok()
+            }
+        }
+    }
+
+    /// Passes the chain events to the handler, waiting for them at most the provided time.
+    async fn settle(&mut self, time: Duration) {
+        let deadline = Instant::now() + time;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match timeout(remaining, self.chain_events.next()).await {
+                Ok(Ok(ChainStatusNotification::BlockImported(header))) => {
+                    if let Err(e) = self.handler.block_imported(header) {
+                        debug!(target: LOG_TARGET, "Replayed import failed: {}.", e);
+                    }
+                }
+                Ok(Ok(ChainStatusNotification::BlockFinalized(_))) => (),
+                Ok(Err(e)) => {
+                    warn!(target: LOG_TARGET, "Chain events ended during replay: {}.", e);
+                    return;
+                }
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Handles the data the way the sync service does, at once rather than through its queues.
+    fn handle_received(&mut self, data: NetworkData<B, J>, peer: String) {
+        use NetworkData::*;
+        match data {
+            StateBroadcast(state) => self.handle_state(state, peer),
+            ExtendedStateBroadcast(extended) => {
+                let (state, favourite_block) = extended.into_parts();
+                self.handle_state(state, peer.clone());
+                let result = self.handler.handle_favourite_block(favourite_block, peer);
+                self.handled(result);
+            }
+            Announcement(header) => {
+                let result = self.handler.handle_favourite_block(header, peer);
+                self.handled(result);
+            }
+            StateBroadcastResponse(justification, maybe_justification) => {
+                let (_, maybe_error) =
+                    self.handler
+                        .handle_state_response(justification, maybe_justification, peer);
+                self.handled(maybe_error.map_or(Ok(()), Err));
+            }
+            BatchedStateBroadcastResponse(justification, justifications) => {
+                let (_, maybe_error) = self.handler.handle_batched_state_response(
+                    iter::once(justification).chain(justifications).collect(),
+                    peer,
+                );
+                self.handled(maybe_error.map_or(Ok(()), Err));
+            }
+            // The state is handled before the request, which waits in a queue in the service.
+            Request(request) | CorrelatedRequest(_, request) => {
+                self.handle_state(request.state().clone(), peer.clone());
+                self.handle_request(request, peer, true);
+            }
+            HeaderRequest(request) => {
+                self.handle_state(request.state().clone(), peer.clone());
+                self.handle_request(request, peer, false);
+            }
+            RequestResponse(response_items) | CorrelatedResponse(_, response_items) => {
+                self.imports_pending = true;
+                let (_, maybe_error) =
+                    self.handler
+                        .handle_request_response(response_items, peer, &CancelToken::new());
+                self.handled(maybe_error.map_or(Ok(()), Err));
+            }
+            WarpRequest(from) => match self.handler.handle_warp_request(from) {
+                Ok(justifications) => self.respond(peer, WarpResponse(justifications)),
+                Err(e) => self.handled::<()>(Err(e)),
+            },
+            WarpResponse(justifications) => {
+                let (_, maybe_error) = self.handler.handle_warp_response(justifications, peer);
+                self.handled(maybe_error.map_or(Ok(()), Err));
+            }
+            // Nothing the handler decides about.
+            _ => (),
+        }
+    }
+
+    fn handle_state(&mut self, state: State<J>, peer: String) {
+        match self.handler.handle_state(state, peer.clone()) {
+            Ok(HandleStateAction::Response(data)) => self.respond(peer, data),
+            result => self.handled(result),
+        }
+    }
+
+    fn handle_request(&mut self, request: Request<J>, peer: String, bodies: bool) {
+        // The capture does not say when the peers disconnected, so everything is done in full.
+        let cancel = CancelToken::new();
+        let result = match bodies {
+            true => self.handler.handle_request(request, &cancel),
+            false => self.handler.handle_headers_request(request, &cancel),
+        };
+        match result {
+            Ok(Action::Response(response_items)) => {
+                self.respond(peer, NetworkData::RequestResponse(response_items))
+            }
+            result => self.handled(result),
+        }
+    }
+
+    fn handled<T>(
+        &mut self,
+        result: Result<T, <Handler<B, String, J, CS, V, F, BI> as HandlerTypes>::Error>,
+    ) {
+        if let Err(e) = result {
+            debug!(target: LOG_TARGET, "Replayed data refused: {}.", e);
+            self.report.handler_errors += 1;
+        }
+    }
+
+    fn respond(&mut self, peer: String, data: NetworkData<B, J>) {
+        self.replayed
+            .entry(peer)
+            .or_default()
+            .extend(decisions(data));
+    }
+
+    /// Matches what the capturing node sent to the peer with what the replay decided, in order.
+    fn compare(&mut self, record: usize, peer: &str) {
+        let (original, replayed) = match (self.original.get_mut(peer), self.replayed.get_mut(peer))
+        {
+            (Some(original), Some(replayed)) => (original, replayed),
+            _ => return,
+        };
+        while !original.is_empty() && !replayed.is_empty() {
+            let sent = original.pop_front();
+            let decided = replayed.pop_front();
+            match sent == decided {
+                true => self.report.matched += 1,
+                false => self.report.diverged(Divergence {
+                    record,
+                    peer: peer.to_string(),
+                    original: sent,
+                    replayed: decided,
+                }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parity_scale_codec::Encode;
+
+    use super::Replayer;
+    use crate::{
+        session::testing::session_boundary_info,
+        sync::{
+            capture::{CaptureRecord, Direction},
+            data::{BranchKnowledge, NetworkData, Request, State, VersionedNetworkData},
+            handler::{Action, DatabaseIO, HandleStateAction},
+            mock::{Backend, MockBlock, MockHeader, MockJustification},
+            BlockImport, ChainStatus, ChainStatusNotifier, Finalizer, Header, Justification,
+        },
+        BlockNumber,
+    };
+
+    const PEER: &str = "peer";
+
+    type TestReplayer<CN> =
+        Replayer<MockBlock, MockJustification, Backend, Backend, Backend, Backend, CN>;
+    type TestData = NetworkData<MockBlock, MockJustification>;
+
+    /// A replayer over a backend with a short finalized chain, and a request for its top from a
+    /// peer at genesis.
+    fn setup() -> (
+        TestReplayer<impl ChainStatusNotifier<MockHeader>>,
+        Request<MockJustification>,
+    ) {
+        let (mut backend, notifier) = Backend::setup(session_boundary_info());
+        let genesis = backend.top_finalized().expect("genesis");
+        for header in genesis.header().random_branch().take(5) {
+            backend.import_block(MockBlock::new(header.clone(), true));
+            backend
+                .finalize(MockJustification::for_header(header))
+                .expect("finalizing in order");
+        }
+        let top = backend.top_finalized().expect("top").header().id();
+        let request = Request::new(
+            top.clone(),
+            BranchKnowledge::LowestId(top),
+            State::new(genesis.into_unverified()),
+        );
+        let replayer = Replayer::new(
+            DatabaseIO::new(backend.clone(), backend.clone(), backend.clone()),
+            notifier,
+            backend,
+            session_boundary_info(),
+            BlockNumber::MAX,
+            1,
+            None,
+        )
+        .expect("mock backend works");
+        (replayer, request)
+    }
+
+    /// What the handler of the replayer responds to the request with, the items split among two
+    /// messages like a large response would be.
+    fn responses<CN: ChainStatusNotifier<MockHeader>>(
+        replayer: &mut TestReplayer<CN>,
+        request: &Request<MockJustification>,
+    ) -> Vec<TestData> {
+        let mut responses = Vec::new();
+        if let HandleStateAction::Response(data) = replayer
+            .handler
+            .handle_state(request.state().clone(), PEER.to_string())
+            .expect("correct state")
+        {
+            responses.push(data);
+        }
+        match replayer
+            .handler
+            .handle_request(request.clone(), &CancelToken::new())
+            .expect("correct request")
+        {
+            Action::Response(response_items) => {
+                let (first, second) = response_items.split_at(response_items.len() / 2);
+                responses.push(NetworkData::RequestResponse(first.to_vec()));
+                responses.push(NetworkData::RequestResponse(second.to_vec()));
+            }
+            other => panic!("expected a response, got {other:?}"),
+        }
+        responses
+    }
+
+    fn record(direction: Direction, data: TestData) -> CaptureRecord {
+        CaptureRecord {
+            timestamp_millis: 0,
+            direction,
+            peers: vec![PEER.to_string()],
+            frame: VersionedNetworkData::V5(data).encode(),
+        }
+    }
+
+    #[tokio::test]
+    async fn matches_identical_responses() {
+        let (mut replayer, request) = setup();
+        let responses = responses(&mut replayer, &request);
+        let items: usize = responses
+            .iter()
+            .map(|data| super::decisions(data.clone()).len())
+            .sum();
+        replayer
+            .replay(record(Direction::Received, NetworkData::Request(request)))
+            .await;
+        for data in responses {
+            replayer.replay(record(Direction::Sent, data)).await;
+        }
+        let report = replayer.finish();
+        assert!(report.identical(), "{report}");
+        assert_eq!(report.records, 4);
+        assert_eq!(report.received, 1);
+        assert_eq!(report.matched, items);
+    }
+
+    #[tokio::test]
+    async fn reports_divergent_responses() {
+        let (mut replayer, request) = setup();
+        let mut responses = responses(&mut replayer, &request);
+        // The capturing node did not send the last item.
+        let last = match responses.last_mut() {
+            Some(NetworkData::RequestResponse(response_items)) => {
+                response_items.pop().expect("the response is not empty")
+            }
+            _ => panic!("the request response comes last"),
+        };
+        let last = super::decisions(NetworkData::RequestResponse(vec![last]));
+        replayer
+            .replay(record(Direction::Received, NetworkData::Request(request)))
+            .await;
+        for data in responses {
+            replayer.replay(record(Direction::Sent, data)).await;
+        }
+        let report = replayer.finish();
+        assert!(!report.identical());
+        assert_eq!((report.mismatched, report.missing, report.extra), (0, 0, 1));
+        let divergence = &report.divergences[0];
+        assert_eq!(divergence.original, None);
+        assert_eq!(divergence.replayed.as_ref(), last.first());
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Error as FmtError, Formatter},
+    mem,
+    num::NonZeroUsize,
+    time::Instant,
+};
+
+use lru::LruCache;
+use rate_limiter::TokenBucket;
+
+use crate::sync::PeerId;
+
+/// How many requests a single peer can send every second, with bursts of up to that many.
+const REQUESTS_PER_SECOND: usize = 10;
+/// How many requests wait for handling at most, any more are dropped.
+const MAX_QUEUED_REQUESTS: usize = 256;
+/// How many peers we keep the request rates of, the ones that sent requests longest ago are
+/// forgotten first.
+const MAX_LIMITED_PEERS: usize = 1024;
+
+/// Why a request was dropped instead of being queued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    /// The peer sent requests faster than allowed.
+    RateExceeded,
+    /// Too many requests are waiting for handling already.
+    QueueFull,
+}
+
+impl Display for DropReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use DropReason::*;
+        match self {
+            RateExceeded => write!(
+                f,
+                "the peer exceeded {REQUESTS_PER_SECOND} requests per second"
+            ),
+            QueueFull => write!(f, "{MAX_QUEUED_REQUESTS} requests are waiting already"),
+        }
+    }
+}
+
+/// Requests from peers waiting for handling, limited both in the rate at which every peer can
+/// send them and in how many of them can wait at once, so that no peer can flood us with
+/// requests that are expensive to handle.
+pub struct RequestQueue<I: PeerId, R> {
+    rates: LruCache<I, TokenBucket>,
+    queue: VecDeque<(I, R)>,
+}
+
+impl<I: PeerId, R> RequestQueue<I, R> {
+    pub fn new() -> Self {
+        RequestQueue {
+            rates: LruCache::new(
+                NonZeroUsize::new(MAX_LIMITED_PEERS).expect("the constant is nonzero"),
+            ),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Whether the peer can send another request now. Dropped requests do not count towards
+    /// the rate, so a peer that exceeded it can send again as soon as it slows down.
+    fn within_rate(&mut self, peer: &I, now: Instant) -> bool {
+        if !self.rates.contains(peer) {
+            self.rates.put(
+                peer.clone(),
+                TokenBucket::new_with_now(REQUESTS_PER_SECOND, now),
+            );
+        }
+        self.rates
+            .get_mut(peer)
+            .expect("the rate was just inserted")
+            .try_take(1, now)
+    }
+
+    /// Queues the request of the peer, unless it has to be dropped. Requests of priority peers
+    /// are not rate limited and get handled before all the others.
+    pub fn push(
+        &mut self,
+        peer: I,
+        request: R,
+        priority: bool,
+        now: Instant,
+    ) -> Result<(), DropReason> {
+        if !priority && !self.within_rate(&peer, now) {
+            return Err(DropReason::RateExceeded);
+        }
+        if self.queue.len() >= MAX_QUEUED_REQUESTS {
+            return Err(DropReason::QueueFull);
+        }
+        match priority {
+            true => self.queue.push_front((peer, request)),
+            false => self.queue.push_back((peer, request)),
+        }
+        Ok(())
+    }
+
+    /// The next request to handle, together with the peer that sent it.
+    pub fn pop(&mut self) -> Option<(I, R)> {
+        self.queue.pop_front()
+    }
+
+    /// Removes the waiting requests of the peer that its newer request makes pointless, returns
+    /// them.
+    pub fn supersede<F: Fn(&R) -> bool>(&mut self, peer: &I, superseded: F) -> Vec<R> {
+        let mut removed = Vec::new();
+        let queue = mem::take(&mut self.queue);
+        for (sender, request) in queue {
+            match &sender == peer && superseded(&request) {
+                true => removed.push(request),
+                false => self.queue.push_back((sender, request)),
+            }
+        }
+        removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{DropReason, RequestQueue, MAX_QUEUED_REQUESTS, REQUESTS_PER_SECOND};
+
+    #[test]
+    fn drops_requests_above_rate() {
+        let mut queue = RequestQueue::new();
+        let now = Instant::now();
+        for request in 0..REQUESTS_PER_SECOND {
+            assert_eq!(queue.push(1, request, false, now), Ok(()));
+        }
+        assert_eq!(queue.push(1, 0, false, now), Err(DropReason::RateExceeded));
+        assert_eq!(queue.push(2, 0, false, now), Ok(()));
+        assert_eq!(queue.push(1, 0, true, now), Ok(()));
+        assert_eq!(
+            queue.push(1, 0, false, now + Duration::from_secs(5)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn accepts_requests_a_second_after_exceeding_rate() {
+        let mut queue = RequestQueue::new();
+        let now = Instant::now();
+        for request in 0..REQUESTS_PER_SECOND {
+            assert_eq!(queue.push(1, request, false, now), Ok(()));
+        }
+        for request in 0..100 {
+            assert_eq!(
+                queue.push(1, request, false, now),
+                Err(DropReason::RateExceeded)
+            );
+        }
+        let later = now + Duration::from_secs(1);
+        for request in 0..REQUESTS_PER_SECOND {
+            assert_eq!(queue.push(1, request, false, later), Ok(()));
+        }
+        assert_eq!(
+            queue.push(1, 0, false, later),
+            Err(DropReason::RateExceeded)
+        );
+    }
+
+    #[test]
+    fn bounds_waiting_requests() {
+        let mut queue = RequestQueue::new();
+        let now = Instant::now();
+        for peer in 0..MAX_QUEUED_REQUESTS {
+            assert_eq!(queue.push(peer, peer, false, now), Ok(()));
+        }
+        assert_eq!(
+            queue.push(MAX_QUEUED_REQUESTS, 0, false, now),
+            Err(DropReason::QueueFull)
+        );
+        assert_eq!(queue.pop(), Some((0, 0)));
+        assert_eq!(queue.push(MAX_QUEUED_REQUESTS, 0, false, now), Ok(()));
+    }
+
+    #[test]
+    fn handles_priority_requests_first() {
+        let mut queue = RequestQueue::new();
+        let now = Instant::now();
+        queue.push(1, 1, false, now).expect("room for requests");
+        queue.push(2, 2, true, now).expect("room for requests");
+        assert_eq!(queue.pop(), Some((2, 2)));
+        assert_eq!(queue.pop(), Some((1, 1)));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn removes_superseded_requests_of_the_peer() {
+        let mut queue = RequestQueue::new();
+        let now = Instant::now();
+        for (peer, request) in [(1, 1), (2, 1), (1, 2), (1, 1)] {
+            queue
+                .push(peer, request, false, now)
+                .expect("room for requests");
+        }
+        assert_eq!(queue.supersede(&1, |request| *request == 1), vec![1, 1]);
+        assert_eq!(queue.pop(), Some((2, 1)));
+        assert_eq!(queue.pop(), Some((1, 2)));
+        assert_eq!(queue.pop(), None);
+    }
+}
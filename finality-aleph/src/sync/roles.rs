@@ -0,0 +1,191 @@
+use std::{collections::HashSet, num::NonZeroUsize};
+
+use lru::LruCache;
+use parity_scale_codec::{Decode, Encode};
+
+use crate::{
+    aleph_primitives::AuthorityId,
+    crypto::{verify, AuthorityPen, Signature},
+    network::{tcp::AuthorityIdWrapper, AlephPeerId, PeerIdentities},
+    sync::{priority::TicketSubject, PeerId},
+};
+
+/// Separates the signatures in tickets from anything else signed with the same key.
+const TICKET_CONTEXT: &[u8] = b"aleph-sync-validator-ticket";
+/// How many peers with proven validator network keys we remember, the ones that proved it longest
+/// ago are forgotten first. They prove it again with every capabilities announcement anyway.
+const MAX_VALIDATOR_PEERS: usize = 256;
+
+fn ticket_message(subject: &[u8]) -> Vec<u8> {
+    let mut message = TICKET_CONTEXT.to_vec();
+    message.extend_from_slice(subject);
+    message
+}
+
+/// A proof that the sending peer uses the key in the validator network. Every node has such a
+/// key, but only the ones of the authorities get linked to their authority keys.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct ValidatorTicket {
+    key: AuthorityId,
+    signature: Signature,
+}
+
+impl ValidatorTicket {
+    /// A ticket for the peer with the identifier, signed with the validator network key of the pen.
+    pub fn new<P: TicketSubject>(pen: &AuthorityPen, peer_id: &P) -> Self {
+        ValidatorTicket {
+            key: pen.authority_id(),
+            signature: pen.sign(&ticket_message(&peer_id.ticket_subject())),
+        }
+    }
+
+    pub fn key(&self) -> &AuthorityId {
+        &self.key
+    }
+}
+
+/// How this node takes part in recognizing the validators among its peers.
+#[derive(Clone, Default)]
+pub struct RolesConfig {
+    /// The ticket proving the key we use in the validator network, sent to all the peers.
+    pub ticket: Option<ValidatorTicket>,
+    /// Where the validator network keys of the authorities are learned from, as the authorities
+    /// authenticate in the sessions.
+    pub identities: PeerIdentities,
+}
+
+/// The peers known to be validators, the most useful ones to ask for blocks when catching up.
+pub struct PeerRoles<I: PeerId> {
+    identities: PeerIdentities,
+    keys: LruCache<I, AuthorityId>,
+}
+
+impl<I: PeerId + TicketSubject> PeerRoles<I> {
+    pub fn new(identities: PeerIdentities) -> Self {
+        PeerRoles {
+            identities,
+            keys: LruCache::new(
+                NonZeroUsize::new(MAX_VALIDATOR_PEERS).expect("the constant is nonzero"),
+            ),
+        }
+    }
+
+    /// Checks the ticket sent by the peer, and if it is valid remembers the key of the peer.
+    /// Returns whether the ticket is valid. Whether the key belongs to an authority is only
+    /// checked when needed, as the authorities might authenticate later.
+    pub fn authenticate(&mut self, peer: I, ticket: &ValidatorTicket) -> bool {
+        if !verify(
+            &ticket.key,
+            &ticket_message(&peer.ticket_subject()),
+            &ticket.signature,
+        ) {
+            return false;
+        }
+        self.keys.put(peer, ticket.key.clone());
+        true
+    }
+
+    fn is_authority_key(&self, key: &AuthorityId) -> bool {
+        self.identities
+            .identity(&AlephPeerId::Validator(AuthorityIdWrapper::from(
+                key.clone(),
+            )))
+            .map_or(false, |identity| identity.authority.is_some())
+    }
+
+    /// Whether the peer proved using the validator network key of an authority.
+    pub fn is_validator(&self, peer: &I) -> bool {
+        self.keys
+            .peek(peer)
+            .map_or(false, |key| self.is_authority_key(key))
+    }
+
+    /// The validators among the peers, if there are any, all the peers otherwise. Anybody at all
+    /// is represented by no peers, and then all the known validators are preferred.
+    pub fn prefer_validators(&self, peers: HashSet<I>) -> HashSet<I> {
+        let validators: HashSet<_> = match peers.is_empty() {
+            true => self
+                .keys
+                .iter()
+                .filter(|(_, key)| self.is_authority_key(key))
+                .map(|(peer, _)| peer.clone())
+                .collect(),
+            false => peers
+                .iter()
+                .filter(|peer| self.is_validator(peer))
+                .cloned()
+                .collect(),
+        };
+        match validators.is_empty() {
+            true => peers,
+            false => validators,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use sp_keystore::{testing::MemoryKeystore, Keystore};
+
+    use super::{PeerRoles, ValidatorTicket};
+    use crate::{
+        aleph_primitives::{AuthorityId, KEY_TYPE},
+        crypto::AuthorityPen,
+        network::{tcp::AuthorityIdWrapper, AlephPeerId, PeerIdentities},
+    };
+
+    fn pen(seed: &str) -> AuthorityPen {
+        let keystore = Arc::new(MemoryKeystore::new());
+        let key = keystore
+            .ed25519_generate_new(KEY_TYPE, Some(seed))
+            .expect("generating keys works");
+        AuthorityPen::new(AuthorityId::from(key), keystore).expect("the key was just generated")
+    }
+
+    fn link_authority(identities: &PeerIdentities, validator: &AuthorityPen) {
+        identities.link(
+            AlephPeerId::Authority(pen("//Authority").authority_id()),
+            AlephPeerId::Validator(AuthorityIdWrapper::from(validator.authority_id())),
+        );
+    }
+
+    #[test]
+    fn recognizes_validators_once_linked() {
+        let identities = PeerIdentities::new();
+        let mut roles = PeerRoles::new(identities.clone());
+        let validator = pen("//Validator");
+        let ticket = ValidatorTicket::new(&validator, &7u32);
+        assert!(!roles.authenticate(8, &ticket));
+        assert!(roles.authenticate(7, &ticket));
+        // Not an authority yet.
+        assert!(!roles.is_validator(&7));
+        link_authority(&identities, &validator);
+        assert!(roles.is_validator(&7));
+        assert!(!roles.is_validator(&8));
+    }
+
+    #[test]
+    fn prefers_validators() {
+        let identities = PeerIdentities::new();
+        let mut roles = PeerRoles::new(identities.clone());
+        let validator = pen("//Validator");
+        let other = pen("//Other");
+        link_authority(&identities, &validator);
+        assert!(roles.authenticate(1, &ValidatorTicket::new(&validator, &1u32)));
+        assert!(roles.authenticate(2, &ValidatorTicket::new(&other, &2u32)));
+        assert_eq!(
+            roles.prefer_validators(HashSet::from([1, 2, 3])),
+            HashSet::from([1])
+        );
+        assert_eq!(
+            roles.prefer_validators(HashSet::from([2, 3])),
+            HashSet::from([2, 3])
+        );
+        assert_eq!(roles.prefer_validators(HashSet::new()), HashSet::from([1]));
+        assert!(PeerRoles::<u32>::new(PeerIdentities::new())
+            .prefer_validators(HashSet::new())
+            .is_empty());
+    }
+}
@@ -1,33 +1,193 @@
 use core::marker::PhantomData;
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    iter, mem,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use futures::{channel::mpsc, StreamExt};
-use log::{debug, error, trace, warn};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::BoxFuture,
+    pin_mut,
+    stream::{self, FuturesUnordered},
+    FutureExt, Sink, Stream, StreamExt,
+};
+use log::{debug, error, info, trace, warn};
+use parity_scale_codec::Encode;
 use substrate_prometheus_endpoint::Registry;
+use tokio::time::{interval, Interval, MissedTickBehavior};
 
 pub use crate::sync::handler::DatabaseIO;
 use crate::{
-    network::GossipNetwork,
-    session::SessionBoundaryInfo,
+    network::{
+        CancelToken, ConnectionEvent, ConnectionEvents, GossipNetwork, Penalty, RequestId,
+        RequestNetwork,
+    },
+    session::{SessionBoundaryInfo, SessionId},
+    shutdown_report::ShutdownRecorder,
     sync::{
-        data::{NetworkData, Request, ResponseItems, State, VersionWrapper, VersionedNetworkData},
+        acknowledgements::{AcknowledgementTag, Acknowledgements},
+        availability::{Availability, Capabilities, PeerAvailability},
+        backfill::{BackfillTask, BodyBackfill, BACKFILL_TICK},
+        block_fetcher::{BlockFetcher, ExternalBodies, FetchError},
+        chain_selection::ChainSelectionStrategy,
+        counters::Counters,
+        data::{
+            limited_response_prefix, BodyRequest, BranchKnowledge, ExtendedState, ForkId,
+            NetworkData, ProtocolFeatures, Request, ResponseItem, ResponseItems, State,
+            VersionWrapper, VersionedNetworkData, VersionedNetworkError,
+            MAX_BATCHED_JUSTIFICATIONS, MAX_BODY_REQUEST_BLOCKS, MAX_WARP_JUSTIFICATIONS,
+        },
+        finalization_hook::FinalizationHooks,
+        forest::{ForestCheckpointStorage, ForestDump, ForestDumps, Interest},
         handler::{Action, Error as HandlerError, HandleStateAction, Handler},
+        imports::{ImportNotifications, ImportedBlock},
+        in_flight::InFlightRequests,
+        justification_fetch::JustificationFetches,
+        justification_latency::JustificationLatencies,
+        justification_queue::{JustificationQueue, Lane},
+        latency_budget::{LatencyBudget, ShedWork, LATENCY_BUDGET},
         message_limiter::MsgLimiter,
         metrics::{Event, Metrics},
+        network_view::{FinalityStatus, NetworkFinalityView},
+        params::Params,
+        peer_rating::{Misbehavior, PeerRatings},
+        peer_trace::{summary, PeerTracing, PEER_TRACE_LOG_TARGET},
+        priority::{PriorityConfig, PriorityPeers, PriorityTicket, TicketError, TicketSubject},
+        provenance::ProvenanceHistory,
+        range_download::{RangeDownload, RANGE_DOWNLOAD_TICK},
+        request_queue::{DropReason, RequestQueue},
+        roles::{PeerRoles, RolesConfig, ValidatorTicket},
+        serving_stats::{ServingOutcome, ServingStats},
+        session_pipeline::SessionPipeline,
+        shed::ShedJustifications,
+        snapshot::{SnapshotPause, SnapshotSchedule, SnapshotTriggers},
+        status::{StatusReports, SyncStatus},
+        suppression::BroadcastSuppression,
         task_queue::TaskQueue,
         tasks::{Action as TaskAction, PreRequest, RequestTask},
         ticker::Ticker,
-        Block, BlockIdFor, BlockIdentifier, BlockImport, ChainStatus, ChainStatusNotification,
-        ChainStatusNotifier, Finalizer, Justification, JustificationSubmissions, RequestBlocks,
-        Verifier, LOG_TARGET,
+        v1_shim::V1Shim,
+        verification_pool::VerificationPool,
+        warp::WarpSync,
+        Block, BlockIdFor, BlockIdentifier, BlockImport, CandidateId, ChainStatus,
+        ChainStatusNotification, ChainStatusNotifier, FinalizedId, Finalizer, Header,
+        Justification, JustificationSubmissions, PeerId, RequestBlocks, Verifier, LOG_TARGET,
     },
+    BlockNumber,
 };
 
-const BROADCAST_COOLDOWN: Duration = Duration::from_millis(600);
-const BROADCAST_PERIOD: Duration = Duration::from_secs(5);
+/// Capabilities are announced once every this many state broadcasts.
+const CAPABILITIES_ANNOUNCEMENT_INTERVAL: u32 = 12;
+/// At most this many stale forest vertices are removed at once, the rest waits for the next turn
+/// of the main loop.
+const FOREST_PRUNING_BUDGET: usize = 512;
+/// In headers-first mode the headers of branches ending further than this above our top
+/// finalized block are fetched before the bodies.
+const HEADERS_FIRST_DISTANCE: BlockNumber = 64;
+/// How often the forest is checkpointed, so that the branches being downloaded survive a restart.
+const FOREST_CHECKPOINT_PERIOD: Duration = Duration::from_secs(30);
+
+/// A request from a peer that is expensive to handle, so it has to wait in the queue.
+enum IncomingRequest<J: Justification> {
+    Full(Request<J>),
+    Correlated(RequestId, Request<J>),
+    Bodies(BodyRequest),
+    Headers(Request<J>),
+    Warp(SessionId),
+}
+
+/// Justifications waiting in the import queue, with whatever came together with them.
+enum QueuedJustifications<B: Block, J: Justification, I: PeerId> {
+    /// Whether it came from the committee.
+    User(J::Unverified, bool),
+    StateResponse(J::Unverified, Option<J::Unverified>, I),
+    BatchedStateResponse(J::Unverified, Vec<J::Unverified>, I),
+    WarpResponse(Vec<J::Unverified>, I),
+    /// Handling stops once the token of the connection it came through is canceled.
+    RequestResponse(ResponseItems<B, J>, I, CancelToken),
+}
+
+impl<B: Block, J: Justification, I: PeerId> QueuedJustifications<B, J, I> {
+    /// The numbers of the gossiped justifications, `None` if they were not gossiped.
+    fn gossiped_numbers(&self) -> Option<Vec<BlockNumber>> {
+        use QueuedJustifications::*;
+        let number = |justification: &J::Unverified| justification.id().number();
+        match self {
+            StateResponse(justification, maybe_justification, _) => Some(
+                iter::once(justification)
+                    .chain(maybe_justification)
+                    .map(number)
+                    .collect(),
+            ),
+            BatchedStateResponse(justification, justifications, _) => Some(
+                iter::once(justification)
+                    .chain(justifications)
+                    .map(number)
+                    .collect(),
+            ),
+            User(..) | WarpResponse(..) | RequestResponse(..) => None,
+        }
+    }
+}
+
+/// The response to one of our requests matched with it by the network, `None` if it did not
+/// arrive in time, together with the requested block and the peer that got the request.
+type AwaitedResponse<B, J, I> = BoxFuture<'static, (BlockIdFor<J>, I, Option<NetworkData<B, J>>)>;
+
+/// A response whose justifications are checked by the verification pool, with the positions of
+/// the checked ones.
+type OffloadedResponse<B, J, I> = (ResponseItems<B, J>, I, CancelToken, Vec<usize>);
+
+/// What the sync service handled in a single step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncEvent {
+    NetworkData,
+    NetworkError,
+    Task,
+    ForestDump,
+    StatusReport,
+    /// Asked the peers for justifications of finalized blocks missing locally.
+    JustificationFetch,
+    ForestPruning,
+    /// Tried finalizing again after the database failed with a transient error.
+    FinalizationRetry,
+    /// Handled a request from a peer that waited in the queue.
+    QueuedRequest,
+    /// Handled justifications that waited in the import queue.
+    QueuedJustifications,
+    /// Got the response matched with one of our requests, or gave up on it.
+    MatchedResponse,
+    /// Queued a response whose justifications the verification pool finished checking.
+    VerifiedResponse,
+    /// A peer connected, or disconnected and everything we knew about it got forgotten.
+    PeerConnection,
+    BackfillTick,
+    /// Imported the bodies fetched from the external block source, or gave up on them.
+    ExternalFetch,
+    /// Advanced the parallel download of the gap below the network.
+    RangeDownloadTick,
+    Broadcast,
+    ChainEvent,
+    ChainEventError,
+    UserJustification,
+    AdditionalUserJustification,
+    UserBlockRequest,
+    /// Waited for the snapshot of the database to be taken.
+    Snapshot,
+    /// Stored a checkpoint of the forest.
+    ForestCheckpoint,
+    /// Applied the sync parameters changed on chain.
+    ParamsUpdate,
+    /// One of the channels with inputs from the user got closed.
+    InputClosed,
+}
 
 /// A service synchronizing the knowledge about the chain between the nodes.
-pub struct Service<B, J, N, CE, CS, V, F, BI>
+pub struct Service<B, J, N, CE, CS, V, F, BI, FC>
 where
     B: Block,
     J: Justification<Header = B::Header>,
@@ -37,17 +197,75 @@ where
     V: Verifier<J>,
     F: Finalizer<J>,
     BI: BlockImport<B>,
+    FC: ForestCheckpointStorage<J>,
 {
     network: VersionWrapper<B, J, N>,
+    connection_events: ConnectionEvents<N::PeerId>,
     handler: Handler<B, N::PeerId, J, CS, V, F, BI>,
     tasks: TaskQueue<RequestTask<BlockIdFor<J>>>,
     broadcast_ticker: Ticker,
+    broadcast_suppression: BroadcastSuppression<N::PeerId, BlockIdFor<J>>,
     chain_events: CE,
     justifications_from_user: mpsc::UnboundedReceiver<J::Unverified>,
+    session_info: SessionBoundaryInfo,
+    last_pushed_session: Option<SessionId>,
     additional_justifications_from_user: mpsc::UnboundedReceiver<J::Unverified>,
     block_requests_from_user: mpsc::UnboundedReceiver<BlockIdFor<J>>,
+    capabilities: Capabilities,
+    fork_id: ForkId,
+    /// The peers that announced following other forks of the chain, nothing they send is used.
+    foreign_peers: HashSet<N::PeerId>,
+    peer_tracing: PeerTracing<N::PeerId>,
+    forest_dumps: ForestDumps<N::PeerId, BlockIdFor<J>>,
+    status_reports: StatusReports<N::PeerId, BlockIdFor<J>>,
+    justification_fetches: JustificationFetches<BlockIdFor<J>, J::Unverified>,
+    /// Where to send the justifications asked for through the fetches, once a peer sends them.
+    pending_fetches: HashMap<BlockIdFor<J>, Vec<oneshot::Sender<J::Unverified>>>,
+    backfill: BackfillTask<BlockIdFor<J>>,
+    backfill_ticker: Interval,
+    /// The source of the missing bodies to use instead of the peers, if any.
+    external_bodies: ExternalBodies<B>,
+    import_notifications: ImportNotifications<N::PeerId, BlockIdFor<J>>,
+    snapshots: SnapshotSchedule<BlockIdFor<J>>,
+    snapshot_pause: Option<SnapshotPause>,
+    counters: Counters,
+    peer_availability: PeerAvailability<N::PeerId>,
+    network_view: NetworkFinalityView<N::PeerId>,
+    finality_status: FinalityStatus,
+    peer_ratings: PeerRatings<N::PeerId>,
+    acknowledgements: Acknowledgements<N::PeerId, BlockIdFor<J>>,
+    broadcasts_until_announcement: u32,
+    shed_justifications: ShedJustifications<BlockIdFor<J>>,
+    /// Every request with the token of the connection it came through, to stop handling it once
+    /// the peer disconnects.
+    request_queue: RequestQueue<N::PeerId, (IncomingRequest<J>, CancelToken)>,
+    serving_stats: ServingStats<N::PeerId>,
+    justification_queue: JustificationQueue<QueuedJustifications<B, J, N::PeerId>>,
+    latency_budget: LatencyBudget,
+    priority_ticket: Option<PriorityTicket>,
+    priority_peers: PriorityPeers<N::PeerId>,
+    validator_ticket: Option<ValidatorTicket>,
+    peer_roles: PeerRoles<N::PeerId>,
+    headers_first: bool,
+    light_sync: bool,
+    warp: Option<WarpSync<BlockIdFor<J>>>,
+    session_pipeline: Option<SessionPipeline>,
+    range_download: Option<RangeDownload<N::PeerId, B>>,
+    range_download_ticker: Interval,
+    max_batch_bytes: usize,
+    max_message_bytes: u32,
+    params: Params,
+    params_updates: mpsc::UnboundedReceiver<Params>,
     _phantom: PhantomData<B>,
     metrics: Metrics,
+    shutdown_recorder: ShutdownRecorder,
+    backup_path: Option<PathBuf>,
+    forest_checkpoints: FC,
+    forest_checkpoint_ticker: Interval,
+    justification_latencies: JustificationLatencies<BlockIdFor<J>>,
+    in_flight: InFlightRequests<N::PeerId, BlockIdFor<J>>,
+    matched_responses: FuturesUnordered<AwaitedResponse<B, J, N::PeerId>>,
+    verification_pool: VerificationPool<OffloadedResponse<B, J, N::PeerId>>,
 }
 
 impl<J: Justification> JustificationSubmissions<J> for mpsc::UnboundedSender<J::Unverified> {
@@ -66,7 +284,7 @@ impl<BI: BlockIdentifier> RequestBlocks<BI> for mpsc::UnboundedSender<BI> {
     }
 }
 
-impl<B, J, N, CE, CS, V, F, BI> Service<B, J, N, CE, CS, V, F, BI>
+impl<B, J, N, CE, CS, V, F, BI, FC> Service<B, J, N, CE, CS, V, F, BI, FC>
 where
     B: Block,
     J: Justification<Header = B::Header>,
@@ -76,10 +294,50 @@ where
     V: Verifier<J>,
     F: Finalizer<J>,
     BI: BlockImport<B>,
+    FC: ForestCheckpointStorage<J>,
+    N::PeerId: TicketSubject,
 {
     /// Create a new service using the provided network for communication.
     /// Also returns an interface for submitting additional justifications,
-    /// and an interface for requesting blocks.
+    /// and an interface for requesting blocks, both usable as sinks.
+    /// The capabilities describe what historical data we are able to serve to peers.
+    /// The fork id is announced to the peers along with the capabilities, and the peers
+    /// announcing other forks get ignored.
+    /// The peers that supplied data about finalized blocks are recorded in the provenance history.
+    /// The messages exchanged with the peer chosen in the peer tracing are logged in detail.
+    /// Dumps of the forest are sent to whoever asks through the forest dumps, and reports of the
+    /// sync status through the status reports.
+    /// Missing bodies of finalized blocks are fetched when requested through the body backfill,
+    /// from the block fetcher rather than the peers if one is provided.
+    /// Blocks with bodies from peers are announced through the import notifications once imported.
+    /// The finalization hooks are called with every block finalized.
+    /// The chain selection strategy decides which branch we extend and which forks of peers we
+    /// request.
+    /// Snapshots of the database are triggered through the snapshot triggers, with sync paused
+    /// while they are taken.
+    /// Long-term statistics surviving restarts are kept in the counters.
+    /// The states advertised by peers are aggregated in the network view.
+    /// The legacy version of the protocol is retired once the legacy cutoff block is finalized.
+    /// Our priority ticket is presented to the validators offering priority service, and the
+    /// followers presenting tickets for the configured keys get served with priority.
+    /// Our validator ticket is presented to all the peers, and the requests go to the peers that
+    /// proved being current validators first.
+    /// In headers-first mode, when far behind, the chains of justifications and headers are
+    /// fetched and verified before the bodies.
+    /// With warp sync, when sessions behind, the chain of justifications ending the sessions is
+    /// verified first, so that the authorities up to the head of the network are known.
+    /// With parallel download, when far behind, the gap below the network is split into ranges
+    /// downloaded from several peers at once.
+    /// With the session pipeline, when sessions behind, the justifications and headers of the
+    /// next session are fetched and verified while the blocks of the current one are imported.
+    /// In light sync only the headers and justifications are requested, never the bodies, and the
+    /// blocks get finalized once their headers connect to the top finalized one. The database
+    /// IO has to finalize them without the blocks in the database then.
+    /// The parameters are replaced with the ones sent through the parameter updates while running.
+    /// When the service exits, a shutdown report is saved under the backup path, if provided.
+    /// The forest is restored from the checkpoint storage, and checkpointed there periodically
+    /// and on exit.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         network: N,
         chain_events: CE,
@@ -88,20 +346,43 @@ where
         session_info: SessionBoundaryInfo,
         additional_justifications_from_user: mpsc::UnboundedReceiver<J::Unverified>,
         metrics_registry: Option<Registry>,
+        capabilities: Capabilities,
+        fork_id: ForkId,
+        provenance: ProvenanceHistory<N::PeerId, BlockIdFor<J>>,
+        peer_tracing: PeerTracing<N::PeerId>,
+        forest_dumps: ForestDumps<N::PeerId, BlockIdFor<J>>,
+        status_reports: StatusReports<N::PeerId, BlockIdFor<J>>,
+        justification_fetches: JustificationFetches<BlockIdFor<J>, J::Unverified>,
+        body_backfill: BodyBackfill,
+        block_fetcher: Option<Arc<dyn BlockFetcher<B>>>,
+        import_notifications: ImportNotifications<N::PeerId, BlockIdFor<J>>,
+        finalization_hooks: FinalizationHooks<J>,
+        chain_selection: Arc<dyn ChainSelectionStrategy<J::Header>>,
+        snapshot_triggers: SnapshotTriggers<BlockIdFor<J>>,
+        counters: Counters,
+        network_view: NetworkFinalityView<N::PeerId>,
+        legacy_cutoff: Option<BlockNumber>,
+        v1_shim: V1Shim<N::PeerId>,
+        priority: PriorityConfig,
+        roles: RolesConfig,
+        headers_first: bool,
+        warp_sync: bool,
+        parallel_download: bool,
+        session_pipeline: bool,
+        light_sync: bool,
+        finalization_batch: BlockNumber,
+        params: Params,
+        params_updates: mpsc::UnboundedReceiver<Params>,
+        backup_path: Option<PathBuf>,
+        forest_checkpoints: FC,
     ) -> Result<
         (
             Self,
-            impl JustificationSubmissions<J> + Clone,
-            impl RequestBlocks<BlockIdFor<J>>,
+            impl JustificationSubmissions<J> + Sink<J::Unverified> + Clone,
+            impl RequestBlocks<BlockIdFor<J>> + Sink<BlockIdFor<J>>,
         ),
         HandlerError<B, J, CS, V, F>,
     > {
-        let network = VersionWrapper::new(network);
-        let handler = Handler::new(database_io, verifier, session_info)?;
-        let tasks = TaskQueue::new();
-        let broadcast_ticker = Ticker::new(BROADCAST_PERIOD, BROADCAST_COOLDOWN);
-        let (justifications_for_sync, justifications_from_user) = mpsc::unbounded();
-        let (block_requests_for_sync, block_requests_from_user) = mpsc::unbounded();
         let metrics = match Metrics::new(metrics_registry) {
             Ok(metrics) => metrics,
             Err(e) => {
@@ -109,31 +390,291 @@ where
                 Metrics::noop()
             }
         };
-        Ok((
-            Service {
-                network,
-                handler,
-                tasks,
-                broadcast_ticker,
-                chain_events,
-                justifications_from_user,
-                additional_justifications_from_user,
-                block_requests_from_user,
-                metrics,
-                _phantom: PhantomData,
-            },
-            justifications_for_sync,
-            block_requests_for_sync,
-        ))
+        let connection_events = network.connection_events();
+        let mut network = VersionWrapper::new(
+            network,
+            legacy_cutoff,
+            params.max_message_bytes,
+            metrics.clone(),
+        )
+        .with_v1_shim(v1_shim);
+        let handler = Handler::new(
+            database_io,
+            verifier,
+            session_info.clone(),
+            params.serving_window,
+            finalization_batch,
+            params.max_response_blocks,
+            provenance,
+            finalization_hooks,
+            metrics.clone(),
+        )?
+        .with_chain_selection(chain_selection);
+        let handler = match light_sync {
+            true => handler.with_headers_only(),
+            false => handler,
+        };
+        let top_finalized = handler.state()?.top_justification().id().number();
+        network.update_top_finalized(top_finalized);
+        network_view.our_finalized(top_finalized);
+        let capabilities = match priority.followers.is_empty() {
+            true => capabilities,
+            false => capabilities.union(Capabilities::PRIORITY_SERVICE),
+        }
+        .union(Capabilities::HEADER_CHAINS)
+        .union(Capabilities::WARP_PROOFS)
+        .union(Capabilities::VALIDATOR_ROLES);
+        let snapshots =
+            SnapshotSchedule::new(snapshot_triggers, session_info.clone(), top_finalized);
+        let justification_latencies = JustificationLatencies::new(session_info.clone());
+        // Without the bodies the authorities of later sessions can only come from warp sync.
+        let warp = match warp_sync || light_sync {
+            true => Some(WarpSync::new(session_info.clone())),
+            false => None,
+        };
+        let session_pipeline = match session_pipeline {
+            true => Some(SessionPipeline::new(session_info.clone())),
+            false => None,
+        };
+        let range_download = match parallel_download {
+            true => Some(RangeDownload::new()),
+            false => None,
+        };
+        let tasks = TaskQueue::new();
+        let broadcast_ticker = Ticker::new(params.broadcast_period, params.broadcast_cooldown);
+        let (justifications_for_sync, justifications_from_user) = mpsc::unbounded();
+        let (block_requests_for_sync, block_requests_from_user) = mpsc::unbounded();
+        let mut backfill_ticker = interval(BACKFILL_TICK);
+        backfill_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut range_download_ticker = interval(RANGE_DOWNLOAD_TICK);
+        range_download_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut forest_checkpoint_ticker = interval(FOREST_CHECKPOINT_PERIOD);
+        forest_checkpoint_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut service = Service {
+            network,
+            connection_events,
+            handler,
+            tasks,
+            broadcast_ticker,
+            broadcast_suppression: BroadcastSuppression::new(),
+            chain_events,
+            justifications_from_user,
+            session_info,
+            last_pushed_session: None,
+            additional_justifications_from_user,
+            block_requests_from_user,
+            capabilities,
+            fork_id,
+            foreign_peers: HashSet::new(),
+            peer_tracing,
+            forest_dumps,
+            status_reports,
+            justification_fetches,
+            pending_fetches: HashMap::new(),
+            backfill: BackfillTask::new(body_backfill),
+            backfill_ticker,
+            external_bodies: ExternalBodies::new(block_fetcher),
+            import_notifications,
+            snapshots,
+            snapshot_pause: None,
+            counters,
+            peer_availability: PeerAvailability::new(),
+            network_view,
+            finality_status: FinalityStatus::NoPeers,
+            peer_ratings: PeerRatings::new(params.peer_rating),
+            acknowledgements: Acknowledgements::new(),
+            broadcasts_until_announcement: 0,
+            shed_justifications: ShedJustifications::new(),
+            request_queue: RequestQueue::new(),
+            serving_stats: ServingStats::new(),
+            justification_queue: JustificationQueue::new(),
+            latency_budget: LatencyBudget::new(LATENCY_BUDGET, top_finalized),
+            priority_ticket: priority.ticket,
+            priority_peers: PriorityPeers::new(priority.followers),
+            validator_ticket: roles.ticket,
+            peer_roles: PeerRoles::new(roles.identities),
+            headers_first,
+            light_sync,
+            warp,
+            session_pipeline,
+            range_download,
+            range_download_ticker,
+            max_batch_bytes: params.max_batch_bytes,
+            max_message_bytes: params.max_message_bytes,
+            params,
+            params_updates,
+            metrics,
+            shutdown_recorder: ShutdownRecorder::new("sync"),
+            backup_path,
+            forest_checkpoints,
+            forest_checkpoint_ticker,
+            justification_latencies,
+            in_flight: InFlightRequests::new(),
+            matched_responses: FuturesUnordered::new(),
+            verification_pool: VerificationPool::new(),
+            _phantom: PhantomData,
+        };
+        service.restore_forest();
+        Ok((service, justifications_for_sync, block_requests_for_sync))
+    }
+
+    fn restore_forest(&mut self) {
+        let checkpoint = match self.forest_checkpoints.load() {
+            Ok(Some(checkpoint)) => checkpoint,
+            Ok(None) => {
+                debug!(target: LOG_TARGET, "No forest checkpoint stored yet.");
+                return;
+            }
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Failed to load the forest checkpoint: {}.", e);
+                return;
+            }
+        };
+        let restored = match self.handler.restore_forest(checkpoint) {
+            Ok(restored) => restored,
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Not restoring the forest checkpoint: {}.", e);
+                return;
+            }
+        };
+        info!(
+            target: LOG_TARGET,
+            "Restored {} vertices of the forest from the checkpoint, skipped {}.",
+            restored.vertices,
+            restored.skipped
+        );
+        for id in restored.wanted {
+            self.request_block(id);
+        }
+        if let Some(id) = restored.highest_justified {
+            self.request_highest_justified(id);
+        }
+    }
+
+    /// Only the parameters coming from the chain can change, the local limits and everything
+    /// derived from them stay as they were when the service started.
+    fn update_params(&mut self, params: Params) {
+        if params == self.params {
+            return;
+        }
+        info!(target: LOG_TARGET, "Sync parameters changed on chain, now running with {:?}.", params);
+        self.handler.set_serving_window(params.serving_window);
+        self.broadcast_ticker
+            .set_timeouts(params.broadcast_period, params.broadcast_cooldown);
+        self.max_batch_bytes = params.max_batch_bytes;
+        self.params = params;
+    }
+
+    fn store_forest_checkpoint(&self) {
+        let checkpoint = self.handler.forest_checkpoint();
+        trace!(
+            target: LOG_TARGET,
+            "Storing a forest checkpoint of {} vertices.",
+            checkpoint.vertices.len()
+        );
+        if let Err(e) = self.forest_checkpoints.store(&checkpoint) {
+            warn!(target: LOG_TARGET, "Failed to store the forest checkpoint: {}.", e);
+        }
+    }
+
+    fn report_event(&mut self, event: Event) {
+        self.metrics.report_event(event);
+        self.shutdown_recorder.count(event.name());
+    }
+
+    fn report_event_error<E: Display>(&mut self, event: Event, error: E) {
+        self.metrics.report_event_error(event);
+        self.shutdown_recorder
+            .record_error(event.name(), format!("{}: {}", event.name(), error));
+    }
+
+    fn report_network_error(&mut self, event: Event, error: &VersionedNetworkError<N::Error>) {
+        if let VersionedNetworkError::MessageTooBig(_) = error {
+            self.report_event(Event::OversizedMessage);
+        }
+        self.report_event_error(event, error);
+    }
+
+    /// The misbehavior of the sender the error proves, if any.
+    fn misbehavior(error: &HandlerError<B, J, CS, V, F>) -> Option<Misbehavior> {
+        match error {
+            HandlerError::Verifier(e) if V::is_invalid(e) => {
+                Some(Misbehavior::InvalidJustification)
+            }
+            HandlerError::HeaderChain(_) | HandlerError::UnorderedJustifications(_, _) => {
+                Some(Misbehavior::MalformedData)
+            }
+            HandlerError::BlockNotImportable | HandlerError::HeaderNotRequired => {
+                Some(Misbehavior::UnrequestedData)
+            }
+            _ => None,
+        }
+    }
+
+    fn rate_peer(&mut self, peer: N::PeerId, misbehavior: Misbehavior) {
+        self.report_event(Event::PeerMisbehavior);
+        debug!(
+            target: LOG_TARGET,
+            "Peer {:?} misbehaved in sync: {:?}.", peer, misbehavior
+        );
+        let penalty = match self
+            .peer_ratings
+            .report(peer.clone(), misbehavior, Instant::now())
+        {
+            Some(penalty) => penalty,
+            None => return,
+        };
+        self.report_event(Event::PeerPenalty);
+        if penalty == Penalty::Ban {
+            self.counters.banned();
+        }
+        info!(
+            target: LOG_TARGET,
+            "Penalizing peer {:?} for misbehaving in sync: {:?}.", peer, penalty
+        );
+        if let Err(e) = self.network.penalize(peer, penalty) {
+            self.report_network_error(Event::PeerPenalty, &e);
+            warn!(target: LOG_TARGET, "Error penalizing peer: {}.", e);
+        }
     }
 
-    fn request_highest_justified(&mut self, block_id: BlockIdFor<J>) {
+    fn rate_error(&mut self, error: &HandlerError<B, J, CS, V, F>, peer: &N::PeerId) {
+        if let Some(misbehavior) = Self::misbehavior(error) {
+            self.rate_peer(peer.clone(), misbehavior);
+        }
+    }
+
+    fn remember_shed_justification(&mut self, error: &HandlerError<B, J, CS, V, F>) {
+        if let HandlerError::JustificationTooNew(id) = error {
+            if self.shed_justifications.shed(id.clone()) {
+                self.report_event(Event::JustificationShed);
+            }
+        }
+    }
+
+    fn request_shed_justifications(&mut self, finalized: BlockNumber) {
+        for _ in 0..self.shed_justifications.finalized(finalized) {
+            self.report_event(Event::JustificationRecovered);
+        }
+        let handler = &self.handler;
+        for id in self.shed_justifications.ready(|id| handler.can_hold(id)) {
+            debug!(
+                target: LOG_TARGET,
+                "Requesting again block {:?}, its justification was dropped before.", id
+            );
+            self.handle_internal_request(id);
+        }
+    }
+
+    fn request_highest_justified(&mut self, block_id: CandidateId<BlockIdFor<J>>) {
         debug!(
             target: LOG_TARGET,
             "Initiating a request for highest justified block {:?}.", block_id
         );
-        self.tasks
-            .schedule_in(RequestTask::new_highest_justified(block_id), Duration::ZERO);
+        self.tasks.schedule_in(
+            RequestTask::new_highest_justified(block_id.into_inner()),
+            Duration::ZERO,
+        );
     }
 
     fn request_block(&mut self, block_id: BlockIdFor<J>) {
@@ -145,13 +686,34 @@ where
             .schedule_in(RequestTask::new_block(block_id), Duration::ZERO);
     }
 
-    fn broadcast(&mut self) {
-        self.metrics.report_event(Event::Broadcast);
+    /// Broadcasts our state. Periodic broadcasts are skipped when all the peers already know it.
+    /// Finalizes the blocks waiting for their batches to fill, so that they do not wait forever
+    /// if the bodies of the following blocks never arrive.
+    fn finalize_pending(&mut self) {
+        if let Err(e) = self.handler.finalize_pending() {
+            warn!(
+                target: LOG_TARGET,
+                "Failed to finalize the pending justified blocks: {}.", e
+            );
+        }
+    }
+
+    fn retry_finalization(&mut self) {
+        if let Err(e) = self.handler.retry_finalization() {
+            warn!(
+                target: LOG_TARGET,
+                "Failed to retry finalizing the justified blocks: {}.", e
+            );
+        }
+    }
+
+    fn broadcast(&mut self, periodic: bool) {
         self.broadcast_ticker.reset();
         let state = match self.handler.state() {
             Ok(state) => state,
             Err(e) => {
-                self.metrics.report_event_error(Event::Broadcast);
+                self.report_event(Event::Broadcast);
+                self.report_event_error(Event::Broadcast, &e);
                 warn!(
                     target: LOG_TARGET,
                     "Failed to construct own knowledge state: {}.", e
@@ -159,21 +721,76 @@ where
                 return;
             }
         };
+        let top = state.top_justification().id();
+        if periodic && self.broadcast_suppression.suppress(&top) {
+            trace!(
+                target: LOG_TARGET,
+                "Skipping broadcast, all peers know our state."
+            );
+            self.report_event(Event::BroadcastSuppressed);
+            return;
+        }
+        self.report_event(Event::Broadcast);
+        self.broadcast_suppression.broadcast(top);
         trace!(target: LOG_TARGET, "Broadcasting state: {:?}", state);
 
-        let data = NetworkData::StateBroadcast(state);
+        // Peers learn about our fork from the favourite block, but the state alone is still
+        // useful without it.
+        let data = match self.handler.favourite_block() {
+            Ok(favourite_block) => {
+                NetworkData::ExtendedStateBroadcast(ExtendedState::new(state, favourite_block))
+            }
+            Err(e) => {
+                debug!(
+                    target: LOG_TARGET,
+                    "Broadcasting state without the favourite block: {}.", e
+                );
+                NetworkData::StateBroadcast(state)
+            }
+        };
+        self.trace_broadcast(&data);
         if let Err(e) = self.network.broadcast(data) {
-            self.metrics.report_event_error(Event::Broadcast);
+            self.report_network_error(Event::Broadcast, &e);
             warn!(target: LOG_TARGET, "Error sending broadcast: {}.", e)
         }
+        self.announce_capabilities();
+    }
+
+    fn announce_capabilities(&mut self) {
+        if self.capabilities.is_empty() {
+            return;
+        }
+        if let Some(remaining) = self.broadcasts_until_announcement.checked_sub(1) {
+            self.broadcasts_until_announcement = remaining;
+            return;
+        }
+        self.broadcasts_until_announcement = CAPABILITIES_ANNOUNCEMENT_INTERVAL - 1;
+        trace!(
+            target: LOG_TARGET,
+            "Announcing capabilities: {:?}",
+            self.capabilities
+        );
+        let data = NetworkData::CapabilitiesAnnouncement(self.capabilities);
+        self.trace_broadcast(&data);
+        if let Err(e) = self.network.broadcast(data) {
+            warn!(
+                target: LOG_TARGET,
+                "Error announcing capabilities: {}.", e
+            )
+        }
+        let data = NetworkData::ForkIdAnnouncement(self.fork_id);
+        self.trace_broadcast(&data);
+        if let Err(e) = self.network.broadcast(data) {
+            warn!(target: LOG_TARGET, "Error announcing the fork id: {}.", e)
+        }
     }
 
     fn send_request(&mut self, pre_request: PreRequest<N::PeerId, J>) {
-        self.metrics.report_event(Event::SendRequest);
+        self.report_event(Event::SendRequest);
         let state = match self.handler.state() {
             Ok(state) => state,
             Err(e) => {
-                self.metrics.report_event_error(Event::SendRequest);
+                self.report_event_error(Event::SendRequest, &e);
                 warn!(
                     target: LOG_TARGET,
                     "Failed to construct own knowledge state: {}.", e
@@ -181,32 +798,369 @@ where
                 return;
             }
         };
-        let (request, peers) = pre_request.with_state(state);
-        trace!(target: LOG_TARGET, "Sending a request: {:?}", request);
-        let data = NetworkData::Request(request);
+        let (request, mut peers) = pre_request.with_state(state);
+        let target = request.target_id().clone();
+        let data = match self.header_request_peers(&request, &peers) {
+            Some(header_peers) => {
+                peers = header_peers;
+                trace!(target: LOG_TARGET, "Sending a header request: {:?}", request);
+                NetworkData::HeaderRequest(request)
+            }
+            None if self.light_sync => {
+                trace!(
+                    target: LOG_TARGET,
+                    "Not requesting {:?}, nobody serves just the headers.", target
+                );
+                return;
+            }
+            None => {
+                if peers.is_empty() {
+                    // Nobody we know of has the block, but archive nodes might.
+                    peers = self
+                        .peer_availability
+                        .peers_serving_body(request.target_id().number());
+                }
+                trace!(target: LOG_TARGET, "Sending a request: {:?}", request);
+                NetworkData::Request(request)
+            }
+        };
+        // Validators of the current session are the most likely to have the blocks.
+        let peers = self.peer_roles.prefer_validators(peers);
+        if let Some(peer) = self.peer_tracing.traced_peer() {
+            if peers.contains(&peer) {
+                info!(
+                    target: PEER_TRACE_LOG_TARGET,
+                    "Possibly sending to {:?}: {}.",
+                    peer,
+                    summary(&data)
+                );
+            }
+        }
 
-        if let Err(e) = self.network.send_to_random(data, peers) {
-            self.metrics.report_event_error(Event::SendRequest);
+        let peer = self.in_flight.choose_peer(&target, &peers);
+        if let Some(timed_out) = self
+            .in_flight
+            .sent(target.clone(), peer.clone(), Instant::now())
+        {
+            self.report_event(Event::RequestTimeout);
+            debug!(
+                target: LOG_TARGET,
+                "Request for {:?} sent to {} got no response within {}ms, asking {}.",
+                target,
+                timed_out
+                    .peer
+                    .map_or("a random peer".to_string(), |peer| format!("{peer:?}")),
+                timed_out.after.as_millis(),
+                peer.as_ref()
+                    .map_or("a random peer".to_string(), |peer| format!("{peer:?}"))
+            );
+        }
+        let result = match peer {
+            Some(peer) => self.send_matched_request(data, target, peer),
+            None => self.network.send_request_to_random(data, peers),
+        };
+        if let Err(e) = result {
+            self.report_network_error(Event::SendRequest, &e);
             warn!(target: LOG_TARGET, "Error sending request: {}.", e);
         }
     }
 
+    /// Sends the request for the block to the peer, awaiting the response separately if the
+    /// network can match it with the request.
+    fn send_matched_request(
+        &mut self,
+        data: NetworkData<B, J>,
+        target: BlockIdFor<J>,
+        peer: N::PeerId,
+    ) -> Result<(), VersionedNetworkError<N::Error>> {
+        if let Some(response) = self.network.request(data, peer.clone())? {
+            self.matched_responses
+                .push(async move { (target, peer, response.await) }.boxed());
+        }
+        Ok(())
+    }
+
+    fn handle_matched_response(
+        &mut self,
+        target: BlockIdFor<J>,
+        response: Option<NetworkData<B, J>>,
+        peer: N::PeerId,
+    ) {
+        match response {
+            Some(NetworkData::RequestResponse(response_items)) => {
+                self.queue_request_response(response_items, Some(target), peer)
+            }
+            Some(data) => self.handle_network_data(data, peer),
+            None => debug!(
+                target: LOG_TARGET,
+                "Request for {:?} sent to {:?} got no matching response in time.", target, peer
+            ),
+        }
+    }
+
+    fn handle_connection_event(&mut self, event: ConnectionEvent<N::PeerId>) {
+        let peer = match event {
+            ConnectionEvent::Connected(peer) => {
+                debug!(target: LOG_TARGET, "Peer {:?} connected.", peer);
+                return;
+            }
+            ConnectionEvent::Disconnected(peer) => peer,
+        };
+        self.foreign_peers.remove(&peer);
+        let abandoned = self.forget_peer(&peer);
+        debug!(
+            target: LOG_TARGET,
+            "Peer {:?} disconnected, reassigning {} requests it did not answer.", peer, abandoned
+        );
+    }
+
+    /// Drops everything we know about the peer. The requests it did not answer go to other
+    /// peers, if we still want the blocks, returns how many of them there were.
+    fn forget_peer(&mut self, peer: &N::PeerId) -> usize {
+        self.handler.peer_disconnected(peer);
+        self.broadcast_suppression.forget_peer(peer);
+        self.peer_availability.forget_peer(peer);
+        let abandoned = self.in_flight.disconnected(peer);
+        for id in &abandoned {
+            let task = match self.handler.interest_provider().get(id) {
+                Interest::Required { .. } => RequestTask::new_block(id.clone()),
+                Interest::HighestJustified { .. } => RequestTask::new_highest_justified(id.clone()),
+                Interest::Uninterested => continue,
+            };
+            self.tasks.schedule_in(task, Duration::ZERO);
+        }
+        abandoned.len()
+    }
+
+    /// Peers on other forks share the genesis with us, so everything they send looks valid,
+    /// but following them would lead us off our chain.
+    fn handle_fork_id(&mut self, fork_id: ForkId, peer: N::PeerId) {
+        if fork_id == self.fork_id {
+            if self.foreign_peers.remove(&peer) {
+                info!(
+                    target: LOG_TARGET,
+                    "Peer {:?} switched to our fork, no longer ignoring it.", peer
+                );
+            }
+            return;
+        }
+        if self.foreign_peers.insert(peer.clone()) {
+            warn!(
+                target: LOG_TARGET,
+                "Peer {:?} follows {}, while we follow {}, ignoring it.", peer, fork_id, self.fork_id
+            );
+            self.forget_peer(&peer);
+        }
+    }
+
+    /// The peers to ask for just the headers of the requested branch, if we should. That is the
+    /// case in headers-first mode, when we miss some of the headers of a branch far above our top
+    /// finalized block, and with the session pipeline, when the branch ends in a later session.
+    /// Once all the headers are known the bodies are requested as usual, and the responses do not
+    /// repeat the headers. In light sync always the case, the bodies are never requested.
+    fn header_request_peers(
+        &self,
+        request: &Request<J>,
+        peers: &HashSet<N::PeerId>,
+    ) -> Option<HashSet<N::PeerId>> {
+        let top_finalized = request.state().top_justification().id().number();
+        let target = request.target_id().number();
+        let pipelined = self
+            .session_pipeline
+            .as_ref()
+            .map_or(false, |pipeline| pipeline.is_ahead(top_finalized, target));
+        let far_ahead = (self.headers_first || pipelined)
+            && target > top_finalized.saturating_add(HEADERS_FIRST_DISTANCE);
+        if !(self.light_sync || far_ahead)
+            || !matches!(request.branch_knowledge(), BranchKnowledge::LowestId(_))
+        {
+            return None;
+        }
+        let header_peers = self.peers_with(
+            Capabilities::HEADER_CHAINS,
+            ProtocolFeatures::HEADER_REQUESTS,
+        );
+        let preferred: HashSet<_> = header_peers.intersection(peers).cloned().collect();
+        match (preferred.is_empty(), header_peers.is_empty()) {
+            (false, _) => Some(preferred),
+            // Nobody we expect to have the branch serves headers, but they might still know it.
+            (true, false) if peers.is_empty() || self.light_sync => Some(header_peers),
+            _ => None,
+        }
+    }
+
+    /// The peers that announced the capabilities and understanding the features of the protocol
+    /// needed to make use of them.
+    fn peers_with(
+        &self,
+        capabilities: Capabilities,
+        features: ProtocolFeatures,
+    ) -> HashSet<N::PeerId> {
+        self.peer_availability
+            .peers_with(capabilities)
+            .into_iter()
+            .filter(|peer| self.network.understands_features(peer, features))
+            .collect()
+    }
+
+    /// Announces the imported block to the peers if it became our favourite, unless we are
+    /// catching up and nobody needs it.
+    fn announce(&mut self, header: J::Header) {
+        if matches!(self.finality_status, FinalityStatus::Behind(_)) {
+            return;
+        }
+        match self.handler.favourite_block() {
+            Ok(favourite_block) if favourite_block.id() == header.id() => {}
+            Ok(_) => return,
+            Err(e) => {
+                debug!(
+                    target: LOG_TARGET,
+                    "Not announcing {:?}, the favourite block is unknown: {}.",
+                    header.id(),
+                    e
+                );
+                return;
+            }
+        }
+        self.report_event(Event::Announce);
+        let data = NetworkData::Announcement(header);
+        self.trace_broadcast(&data);
+        if let Err(e) = self.network.broadcast(data) {
+            self.report_network_error(Event::Announce, &e);
+            warn!(target: LOG_TARGET, "Error sending announcement: {}.", e)
+        }
+    }
+
+    fn trace_broadcast(&self, data: &NetworkData<B, J>) {
+        if let Some(peer) = self.peer_tracing.traced_peer() {
+            info!(
+                target: PEER_TRACE_LOG_TARGET,
+                "Broadcasting, also to {:?}: {}.",
+                peer,
+                summary(data)
+            );
+        }
+    }
+
     fn send_to(&mut self, data: NetworkData<B, J>, peer: N::PeerId) {
-        self.metrics.report_event(Event::SendTo);
+        if let Err(e) = self.try_send_to(data, peer) {
+            self.report_network_error(Event::SendTo, &e);
+            warn!(target: LOG_TARGET, "Error sending response: {}.", e);
+        }
+    }
+
+    fn try_send_to(
+        &mut self,
+        data: NetworkData<B, J>,
+        peer: N::PeerId,
+    ) -> Result<(), VersionedNetworkError<N::Error>> {
+        self.report_event(Event::SendTo);
+        if self.peer_tracing.is_traced(&peer) {
+            info!(
+                target: PEER_TRACE_LOG_TARGET,
+                "Sending to {:?}: {}.",
+                peer,
+                summary(&data)
+            );
+        }
         trace!(
             target: LOG_TARGET,
             "Sending data {:?} to peer {:?}",
             data,
             peer
         );
-        if let Err(e) = self.network.send_to(data, peer) {
-            self.metrics.report_event_error(Event::SendTo);
-            warn!(target: LOG_TARGET, "Error sending response: {}.", e);
+        use NetworkData::*;
+        match data {
+            Request(_) | BodyRequest(_) | HeaderRequest(_) | WarpRequest(_) => {
+                self.network.send_request(data, peer)
+            }
+            RequestResponse(_) | WarpResponse(_) => self.network.send_response(data, peer),
+            data => self.network.send_to(data, peer),
+        }
+    }
+
+    /// Asks the peer to acknowledge receiving the justifications we just sent, so that we do not
+    /// resend them while the peer is still catching up.
+    fn request_acknowledgement(&mut self, justifications: Vec<BlockIdFor<J>>, peer: N::PeerId) {
+        if let Some(tag) =
+            self.acknowledgements
+                .request(peer.clone(), justifications, Instant::now())
+        {
+            self.report_event(Event::AcknowledgementRequest);
+            self.send_to(NetworkData::AcknowledgementRequest(tag), peer);
+        }
+    }
+
+    /// Sends the justification carrying data to the peer, unless it recently acknowledged
+    /// receiving all of the justifications already.
+    fn send_justifications_to(&mut self, data: NetworkData<B, J>, peer: N::PeerId) {
+        let justifications = data.justification_ids();
+        let now = Instant::now();
+        if !justifications.is_empty()
+            && justifications
+                .iter()
+                .all(|id| self.acknowledgements.delivered(&peer, id, now))
+        {
+            self.report_event(Event::ResendSkipped);
+            trace!(
+                target: LOG_TARGET,
+                "Not resending justifications {:?} already delivered to {:?}.",
+                justifications,
+                peer
+            );
+            return;
+        }
+        match self.try_send_to(data, peer.clone()) {
+            Ok(()) => self.request_acknowledgement(justifications, peer),
+            Err(e) => {
+                self.report_network_error(Event::SendTo, &e);
+                warn!(target: LOG_TARGET, "Error sending response: {}.", e);
+            }
+        }
+    }
+
+    /// Sends the response items, splitting them in halves whenever they turn out too big for a
+    /// single message. Every part carries the identifier of the request, if it had one.
+    fn send_response(
+        &mut self,
+        response_items: &[ResponseItem<B, J>],
+        request_id: Option<RequestId>,
+        peer: N::PeerId,
+    ) {
+        let data = match request_id {
+            Some(id) => NetworkData::CorrelatedResponse(id, response_items.to_vec()),
+            None => NetworkData::RequestResponse(response_items.to_vec()),
+        };
+        let justifications = data.justification_ids();
+        let size = data.encoded_size();
+        match self.try_send_to(data, peer.clone()) {
+            Ok(()) => {
+                self.counters.bytes_served(size as u64);
+                self.request_acknowledgement(justifications, peer)
+            }
+            Err(e @ VersionedNetworkError::MessageTooBig(_)) if response_items.len() > 1 => {
+                debug!(
+                    target: LOG_TARGET,
+                    "Splitting a response to {:?} of {} items: {}.",
+                    peer,
+                    response_items.len(),
+                    e
+                );
+                self.report_event(Event::OversizedMessage);
+                self.report_event(Event::SplitResponse);
+                let (older, newer) = response_items.split_at(response_items.len() / 2);
+                self.send_response(older, request_id, peer.clone());
+                self.send_response(newer, request_id, peer);
+            }
+            Err(e) => {
+                self.report_network_error(Event::SendTo, &e);
+                warn!(target: LOG_TARGET, "Error sending response: {}.", e);
+            }
         }
     }
 
     fn handle_state(&mut self, state: State<J>, peer: N::PeerId) {
-        self.metrics.report_event(Event::HandleState);
+        self.report_event(Event::HandleState);
         use HandleStateAction::*;
         trace!(
             target: LOG_TARGET,
@@ -214,14 +1168,23 @@ where
             state,
             peer
         );
+        self.broadcast_suppression
+            .peer_state(peer.clone(), state.top_justification().id());
+        self.network_view.peer_state(
+            peer.clone(),
+            state.top_justification().id().number(),
+            Instant::now(),
+        );
         match self.handler.handle_state(state, peer.clone()) {
             Ok(action) => match action {
-                Response(data) => self.send_to(data, peer),
+                Response(data) => self.send_justifications_to(data, peer),
                 HighestJustified(block_id) => self.request_highest_justified(block_id),
                 Noop => (),
             },
             Err(e) => {
-                self.metrics.report_event_error(Event::HandleState);
+                self.report_event_error(Event::HandleState, &e);
+                self.remember_shed_justification(&e);
+                self.rate_error(&e, &peer);
                 match e {
                     HandlerError::Verifier(e) => debug!(
                         target: LOG_TARGET,
@@ -236,6 +1199,32 @@ where
         }
     }
 
+    fn handle_favourite_block(&mut self, header: J::Header, peer: N::PeerId) {
+        trace!(
+            target: LOG_TARGET,
+            "Handling favourite block {:?} of {:?}.",
+            header.id(),
+            peer
+        );
+        match self
+            .handler
+            .handle_favourite_block(header.clone(), peer.clone())
+        {
+            Ok(true) => self.request_block(header.id()),
+            Ok(false) => {}
+            Err(e) => {
+                self.rate_error(&e, &peer);
+                debug!(
+                    target: LOG_TARGET,
+                    "Failed to handle favourite block {:?} of {:?}: {}.",
+                    header.id(),
+                    peer,
+                    e
+                );
+            }
+        }
+    }
+
     fn handle_state_response(
         &mut self,
         justification: J::Unverified,
@@ -249,10 +1238,15 @@ where
             maybe_justification,
             peer
         );
-        self.metrics.report_event(Event::HandleStateResponse);
+        self.report_event(Event::HandleStateResponse);
         let (maybe_id, maybe_error) =
             self.handler
                 .handle_state_response(justification, maybe_justification, peer.clone());
+        if let Some(e) = &maybe_error {
+            self.report_event_error(Event::HandleStateResponse, e);
+            self.remember_shed_justification(e);
+            self.rate_error(e, &peer);
+        }
         match maybe_error {
             Some(HandlerError::Verifier(e)) => debug!(
                 target: LOG_TARGET,
@@ -269,27 +1263,257 @@ where
         }
     }
 
-    fn handle_justification_from_user(&mut self, justification: J::Unverified) {
+    /// Only the highest of the newly justified blocks is requested as such, and peers refuse
+    /// requests too far ahead of our top finalized block, so the lower ones are requested as
+    /// plain blocks, to keep finalizing while catching up.
+    fn handle_warp_response(&mut self, mut justifications: Vec<J::Unverified>, peer: N::PeerId) {
         trace!(
             target: LOG_TARGET,
-            "Handling a justification {:?} from user.",
-            justification,
+            "Handling a warp response with {} justifications from {:?}.",
+            justifications.len(),
+            peer
         );
-        self.metrics
-            .report_event(Event::HandleJustificationFromUser);
-        match self.handler.handle_justification_from_user(justification) {
-            Ok(Some(id)) => self.request_highest_justified(id),
-            Ok(_) => {}
-            Err(e) => {
-                self.metrics
-                    .report_event_error(Event::HandleJustificationFromUser);
-                match e {
-                    HandlerError::Verifier(e) => debug!(
-                        target: LOG_TARGET,
-                        "Could not verify justification from user: {}", e
-                    ),
-                    e => warn!(
-                        target: LOG_TARGET,
+        if self.warp.is_none() && self.session_pipeline.is_none() {
+            debug!(
+                target: LOG_TARGET,
+                "Ignoring a warp response from {:?}, warp sync is disabled.", peer
+            );
+            return;
+        }
+        self.report_event(Event::HandleStateResponse);
+        if justifications.len() > MAX_WARP_JUSTIFICATIONS {
+            debug!(
+                target: LOG_TARGET,
+                "Ignoring {} justifications over the warp limit from {:?}.",
+                justifications.len() - MAX_WARP_JUSTIFICATIONS,
+                peer
+            );
+            justifications.truncate(MAX_WARP_JUSTIFICATIONS);
+        }
+        let (maybe_highest, maybe_error) = self
+            .handler
+            .handle_warp_response(justifications, peer.clone());
+        if let Some(e) = &maybe_error {
+            self.report_event_error(Event::HandleStateResponse, e);
+            self.remember_shed_justification(e);
+            self.rate_error(e, &peer);
+        }
+        match maybe_error {
+            // Expected, we are warping ahead of what the forest can hold.
+            Some(HandlerError::JustificationTooNew(id)) => trace!(
+                target: LOG_TARGET,
+                "Justification of {:?} from the warp response is too new for now.", id
+            ),
+            Some(HandlerError::Verifier(e)) => debug!(
+                target: LOG_TARGET,
+                "Could not verify justification in warp response from {:?}: {}.", peer, e
+            ),
+            Some(e) => warn!(
+                target: LOG_TARGET,
+                "Failed to handle warp response from {:?}: {}.", peer, e
+            ),
+            None => {}
+        }
+        if let Some(highest) = maybe_highest {
+            debug!(
+                target: LOG_TARGET,
+                "Verified the justifications ending sessions up to {:?}.", highest
+            );
+            if let Some(warp) = &mut self.warp {
+                warp.verified(highest.into_inner());
+            }
+        }
+    }
+
+    /// Asks a peer serving warp proofs for the justifications ending the sessions we are missing,
+    /// if the network is far enough ahead. The median is used, so that a few peers lying about
+    /// their state cannot make us warp.
+    fn request_warp(&mut self) {
+        let warp = match &mut self.warp {
+            Some(warp) => warp,
+            None => return,
+        };
+        let now = Instant::now();
+        let stats = match self.network_view.stats(now) {
+            Some(stats) => stats,
+            None => return,
+        };
+        let peer = match self
+            .peers_with(Capabilities::WARP_PROOFS, ProtocolFeatures::WARP)
+            .into_iter()
+            .next()
+        {
+            Some(peer) => peer,
+            None => return,
+        };
+        if let Some(from) = warp.next_request(stats.our_finalized, stats.median_finalized, now) {
+            debug!(
+                target: LOG_TARGET,
+                "Requesting the justifications ending sessions from {:?} from {:?}.", from, peer
+            );
+            self.send_to(NetworkData::WarpRequest(from), peer);
+        }
+    }
+
+    /// Asks a peer serving warp proofs for the justifications ending the current and the next
+    /// session, if the network already finalized them, so that the next session is fetched and
+    /// verified while the current one is still imported.
+    fn request_session_lookahead(&mut self) {
+        let pipeline = match &mut self.session_pipeline {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        let stats = match self.network_view.stats(Instant::now()) {
+            Some(stats) => stats,
+            None => return,
+        };
+        let peer = match self
+            .peers_with(Capabilities::WARP_PROOFS, ProtocolFeatures::WARP)
+            .into_iter()
+            .next()
+        {
+            Some(peer) => peer,
+            None => return,
+        };
+        if let Some(from) = pipeline.next_request(
+            stats.our_finalized,
+            self.handler.highest_justified_number(),
+            stats.median_finalized,
+            Instant::now(),
+        ) {
+            debug!(
+                target: LOG_TARGET,
+                "Requesting the justifications ending sessions from {:?} ahead of import from {:?}.",
+                from,
+                peer
+            );
+            self.send_to(NetworkData::WarpRequest(from), peer);
+        }
+    }
+
+    fn handle_batched_state_response(
+        &mut self,
+        justification: J::Unverified,
+        mut justifications: Vec<J::Unverified>,
+        peer: N::PeerId,
+    ) {
+        trace!(
+            target: LOG_TARGET,
+            "Handling batched state response {:?} {:?} received from {:?}.",
+            justification,
+            justifications,
+            peer
+        );
+        self.report_event(Event::HandleStateResponse);
+        if justifications.len() >= MAX_BATCHED_JUSTIFICATIONS {
+            debug!(
+                target: LOG_TARGET,
+                "Ignoring {} justifications over the batch limit from {:?}.",
+                justifications.len() + 1 - MAX_BATCHED_JUSTIFICATIONS,
+                peer
+            );
+            justifications.truncate(MAX_BATCHED_JUSTIFICATIONS - 1);
+        }
+        let (ids, maybe_error) = self.handler.handle_batched_state_response(
+            iter::once(justification).chain(justifications).collect(),
+            peer.clone(),
+        );
+        if let Some(e) = &maybe_error {
+            self.report_event_error(Event::HandleStateResponse, e);
+            self.remember_shed_justification(e);
+            self.rate_error(e, &peer);
+        }
+        match maybe_error {
+            Some(HandlerError::Verifier(e)) => debug!(
+                target: LOG_TARGET,
+                "Could not verify justification in batched sync state from {:?}: {}.", peer, e
+            ),
+            Some(e) => warn!(
+                target: LOG_TARGET,
+                "Failed to handle batched sync state response from {:?}: {}.", peer, e
+            ),
+            _ => {}
+        }
+        if let Some((highest, lower)) = ids.split_last() {
+            for id in lower {
+                self.request_block(id.id().clone());
+            }
+            self.request_highest_justified(highest.clone());
+        }
+    }
+
+    /// Whether the justification is of the last block of a session, which we did not push yet.
+    fn unpushed_session_end(&self, justification: &J::Unverified) -> Option<SessionId> {
+        let number = justification.id().number();
+        let session = self.session_info.session_id_from_block_num(number);
+        if self.session_info.last_block_of_session(session) != number {
+            return None;
+        }
+        match self.last_pushed_session {
+            Some(pushed) if pushed >= session => None,
+            _ => Some(session),
+        }
+    }
+
+    /// Pushes the justification closing the session to all the peers, so that the proof chain
+    /// through session boundaries propagates without waiting for state broadcasts.
+    fn push_session_end_justification(&mut self, justification: J::Unverified, session: SessionId) {
+        self.report_event(Event::PushSessionEndJustification);
+        self.last_pushed_session = Some(session);
+        debug!(
+            target: LOG_TARGET,
+            "Pushing justification {:?} closing session {:?} to all peers.",
+            justification.id(),
+            session
+        );
+        let data = NetworkData::StateBroadcastResponse(justification, None);
+        self.trace_broadcast(&data);
+        if let Err(e) = self.network.broadcast(data) {
+            self.report_network_error(Event::PushSessionEndJustification, &e);
+            warn!(
+                target: LOG_TARGET,
+                "Error pushing session end justification: {}.", e
+            );
+        }
+    }
+
+    /// Justifications from the committee closing a session are additionally pushed to all peers.
+    fn handle_justification_from_user(
+        &mut self,
+        justification: J::Unverified,
+        from_committee: bool,
+    ) {
+        trace!(
+            target: LOG_TARGET,
+            "Handling a justification {:?} from user.",
+            justification,
+        );
+        self.report_event(Event::HandleJustificationFromUser);
+        let session_end = match from_committee {
+            true => self
+                .unpushed_session_end(&justification)
+                .map(|session| (justification.clone(), session)),
+            false => None,
+        };
+        match self.handler.handle_justification_from_user(justification) {
+            Ok(maybe_id) => {
+                if let Some((justification, session)) = session_end {
+                    self.push_session_end_justification(justification, session);
+                }
+                if let Some(id) = maybe_id {
+                    self.request_highest_justified(id);
+                }
+            }
+            Err(e) => {
+                self.report_event_error(Event::HandleJustificationFromUser, &e);
+                self.remember_shed_justification(&e);
+                match e {
+                    HandlerError::Verifier(e) => debug!(
+                        target: LOG_TARGET,
+                        "Could not verify justification from user: {}", e
+                    ),
+                    e => warn!(
+                        target: LOG_TARGET,
                         "Failed to handle justification from user: {}", e
                     ),
                 }
@@ -297,130 +1521,407 @@ where
         }
     }
 
-    fn handle_request_response(&mut self, response_items: ResponseItems<B, J>, peer: N::PeerId) {
+    fn handle_request_response(
+        &mut self,
+        response_items: ResponseItems<B, J>,
+        peer: N::PeerId,
+        cancel: CancelToken,
+    ) {
         trace!(
             target: LOG_TARGET,
             "Handling request response from peer {:?}. Items: {:?}.",
             peer,
             response_items,
         );
-        self.metrics.report_event(Event::HandleRequestResponse);
-        let (maybe_id, maybe_error) = self
-            .handler
-            .handle_request_response(response_items, peer.clone());
+        self.report_event(Event::HandleRequestResponse);
+        self.answer_fetches(&response_items, &peer);
+        let response_items = self.take_downloaded_blocks(response_items, &peer);
+        if response_items.is_empty() {
+            return;
+        }
+        self.backfill_bodies(&response_items, &peer);
+        let only_headers = response_items
+            .iter()
+            .any(|item| matches!(item, ResponseItem::Header(_)))
+            && !response_items
+                .iter()
+                .any(|item| matches!(item, ResponseItem::Block(_)));
+        let (maybe_id, maybe_error) =
+            self.handler
+                .handle_request_response(response_items, peer.clone(), &cancel);
+        let only_headers = only_headers && maybe_error.is_none();
+        if let Some(e) = &maybe_error {
+            self.report_event_error(Event::HandleRequestResponse, e);
+            self.remember_shed_justification(e);
+            self.rate_error(e, &peer);
+        }
         match maybe_error {
             Some(HandlerError::Verifier(e)) => debug!(
                 target: LOG_TARGET,
                 "Could not verify justification from user: {}", e
             ),
+            Some(HandlerError::Canceled) => debug!(
+                target: LOG_TARGET,
+                "Stopped handling the response from {:?}, it disconnected.", peer
+            ),
             Some(e) => warn!(
                 target: LOG_TARGET,
                 "Failed to handle sync request response from {:?}: {}.", peer, e
             ),
             _ => {}
         }
+        if only_headers {
+            debug!(
+                target: LOG_TARGET,
+                "Received headers from {:?}, {} blocks wait for their bodies.",
+                peer,
+                self.handler.pending_bodies()
+            );
+        }
         if let Some(id) = maybe_id {
             self.request_highest_justified(id);
         }
     }
 
-    fn handle_request(&mut self, request: Request<J>, peer: N::PeerId) {
+    fn queue_request(&mut self, request: IncomingRequest<J>, peer: N::PeerId) {
+        // A newer request for the same target makes the ones still waiting pointless, the peer
+        // would get the same response twice.
+        if let IncomingRequest::Full(request) = &request {
+            let target = request.target_id().clone();
+            let superseded = self.request_queue.supersede(&peer, |(queued, _)| {
+                matches!(queued, IncomingRequest::Full(queued) if queued.target_id() == &target)
+            });
+            for _ in superseded {
+                self.record_serving(peer.clone(), ServingOutcome::Canceled, Duration::ZERO);
+            }
+        }
+        let priority = self.priority_peers.is_priority(&peer);
+        let cancel = self.network.connection(&peer);
+        if let Err(reason) =
+            self.request_queue
+                .push(peer.clone(), (request, cancel), priority, Instant::now())
+        {
+            self.report_event(Event::RequestDropped);
+            self.record_serving(peer.clone(), ServingOutcome::LimitExceeded, Duration::ZERO);
+            debug!(
+                target: LOG_TARGET,
+                "Dropping a request from {:?}: {}.", peer, reason
+            );
+            if reason == DropReason::RateExceeded {
+                self.rate_peer(peer, Misbehavior::RequestFlood);
+            }
+        }
+    }
+
+    /// Queues the response for handling, as an answer to the request for the block if the
+    /// network matched it with one, and to all the requests sent to the peer otherwise.
+    fn queue_request_response(
+        &mut self,
+        response_items: ResponseItems<B, J>,
+        answered: Option<BlockIdFor<J>>,
+        peer: N::PeerId,
+    ) {
+        // Honest nodes using our limits refuse to send responses this big.
+        let size = response_items.encoded_size();
+        if size > self.max_message_bytes as usize {
+            warn!(
+                target: LOG_TARGET,
+                "Dropping a response of {} bytes from {:?}.", size, peer
+            );
+            return self.rate_peer(peer, Misbehavior::OversizedResponse);
+        }
+        match answered {
+            Some(target) => self.in_flight.answered(&target, &peer),
+            None => self.in_flight.responded(&peer),
+        }
+        let cancel = self.network.connection(&peer);
+        // The signatures are checked on the workers first, unless they are all busy.
+        if !self.verification_pool.is_full() {
+            let (positions, checks): (Vec<_>, Vec<_>) = self
+                .handler
+                .offload_verification(&response_items)
+                .into_iter()
+                .unzip();
+            if !checks.is_empty() {
+                return self
+                    .verification_pool
+                    .submit((response_items, peer, cancel, positions), checks);
+            }
+        }
+        self.queue_justifications(
+            Lane::Requested,
+            QueuedJustifications::RequestResponse(response_items, peer, cancel),
+        )
+    }
+
+    /// Queues the response once its justifications were checked by the verification pool. The
+    /// ones that failed are verified again in place, to get the error.
+    fn handle_verified_response(
+        &mut self,
+        response: OffloadedResponse<B, J, N::PeerId>,
+        results: Vec<bool>,
+    ) {
+        let (response_items, peer, cancel, positions) = response;
+        if cancel.is_canceled() {
+            debug!(
+                target: LOG_TARGET,
+                "Dropping a verified response from {:?}, who disconnected.", peer
+            );
+            return;
+        }
+        let passed: Vec<_> = positions
+            .into_iter()
+            .zip(results)
+            .filter_map(|(position, passed)| passed.then_some(position))
+            .collect();
+        self.handler.passed_checks(&response_items, &passed);
+        self.queue_justifications(
+            Lane::Requested,
+            QueuedJustifications::RequestResponse(response_items, peer, cancel),
+        )
+    }
+
+    fn queue_justifications(
+        &mut self,
+        lane: Lane,
+        justifications: QueuedJustifications<B, J, N::PeerId>,
+    ) {
+        if let Err(e) = self.justification_queue.push(lane, justifications) {
+            self.report_event(Event::JustificationsDropped);
+            debug!(target: LOG_TARGET, "Dropping justifications: {}.", e);
+        }
+    }
+
+    fn handle_queued_justifications(&mut self) {
+        use QueuedJustifications::*;
+        let (_, queued) = match self.justification_queue.pop() {
+            Some(queued) => queued,
+            None => return,
+        };
+        // Gossip about finalized blocks is the first thing to go when we cannot keep up.
+        let historical = queued.gossiped_numbers().map_or(false, |numbers| {
+            numbers
+                .into_iter()
+                .all(|number| self.latency_budget.is_historical(number))
+        });
+        if historical
+            && self
+                .latency_budget
+                .sheds(ShedWork::HistoricalVerification, Instant::now())
+        {
+            return self.report_shed(ShedWork::HistoricalVerification);
+        }
+        match queued {
+            User(justification, from_committee) => {
+                self.handle_justification_from_user(justification, from_committee)
+            }
+            StateResponse(justification, maybe_justification, peer) => {
+                self.handle_state_response(justification, maybe_justification, peer)
+            }
+            BatchedStateResponse(justification, justifications, peer) => {
+                self.handle_batched_state_response(justification, justifications, peer)
+            }
+            WarpResponse(justifications, peer) => self.handle_warp_response(justifications, peer),
+            RequestResponse(response_items, peer, cancel) => {
+                self.handle_request_response(response_items, peer, cancel)
+            }
+        }
+    }
+
+    fn report_shed(&mut self, work: ShedWork) {
+        let event = match work {
+            ShedWork::HistoricalVerification => Event::HistoricalVerificationShed,
+            ShedWork::ColdRequest => Event::ColdRequestShed,
+        };
+        self.report_event(event);
+        debug!(
+            target: LOG_TARGET,
+            "Shedding {:?}, the backlog took longer than {:?}.", work, LATENCY_BUDGET
+        );
+    }
+
+    /// Whether the request needs data far below our top finalized block. Requests of priority
+    /// peers are never cold, they are served regardless of how far behind they are.
+    fn is_cold(&self, request: &IncomingRequest<J>, peer: &N::PeerId) -> bool {
+        if self.priority_peers.is_priority(peer) {
+            return false;
+        }
+        let requester_finalized = match request {
+            IncomingRequest::Full(request)
+            | IncomingRequest::Correlated(_, request)
+            | IncomingRequest::Headers(request) => {
+                request.state().top_justification().id().number()
+            }
+            IncomingRequest::Bodies(request) => request.to(),
+            IncomingRequest::Warp(from) => self.session_info.first_block_of_session(*from),
+        };
+        self.latency_budget.is_cold(requester_finalized)
+    }
+
+    fn handle_queued_request(&mut self) {
+        let (peer, (request, cancel)) = match self.request_queue.pop() {
+            Some(queued) => queued,
+            None => return,
+        };
+        if cancel.is_canceled() {
+            debug!(
+                target: LOG_TARGET,
+                "Not handling a request from {:?}, it disconnected.", peer
+            );
+            return self.record_serving(peer, ServingOutcome::Canceled, Duration::ZERO);
+        }
+        let start = Instant::now();
+        if self.latency_budget.sheds(ShedWork::ColdRequest, start) && self.is_cold(&request, &peer)
+        {
+            self.report_shed(ShedWork::ColdRequest);
+            return self.record_serving(peer, ServingOutcome::LimitExceeded, Duration::ZERO);
+        }
+        let outcome = match request {
+            IncomingRequest::Full(request) => {
+                self.handle_request(request, None, peer.clone(), &cancel)
+            }
+            IncomingRequest::Correlated(id, request) => {
+                self.handle_request(request, Some(id), peer.clone(), &cancel)
+            }
+            IncomingRequest::Bodies(request) => {
+                self.handle_body_request(request, peer.clone(), &cancel)
+            }
+            IncomingRequest::Headers(request) => {
+                self.handle_headers_request(request, peer.clone(), &cancel)
+            }
+            IncomingRequest::Warp(from) => self.handle_warp_request(from, peer.clone(), &cancel),
+        };
+        self.record_serving(peer, outcome, start.elapsed());
+    }
+
+    fn record_serving(&mut self, peer: N::PeerId, outcome: ServingOutcome, serving_time: Duration) {
+        self.metrics.report_request_served(outcome, serving_time);
+        self.serving_stats.record(peer, outcome, serving_time);
+    }
+
+    fn handle_request(
+        &mut self,
+        request: Request<J>,
+        request_id: Option<RequestId>,
+        peer: N::PeerId,
+        cancel: &CancelToken,
+    ) -> ServingOutcome {
         trace!(
             target: LOG_TARGET,
             "Handling a request {:?} from {:?}.",
             request,
             peer
         );
-        self.metrics.report_event(Event::HandleRequest);
+        self.report_event(Event::HandleRequest);
 
-        match self.handler.handle_request(request) {
+        let result = match self.priority_peers.is_priority(&peer) {
+            true => self.handler.handle_priority_request(request, cancel),
+            false => self.handler.handle_request(request, cancel),
+        };
+        match result {
             Ok(Action::Response(response_items)) => {
-                let mut limiter = MsgLimiter::new(&response_items);
-                loop {
-                    match limiter.next_largest_msg() {
-                        Ok(None) => {
-                            break;
-                        }
-                        Ok(Some(chunk)) => {
-                            self.send_to(NetworkData::RequestResponse(chunk.to_vec()), peer.clone())
-                        }
-                        Err(e) => {
-                            error!(
-                                target: LOG_TARGET,
-                                "Error while sending request response: {}.", e
-                            );
-                            break self.metrics.report_event_error(Event::HandleRequest);
-                        }
-                    }
-                }
+                self.send_response_in_chunks(&response_items, request_id, peer, cancel)
+            }
+            Ok(Action::RequestBlock(id)) => {
+                self.request_block(id);
+                ServingOutcome::Served
+            }
+            Err(HandlerError::Canceled) => {
+                debug!(
+                    target: LOG_TARGET,
+                    "Stopped handling a request from {:?}, it disconnected.", peer
+                );
+                ServingOutcome::Canceled
             }
-            Ok(Action::RequestBlock(id)) => self.request_block(id),
             Err(e) => {
-                self.metrics.report_event_error(Event::HandleRequest);
+                self.report_event_error(Event::HandleRequest, &e);
                 match e {
                     HandlerError::Verifier(e) => debug!(
                         target: LOG_TARGET,
                         "Could not verify justification from user: {}", e
                     ),
+                    HandlerError::OutsideServingWindow => debug!(
+                        target: LOG_TARGET,
+                        "Not serving request from {:?}: {}.", peer, e
+                    ),
                     e => warn!(
                         target: LOG_TARGET,
                         "Error handling request from {:?}: {}.", peer, e
                     ),
                 }
+                ServingOutcome::Rejected
             }
-            _ => {}
-        }
-    }
-
-    fn handle_task(&mut self, task: RequestTask<BlockIdFor<J>>) {
-        trace!(target: LOG_TARGET, "Handling task {}.", task);
-        if let TaskAction::Request(pre_request, (task, delay)) =
-            task.process(self.handler.interest_provider())
-        {
-            self.send_request(pre_request);
-            self.tasks.schedule_in(task, delay);
+            _ => ServingOutcome::Served,
         }
-        self.metrics.report_event(Event::HandleTask);
     }
 
-    fn handle_chain_event(&mut self, event: ChainStatusNotification<J::Header>) {
-        use ChainStatusNotification::*;
-        match event {
-            BlockImported(header) => {
-                trace!(target: LOG_TARGET, "Handling a new imported block.");
-                self.metrics.report_event(Event::HandleBlockImported);
-                if let Err(e) = self.handler.block_imported(header) {
-                    self.metrics.report_event_error(Event::HandleBlockImported);
-                    error!(
-                        target: LOG_TARGET,
-                        "Error marking block as imported: {}.", e
-                    )
-                }
+    /// Sends the response split into messages of at most the configured batch size, each within
+    /// the limits on the numbers of items a message can contain. Stops once the peer disconnects.
+    fn send_response_in_chunks(
+        &mut self,
+        mut response_items: &[ResponseItem<B, J>],
+        request_id: Option<RequestId>,
+        peer: N::PeerId,
+        cancel: &CancelToken,
+    ) -> ServingOutcome {
+        while !response_items.is_empty() {
+            if cancel.is_canceled() {
+                return ServingOutcome::Canceled;
             }
-            BlockFinalized(_) => {
-                trace!(target: LOG_TARGET, "Handling a new finalized block.");
-                self.metrics.report_event(Event::HandleBlockFinalized);
-                if self.broadcast_ticker.try_tick() {
-                    self.broadcast();
+            let (part, rest) = response_items.split_at(limited_response_prefix(response_items));
+            response_items = rest;
+            let mut limiter = MsgLimiter::with_limit(part, self.max_batch_bytes);
+            loop {
+                match limiter.next_largest_msg() {
+                    Ok(None) => {
+                        break;
+                    }
+                    Ok(Some(chunk)) => self.send_response(chunk, request_id, peer.clone()),
+                    Err(e) => {
+                        error!(
+                            target: LOG_TARGET,
+                            "Error while sending request response: {}.", e
+                        );
+                        self.report_event_error(Event::HandleRequest, &e);
+                        return ServingOutcome::Rejected;
+                    }
                 }
             }
         }
+        ServingOutcome::Served
     }
 
-    fn handle_internal_request(&mut self, id: BlockIdFor<J>) {
+    fn handle_headers_request(
+        &mut self,
+        request: Request<J>,
+        peer: N::PeerId,
+        cancel: &CancelToken,
+    ) -> ServingOutcome {
         trace!(
             target: LOG_TARGET,
-            "Handling an internal request for block {:?}.",
-            id,
+            "Handling a header request {:?} from {:?}.",
+            request,
+            peer
         );
-        self.metrics.report_event(Event::HandleInternalRequest);
-        match self.handler.handle_internal_request(&id) {
-            Ok(true) => self.request_block(id),
-
-            Ok(_) => debug!(target: LOG_TARGET, "Already requested block {:?}.", id),
-
+        self.report_event(Event::HandleRequest);
+        match self.handler.handle_headers_request(request, cancel) {
+            Ok(Action::Response(response_items)) => {
+                self.send_response_in_chunks(&response_items, None, peer, cancel)
+            }
+            Ok(Action::RequestBlock(id)) => {
+                self.request_block(id);
+                ServingOutcome::Served
+            }
+            Ok(Action::Noop) => ServingOutcome::Served,
+            Err(HandlerError::Canceled) => {
+                debug!(
+                    target: LOG_TARGET,
+                    "Stopped handling a header request from {:?}, it disconnected.", peer
+                );
+                ServingOutcome::Canceled
+            }
             Err(e) => {
-                self.metrics.report_event(Event::HandleInternalRequest);
+                self.report_event_error(Event::HandleRequest, &e);
                 match e {
                     HandlerError::Verifier(e) => debug!(
                         target: LOG_TARGET,
@@ -428,65 +1929,1047 @@ where
                     ),
                     e => warn!(
                         target: LOG_TARGET,
-                        "Error handling internal request for block {:?}: {}.", id, e
+                        "Error handling header request from {:?}: {}.", peer, e
                     ),
                 }
+                ServingOutcome::Rejected
             }
         }
     }
 
-    fn handle_network_data(&mut self, data: NetworkData<B, J>, peer: N::PeerId) {
-        use NetworkData::*;
-        match data {
-            StateBroadcast(state) => self.handle_state(state, peer),
-            StateBroadcastResponse(justification, maybe_justification) => {
-                self.handle_state_response(justification, maybe_justification, peer)
+    fn handle_warp_request(
+        &mut self,
+        from: SessionId,
+        peer: N::PeerId,
+        cancel: &CancelToken,
+    ) -> ServingOutcome {
+        trace!(
+            target: LOG_TARGET,
+            "Handling a warp request from session {:?} from {:?}.",
+            from,
+            peer
+        );
+        self.report_event(Event::HandleRequest);
+        let justifications = match self.handler.handle_warp_request(from) {
+            Ok(justifications) => justifications,
+            Err(e) => {
+                self.report_event_error(Event::HandleRequest, &e);
+                warn!(
+                    target: LOG_TARGET,
+                    "Error handling warp request from {:?}: {}.", peer, e
+                );
+                return ServingOutcome::Rejected;
             }
-            Request(request) => {
-                let state = request.state().clone();
-                self.handle_request(request, peer.clone());
-                self.handle_state(state, peer);
+        };
+        let mut limiter = MsgLimiter::with_limit(&justifications, self.max_batch_bytes);
+        loop {
+            if cancel.is_canceled() {
+                break ServingOutcome::Canceled;
+            }
+            match limiter.next_largest_msg() {
+                Ok(None) => break ServingOutcome::Served,
+                Ok(Some(chunk)) => {
+                    self.send_to(NetworkData::WarpResponse(chunk.to_vec()), peer.clone())
+                }
+                Err(e) => {
+                    error!(
+                        target: LOG_TARGET,
+                        "Error while sending warp response: {}.", e
+                    );
+                    self.report_event_error(Event::HandleRequest, &e);
+                    break ServingOutcome::Rejected;
+                }
             }
-            RequestResponse(response_items) => self.handle_request_response(response_items, peer),
         }
     }
 
-    /// Stay synchronized.
-    pub async fn run(mut self) {
-        loop {
-            tokio::select! {
-                maybe_data = self.network.next() => match maybe_data {
-                    Ok((data, peer)) => self.handle_network_data(data, peer),
-                    Err(e) => warn!(target: LOG_TARGET, "Error receiving data from network: {}.", e),
+    fn handle_body_request(
+        &mut self,
+        request: BodyRequest,
+        peer: N::PeerId,
+        cancel: &CancelToken,
+    ) -> ServingOutcome {
+        trace!(
+            target: LOG_TARGET,
+            "Handling a body request {:?} from {:?}.",
+            request,
+            peer
+        );
+        self.report_event(Event::HandleRequest);
+        if !self.capabilities.contains(Capabilities::BODY_RANGES) {
+            debug!(
+                target: LOG_TARGET,
+                "Not serving a body request from {:?}, we never announced serving them.", peer
+            );
+            return ServingOutcome::Rejected;
+        }
+        match self.handler.handle_body_request(request) {
+            Ok(response_items) if response_items.is_empty() => ServingOutcome::Served,
+            Ok(response_items) => self.send_response_in_chunks(&response_items, None, peer, cancel),
+            Err(e) => {
+                self.report_event_error(Event::HandleRequest, &e);
+                warn!(
+                    target: LOG_TARGET,
+                    "Error handling body request from {:?}: {}.", peer, e
+                );
+                ServingOutcome::Rejected
+            }
+        }
+    }
+
+    fn handle_task(&mut self, task: RequestTask<BlockIdFor<J>>) {
+        trace!(target: LOG_TARGET, "Handling task {}.", task);
+        if let TaskAction::Request(pre_request, (task, delay)) =
+            task.process(self.handler.interest_provider())
+        {
+            self.send_request(pre_request);
+            self.tasks.schedule_in(task, delay);
+        }
+        self.report_event(Event::HandleTask);
+    }
+
+    fn handle_chain_event(&mut self, event: ChainStatusNotification<J::Header>) {
+        use ChainStatusNotification::*;
+        match event {
+            BlockImported(header) => {
+                trace!(target: LOG_TARGET, "Handling a new imported block.");
+                self.report_event(Event::HandleBlockImported);
+                let id = header.id();
+                self.justification_latencies
+                    .imported(id.clone(), Instant::now());
+                // Imported through whatever path, no point waiting for the response.
+                self.in_flight.cancel(&id);
+                // Must be checked before the import is handled, as it might finalize the block.
+                let bodies_from = self.handler.bodies_from(&id);
+                if !bodies_from.is_empty() {
+                    self.import_notifications.notify(ImportedBlock {
+                        block: id,
+                        bodies_from,
+                    });
+                }
+                if let Err(e) = self.handler.block_imported(header.clone()) {
+                    self.report_event_error(Event::HandleBlockImported, &e);
+                    error!(
+                        target: LOG_TARGET,
+                        "Error marking block as imported: {}.", e
+                    )
+                }
+                self.announce(header);
+            }
+            BlockFinalized(header) => {
+                trace!(target: LOG_TARGET, "Handling a new finalized block.");
+                self.report_event(Event::HandleBlockFinalized);
+                self.counters.justification_imported();
+                self.metrics.report_justification_imported(
+                    self.session_info
+                        .session_id_from_block_num(header.id().number()),
+                );
+                self.measure_justification_latency(&header.id());
+                self.network.update_top_finalized(header.id().number());
+                self.network_view.our_finalized(header.id().number());
+                self.latency_budget.finalized(header.id().number());
+                self.in_flight.prune(header.id().number());
+                self.request_shed_justifications(header.id().number());
+                if self.broadcast_ticker.try_tick() {
+                    self.broadcast(false);
+                }
+                self.trigger_snapshot(&header.id());
+            }
+        }
+    }
+
+    fn measure_justification_latency(&mut self, id: &BlockIdFor<J>) {
+        let (latency, summary) = self.justification_latencies.justified(id, Instant::now());
+        if let Some(latency) = latency {
+            self.metrics.report_justification_latency(
+                latency,
+                self.session_info.session_id_from_block_num(id.number()),
+            );
+        }
+        if let Some(summary) = summary {
+            self.metrics.report_session_latencies(&summary);
+            info!(target: LOG_TARGET, "Justification latencies of {}.", summary);
+        }
+    }
+
+    /// Reports the view of the finality of the network, logging when we fall behind or the whole
+    /// network stalls.
+    fn check_network_view(&mut self) {
+        let now = Instant::now();
+        let status = self.network_view.status(now);
+        self.metrics
+            .report_network_view(self.network_view.stats(now), status);
+        let changed = mem::discriminant(&status) != mem::discriminant(&self.finality_status);
+        self.finality_status = status;
+        if !changed {
+            return;
+        }
+        match status {
+            FinalityStatus::Behind(_) | FinalityStatus::NetworkStalled(_) => {
+                warn!(target: LOG_TARGET, "Finality status changed: {}.", status)
+            }
+            FinalityStatus::NoPeers | FinalityStatus::InSync => {
+                info!(target: LOG_TARGET, "Finality status changed: {}.", status)
+            }
+        }
+    }
+
+    fn trigger_snapshot(&mut self, finalized: &BlockIdFor<J>) {
+        if let Some((point, pause)) = self.snapshots.finalized(finalized) {
+            self.report_event(Event::SnapshotTrigger);
+            info!(
+                target: LOG_TARGET,
+                "Triggering a snapshot after session {:?} at {:?}, {}.",
+                point.session,
+                point.block,
+                match pause {
+                    Some(_) => "pausing until it is taken",
+                    None => "nobody is taking it",
+                }
+            );
+            self.snapshot_pause = pause;
+        }
+    }
+
+    fn handle_internal_request(&mut self, id: BlockIdFor<J>) {
+        trace!(
+            target: LOG_TARGET,
+            "Handling an internal request for block {:?}.",
+            id,
+        );
+        self.report_event(Event::HandleInternalRequest);
+        match self.handler.handle_internal_request(&id) {
+            Ok(true) => self.request_block(id),
+
+            Ok(_) => debug!(target: LOG_TARGET, "Already requested block {:?}.", id),
+
+            Err(e) => {
+                self.report_event(Event::HandleInternalRequest);
+                match e {
+                    HandlerError::Verifier(e) => debug!(
+                        target: LOG_TARGET,
+                        "Could not verify justification from user: {}", e
+                    ),
+                    e => warn!(
+                        target: LOG_TARGET,
+                        "Error handling internal request for block {:?}: {}.", id, e
+                    ),
+                }
+            }
+        }
+    }
+
+    fn handle_capabilities(&mut self, capabilities: Capabilities, peer: N::PeerId) {
+        trace!(
+            target: LOG_TARGET,
+            "Handling capabilities {:?} announced by {:?}.",
+            capabilities,
+            peer
+        );
+        if self
+            .peer_availability
+            .update_capabilities(peer.clone(), capabilities)
+        {
+            self.send_to(NetworkData::AvailabilityRequest, peer.clone());
+        }
+        // Sent with every announcement, so that validators restarting learn about us again.
+        if capabilities.contains(Capabilities::PRIORITY_SERVICE) {
+            if let Some(ticket) = self.priority_ticket.clone() {
+                self.send_to(NetworkData::PriorityTicket(ticket), peer.clone());
+            }
+        }
+        if capabilities.contains(Capabilities::VALIDATOR_ROLES) {
+            if let Some(ticket) = self.validator_ticket.clone() {
+                self.send_to(NetworkData::ValidatorTicket(ticket), peer);
+            }
+        }
+    }
+
+    fn handle_validator_ticket(&mut self, ticket: ValidatorTicket, peer: N::PeerId) {
+        trace!(
+            target: LOG_TARGET,
+            "Handling a validator ticket {:?} from {:?}.",
+            ticket,
+            peer
+        );
+        if !self.peer_roles.authenticate(peer.clone(), &ticket) {
+            debug!(
+                target: LOG_TARGET,
+                "Rejected validator ticket from {:?}: bad signature.", peer
+            );
+            self.rate_peer(peer, Misbehavior::MalformedData);
+        }
+    }
+
+    fn handle_priority_ticket(&mut self, ticket: PriorityTicket, peer: N::PeerId) {
+        trace!(
+            target: LOG_TARGET,
+            "Handling a priority ticket {:?} from {:?}.",
+            ticket,
+            peer
+        );
+        if !self.priority_peers.serves_followers() {
+            debug!(
+                target: LOG_TARGET,
+                "Ignoring a priority ticket from {:?}, we do not serve any followers.", peer
+            );
+            return;
+        }
+        match self.priority_peers.authenticate(peer.clone(), &ticket) {
+            Ok(true) => info!(
+                target: LOG_TARGET,
+                "Serving {:?} with priority, authenticated with key {:?}.",
+                peer,
+                ticket.key()
+            ),
+            Ok(false) => {}
+            Err(e @ TicketError::UnknownKey) => debug!(
+                target: LOG_TARGET,
+                "Not serving {:?} with priority: {}.", peer, e
+            ),
+            Err(e @ TicketError::BadSignature) => {
+                debug!(
+                    target: LOG_TARGET,
+                    "Rejected priority ticket from {:?}: {}.", peer, e
+                );
+                self.rate_peer(peer, Misbehavior::MalformedData);
+            }
+        }
+    }
+
+    fn backfill_tick(&mut self) {
+        if !self.capabilities.contains(Capabilities::BLOCK_BODIES) {
+            // Without serving bodies we are not an archive, so there is nothing to fill.
+            if self.backfill.wants_anything() {
+                warn!(
+                    target: LOG_TARGET,
+                    "Not backfilling block bodies, this node does not keep them."
+                );
+                self.backfill.stop();
+            }
+            return;
+        }
+        if self.external_bodies.is_fetching() {
+            return;
+        }
+        let top_finalized = match self.handler.state() {
+            Ok(state) => state.top_justification().id().number(),
+            Err(e) => {
+                self.report_event_error(Event::BackfillRequest, &e);
+                return;
+            }
+        };
+        let handler = &self.handler;
+        let id = match self.backfill.tick(top_finalized, |number| {
+            handler
+                .missing_body(number)
+                .map(|maybe_id| maybe_id.map(FinalizedId::into_inner))
+        }) {
+            Ok(Some(id)) => id,
+            Ok(None) => return,
+            Err(e) => {
+                self.report_event_error(Event::BackfillRequest, &e);
+                warn!(
+                    target: LOG_TARGET,
+                    "Failed to look for missing block bodies: {}.", e
+                );
+                return;
+            }
+        };
+        self.report_event(Event::BackfillRequest);
+        if self.external_bodies.is_usable(Instant::now()) {
+            return self.fetch_external_bodies(id, top_finalized);
+        }
+        let peers = self
+            .peer_availability
+            .peers_serving_body_ranges(id.number());
+        if !peers.is_empty() {
+            let to = id
+                .number()
+                .saturating_add(MAX_BODY_REQUEST_BLOCKS - 1)
+                .min(top_finalized);
+            debug!(
+                target: LOG_TARGET,
+                "Requesting the bodies of blocks {} to {} from one of {} peers.",
+                id.number(),
+                to,
+                peers.len()
+            );
+            let request = BodyRequest::new(id.number(), to);
+            if let Err(e) = self
+                .network
+                .send_request_to_random(NetworkData::BodyRequest(request), peers)
+            {
+                self.report_network_error(Event::BackfillRequest, &e);
+                warn!(target: LOG_TARGET, "Error sending body request: {}.", e);
+            }
+            return;
+        }
+        // Peers running older software only serve bodies through the regular requests.
+        let state = match self.handler.backfill_state(&id) {
+            Ok(state) => state,
+            Err(e) => {
+                self.report_event_error(Event::BackfillRequest, &e);
+                warn!(
+                    target: LOG_TARGET,
+                    "Cannot request the missing body of {:?}: {}.", id, e
+                );
+                return;
+            }
+        };
+        let peers = self.peer_availability.peers_serving_body(id.number());
+        debug!(
+            target: LOG_TARGET,
+            "Requesting the missing body of {:?} from one of {} peers.",
+            id,
+            peers.len()
+        );
+        let request = Request::new(id.clone(), BranchKnowledge::LowestId(id), state);
+        if let Err(e) = self
+            .network
+            .send_request_to_random(NetworkData::Request(request), peers)
+        {
+            self.report_network_error(Event::BackfillRequest, &e);
+            warn!(target: LOG_TARGET, "Error sending backfill request: {}.", e);
+        }
+    }
+
+    /// Fetches the missing bodies from the block up from the external source, as many as a body
+    /// request to a peer would get.
+    fn fetch_external_bodies(&mut self, id: BlockIdFor<J>, top_finalized: BlockNumber) {
+        let to = id
+            .number()
+            .saturating_add(MAX_BODY_REQUEST_BLOCKS - 1)
+            .min(top_finalized);
+        let mut ids = vec![id.clone()];
+        for number in id.number().saturating_add(1)..=to {
+            match self.handler.missing_body(number) {
+                Ok(Some(missing)) if self.backfill.wants(missing.id()) => {
+                    ids.push(missing.into_inner())
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.report_event_error(Event::ExternalFetch, &e);
+                    break;
+                }
+            }
+        }
+        debug!(
+            target: LOG_TARGET,
+            "Fetching the bodies of {} blocks from {:?} up from the external source.",
+            ids.len(),
+            id
+        );
+        self.report_event(Event::ExternalFetch);
+        self.external_bodies.start(ids);
+    }
+
+    /// Imports the bodies from the external source that are still missing.
+    fn handle_external_bodies(&mut self, result: Result<Vec<B>, FetchError<BlockIdFor<J>>>) {
+        let blocks = match result {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                self.report_event_error(Event::ExternalFetch, &e);
+                warn!(
+                    target: LOG_TARGET,
+                    "Failed to fetch block bodies from the external source, asking peers for a while: {}.",
+                    e
+                );
+                return;
+            }
+        };
+        for block in blocks {
+            let id = block.header().id();
+            match self.handler.missing_body(id.number()) {
+                Ok(Some(missing)) if missing.id() == &id => {
+                    self.report_event(Event::BackfillBody);
+                    self.handler.import_fetched_block(block);
+                    self.backfill.body_fetched(&id);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.report_event_error(Event::BackfillBody, &e);
+                    warn!(
+                        target: LOG_TARGET,
+                        "Failed to check whether the body of {:?} is missing: {}.", id, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Imports the bodies of finalized blocks that are missing, the handler would skip them.
+    fn backfill_bodies(&mut self, response_items: &ResponseItems<B, J>, peer: &N::PeerId) {
+        for item in response_items {
+            let block = match item {
+                ResponseItem::Block(block) if self.backfill.wants(&block.header().id()) => block,
+                _ => continue,
+            };
+            let id = block.header().id();
+            match self.handler.missing_body(id.number()) {
+                Ok(Some(missing)) if missing.id() == &id => {
+                    self.report_event(Event::BackfillBody);
+                    self.handler.import_block_from(block.clone(), peer.clone());
+                    self.backfill.body_fetched(&id);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.report_event_error(Event::BackfillBody, &e);
+                    warn!(
+                        target: LOG_TARGET,
+                        "Failed to check whether the body of {:?} is missing: {}.", id, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Advances the parallel download of the gap below the network, starting it when far
+    /// behind, and requests the ranges that are due from the peers that finalized them.
+    fn range_download_tick(&mut self) {
+        if self.range_download.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        let network_finalized = match self.network_view.stats(now) {
+            Some(stats) => stats.median_finalized,
+            None => return,
+        };
+        let top_finalized = match self.handler.state() {
+            Ok(state) => state.top_justification().id(),
+            Err(e) => {
+                self.report_event_error(Event::RangeRequest, &e);
+                return;
+            }
+        };
+        let highest_holdable = self.handler.highest_holdable();
+        let serving = self
+            .peer_availability
+            .peers_serving_body_ranges(top_finalized.number() + 1);
+        let candidates: Vec<_> = self
+            .network_view
+            .peers(now)
+            .into_iter()
+            .filter(|state| state.fresh && serving.contains(&state.peer))
+            .map(|state| (state.peer, state.finalized))
+            .collect();
+        let requests = match &mut self.range_download {
+            Some(download) => {
+                download.update(top_finalized, network_finalized, highest_holdable);
+                download.requests(&candidates, now)
+            }
+            None => return,
+        };
+        for (peer, request) in requests {
+            self.report_event(Event::RangeRequest);
+            debug!(
+                target: LOG_TARGET,
+                "Requesting blocks {} to {} from {:?}.",
+                request.from(),
+                request.to(),
+                peer
+            );
+            if let Err(e) = self
+                .network
+                .send_request(NetworkData::BodyRequest(request), peer)
+            {
+                self.report_network_error(Event::RangeRequest, &e);
+                warn!(target: LOG_TARGET, "Error sending range request: {}.", e);
+            }
+        }
+    }
+
+    /// Takes the blocks of the ranges requested from the peer, importing the ones extending the
+    /// chain, and returns the rest of the items for the handler.
+    fn take_downloaded_blocks(
+        &mut self,
+        response_items: ResponseItems<B, J>,
+        peer: &N::PeerId,
+    ) -> ResponseItems<B, J> {
+        let download = match &mut self.range_download {
+            Some(download) if download.active() => download,
+            _ => return response_items,
+        };
+        let mut rest = Vec::new();
+        for item in response_items {
+            match item {
+                ResponseItem::Block(block) => {
+                    if let Some(block) = download.downloaded(peer, block) {
+                        rest.push(ResponseItem::Block(block));
+                    }
+                }
+                item => rest.push(item),
+            }
+        }
+        let ready = download.ready();
+        if !ready.is_empty() {
+            trace!(
+                target: LOG_TARGET,
+                "Importing {} downloaded blocks, {} ranges in flight.",
+                ready.len(),
+                download.in_flight()
+            );
+        }
+        for (peer, block) in ready {
+            self.report_event(Event::RangeBlock);
+            self.handler.import_block_from(block, peer);
+        }
+        rest
+    }
+
+    fn handle_availability_request(&mut self, peer: N::PeerId) {
+        trace!(
+            target: LOG_TARGET,
+            "Handling availability request from {:?}.",
+            peer
+        );
+        if self.capabilities.is_empty() {
+            return;
+        }
+        let top_finalized = match self.handler.state() {
+            Ok(state) => state.top_justification().id().number(),
+            Err(e) => {
+                warn!(
+                    target: LOG_TARGET,
+                    "Failed to construct own knowledge state: {}.", e
+                );
+                return;
+            }
+        };
+        let availability = Availability::for_archive(self.capabilities, top_finalized);
+        self.send_to(NetworkData::AvailabilityResponse(availability), peer);
+    }
+
+    fn handle_acknowledgement(&mut self, tag: AcknowledgementTag, peer: N::PeerId) {
+        self.report_event(Event::Acknowledgement);
+        if !self
+            .acknowledgements
+            .acknowledged(peer.clone(), tag, Instant::now())
+        {
+            debug!(
+                target: LOG_TARGET,
+                "Received an unexpected acknowledgement {} from {:?}.", tag, peer
+            );
+        }
+    }
+
+    fn handle_network_data(&mut self, data: NetworkData<B, J>, peer: N::PeerId) {
+        use NetworkData::*;
+        if self.peer_tracing.is_traced(&peer) {
+            info!(
+                target: PEER_TRACE_LOG_TARGET,
+                "Received from {:?}: {}.",
+                peer,
+                summary(&data)
+            );
+        }
+        if self.foreign_peers.contains(&peer) && !matches!(data, ForkIdAnnouncement(_)) {
+            trace!(
+                target: LOG_TARGET,
+                "Ignoring data from {:?}, which follows another fork.", peer
+            );
+            return;
+        }
+        match data {
+            StateBroadcast(state) => self.handle_state(state, peer),
+            ExtendedStateBroadcast(extended) => {
+                let (state, favourite_block) = extended.into_parts();
+                self.handle_state(state, peer.clone());
+                self.handle_favourite_block(favourite_block, peer);
+            }
+            StateBroadcastResponse(justification, maybe_justification) => self
+                .queue_justifications(
+                    Lane::Gossip,
+                    QueuedJustifications::StateResponse(justification, maybe_justification, peer),
+                ),
+            BatchedStateBroadcastResponse(justification, justifications) => self
+                .queue_justifications(
+                    Lane::Gossip,
+                    QueuedJustifications::BatchedStateResponse(justification, justifications, peer),
+                ),
+            Request(request) => {
+                // The state is cheap to handle, only the request itself waits in the queue.
+                let state = request.state().clone();
+                self.queue_request(IncomingRequest::Full(request), peer.clone());
+                self.handle_state(state, peer);
+            }
+            RequestResponse(response_items) => {
+                self.queue_request_response(response_items, None, peer)
+            }
+            CorrelatedRequest(id, request) => {
+                let state = request.state().clone();
+                self.queue_request(IncomingRequest::Correlated(id, request), peer.clone());
+                self.handle_state(state, peer);
+            }
+            // The network hands the ones matching our requests over to the requesters.
+            CorrelatedResponse(id, _) => debug!(
+                target: LOG_TARGET,
+                "Dropping a response from {:?} to request {}, which we did not send.", peer, id
+            ),
+            CapabilitiesAnnouncement(capabilities) => self.handle_capabilities(capabilities, peer),
+            AvailabilityRequest => self.handle_availability_request(peer),
+            AvailabilityResponse(availability) => self
+                .peer_availability
+                .update_availability(peer, availability),
+            // Everything sent before the request was handled already, since messages from a
+            // single peer arrive in order.
+            AcknowledgementRequest(tag) => self.send_to(Acknowledgement(tag), peer),
+            Acknowledgement(tag) => self.handle_acknowledgement(tag, peer),
+            BodyRequest(request) => self.queue_request(IncomingRequest::Bodies(request), peer),
+            HeaderRequest(request) => {
+                let state = request.state().clone();
+                self.queue_request(IncomingRequest::Headers(request), peer.clone());
+                self.handle_state(state, peer);
+            }
+            PriorityTicket(ticket) => self.handle_priority_ticket(ticket, peer),
+            ValidatorTicket(ticket) => self.handle_validator_ticket(ticket, peer),
+            Announcement(header) => self.handle_favourite_block(header, peer),
+            ForkIdAnnouncement(fork_id) => self.handle_fork_id(fork_id, peer),
+            WarpRequest(from) => self.queue_request(IncomingRequest::Warp(from), peer),
+            WarpResponse(justifications) => self.queue_justifications(
+                Lane::Requested,
+                QueuedJustifications::WarpResponse(justifications, peer),
+            ),
+        }
+    }
+
+    fn handle_forest_dump_requests(
+        &mut self,
+        requests: Vec<oneshot::Sender<ForestDump<N::PeerId, BlockIdFor<J>>>>,
+    ) {
+        if requests.is_empty() {
+            return;
+        }
+        let dump = self.handler.forest_dump();
+        debug!(
+            target: LOG_TARGET,
+            "Dumping the forest with {} vertices.",
+            dump.vertices.len()
+        );
+        for request in requests {
+            // The requester might have given up waiting already.
+            let _ = request.send(dump.clone());
+        }
+    }
+
+    fn status(&self) -> Result<SyncStatus<N::PeerId, BlockIdFor<J>>, HandlerError<B, J, CS, V, F>> {
+        Ok(SyncStatus {
+            top_finalized: self.handler.state()?.top_justification().id(),
+            favourite: self.handler.favourite_block()?.id(),
+            forks: self.handler.forks(),
+            pending_bodies: self.handler.pending_bodies(),
+            in_flight_requests: self.in_flight.waiting(),
+            peers: self.network_view.peers(Instant::now()),
+            peer_scores: self.peer_ratings.scores(Instant::now()),
+            serving: self.serving_stats.peers(),
+            recent_events: self.shutdown_recorder.last_events(),
+            recent_errors: self.shutdown_recorder.last_errors(),
+        })
+    }
+
+    fn handle_status_requests(
+        &mut self,
+        requests: Vec<oneshot::Sender<SyncStatus<N::PeerId, BlockIdFor<J>>>>,
+    ) {
+        if requests.is_empty() {
+            return;
+        }
+        let status = match self.status() {
+            Ok(status) => status,
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Error reporting the sync status: {}.", e);
+                return;
+            }
+        };
+        for request in requests {
+            // The requester might have given up waiting already.
+            let _ = request.send(status.clone());
+        }
+    }
+
+    /// Asks a random peer serving header chains for the justifications, with a state pretending
+    /// we only know the justification right before the session of the block, so that the
+    /// response can reach down to it.
+    fn handle_fetch_requests(
+        &mut self,
+        requests: Vec<(BlockIdFor<J>, oneshot::Sender<J::Unverified>)>,
+    ) {
+        // The requesters that gave up waiting do not need to be answered anymore.
+        self.pending_fetches.retain(|_, senders| {
+            senders.retain(|sender| !sender.is_canceled());
+            !senders.is_empty()
+        });
+        for (id, sender) in requests {
+            self.report_event(Event::JustificationFetch);
+            let state = match self.handler.backfill_state(&id) {
+                Ok(state) => state,
+                Err(e) => {
+                    self.report_event_error(Event::JustificationFetch, &e);
+                    warn!(
+                        target: LOG_TARGET,
+                        "Failed to construct the state for fetching the justification of {:?}: {}.",
+                        id,
+                        e
+                    );
+                    continue;
+                }
+            };
+            let peers = self.peers_with(
+                Capabilities::HEADER_CHAINS,
+                ProtocolFeatures::HEADER_REQUESTS,
+            );
+            if peers.is_empty() {
+                debug!(
+                    target: LOG_TARGET,
+                    "Not fetching the justification of {:?}, no peer serves header chains.", id
+                );
+                continue;
+            }
+            debug!(
+                target: LOG_TARGET,
+                "Fetching the justification of {:?} from one of {} peers.",
+                id,
+                peers.len()
+            );
+            let request = Request::new(id.clone(), BranchKnowledge::TopImported(id.clone()), state);
+            if let Err(e) = self
+                .network
+                .send_request_to_random(NetworkData::HeaderRequest(request), peers)
+            {
+                self.report_network_error(Event::JustificationFetch, &e);
+                warn!(target: LOG_TARGET, "Error sending justification fetch: {}.", e);
+                continue;
+            }
+            self.pending_fetches.entry(id).or_default().push(sender);
+        }
+    }
+
+    /// Answers the fetches of the justifications in the response, if they verify.
+    fn answer_fetches(&mut self, response_items: &ResponseItems<B, J>, peer: &N::PeerId) {
+        if self.pending_fetches.is_empty() {
+            return;
+        }
+        for item in response_items {
+            let justification = match item {
+                ResponseItem::Justification(justification) => justification,
+                _ => continue,
+            };
+            let id = justification.id();
+            let senders = match self.pending_fetches.remove(&id) {
+                Some(senders) => senders,
+                None => continue,
+            };
+            match self.handler.verify_finalized(justification.clone()) {
+                Ok(justification) => {
+                    let justification = justification.into_unverified();
+                    for sender in senders {
+                        // The requester might have given up waiting already.
+                        let _ = sender.send(justification.clone());
+                    }
+                }
+                Err(e) => {
+                    debug!(
+                        target: LOG_TARGET,
+                        "Fetched justification of {:?} from {:?} does not verify: {}.", id, peer, e
+                    );
+                    self.pending_fetches.insert(id, senders);
+                }
+            }
+        }
+    }
+
+    /// Waits for the next input of the service and handles it, returning what it was.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancellation safe, the inputs are only taken once they are handled, so
+    /// it can be used in a select loop of the embedder.
+    pub async fn step(&mut self) -> SyncEvent {
+        use SyncEvent::*;
+        // Nothing gets imported or finalized until the snapshot is taken.
+        if let Some(pause) = &mut self.snapshot_pause {
+            if !pause.wait().await {
+                warn!(
+                    target: LOG_TARGET,
+                    "Resuming sync before the snapshot got taken."
+                );
+            }
+            self.snapshot_pause = None;
+            return Snapshot;
+        }
+        self.latency_budget.iteration(
+            !self.request_queue.is_empty() || !self.justification_queue.is_empty(),
+            Instant::now(),
+        );
+        tokio::select! {
+            maybe_data = self.network.next() => match maybe_data {
+                Ok((data, peer)) => {
+                    self.handle_network_data(data, peer);
+                    NetworkData
                 },
-                Some(task) = self.tasks.pop() => self.handle_task(task),
-                _ = self.broadcast_ticker.wait_and_tick() => self.broadcast(),
-                maybe_event = self.chain_events.next() => match maybe_event {
-                    Ok(chain_event) => self.handle_chain_event(chain_event),
-                    Err(e) => warn!(target: LOG_TARGET, "Error when receiving a chain event: {}.", e),
+                Err(e) => {
+                    warn!(target: LOG_TARGET, "Error receiving data from network: {}.", e);
+                    NetworkError
                 },
-                maybe_justification = self.justifications_from_user.next() => match maybe_justification {
-                    Some(justification) => {
-                        debug!(target: LOG_TARGET, "Received new justification from user: {:?}.", justification);
-                        self.handle_justification_from_user(justification);
-                    },
-                    None => warn!(target: LOG_TARGET, "Channel with justifications from user closed."),
+            },
+            Some(task) = self.tasks.pop() => {
+                self.handle_task(task);
+                Task
+            },
+            Some((target, peer, response)) = self.matched_responses.next() => {
+                self.handle_matched_response(target, response, peer);
+                MatchedResponse
+            },
+            Some((response, results)) = self.verification_pool.next() => {
+                self.handle_verified_response(response, results);
+                VerifiedResponse
+            },
+            event = self.connection_events.next() => {
+                self.handle_connection_event(event);
+                PeerConnection
+            },
+            requests = self.forest_dumps.requests() => {
+                self.handle_forest_dump_requests(requests);
+                ForestDump
+            },
+            requests = self.status_reports.requests() => {
+                self.handle_status_requests(requests);
+                StatusReport
+            },
+            requests = self.justification_fetches.requests() => {
+                self.handle_fetch_requests(requests);
+                JustificationFetch
+            },
+            _ = tokio::task::yield_now(), if self.handler.forest_pruning_pending() => {
+                self.handler.prune_forest(FOREST_PRUNING_BUDGET);
+                ForestPruning
+            },
+            _ = tokio::time::sleep_until(self.handler.finalization_retry_at().unwrap_or_else(Instant::now).into()), if self.handler.finalization_retry_at().is_some() => {
+                self.retry_finalization();
+                FinalizationRetry
+            },
+            _ = tokio::task::yield_now(), if !self.request_queue.is_empty() => {
+                self.handle_queued_request();
+                QueuedRequest
+            },
+            _ = tokio::task::yield_now(), if !self.justification_queue.is_empty() => {
+                self.handle_queued_justifications();
+                QueuedJustifications
+            },
+            _ = self.backfill_ticker.tick() => {
+                self.backfill_tick();
+                BackfillTick
+            },
+            result = self.external_bodies.next() => {
+                self.handle_external_bodies(result);
+                ExternalFetch
+            },
+            _ = self.range_download_ticker.tick() => {
+                self.range_download_tick();
+                RangeDownloadTick
+            },
+            _ = self.forest_checkpoint_ticker.tick() => {
+                self.store_forest_checkpoint();
+                ForestCheckpoint
+            },
+            Some(params) = self.params_updates.next() => {
+                self.update_params(params);
+                ParamsUpdate
+            },
+            _ = self.broadcast_ticker.wait_and_tick() => {
+                self.finalize_pending();
+                self.broadcast(true);
+                self.check_network_view();
+                self.request_warp();
+                self.request_session_lookahead();
+                Broadcast
+            },
+            maybe_event = self.chain_events.next() => match maybe_event {
+                Ok(chain_event) => {
+                    self.handle_chain_event(chain_event);
+                    ChainEvent
                 },
-                maybe_justification = self.additional_justifications_from_user.next() => match maybe_justification {
-                    Some(justification) => {
-                        debug!(target: LOG_TARGET, "Received new additional justification from user: {:?}.", justification);
-                        self.handle_justification_from_user(justification);
-                    },
-                    None => warn!(target: LOG_TARGET, "Channel with additional justifications from user closed."),
+                Err(e) => {
+                    warn!(target: LOG_TARGET, "Error when receiving a chain event: {}.", e);
+                    ChainEventError
                 },
-                maybe_block_id = self.block_requests_from_user.next() => match maybe_block_id {
-                    Some(block_id) => {
-                        debug!(target: LOG_TARGET, "Received new internal block request from user: {:?}.", block_id);
-                        self.handle_internal_request(block_id)
-                    },
-                    None => warn!(target: LOG_TARGET, "Channel with internal block request from user closed."),
+            },
+            // Left in the channel while the lane is full, so none get dropped.
+            maybe_justification = self.justifications_from_user.next(), if self.justification_queue.has_room(Lane::User) => match maybe_justification {
+                Some(justification) => {
+                    debug!(target: LOG_TARGET, "Received new justification from user: {:?}.", justification);
+                    self.queue_justifications(Lane::User, QueuedJustifications::User(justification, true));
+                    UserJustification
                 },
-            }
+                None => {
+                    warn!(target: LOG_TARGET, "Channel with justifications from user closed.");
+                    InputClosed
+                },
+            },
+            maybe_justification = self.additional_justifications_from_user.next(), if self.justification_queue.has_room(Lane::User) => match maybe_justification {
+                Some(justification) => {
+                    debug!(target: LOG_TARGET, "Received new additional justification from user: {:?}.", justification);
+                    self.queue_justifications(Lane::User, QueuedJustifications::User(justification, false));
+                    AdditionalUserJustification
+                },
+                None => {
+                    warn!(target: LOG_TARGET, "Channel with additional justifications from user closed.");
+                    InputClosed
+                },
+            },
+            maybe_block_id = self.block_requests_from_user.next() => match maybe_block_id {
+                Some(block_id) => {
+                    debug!(target: LOG_TARGET, "Received new internal block request from user: {:?}.", block_id);
+                    self.handle_internal_request(block_id);
+                    UserBlockRequest
+                },
+                None => {
+                    warn!(target: LOG_TARGET, "Channel with internal block request from user closed.");
+                    InputClosed
+                },
+            },
+        }
+    }
+
+    /// The service as a stream of the inputs it handled, it keeps synchronizing for as long as
+    /// the stream is polled.
+    pub fn into_events(self) -> impl Stream<Item = SyncEvent> {
+        stream::unfold(self, |mut service| async move {
+            let event = service.step().await;
+            Some((event, service))
+        })
+    }
+
+    /// Stay synchronized.
+    pub async fn run(self) {
+        let events = self.into_events();
+        pin_mut!(events);
+        while events.next().await.is_some() {}
+    }
+}
+
+impl<B, J, N, CE, CS, V, F, BI, FC> Drop for Service<B, J, N, CE, CS, V, F, BI, FC>
+where
+    B: Block,
+    J: Justification<Header = B::Header>,
+    N: GossipNetwork<VersionedNetworkData<B, J>>,
+    CE: ChainStatusNotifier<B::Header>,
+    CS: ChainStatus<B, J>,
+    V: Verifier<J>,
+    F: Finalizer<J>,
+    BI: BlockImport<B>,
+    FC: ForestCheckpointStorage<J>,
+{
+    fn drop(&mut self) {
+        if let Err(e) = self
+            .forest_checkpoints
+            .store(&self.handler.forest_checkpoint())
+        {
+            warn!(target: LOG_TARGET, "Failed to store the forest checkpoint: {}.", e);
         }
+        let unfinished_requests = self.tasks.tasks().map(|task| task.to_string());
+        self.shutdown_recorder
+            .report(unfinished_requests)
+            .emit(self.backup_path.as_deref());
     }
 }
@@ -0,0 +1,156 @@
+use std::{cmp::Reverse, num::NonZeroUsize, time::Duration};
+
+use lru::LruCache;
+
+use crate::sync::PeerId;
+
+/// How many peers we keep the statistics of, the ones that sent requests longest ago are
+/// forgotten first.
+const MAX_SERVED_PEERS: usize = 1024;
+
+/// What became of a request a peer sent us.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ServingOutcome {
+    /// We responded, or found out we need a block the peer knows about.
+    Served,
+    /// We could not or would not respond, e.g. because it was outside the serving window.
+    Rejected,
+    /// It was dropped without handling, because the peer or all the peers together sent too
+    /// many of them, or because it was cold and we were too far behind with our work.
+    LimitExceeded,
+    /// We gave up on it before responding, because the peer disconnected or sent a newer request
+    /// superseding it.
+    Canceled,
+}
+
+impl ServingOutcome {
+    pub fn name(&self) -> &'static str {
+        use ServingOutcome::*;
+        match self {
+            Served => "served",
+            Rejected => "rejected",
+            LimitExceeded => "limit_exceeded",
+            Canceled => "canceled",
+        }
+    }
+}
+
+pub const ALL_SERVING_OUTCOMES: [ServingOutcome; 4] = [
+    ServingOutcome::Served,
+    ServingOutcome::Rejected,
+    ServingOutcome::LimitExceeded,
+    ServingOutcome::Canceled,
+];
+
+/// How the requests of a single peer were served.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerServing<I: PeerId> {
+    pub peer: I,
+    pub served: u64,
+    pub rejected: u64,
+    pub limit_exceeded: u64,
+    pub canceled: u64,
+    /// How long handling the requests that were not dropped took, in total and at most.
+    pub total_serving_time: Duration,
+    pub max_serving_time: Duration,
+}
+
+impl<I: PeerId> PeerServing<I> {
+    fn new(peer: I) -> Self {
+        PeerServing {
+            peer,
+            served: 0,
+            rejected: 0,
+            limit_exceeded: 0,
+            canceled: 0,
+            total_serving_time: Duration::ZERO,
+            max_serving_time: Duration::ZERO,
+        }
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.served + self.rejected + self.limit_exceeded + self.canceled
+    }
+}
+
+/// The statistics of serving the requests of the peers, for finding out who loads us the most.
+pub struct ServingStats<I: PeerId> {
+    peers: LruCache<I, PeerServing<I>>,
+}
+
+impl<I: PeerId> ServingStats<I> {
+    pub fn new() -> Self {
+        ServingStats {
+            peers: LruCache::new(
+                NonZeroUsize::new(MAX_SERVED_PEERS).expect("the constant is nonzero"),
+            ),
+        }
+    }
+
+    /// Records what became of a request of the peer, and how long handling it took.
+    pub fn record(&mut self, peer: I, outcome: ServingOutcome, serving_time: Duration) {
+        if !self.peers.contains(&peer) {
+            self.peers.put(peer.clone(), PeerServing::new(peer.clone()));
+        }
+        let serving = self.peers.get_mut(&peer).expect("we just inserted it");
+        match outcome {
+            ServingOutcome::Served => serving.served += 1,
+            ServingOutcome::Rejected => serving.rejected += 1,
+            ServingOutcome::LimitExceeded => serving.limit_exceeded += 1,
+            ServingOutcome::Canceled => serving.canceled += 1,
+        }
+        serving.total_serving_time += serving_time;
+        serving.max_serving_time = serving.max_serving_time.max(serving_time);
+    }
+
+    /// The statistics of all the remembered peers, the ones that sent the most requests first.
+    pub fn peers(&self) -> Vec<PeerServing<I>> {
+        let mut peers: Vec<_> = self
+            .peers
+            .iter()
+            .map(|(_, serving)| serving.clone())
+            .collect();
+        peers.sort_by_key(|serving| Reverse(serving.requests()));
+        peers
+    }
+}
+
+impl<I: PeerId> Default for ServingStats<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ServingOutcome::*, ServingStats};
+
+    #[test]
+    fn counts_outcomes_per_peer() {
+        let mut stats = ServingStats::<u32>::new();
+        stats.record(1, Served, Duration::from_millis(5));
+        stats.record(2, Rejected, Duration::from_millis(1));
+        stats.record(2, LimitExceeded, Duration::ZERO);
+        stats.record(2, Served, Duration::from_millis(20));
+        stats.record(2, Canceled, Duration::from_millis(2));
+        let peers = stats.peers();
+        assert_eq!(peers.len(), 2);
+        let busiest = &peers[0];
+        assert_eq!(busiest.peer, 2);
+        assert_eq!(
+            (
+                busiest.served,
+                busiest.rejected,
+                busiest.limit_exceeded,
+                busiest.canceled
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(busiest.total_serving_time, Duration::from_millis(23));
+        assert_eq!(busiest.max_serving_time, Duration::from_millis(20));
+        assert_eq!(peers[1].peer, 1);
+        assert_eq!(peers[1].requests(), 1);
+    }
+}
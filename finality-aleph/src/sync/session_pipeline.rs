@@ -0,0 +1,110 @@
+use std::{
+    cmp::max,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    session::{SessionBoundaryInfo, SessionId},
+    BlockNumber,
+};
+
+/// How long we wait for the justifications ending the sessions before asking again, possibly
+/// someone else.
+const LOOKAHEAD_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Decides when to ask for the justifications ending the current and the next session during a
+/// catch-up spanning sessions, so that the headers of the next session get fetched and verified
+/// while the blocks of the current one are still imported and finalized. The lookahead is a
+/// single session, the forest holds at most two sessions above the top finalized block, and the
+/// authorities of the later sessions are not known before the next one gets finalized anyway.
+pub struct SessionPipeline {
+    session_info: SessionBoundaryInfo,
+    last_request: Option<(SessionId, Instant)>,
+}
+
+impl SessionPipeline {
+    pub fn new(session_info: SessionBoundaryInfo) -> Self {
+        SessionPipeline {
+            session_info,
+            last_request: None,
+        }
+    }
+
+    /// The session the justifications ending sessions should be requested from, if they should
+    /// be requested now. Nothing is requested once the end of the next session is justified, or
+    /// while the network did not finalize it yet.
+    pub fn next_request(
+        &mut self,
+        our_finalized: BlockNumber,
+        highest_justified: BlockNumber,
+        network_finalized: BlockNumber,
+        now: Instant,
+    ) -> Option<SessionId> {
+        let session = self.session_info.session_id_from_block_num(our_finalized);
+        let next_session_end = self.session_info.last_block_of_session(session.next());
+        let justified = max(our_finalized, highest_justified);
+        if network_finalized < next_session_end || justified >= next_session_end {
+            return None;
+        }
+        let from = match justified >= self.session_info.last_block_of_session(session) {
+            true => session.next(),
+            false => session,
+        };
+        if let Some((requested, at)) = self.last_request {
+            if requested == from && now.saturating_duration_since(at) < LOOKAHEAD_REQUEST_TIMEOUT {
+                return None;
+            }
+        }
+        self.last_request = Some((from, now));
+        Some(from)
+    }
+
+    /// Whether the block is in a later session than our top finalized one, so its headers are
+    /// worth fetching and verifying before the bodies.
+    pub fn is_ahead(&self, our_finalized: BlockNumber, number: BlockNumber) -> bool {
+        self.session_info.session_id_from_block_num(number)
+            > self.session_info.session_id_from_block_num(our_finalized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{SessionPipeline, LOOKAHEAD_REQUEST_TIMEOUT};
+    use crate::session::{testing::session_boundary_info, SessionId};
+
+    #[test]
+    fn looks_one_session_ahead() {
+        let mut pipeline = SessionPipeline::new(session_boundary_info());
+        let now = Instant::now();
+        // The network did not finalize the end of the next session yet.
+        assert_eq!(pipeline.next_request(5, 5, 38, now), None);
+        assert_eq!(pipeline.next_request(5, 5, 39, now), Some(SessionId(0)));
+        // Still waiting for the response.
+        assert_eq!(
+            pipeline.next_request(5, 5, 39, now + Duration::from_secs(1)),
+            None
+        );
+        assert_eq!(
+            pipeline.next_request(5, 5, 39, now + LOOKAHEAD_REQUEST_TIMEOUT),
+            Some(SessionId(0))
+        );
+        // With the end of the current session known, only the next one is missing.
+        assert_eq!(pipeline.next_request(5, 19, 100, now), Some(SessionId(1)));
+        assert_eq!(pipeline.next_request(5, 39, 100, now), None);
+        // Having finalized the end of the current session means the same.
+        assert_eq!(
+            pipeline.next_request(19, 19, 100, now + LOOKAHEAD_REQUEST_TIMEOUT),
+            Some(SessionId(1))
+        );
+    }
+
+    #[test]
+    fn recognizes_blocks_of_later_sessions() {
+        let pipeline = SessionPipeline::new(session_boundary_info());
+        assert!(!pipeline.is_ahead(5, 19));
+        assert!(pipeline.is_ahead(5, 20));
+        assert!(pipeline.is_ahead(19, 25));
+    }
+}
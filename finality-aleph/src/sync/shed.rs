@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+
+use crate::{BlockIdentifier, BlockNumber};
+
+/// How many shed justifications we remember, the ones beyond that are only going to arrive when
+/// peers rebroadcast them.
+const MAX_REMEMBERED: usize = 1024;
+
+/// Remembers the blocks whose justifications had to be dropped, because they were too far ahead to
+/// be held, so that they can be requested again once there is room for them.
+pub struct ShedJustifications<BI: BlockIdentifier> {
+    shed: HashSet<BI>,
+    requested: HashSet<BI>,
+}
+
+impl<BI: BlockIdentifier> ShedJustifications<BI> {
+    pub fn new() -> Self {
+        ShedJustifications {
+            shed: HashSet::new(),
+            requested: HashSet::new(),
+        }
+    }
+
+    /// Remembers that the justification of the block was dropped.
+    /// Returns whether it was not remembered before.
+    pub fn shed(&mut self, id: BI) -> bool {
+        if self.shed.len() + self.requested.len() >= MAX_REMEMBERED || self.requested.contains(&id)
+        {
+            return false;
+        }
+        self.shed.insert(id)
+    }
+
+    /// Returns the blocks there is room for now, they should be requested again.
+    pub fn ready<F: Fn(&BI) -> bool>(&mut self, has_room: F) -> Vec<BI> {
+        let ready: Vec<_> = self
+            .shed
+            .iter()
+            .filter(|id| has_room(id))
+            .cloned()
+            .collect();
+        for id in &ready {
+            self.shed.remove(id);
+            self.requested.insert(id.clone());
+        }
+        ready
+    }
+
+    /// Forgets all the blocks up to the given finalized number.
+    /// Returns how many of them were requested again, i.e. recovered.
+    pub fn finalized(&mut self, number: BlockNumber) -> usize {
+        self.shed.retain(|id| id.number() > number);
+        let requested = self.requested.len();
+        self.requested.retain(|id| id.number() > number);
+        requested - self.requested.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ShedJustifications, MAX_REMEMBERED};
+    use crate::{
+        sync::{mock::MockIdentifier, Header},
+        BlockIdentifier,
+    };
+
+    fn branch(length: usize) -> Vec<MockIdentifier> {
+        MockIdentifier::new_random(0)
+            .random_branch()
+            .take(length)
+            .map(|header| header.id())
+            .collect()
+    }
+
+    #[test]
+    fn requests_again_when_there_is_room() {
+        let mut shed = ShedJustifications::new();
+        let ids = branch(10);
+        for id in &ids {
+            assert!(shed.shed(id.clone()));
+        }
+        assert!(!shed.shed(ids[0].clone()));
+        let mut ready = shed.ready(|id| id.number() <= 5);
+        ready.sort_by_key(|id| id.number());
+        assert_eq!(ready, ids[..5].to_vec());
+        assert!(shed.ready(|id| id.number() <= 5).is_empty());
+        assert!(!shed.shed(ids[0].clone()));
+    }
+
+    #[test]
+    fn counts_recovered_on_finalization() {
+        let mut shed = ShedJustifications::new();
+        let ids = branch(10);
+        for id in &ids {
+            shed.shed(id.clone());
+        }
+        shed.ready(|id| id.number() <= 5);
+        assert_eq!(shed.finalized(3), 3);
+        assert_eq!(shed.finalized(8), 2);
+        assert_eq!(shed.ready(|_| true).len(), 2);
+    }
+
+    #[test]
+    fn remembers_limited_number() {
+        let mut shed = ShedJustifications::new();
+        for id in branch(MAX_REMEMBERED) {
+            assert!(shed.shed(id));
+        }
+        assert!(!shed.shed(MockIdentifier::new_random(0)));
+    }
+}
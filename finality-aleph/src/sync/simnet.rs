@@ -0,0 +1,809 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Error as FmtError, Formatter},
+    iter,
+    str::FromStr,
+};
+
+use futures::FutureExt;
+use log::debug;
+
+use crate::{
+    network::CancelToken,
+    session::{SessionBoundaryInfo, SessionId},
+    sync::{
+        data::{NetworkData, Request, State},
+        finalization_hook::FinalizationHooks,
+        forest::Interest,
+        handler::{Action, DatabaseIO, HandleStateAction, Handler},
+        metrics::Metrics,
+        mock::{Backend, MockBlock, MockHeader, MockIdentifier, MockJustification, MockPeerId},
+        provenance::ProvenanceHistory,
+        BlockImport, BlockStatus, CandidateId, ChainStatus, ChainStatusNotification,
+        ChainStatusNotifier, Header, LOG_TARGET,
+    },
+    BlockIdentifier, BlockNumber, SessionPeriod,
+};
+
+type SimnetHandler =
+    Handler<MockBlock, MockPeerId, MockJustification, Backend, Backend, Backend, Backend>;
+
+/// A range of sessions, including both ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl SessionRange {
+    fn contains(&self, session: SessionId) -> bool {
+        self.first <= session.0 && session.0 <= self.last
+    }
+}
+
+/// A fault specification that could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FaultParseError(String);
+
+impl Display for FaultParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(f, "invalid fault specification: {}", self.0)
+    }
+}
+
+fn parse_number(number: &str) -> Result<u32, FaultParseError> {
+    number
+        .trim()
+        .parse()
+        .map_err(|_| FaultParseError(format!("{number:?} is not a number")))
+}
+
+impl FromStr for SessionRange {
+    type Err = FaultParseError;
+
+    /// Either a single session, like `3`, or a range of them, like `3-5`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, last) = match s.split_once('-') {
+            Some((first, last)) => (parse_number(first)?, parse_number(last)?),
+            None => {
+                let session = parse_number(s)?;
+                (session, session)
+            }
+        };
+        match first <= last {
+            true => Ok(SessionRange { first, last }),
+            false => Err(FaultParseError(format!(
+                "the sessions {s:?} are out of order"
+            ))),
+        }
+    }
+}
+
+/// A node that is offline for a range of sessions, keeping its database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crash {
+    pub node: usize,
+    pub sessions: SessionRange,
+}
+
+impl FromStr for Crash {
+    type Err = FaultParseError;
+
+    /// The node and the sessions, like `2@3-5`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (node, sessions) = s
+            .split_once('@')
+            .ok_or_else(|| FaultParseError(format!("{s:?} should look like NODE@SESSIONS")))?;
+        Ok(Crash {
+            node: parse_number(node)? as usize,
+            sessions: sessions.parse()?,
+        })
+    }
+}
+
+/// What to simulate. Every block slot some node produces a block, and a committee of the
+/// honest nodes finalizes blocks as soon as enough of them are connected and hold them.
+#[derive(Clone, Debug)]
+pub struct SimnetConfig {
+    /// How many nodes take part, both in producing blocks and in the finality committee.
+    pub nodes: usize,
+    /// For how many sessions the simulation runs, not counting the time to recover.
+    pub sessions: u32,
+    pub session_period: SessionPeriod,
+    /// The nodes are split into two halves during these sessions.
+    pub partitions: Vec<SessionRange>,
+    pub crashes: Vec<Crash>,
+    /// How many of the nodes, counting from the last one, send invalid justifications instead
+    /// of participating.
+    pub byzantine: usize,
+}
+
+/// What can be wrong with a simulation configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SimnetError {
+    NoNodes,
+    NoSessions,
+    EmptySessions,
+    TooManyByzantine(usize),
+    UnknownNode(usize),
+    Handler(String),
+}
+
+impl Display for SimnetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use SimnetError::*;
+        match self {
+            NoNodes => write!(f, "there has to be at least one node"),
+            NoSessions => write!(f, "there has to be at least one session"),
+            EmptySessions => write!(f, "sessions have to contain at least one block"),
+            TooManyByzantine(byzantine) => {
+                write!(f, "{byzantine} byzantine nodes are more than all the nodes")
+            }
+            UnknownNode(node) => write!(f, "there is no node {node} to crash"),
+            Handler(e) => write!(f, "failed to set up a node: {e}"),
+        }
+    }
+}
+
+/// How a single node fared.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeReport {
+    pub byzantine: bool,
+    pub top_finalized: BlockNumber,
+    /// In block slots, from the production of a block to the node finalizing it.
+    pub mean_finality_delay: Option<f64>,
+    pub max_finality_delay: Option<u32>,
+    /// How many pieces of data the node failed to handle, including the invalid ones.
+    pub errors: usize,
+}
+
+/// The outcome of a simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimnetReport {
+    /// The block slots the faults were active for.
+    pub slots: u32,
+    pub produced: usize,
+    /// The top block finalized by the committee.
+    pub finalized: BlockNumber,
+    /// The block slots in which the committee finalized nothing.
+    pub stalled_slots: u32,
+    /// How many block slots after the faults ended all the honest nodes caught up with the
+    /// committee, `None` if they did not within a session.
+    pub recovery_slots: Option<u32>,
+    pub nodes: Vec<NodeReport>,
+}
+
+impl Display for SimnetReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        writeln!(
+            f,
+            "Simulated {} nodes for {} block slots, produced {} blocks, finalized up to #{}, \
+            finality stalled for {} slots.",
+            self.nodes.len(),
+            self.slots,
+            self.produced,
+            self.finalized,
+            self.stalled_slots
+        )?;
+        match self.recovery_slots {
+            Some(slots) => writeln!(
+                f,
+                "All honest nodes caught up {slots} slots after the faults ended."
+            )?,
+            None => writeln!(
+                f,
+                "Some honest nodes did not catch up within a session after the faults ended."
+            )?,
+        }
+        writeln!(
+            f,
+            "{:>5} {:>9} {:>10} {:>10} {:>9} {:>7}",
+            "node", "role", "finalized", "mean delay", "max delay", "errors"
+        )?;
+        for (index, node) in self.nodes.iter().enumerate() {
+            let role = match node.byzantine {
+                true => "byzantine",
+                false => "honest",
+            };
+            let mean_delay = node
+                .mean_finality_delay
+                .map_or_else(|| "-".to_string(), |delay| format!("{delay:.2}"));
+            let max_delay = node
+                .max_finality_delay
+                .map_or_else(|| "-".to_string(), |delay| delay.to_string());
+            writeln!(
+                f,
+                "{:>5} {:>9} {:>10} {:>10} {:>9} {:>7}",
+                index,
+                role,
+                format!("#{}", node.top_finalized),
+                mean_delay,
+                max_delay,
+                node.errors
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct Node<N: ChainStatusNotifier<MockHeader>> {
+    byzantine: bool,
+    handler: SimnetHandler,
+    backend: Backend,
+    notifier: N,
+    best: MockHeader,
+    pending: HashSet<MockIdentifier>,
+    top_finalized: BlockNumber,
+    finality_delays: Vec<u32>,
+    errors: usize,
+}
+
+impl<N: ChainStatusNotifier<MockHeader>> Node<N> {
+    fn error(&mut self, e: impl Display) {
+        debug!(target: LOG_TARGET, "Simulated node failed to handle data: {}.", e);
+        self.errors += 1;
+    }
+
+    /// Passes the chain events to the handler, `produced_at` are the slots in which the
+    /// finalized blocks were produced.
+    fn handle_notifications(&mut self, slot: u32, produced_at: &[u32]) {
+        while let Some(Ok(notification)) = self.notifier.next().now_or_never() {
+            match notification {
+                ChainStatusNotification::BlockImported(header) => {
+                    if header.id().number() > self.best.id().number() {
+                        self.best = header.clone();
+                    }
+                    if let Err(e) = self.handler.block_imported(header) {
+                        self.error(e);
+                    }
+                }
+                ChainStatusNotification::BlockFinalized(header) => {
+                    let number = header.id().number();
+                    for finalized in self.top_finalized + 1..=number {
+                        self.finality_delays
+                            .push(slot.saturating_sub(produced_at[finalized as usize]));
+                    }
+                    self.top_finalized = number;
+                    if self.best.id().number() <= number
+                        || matches!(
+                            self.backend.status_of(self.best.id()),
+                            Ok(BlockStatus::Unknown)
+                        )
+                    {
+                        self.best = header;
+                    }
+                }
+            }
+        }
+    }
+
+    fn report(&self) -> NodeReport {
+        let delays = &self.finality_delays;
+        NodeReport {
+            byzantine: self.byzantine,
+            top_finalized: self.top_finalized,
+            mean_finality_delay: match delays.is_empty() {
+                true => None,
+                false => Some(delays.iter().map(|d| *d as f64).sum::<f64>() / delays.len() as f64),
+            },
+            max_finality_delay: delays.iter().max().copied(),
+            errors: self.errors,
+        }
+    }
+}
+
+fn setup_nodes(
+    config: &SimnetConfig,
+    session_info: SessionBoundaryInfo,
+) -> Result<Vec<Node<impl ChainStatusNotifier<MockHeader>>>, SimnetError> {
+    (0..config.nodes)
+        .map(|index| {
+            let (backend, notifier) = Backend::setup(session_info.clone());
+            let database_io = DatabaseIO::new(backend.clone(), backend.clone(), backend.clone());
+            let handler = Handler::new(
+                database_io,
+                backend.clone(),
+                session_info.clone(),
+                BlockNumber::MAX,
+                1,
+                None,
+                ProvenanceHistory::new(),
+                FinalizationHooks::new(),
+                Metrics::noop(),
+            )
+            .map_err(|e| SimnetError::Handler(e.to_string()))?;
+            Ok(Node {
+                byzantine: index >= config.nodes - config.byzantine,
+                handler,
+                backend,
+                notifier,
+                best: MockHeader::genesis(),
+                pending: HashSet::new(),
+                top_finalized: 0,
+                finality_delays: Vec::new(),
+                errors: 0,
+            })
+        })
+        .collect()
+}
+
+struct Simnet<N: ChainStatusNotifier<MockHeader>> {
+    config: SimnetConfig,
+    nodes: Vec<Node<N>>,
+    quorum: usize,
+    slot: u32,
+    faults: bool,
+    headers: HashMap<MockIdentifier, (MockHeader, u32)>,
+    top_finalized: MockIdentifier,
+    /// The slots in which the finalized blocks were produced, by number.
+    produced_at: Vec<u32>,
+    produced: usize,
+}
+
+impl<N: ChainStatusNotifier<MockHeader>> Simnet<N> {
+    fn session(&self) -> SessionId {
+        SessionId(self.slot / self.config.session_period.0)
+    }
+
+    fn alive(&self, node: usize) -> bool {
+        let session = self.session();
+        !self.faults
+            || !self
+                .config
+                .crashes
+                .iter()
+                .any(|crash| crash.node == node && crash.sessions.contains(session))
+    }
+
+    /// The part of the network the node is in, all the nodes are in one unless partitioned.
+    fn group(&self, node: usize) -> usize {
+        let session = self.session();
+        let partitioned = self.faults
+            && self
+                .config
+                .partitions
+                .iter()
+                .any(|partition| partition.contains(session));
+        match partitioned && node >= self.config.nodes / 2 {
+            true => 1,
+            false => 0,
+        }
+    }
+
+    fn reachable(&self, from: usize, to: usize) -> bool {
+        from != to && self.alive(from) && self.alive(to) && self.group(from) == self.group(to)
+    }
+
+    fn handle_notifications(&mut self, node: usize) {
+        self.nodes[node].handle_notifications(self.slot, &self.produced_at);
+    }
+
+    /// The produced blocks from above the finalized number up to the header, oldest first.
+    fn branch(&self, header: &MockHeader, finalized: BlockNumber) -> Vec<MockHeader> {
+        let mut branch = vec![header.clone()];
+        let mut current = header.clone();
+        while let Some((parent, _)) = current
+            .parent_id()
+            .filter(|parent| parent.number() > finalized)
+            .and_then(|parent| self.headers.get(&parent))
+        {
+            current = parent.clone();
+            branch.push(current.clone());
+        }
+        branch.reverse();
+        branch
+    }
+
+    /// The author of the slot extends its best block, at most a session ahead of its top
+    /// finalized block, and gossips the branch to the nodes it can reach.
+    fn produce(&mut self) {
+        let author = self.slot as usize % self.nodes.len();
+        if self.nodes[author].byzantine || !self.alive(author) {
+            return;
+        }
+        let node = &self.nodes[author];
+        if node.best.id().number() >= node.top_finalized + self.config.session_period.0 {
+            return;
+        }
+        let header = node.best.random_child();
+        self.headers
+            .insert(header.id(), (header.clone(), self.slot));
+        self.produced += 1;
+        let branch = self.branch(&header, self.nodes[author].top_finalized);
+        for node in 0..self.nodes.len() {
+            if node != author && !self.reachable(author, node) {
+                continue;
+            }
+            for header in &branch {
+                self.nodes[node]
+                    .backend
+                    .import_block(MockBlock::new(header.clone(), true));
+            }
+            self.handle_notifications(node);
+        }
+    }
+
+    /// The produced blocks above the top finalized one up to the header, `None` if the header
+    /// does not extend the top finalized block.
+    fn unfinalized_ancestry(&self, header: &MockHeader) -> Option<Vec<MockHeader>> {
+        let branch = self.branch(header, self.top_finalized.number());
+        match branch.first()?.parent_id()? == self.top_finalized {
+            true => Some(branch),
+            false => None,
+        }
+    }
+
+    /// A committee of connected honest nodes finalizes the highest block a quorum of them holds.
+    fn finalize(&mut self) -> bool {
+        let mut best_finalizable: Option<(Vec<MockHeader>, Vec<usize>)> = None;
+        for group in 0..=1 {
+            let ancestries: Vec<_> = (0..self.nodes.len())
+                .filter(|node| {
+                    !self.nodes[*node].byzantine && self.alive(*node) && self.group(*node) == group
+                })
+                .filter_map(|node| {
+                    self.unfinalized_ancestry(&self.nodes[node].best)
+                        .map(|ancestry| (node, ancestry))
+                })
+                .collect();
+            let mut holders: HashMap<MockIdentifier, Vec<usize>> = HashMap::new();
+            for (node, ancestry) in &ancestries {
+                for header in ancestry {
+                    holders.entry(header.id()).or_default().push(*node);
+                }
+            }
+            let highest = holders
+                .iter()
+                .filter(|(_, holders)| holders.len() >= self.quorum)
+                .max_by_key(|(id, _)| id.number());
+            if let Some((id, holders)) = highest {
+                let header = self.headers[id].0.clone();
+                let ancestry = self
+                    .unfinalized_ancestry(&header)
+                    .expect("only ancestries are considered");
+                best_finalizable = Some((ancestry, holders.clone()));
+            }
+        }
+        let (ancestry, holders) = match best_finalizable {
+            Some(finalizable) => finalizable,
+            None => return false,
+        };
+        for header in &ancestry {
+            self.produced_at.push(self.headers[&header.id()].1);
+        }
+        self.top_finalized = ancestry.last().expect("ancestries are not empty").id();
+        for node in holders {
+            for header in &ancestry {
+                if header.id().number() <= self.nodes[node].top_finalized {
+                    continue;
+                }
+                let justification = MockJustification::for_header(header.clone());
+                if let Err(e) = self.nodes[node]
+                    .handler
+                    .handle_justification_from_user(justification)
+                {
+                    self.nodes[node].error(e);
+                }
+                self.handle_notifications(node);
+            }
+        }
+        true
+    }
+
+    /// A justification byzantine nodes try to convince others with.
+    fn invalid_justification(&self) -> MockJustification {
+        MockJustification::invalid_for_header(MockHeader::random_parentless(
+            self.top_finalized.number() + 1,
+        ))
+    }
+
+    fn handle_response(
+        &mut self,
+        node: usize,
+        peer: usize,
+        data: NetworkData<MockBlock, MockJustification>,
+    ) {
+        let (ids, maybe_error) = match data {
+            NetworkData::StateBroadcastResponse(justification, maybe_justification) => {
+                let (maybe_id, maybe_error) = self.nodes[node].handler.handle_state_response(
+                    justification,
+                    maybe_justification,
+                    peer as MockPeerId,
+                );
+                (maybe_id.into_iter().collect(), maybe_error)
+            }
+            NetworkData::BatchedStateBroadcastResponse(justification, justifications) => {
+                self.nodes[node].handler.handle_batched_state_response(
+                    iter::once(justification).chain(justifications).collect(),
+                    peer as MockPeerId,
+                )
+            }
+            _ => (Vec::new(), None),
+        };
+        self.nodes[node]
+            .pending
+            .extend(ids.into_iter().map(CandidateId::into_inner));
+        if let Some(e) = maybe_error {
+            self.nodes[node].error(e);
+        }
+        self.handle_notifications(node);
+    }
+
+    /// Every node broadcasts its state to all the nodes it can reach, which respond.
+    fn exchange_states(&mut self) {
+        for node in 0..self.nodes.len() {
+            for peer in 0..self.nodes.len() {
+                if !self.reachable(node, peer) {
+                    continue;
+                }
+                let state = match self.nodes[node].byzantine {
+                    true => State::new(self.invalid_justification()),
+                    false => match self.nodes[node].handler.state() {
+                        Ok(state) => state,
+                        Err(e) => {
+                            self.nodes[node].error(e);
+                            continue;
+                        }
+                    },
+                };
+                if self.nodes[peer].byzantine {
+                    if !self.nodes[node].byzantine {
+                        let response = NetworkData::BatchedStateBroadcastResponse(
+                            self.invalid_justification(),
+                            Vec::new(),
+                        );
+                        self.handle_response(node, peer, response);
+                    }
+                    continue;
+                }
+                match self.nodes[peer]
+                    .handler
+                    .handle_state(state, node as MockPeerId)
+                {
+                    Ok(HandleStateAction::Response(data)) if !self.nodes[node].byzantine => {
+                        self.handle_response(node, peer, data)
+                    }
+                    Ok(HandleStateAction::HighestJustified(id)) => {
+                        self.nodes[peer].pending.insert(id.into_inner());
+                    }
+                    Ok(_) => {}
+                    Err(e) => self.nodes[peer].error(e),
+                }
+                self.handle_notifications(peer);
+            }
+        }
+    }
+
+    /// Honest nodes request what they are interested in from the peers knowing most about it,
+    /// byzantine peers never answer.
+    fn exchange_requests(&mut self) {
+        for node in 0..self.nodes.len() {
+            if self.nodes[node].byzantine || !self.alive(node) {
+                continue;
+            }
+            let mut pending: Vec<_> = self.nodes[node].pending.drain().collect();
+            pending.sort_by_key(|id| id.number());
+            for id in pending {
+                let (know_most, branch_knowledge) =
+                    match self.nodes[node].handler.interest_provider().get(&id) {
+                        Interest::Uninterested => continue,
+                        Interest::Required {
+                            know_most,
+                            branch_knowledge,
+                        }
+                        | Interest::HighestJustified {
+                            know_most,
+                            branch_knowledge,
+                        } => (know_most, branch_knowledge),
+                    };
+                self.nodes[node].pending.insert(id.clone());
+                let mut candidates: Vec<_> = know_most
+                    .into_iter()
+                    .map(|peer| peer as usize)
+                    .filter(|peer| self.reachable(node, *peer))
+                    .collect();
+                if candidates.is_empty() {
+                    continue;
+                }
+                candidates.sort();
+                let peer = candidates[self.slot as usize % candidates.len()];
+                if self.nodes[peer].byzantine {
+                    continue;
+                }
+                let state = match self.nodes[node].handler.state() {
+                    Ok(state) => state,
+                    Err(e) => {
+                        self.nodes[node].error(e);
+                        continue;
+                    }
+                };
+                let request = Request::new(id, branch_knowledge, state);
+                let cancel = CancelToken::new();
+                match self.nodes[peer].handler.handle_request(request, &cancel) {
+                    Ok(Action::Response(items)) => {
+                        let (maybe_id, maybe_error) = self.nodes[node]
+                            .handler
+                            .handle_request_response(items, peer as MockPeerId, &cancel);
+                        self.nodes[node]
+                            .pending
+                            .extend(maybe_id.map(CandidateId::into_inner));
+                        if let Some(e) = maybe_error {
+                            self.nodes[node].error(e);
+                        }
+                        self.handle_notifications(node);
+                    }
+                    Ok(Action::RequestBlock(id)) => {
+                        self.nodes[peer].pending.insert(id);
+                    }
+                    Ok(Action::Noop) => {}
+                    Err(e) => self.nodes[peer].error(e),
+                }
+            }
+        }
+    }
+
+    fn run_slot(&mut self) -> bool {
+        self.produce();
+        let finalized = self.finalize();
+        self.exchange_states();
+        self.exchange_requests();
+        for node in &mut self.nodes {
+            // Pruning takes no simulated time, so everything stale is removed at once.
+            node.handler.prune_forest(usize::MAX);
+        }
+        self.slot += 1;
+        finalized
+    }
+
+    fn caught_up(&self) -> bool {
+        self.nodes
+            .iter()
+            .all(|node| node.byzantine || node.top_finalized == self.top_finalized.number())
+    }
+}
+
+impl SimnetConfig {
+    fn validate(&self) -> Result<(), SimnetError> {
+        use SimnetError::*;
+        if self.nodes == 0 {
+            return Err(NoNodes);
+        }
+        if self.sessions == 0 {
+            return Err(NoSessions);
+        }
+        if self.session_period.0 == 0 {
+            return Err(EmptySessions);
+        }
+        if self.byzantine > self.nodes {
+            return Err(TooManyByzantine(self.byzantine));
+        }
+        match self.crashes.iter().find(|crash| crash.node >= self.nodes) {
+            Some(crash) => Err(UnknownNode(crash.node)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Runs nodes with the sync handlers on top of mock backends, exchanging data directly, and
+/// reports how finality fared. After the configured sessions the faults end, and the nodes get
+/// up to a session to catch up.
+pub fn run_simnet(config: SimnetConfig) -> Result<SimnetReport, SimnetError> {
+    config.validate()?;
+    let session_info = SessionBoundaryInfo::new(config.session_period);
+    let nodes = setup_nodes(&config, session_info.clone())?;
+    let mut simnet = Simnet {
+        quorum: config.nodes - (config.nodes - 1) / 3,
+        config,
+        nodes,
+        slot: 0,
+        faults: true,
+        headers: HashMap::new(),
+        top_finalized: MockHeader::genesis().id(),
+        produced_at: vec![0],
+        produced: 0,
+    };
+    let slots = simnet.config.sessions * simnet.config.session_period.0;
+    let mut stalled_slots = 0;
+    while simnet.slot < slots {
+        if !simnet.run_slot() {
+            stalled_slots += 1;
+        }
+    }
+    simnet.faults = false;
+    let mut recovery_slots = None;
+    for slot in 0..=simnet.config.session_period.0 {
+        if simnet.caught_up() {
+            recovery_slots = Some(slot);
+            break;
+        }
+        simnet.run_slot();
+    }
+    Ok(SimnetReport {
+        slots,
+        produced: simnet.produced,
+        finalized: simnet.top_finalized.number(),
+        stalled_slots,
+        recovery_slots,
+        nodes: simnet.nodes.iter().map(Node::report).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_simnet, Crash, SessionRange, SimnetConfig, SimnetError};
+    use crate::SessionPeriod;
+
+    fn config(nodes: usize) -> SimnetConfig {
+        SimnetConfig {
+            nodes,
+            sessions: 6,
+            session_period: SessionPeriod(20),
+            partitions: Vec::new(),
+            crashes: Vec::new(),
+            byzantine: 0,
+        }
+    }
+
+    #[test]
+    fn parses_faults() {
+        assert_eq!("3-5".parse(), Ok(SessionRange { first: 3, last: 5 }));
+        assert_eq!("4".parse(), Ok(SessionRange { first: 4, last: 4 }));
+        assert!("5-3".parse::<SessionRange>().is_err());
+        assert_eq!(
+            "2@1-2".parse(),
+            Ok(Crash {
+                node: 2,
+                sessions: SessionRange { first: 1, last: 2 }
+            })
+        );
+        assert!("2".parse::<Crash>().is_err());
+    }
+
+    #[test]
+    fn finalizes_everything_without_faults() {
+        let report = run_simnet(config(4)).expect("the config is correct");
+        assert_eq!(report.produced, 120);
+        assert_eq!(report.finalized, 120);
+        assert_eq!(report.stalled_slots, 0);
+        assert_eq!(report.recovery_slots, Some(0));
+        for node in report.nodes {
+            assert_eq!(node.top_finalized, 120);
+            assert_eq!(node.max_finality_delay, Some(0));
+            assert_eq!(node.errors, 0);
+        }
+    }
+
+    #[test]
+    fn recovers_from_faults() {
+        // With one byzantine node the five honest ones left outside of the partition and the
+        // crash are exactly a quorum.
+        let mut config = config(7);
+        config.partitions.push(SessionRange { first: 1, last: 1 });
+        config.crashes.push(Crash {
+            node: 1,
+            sessions: SessionRange { first: 2, last: 4 },
+        });
+        config.byzantine = 1;
+        let report = run_simnet(config).expect("the config is correct");
+        assert!(report.stalled_slots >= 20, "should stall while partitioned");
+        assert!(report.recovery_slots.is_some(), "should recover");
+        let crashed = &report.nodes[1];
+        assert_eq!(crashed.top_finalized, report.finalized);
+        assert!(crashed.max_finality_delay > Some(20));
+        assert!(report.nodes[6].byzantine);
+        assert!(report.nodes[0].errors > 0, "should reject invalid data");
+    }
+
+    #[test]
+    fn rejects_incorrect_configs() {
+        let mut incorrect = config(4);
+        incorrect.byzantine = 5;
+        assert_eq!(run_simnet(incorrect), Err(SimnetError::TooManyByzantine(5)));
+        let mut incorrect = config(4);
+        incorrect.crashes.push(Crash {
+            node: 4,
+            sessions: SessionRange { first: 0, last: 0 },
+        });
+        assert_eq!(run_simnet(incorrect), Err(SimnetError::UnknownNode(4)));
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    fs,
+    io::Error as IoError,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    StreamExt,
+};
+use log::{info, warn};
+use parking_lot::Mutex;
+use tokio::time::{sleep, timeout_at, Instant};
+
+use crate::{
+    session::{SessionBoundaryInfo, SessionId},
+    sync::LOG_TARGET,
+    BlockIdentifier, BlockNumber,
+};
+
+/// The longest sync is paused for a single snapshot, even if some subscriber did not finish.
+pub const MAX_SNAPSHOT_PAUSE: Duration = Duration::from_secs(30);
+/// The file the exporter writes the snapshot point to, and waits for to be removed.
+pub const SNAPSHOT_MARKER_FILE: &str = "snapshot.pending";
+/// How often the exporter checks whether the external tooling finished.
+const MARKER_POLL_PERIOD: Duration = Duration::from_millis(100);
+
+/// A finality consistent point of the database, after the given session was finalized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotPoint<BI: BlockIdentifier> {
+    /// The last session finalized in full.
+    pub session: SessionId,
+    /// The top finalized block at the moment of the snapshot.
+    pub block: BI,
+}
+
+/// Keeps sync paused while held, the pause ends once all the holds of a trigger are dropped.
+#[derive(Clone)]
+pub struct SnapshotHold {
+    _sender: Arc<oneshot::Sender<()>>,
+}
+
+/// What a subscriber gets when a snapshot should be taken. Sync stays paused until the trigger,
+/// or at least its hold, is dropped, but no longer than `MAX_SNAPSHOT_PAUSE`.
+pub struct SnapshotTrigger<BI: BlockIdentifier> {
+    pub point: SnapshotPoint<BI>,
+    pub hold: SnapshotHold,
+}
+
+/// The pause of sync for the duration of a snapshot.
+pub struct SnapshotPause {
+    finished: oneshot::Receiver<()>,
+    deadline: Instant,
+}
+
+impl SnapshotPause {
+    /// Waits until all the subscribers are done, returns whether they were before the deadline.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancellation safe, the deadline stays the same.
+    pub async fn wait(&mut self) -> bool {
+        timeout_at(self.deadline, &mut self.finished).await.is_ok()
+    }
+}
+
+/// Triggers snapshots of the database once every configured number of finalized sessions.
+/// Can be cloned and subscribed to while sync is running.
+#[derive(Clone)]
+pub struct SnapshotTriggers<BI: BlockIdentifier> {
+    every_sessions: Option<NonZeroU32>,
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<SnapshotTrigger<BI>>>>>,
+}
+
+impl<BI: BlockIdentifier> SnapshotTriggers<BI> {
+    /// Snapshots are triggered every `every_sessions` sessions, never without it.
+    pub fn new(every_sessions: Option<NonZeroU32>) -> Self {
+        SnapshotTriggers {
+            every_sessions,
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// A subscriber that never takes the triggers keeps sync paused for the maximum time at
+    /// every snapshot, so it should be dropped when no longer used.
+    pub fn subscribe(&self) -> SnapshotSubscription<BI> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.lock().push(sender);
+        SnapshotSubscription { receiver }
+    }
+
+    /// Sends the trigger to all the current subscribers, returns the pause to wait for if there
+    /// were any.
+    fn trigger(&self, point: SnapshotPoint<BI>) -> Option<SnapshotPause> {
+        let (sender, finished) = oneshot::channel();
+        let hold = SnapshotHold {
+            _sender: Arc::new(sender),
+        };
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|subscriber| {
+            subscriber
+                .unbounded_send(SnapshotTrigger {
+                    point: point.clone(),
+                    hold: hold.clone(),
+                })
+                .is_ok()
+        });
+        match subscribers.is_empty() {
+            true => None,
+            false => Some(SnapshotPause {
+                finished,
+                deadline: Instant::now() + MAX_SNAPSHOT_PAUSE,
+            }),
+        }
+    }
+}
+
+/// A single subscription to the snapshot triggers.
+pub struct SnapshotSubscription<BI: BlockIdentifier> {
+    receiver: mpsc::UnboundedReceiver<SnapshotTrigger<BI>>,
+}
+
+impl<BI: BlockIdentifier> SnapshotSubscription<BI> {
+    /// The next trigger, `None` once all the trigger handles are dropped.
+    pub async fn next(&mut self) -> Option<SnapshotTrigger<BI>> {
+        self.receiver.next().await
+    }
+}
+
+/// Decides when the next snapshot is due, used by the sync service.
+pub struct SnapshotSchedule<BI: BlockIdentifier> {
+    triggers: SnapshotTriggers<BI>,
+    session_info: SessionBoundaryInfo,
+    last: Option<SessionId>,
+}
+
+impl<BI: BlockIdentifier> SnapshotSchedule<BI> {
+    /// Counts the sessions from the one finalized in full at the top finalized block.
+    pub fn new(
+        triggers: SnapshotTriggers<BI>,
+        session_info: SessionBoundaryInfo,
+        top_finalized: BlockNumber,
+    ) -> Self {
+        let mut schedule = SnapshotSchedule {
+            triggers,
+            session_info,
+            last: None,
+        };
+        schedule.last = schedule.finalized_session(top_finalized);
+        schedule
+    }
+
+    /// The last session all the blocks of which are finalized.
+    fn finalized_session(&self, top_finalized: BlockNumber) -> Option<SessionId> {
+        let session = self.session_info.session_id_from_block_num(top_finalized);
+        match self.session_info.last_block_of_session(session) == top_finalized {
+            true => Some(session),
+            false => session.0.checked_sub(1).map(SessionId),
+        }
+    }
+
+    /// Informs about a newly finalized block, returns the point of the snapshot if one is due.
+    fn due(&mut self, block: &BI) -> Option<SnapshotPoint<BI>> {
+        let every = self.triggers.every_sessions?;
+        let session = self.finalized_session(block.number())?;
+        let finalized_since = match self.last {
+            Some(last) => session.0.saturating_sub(last.0),
+            None => session.0.saturating_add(1),
+        };
+        if finalized_since < every.get() {
+            return None;
+        }
+        self.last = Some(session);
+        Some(SnapshotPoint {
+            session,
+            block: block.clone(),
+        })
+    }
+
+    /// Informs about a newly finalized block, triggers a snapshot if one is due. Returns the
+    /// point and the pause sync should wait for, if anybody is snapshotting.
+    pub fn finalized(&mut self, block: &BI) -> Option<(SnapshotPoint<BI>, Option<SnapshotPause>)> {
+        let point = self.due(block)?;
+        let pause = self.triggers.trigger(point.clone());
+        Some((point, pause))
+    }
+}
+
+fn write_marker<BI: BlockIdentifier>(
+    path: &Path,
+    point: &SnapshotPoint<BI>,
+) -> Result<(), IoError> {
+    let temporary = path.with_extension("tmp");
+    fs::write(
+        &temporary,
+        format!(
+            "session {}\nblock {} {:?}\n",
+            point.session.0,
+            point.block.number(),
+            point.block
+        ),
+    )?;
+    // Renamed, so that the tooling never sees a partially written marker.
+    fs::rename(temporary, path)
+}
+
+/// Exports the snapshot points for external tooling, by writing each of them to
+/// `SNAPSHOT_MARKER_FILE` in the directory. Sync stays paused until the tooling removes the
+/// file, or `MAX_SNAPSHOT_PAUSE` passes.
+pub async fn run_snapshot_exporter<BI: BlockIdentifier>(
+    mut subscription: SnapshotSubscription<BI>,
+    directory: PathBuf,
+) {
+    if let Err(e) = fs::create_dir_all(&directory) {
+        warn!(
+            target: LOG_TARGET,
+            "Not exporting snapshot points, cannot create {:?}: {}.", directory, e
+        );
+        return;
+    }
+    let marker = directory.join(SNAPSHOT_MARKER_FILE);
+    while let Some(SnapshotTrigger { point, hold }) = subscription.next().await {
+        if let Err(e) = write_marker(&marker, &point) {
+            warn!(
+                target: LOG_TARGET,
+                "Failed to export snapshot point {:?}: {}.", point, e
+            );
+            continue;
+        }
+        info!(
+            target: LOG_TARGET,
+            "Exported snapshot point after session {:?} at {:?}.", point.session, point.block
+        );
+        let deadline = Instant::now() + MAX_SNAPSHOT_PAUSE;
+        while marker.exists() && Instant::now() < deadline {
+            sleep(MARKER_POLL_PERIOD).await;
+        }
+        drop(hold);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::{SnapshotPoint, SnapshotSchedule, SnapshotTriggers};
+    use crate::{
+        session::{SessionBoundaryInfo, SessionId},
+        sync::mock::MockIdentifier,
+        SessionPeriod,
+    };
+
+    const SESSION_PERIOD: u32 = 10;
+
+    fn schedule(every: u32, top_finalized: u32) -> SnapshotSchedule<MockIdentifier> {
+        SnapshotSchedule::new(
+            SnapshotTriggers::new(NonZeroU32::new(every)),
+            SessionBoundaryInfo::new(SessionPeriod(SESSION_PERIOD)),
+            top_finalized,
+        )
+    }
+
+    #[test]
+    fn triggers_every_configured_number_of_sessions() {
+        let mut schedule = schedule(2, 5);
+        // Only one session finalized since the start.
+        assert_eq!(schedule.due(&MockIdentifier::new_random(9)), None);
+        let block = MockIdentifier::new_random(19);
+        assert_eq!(
+            schedule.due(&block),
+            Some(SnapshotPoint {
+                session: SessionId(1),
+                block
+            })
+        );
+        assert_eq!(schedule.due(&MockIdentifier::new_random(35)), None);
+        // Finalizing many blocks at once still triggers only once.
+        let block = MockIdentifier::new_random(75);
+        assert_eq!(
+            schedule.due(&block),
+            Some(SnapshotPoint {
+                session: SessionId(6),
+                block
+            })
+        );
+    }
+
+    #[test]
+    fn never_triggers_unconfigured() {
+        let mut schedule = schedule(0, 0);
+        assert_eq!(schedule.due(&MockIdentifier::new_random(1000)), None);
+    }
+
+    #[tokio::test]
+    async fn pauses_until_subscribers_are_done() {
+        let triggers = SnapshotTriggers::new(NonZeroU32::new(1));
+        let mut schedule = SnapshotSchedule::new(
+            triggers.clone(),
+            SessionBoundaryInfo::new(SessionPeriod(SESSION_PERIOD)),
+            0,
+        );
+        let block = MockIdentifier::new_random(9);
+        let (_, pause) = schedule.finalized(&block).expect("should be due");
+        assert!(pause.is_none());
+        let mut subscription = triggers.subscribe();
+        let block = MockIdentifier::new_random(19);
+        let (_, pause) = schedule.finalized(&block).expect("should be due");
+        let mut pause = pause.expect("should pause with a subscriber");
+        let trigger = subscription.next().await.expect("should be triggered");
+        assert_eq!(trigger.point.block, block);
+        drop(trigger);
+        assert!(pause.wait().await);
+    }
+}
@@ -0,0 +1,110 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use tokio::{sync::Notify, time::timeout};
+
+use crate::{
+    sync::{
+        network_view::PeerFinality, peer_rating::PeerScore, serving_stats::PeerServing, PeerId,
+    },
+    BlockIdentifier,
+};
+
+/// How long to wait for the sync service to answer a status request.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A snapshot of what the sync service is doing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncStatus<I: PeerId, BI: BlockIdentifier> {
+    /// The block of our highest justification.
+    pub top_finalized: BI,
+    /// The block we are currently building on.
+    pub favourite: BI,
+    /// How many tips of the trees in the forest there are.
+    pub forks: usize,
+    /// How many blocks have known headers, but still wait for their bodies.
+    pub pending_bodies: usize,
+    /// How many requests for blocks still wait for responses.
+    pub in_flight_requests: usize,
+    /// The last states advertised by the peers, the most recently updated first.
+    pub peers: Vec<PeerFinality<I>>,
+    /// The scores of the misbehaving peers with their recent misbehavior, the most recently
+    /// misbehaving first.
+    pub peer_scores: Vec<PeerScore<I>>,
+    /// How the requests of the peers were served, the ones that sent the most first.
+    pub serving: Vec<PeerServing<I>>,
+    /// The most recently handled events, the latest first.
+    pub recent_events: Vec<&'static str>,
+    /// The most recent errors, the latest first.
+    pub recent_errors: Vec<String>,
+}
+
+/// Lets anyone holding it ask the running sync service for its status.
+#[derive(Clone)]
+pub struct StatusReports<I: PeerId, BI: BlockIdentifier> {
+    pending: Arc<Mutex<Vec<oneshot::Sender<SyncStatus<I, BI>>>>>,
+    requested: Arc<Notify>,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> StatusReports<I, BI> {
+    pub fn new() -> Self {
+        StatusReports {
+            pending: Arc::new(Mutex::new(Vec::new())),
+            requested: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Asks for the status, returns `None` if sync did not respond in time.
+    pub async fn report(&self) -> Option<SyncStatus<I, BI>> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().push(sender);
+        self.requested.notify_one();
+        timeout(STATUS_TIMEOUT, receiver).await.ok()?.ok()
+    }
+
+    /// Waits for status requests, returns where to send the reports.
+    pub async fn requests(&self) -> Vec<oneshot::Sender<SyncStatus<I, BI>>> {
+        self.requested.notified().await;
+        std::mem::take(&mut *self.pending.lock())
+    }
+}
+
+impl<I: PeerId, BI: BlockIdentifier> Default for StatusReports<I, BI> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StatusReports, SyncStatus};
+    use crate::sync::mock::MockIdentifier;
+
+    #[tokio::test]
+    async fn answers_status_requests() {
+        let reports = StatusReports::<u32, MockIdentifier>::new();
+        let answering = reports.clone();
+        let top_finalized = MockIdentifier::new_random(7);
+        let status = SyncStatus {
+            top_finalized: top_finalized.clone(),
+            favourite: top_finalized,
+            forks: 0,
+            pending_bodies: 0,
+            in_flight_requests: 0,
+            peers: Vec::new(),
+            peer_scores: Vec::new(),
+            serving: Vec::new(),
+            recent_events: vec!["handle_state"],
+            recent_errors: Vec::new(),
+        };
+        let expected = status.clone();
+        let answer = tokio::spawn(async move {
+            for sender in answering.requests().await {
+                let _ = sender.send(status.clone());
+            }
+        });
+        assert_eq!(reports.report().await, Some(expected));
+        answer.await.expect("should not panic");
+    }
+}
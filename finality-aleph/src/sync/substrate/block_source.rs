@@ -0,0 +1,178 @@
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    str::FromStr,
+    time::Duration,
+};
+
+use futures::{stream, StreamExt, TryStreamExt};
+use hyper::{
+    body::HttpBody,
+    client::{connect::HttpConnector, Client},
+    Body, StatusCode, Uri,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use parity_scale_codec::Decode;
+use tokio::time::timeout;
+
+use crate::{aleph_primitives::Block, sync::BlockFetcher, BlockId};
+
+/// How long a single block can take to arrive.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many blocks are fetched from the source at once.
+const MAX_CONCURRENT_FETCHES: usize = 16;
+/// Larger responses are not blocks, at least not ones the chain would accept.
+const MAX_BLOCK_BYTES: usize = 16 * 1024 * 1024;
+
+/// What can be wrong with the address of the block source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockSourceError {
+    UnsupportedScheme,
+    MissingHost,
+    Malformed(String),
+}
+
+impl Display for BlockSourceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use BlockSourceError::*;
+        match self {
+            UnsupportedScheme => write!(f, "only https:// block sources are supported"),
+            MissingHost => write!(f, "the block source has no host"),
+            Malformed(e) => write!(f, "malformed block source address: {e}"),
+        }
+    }
+}
+
+/// The address of an HTTPS source of finalized blocks, e.g. an exchange or a CDN, serving the
+/// SCALE-encoded block with the hash `0x...` at `<address>/0x...`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockSource {
+    base: String,
+}
+
+impl BlockSource {
+    fn uri(&self, id: &BlockId) -> Result<Uri, String> {
+        format!("{}/{:?}", self.base, id.hash())
+            .parse()
+            .map_err(|e| format!("{e}"))
+    }
+}
+
+impl FromStr for BlockSource {
+    type Err = BlockSourceError;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        use BlockSourceError::*;
+        let uri: Uri = address.parse().map_err(|e| Malformed(format!("{e}")))?;
+        if uri.scheme_str() != Some("https") {
+            return Err(UnsupportedScheme);
+        }
+        if uri.host().map_or(true, str::is_empty) {
+            return Err(MissingHost);
+        }
+        if uri.query().is_some() {
+            return Err(Malformed("the address cannot have a query".to_string()));
+        }
+        Ok(BlockSource {
+            base: address.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+impl Display for BlockSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        write!(f, "{}", self.base)
+    }
+}
+
+/// Fetches the blocks from an HTTPS block source, one request per block.
+pub struct HttpsBlockFetcher {
+    source: BlockSource,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl HttpsBlockFetcher {
+    pub fn new(source: BlockSource) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_only()
+            .enable_http1()
+            .build();
+        HttpsBlockFetcher {
+            source,
+            client: Client::builder().build(connector),
+        }
+    }
+
+    /// The block, if the source has it.
+    async fn fetch_block(&self, id: BlockId) -> Result<Option<Block>, String> {
+        let uri = self.source.uri(&id)?;
+        let mut response = self
+            .client
+            .get(uri)
+            .await
+            .map_err(|e| format!("request for {id:?} failed: {e}"))?;
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND => return Ok(None),
+            status => return Err(format!("request for {id:?} failed with status {status}")),
+        }
+        let mut encoded = Vec::new();
+        while let Some(chunk) = response.body_mut().data().await {
+            let chunk = chunk.map_err(|e| format!("receiving {id:?} failed: {e}"))?;
+            if encoded.len() + chunk.len() > MAX_BLOCK_BYTES {
+                return Err(format!("{id:?} is larger than {MAX_BLOCK_BYTES} bytes"));
+            }
+            encoded.extend_from_slice(&chunk);
+        }
+        Block::decode(&mut &encoded[..])
+            .map(Some)
+            .map_err(|e| format!("malformed {id:?}: {e}"))
+    }
+}
+
+#[async_trait::async_trait]
+impl BlockFetcher<Block> for HttpsBlockFetcher {
+    async fn fetch(&self, ids: Vec<BlockId>) -> Result<Vec<Block>, String> {
+        stream::iter(ids)
+            .map(|id| async move {
+                timeout(FETCH_TIMEOUT, self.fetch_block(id.clone()))
+                    .await
+                    .map_err(|_| format!("fetching {id:?} timed out"))?
+            })
+            .buffered(MAX_CONCURRENT_FETCHES)
+            .try_filter_map(|maybe_block| async move { Ok(maybe_block) })
+            .try_collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BlockSource,
+        BlockSourceError::{Malformed, UnsupportedScheme},
+    };
+    use crate::BlockId;
+
+    #[test]
+    fn parses_block_sources() {
+        let source: BlockSource = "https://blocks.example.com/mainnet/"
+            .parse()
+            .expect("valid source");
+        assert_eq!(source.to_string(), "https://blocks.example.com/mainnet");
+        let id = BlockId::new([1; 32].into(), 7);
+        assert_eq!(
+            source.uri(&id).expect("valid uri").path(),
+            format!("/mainnet/0x{}", "01".repeat(32))
+        );
+        assert_eq!(
+            "http://blocks.example.com".parse::<BlockSource>(),
+            Err(UnsupportedScheme)
+        );
+        assert!("https:///blocks".parse::<BlockSource>().is_err());
+        assert!(matches!(
+            "https://blocks.example.com/?network=mainnet".parse::<BlockSource>(),
+            Err(Malformed(_))
+        ));
+    }
+}
@@ -15,7 +15,7 @@ use crate::{
     },
     justification::backwards_compatible_decode,
     sync::{
-        substrate::{BlockId, Justification},
+        substrate::{archived_justification, BlockId, Justification},
         BlockIdFor, BlockStatus, ChainStatus, FinalizationStatus, Header, LOG_TARGET,
     },
 };
@@ -122,14 +122,24 @@ impl SubstrateChainStatus {
         if header == self.genesis_header {
             return Ok(Some(Justification::genesis_justification(header)));
         };
-        let encoded_justification = match self
+        let stored = self
             .backend
             .blockchain()
             .justifications(header.hash())?
-            .and_then(|j| j.into_justification(ALEPH_ENGINE_ID))
-        {
+            .and_then(|j| j.into_justification(ALEPH_ENGINE_ID));
+        // Justifications the retention allows pruning are archived instead.
+        let encoded_justification = match stored {
             Some(justification) => justification,
-            None => return Ok(None),
+            None => match archived_justification(
+                &*self.backend,
+                &BlockId {
+                    hash: header.hash(),
+                    number: *header.number(),
+                },
+            )? {
+                Some(justification) => justification,
+                None => return Ok(None),
+            },
         };
 
         match backwards_compatible_decode(encoded_justification) {
@@ -0,0 +1,61 @@
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    sync::Arc,
+};
+
+use parity_scale_codec::{Decode, Encode, Error as CodecError};
+use sc_client_api::AuxStore;
+
+use crate::sync::counters::{CounterStorage, CounterValues};
+
+/// The key of the counters in the auxiliary storage.
+const COUNTERS_KEY: &[u8] = b"aleph_sync_counters";
+
+/// What can go wrong when persisting the counters.
+#[derive(Debug)]
+pub enum AuxCounterStorageError {
+    Backend(sp_blockchain::Error),
+    Decoding(CodecError),
+}
+
+impl Display for AuxCounterStorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use AuxCounterStorageError::*;
+        match self {
+            Backend(e) => write!(f, "auxiliary storage failure: {e}"),
+            Decoding(e) => write!(f, "stored counters are malformed: {e}"),
+        }
+    }
+}
+
+/// Persists the counters in the auxiliary storage of the client.
+pub struct AuxCounterStorage<C: AuxStore> {
+    client: Arc<C>,
+}
+
+impl<C: AuxStore> AuxCounterStorage<C> {
+    pub fn new(client: Arc<C>) -> Self {
+        AuxCounterStorage { client }
+    }
+}
+
+impl<C: AuxStore> CounterStorage for AuxCounterStorage<C> {
+    type Error = AuxCounterStorageError;
+
+    fn load(&self) -> Result<Option<CounterValues>, Self::Error> {
+        use AuxCounterStorageError::*;
+        match self.client.get_aux(COUNTERS_KEY).map_err(Backend)? {
+            Some(encoded) => Ok(Some(
+                CounterValues::decode(&mut &encoded[..]).map_err(Decoding)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&self, values: &CounterValues) -> Result<(), Self::Error> {
+        let encoded = values.encode();
+        self.client
+            .insert_aux(&[(COUNTERS_KEY, &encoded[..])], &[])
+            .map_err(AuxCounterStorageError::Backend)
+    }
+}
@@ -7,8 +7,9 @@ use crate::{
     finalization::{AlephFinalizer, BlockFinalizer},
     sync::{
         substrate::{InnerJustification, Justification},
-        Finalizer,
+        FinalizedId, Finalizer,
     },
+    BlockId,
 };
 
 impl<BE, C> Finalizer<Justification> for AlephFinalizer<Block, BE, C>
@@ -18,16 +19,25 @@ where
 {
     type Error = ClientError;
 
-    fn finalize(&self, justification: Justification) -> Result<(), Self::Error> {
+    fn finalize(&self, justification: Justification) -> Result<FinalizedId<BlockId>, Self::Error> {
         match justification.inner_justification {
-            InnerJustification::AlephJustification(aleph_justification) => self.finalize_block(
-                (justification.header.hash(), *justification.header.number()).into(),
-                aleph_justification.into(),
-            ),
+            InnerJustification::AlephJustification(aleph_justification) => {
+                let block: BlockId =
+                    (justification.header.hash(), *justification.header.number()).into();
+                self.finalize_block(block.clone(), aleph_justification.into())?;
+                Ok(FinalizedId::new(block))
+            }
             _ => Err(Self::Error::BadJustification(
                 "Trying fo finalize the genesis block using virtual sync justification."
                     .to_string(),
             )),
         }
     }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        matches!(
+            error,
+            ClientError::Backend(_) | ClientError::DatabaseError(_) | ClientError::StateDatabase(_)
+        )
+    }
 }
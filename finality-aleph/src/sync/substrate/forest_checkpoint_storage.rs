@@ -0,0 +1,68 @@
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    sync::Arc,
+};
+
+use parity_scale_codec::{Decode, Encode, Error as CodecError};
+use sc_client_api::AuxStore;
+
+use crate::sync::{
+    forest::{ForestCheckpoint, ForestCheckpointStorage},
+    substrate::Justification,
+};
+
+/// The key of the forest checkpoint in the auxiliary storage.
+const FOREST_CHECKPOINT_KEY: &[u8] = b"aleph_sync_forest_checkpoint";
+
+/// What can go wrong when persisting the forest checkpoint.
+#[derive(Debug)]
+pub enum AuxForestCheckpointStorageError {
+    Backend(sp_blockchain::Error),
+    Decoding(CodecError),
+}
+
+impl Display for AuxForestCheckpointStorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use AuxForestCheckpointStorageError::*;
+        match self {
+            Backend(e) => write!(f, "auxiliary storage failure: {e}"),
+            Decoding(e) => write!(f, "stored forest checkpoint is malformed: {e}"),
+        }
+    }
+}
+
+/// Persists the forest checkpoint in the auxiliary storage of the client.
+pub struct AuxForestCheckpointStorage<C: AuxStore> {
+    client: Arc<C>,
+}
+
+impl<C: AuxStore> AuxForestCheckpointStorage<C> {
+    pub fn new(client: Arc<C>) -> Self {
+        AuxForestCheckpointStorage { client }
+    }
+}
+
+impl<C: AuxStore> ForestCheckpointStorage<Justification> for AuxForestCheckpointStorage<C> {
+    type Error = AuxForestCheckpointStorageError;
+
+    fn load(&self) -> Result<Option<ForestCheckpoint<Justification>>, Self::Error> {
+        use AuxForestCheckpointStorageError::*;
+        match self
+            .client
+            .get_aux(FOREST_CHECKPOINT_KEY)
+            .map_err(Backend)?
+        {
+            Some(encoded) => Ok(Some(
+                ForestCheckpoint::decode(&mut &encoded[..]).map_err(Decoding)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&self, checkpoint: &ForestCheckpoint<Justification>) -> Result<(), Self::Error> {
+        let encoded = checkpoint.encode();
+        self.client
+            .insert_aux(&[(FOREST_CHECKPOINT_KEY, &encoded[..])], &[])
+            .map_err(AuxForestCheckpointStorageError::Backend)
+    }
+}
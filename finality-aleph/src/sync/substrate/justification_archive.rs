@@ -0,0 +1,537 @@
+use std::{
+    fmt::{Display, Error as FmtError, Formatter},
+    sync::Arc,
+    time::Duration,
+};
+
+use log::{debug, warn};
+use parity_scale_codec::{Decode, Encode, Error as CodecError};
+use sc_client_api::{AuxStore, HeaderBackend};
+use serde::{Deserialize, Serialize};
+use sp_runtime::Justification as SubstrateJustification;
+use tokio::{task::spawn_blocking, time::sleep};
+
+use crate::{
+    aleph_primitives::{Block, BlockHash, BlockNumber},
+    session::{SessionBoundaryInfo, SessionId},
+    sync::{
+        compression::{CompressedData, Compression, CompressionError},
+        LOG_TARGET,
+    },
+    BlockId,
+};
+
+/// The prefix of the keys of the archived justifications in the auxiliary storage.
+const ARCHIVE_PREFIX: &[u8] = b"aleph_sync_archived_justification";
+/// The prefix of the keys of the archived justifications stored compressed.
+const COMPRESSED_ARCHIVE_PREFIX: &[u8] = b"aleph_sync_compressed_justification";
+/// The most bytes an archived justification can decompress to, far more than the justification
+/// of any committee takes.
+const MAX_ARCHIVED_JUSTIFICATION_BYTES: usize = 4 * 1024 * 1024;
+/// The key of the first session whose archived justifications were not pruned yet.
+const PRUNED_BELOW_KEY: &[u8] = b"aleph_sync_justifications_pruned_below";
+/// How often the archived justifications outside the retention are pruned.
+const PRUNING_PERIOD: Duration = Duration::from_secs(60);
+
+/// Which justifications of finalized blocks are kept. The ones of the last blocks of sessions are
+/// always kept, warp sync and peers catching up need them, and they finalize all the blocks of
+/// their sessions anyway.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JustificationRetention {
+    /// Every justification is kept forever, in the backend.
+    #[default]
+    KeepAll,
+    /// Only the justifications of the last blocks of sessions are kept once the sessions end.
+    SessionBoundaries,
+    /// All the justifications of this many of the most recent sessions are kept, including the
+    /// current one, only the ones of the last blocks of sessions for the older ones.
+    LastSessions(u32),
+}
+
+impl JustificationRetention {
+    /// How many of the most recent sessions keep all their justifications, `None` if all of them
+    /// do. The current session always does, the top finalized block needs its justification.
+    fn kept_sessions(&self) -> Option<u32> {
+        use JustificationRetention::*;
+        match self {
+            KeepAll => None,
+            SessionBoundaries => Some(1),
+            LastSessions(sessions) => Some((*sessions).max(1)),
+        }
+    }
+
+    /// The first session whose justifications are all kept, when the top finalized block is in
+    /// the given one, `None` if all of them are kept.
+    fn first_kept_session(&self, top_session: SessionId) -> Option<SessionId> {
+        self.kept_sessions()
+            .map(|kept| SessionId(top_session.0.saturating_sub(kept - 1)))
+    }
+}
+
+fn archive_key(number: BlockNumber) -> Vec<u8> {
+    (ARCHIVE_PREFIX, number).encode()
+}
+
+fn compressed_archive_key(number: BlockNumber) -> Vec<u8> {
+    (COMPRESSED_ARCHIVE_PREFIX, number).encode()
+}
+
+/// The compressed entry of the auxiliary storage archiving the justification of the block.
+fn compressed_entry(
+    hash: BlockHash,
+    number: BlockNumber,
+    justification: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), CompressionError> {
+    let compressed = Compression::default().compress(justification)?;
+    Ok((compressed_archive_key(number), (hash, compressed).encode()))
+}
+
+/// Decides which justifications the finalizer stores in the archive in the auxiliary storage,
+/// where they can be pruned, instead of the backend, which keeps them forever.
+#[derive(Clone, Debug)]
+pub struct JustificationArchiving {
+    retention: JustificationRetention,
+    session_info: SessionBoundaryInfo,
+    compress: bool,
+}
+
+impl JustificationArchiving {
+    pub fn new(retention: JustificationRetention, session_info: SessionBoundaryInfo) -> Self {
+        JustificationArchiving {
+            retention,
+            session_info,
+            compress: false,
+        }
+    }
+
+    /// Whether the justifications get compressed before archiving.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Whether the justification of the block with this number goes to the archive.
+    pub fn archives(&self, number: BlockNumber) -> bool {
+        let session = self.session_info.session_id_from_block_num(number);
+        self.retention != JustificationRetention::KeepAll
+            && self.session_info.last_block_of_session(session) != number
+    }
+
+    /// The entry of the auxiliary storage archiving the justification of the block.
+    pub fn entry(
+        &self,
+        block: &BlockId,
+        justification: &SubstrateJustification,
+    ) -> (Vec<u8>, Option<Vec<u8>>) {
+        if self.compress {
+            match compressed_entry(block.hash, block.number, &justification.1) {
+                Ok((key, value)) => return (key, Some(value)),
+                Err(e) => warn!(
+                    target: LOG_TARGET,
+                    "Failed to compress the justification of block #{}, archiving it as it is: {}.",
+                    block.number,
+                    e
+                ),
+            }
+        }
+        (
+            archive_key(block.number),
+            Some((block.hash, &justification.1).encode()),
+        )
+    }
+}
+
+/// The archived data of the block, if the entry is the one of that block.
+fn decode_entry<T: Decode>(block: &BlockId, encoded: &[u8]) -> Option<T> {
+    match <(BlockHash, T)>::decode(&mut &encoded[..]) {
+        Ok((hash, data)) if hash == block.hash => Some(data),
+        // Only finalized blocks are archived, so another one with the same number never is.
+        Ok(_) => None,
+        Err(e) => {
+            warn!(
+                target: LOG_TARGET,
+                "Archived justification of block #{} is malformed: {}.", block.number, e
+            );
+            None
+        }
+    }
+}
+
+/// The encoded justification of the finalized block from the archive, if it is there. Works
+/// regardless of the current retention and compression, the justifications archived under
+/// different ones are still found.
+pub fn archived_justification<A: AuxStore>(
+    store: &A,
+    block: &BlockId,
+) -> Result<Option<Vec<u8>>, sp_blockchain::Error> {
+    if let Some(encoded) = store.get_aux(&archive_key(block.number))? {
+        return Ok(decode_entry(block, &encoded));
+    }
+    let compressed: CompressedData = match store
+        .get_aux(&compressed_archive_key(block.number))?
+        .and_then(|encoded| decode_entry(block, &encoded))
+    {
+        Some(compressed) => compressed,
+        None => return Ok(None),
+    };
+    match Compression::default().decompress(&compressed, MAX_ARCHIVED_JUSTIFICATION_BYTES) {
+        Ok(justification) => Ok(Some(justification)),
+        Err(e) => {
+            warn!(
+                target: LOG_TARGET,
+                "Archived justification of block #{} does not decompress: {}.", block.number, e
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// What can go wrong when pruning or compressing the archived justifications.
+#[derive(Debug)]
+pub enum JustificationPruningError {
+    Backend(sp_blockchain::Error),
+    Decoding(CodecError),
+    Compression(CompressionError),
+}
+
+impl Display for JustificationPruningError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use JustificationPruningError::*;
+        match self {
+            Backend(e) => write!(f, "auxiliary storage failure: {e}"),
+            Decoding(e) => write!(f, "stored pruning progress is malformed: {e}"),
+            Compression(e) => write!(f, "failed to compress a justification: {e}"),
+        }
+    }
+}
+
+/// The stored first session whose archived justifications were not pruned yet, `None` if
+/// nothing was ever archived.
+fn stored_pruned_below<A: AuxStore>(
+    store: &A,
+) -> Result<Option<SessionId>, JustificationPruningError> {
+    use JustificationPruningError::*;
+    store
+        .get_aux(PRUNED_BELOW_KEY)
+        .map_err(Backend)?
+        .map(|encoded| SessionId::decode(&mut &encoded[..]).map_err(Decoding))
+        .transpose()
+}
+
+/// The first session whose archived justifications were not pruned yet. The first time it is
+/// asked for, nothing was archived before the current session, which gets stored, so that
+/// restarting before anything is pruned does not forget about the earlier sessions.
+fn pruned_below<A: AuxStore>(
+    store: &A,
+    top_session: SessionId,
+) -> Result<SessionId, JustificationPruningError> {
+    match stored_pruned_below(store)? {
+        Some(session) => Ok(session),
+        None => {
+            let progress = top_session.encode();
+            store
+                .insert_aux(&[(PRUNED_BELOW_KEY, &progress[..])], &[])
+                .map_err(JustificationPruningError::Backend)?;
+            Ok(top_session)
+        }
+    }
+}
+
+/// Prunes all the sessions outside the retention, when the top finalized block is in the given
+/// session, one database transaction per session, returns how many were pruned.
+fn prune_sessions<A: AuxStore>(
+    store: &A,
+    retention: JustificationRetention,
+    session_info: &SessionBoundaryInfo,
+    top_session: SessionId,
+) -> Result<u32, JustificationPruningError> {
+    let first_kept = match retention.first_kept_session(top_session) {
+        Some(session) => session,
+        None => return Ok(0),
+    };
+    let mut session = pruned_below(store, top_session)?;
+    let mut pruned = 0;
+    while session < first_kept {
+        // The justification of the last block of the session is in the backend.
+        let keys: Vec<_> = (session_info.first_block_of_session(session)
+            ..session_info.last_block_of_session(session))
+            .flat_map(|number| [archive_key(number), compressed_archive_key(number)])
+            .collect();
+        let deleted: Vec<&[u8]> = keys.iter().map(|key| &key[..]).collect();
+        session = session.next();
+        let progress = session.encode();
+        store
+            .insert_aux(&[(PRUNED_BELOW_KEY, &progress[..])], &deleted)
+            .map_err(JustificationPruningError::Backend)?;
+        pruned += 1;
+    }
+    Ok(pruned)
+}
+
+/// Compresses the justifications archived as they are, e.g. before compression got enabled, in
+/// the sessions up to the given one, one database transaction per session. Returns how many
+/// justifications were compressed.
+pub fn compress_archived_justifications<A: AuxStore>(
+    store: &A,
+    session_info: &SessionBoundaryInfo,
+    top_session: SessionId,
+) -> Result<u32, JustificationPruningError> {
+    use JustificationPruningError::*;
+    // The earlier sessions were pruned already.
+    let mut session = match stored_pruned_below(store)? {
+        Some(session) => session,
+        None => return Ok(0),
+    };
+    let mut compressed = 0;
+    while session <= top_session {
+        let mut inserted = Vec::new();
+        let mut deleted = Vec::new();
+        for number in session_info.first_block_of_session(session)
+            ..session_info.last_block_of_session(session)
+        {
+            let key = archive_key(number);
+            let encoded = match store.get_aux(&key).map_err(Backend)? {
+                Some(encoded) => encoded,
+                None => continue,
+            };
+            // Malformed entries are left as they are, they are never read anyway.
+            if let Ok((hash, justification)) = <(BlockHash, Vec<u8>)>::decode(&mut &encoded[..]) {
+                inserted.push(compressed_entry(hash, number, &justification).map_err(Compression)?);
+                deleted.push(key);
+            }
+        }
+        let inserted: Vec<(&[u8], &[u8])> = inserted
+            .iter()
+            .map(|(key, value)| (&key[..], &value[..]))
+            .collect();
+        let deleted: Vec<&[u8]> = deleted.iter().map(|key| &key[..]).collect();
+        if !deleted.is_empty() {
+            store.insert_aux(&inserted, &deleted).map_err(Backend)?;
+            compressed += deleted.len() as u32;
+        }
+        session = session.next();
+    }
+    Ok(compressed)
+}
+
+/// Removes the archived justifications of the sessions outside the retention.
+pub struct JustificationPruner<C: AuxStore + HeaderBackend<Block>> {
+    client: Arc<C>,
+    retention: JustificationRetention,
+    session_info: SessionBoundaryInfo,
+}
+
+impl<C: AuxStore + HeaderBackend<Block>> JustificationPruner<C> {
+    /// Has to be created before anything gets archived, as it stores where archiving started,
+    /// unless some earlier run already did.
+    pub fn new(
+        client: Arc<C>,
+        retention: JustificationRetention,
+        session_info: SessionBoundaryInfo,
+    ) -> Result<Self, JustificationPruningError> {
+        let pruner = JustificationPruner {
+            client,
+            retention,
+            session_info,
+        };
+        pruned_below(&*pruner.client, pruner.top_session())?;
+        Ok(pruner)
+    }
+
+    fn top_session(&self) -> SessionId {
+        self.session_info
+            .session_id_from_block_num(self.client.info().finalized_number)
+    }
+
+    fn prune(&self) -> Result<u32, JustificationPruningError> {
+        prune_sessions(
+            &*self.client,
+            self.retention,
+            &self.session_info,
+            self.top_session(),
+        )
+    }
+}
+
+/// Periodically prunes the archived justifications, until the blocking pool goes away.
+pub async fn run_justification_pruning<C>(pruner: JustificationPruner<C>)
+where
+    C: AuxStore + HeaderBackend<Block> + Send + Sync + 'static,
+{
+    let pruner = Arc::new(pruner);
+    loop {
+        let pruning = pruner.clone();
+        match spawn_blocking(move || pruning.prune()).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(sessions)) => debug!(
+                target: LOG_TARGET,
+                "Pruned the archived justifications of {} sessions.", sessions
+            ),
+            Ok(Err(e)) => warn!(
+                target: LOG_TARGET,
+                "Failed to prune archived justifications: {}.", e
+            ),
+            Err(e) => {
+                warn!(
+                    target: LOG_TARGET,
+                    "Justification pruning failed, stopping it: {}.", e
+                );
+                return;
+            }
+        }
+        sleep(PRUNING_PERIOD).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sc_client_api::AuxStore;
+    use sp_runtime::Justification as SubstrateJustification;
+
+    use super::{
+        archive_key, archived_justification, compress_archived_justifications,
+        compressed_archive_key, prune_sessions, pruned_below, JustificationArchiving,
+        JustificationRetention::*,
+    };
+    use crate::{
+        aleph_primitives::BlockHash,
+        session::{SessionBoundaryInfo, SessionId},
+        testing::mocks::{TestClient, TestClientBuilder, TestClientBuilderExt},
+        BlockId, SessionPeriod,
+    };
+
+    fn archive(client: &TestClient, (key, value): (Vec<u8>, Option<Vec<u8>>)) {
+        let value = value.expect("archiving inserts");
+        client
+            .insert_aux(&[(&key[..], &value[..])], &[])
+            .expect("storage works");
+    }
+
+    #[test]
+    fn archives_all_but_the_last_blocks_of_sessions() {
+        let session_info = SessionBoundaryInfo::new(SessionPeriod(10));
+        let archiving = JustificationArchiving::new(LastSessions(2), session_info.clone());
+        assert!(archiving.archives(1));
+        assert!(archiving.archives(10));
+        assert!(!archiving.archives(9));
+        assert!(!archiving.archives(19));
+        let keeping = JustificationArchiving::new(KeepAll, session_info);
+        assert!(!keeping.archives(1));
+    }
+
+    #[test]
+    fn keeps_the_current_session() {
+        assert_eq!(KeepAll.first_kept_session(SessionId(5)), None);
+        assert_eq!(
+            SessionBoundaries.first_kept_session(SessionId(5)),
+            Some(SessionId(5))
+        );
+        assert_eq!(
+            LastSessions(3).first_kept_session(SessionId(5)),
+            Some(SessionId(3))
+        );
+        assert_eq!(
+            LastSessions(0).first_kept_session(SessionId(5)),
+            Some(SessionId(5))
+        );
+        assert_eq!(
+            LastSessions(10).first_kept_session(SessionId(5)),
+            Some(SessionId(0))
+        );
+    }
+
+    #[test]
+    fn prunes_sessions_archived_before_restart() {
+        let client = TestClientBuilder::new().build();
+        let session_info = SessionBoundaryInfo::new(SessionPeriod(10));
+        // Archiving gets enabled in the second session.
+        assert_eq!(
+            pruned_below(&client, SessionId(2)).expect("storage works"),
+            SessionId(2)
+        );
+        // The justification of the last block of the session is not archived.
+        let archived: Vec<_> = (20..39).filter(|number| *number != 29).collect();
+        let keys: Vec<_> = archived.iter().copied().map(archive_key).collect();
+        let entries: Vec<_> = keys
+            .iter()
+            .map(|key| (&key[..], &b"archived"[..]))
+            .collect();
+        client.insert_aux(&entries, &[]).expect("storage works");
+
+        // The node restarts in the next session, before anything got pruned.
+        assert_eq!(
+            pruned_below(&client, SessionId(3)).expect("storage works"),
+            SessionId(2)
+        );
+        assert_eq!(
+            prune_sessions(&client, SessionBoundaries, &session_info, SessionId(4))
+                .expect("storage works"),
+            2
+        );
+        for number in archived {
+            assert_eq!(
+                client.get_aux(&archive_key(number)).expect("storage works"),
+                None
+            );
+        }
+        assert_eq!(
+            pruned_below(&client, SessionId(4)).expect("storage works"),
+            SessionId(4)
+        );
+    }
+
+    #[test]
+    fn reads_compressed_and_migrated_justifications() {
+        let client = TestClientBuilder::new().build();
+        let session_info = SessionBoundaryInfo::new(SessionPeriod(10));
+        let justification: SubstrateJustification = (*b"ALPH", vec![7; 1000]);
+        let plain = BlockId::new(BlockHash::repeat_byte(1), 3);
+        let compressed = BlockId::new(BlockHash::repeat_byte(2), 4);
+        let archiving = JustificationArchiving::new(SessionBoundaries, session_info.clone());
+        pruned_below(&client, SessionId(0)).expect("storage works");
+        archive(&client, archiving.entry(&plain, &justification));
+        let entry = archiving
+            .with_compression(true)
+            .entry(&compressed, &justification);
+        assert_eq!(entry.0, compressed_archive_key(4));
+        assert!(entry.1.as_ref().map(Vec::len) < Some(justification.1.len()));
+        archive(&client, entry);
+
+        for block in [&plain, &compressed] {
+            assert_eq!(
+                archived_justification(&client, block).expect("storage works"),
+                Some(justification.1.clone())
+            );
+        }
+        assert_eq!(
+            archived_justification(&client, &BlockId::new(BlockHash::repeat_byte(3), 4))
+                .expect("storage works"),
+            None
+        );
+
+        assert_eq!(
+            compress_archived_justifications(&client, &session_info, SessionId(0))
+                .expect("storage works"),
+            1
+        );
+        assert_eq!(
+            client.get_aux(&archive_key(3)).expect("storage works"),
+            None
+        );
+        assert_eq!(
+            archived_justification(&client, &plain).expect("storage works"),
+            Some(justification.1.clone())
+        );
+
+        assert_eq!(
+            prune_sessions(&client, SessionBoundaries, &session_info, SessionId(1))
+                .expect("storage works"),
+            1
+        );
+        assert_eq!(
+            archived_justification(&client, &compressed).expect("storage works"),
+            None
+        );
+    }
+}
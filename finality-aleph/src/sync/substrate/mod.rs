@@ -1,6 +1,10 @@
+use parity_scale_codec::Encode;
 use sc_consensus::import_queue::{ImportQueueService, IncomingBlock};
 use sp_consensus::BlockOrigin;
-use sp_runtime::traits::{CheckedSub, Header as _, One};
+use sp_runtime::{
+    traits::{CheckedSub, Hash, Header as SubstrateHeader, One},
+    DigestItem, StateVersion,
+};
 
 use crate::{
     aleph_primitives::{Block, Header},
@@ -8,18 +12,33 @@ use crate::{
     BlockId,
 };
 
+mod block_source;
 mod chain_status;
+mod counter_storage;
 mod finalizer;
+mod forest_checkpoint_storage;
 mod justification;
+mod justification_archive;
 mod status_notifier;
 mod verification;
 
+pub use block_source::{BlockSource, BlockSourceError, HttpsBlockFetcher};
 pub use chain_status::SubstrateChainStatus;
+pub use counter_storage::AuxCounterStorage;
+pub use forest_checkpoint_storage::AuxForestCheckpointStorage;
 pub use justification::{
     InnerJustification, Justification, JustificationTranslator, TranslateError,
 };
+pub use justification_archive::{
+    archived_justification, compress_archived_justifications, run_justification_pruning,
+    JustificationArchiving, JustificationPruner, JustificationPruningError, JustificationRetention,
+};
 pub use status_notifier::SubstrateChainStatusNotifier;
-pub use verification::{SessionVerifier, SubstrateFinalizationInfo, VerifierCache};
+pub use verification::{
+    CustodyPolicy, EmergencyAudit, EmergencyFinalization, FinalizationInfo,
+    SessionVerificationError, SessionVerifier, SubstrateFinalizationInfo, VerificationSampling,
+    VerifierCache, EMERGENCY_AUDIT_LOG_TARGET,
+};
 
 /// Wrapper around the trait object that we get from Substrate.
 pub struct BlockImporter(pub Box<dyn ImportQueueService<Block>>);
@@ -60,6 +79,10 @@ impl HeaderT for Header {
             number,
         })
     }
+
+    fn is_sealed(&self) -> bool {
+        matches!(self.digest().logs().last(), Some(DigestItem::Seal(..)))
+    }
 }
 
 impl BlockT for Block {
@@ -69,4 +92,12 @@ impl BlockT for Block {
     fn header(&self) -> &Self::Header {
         &self.header
     }
+
+    fn body_matches_header(&self) -> bool {
+        let extrinsics = self.extrinsics.iter().map(Encode::encode).collect();
+        <<Header as SubstrateHeader>::Hashing as Hash>::ordered_trie_root(
+            extrinsics,
+            StateVersion::V0,
+        ) == *self.header.extrinsics_root()
+    }
 }
@@ -0,0 +1,146 @@
+use std::{collections::VecDeque, sync::Arc, time::SystemTime};
+
+use log::info;
+use parking_lot::Mutex;
+
+use crate::{
+    justification::{AlephJustification, EmergencyReason},
+    AuthorityId, BlockId,
+};
+
+/// How many emergency finalizations we remember.
+const MAX_AUDIT_ENTRIES: usize = 1024;
+
+/// The log target under which every accepted emergency justification is reported.
+pub const EMERGENCY_AUDIT_LOG_TARGET: &str = "aleph-emergency-audit";
+
+/// An emergency justification that passed verification, with whoever is accountable for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmergencyFinalization {
+    pub block: BlockId,
+    /// `None` if the justification came without a chain of custody.
+    pub reason: Option<EmergencyReason>,
+    /// `None` if the justification came without a chain of custody.
+    pub operator: Option<AuthorityId>,
+    pub accepted_at: SystemTime,
+}
+
+/// The emergency justifications accepted most recently, can be cloned and inspected while sync
+/// is running.
+#[derive(Clone)]
+pub struct EmergencyAudit {
+    entries: Arc<Mutex<VecDeque<EmergencyFinalization>>>,
+}
+
+impl EmergencyAudit {
+    pub fn new() -> Self {
+        EmergencyAudit {
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Records the justification of the block if it is an emergency one, unless the same one was
+    /// recorded already.
+    pub fn record(&self, block: BlockId, justification: &AlephJustification, now: SystemTime) {
+        let (reason, operator) = match justification {
+            AlephJustification::CommitteeMultisignature(_) => return,
+            AlephJustification::EmergencySignature(_) => (None, None),
+            AlephJustification::EmergencyCustody(custody) => {
+                (Some(custody.reason), Some(custody.operator.clone()))
+            }
+        };
+        let mut entries = self.entries.lock();
+        if entries.iter().any(|entry| {
+            entry.block == block && entry.reason == reason && entry.operator == operator
+        }) {
+            return;
+        }
+        match (&reason, &operator) {
+            (Some(reason), Some(operator)) => info!(
+                target: EMERGENCY_AUDIT_LOG_TARGET,
+                "Accepted emergency justification of {} ordered by operator {:?} with reason {}.",
+                block,
+                operator,
+                reason
+            ),
+            _ => info!(
+                target: EMERGENCY_AUDIT_LOG_TARGET,
+                "Accepted emergency justification of {} without a chain of custody.", block
+            ),
+        }
+        if entries.len() == MAX_AUDIT_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(EmergencyFinalization {
+            block,
+            reason,
+            operator,
+            accepted_at: now,
+        });
+    }
+
+    /// At most `limit` most recently accepted emergency justifications, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<EmergencyFinalization> {
+        self.entries
+            .lock()
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for EmergencyAudit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use sp_core::Pair;
+
+    use super::EmergencyAudit;
+    use crate::{
+        aleph_primitives::{AuthorityPair, BlockHash},
+        justification::{AlephJustification, EmergencyCustody},
+        BlockId, NodeCount, SignatureSet,
+    };
+
+    #[test]
+    fn records_only_new_emergency_justifications() {
+        let audit = EmergencyAudit::new();
+        let now = SystemTime::now();
+        let emergency = AuthorityPair::generate().0;
+        let operator = AuthorityPair::generate().0;
+        let first = BlockId::new(BlockHash::random(), 7);
+        let second = BlockId::new(BlockHash::random(), 8);
+        audit.record(
+            first.clone(),
+            &AlephJustification::CommitteeMultisignature(SignatureSet::with_size(NodeCount(4)).0),
+            now,
+        );
+        assert!(audit.recent(10).is_empty());
+        let plain = AlephJustification::EmergencySignature(emergency.sign(&[7]));
+        audit.record(first.clone(), &plain, now);
+        audit.record(first.clone(), &plain, now);
+        let custody = AlephJustification::EmergencyCustody(EmergencyCustody {
+            reason: 3,
+            operator: operator.public(),
+            operator_signature: operator.sign(&[8]),
+            emergency_signature: emergency.sign(&[8]),
+        });
+        audit.record(second.clone(), &custody, now);
+        let recent = audit.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].block, second);
+        assert_eq!(recent[0].reason, Some(3));
+        assert_eq!(recent[0].operator, Some(operator.public()));
+        assert_eq!(recent[1].block, first);
+        assert_eq!(recent[1].operator, None);
+        assert_eq!(audit.recent(1).len(), 1);
+    }
+}
@@ -1,18 +1,25 @@
 use std::{
     collections::{hash_map::Entry, HashMap},
     fmt::{Debug, Display, Error as FmtError, Formatter},
+    num::NonZeroUsize,
 };
 
+use lru::LruCache;
 use sp_runtime::SaturatedConversion;
 
 use crate::{
-    aleph_primitives::BlockNumber,
+    aleph_primitives::{BlockNumber, SessionAuthorityData},
     session::{SessionBoundaryInfo, SessionId},
     session_map::AuthorityProvider,
+    session_prefetch::PrefetchedAuthorities,
     sync::{
-        substrate::verification::{verifier::SessionVerifier, FinalizationInfo},
+        substrate::verification::{
+            audit::EmergencyAudit, sampling::VerificationSampling, CustodyPolicy, FinalizationInfo,
+            SessionVerifier,
+        },
         Header,
     },
+    AuthorityId,
 };
 
 /// Ways in which a justification can fail verification.
@@ -21,6 +28,7 @@ pub enum CacheError {
     UnknownAuthorities(SessionId),
     SessionTooOld(SessionId, SessionId),
     SessionInFuture(SessionId, SessionId),
+    UnknownEmergencySigner(SessionId),
     BadGenesisHeader,
 }
 
@@ -42,6 +50,12 @@ impl Display for CacheError {
                     "authorities for session {session:?} not known even though they should be"
                 )
             }
+            UnknownEmergencySigner(session) => {
+                write!(
+                    f,
+                    "emergency finalizer of session {session:?} not known yet"
+                )
+            }
             BadGenesisHeader => {
                 write!(
                     f,
@@ -52,10 +66,15 @@ impl Display for CacheError {
     }
 }
 
+/// How many justifications that passed their offloaded checks are remembered, until verified.
+const MAX_PASSED_CHECKS: usize = 4096;
+
 /// Cache storing SessionVerifier structs for multiple sessions. Keeps up to `cache_size` verifiers of top sessions.
 /// If the session is too new or ancient it will fail to return a SessionVerifier.
 /// Highest session verifier this cache returns is for the session after the current finalization session.
 /// Lowest session verifier this cache returns is for `top_returned_session` - `cache_size`.
+/// Verifiers of later sessions are only returned if their authorities were learned from verified
+/// justifications of the blocks ending the sessions before them, as during warp sync.
 pub struct VerifierCache<AP, FI, H>
 where
     AP: AuthorityProvider,
@@ -63,6 +82,8 @@ where
     H: Header,
 {
     sessions: HashMap<SessionId, SessionVerifier>,
+    /// Verifiers of sessions above the upper bound, without the emergency finalizers.
+    warped: HashMap<SessionId, SessionVerifier>,
     session_info: SessionBoundaryInfo,
     finalization_info: FI,
     authority_provider: AP,
@@ -70,6 +91,14 @@ where
     /// Lowest currently available session.
     lower_bound: SessionId,
     genesis_header: H,
+    custody: CustodyPolicy,
+    emergency_audit: EmergencyAudit,
+    /// Authorities fetched ahead of the sessions, used instead of asking the runtime.
+    prefetched: PrefetchedAuthorities,
+    /// Which justifications below a trusted checkpoint are verified, all if not set.
+    sampling: Option<VerificationSampling>,
+    /// The digests of the justifications whose signatures were already checked by the workers.
+    passed_checks: LruCache<[u8; 32], ()>,
 }
 
 impl<AP, FI, H> VerifierCache<AP, FI, H>
@@ -84,21 +113,70 @@ where
         authority_provider: AP,
         cache_size: usize,
         genesis_header: H,
+        custody: CustodyPolicy,
+        emergency_audit: EmergencyAudit,
     ) -> Self {
         Self {
             sessions: HashMap::new(),
+            warped: HashMap::new(),
             session_info,
             finalization_info,
             authority_provider,
             cache_size,
             lower_bound: SessionId(0),
             genesis_header,
+            custody,
+            emergency_audit,
+            prefetched: PrefetchedAuthorities::new(),
+            sampling: None,
+            passed_checks: LruCache::new(
+                NonZeroUsize::new(MAX_PASSED_CHECKS).expect("the constant is nonzero"),
+            ),
         }
     }
 
+    /// Makes the cache use the authorities prefetched ahead of the sessions, if any, before
+    /// asking the runtime for them.
+    pub fn with_prefetched_authorities(self, prefetched: PrefetchedAuthorities) -> Self {
+        Self { prefetched, ..self }
+    }
+
+    /// Makes the cache only verify a sample of the justifications below a trusted checkpoint, if
+    /// provided.
+    pub fn with_verification_sampling(self, sampling: Option<VerificationSampling>) -> Self {
+        Self { sampling, ..self }
+    }
+
+    /// Whether the signatures of the justification of the block should be verified, rather than
+    /// trusted because of sampling.
+    pub fn should_verify(&self, number: BlockNumber) -> bool {
+        self.sampling.as_ref().map_or(true, |sampling| {
+            sampling.should_verify(number, &self.session_info)
+        })
+    }
+
     pub fn genesis_header(&self) -> &H {
         &self.genesis_header
     }
+
+    pub fn session_info(&self) -> &SessionBoundaryInfo {
+        &self.session_info
+    }
+
+    /// Remembers that the justification with the digest passed its offloaded check.
+    pub fn record_passed_check(&mut self, digest: [u8; 32]) {
+        self.passed_checks.put(digest, ());
+    }
+
+    /// Whether the justification with the digest passed its offloaded check, forgetting it.
+    pub fn take_passed_check(&mut self, digest: &[u8; 32]) -> bool {
+        self.passed_checks.pop(digest).is_some()
+    }
+
+    /// Where the accepted emergency justifications are recorded.
+    pub fn emergency_audit(&self) -> &EmergencyAudit {
+        &self.emergency_audit
+    }
 }
 
 /// Download authorities for the session and return `SessionVerifier` for them. `session_id` should be the first session,
@@ -107,6 +185,7 @@ fn download_session_verifier<AP: AuthorityProvider>(
     authority_provider: &AP,
     session_id: SessionId,
     session_info: &SessionBoundaryInfo,
+    custody: &CustodyPolicy,
 ) -> Option<SessionVerifier> {
     let maybe_authority_data = match session_id {
         SessionId(0) => authority_provider.authority_data(0),
@@ -116,7 +195,7 @@ fn download_session_verifier<AP: AuthorityProvider>(
         }
     };
 
-    maybe_authority_data.map(|a| a.into())
+    maybe_authority_data.map(|a| SessionVerifier::from(a).with_custody(custody.clone()))
 }
 
 impl<AP, FI, H> VerifierCache<AP, FI, H>
@@ -128,9 +207,34 @@ where
     /// Prune all sessions with a number smaller than `session_id`
     fn prune(&mut self, session_id: SessionId) {
         self.sessions.retain(|&id, _| id >= session_id);
+        self.warped.retain(|&id, _| id >= session_id);
         self.lower_bound = session_id;
     }
 
+    /// We are sure about authorities in all session that have first block from previous session finalized.
+    fn upper_bound(&self) -> SessionId {
+        self.session_info
+            .session_id_from_block_num(self.finalization_info.finalized_number())
+            .next()
+    }
+
+    /// Remembers the authorities of a session above the upper bound, learned from a verified
+    /// justification of the block ending the previous session. The emergency finalizer is not
+    /// announced there, so emergency justifications of the session cannot be verified.
+    pub fn warp(&mut self, session_id: SessionId, authorities: Vec<AuthorityId>) {
+        if session_id <= self.upper_bound() {
+            return;
+        }
+        let verifier = SessionVerifier::from(SessionAuthorityData::new(authorities, None))
+            .with_custody(self.custody.clone());
+        self.warped.insert(session_id, verifier);
+    }
+
+    /// Whether the verifier of the session was learned through `warp`.
+    pub fn is_warped(&self, session_id: SessionId) -> bool {
+        session_id > self.upper_bound() && self.warped.contains_key(&session_id)
+    }
+
     /// Returns session verifier for block number if available. Updates cache if necessary.
     pub fn get(&mut self, number: BlockNumber) -> Result<&SessionVerifier, CacheError> {
         let session_id = self.session_info.session_id_from_block_num(number);
@@ -139,15 +243,12 @@ where
             return Err(CacheError::SessionTooOld(session_id, self.lower_bound));
         }
 
-        // We are sure about authorities in all session that have first block from previous session finalized.
-        let upper_bound = SessionId(
-            self.session_info
-                .session_id_from_block_num(self.finalization_info.finalized_number())
-                .0
-                + 1,
-        );
+        let upper_bound = self.upper_bound();
         if session_id > upper_bound {
-            return Err(CacheError::SessionInFuture(session_id, upper_bound));
+            return self
+                .warped
+                .get(&session_id)
+                .ok_or(CacheError::SessionInFuture(session_id, upper_bound));
         }
 
         if session_id.0
@@ -167,11 +268,17 @@ where
         let verifier = match self.sessions.entry(session_id) {
             Entry::Occupied(occupied) => occupied.into_mut(),
             Entry::Vacant(vacant) => {
-                let verifier = download_session_verifier(
-                    &self.authority_provider,
-                    session_id,
-                    &self.session_info,
-                )
+                let verifier = match self.prefetched.take(session_id) {
+                    Some(authority_data) => Some(
+                        SessionVerifier::from(authority_data).with_custody(self.custody.clone()),
+                    ),
+                    None => download_session_verifier(
+                        &self.authority_provider,
+                        session_id,
+                        &self.session_info,
+                        &self.custody,
+                    ),
+                }
                 .ok_or(CacheError::UnknownAuthorities(session_id))?;
                 vacant.insert(verifier)
             }
@@ -179,19 +286,36 @@ where
 
         Ok(verifier)
     }
+
+    /// Returns session verifier for the number of an already finalized block, also of sessions
+    /// older than the cached ones, the authorities of which are downloaded again without caching.
+    pub fn get_finalized(&mut self, number: BlockNumber) -> Result<SessionVerifier, CacheError> {
+        let session_id = self.session_info.session_id_from_block_num(number);
+        if session_id >= self.lower_bound {
+            return self.get(number).cloned();
+        }
+        download_session_verifier(
+            &self.authority_provider,
+            session_id,
+            &self.session_info,
+            &self.custody,
+        )
+        .ok_or(CacheError::UnknownAuthorities(session_id))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{cell::Cell, collections::HashMap};
+    use std::{cell::Cell, collections::HashMap, num::NonZeroU32};
 
     use super::{
-        AuthorityProvider, BlockNumber, CacheError, FinalizationInfo, SessionVerifier,
-        VerifierCache,
+        AuthorityProvider, BlockNumber, CacheError, CustodyPolicy, EmergencyAudit,
+        FinalizationInfo, SessionVerifier, VerificationSampling, VerifierCache,
     };
     use crate::{
         aleph_primitives::SessionAuthorityData,
         session::{testing::authority_data, SessionBoundaryInfo, SessionId},
+        session_prefetch::PrefetchedAuthorities,
         sync::mock::MockHeader,
         SessionPeriod,
     };
@@ -261,6 +385,8 @@ mod tests {
             authority_provider,
             CACHE_SIZE,
             genesis_header,
+            CustodyPolicy::default(),
+            EmergencyAudit::new(),
         )
     }
 
@@ -339,6 +465,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn downloads_verifiers_of_pruned_finalized_sessions() {
+        let finalized_number = Cell::new(0);
+
+        let mut verifier = setup_test(3, &finalized_number);
+
+        finalize_first_in_session(&finalized_number, 2);
+        check_session_verifier(&mut verifier, 3);
+        assert!(session_verifier(&mut verifier, 0).is_err());
+
+        let expected_verifier: SessionVerifier = authority_data_for_session(0).into();
+        assert_eq!(
+            verifier.get_finalized(SESSION_PERIOD - 1),
+            Ok(expected_verifier)
+        );
+        // The downloaded verifier is not cached, the cached sessions stay as they were.
+        assert!(session_verifier(&mut verifier, 0).is_err());
+        check_session_verifier(&mut verifier, 3);
+    }
+
     #[test]
     fn session_from_future() {
         let finalized_number = Cell::new(0);
@@ -354,6 +500,55 @@ mod tests {
         );
     }
 
+    #[test]
+    fn uses_warped_sessions() {
+        let finalized_number = Cell::new(0);
+        let mut verifier = setup_test(0, &finalized_number);
+        let authorities = authority_data_for_session(3).authorities().clone();
+
+        // Not above the upper bound, so nothing to learn.
+        verifier.warp(SessionId(1), authorities.clone());
+        assert!(!verifier.is_warped(SessionId(1)));
+        verifier.warp(SessionId(3), authorities.clone());
+        assert!(verifier.is_warped(SessionId(3)));
+        assert_eq!(
+            session_verifier(&mut verifier, 2),
+            Err(CacheError::SessionInFuture(SessionId(2), SessionId(1)))
+        );
+        // Far ahead sessions do not prune the ones we need currently.
+        let expected_verifier: SessionVerifier =
+            SessionAuthorityData::new(authorities, None).into();
+        assert_eq!(session_verifier(&mut verifier, 3), Ok(expected_verifier));
+        check_session_verifier(&mut verifier, 0);
+    }
+
+    #[test]
+    fn uses_prefetched_authorities() {
+        let finalized_number = Cell::new(0);
+        let prefetched = PrefetchedAuthorities::new();
+        let mut verifier =
+            setup_test(0, &finalized_number).with_prefetched_authorities(prefetched.clone());
+        prefetched.insert(SessionId(1), authority_data_for_session(1));
+
+        // The runtime does not know the authorities, but they were prefetched.
+        check_session_verifier(&mut verifier, 1);
+        assert_eq!(prefetched.take(SessionId(1)), None);
+    }
+
+    #[test]
+    fn verifies_all_but_sampled_out_justifications() {
+        let finalized_number = Cell::new(0);
+        let verifier = setup_test(0, &finalized_number);
+        assert!(verifier.should_verify(1));
+
+        let checkpoint = 3 * SESSION_PERIOD;
+        let sampling =
+            VerificationSampling::new(checkpoint, NonZeroU32::new(u32::MAX).expect("not zero"));
+        let verifier = verifier.with_verification_sampling(Some(sampling));
+        assert!(verifier.should_verify(SESSION_PERIOD - 1));
+        assert!(verifier.should_verify(checkpoint + 1));
+    }
+
     #[test]
     fn authority_provider_error() {
         let finalized_number = Cell::new(0);
@@ -1,29 +1,34 @@
 use std::{
     fmt::{Debug, Display, Error as FmtError, Formatter},
     sync::Arc,
+    time::SystemTime,
 };
 
+use log::debug;
 use parity_scale_codec::Encode;
 use sc_client_api::HeaderBackend;
+use sp_core::hashing::blake2_256;
 use sp_runtime::traits::Header as SubstrateHeader;
 
 use crate::{
-    aleph_primitives::{Block, BlockNumber, Header},
+    aleph_primitives::{AuthorityId, Block, BlockNumber, ConsensusLog, Header, ALEPH_ENGINE_ID},
+    justification::AlephJustification,
     session_map::AuthorityProvider,
     sync::{
-        substrate::{
-            verification::{cache::CacheError, verifier::SessionVerificationError},
-            InnerJustification, Justification,
-        },
-        Verifier,
+        substrate::{verification::cache::CacheError, InnerJustification, Justification},
+        OffloadedCheck, Verifier, LOG_TARGET,
     },
+    BlockId,
 };
 
+mod audit;
 mod cache;
-mod verifier;
+mod sampling;
 
+pub use audit::{EmergencyAudit, EmergencyFinalization, EMERGENCY_AUDIT_LOG_TARGET};
 pub use cache::VerifierCache;
-pub use verifier::SessionVerifier;
+pub use light_verifier::{CustodyPolicy, SessionVerificationError, SessionVerifier};
+pub use sampling::VerificationSampling;
 
 /// Supplies finalized number. Will be unified together with other traits we used in A0-1839.
 pub trait FinalizationInfo {
@@ -49,6 +54,8 @@ impl<BE: HeaderBackend<Block>> FinalizationInfo for SubstrateFinalizationInfo<BE
 pub enum VerificationError {
     Verification(SessionVerificationError),
     Cache(CacheError),
+    NotSessionEnd(BlockNumber),
+    MissingAuthorityChange(BlockNumber),
 }
 
 impl From<SessionVerificationError> for VerificationError {
@@ -69,7 +76,52 @@ impl Display for VerificationError {
         match self {
             Verification(e) => write!(f, "{e}"),
             Cache(e) => write!(f, "{e}"),
+            NotSessionEnd(number) => {
+                write!(f, "block {number} does not end a session")
+            }
+            MissingAuthorityChange(number) => {
+                write!(
+                    f,
+                    "block {number} ends a session without announcing the next authorities"
+                )
+            }
+        }
+    }
+}
+
+/// Identifies the justification together with the block it justifies, for remembering the passed
+/// checks.
+fn check_digest(justification: &AlephJustification, header: &Header) -> [u8; 32] {
+    blake2_256(&(header.hash(), justification).encode())
+}
+
+impl<AP, FS> VerifierCache<AP, FS, Header>
+where
+    AP: AuthorityProvider,
+    FS: FinalizationInfo,
+{
+    /// Verifies the justification of the block ending a session and learns the authorities of
+    /// the next one from its digest.
+    fn verify_session_end(
+        &mut self,
+        justification: Justification,
+    ) -> Result<Justification, VerificationError> {
+        let number = *justification.header.number();
+        let session = self.session_info().session_id_from_block_num(number);
+        if self.session_info().last_block_of_session(session) != number {
+            return Err(VerificationError::NotSessionEnd(number));
         }
+        let justification = self.verify(justification)?;
+        let authorities = justification
+            .header
+            .digest()
+            .convert_first(|item| {
+                item.consensus_try_to::<ConsensusLog<AuthorityId>>(&ALEPH_ENGINE_ID)
+            })
+            .map(|ConsensusLog::AlephAuthorityChange(authorities)| authorities)
+            .ok_or(VerificationError::MissingAuthorityChange(number))?;
+        self.warp(session.next(), authorities);
+        Ok(justification)
     }
 }
 
@@ -84,8 +136,45 @@ where
         let header = &justification.header;
         match &justification.inner_justification {
             InnerJustification::AlephJustification(aleph_justification) => {
+                let sampled_out = matches!(
+                    aleph_justification,
+                    AlephJustification::CommitteeMultisignature(_)
+                ) && !self.should_verify(*header.number());
+                // The workers might have checked the signatures already, offloading them.
+                let passed = !sampled_out
+                    && self.take_passed_check(&check_digest(aleph_justification, header));
                 let verifier = self.get(*header.number())?;
-                verifier.verify_bytes(aleph_justification, header.hash().encode())?;
+                if sampled_out {
+                    debug!(
+                        target: LOG_TARGET,
+                        "Accepting justification of block #{} below the trusted checkpoint without verifying it.",
+                        header.number()
+                    );
+                    return Ok(justification);
+                }
+                let result = match passed {
+                    true => Ok(()),
+                    false => verifier.verify_bytes(aleph_justification, header.hash().encode()),
+                };
+                match result {
+                    Ok(()) => {}
+                    // Sessions learned during warp sync come without their emergency finalizers.
+                    Err(SessionVerificationError::NoEmergencySigner) => {
+                        let session = self
+                            .session_info()
+                            .session_id_from_block_num(*header.number());
+                        return Err(match self.is_warped(session) {
+                            true => CacheError::UnknownEmergencySigner(session).into(),
+                            false => SessionVerificationError::NoEmergencySigner.into(),
+                        });
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+                self.emergency_audit().record(
+                    BlockId::new(header.hash(), *header.number()),
+                    aleph_justification,
+                    SystemTime::now(),
+                );
                 Ok(justification)
             }
             InnerJustification::Genesis => match header == self.genesis_header() {
@@ -94,4 +183,75 @@ where
             },
         }
     }
+
+    fn is_invalid(error: &Self::Error) -> bool {
+        use VerificationError::*;
+        matches!(
+            error,
+            Verification(_)
+                | Cache(CacheError::BadGenesisHeader)
+                | NotSessionEnd(_)
+                | MissingAuthorityChange(_)
+        )
+    }
+
+    fn verify_session_chain(
+        &mut self,
+        justifications: Vec<Justification>,
+    ) -> (Vec<Justification>, Option<Self::Error>) {
+        let mut verified = Vec::new();
+        for justification in justifications {
+            match self.verify_session_end(justification) {
+                Ok(justification) => verified.push(justification),
+                Err(e) => return (verified, Some(e)),
+            }
+        }
+        (verified, None)
+    }
+
+    fn verify_finalized(
+        &mut self,
+        justification: Justification,
+    ) -> Result<Justification, Self::Error> {
+        let header = &justification.header;
+        match &justification.inner_justification {
+            InnerJustification::AlephJustification(aleph_justification) => {
+                self.get_finalized(*header.number())?
+                    .verify_bytes(aleph_justification, header.hash().encode())?;
+                Ok(justification)
+            }
+            InnerJustification::Genesis => self.verify(justification),
+        }
+    }
+
+    fn offload(&mut self, justification: &Justification) -> Option<OffloadedCheck> {
+        let header = &justification.header;
+        let aleph_justification = match &justification.inner_justification {
+            InnerJustification::AlephJustification(aleph_justification) => {
+                aleph_justification.clone()
+            }
+            InnerJustification::Genesis => return None,
+        };
+        // Sampled out justifications are cheap already, and ones we cannot verify yet fail fast.
+        if matches!(
+            aleph_justification,
+            AlephJustification::CommitteeMultisignature(_)
+        ) && !self.should_verify(*header.number())
+        {
+            return None;
+        }
+        let verifier = self.get(*header.number()).ok()?.clone();
+        let bytes = header.hash().encode();
+        Some(Box::new(move || {
+            verifier.verify_bytes(&aleph_justification, bytes).is_ok()
+        }))
+    }
+
+    fn passed_check(&mut self, justification: &Justification) {
+        if let InnerJustification::AlephJustification(aleph_justification) =
+            &justification.inner_justification
+        {
+            self.record_passed_check(check_digest(aleph_justification, &justification.header));
+        }
+    }
 }
@@ -0,0 +1,105 @@
+use std::num::NonZeroU32;
+
+use rand::{thread_rng, Rng};
+
+use crate::{aleph_primitives::BlockNumber, session::SessionBoundaryInfo};
+
+/// Verifying only some of the justifications of the blocks below a trusted checkpoint, to rebuild
+/// archives of long chains faster.
+///
+/// Below the checkpoint the signatures of a justification are only verified with probability
+/// `1 / one_in`, except for the blocks ending sessions, which are always verified, as the
+/// authorities of the following sessions are learned from them. The blocks above the checkpoint
+/// and emergency justifications are always verified. This trusts the peers not to have forged
+/// the justifications that were not verified: a chain with `k` forged justifications below the
+/// checkpoint is accepted with probability `(1 - 1 / one_in)^k`, see `undetected_probability`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerificationSampling {
+    checkpoint: BlockNumber,
+    one_in: NonZeroU32,
+}
+
+impl VerificationSampling {
+    pub fn new(checkpoint: BlockNumber, one_in: NonZeroU32) -> Self {
+        VerificationSampling { checkpoint, one_in }
+    }
+
+    pub fn checkpoint(&self) -> BlockNumber {
+        self.checkpoint
+    }
+
+    pub fn one_in(&self) -> NonZeroU32 {
+        self.one_in
+    }
+
+    /// Whether the signatures of the justification of the block should be verified.
+    pub fn should_verify(&self, number: BlockNumber, session_info: &SessionBoundaryInfo) -> bool {
+        self.should_verify_with(number, session_info, &mut thread_rng())
+    }
+
+    fn should_verify_with<R: Rng>(
+        &self,
+        number: BlockNumber,
+        session_info: &SessionBoundaryInfo,
+        rng: &mut R,
+    ) -> bool {
+        if number > self.checkpoint {
+            return true;
+        }
+        let session_id = session_info.session_id_from_block_num(number);
+        if session_info.last_block_of_session(session_id) == number {
+            return true;
+        }
+        rng.gen_ratio(1, self.one_in.get())
+    }
+
+    /// The probability that none of `forged` justifications below the checkpoint gets verified.
+    pub fn undetected_probability(&self, forged: u32) -> f64 {
+        let missed = 1.0 - 1.0 / f64::from(self.one_in.get());
+        missed.powf(f64::from(forged))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::VerificationSampling;
+    use crate::session::{SessionBoundaryInfo, SessionPeriod};
+
+    const SESSION_PERIOD: u32 = 30;
+    const CHECKPOINT: u32 = 10 * SESSION_PERIOD;
+
+    fn sampling(one_in: u32) -> VerificationSampling {
+        VerificationSampling::new(CHECKPOINT, NonZeroU32::new(one_in).expect("not zero"))
+    }
+
+    #[test]
+    fn verifies_session_ends_and_blocks_above_checkpoint() {
+        let sampling = sampling(u32::MAX);
+        let session_info = SessionBoundaryInfo::new(SessionPeriod(SESSION_PERIOD));
+        let mut rng = StdRng::seed_from_u64(0);
+        for number in 0..2 * CHECKPOINT {
+            let should_verify = sampling.should_verify_with(number, &session_info, &mut rng);
+            let expected = number > CHECKPOINT || number % SESSION_PERIOD == SESSION_PERIOD - 1;
+            assert_eq!(should_verify, expected, "block #{number}");
+        }
+    }
+
+    #[test]
+    fn samples_blocks_below_checkpoint() {
+        let one_in_four = sampling(4);
+        let session_info = SessionBoundaryInfo::new(SessionPeriod(u32::MAX));
+        let mut rng = StdRng::seed_from_u64(0);
+        let verified = (0..CHECKPOINT)
+            .filter(|number| one_in_four.should_verify_with(*number, &session_info, &mut rng))
+            .count();
+        assert!(verified > 0);
+        assert!(verified < CHECKPOINT as usize / 2);
+        assert_eq!(one_in_four.undetected_probability(0), 1.0);
+        assert_eq!(one_in_four.undetected_probability(2), 0.5625);
+        assert_eq!(sampling(1).undetected_probability(1), 0.0);
+    }
+}
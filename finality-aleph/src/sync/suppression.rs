@@ -0,0 +1,118 @@
+use std::num::NonZeroUsize;
+
+use lru::LruCache;
+
+use crate::{sync::PeerId, BlockIdentifier};
+
+/// How many peers we remember the announced state of.
+const MAX_REMEMBERED_PEERS: usize = 256;
+/// How many periodic broadcasts in a row can be skipped, so that peers which only listen still
+/// hear from us every once in a while.
+const MAX_SUPPRESSED_IN_ROW: u32 = 9;
+
+/// Decides whether a periodic state broadcast can be skipped, which is the case when all the
+/// peers we know of announced the same top finalized block as ours, and it did not change since
+/// our last broadcast.
+pub struct BroadcastSuppression<I: PeerId, BI: BlockIdentifier> {
+    peer_tops: LruCache<I, BI>,
+    last_broadcast: Option<BI>,
+    suppressed_in_row: u32,
+}
+
+impl<I: PeerId, BI: BlockIdentifier> BroadcastSuppression<I, BI> {
+    pub fn new() -> Self {
+        BroadcastSuppression {
+            peer_tops: LruCache::new(
+                NonZeroUsize::new(MAX_REMEMBERED_PEERS).expect("the constant is nonzero"),
+            ),
+            last_broadcast: None,
+            suppressed_in_row: 0,
+        }
+    }
+
+    /// Remembers the top finalized block announced by the peer.
+    pub fn peer_state(&mut self, peer: I, top: BI) {
+        self.peer_tops.put(peer, top);
+    }
+
+    /// Forgets the state of the peer, which disconnected and no longer hears our broadcasts.
+    pub fn forget_peer(&mut self, peer: &I) {
+        self.peer_tops.pop(peer);
+    }
+
+    /// Remembers that we broadcast a state with the given top finalized block.
+    pub fn broadcast(&mut self, top: BI) {
+        self.last_broadcast = Some(top);
+        self.suppressed_in_row = 0;
+    }
+
+    /// Whether the periodic broadcast of a state with the given top finalized block should be
+    /// skipped. If this returns `true` the broadcast is considered skipped.
+    pub fn suppress(&mut self, top: &BI) -> bool {
+        let all_up_to_date = self.last_broadcast.as_ref() == Some(top)
+            && !self.peer_tops.is_empty()
+            && self.peer_tops.iter().all(|(_, peer_top)| peer_top == top);
+        if !all_up_to_date || self.suppressed_in_row >= MAX_SUPPRESSED_IN_ROW {
+            return false;
+        }
+        self.suppressed_in_row += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BroadcastSuppression, MAX_SUPPRESSED_IN_ROW};
+    use crate::sync::{
+        mock::{MockIdentifier, MockPeerId},
+        Header,
+    };
+
+    fn branch(length: usize) -> Vec<MockIdentifier> {
+        MockIdentifier::new_random(0)
+            .random_branch()
+            .take(length)
+            .map(|header| header.id())
+            .collect()
+    }
+
+    #[test]
+    fn suppresses_when_everyone_up_to_date() {
+        let mut suppression = BroadcastSuppression::<MockPeerId, _>::new();
+        let ids = branch(2);
+        assert!(!suppression.suppress(&ids[0]));
+        suppression.broadcast(ids[0].clone());
+        assert!(!suppression.suppress(&ids[0]));
+        suppression.peer_state(1, ids[0].clone());
+        suppression.peer_state(2, ids[0].clone());
+        assert!(suppression.suppress(&ids[0]));
+        // a new block got finalized
+        assert!(!suppression.suppress(&ids[1]));
+    }
+
+    #[test]
+    fn does_not_suppress_when_some_peer_differs() {
+        let mut suppression = BroadcastSuppression::<MockPeerId, _>::new();
+        let ids = branch(2);
+        suppression.broadcast(ids[1].clone());
+        suppression.peer_state(1, ids[1].clone());
+        suppression.peer_state(2, ids[0].clone());
+        assert!(!suppression.suppress(&ids[1]));
+        suppression.peer_state(2, ids[1].clone());
+        assert!(suppression.suppress(&ids[1]));
+    }
+
+    #[test]
+    fn suppresses_limited_number_in_row() {
+        let mut suppression = BroadcastSuppression::<MockPeerId, _>::new();
+        let id = MockIdentifier::new_random(7);
+        suppression.broadcast(id.clone());
+        suppression.peer_state(1, id.clone());
+        for _ in 0..MAX_SUPPRESSED_IN_ROW {
+            assert!(suppression.suppress(&id));
+        }
+        assert!(!suppression.suppress(&id));
+        suppression.broadcast(id.clone());
+        assert!(suppression.suppress(&id));
+    }
+}
@@ -60,6 +60,11 @@ impl<T> TaskQueue<T> {
         }
     }
 
+    /// All the scheduled tasks, in no particular order.
+    pub fn tasks(&self) -> impl Iterator<Item = &T> {
+        self.queue.iter().map(|scheduled| &scheduled.task)
+    }
+
     /// Schedules `task` for after `delay`.
     pub fn schedule_in(&mut self, task: T, delay: Duration) {
         let scheduled_time = match Instant::now().checked_add(delay) {
@@ -17,15 +17,18 @@ use crate::{
 };
 
 const MIN_DELAY: Duration = Duration::from_millis(300);
+const MAX_DELAY: Duration = Duration::from_secs(10);
 const ADDITIONAL_DELAY: Duration = Duration::from_millis(200);
 
-// The delay is the minimum delay, plus uniformly randomly chosen multiple of additional delay,
-// linear with the ettempt number.
+// The delay is the minimum delay doubled with every attempt up to the maximum delay, plus
+// uniformly randomly chosen fraction of additional delay, so that the retries of many requests are
+// spread out. The request is retried once the delay passes, so it is also how long a response is
+// waited for.
 fn delay_for_attempt(attempt: u32) -> Duration {
     MIN_DELAY
-        + ADDITIONAL_DELAY
-            .mul_f32(thread_rng().gen())
-            .saturating_mul(attempt)
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(MAX_DELAY)
+        + ADDITIONAL_DELAY.mul_f32(thread_rng().gen())
 }
 
 enum RequestKind {
@@ -0,0 +1,494 @@
+//! Simulations of several nodes running the sync handler, exchanging their messages over an
+//! in-memory network in virtual time, with the faults scripted by the test. Everything the
+//! harness decides, from the hashes of the produced blocks to the fates of the messages, is
+//! derived from a seed, so a failing scenario can be replayed exactly.
+use std::{fmt::Display, iter, mem, time::Duration};
+
+use futures::FutureExt;
+use log::debug;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    network::CancelToken,
+    session::SessionBoundaryInfo,
+    sync::{
+        data::{ExtendedState, NetworkData, Request, State},
+        finalization_hook::FinalizationHooks,
+        forest::Interest,
+        handler::{Action, DatabaseIO, HandleStateAction, Handler},
+        metrics::Metrics,
+        mock::{Backend, MockBlock, MockHeader, MockIdentifier, MockJustification, MockPeerId},
+        provenance::ProvenanceHistory,
+        Block, BlockImport, CandidateId, ChainStatus, ChainStatusNotification, ChainStatusNotifier,
+        Header, LOG_TARGET,
+    },
+    BlockIdentifier, BlockNumber, SessionPeriod,
+};
+
+mod network;
+
+pub use network::{Fate, LinkConditions, SimulatedNetwork, TraceEntry};
+
+type Data = NetworkData<MockBlock, MockJustification>;
+type TestHandler =
+    Handler<MockBlock, MockPeerId, MockJustification, Backend, Backend, Backend, Backend>;
+
+/// How often the nodes broadcast their states and retry their requests.
+pub const TICK: Duration = Duration::from_millis(100);
+const SESSION_PERIOD: SessionPeriod = SessionPeriod(1000);
+
+/// Something the test makes happen at a given time.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Scripted {
+    /// The node builds a block on top of its best one.
+    Produce(usize),
+    /// The committee hands the node the justification of its best block.
+    Justify(usize),
+    /// The network splits into the parts, see [`SimulatedNetwork::partition`].
+    Partition(Vec<Vec<usize>>),
+    Heal,
+    /// The link from one node to the other changes, only in that direction.
+    SetLink(usize, usize, LinkConditions),
+}
+
+struct SimulatedNode<N: ChainStatusNotifier<MockHeader>> {
+    handler: TestHandler,
+    backend: Backend,
+    notifier: N,
+    best: MockHeader,
+    /// The blocks the node wants, in the order it learned about them.
+    pending: Vec<MockIdentifier>,
+    errors: usize,
+}
+
+impl<N: ChainStatusNotifier<MockHeader>> SimulatedNode<N> {
+    fn error(&mut self, e: impl Display) {
+        debug!(target: LOG_TARGET, "Simulated node failed to handle data: {}.", e);
+        self.errors += 1;
+    }
+
+    fn want<I: IntoIterator<Item = CandidateId<MockIdentifier>>>(&mut self, ids: I) {
+        for id in ids {
+            self.want_block(id.into_inner());
+        }
+    }
+
+    fn want_block(&mut self, id: MockIdentifier) {
+        if !self.pending.contains(&id) {
+            self.pending.push(id);
+        }
+    }
+
+    fn descends_from_finalized(&self, header: &MockHeader, finalized: &MockIdentifier) -> bool {
+        let mut current = header.clone();
+        while current.id().number() > finalized.number() {
+            current = match current
+                .parent_id()
+                .and_then(|parent| self.backend.block(parent).ok().flatten())
+            {
+                Some(parent) => parent.header().clone(),
+                None => return false,
+            };
+        }
+        &current.id() == finalized
+    }
+
+    fn handle_notifications(&mut self) {
+        while let Some(Ok(notification)) = self.notifier.next().now_or_never() {
+            match notification {
+                ChainStatusNotification::BlockImported(header) => {
+                    if header.id().number() > self.best.id().number() {
+                        self.best = header.clone();
+                    }
+                    if let Err(e) = self.handler.block_imported(header) {
+                        self.error(e);
+                    }
+                }
+                ChainStatusNotification::BlockFinalized(header) => {
+                    if !self.descends_from_finalized(&self.best, &header.id()) {
+                        self.best = header;
+                    }
+                }
+            }
+        }
+    }
+
+    fn state(&mut self) -> Option<State<MockJustification>> {
+        match self.handler.state() {
+            Ok(state) => Some(state),
+            Err(e) => {
+                self.error(e);
+                None
+            }
+        }
+    }
+}
+
+fn setup_node(
+    session_info: SessionBoundaryInfo,
+) -> SimulatedNode<impl ChainStatusNotifier<MockHeader>> {
+    let (backend, notifier) = Backend::setup(session_info.clone());
+    let database_io = DatabaseIO::new(backend.clone(), backend.clone(), backend.clone());
+    let handler = Handler::new(
+        database_io,
+        backend.clone(),
+        session_info,
+        BlockNumber::MAX,
+        1,
+        None,
+        ProvenanceHistory::new(),
+        FinalizationHooks::new(),
+        Metrics::noop(),
+    )
+    .expect("mock backend works");
+    SimulatedNode {
+        handler,
+        backend,
+        notifier,
+        best: MockHeader::genesis(),
+        pending: Vec::new(),
+        errors: 0,
+    }
+}
+
+/// Nodes running the sync handlers on top of mock backends, broadcasting their states every
+/// tick and exchanging requests and responses over the simulated network. The blocks are only
+/// produced and justified when the script says so.
+pub struct Simulation<N: ChainStatusNotifier<MockHeader>> {
+    nodes: Vec<SimulatedNode<N>>,
+    network: SimulatedNetwork<Data>,
+    /// Sorted by time, the actions scheduled for the same time in the order they were.
+    script: Vec<(Duration, Scripted)>,
+    next_tick: Duration,
+    ticks: usize,
+    rng: StdRng,
+}
+
+/// A simulation of the nodes, with all the links in the conditions, nothing scheduled yet.
+pub fn simulation(
+    nodes: usize,
+    conditions: LinkConditions,
+    seed: u64,
+) -> Simulation<impl ChainStatusNotifier<MockHeader>> {
+    let session_info = SessionBoundaryInfo::new(SESSION_PERIOD);
+    Simulation {
+        nodes: (0..nodes)
+            .map(|_| setup_node(session_info.clone()))
+            .collect(),
+        network: SimulatedNetwork::new(nodes, conditions, seed),
+        script: Vec::new(),
+        next_tick: Duration::ZERO,
+        ticks: 0,
+        // Different from the one of the network, so the blocks do not depend on the faults.
+        rng: StdRng::seed_from_u64(seed.wrapping_add(1)),
+    }
+}
+
+impl<N: ChainStatusNotifier<MockHeader>> Simulation<N> {
+    pub fn schedule(&mut self, at: Duration, action: Scripted) {
+        let position = self.script.partition_point(|(time, _)| *time <= at);
+        self.script.insert(position, (at, action));
+    }
+
+    /// Runs everything that happens up to the time, inclusive.
+    pub fn run_until(&mut self, end: Duration) {
+        loop {
+            let next = iter::once(self.next_tick)
+                .chain(self.network.next_arrival())
+                .chain(self.script.first().map(|(at, _)| *at))
+                .min()
+                .expect("there is always the next tick");
+            if next > end {
+                return self.network.advance_to(end);
+            }
+            self.network.advance_to(next);
+            while self.script.first().map_or(false, |(at, _)| *at <= next) {
+                let (_, action) = self.script.remove(0);
+                self.apply(action);
+            }
+            while let Some((from, to, data)) = self.network.deliver() {
+                self.handle_data(to, from, data);
+            }
+            if self.next_tick <= next {
+                self.tick();
+                self.next_tick += TICK;
+            }
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.network.now()
+    }
+
+    pub fn network(&self) -> &SimulatedNetwork<Data> {
+        &self.network
+    }
+
+    pub fn top_finalized(&self, node: usize) -> MockIdentifier {
+        self.nodes[node]
+            .backend
+            .top_finalized()
+            .expect("mock backend works")
+            .id()
+    }
+
+    pub fn best(&self, node: usize) -> MockIdentifier {
+        self.nodes[node].best.id()
+    }
+
+    /// How many pieces of data the node failed to handle.
+    pub fn errors(&self, node: usize) -> usize {
+        self.nodes[node].errors
+    }
+
+    fn apply(&mut self, action: Scripted) {
+        use Scripted::*;
+        debug!(target: LOG_TARGET, "Simulation at {:?}: {:?}.", self.now(), action);
+        match action {
+            Produce(node) => {
+                let header = self.nodes[node].best.seeded_child(&mut self.rng);
+                let node = &mut self.nodes[node];
+                node.backend.import_block(MockBlock::new(header, true));
+                node.handle_notifications();
+            }
+            Justify(node) => {
+                let node = &mut self.nodes[node];
+                let justification = MockJustification::for_header(node.best.clone());
+                match node.handler.handle_justification_from_user(justification) {
+                    Ok(maybe_id) => node.want(maybe_id),
+                    Err(e) => node.error(e),
+                }
+                node.handle_notifications();
+            }
+            Partition(parts) => {
+                let parts: Vec<_> = parts.iter().map(|part| &part[..]).collect();
+                self.network.partition(&parts);
+            }
+            Heal => self.network.heal(),
+            SetLink(from, to, conditions) => self.network.set_link(from, to, conditions),
+        }
+    }
+
+    /// Every node broadcasts its state with its best block, and asks for the blocks it still
+    /// wants, taking turns between the peers that know most about them.
+    fn tick(&mut self) {
+        self.ticks += 1;
+        for node in 0..self.nodes.len() {
+            self.nodes[node].handler.prune_forest(usize::MAX);
+            let state = match self.nodes[node].state() {
+                Some(state) => state,
+                None => continue,
+            };
+            let best = self.nodes[node].best.clone();
+            for peer in (0..self.nodes.len()).filter(|peer| *peer != node) {
+                let data = NetworkData::ExtendedStateBroadcast(ExtendedState::new(
+                    state.clone(),
+                    best.clone(),
+                ));
+                self.network.send(node, peer, data);
+            }
+            let pending = mem::take(&mut self.nodes[node].pending);
+            for id in pending {
+                let (know_most, branch_knowledge) =
+                    match self.nodes[node].handler.interest_provider().get(&id) {
+                        Interest::Uninterested => continue,
+                        Interest::Required {
+                            know_most,
+                            branch_knowledge,
+                        }
+                        | Interest::HighestJustified {
+                            know_most,
+                            branch_knowledge,
+                        } => (know_most, branch_knowledge),
+                    };
+                self.nodes[node].want_block(id.clone());
+                let mut peers: Vec<_> = know_most.into_iter().collect();
+                if peers.is_empty() {
+                    continue;
+                }
+                peers.sort();
+                let peer = peers[self.ticks % peers.len()] as usize;
+                let request = Request::new(id, branch_knowledge, state.clone());
+                self.network.send(node, peer, NetworkData::Request(request));
+            }
+        }
+    }
+
+    fn handle_state(&mut self, node: usize, peer: usize, state: State<MockJustification>) {
+        match self.nodes[node]
+            .handler
+            .handle_state(state, peer as MockPeerId)
+        {
+            Ok(HandleStateAction::Response(data)) => self.network.send(node, peer, data),
+            Ok(HandleStateAction::HighestJustified(id)) => self.nodes[node].want(Some(id)),
+            Ok(HandleStateAction::Noop) => {}
+            Err(e) => self.nodes[node].error(e),
+        }
+    }
+
+    fn handle_data(&mut self, node: usize, peer: usize, data: Data) {
+        use NetworkData::*;
+        let peer_id = peer as MockPeerId;
+        match data {
+            StateBroadcast(state) => self.handle_state(node, peer, state),
+            ExtendedStateBroadcast(extended) => {
+                let (state, favourite_block) = extended.into_parts();
+                self.handle_state(node, peer, state);
+                let id = favourite_block.id();
+                match self.nodes[node]
+                    .handler
+                    .handle_favourite_block(favourite_block, peer_id)
+                {
+                    Ok(true) => self.nodes[node].want_block(id),
+                    Ok(false) => {}
+                    Err(e) => self.nodes[node].error(e),
+                }
+            }
+            StateBroadcastResponse(justification, maybe_justification) => {
+                let (maybe_id, maybe_error) = self.nodes[node].handler.handle_state_response(
+                    justification,
+                    maybe_justification,
+                    peer_id,
+                );
+                self.nodes[node].want(maybe_id);
+                if let Some(e) = maybe_error {
+                    self.nodes[node].error(e);
+                }
+            }
+            BatchedStateBroadcastResponse(justification, justifications) => {
+                let (ids, maybe_error) = self.nodes[node].handler.handle_batched_state_response(
+                    iter::once(justification).chain(justifications).collect(),
+                    peer_id,
+                );
+                self.nodes[node].want(ids);
+                if let Some(e) = maybe_error {
+                    self.nodes[node].error(e);
+                }
+            }
+            Request(request) => {
+                match self.nodes[node]
+                    .handler
+                    .handle_request(request, &CancelToken::new())
+                {
+                    Ok(Action::Response(items)) => {
+                        self.network.send(node, peer, RequestResponse(items))
+                    }
+                    Ok(Action::RequestBlock(id)) => self.nodes[node].want_block(id),
+                    Ok(Action::Noop) => {}
+                    Err(e) => self.nodes[node].error(e),
+                }
+            }
+            RequestResponse(items) => {
+                let (maybe_id, maybe_error) = self.nodes[node].handler.handle_request_response(
+                    items,
+                    peer_id,
+                    &CancelToken::new(),
+                );
+                self.nodes[node].want(maybe_id);
+                if let Some(e) = maybe_error {
+                    self.nodes[node].error(e);
+                }
+            }
+            // The simulated nodes only speak the basic protocol.
+            _ => {}
+        }
+        self.nodes[node].handle_notifications();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{simulation, Fate, LinkConditions, Scripted::*, Simulation, TICK};
+    use crate::{
+        sync::{mock::MockHeader, ChainStatusNotifier},
+        BlockIdentifier,
+    };
+
+    const LATENCY: Duration = Duration::from_millis(50);
+
+    fn produce_and_justify<N: ChainStatusNotifier<MockHeader>>(
+        simulation: &mut Simulation<N>,
+        node: usize,
+        blocks: u32,
+        from: Duration,
+    ) -> Duration {
+        for block in 0..blocks {
+            simulation.schedule(from + TICK * block, Produce(node));
+        }
+        let justified_at = from + TICK * blocks;
+        simulation.schedule(justified_at, Justify(node));
+        justified_at
+    }
+
+    #[test]
+    fn catches_up_over_the_network() {
+        let mut simulation = simulation(4, LinkConditions::reliable(LATENCY), 0);
+        let justified_at = produce_and_justify(&mut simulation, 0, 5, Duration::ZERO);
+        simulation.run_until(justified_at + TICK * 20);
+        let finalized = simulation.top_finalized(0);
+        assert_eq!(finalized.number(), 5);
+        for node in 1..4 {
+            assert_eq!(simulation.top_finalized(node), finalized);
+            assert_eq!(simulation.errors(node), 0);
+        }
+    }
+
+    #[test]
+    fn catches_up_despite_loss_and_reordering() {
+        let conditions = LinkConditions {
+            latency: LATENCY,
+            jitter: LATENCY * 4,
+            loss: 0.2,
+        };
+        let mut simulation = simulation(4, conditions, 3);
+        // One of the nodes only hears from the producer very late.
+        simulation.schedule(
+            Duration::ZERO,
+            SetLink(0, 3, LinkConditions::reliable(TICK * 10)),
+        );
+        let justified_at = produce_and_justify(&mut simulation, 0, 10, Duration::ZERO);
+        simulation.run_until(justified_at + TICK * 100);
+        let finalized = simulation.top_finalized(0);
+        assert_eq!(finalized.number(), 10);
+        for node in 1..4 {
+            assert_eq!(simulation.top_finalized(node), finalized);
+        }
+    }
+
+    fn forked_simulation(seed: u64) -> Simulation<impl ChainStatusNotifier<MockHeader>> {
+        let mut simulation = simulation(4, LinkConditions::reliable(LATENCY), seed);
+        simulation.schedule(Duration::ZERO, Partition(vec![vec![0, 1], vec![2, 3]]));
+        // The longer fork loses, as the other one gets justified.
+        produce_and_justify(&mut simulation, 0, 3, TICK);
+        for block in 0..6 {
+            simulation.schedule(TICK * (block + 1), Produce(2));
+        }
+        simulation.schedule(TICK * 10, Heal);
+        simulation.run_until(TICK * 40);
+        simulation
+    }
+
+    #[test]
+    fn resolves_forks_after_partitions() {
+        let simulation = forked_simulation(0);
+        let finalized = simulation.top_finalized(0);
+        assert_eq!(finalized.number(), 3);
+        for node in 1..4 {
+            assert_eq!(simulation.top_finalized(node), finalized);
+        }
+        assert!(simulation.network().count(Fate::Partitioned) > 0);
+    }
+
+    #[test]
+    fn replays_deterministically() {
+        let first = forked_simulation(5);
+        let second = forked_simulation(5);
+        assert_eq!(first.network().trace(), second.network().trace());
+        for node in 0..4 {
+            assert_eq!(first.top_finalized(node), second.top_finalized(node));
+            assert_eq!(first.best(node), second.best(node));
+        }
+    }
+}
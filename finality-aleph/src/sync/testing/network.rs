@@ -0,0 +1,298 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap},
+    time::Duration,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// How a link between two nodes treats the messages sent over it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkConditions {
+    /// How long every message takes at least.
+    pub latency: Duration,
+    /// Up to how much longer a message can take, so messages sent close together can overtake
+    /// each other.
+    pub jitter: Duration,
+    /// The chance of a message getting lost, between zero and one.
+    pub loss: f64,
+}
+
+impl LinkConditions {
+    /// A link delivering everything, in order, after the latency.
+    pub fn reliable(latency: Duration) -> Self {
+        LinkConditions {
+            latency,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+        }
+    }
+}
+
+/// What happened to a message sent over the network.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fate {
+    Delivered,
+    /// The link lost it.
+    Lost,
+    /// The nodes were in different parts of the network when it was sent or would have arrived.
+    Partitioned,
+}
+
+/// A single entry of the trace of the network.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// When the message was delivered or dropped.
+    pub at: Duration,
+    pub from: usize,
+    pub to: usize,
+    pub fate: Fate,
+}
+
+struct Envelope<D> {
+    at: Duration,
+    /// Breaks ties between messages arriving at the same time, in the order they were sent.
+    sequence: u64,
+    from: usize,
+    to: usize,
+    data: D,
+}
+
+impl<D> PartialEq for Envelope<D> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.sequence) == (other.at, other.sequence)
+    }
+}
+
+impl<D> Eq for Envelope<D> {}
+
+impl<D> PartialOrd for Envelope<D> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<D> Ord for Envelope<D> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.sequence).cmp(&(other.at, other.sequence))
+    }
+}
+
+/// An in-memory network between a fixed number of nodes, in virtual time. Whether the messages
+/// get delivered, and when, is decided by the conditions of the links and a seeded random number
+/// generator, so the same sequence of sends always leads to the same deliveries.
+pub struct SimulatedNetwork<D> {
+    nodes: usize,
+    default_conditions: LinkConditions,
+    links: HashMap<(usize, usize), LinkConditions>,
+    /// The part of the network every node is in, messages only go within parts.
+    parts: Vec<usize>,
+    in_flight: BinaryHeap<Reverse<Envelope<D>>>,
+    sequence: u64,
+    now: Duration,
+    rng: StdRng,
+    trace: Vec<TraceEntry>,
+}
+
+impl<D> SimulatedNetwork<D> {
+    pub fn new(nodes: usize, default_conditions: LinkConditions, seed: u64) -> Self {
+        SimulatedNetwork {
+            nodes,
+            default_conditions,
+            links: HashMap::new(),
+            parts: vec![0; nodes],
+            in_flight: BinaryHeap::new(),
+            sequence: 0,
+            now: Duration::ZERO,
+            rng: StdRng::seed_from_u64(seed),
+            trace: Vec::new(),
+        }
+    }
+
+    /// The virtual time since the start of the simulation.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Changes the conditions of the link from one node to the other, only in that direction.
+    pub fn set_link(&mut self, from: usize, to: usize, conditions: LinkConditions) {
+        self.links.insert((from, to), conditions);
+    }
+
+    /// Splits the network into parts, the nodes not in any of them form one more part. Messages
+    /// already in flight between the parts get dropped when they would have arrived.
+    pub fn partition(&mut self, parts: &[&[usize]]) {
+        self.parts = vec![parts.len(); self.nodes];
+        for (part, nodes) in parts.iter().enumerate() {
+            for node in nodes.iter() {
+                self.parts[*node] = part;
+            }
+        }
+    }
+
+    /// Joins all the parts of the network back together.
+    pub fn heal(&mut self) {
+        self.parts = vec![0; self.nodes];
+    }
+
+    pub fn connected(&self, from: usize, to: usize) -> bool {
+        from != to && self.parts[from] == self.parts[to]
+    }
+
+    fn conditions(&self, from: usize, to: usize) -> LinkConditions {
+        self.links
+            .get(&(from, to))
+            .copied()
+            .unwrap_or(self.default_conditions)
+    }
+
+    pub fn send(&mut self, from: usize, to: usize, data: D) {
+        if !self.connected(from, to) {
+            return self.record(from, to, Fate::Partitioned);
+        }
+        let conditions = self.conditions(from, to);
+        if conditions.loss > 0.0 && self.rng.gen_bool(conditions.loss.min(1.0)) {
+            return self.record(from, to, Fate::Lost);
+        }
+        let jitter = match conditions.jitter.is_zero() {
+            true => Duration::ZERO,
+            false => self.rng.gen_range(Duration::ZERO..=conditions.jitter),
+        };
+        self.sequence += 1;
+        self.in_flight.push(Reverse(Envelope {
+            at: self.now + conditions.latency + jitter,
+            sequence: self.sequence,
+            from,
+            to,
+            data,
+        }));
+    }
+
+    fn record(&mut self, from: usize, to: usize, fate: Fate) {
+        self.trace.push(TraceEntry {
+            at: self.now,
+            from,
+            to,
+            fate,
+        });
+    }
+
+    /// When the next message arrives, if any are in flight.
+    pub fn next_arrival(&self) -> Option<Duration> {
+        self.in_flight.peek().map(|Reverse(envelope)| envelope.at)
+    }
+
+    /// Moves the virtual time forward, never back.
+    pub fn advance_to(&mut self, time: Duration) {
+        self.now = self.now.max(time);
+    }
+
+    /// The next message arriving no later than now, with the nodes it goes from and to.
+    pub fn deliver(&mut self) -> Option<(usize, usize, D)> {
+        while self.next_arrival()? <= self.now {
+            let Reverse(envelope) = self.in_flight.pop()?;
+            if !self.connected(envelope.from, envelope.to) {
+                self.record(envelope.from, envelope.to, Fate::Partitioned);
+                continue;
+            }
+            self.record(envelope.from, envelope.to, Fate::Delivered);
+            return Some((envelope.from, envelope.to, envelope.data));
+        }
+        None
+    }
+
+    /// Everything that happened to the messages so far, in order.
+    pub fn trace(&self) -> &[TraceEntry] {
+        &self.trace
+    }
+
+    /// How many messages met the fate so far.
+    pub fn count(&self, fate: Fate) -> usize {
+        self.trace.iter().filter(|entry| entry.fate == fate).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Fate, LinkConditions, SimulatedNetwork};
+
+    const LATENCY: Duration = Duration::from_millis(50);
+
+    fn deliveries(network: &mut SimulatedNetwork<u32>) -> Vec<u32> {
+        let mut delivered = Vec::new();
+        while let Some(arrival) = network.next_arrival() {
+            network.advance_to(arrival);
+            while let Some((_, _, data)) = network.deliver() {
+                delivered.push(data);
+            }
+        }
+        delivered
+    }
+
+    #[test]
+    fn delivers_after_latency() {
+        let mut network = SimulatedNetwork::new(2, LinkConditions::reliable(LATENCY), 0);
+        network.send(0, 1, 7);
+        network.send(0, 1, 8);
+        assert_eq!(network.next_arrival(), Some(LATENCY));
+        assert_eq!(network.deliver(), None);
+        network.advance_to(LATENCY);
+        assert_eq!(network.deliver(), Some((0, 1, 7)));
+        assert_eq!(network.deliver(), Some((0, 1, 8)));
+        assert_eq!(network.deliver(), None);
+    }
+
+    #[test]
+    fn reorders_and_loses_deterministically() {
+        let conditions = LinkConditions {
+            latency: LATENCY,
+            jitter: LATENCY * 4,
+            loss: 0.3,
+        };
+        let run = |seed| {
+            let mut network = SimulatedNetwork::new(2, conditions, seed);
+            for data in 0..100 {
+                network.send(0, 1, data);
+                network.advance_to(network.now() + Duration::from_millis(1));
+            }
+            (deliveries(&mut network), network.trace().to_vec())
+        };
+        let (delivered, trace) = run(7);
+        assert_eq!((delivered.clone(), trace), run(7));
+        assert!(delivered.len() < 100, "should lose some");
+        assert!(delivered.len() > 40, "should deliver most");
+        assert!(
+            delivered.windows(2).any(|pair| pair[0] > pair[1]),
+            "should reorder some"
+        );
+    }
+
+    #[test]
+    fn drops_messages_across_partitions() {
+        let mut network = SimulatedNetwork::new(3, LinkConditions::reliable(LATENCY), 0);
+        network.send(0, 1, 1);
+        network.partition(&[&[0]]);
+        network.send(0, 2, 2);
+        network.send(1, 2, 3);
+        assert_eq!(deliveries(&mut network), vec![3]);
+        assert_eq!(network.count(Fate::Partitioned), 2);
+        network.heal();
+        network.send(0, 2, 4);
+        assert_eq!(deliveries(&mut network), vec![4]);
+        // Asymmetric conditions only affect one direction.
+        network.set_link(
+            1,
+            0,
+            LinkConditions {
+                loss: 1.0,
+                ..LinkConditions::reliable(LATENCY)
+            },
+        );
+        network.send(1, 0, 5);
+        network.send(0, 1, 6);
+        assert_eq!(deliveries(&mut network), vec![6]);
+        assert_eq!(network.count(Fate::Lost), 1);
+    }
+}
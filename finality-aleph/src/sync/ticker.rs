@@ -65,6 +65,13 @@ impl Ticker {
         }
     }
 
+    /// Replaces the timeouts, still timing from the last reset. Enforces `max_timeout` >=
+    /// `min_timeout`.
+    pub fn set_timeouts(&mut self, max_timeout: Duration, min_timeout: Duration) {
+        self.max_timeout = max_timeout.max(min_timeout);
+        self.min_timeout = min_timeout;
+    }
+
     /// Reset the ticker, making it time from the moment of this call.
     /// Behaves as if it was just created with the same parametres.
     pub fn reset(&mut self) {
@@ -171,6 +178,14 @@ mod tests {
         assert!(!ticker.try_tick());
     }
 
+    #[tokio::test]
+    async fn wait_after_set_timeouts() {
+        let mut ticker = setup_ticker();
+
+        ticker.set_timeouts(MIN_TIMEOUT_PLUS, MIN_TIMEOUT);
+        assert_eq!(timeout(MAX_TIMEOUT, ticker.wait_and_tick()).await, Ok(true));
+    }
+
     #[tokio::test]
     async fn wait_after_late_reset() {
         let mut ticker = setup_ticker();
@@ -0,0 +1,269 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sync::{
+        data::{NetworkData, NetworkDataV1, ResponseItem, MAX_BATCHED_JUSTIFICATIONS},
+        Block, Header, Justification, PeerId,
+    },
+    BlockIdentifier,
+};
+
+/// The features of the newer versions of the sync protocol that can be translated into
+/// sequences of messages of the first one.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShimFeature {
+    /// Responses carrying headers or blocks are split into responses with only the
+    /// justifications, followed by a state response with the highest of them, announcing it, so
+    /// that the peer gets the blocks another way.
+    BlockResponses,
+    /// Batched state responses are sent as several state responses, instead of only the first
+    /// two justifications.
+    BatchedStateResponses,
+    /// Warp responses are sent as responses with the justifications.
+    WarpResponses,
+    /// Responses identifying the requests they answer are sent as plain responses.
+    CorrelatedResponses,
+}
+
+/// The peers on forks that will never understand anything but the first version of the sync
+/// protocol, for bridged deployments. They only ever get data in the first version, also after
+/// the legacy cutoff, with the configured features translated into sequences of messages of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct V1Shim<I: PeerId> {
+    peers: HashMap<I, HashSet<ShimFeature>>,
+}
+
+impl<I: PeerId> V1Shim<I> {
+    pub fn new() -> Self {
+        V1Shim {
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Treats the peer as understanding only the first version, translating the features for it.
+    pub fn add_peer<F: IntoIterator<Item = ShimFeature>>(&mut self, peer: I, features: F) {
+        self.peers.insert(peer, features.into_iter().collect());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Whether the peer only ever gets data in the first version.
+    pub fn is_shimmed(&self, peer: &I) -> bool {
+        self.peers.contains_key(peer)
+    }
+
+    fn translates(&self, peer: &I, feature: ShimFeature) -> bool {
+        self.peers
+            .get(peer)
+            .map_or(false, |features| features.contains(&feature))
+    }
+
+    /// The messages of the first version to send the peer instead of the data, none if the data
+    /// has no equivalent there.
+    pub fn translate<B, J>(&self, data: &NetworkData<B, J>, peer: &I) -> Vec<NetworkDataV1<J>>
+    where
+        B: Block,
+        J: Justification<Header = B::Header>,
+    {
+        use ShimFeature::*;
+        match data {
+            NetworkData::RequestResponse(response_items)
+                if self.translates(peer, BlockResponses)
+                    && response_items
+                        .iter()
+                        .any(|item| !matches!(item, ResponseItem::Justification(_))) =>
+            {
+                let justifications =
+                    ResponseItem::justifications_from_response_items(response_items);
+                let highest = justifications
+                    .iter()
+                    .max_by_key(|justification| justification.id().number())
+                    .cloned();
+                let mut messages = justification_responses(justifications);
+                if let Some(highest) = highest {
+                    messages.push(NetworkDataV1::StateBroadcastResponse(highest, None));
+                }
+                messages
+            }
+            NetworkData::BatchedStateBroadcastResponse(justification, justifications)
+                if self.translates(peer, BatchedStateResponses) =>
+            {
+                let mut messages = vec![NetworkDataV1::StateBroadcastResponse(
+                    justification.clone(),
+                    justifications.first().cloned(),
+                )];
+                for pair in justifications.iter().skip(1).collect::<Vec<_>>().chunks(2) {
+                    messages.push(NetworkDataV1::StateBroadcastResponse(
+                        pair[0].clone(),
+                        pair.get(1).map(|justification| (*justification).clone()),
+                    ));
+                }
+                messages
+            }
+            NetworkData::WarpResponse(justifications) if self.translates(peer, WarpResponses) => {
+                justification_responses(justifications.clone())
+            }
+            NetworkData::CorrelatedResponse(_, response_items)
+                if self.translates(peer, CorrelatedResponses) =>
+            {
+                self.translate(&NetworkData::RequestResponse(response_items.clone()), peer)
+            }
+            data => NetworkDataV1::try_from(data).into_iter().collect(),
+        }
+    }
+}
+
+impl<I: PeerId> Default for V1Shim<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The justifications in responses of the first version, small enough for any of its nodes.
+fn justification_responses<J: Justification>(
+    justifications: Vec<J::Unverified>,
+) -> Vec<NetworkDataV1<J>> {
+    justifications
+        .chunks(MAX_BATCHED_JUSTIFICATIONS)
+        .map(|chunk| NetworkDataV1::RequestResponse(chunk.to_vec()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ShimFeature::*, V1Shim};
+    use crate::sync::{
+        data::{NetworkData, NetworkDataV1, ResponseItem, MAX_BATCHED_JUSTIFICATIONS},
+        mock::{MockBlock, MockHeader, MockJustification},
+        Header, Justification,
+    };
+
+    type Data = NetworkData<MockBlock, MockJustification>;
+
+    fn justifications(count: usize) -> Vec<MockJustification> {
+        MockHeader::random_parentless(0)
+            .random_branch()
+            .take(count)
+            .map(MockJustification::for_header)
+            .collect()
+    }
+
+    fn numbers(justifications: &[MockJustification]) -> Vec<u32> {
+        justifications
+            .iter()
+            .map(|justification| justification.id().number())
+            .collect()
+    }
+
+    fn shim(features: Vec<super::ShimFeature>) -> V1Shim<u32> {
+        let mut shim = V1Shim::new();
+        shim.add_peer(1, features);
+        shim
+    }
+
+    #[test]
+    fn splits_block_responses() {
+        let justifications = justifications(MAX_BATCHED_JUSTIFICATIONS + 1);
+        let mut response_items: Vec<_> = justifications
+            .iter()
+            .cloned()
+            .map(ResponseItem::Justification)
+            .collect();
+        response_items.push(ResponseItem::Block(MockBlock::new(
+            justifications[0].header().clone(),
+            true,
+        )));
+        let data = Data::RequestResponse(response_items);
+        let shim = shim(vec![BlockResponses]);
+
+        let messages = shim.translate(&data, &1);
+        assert_eq!(messages.len(), 3);
+        match (&messages[0], &messages[1], &messages[2]) {
+            (
+                NetworkDataV1::RequestResponse(first),
+                NetworkDataV1::RequestResponse(second),
+                NetworkDataV1::StateBroadcastResponse(highest, None),
+            ) => {
+                assert_eq!(first.len(), MAX_BATCHED_JUSTIFICATIONS);
+                assert_eq!(
+                    numbers(second),
+                    numbers(&justifications[MAX_BATCHED_JUSTIFICATIONS..])
+                );
+                assert_eq!(
+                    highest.id(),
+                    justifications[MAX_BATCHED_JUSTIFICATIONS].id()
+                );
+            }
+            other => panic!("unexpected translation {other:?}"),
+        }
+
+        // Without the feature, or for other peers, only the usual equivalent is sent.
+        for (shim, peer) in [(V1Shim::new(), 1), (shim, 2)] {
+            match &shim.translate(&data, &peer)[..] {
+                [NetworkDataV1::RequestResponse(all)] => {
+                    assert_eq!(all.len(), MAX_BATCHED_JUSTIFICATIONS + 1)
+                }
+                other => panic!("unexpected translation {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn translates_batched_warp_and_correlated_responses() {
+        let justifications = justifications(6);
+        let shim = shim(vec![
+            BatchedStateResponses,
+            WarpResponses,
+            CorrelatedResponses,
+        ]);
+
+        let batched = Data::BatchedStateBroadcastResponse(
+            justifications[0].clone(),
+            justifications[1..].to_vec(),
+        );
+        let pairs: Vec<_> = shim
+            .translate(&batched, &1)
+            .into_iter()
+            .map(|message| match message {
+                NetworkDataV1::StateBroadcastResponse(justification, maybe_justification) => (
+                    justification.id().number(),
+                    maybe_justification.map(|justification| justification.id().number()),
+                ),
+                other => panic!("unexpected message {other:?}"),
+            })
+            .collect();
+        let expected: Vec<_> = numbers(&justifications);
+        assert_eq!(
+            pairs,
+            vec![
+                (expected[0], Some(expected[1])),
+                (expected[2], Some(expected[3])),
+                (expected[4], Some(expected[5])),
+            ]
+        );
+
+        let warp = Data::WarpResponse(justifications.clone());
+        match &shim.translate(&warp, &1)[..] {
+            [NetworkDataV1::RequestResponse(all)] => assert_eq!(numbers(all), expected),
+            other => panic!("unexpected translation {other:?}"),
+        }
+        assert!(V1Shim::<u32>::new().translate(&warp, &1).is_empty());
+
+        let correlated = Data::CorrelatedResponse(
+            7,
+            justifications
+                .into_iter()
+                .map(ResponseItem::Justification)
+                .collect(),
+        );
+        match &shim.translate(&correlated, &1)[..] {
+            [NetworkDataV1::RequestResponse(all)] => assert_eq!(numbers(all), expected),
+            other => panic!("unexpected translation {other:?}"),
+        }
+    }
+}
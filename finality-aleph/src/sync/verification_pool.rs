@@ -0,0 +1,100 @@
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+use log::warn;
+use tokio::task::spawn_blocking;
+
+use crate::sync::{OffloadedCheck, LOG_TARGET};
+
+/// How many batches of checks run on the blocking threads at once, at most.
+const MAX_RUNNING_BATCHES: usize = 4;
+
+/// Runs the offloaded checks of batches of justifications on the blocking threads, handing the
+/// batches back together with the results of their checks, in the order they finish in.
+pub struct VerificationPool<T: Send + 'static> {
+    running: FuturesUnordered<BoxFuture<'static, (T, Vec<bool>)>>,
+}
+
+impl<T: Send + 'static> VerificationPool<T> {
+    pub fn new() -> Self {
+        VerificationPool {
+            running: FuturesUnordered::new(),
+        }
+    }
+
+    /// Whether another batch would exceed the bound, it should be verified in place then.
+    pub fn is_full(&self) -> bool {
+        self.running.len() >= MAX_RUNNING_BATCHES
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    /// Starts running the checks of the batch, the batch comes back once they all finish. Checks
+    /// that could not run count as failed, the batch gets verified in place then.
+    pub fn submit(&mut self, batch: T, checks: Vec<OffloadedCheck>) {
+        let handle = spawn_blocking(move || checks.into_iter().map(|check| check()).collect());
+        self.running.push(
+            async move {
+                let results = handle.await.unwrap_or_else(|e| {
+                    warn!(target: LOG_TARGET, "Offloaded verification failed: {}.", e);
+                    Vec::new()
+                });
+                (batch, results)
+            }
+            .boxed(),
+        );
+    }
+
+    /// The next batch whose checks finished with their results, `None` if none are running.
+    pub async fn next(&mut self) -> Option<(T, Vec<bool>)> {
+        self.running.next().await
+    }
+}
+
+impl<T: Send + 'static> Default for VerificationPool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use super::{VerificationPool, MAX_RUNNING_BATCHES};
+    use crate::sync::OffloadedCheck;
+
+    fn check(result: bool) -> OffloadedCheck {
+        Box::new(move || result)
+    }
+
+    #[tokio::test]
+    async fn returns_batches_as_they_finish() {
+        let mut pool = VerificationPool::new();
+        assert!(pool.is_empty());
+        let (release, released) = mpsc::channel::<()>();
+        let slow: OffloadedCheck = Box::new(move || released.recv().is_ok());
+        pool.submit("slow", vec![slow]);
+        pool.submit("fast", vec![check(true), check(false)]);
+        assert_eq!(pool.next().await, Some(("fast", vec![true, false])));
+        release.send(()).expect("the check is waiting");
+        assert_eq!(pool.next().await, Some(("slow", vec![true])));
+        assert!(pool.is_empty());
+        assert_eq!(pool.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn is_bounded() {
+        let mut pool = VerificationPool::new();
+        for batch in 0..MAX_RUNNING_BATCHES {
+            assert!(!pool.is_full());
+            pool.submit(batch, vec![check(true)]);
+        }
+        assert!(pool.is_full());
+        let _ = tokio::time::timeout(Duration::from_secs(5), pool.next()).await;
+        assert!(!pool.is_full());
+    }
+}
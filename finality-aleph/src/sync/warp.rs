@@ -0,0 +1,120 @@
+use std::{
+    cmp::max,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    session::{SessionBoundaryInfo, SessionId},
+    BlockIdentifier, BlockNumber,
+};
+
+/// How many sessions ahead of us the network has to be for warping to be worth it.
+const WARP_DISTANCE: u32 = 2;
+/// How long we wait for a warp response before asking again, possibly someone else.
+const WARP_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Decides when to ask for the chain of justifications ending sessions and from which session,
+/// so that the authorities of all the sessions up to the head of the network get learned before
+/// the blocks are there.
+pub struct WarpSync<BI: BlockIdentifier> {
+    session_info: SessionBoundaryInfo,
+    highest_verified: Option<BI>,
+    last_request: Option<Instant>,
+}
+
+impl<BI: BlockIdentifier> WarpSync<BI> {
+    pub fn new(session_info: SessionBoundaryInfo) -> Self {
+        WarpSync {
+            session_info,
+            highest_verified: None,
+            last_request: None,
+        }
+    }
+
+    /// The session the chain of justifications should start with, if it should be requested now.
+    /// The first session we do not know the end of is the one after the highest verified block,
+    /// or our highest finalized one, if it ends a session.
+    pub fn next_request(
+        &mut self,
+        our_finalized: BlockNumber,
+        network_finalized: BlockNumber,
+        now: Instant,
+    ) -> Option<SessionId> {
+        let known = max(
+            our_finalized,
+            self.highest_verified.as_ref().map_or(0, |id| id.number()),
+        );
+        let known_session = self.session_info.session_id_from_block_num(known);
+        let network_session = self
+            .session_info
+            .session_id_from_block_num(network_finalized);
+        if network_session.0 < known_session.0.saturating_add(WARP_DISTANCE) {
+            return None;
+        }
+        if let Some(last_request) = self.last_request {
+            if now.saturating_duration_since(last_request) < WARP_REQUEST_TIMEOUT {
+                return None;
+            }
+        }
+        self.last_request = Some(now);
+        Some(
+            match self.session_info.last_block_of_session(known_session) == known {
+                true => known_session.next(),
+                false => known_session,
+            },
+        )
+    }
+
+    /// Records the highest justification verified from a warp response, so that the next
+    /// request continues right after it, without waiting.
+    pub fn verified(&mut self, id: BI) {
+        if self
+            .highest_verified
+            .as_ref()
+            .map_or(true, |highest| highest.number() < id.number())
+        {
+            self.highest_verified = Some(id);
+            self.last_request = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{WarpSync, WARP_REQUEST_TIMEOUT};
+    use crate::{
+        session::{testing::session_boundary_info, SessionId},
+        sync::mock::MockIdentifier,
+    };
+
+    #[test]
+    fn requests_only_when_far_behind() {
+        let mut warp = WarpSync::<MockIdentifier>::new(session_boundary_info());
+        let now = Instant::now();
+        assert_eq!(warp.next_request(5, 30, now), None);
+        assert_eq!(warp.next_request(5, 45, now), Some(SessionId(0)));
+        // Still waiting for the response.
+        assert_eq!(warp.next_request(5, 45, now + Duration::from_secs(1)), None);
+        assert_eq!(
+            warp.next_request(5, 45, now + WARP_REQUEST_TIMEOUT),
+            Some(SessionId(0))
+        );
+    }
+
+    #[test]
+    fn continues_after_verified() {
+        let mut warp = WarpSync::<MockIdentifier>::new(session_boundary_info());
+        let now = Instant::now();
+        assert_eq!(warp.next_request(19, 200, now), Some(SessionId(1)));
+        warp.verified(MockIdentifier::new_random(79));
+        assert_eq!(warp.next_request(19, 200, now), Some(SessionId(4)));
+        // Lower ones change nothing.
+        warp.verified(MockIdentifier::new_random(59));
+        assert_eq!(warp.next_request(19, 200, now), None);
+        // Close enough to the network.
+        warp.verified(MockIdentifier::new_random(179));
+        assert_eq!(warp.next_request(19, 190, now), None);
+    }
+}
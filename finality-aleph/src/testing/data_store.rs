@@ -12,6 +12,7 @@ use sp_runtime::traits::Block as BlockT;
 use tokio::time::timeout;
 
 use crate::{
+    abft::AbftMetrics,
     aleph_primitives::BlockNumber,
     data_io::{AlephData, AlephNetworkMessage, DataStore, DataStoreConfig, MAX_DATA_BRANCH_LEN},
     network::{
@@ -160,6 +161,7 @@ fn prepare_data_store(
         available_proposals_cache_capacity: NonZeroUsize::new(8000).unwrap(),
         periodic_maintenance_interval: Duration::from_millis(20),
         request_block_after: Duration::from_millis(30),
+        finalization_depth_offset: Default::default(),
     };
 
     let session_boundaries = if let Some(session_boundaries) = session_boundaries {
@@ -173,6 +175,7 @@ fn prepare_data_store(
         block_requester,
         data_store_config,
         test_network,
+        AbftMetrics::noop(),
     );
 
     let chain_builder = ClientChainBuilder::new(client, Arc::new(TestClientBuilder::new().build()));
@@ -0,0 +1,34 @@
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// A fresh path in the temporary directory, removed with everything in it when dropped. The
+/// directory itself is not created, so that the code under test can do it.
+pub struct TestDirectory {
+    path: PathBuf,
+}
+
+impl TestDirectory {
+    pub fn new(name: &str) -> Self {
+        TestDirectory {
+            path: std::env::temp_dir().join(format!("aleph-{name}-test-{}", rand::random::<u64>())),
+        }
+    }
+}
+
+impl Deref for TestDirectory {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestDirectory {
+    fn drop(&mut self) {
+        // The directory might never have been created.
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
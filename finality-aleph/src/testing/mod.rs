@@ -1,4 +1,7 @@
 pub mod client_chain_builder;
 mod data_store;
+mod directory;
 pub mod mocks;
 mod network;
+
+pub use directory::TestDirectory;
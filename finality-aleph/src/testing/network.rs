@@ -25,7 +25,8 @@ use crate::{
             authentication, ConnectionManager, ConnectionManagerConfig, DataInSession,
             ManagerError, SessionHandler, SessionManager, VersionedAuthentication,
         },
-        GossipError, GossipNetwork, GossipService, MockEvent, MockRawNetwork, Protocol,
+        GossipError, GossipNetwork, GossipService, MockEvent, MockRawNetwork, PeerIdentities,
+        Protocol, PublicNetworkLimits,
     },
     MillisecsPerBlock, NodeIndex, Recipient, SessionId, SessionPeriod,
 };
@@ -104,6 +105,8 @@ async fn prepare_one_session_test_data() -> TestData {
         network.clone(),
         task_manager.spawn_handle().into(),
         None,
+        None,
+        PublicNetworkLimits::default(),
     );
 
     let (connection_manager_service, session_manager) = ConnectionManager::new(
@@ -111,6 +114,7 @@ async fn prepare_one_session_test_data() -> TestData {
         validator_network.clone(),
         gossip_network,
         ConnectionManagerConfig::with_session_period(&SESSION_PERIOD, &MILLISECS_PER_BLOCK),
+        PeerIdentities::new(),
     );
     let session_manager = Box::new(session_manager);
     let sync_network = Box::new(sync_network);
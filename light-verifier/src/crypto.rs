@@ -0,0 +1,89 @@
+use aleph_bft_crypto::SignatureSet;
+use parity_scale_codec::{Decode, Encode};
+use primitives::{AuthorityId, AuthoritySignature};
+use sp_application_crypto::RuntimeAppPublic;
+
+/// A signature of a single authority, as gathered in committee multisignatures.
+#[derive(PartialEq, Eq, Clone, Debug, Hash, Decode, Encode)]
+pub struct Signature(AuthoritySignature);
+
+impl From<AuthoritySignature> for Signature {
+    fn from(authority_signature: AuthoritySignature) -> Signature {
+        Signature(authority_signature)
+    }
+}
+
+impl Signature {
+    /// Whether this is a signature of the message made by the authority.
+    pub fn verify(&self, authority: &AuthorityId, message: &[u8]) -> bool {
+        authority.verify(&message, &self.0)
+    }
+}
+
+/// Whether more than two thirds of the authorities signed the message, and all the signatures
+/// are correct.
+pub fn is_complete(
+    authorities: &[AuthorityId],
+    message: &[u8],
+    partial: &SignatureSet<Signature>,
+) -> bool {
+    let threshold = 2 * authorities.len() / 3 + 1;
+    if partial.iter().count() < threshold {
+        return false;
+    }
+    partial
+        .iter()
+        .all(|(index, signature)| match authorities.get(index.0) {
+            Some(authority) => signature.verify(authority, message),
+            None => false,
+        })
+}
+
+/// Old format of signatures, which unnecessarily contained the index of the signer.
+#[derive(PartialEq, Eq, Clone, Debug, Decode, Encode)]
+pub(crate) struct SignatureV1 {
+    pub _id: u64,
+    pub sgn: AuthoritySignature,
+}
+
+impl From<SignatureV1> for Signature {
+    fn from(sig_v1: SignatureV1) -> Signature {
+        Signature(sig_v1.sgn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aleph_bft_crypto::{NodeCount, NodeIndex, PartialMultisignature, SignatureSet};
+    use primitives::AuthorityPair;
+    use sp_core::Pair;
+
+    use super::{is_complete, Signature};
+
+    #[test]
+    fn requires_more_than_two_thirds_of_correct_signatures() {
+        let pairs: Vec<_> = (0..4).map(|_| AuthorityPair::generate().0).collect();
+        let authorities: Vec<_> = pairs.iter().map(|pair| pair.public()).collect();
+        let message = b"message";
+        let signatures = |signers: &[usize]| {
+            signers.iter().fold(
+                SignatureSet::with_size(NodeCount(pairs.len())),
+                |signatures: SignatureSet<Signature>, index| {
+                    signatures.add_signature(&pairs[*index].sign(message).into(), NodeIndex(*index))
+                },
+            )
+        };
+        assert!(!is_complete(&authorities, message, &signatures(&[0, 1])));
+        assert!(is_complete(&authorities, message, &signatures(&[0, 1, 2])));
+        assert!(!is_complete(
+            &authorities[..3],
+            message,
+            &signatures(&[0, 1, 3])
+        ));
+        assert!(!is_complete(
+            &authorities,
+            b"other",
+            &signatures(&[0, 1, 2])
+        ));
+    }
+}
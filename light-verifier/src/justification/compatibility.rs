@@ -3,16 +3,16 @@ use std::{
     mem::size_of,
 };
 
+use aleph_bft_crypto::{PartialMultisignature, SignatureSet};
 use log::warn;
 use parity_scale_codec::{Decode, DecodeAll, Encode, Error as CodecError, Input as CodecInput};
 
 use crate::{
-    abft::SignatureSet,
     crypto::{Signature, SignatureV1},
     justification::{AlephJustification, LOG_TARGET},
-    Version,
 };
 
+type Version = u16;
 type ByteCount = u16;
 
 /// Old format of justifications, needed for backwards compatibility.
@@ -104,9 +104,9 @@ impl Encode for VersionedAlephJustification {
         use VersionedAlephJustification::*;
         match self {
             Other(version, payload) => encode_with_version(*version, payload),
-            V1(justification) => encode_with_version(Version(1), &justification.encode()),
-            V2(justification) => encode_with_version(Version(2), &justification.encode()),
-            V3(justification) => encode_with_version(Version(3), &justification.encode()),
+            V1(justification) => encode_with_version(1, &justification.encode()),
+            V2(justification) => encode_with_version(2, &justification.encode()),
+            V3(justification) => encode_with_version(3, &justification.encode()),
         }
     }
 }
@@ -117,9 +117,9 @@ impl Decode for VersionedAlephJustification {
         let version = Version::decode(input)?;
         let num_bytes = ByteCount::decode(input)?;
         match version {
-            Version(1) => Ok(V1(AlephJustificationV1::decode(input)?)),
-            Version(2) => Ok(V2(AlephJustificationV2::decode(input)?)),
-            Version(3) => Ok(V3(AlephJustification::decode(input)?)),
+            1 => Ok(V1(AlephJustificationV1::decode(input)?)),
+            2 => Ok(V2(AlephJustificationV2::decode(input)?)),
+            3 => Ok(V3(AlephJustification::decode(input)?)),
             _ => {
                 let mut payload = vec![0; num_bytes.into()];
                 input.read(payload.as_mut_slice())?;
@@ -141,11 +141,7 @@ impl Display for Error {
         match self {
             BadFormat => write!(f, "malformed encoding"),
             UnknownVersion(version) => {
-                write!(
-                    f,
-                    "justification encoded with unknown version {}",
-                    version.0
-                )
+                write!(f, "justification encoded with unknown version {version}")
             }
         }
     }
@@ -203,7 +199,9 @@ pub fn versioned_encode(justification: AlephJustification) -> Vec<u8> {
 
 #[cfg(test)]
 mod test {
+    use aleph_bft_crypto::{NodeCount, NodeIndex, PartialMultisignature, SignatureSet};
     use parity_scale_codec::{Decode, Encode};
+    use primitives::{AuthorityPair, AuthoritySignature};
     use sp_core::Pair;
 
     use super::{
@@ -211,19 +209,17 @@ mod test {
         VersionedAlephJustification,
     };
     use crate::{
-        aleph_primitives::{AuthorityPair, AuthoritySignature},
         crypto::{Signature, SignatureV1},
-        justification::AlephJustification,
-        NodeCount, SignatureSet, Version,
+        justification::{AlephJustification, EmergencyCustody},
     };
 
     #[test]
     fn correctly_decodes_v1() {
-        let mut signature_set: SignatureSet<SignatureV1> = SignatureSet::with_size(7.into());
+        let mut signature_set: SignatureSet<SignatureV1> = SignatureSet::with_size(NodeCount(7));
         for i in 0..7 {
-            let id = i.into();
+            let id = NodeIndex(i);
             let signature_v1 = SignatureV1 {
-                _id: id,
+                _id: i as u64,
                 sgn: AuthorityPair::generate()
                     .0
                     .sign(vec![0u8, 0u8, 0u8, 0u8].as_slice()),
@@ -242,12 +238,12 @@ mod test {
 
     #[test]
     fn correctly_decodes_v2() {
-        let mut signature_set: SignatureSet<Signature> = SignatureSet::with_size(7.into());
+        let mut signature_set: SignatureSet<Signature> = SignatureSet::with_size(NodeCount(7));
         for i in 0..7 {
             let authority_signature: AuthoritySignature = AuthorityPair::generate()
                 .0
                 .sign(vec![0u8, 0u8, 0u8, 0u8].as_slice());
-            signature_set = signature_set.add_signature(&authority_signature.into(), i.into());
+            signature_set = signature_set.add_signature(&authority_signature.into(), NodeIndex(i));
         }
 
         let just_v2 = AlephJustificationV2 {
@@ -261,12 +257,12 @@ mod test {
 
     #[test]
     fn correctly_decodes_v3_committee() {
-        let mut signature_set: SignatureSet<Signature> = SignatureSet::with_size(7.into());
+        let mut signature_set: SignatureSet<Signature> = SignatureSet::with_size(NodeCount(7));
         for i in 0..7 {
             let authority_signature: AuthoritySignature = AuthorityPair::generate()
                 .0
                 .sign(vec![0u8, 0u8, 0u8, 0u8].as_slice());
-            signature_set = signature_set.add_signature(&authority_signature.into(), i.into());
+            signature_set = signature_set.add_signature(&authority_signature.into(), NodeIndex(i));
         }
 
         let just_v3 = AlephJustification::CommitteeMultisignature(signature_set);
@@ -276,9 +272,29 @@ mod test {
         assert_eq!(decoded, Ok(just_v3));
     }
 
+    #[test]
+    fn correctly_decodes_v3_emergency_custody() {
+        let emergency = AuthorityPair::generate().0;
+        let operator = AuthorityPair::generate().0;
+        let emergency_signature = emergency.sign(&[0u8; 4]);
+        let just_v3 = AlephJustification::EmergencyCustody(EmergencyCustody {
+            reason: 7,
+            operator: operator.public(),
+            operator_signature: operator.sign(&EmergencyCustody::operator_payload(
+                &[0u8; 4],
+                7,
+                &emergency_signature,
+            )),
+            emergency_signature,
+        });
+        let encoded_just = versioned_encode(just_v3.clone());
+        let decoded = backwards_compatible_decode(encoded_just);
+        assert_eq!(decoded, Ok(just_v3));
+    }
+
     #[test]
     fn correctly_decodes_other() {
-        let other = VersionedAlephJustification::Other(Version(43), vec![21, 37]);
+        let other = VersionedAlephJustification::Other(43, vec![21, 37]);
         let encoded = other.encode();
         let decoded = VersionedAlephJustification::decode(&mut encoded.as_slice());
         assert_eq!(decoded, Ok(other));
@@ -292,7 +308,8 @@ mod test {
             Ok(AlephJustification::CommitteeMultisignature(signature)) => {
                 assert_eq!(signature.size(), NodeCount(expected_node_count))
             }
-            Ok(AlephJustification::EmergencySignature(_)) => {
+            Ok(AlephJustification::EmergencySignature(_))
+            | Ok(AlephJustification::EmergencyCustody(_)) => {
                 panic!("decoded V1 as emergency signature")
             }
             Err(e) => panic!("decoding V1 failed: {e}"),
@@ -0,0 +1,54 @@
+use aleph_bft_crypto::SignatureSet;
+use parity_scale_codec::{Decode, Encode};
+use primitives::{AuthorityId, AuthoritySignature, ALEPH_ENGINE_ID};
+
+use crate::crypto::Signature;
+
+mod compatibility;
+
+pub use compatibility::{backwards_compatible_decode, versioned_encode, Error as DecodeError};
+
+const LOG_TARGET: &str = "aleph-justification";
+
+/// Why an emergency finalization was performed, the meaning of the codes is up to the operators.
+pub type EmergencyReason = u16;
+
+/// An emergency signature of a block together with the operator that ordered the emergency
+/// finalization and the reason for it, so that the finalization can be attributed afterwards.
+#[derive(Clone, Encode, Decode, Debug, PartialEq, Eq)]
+pub struct EmergencyCustody {
+    pub reason: EmergencyReason,
+    pub operator: AuthorityId,
+    /// The signature of the operator over the block, the reason and the emergency signature.
+    pub operator_signature: AuthoritySignature,
+    pub emergency_signature: AuthoritySignature,
+}
+
+impl EmergencyCustody {
+    /// What the operator signs, given the encoded hash of the block.
+    pub fn operator_payload(
+        block_bytes: &[u8],
+        reason: EmergencyReason,
+        emergency_signature: &AuthoritySignature,
+    ) -> Vec<u8> {
+        let mut payload = block_bytes.to_vec();
+        reason.encode_to(&mut payload);
+        emergency_signature.encode_to(&mut payload);
+        payload
+    }
+}
+
+/// A proof of block finality, currently in the form of a sufficiently long list of signatures or a
+/// sudo signature of a block for emergency finalization, possibly with its chain of custody.
+#[derive(Clone, Encode, Decode, Debug, PartialEq, Eq)]
+pub enum AlephJustification {
+    CommitteeMultisignature(SignatureSet<Signature>),
+    EmergencySignature(AuthoritySignature),
+    EmergencyCustody(EmergencyCustody),
+}
+
+impl From<AlephJustification> for ([u8; 4], Vec<u8>) {
+    fn from(val: AlephJustification) -> Self {
+        (ALEPH_ENGINE_ID, versioned_encode(val))
+    }
+}
@@ -0,0 +1,423 @@
+//! Minimal verification of aleph justifications, meant to be embedded in wallet backends and
+//! bridges. It requires no substrate client, only the authority sets, which the embedder has to
+//! supply, e.g. from storage proofs checked against headers finalized earlier.
+
+use std::fmt::{Display, Error as FmtError, Formatter};
+
+use parity_scale_codec::Encode;
+
+mod crypto;
+mod justification;
+mod periods;
+mod verifier;
+
+pub use primitives::{
+    AuthorityId, BlockHash, BlockNumber, SessionAuthorityData, SessionIndex, SessionPeriodChange,
+};
+
+pub use crate::{
+    crypto::{is_complete, Signature},
+    justification::{
+        backwards_compatible_decode, versioned_encode, AlephJustification, DecodeError,
+        EmergencyCustody, EmergencyReason,
+    },
+    periods::{PeriodsError, SessionPeriods},
+    verifier::{CustodyPolicy, SessionVerificationError, SessionVerifier},
+};
+
+/// Ways in which verification with the light verifier can fail.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The justification is not correctly encoded.
+    Decode(DecodeError),
+    /// The block does not belong to the session the verifier is currently in.
+    WrongSession {
+        expected: SessionIndex,
+        block_session: SessionIndex,
+    },
+    /// The block or the session is too far in the chain for its session or last block to be
+    /// representable.
+    OutOfRange(BlockNumber),
+    Verification(SessionVerificationError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use Error::*;
+        match self {
+            Decode(e) => write!(f, "failed to decode justification: {e}"),
+            WrongSession {
+                expected,
+                block_session,
+            } => write!(
+                f,
+                "block from session {block_session} while verifying session {expected}"
+            ),
+            OutOfRange(number) => write!(f, "block or session {number} out of range"),
+            Verification(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::Decode(e)
+    }
+}
+
+impl From<SessionVerificationError> for Error {
+    fn from(e: SessionVerificationError) -> Self {
+        Error::Verification(e)
+    }
+}
+
+/// Verifies justifications of a single session at a time, following the chain through session
+/// handoffs, with the session lengths changing as `periods` says.
+pub struct LightVerifier {
+    periods: SessionPeriods,
+    session: SessionIndex,
+    verifier: SessionVerifier,
+}
+
+impl LightVerifier {
+    /// A verifier starting at the given session, which has to be governed by the given authorities.
+    pub fn new(
+        periods: SessionPeriods,
+        session: SessionIndex,
+        authority_data: SessionAuthorityData,
+    ) -> Self {
+        LightVerifier {
+            periods,
+            session,
+            verifier: authority_data.into(),
+        }
+    }
+
+    /// The session the verifier is currently in.
+    pub fn session(&self) -> SessionIndex {
+        self.session
+    }
+
+    /// Verifies that the justification finalizes the block with the given hash and number. The
+    /// block has to belong to the current session.
+    pub fn verify(
+        &self,
+        hash: BlockHash,
+        number: BlockNumber,
+        justification: &AlephJustification,
+    ) -> Result<(), Error> {
+        let block_session = self
+            .periods
+            .session_of_block(number)
+            .ok_or(Error::OutOfRange(number))?;
+        if block_session != self.session {
+            return Err(Error::WrongSession {
+                expected: self.session,
+                block_session,
+            });
+        }
+        self.verifier
+            .verify_bytes(justification, hash.encode())
+            .map_err(Error::from)
+    }
+
+    /// Decodes the justification, in any of the formats ever used, and verifies it as `verify`
+    /// does. Returns the decoded justification if it is correct.
+    pub fn verify_encoded(
+        &self,
+        hash: BlockHash,
+        number: BlockNumber,
+        encoded_justification: Vec<u8>,
+    ) -> Result<AlephJustification, Error> {
+        let justification = backwards_compatible_decode(encoded_justification)?;
+        self.verify(hash, number, &justification)?;
+        Ok(justification)
+    }
+
+    /// Moves the verifier to the next session, once the justification proves that the last block
+    /// of the current session, with the given hash, is finalized. The authorities of the next
+    /// session have to be taken from the state of that block, proving that is up to the caller.
+    pub fn hand_off(
+        &mut self,
+        last_block_hash: BlockHash,
+        justification: &AlephJustification,
+        next_authority_data: SessionAuthorityData,
+    ) -> Result<(), Error> {
+        let last_block = self
+            .periods
+            .last_block_of_session(self.session)
+            .ok_or(Error::OutOfRange(self.session))?;
+        self.verify(last_block_hash, last_block, justification)?;
+        self.session += 1;
+        self.verifier = next_authority_data.into();
+        Ok(())
+    }
+}
+
+/// Who signed a justification, according to the authorities of its session. Says nothing about
+/// whether the signatures are correct.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Signers {
+    /// The indices of the committee members that signed with their keys, `None` for indices
+    /// outside of the committee, and the size of the committee.
+    Committee {
+        signed: Vec<(usize, Option<AuthorityId>)>,
+        members: usize,
+    },
+    /// The emergency finalizer of the session, if it has one.
+    Emergency(Option<AuthorityId>),
+    /// The emergency finalizer of the session, if it has one, together with the operator that
+    /// ordered the emergency finalization and the reason for it.
+    Custody {
+        finalizer: Option<AuthorityId>,
+        operator: AuthorityId,
+        reason: EmergencyReason,
+    },
+}
+
+/// Who signed the justification, given the authorities of its session.
+pub fn signers(
+    authority_data: &SessionAuthorityData,
+    justification: &AlephJustification,
+) -> Signers {
+    use AlephJustification::*;
+    let authorities = authority_data.authorities();
+    let finalizer = authority_data.emergency_finalizer().clone();
+    match justification {
+        CommitteeMultisignature(multisignature) => Signers::Committee {
+            signed: multisignature
+                .iter()
+                .map(|(index, _)| (index.0, authorities.get(index.0).cloned()))
+                .collect(),
+            members: authorities.len(),
+        },
+        EmergencySignature(_) => Signers::Emergency(finalizer),
+        EmergencyCustody(custody) => Signers::Custody {
+            finalizer,
+            operator: custody.operator.clone(),
+            reason: custody.reason,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aleph_bft_crypto::{NodeCount, NodeIndex, PartialMultisignature, SignatureSet};
+    use parity_scale_codec::Encode;
+    use primitives::AuthorityPair;
+    use sp_core::Pair;
+
+    use super::{
+        signers, versioned_encode, AlephJustification, BlockHash, Error, LightVerifier,
+        SessionAuthorityData, SessionPeriodChange, SessionPeriods, SessionVerificationError,
+        Signers,
+    };
+
+    const SESSION_PERIOD: u32 = 10;
+
+    fn periods() -> SessionPeriods {
+        SessionPeriods::fixed(SESSION_PERIOD).expect("the period is nonzero")
+    }
+
+    fn pairs(names: &[&str]) -> Vec<AuthorityPair> {
+        names
+            .iter()
+            .map(|name| AuthorityPair::from_string(name, None).expect("the seed is correct"))
+            .collect()
+    }
+
+    fn authority_data(pairs: &[AuthorityPair]) -> SessionAuthorityData {
+        SessionAuthorityData::new(pairs.iter().map(|pair| pair.public()).collect(), None)
+    }
+
+    fn justification(
+        pairs: &[AuthorityPair],
+        signers: usize,
+        hash: BlockHash,
+    ) -> AlephJustification {
+        let signature_set = pairs.iter().enumerate().take(signers).fold(
+            SignatureSet::with_size(NodeCount(pairs.len())),
+            |signature_set, (index, pair)| {
+                signature_set.add_signature(&pair.sign(&hash.encode()).into(), NodeIndex(index))
+            },
+        );
+        AlephJustification::CommitteeMultisignature(signature_set)
+    }
+
+    #[test]
+    fn accepts_correct_justification() {
+        let pairs = pairs(&["//Alice", "//Bob", "//Charlie", "//Dave"]);
+        let verifier = LightVerifier::new(periods(), 0, authority_data(&pairs));
+        let hash = BlockHash::repeat_byte(1);
+        let justification = justification(&pairs, 3, hash);
+        assert_eq!(verifier.verify(hash, 5, &justification), Ok(()));
+        assert_eq!(
+            verifier.verify_encoded(hash, 5, versioned_encode(justification.clone())),
+            Ok(justification)
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_or_wrong_justification() {
+        let pairs = pairs(&["//Alice", "//Bob", "//Charlie", "//Dave"]);
+        let verifier = LightVerifier::new(periods(), 0, authority_data(&pairs));
+        let hash = BlockHash::repeat_byte(1);
+        assert_eq!(
+            verifier.verify(hash, 5, &justification(&pairs, 2, hash)),
+            Err(Error::Verification(
+                SessionVerificationError::BadMultisignature
+            ))
+        );
+        assert_eq!(
+            verifier.verify(
+                BlockHash::repeat_byte(2),
+                5,
+                &justification(&pairs, 4, hash)
+            ),
+            Err(Error::Verification(
+                SessionVerificationError::BadMultisignature
+            ))
+        );
+        assert!(matches!(
+            verifier.verify_encoded(hash, 5, vec![7; 3]),
+            Err(Error::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn names_committee_signers() {
+        let pairs = pairs(&["//Alice", "//Bob", "//Charlie", "//Dave"]);
+        let hash = BlockHash::repeat_byte(1);
+        assert_eq!(
+            signers(&authority_data(&pairs), &justification(&pairs, 3, hash)),
+            Signers::Committee {
+                signed: pairs
+                    .iter()
+                    .take(3)
+                    .enumerate()
+                    .map(|(index, pair)| (index, Some(pair.public())))
+                    .collect(),
+                members: 4,
+            }
+        );
+        assert_eq!(
+            signers(
+                &authority_data(&pairs[..2]),
+                &justification(&pairs, 3, hash)
+            ),
+            Signers::Committee {
+                signed: vec![
+                    (0, Some(pairs[0].public())),
+                    (1, Some(pairs[1].public())),
+                    (2, None),
+                ],
+                members: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_block_from_other_session() {
+        let pairs = pairs(&["//Alice", "//Bob", "//Charlie"]);
+        let verifier = LightVerifier::new(periods(), 1, authority_data(&pairs));
+        let hash = BlockHash::repeat_byte(1);
+        assert_eq!(
+            verifier.verify(hash, 25, &justification(&pairs, 3, hash)),
+            Err(Error::WrongSession {
+                expected: 1,
+                block_session: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn follows_session_handoff() {
+        let old_pairs = pairs(&["//Alice", "//Bob", "//Charlie"]);
+        let new_pairs = pairs(&["//Dave", "//Eve", "//Ferdie"]);
+        let mut verifier = LightVerifier::new(periods(), 0, authority_data(&old_pairs));
+        let last_block_hash = BlockHash::repeat_byte(1);
+
+        assert!(verifier
+            .hand_off(
+                last_block_hash,
+                &justification(&new_pairs, 3, last_block_hash),
+                authority_data(&new_pairs),
+            )
+            .is_err());
+        assert_eq!(verifier.session(), 0);
+
+        assert_eq!(
+            verifier.hand_off(
+                last_block_hash,
+                &justification(&old_pairs, 3, last_block_hash),
+                authority_data(&new_pairs),
+            ),
+            Ok(())
+        );
+        assert_eq!(verifier.session(), 1);
+        let hash = BlockHash::repeat_byte(2);
+        assert_eq!(
+            verifier.verify(hash, 15, &justification(&new_pairs, 3, hash)),
+            Ok(())
+        );
+        assert!(verifier
+            .verify(hash, 15, &justification(&old_pairs, 3, hash))
+            .is_err());
+    }
+
+    #[test]
+    fn follows_handoff_across_period_change() {
+        let old_pairs = pairs(&["//Alice", "//Bob", "//Charlie"]);
+        let new_pairs = pairs(&["//Dave", "//Eve", "//Ferdie"]);
+        let periods = SessionPeriods::new(vec![
+            SessionPeriodChange {
+                session: 0,
+                first_block: 0,
+                period: SESSION_PERIOD,
+            },
+            SessionPeriodChange {
+                session: 2,
+                first_block: 2 * SESSION_PERIOD,
+                period: 5,
+            },
+        ])
+        .expect("the changes are consistent");
+        let mut verifier = LightVerifier::new(periods, 2, authority_data(&old_pairs));
+        let hash = BlockHash::repeat_byte(1);
+        assert_eq!(
+            verifier.verify(hash, 25, &justification(&old_pairs, 3, hash)),
+            Err(Error::WrongSession {
+                expected: 2,
+                block_session: 3,
+            })
+        );
+        assert_eq!(
+            verifier.hand_off(
+                hash,
+                &justification(&old_pairs, 3, hash),
+                authority_data(&new_pairs),
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            verifier.verify(hash, 25, &justification(&new_pairs, 3, hash)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn refuses_handoff_past_last_representable_block() {
+        let pairs = pairs(&["//Alice", "//Bob", "//Charlie"]);
+        let mut verifier = LightVerifier::new(periods(), u32::MAX, authority_data(&pairs));
+        let hash = BlockHash::repeat_byte(1);
+        assert_eq!(
+            verifier.hand_off(
+                hash,
+                &justification(&pairs, 3, hash),
+                authority_data(&pairs)
+            ),
+            Err(Error::OutOfRange(u32::MAX))
+        );
+        assert_eq!(verifier.session(), u32::MAX);
+    }
+}
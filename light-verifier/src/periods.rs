@@ -0,0 +1,159 @@
+use std::fmt::{Display, Error as FmtError, Formatter};
+
+use primitives::{BlockNumber, SessionIndex, SessionPeriodChange};
+
+/// What can be wrong with the changes of the session length.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeriodsError {
+    NotFromGenesis,
+    ZeroPeriod(SessionIndex),
+    /// The change does not start at the first block after the previous sessions, or is out of
+    /// order.
+    Misaligned(SessionIndex),
+}
+
+impl Display for PeriodsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use PeriodsError::*;
+        match self {
+            NotFromGenesis => write!(f, "the first session length does not start at the genesis"),
+            ZeroPeriod(session) => write!(f, "the sessions starting with {session} have no blocks"),
+            Misaligned(session) => write!(
+                f,
+                "the change starting with session {session} does not follow the previous sessions"
+            ),
+        }
+    }
+}
+
+/// The lengths of the sessions, as reported by the `session_period_changes` runtime call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionPeriods {
+    /// In the order of sessions, the first one starting with the genesis.
+    changes: Vec<SessionPeriodChange>,
+}
+
+impl SessionPeriods {
+    /// The sessions following the changes, which have to start with the genesis and follow one
+    /// another.
+    pub fn new(changes: Vec<SessionPeriodChange>) -> Result<Self, PeriodsError> {
+        use PeriodsError::*;
+        match changes.first() {
+            Some(first) if first.session == 0 && first.first_block == 0 => {}
+            _ => return Err(NotFromGenesis),
+        }
+        if let Some(change) = changes.iter().find(|change| change.period == 0) {
+            return Err(ZeroPeriod(change.session));
+        }
+        for pair in changes.windows(2) {
+            let (previous, next) = (&pair[0], &pair[1]);
+            let expected_first_block = next
+                .session
+                .checked_sub(previous.session)
+                .filter(|sessions| *sessions > 0)
+                .and_then(|sessions| sessions.checked_mul(previous.period))
+                .and_then(|blocks| blocks.checked_add(previous.first_block));
+            if expected_first_block != Some(next.first_block) {
+                return Err(Misaligned(next.session));
+            }
+        }
+        Ok(SessionPeriods { changes })
+    }
+
+    /// All the sessions last `period` blocks, which has to be nonzero.
+    pub fn fixed(period: u32) -> Result<Self, PeriodsError> {
+        Self::new(vec![SessionPeriodChange {
+            session: 0,
+            first_block: 0,
+            period,
+        }])
+    }
+
+    /// The session the block belongs to, `None` if it is not representable.
+    pub fn session_of_block(&self, number: BlockNumber) -> Option<SessionIndex> {
+        let change = self
+            .changes
+            .iter()
+            .rev()
+            .find(|change| change.first_block <= number)?;
+        change
+            .session
+            .checked_add((number - change.first_block) / change.period)
+    }
+
+    /// The first block of the session, `None` if it is not representable.
+    pub fn first_block_of_session(&self, session: SessionIndex) -> Option<BlockNumber> {
+        let change = self
+            .changes
+            .iter()
+            .rev()
+            .find(|change| change.session <= session)?;
+        (session - change.session)
+            .checked_mul(change.period)?
+            .checked_add(change.first_block)
+    }
+
+    /// The last block of the session, `None` if it is not representable.
+    pub fn last_block_of_session(&self, session: SessionIndex) -> Option<BlockNumber> {
+        self.first_block_of_session(session.checked_add(1)?)?
+            .checked_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use primitives::SessionPeriodChange;
+
+    use super::{PeriodsError, SessionPeriods};
+
+    fn change(session: u32, first_block: u32, period: u32) -> SessionPeriodChange {
+        SessionPeriodChange {
+            session,
+            first_block,
+            period,
+        }
+    }
+
+    #[test]
+    fn follows_period_changes() {
+        let periods = SessionPeriods::new(vec![change(0, 0, 10), change(2, 20, 5)])
+            .expect("the changes are consistent");
+        assert_eq!(periods.session_of_block(19), Some(1));
+        assert_eq!(periods.session_of_block(20), Some(2));
+        assert_eq!(periods.session_of_block(25), Some(3));
+        assert_eq!(periods.first_block_of_session(3), Some(25));
+        assert_eq!(periods.last_block_of_session(1), Some(19));
+        assert_eq!(periods.last_block_of_session(3), Some(29));
+    }
+
+    #[test]
+    fn rejects_inconsistent_changes() {
+        use PeriodsError::*;
+        assert_eq!(SessionPeriods::new(Vec::new()), Err(NotFromGenesis));
+        assert_eq!(
+            SessionPeriods::new(vec![change(1, 10, 5)]),
+            Err(NotFromGenesis)
+        );
+        assert_eq!(SessionPeriods::fixed(0), Err(ZeroPeriod(0)));
+        assert_eq!(
+            SessionPeriods::new(vec![change(0, 0, 10), change(2, 25, 5)]),
+            Err(Misaligned(2))
+        );
+        assert_eq!(
+            SessionPeriods::new(vec![change(0, 0, 10), change(0, 0, 5)]),
+            Err(Misaligned(0))
+        );
+    }
+
+    #[test]
+    fn does_not_overflow_for_distant_sessions() {
+        let periods = SessionPeriods::fixed(900).expect("the period is nonzero");
+        assert_eq!(
+            periods.first_block_of_session(u32::MAX / 900),
+            Some(4294966500)
+        );
+        assert_eq!(periods.last_block_of_session(u32::MAX / 900), None);
+        assert_eq!(periods.last_block_of_session(u32::MAX), None);
+        assert_eq!(periods.session_of_block(u32::MAX), Some(u32::MAX / 900));
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashSet,
+    fmt::{Display, Error as FmtError, Formatter},
+};
+
+use primitives::{AuthorityId, AuthoritySignature, SessionAuthorityData};
+use sp_application_crypto::RuntimeAppPublic;
+
+use crate::{
+    crypto::is_complete,
+    justification::{AlephJustification, EmergencyCustody},
+};
+
+/// Which chain of custody emergency justifications have to have.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CustodyPolicy {
+    /// Whether emergency signatures without an operator are rejected.
+    pub required: bool,
+    /// The operators that can order emergency finalizations, any if empty.
+    pub operators: HashSet<AuthorityId>,
+}
+
+/// A justification verifier within a single session.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SessionVerifier {
+    authorities: Vec<AuthorityId>,
+    emergency_signer: Option<AuthorityId>,
+    custody: CustodyPolicy,
+}
+
+impl From<SessionAuthorityData> for SessionVerifier {
+    fn from(authority_data: SessionAuthorityData) -> Self {
+        SessionVerifier {
+            authorities: authority_data.authorities().to_vec(),
+            emergency_signer: authority_data.emergency_finalizer().clone(),
+            custody: CustodyPolicy::default(),
+        }
+    }
+}
+
+/// Ways in which a justification can be wrong.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionVerificationError {
+    BadMultisignature,
+    BadEmergencySignature,
+    NoEmergencySigner,
+    MissingCustody,
+    UnknownOperator(AuthorityId),
+    BadOperatorSignature,
+}
+
+impl Display for SessionVerificationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtError> {
+        use SessionVerificationError::*;
+        match self {
+            BadMultisignature => write!(f, "bad multisignature"),
+            BadEmergencySignature => write!(f, "bad emergency signature"),
+            NoEmergencySigner => write!(f, "no emergency signer defined"),
+            MissingCustody => write!(f, "emergency signature without an operator"),
+            UnknownOperator(operator) => {
+                write!(
+                    f,
+                    "emergency finalization ordered by unknown operator {operator:?}"
+                )
+            }
+            BadOperatorSignature => write!(f, "bad emergency operator signature"),
+        }
+    }
+}
+
+impl SessionVerifier {
+    /// The verifier requiring the given chain of custody of emergency justifications.
+    pub fn with_custody(self, custody: CustodyPolicy) -> Self {
+        SessionVerifier { custody, ..self }
+    }
+
+    fn verify_emergency_signature(
+        &self,
+        bytes: &[u8],
+        signature: &AuthoritySignature,
+    ) -> Result<(), SessionVerificationError> {
+        use SessionVerificationError::*;
+        match self
+            .emergency_signer
+            .as_ref()
+            .ok_or(NoEmergencySigner)?
+            .verify(&bytes, signature)
+        {
+            true => Ok(()),
+            false => Err(BadEmergencySignature),
+        }
+    }
+
+    fn verify_custody(
+        &self,
+        bytes: &[u8],
+        custody: &EmergencyCustody,
+    ) -> Result<(), SessionVerificationError> {
+        use SessionVerificationError::*;
+        self.verify_emergency_signature(bytes, &custody.emergency_signature)?;
+        if !self.custody.operators.is_empty() && !self.custody.operators.contains(&custody.operator)
+        {
+            return Err(UnknownOperator(custody.operator.clone()));
+        }
+        let payload =
+            EmergencyCustody::operator_payload(bytes, custody.reason, &custody.emergency_signature);
+        match custody
+            .operator
+            .verify(&payload, &custody.operator_signature)
+        {
+            true => Ok(()),
+            false => Err(BadOperatorSignature),
+        }
+    }
+
+    /// Verifies the correctness of a justification for supplied bytes.
+    pub fn verify_bytes(
+        &self,
+        justification: &AlephJustification,
+        bytes: Vec<u8>,
+    ) -> Result<(), SessionVerificationError> {
+        use AlephJustification::*;
+        use SessionVerificationError::*;
+        match justification {
+            CommitteeMultisignature(multisignature) => {
+                match is_complete(&self.authorities, &bytes, multisignature) {
+                    true => Ok(()),
+                    false => Err(BadMultisignature),
+                }
+            }
+            EmergencySignature(signature) => {
+                if self.custody.required {
+                    return Err(MissingCustody);
+                }
+                self.verify_emergency_signature(&bytes, signature)
+            }
+            AlephJustification::EmergencyCustody(custody) => self.verify_custody(&bytes, custody),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parity_scale_codec::Encode;
+    use primitives::{AuthorityPair, BlockHash, SessionAuthorityData};
+    use sp_core::Pair;
+
+    use super::{CustodyPolicy, SessionVerificationError, SessionVerifier};
+    use crate::justification::{AlephJustification, EmergencyCustody};
+
+    fn custody(
+        emergency: &AuthorityPair,
+        operator: &AuthorityPair,
+        bytes: &[u8],
+        reason: u16,
+    ) -> AlephJustification {
+        let emergency_signature = emergency.sign(bytes);
+        AlephJustification::EmergencyCustody(EmergencyCustody {
+            reason,
+            operator: operator.public(),
+            operator_signature: operator.sign(&EmergencyCustody::operator_payload(
+                bytes,
+                reason,
+                &emergency_signature,
+            )),
+            emergency_signature,
+        })
+    }
+
+    #[test]
+    fn verifies_chain_of_custody() {
+        let emergency = AuthorityPair::generate().0;
+        let operator = AuthorityPair::generate().0;
+        let stranger = AuthorityPair::generate().0;
+        let bytes = BlockHash::repeat_byte(7).encode();
+        let verifier = SessionVerifier::from(SessionAuthorityData::new(
+            Vec::new(),
+            Some(emergency.public()),
+        ));
+        let plain = AlephJustification::EmergencySignature(emergency.sign(&bytes));
+        assert_eq!(verifier.verify_bytes(&plain, bytes.clone()), Ok(()));
+        let custodied = custody(&emergency, &operator, &bytes, 3);
+        assert_eq!(verifier.verify_bytes(&custodied, bytes.clone()), Ok(()));
+
+        let verifier = verifier.with_custody(CustodyPolicy {
+            required: true,
+            operators: [operator.public()].into_iter().collect(),
+        });
+        assert_eq!(
+            verifier.verify_bytes(&plain, bytes.clone()),
+            Err(SessionVerificationError::MissingCustody)
+        );
+        assert_eq!(verifier.verify_bytes(&custodied, bytes.clone()), Ok(()));
+        assert_eq!(
+            verifier.verify_bytes(&custody(&emergency, &stranger, &bytes, 3), bytes.clone()),
+            Err(SessionVerificationError::UnknownOperator(stranger.public()))
+        );
+        assert_eq!(
+            verifier.verify_bytes(&custody(&stranger, &operator, &bytes, 3), bytes.clone()),
+            Err(SessionVerificationError::BadEmergencySignature)
+        );
+        let mut forged = custody(&emergency, &operator, &bytes, 3);
+        if let AlephJustification::EmergencyCustody(custody) = &mut forged {
+            custody.reason = 4;
+        }
+        assert_eq!(
+            verifier.verify_bytes(&forged, bytes),
+            Err(SessionVerificationError::BadOperatorSignature)
+        );
+    }
+}
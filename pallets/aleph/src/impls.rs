@@ -1,4 +1,9 @@
-use primitives::{FinalityCommitteeManager, SessionIndex};
+use frame_support::{
+    sp_runtime::{traits::SaturatedConversion, Permill},
+    traits::EstimateNextSessionRotation,
+    weights::Weight,
+};
+use primitives::{BlockNumber, FinalityCommitteeManager, SessionIndex};
 use sp_std::vec::Vec;
 
 use crate::{
@@ -58,3 +63,36 @@ impl<T: Config> FinalityCommitteeManager<T::AccountId> for Pallet<T> {
         NextFinalityCommittee::<T>::put(committee);
     }
 }
+
+impl<T: Config> pallet_session::ShouldEndSession<T::BlockNumber> for Pallet<T> {
+    fn should_end_session(now: T::BlockNumber) -> bool {
+        let now: BlockNumber = now.saturated_into();
+        let change = Self::session_period_at(now);
+        (now - change.first_block) % change.period == 0
+    }
+}
+
+impl<T: Config> EstimateNextSessionRotation<T::BlockNumber> for Pallet<T> {
+    fn average_session_length() -> T::BlockNumber {
+        Self::current_session_period().into()
+    }
+
+    fn estimate_current_session_progress(now: T::BlockNumber) -> (Option<Permill>, Weight) {
+        let now: BlockNumber = now.saturated_into();
+        let change = Self::session_period_at(now);
+        let current = (now - change.first_block) % change.period + 1;
+        (
+            Some(Permill::from_rational(current, change.period)),
+            Weight::zero(),
+        )
+    }
+
+    fn estimate_next_session_rotation(now: T::BlockNumber) -> (Option<T::BlockNumber>, Weight) {
+        let now: BlockNumber = now.saturated_into();
+        let change = Self::session_period_at(now);
+        // The scheduled changes start where a session of the current length would end anyway.
+        let since_start = (now - change.first_block) % change.period;
+        let next = now.saturating_add(change.period - since_start);
+        (Some(next.into()), Weight::zero())
+    }
+}
@@ -13,6 +13,18 @@
 //! It is always possible to reschedule a version change. In order to cancel a scheduled version
 //! change rather than reschedule it, a new version change should be scheduled with
 //! `version_incoming` set to the current value of `FinalityVersion`.
+//!
+//! The pallet also stores `SyncParameters`, the network-wide parameters of block synchronization,
+//! which are exposed to the nodes through the same Runtime API and can only be changed by root.
+//!
+//! Similarly `FinalizationDepthOffset` is how many blocks below the head decided by AlephBFT the
+//! blocks are finalized. It has to be the same for the whole committee, so a change set by root
+//! is kept in `NextFinalizationDepthOffset` and only takes effect with the next session.
+//!
+//! The length of the sessions starts as `Config::SessionPeriod` and changes as root schedules in
+//! `SessionPeriodChanges`, at least 2 sessions in advance, so that the nodes learn about a change
+//! from finalized blocks before it takes effect. The pallet ends the sessions accordingly, when
+//! used as `ShouldEndSession` and `NextSessionRotation` of `pallet_session`.
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
@@ -26,15 +38,16 @@ mod traits;
 
 use frame_support::{
     log,
-    sp_runtime::{BoundToRuntimeAppPublic, DigestItem},
+    sp_runtime::{traits::SaturatedConversion, BoundToRuntimeAppPublic, DigestItem},
     traits::{OneSessionHandler, StorageVersion},
 };
 pub use pallet::*;
 #[cfg(feature = "std")]
 use primitives::LEGACY_FINALITY_VERSION;
 use primitives::{
-    ConsensusLog::AlephAuthorityChange, SessionIndex, Version, VersionChange, ALEPH_ENGINE_ID,
-    DEFAULT_FINALITY_VERSION,
+    BlockNumber, ConsensusLog::AlephAuthorityChange, SessionIndex, SessionPeriodChange, SyncParams,
+    Version, VersionChange, ALEPH_ENGINE_ID, DEFAULT_FINALITY_VERSION,
+    MAX_FINALIZATION_DEPTH_OFFSET,
 };
 use sp_std::prelude::*;
 
@@ -65,6 +78,8 @@ pub mod pallet {
         type SessionInfoProvider: SessionInfoProvider<<Self as frame_system::Config>::BlockNumber>;
         type SessionManager: SessionManager<<Self as frame_system::Config>::AccountId>;
         type NextSessionAuthorityProvider: NextSessionAuthorityProvider<Self>;
+        /// The length of the sessions before the first change of it.
+        type SessionPeriod: Get<u32>;
     }
 
     #[pallet::event]
@@ -73,6 +88,9 @@ pub mod pallet {
         ChangeEmergencyFinalizer(T::AuthorityId),
         ScheduleFinalityVersionChange(VersionChange),
         FinalityVersionChange(VersionChange),
+        ChangeSyncParams(SyncParams),
+        ScheduleFinalizationDepthOffsetChange(BlockNumber),
+        ScheduleSessionPeriodChange(SessionPeriodChange),
     }
 
     #[pallet::pallet]
@@ -129,6 +147,27 @@ pub mod pallet {
     pub(super) type FinalityScheduledVersionChange<T: Config> =
         StorageValue<_, VersionChange, OptionQuery>;
 
+    /// Network-wide parameters of block synchronization.
+    #[pallet::storage]
+    #[pallet::getter(fn sync_params)]
+    pub(super) type SyncParameters<T: Config> = StorageValue<_, SyncParams, ValueQuery>;
+
+    /// How many blocks below the head decided by AlephBFT the blocks of the current session are
+    /// finalized.
+    #[pallet::storage]
+    #[pallet::getter(fn finalization_depth_offset)]
+    pub(super) type FinalizationDepthOffset<T: Config> = StorageValue<_, BlockNumber, ValueQuery>;
+
+    /// The finalization depth offset taking effect with the next session.
+    #[pallet::storage]
+    type NextFinalizationDepthOffset<T: Config> = StorageValue<_, BlockNumber, OptionQuery>;
+
+    /// The changes of the length of the sessions, in the order of sessions, not including the
+    /// initial length.
+    #[pallet::storage]
+    pub(super) type SessionPeriodChanges<T: Config> =
+        StorageValue<_, Vec<SessionPeriodChange>, ValueQuery>;
+
     #[pallet::hooks]
     impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
         fn on_finalize(block_number: T::BlockNumber) {
@@ -212,10 +251,87 @@ pub mod pallet {
             <NextEmergencyFinalizer<T>>::put(emergency_finalizer);
         }
 
+        pub(crate) fn update_finalization_depth_offset() {
+            if let Some(offset) = <NextFinalizationDepthOffset<T>>::take() {
+                <FinalizationDepthOffset<T>>::put(offset);
+            }
+        }
+
+        pub fn next_session_finalization_depth_offset() -> BlockNumber {
+            <NextFinalizationDepthOffset<T>>::get().unwrap_or_else(Self::finalization_depth_offset)
+        }
+
         pub(crate) fn current_session() -> u32 {
             T::SessionInfoProvider::current_session()
         }
 
+        fn initial_session_period() -> SessionPeriodChange {
+            SessionPeriodChange {
+                session: 0,
+                first_block: 0,
+                period: T::SessionPeriod::get(),
+            }
+        }
+
+        /// All the lengths of the sessions, starting with the initial one, including the
+        /// scheduled changes.
+        pub fn session_period_changes() -> Vec<SessionPeriodChange> {
+            let mut changes = sp_std::vec![Self::initial_session_period()];
+            changes.extend(<SessionPeriodChanges<T>>::get());
+            changes
+        }
+
+        /// The length of the sessions in effect at the block.
+        pub(crate) fn session_period_at(block: BlockNumber) -> SessionPeriodChange {
+            <SessionPeriodChanges<T>>::get()
+                .into_iter()
+                .rev()
+                .find(|change| change.first_block <= block)
+                .unwrap_or_else(Self::initial_session_period)
+        }
+
+        /// The length of the current session.
+        pub fn current_session_period() -> u32 {
+            let now = <frame_system::Pallet<T>>::block_number().saturated_into();
+            Self::session_period_at(now).period
+        }
+
+        // A change scheduled for the same or a later session than the pending ones replaces them.
+        // To cancel the pending changes, schedule the length they would replace.
+        pub(crate) fn do_schedule_session_period_change(
+            session: SessionIndex,
+            period: u32,
+        ) -> Result<SessionPeriodChange, &'static str> {
+            if period == 0 {
+                return Err("Sessions cannot be empty!");
+            }
+            if session < Self::current_session() + 2 {
+                return Err(
+                    "Tried to schedule a session period change less than 2 sessions in advance!",
+                );
+            }
+
+            let mut changes = <SessionPeriodChanges<T>>::get();
+            changes.retain(|change| change.session < session);
+            let previous = changes
+                .last()
+                .copied()
+                .unwrap_or_else(Self::initial_session_period);
+            let first_block = (session - previous.session)
+                .checked_mul(previous.period)
+                .and_then(|blocks| blocks.checked_add(previous.first_block))
+                .ok_or("Tried to schedule a session period change too far in the future!")?;
+            let change = SessionPeriodChange {
+                session,
+                first_block,
+                period,
+            };
+            changes.push(change);
+            <SessionPeriodChanges<T>>::put(changes);
+
+            Ok(change)
+        }
+
         // If a scheduled future version change is rescheduled to a different session,
         // it is possible to reschedule it with the same version as initially.
         // To cancel a future version change, reschedule it with the current version.
@@ -299,6 +415,62 @@ pub mod pallet {
             Self::deposit_event(Event::ScheduleFinalityVersionChange(version_change));
             Ok(())
         }
+
+        /// Sets the parameters of block synchronization. Nodes pick them up once the block
+        /// setting them is finalized, and can only use stricter values than these.
+        #[pallet::call_index(2)]
+        #[pallet::weight((T::BlockWeights::get().max_block, DispatchClass::Operational))]
+        pub fn set_sync_params(origin: OriginFor<T>, params: SyncParams) -> DispatchResult {
+            ensure_root(origin)?;
+
+            if !params.is_valid() {
+                return Err(DispatchError::Other("Invalid sync parameters!"));
+            }
+
+            <SyncParameters<T>>::put(params);
+            Self::deposit_event(Event::ChangeSyncParams(params));
+            Ok(())
+        }
+
+        /// Sets how many blocks below the head decided by AlephBFT the blocks get finalized,
+        /// starting with the next session. The blocks above the finalized one are decided, but
+        /// not irreversible yet, giving external auditors a buffer. The last block of a session
+        /// is always finalized without delay.
+        #[pallet::call_index(3)]
+        #[pallet::weight((T::BlockWeights::get().max_block, DispatchClass::Operational))]
+        pub fn set_finalization_depth_offset(
+            origin: OriginFor<T>,
+            offset: BlockNumber,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
+
+            if offset > MAX_FINALIZATION_DEPTH_OFFSET {
+                return Err(DispatchError::Other("Finalization depth offset too large!"));
+            }
+
+            <NextFinalizationDepthOffset<T>>::put(offset);
+            Self::deposit_event(Event::ScheduleFinalizationDepthOffsetChange(offset));
+            Ok(())
+        }
+
+        /// Schedules the sessions starting with the provided one to last `period` blocks each.
+        /// The change has to be scheduled at least 2 sessions in advance, and replaces the
+        /// changes scheduled for the same or later sessions.
+        #[pallet::call_index(4)]
+        #[pallet::weight((T::BlockWeights::get().max_block, DispatchClass::Operational))]
+        pub fn schedule_session_period_change(
+            origin: OriginFor<T>,
+            session: SessionIndex,
+            period: u32,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
+
+            let change = Self::do_schedule_session_period_change(session, period)
+                .map_err(DispatchError::Other)?;
+
+            Self::deposit_event(Event::ScheduleSessionPeriodChange(change));
+            Ok(())
+        }
     }
 
     impl<T: Config> BoundToRuntimeAppPublic for Pallet<T> {
@@ -324,6 +496,7 @@ pub mod pallet {
             T::AccountId: 'a,
         {
             Self::update_emergency_finalizer();
+            Self::update_finalization_depth_offset();
             if changed {
                 Self::update_authorities(queued_validators.collect());
             }
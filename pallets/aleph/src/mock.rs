@@ -157,6 +157,7 @@ impl Config for Test {
     type SessionInfoProvider = SessionInfoImpl;
     type SessionManager = ();
     type NextSessionAuthorityProvider = Session;
+    type SessionPeriod = ConstU32<1>;
 }
 
 pub fn to_authority(id: &u64) -> AuthorityId {
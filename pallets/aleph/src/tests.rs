@@ -1,7 +1,11 @@
 #![cfg(test)]
 
-use frame_support::{storage_alias, traits::OneSessionHandler};
-use primitives::VersionChange;
+use frame_support::{
+    storage_alias,
+    traits::{EstimateNextSessionRotation, OneSessionHandler},
+};
+use pallet_session::ShouldEndSession;
+use primitives::{SessionPeriodChange, SyncParams, VersionChange, MAX_FINALIZATION_DEPTH_OFFSET};
 
 use crate::{mock::*, NextFinalityCommittee};
 
@@ -148,3 +152,120 @@ fn test_finality_version_scheduling() {
         assert!(scheduling_result.is_err());
     })
 }
+
+#[test]
+fn test_setting_sync_params() {
+    new_test_ext(&[(1u64, 1u64), (2u64, 2u64)]).execute_with(|| {
+        assert_eq!(Aleph::sync_params(), SyncParams::default());
+
+        let params = SyncParams {
+            serving_window: 1000,
+            max_batch_bytes: 10 * 1024 * 1024,
+            ..Default::default()
+        };
+        assert!(Aleph::set_sync_params(RuntimeOrigin::signed(1), params).is_err());
+        assert_eq!(Aleph::sync_params(), SyncParams::default());
+
+        assert_eq!(
+            Aleph::set_sync_params(RuntimeOrigin::root(), params),
+            Ok(())
+        );
+        assert_eq!(Aleph::sync_params(), params);
+
+        let invalid_params = SyncParams {
+            min_broadcast_period_ms: 10_000,
+            max_broadcast_period_ms: 1_000,
+            ..params
+        };
+        assert!(Aleph::set_sync_params(RuntimeOrigin::root(), invalid_params).is_err());
+        assert_eq!(Aleph::sync_params(), params);
+    })
+}
+
+#[test]
+fn test_finalization_depth_offset_takes_effect_next_session() {
+    new_test_ext(&[(1u64, 1u64), (2u64, 2u64)]).execute_with(|| {
+        initialize_session();
+        run_session(1);
+
+        assert_eq!(Aleph::finalization_depth_offset(), 0);
+        assert!(Aleph::set_finalization_depth_offset(RuntimeOrigin::signed(1), 5).is_err());
+        assert!(Aleph::set_finalization_depth_offset(
+            RuntimeOrigin::root(),
+            MAX_FINALIZATION_DEPTH_OFFSET + 1
+        )
+        .is_err());
+        assert_eq!(Aleph::next_session_finalization_depth_offset(), 0);
+
+        assert_eq!(
+            Aleph::set_finalization_depth_offset(RuntimeOrigin::root(), 5),
+            Ok(())
+        );
+        assert_eq!(Aleph::finalization_depth_offset(), 0);
+        assert_eq!(Aleph::next_session_finalization_depth_offset(), 5);
+
+        run_session(2);
+
+        assert_eq!(Aleph::finalization_depth_offset(), 5);
+        assert_eq!(Aleph::next_session_finalization_depth_offset(), 5);
+    })
+}
+
+#[test]
+fn test_session_period_change_scheduling() {
+    new_test_ext(&[(1u64, 1u64), (2u64, 2u64)]).execute_with(|| {
+        initialize_session();
+        run_session(1);
+
+        let initial = SessionPeriodChange {
+            session: 0,
+            first_block: 0,
+            period: 1,
+        };
+        assert_eq!(Aleph::session_period_changes(), vec![initial]);
+        assert!(Aleph::schedule_session_period_change(RuntimeOrigin::signed(1), 3, 5).is_err());
+        assert!(Aleph::schedule_session_period_change(RuntimeOrigin::root(), 2, 5).is_err());
+        assert!(Aleph::schedule_session_period_change(RuntimeOrigin::root(), 3, 0).is_err());
+        assert_eq!(Aleph::session_period_changes(), vec![initial]);
+
+        assert_eq!(
+            Aleph::schedule_session_period_change(RuntimeOrigin::root(), 4, 5),
+            Ok(())
+        );
+        let change = SessionPeriodChange {
+            session: 3,
+            first_block: 3,
+            period: 10,
+        };
+        // Replaces the later change.
+        assert_eq!(
+            Aleph::schedule_session_period_change(RuntimeOrigin::root(), 3, 10),
+            Ok(())
+        );
+        assert_eq!(Aleph::session_period_changes(), vec![initial, change]);
+        assert_eq!(
+            Aleph::schedule_session_period_change(RuntimeOrigin::root(), 5, 4),
+            Ok(())
+        );
+        let later_change = SessionPeriodChange {
+            session: 5,
+            first_block: 23,
+            period: 4,
+        };
+        assert_eq!(
+            Aleph::session_period_changes(),
+            vec![initial, change, later_change]
+        );
+
+        assert!(Aleph::should_end_session(2));
+        assert!(Aleph::should_end_session(3));
+        assert!(!Aleph::should_end_session(4));
+        assert!(Aleph::should_end_session(13));
+        assert!(Aleph::should_end_session(23));
+        assert!(!Aleph::should_end_session(25));
+        assert!(Aleph::should_end_session(27));
+        assert_eq!(Aleph::estimate_next_session_rotation(2).0, Some(3));
+        assert_eq!(Aleph::estimate_next_session_rotation(5).0, Some(13));
+        assert_eq!(Aleph::estimate_next_session_rotation(23).0, Some(27));
+    })
+}
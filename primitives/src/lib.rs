@@ -105,6 +105,17 @@ pub const CURRENT_FINALITY_VERSION: u16 = LEGACY_FINALITY_VERSION + 1;
 pub const LEGACY_FINALITY_VERSION: u16 = 1;
 pub const LENIENT_THRESHOLD: Perquintill = Perquintill::from_percent(90);
 
+pub const DEFAULT_SYNC_SERVING_WINDOW: BlockNumber = BlockNumber::MAX;
+pub const DEFAULT_SYNC_MAX_BATCH_BYTES: u32 = 3 * MAX_BLOCK_SIZE + 1024;
+/// A batch has to fit at least a single block.
+pub const MIN_SYNC_MAX_BATCH_BYTES: u32 = MAX_BLOCK_SIZE + 1024;
+pub const DEFAULT_SYNC_MIN_BROADCAST_PERIOD_MS: u64 = 600;
+pub const DEFAULT_SYNC_MAX_BROADCAST_PERIOD_MS: u64 = 5000;
+
+/// Maximum number of blocks finalization can trail the head decided by AlephBFT, bounds the size
+/// of the proposals.
+pub const MAX_FINALIZATION_DEPTH_OFFSET: BlockNumber = 64;
+
 /// Hold set of validators that produce blocks and set of validators that participate in finality
 /// during session.
 #[derive(Decode, Encode, TypeInfo, Debug, Clone, PartialEq, Eq)]
@@ -149,6 +160,50 @@ impl Default for CommitteeSeats {
     }
 }
 
+/// Parameters of the block synchronization, common for the whole network.
+/// Nodes may locally tighten them, but never loosen.
+#[derive(Decode, Encode, TypeInfo, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct SyncParams {
+    /// How far below its top finalized block a node serves requests from lagging peers.
+    pub serving_window: BlockNumber,
+    /// Maximal size in bytes of a single batch of data sent in response to a request.
+    pub max_batch_bytes: u32,
+    /// Minimal time between two broadcasts of a node's knowledge state, in milliseconds.
+    pub min_broadcast_period_ms: u64,
+    /// Maximal time between two broadcasts of a node's knowledge state, in milliseconds.
+    pub max_broadcast_period_ms: u64,
+}
+
+impl SyncParams {
+    pub fn is_valid(&self) -> bool {
+        self.max_batch_bytes >= MIN_SYNC_MAX_BATCH_BYTES
+            && self.min_broadcast_period_ms > 0
+            && self.min_broadcast_period_ms <= self.max_broadcast_period_ms
+    }
+}
+
+impl Default for SyncParams {
+    fn default() -> Self {
+        SyncParams {
+            serving_window: DEFAULT_SYNC_SERVING_WINDOW,
+            max_batch_bytes: DEFAULT_SYNC_MAX_BATCH_BYTES,
+            min_broadcast_period_ms: DEFAULT_SYNC_MIN_BROADCAST_PERIOD_MS,
+            max_broadcast_period_ms: DEFAULT_SYNC_MAX_BROADCAST_PERIOD_MS,
+        }
+    }
+}
+
+/// A change of the length of the sessions. The sessions starting with `session`, at block
+/// `first_block`, last `period` blocks each, until the next change.
+#[derive(Decode, Encode, TypeInfo, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct SessionPeriodChange {
+    pub session: SessionIndex,
+    pub first_block: BlockNumber,
+    pub period: u32,
+}
+
 pub trait FinalityCommitteeManager<T> {
     /// `committee` is the set elected for finality committee for the next session
     fn on_next_session_finality_committee(committee: Vec<T>);
@@ -274,6 +329,7 @@ pub enum ConsensusLog<N: sp_runtime::RuntimeAppPublic> {
 }
 
 sp_api::decl_runtime_apis! {
+    #[api_version(4)]
     pub trait AlephSessionApi {
         fn next_session_authorities() -> Result<Vec<AuthorityId>, ApiError>;
         fn authorities() -> Vec<AuthorityId>;
@@ -293,6 +349,23 @@ sp_api::decl_runtime_apis! {
         fn predict_session_committee(
             session: SessionIndex
         ) -> Result<SessionCommittee<AccountId>, SessionValidatorError>;
+        /// Parameters of the block synchronization set by governance.
+        #[api_version(2)]
+        fn sync_params() -> SyncParams;
+        /// All the changes of the session length in the order of sessions, the first one starting
+        /// with the genesis. Includes the scheduled ones, a change has to be known at the first
+        /// block of the session before the one it starts with, so that nodes can follow it.
+        #[api_version(3)]
+        fn session_period_changes() -> Vec<SessionPeriodChange>;
+        /// How many blocks below the head decided by AlephBFT the blocks of the current session
+        /// are finalized.
+        #[api_version(4)]
+        fn finalization_depth_offset() -> BlockNumber;
+        /// How many blocks below the head decided by AlephBFT the blocks of the next session are
+        /// finalized. Read at the last block of a session by all the validators of the next one,
+        /// so that they agree on it.
+        #[api_version(4)]
+        fn next_session_finalization_depth_offset() -> BlockNumber;
     }
 }
 
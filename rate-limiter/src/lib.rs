@@ -1,6 +1,9 @@
 mod rate_limiter;
 mod token_bucket;
 
-pub use crate::rate_limiter::{RateLimiter, SleepingRateLimiter};
+pub use crate::{
+    rate_limiter::{RateLimiter, SleepingRateLimiter},
+    token_bucket::TokenBucket,
+};
 
 const LOG_TARGET: &str = "rate-limiter";
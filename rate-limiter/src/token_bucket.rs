@@ -27,7 +27,8 @@ impl TokenBucket {
         }
     }
 
-    #[cfg(test)]
+    /// Constructs a instance of [TokenBucket] with given target rate-per-second, as if it was
+    /// created at `now`.
     pub fn new_with_now(rate_per_second: usize, now: Instant) -> Self {
         Self {
             last_update: now,
@@ -87,6 +88,17 @@ impl TokenBucket {
         None
     }
 
+    /// Takes the requested tokens if they are available at `now`, otherwise takes nothing.
+    /// Unlike [TokenBucket::rate_limit], a refused request is not charged for, so it does not
+    /// delay the ones after it.
+    pub fn try_take(&mut self, requested: usize, now: Instant) -> bool {
+        if self.update_units(now) < requested {
+            return false;
+        }
+        self.available -= requested;
+        true
+    }
+
     fn token_limit(&self) -> usize {
         self.rate_per_second
     }
@@ -208,4 +220,20 @@ mod tests {
             Some(Duration::from_secs(6))
         );
     }
+
+    #[test]
+    fn refused_takes_are_not_charged() {
+        let limit_per_second = 10;
+        let now = Instant::now();
+        let mut rate_limiter = TokenBucket::new_with_now(limit_per_second, now);
+
+        assert!(rate_limiter.try_take(10, now));
+        for _ in 0..100 {
+            assert!(!rate_limiter.try_take(1, now));
+        }
+        assert!(!rate_limiter.try_take(6, now + Duration::from_millis(500)));
+        assert!(rate_limiter.try_take(5, now + Duration::from_millis(500)));
+        assert!(rate_limiter.try_take(10, now + Duration::from_millis(1500)));
+        assert!(!rate_limiter.try_take(1, now + Duration::from_millis(1500)));
+    }
 }